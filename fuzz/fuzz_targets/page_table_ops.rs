@@ -0,0 +1,30 @@
+//! Runs arbitrary operations on a page table of every entry format, checked
+//! against the shadow model of `page_table_multiarch::mock`.
+//!
+//! The first byte of the input selects the format, and the rest is decoded
+//! by `mock::fuzz::OpDecoder`. Run with `cargo fuzz run page_table_ops` from
+//! the root of the repository. The seeds in `corpus/page_table_ops` are also
+//! replayed by the tests of `page_table_multiarch`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use memory_addr::VirtAddr;
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::fuzz::run;
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&format, ops)) = data.split_first() else {
+        return;
+    };
+    match format % 4 {
+        0 => run::<X64PagingMetaData, X64PTE>(ops),
+        1 => run::<A64PagingMetaData, A64PTE>(ops),
+        2 => run::<Sv39MetaData<VirtAddr>, Rv64PTE>(ops),
+        _ => run::<LA64MetaData, LA64PTE>(ops),
+    }
+});
@@ -4,7 +4,8 @@ use aarch64_cpu::registers::MAIR_EL1;
 use core::fmt;
 use memory_addr::PhysAddr;
 
-use crate::{GenericPTE, MappingFlags};
+use crate::{AbsentEntry, AccessContext, AccessType, GenericPTE, MappingFlags};
+use crate::{NonPresentPayload, debug_check_huge};
 
 bitflags::bitflags! {
     /// Memory attribute fields in the VMSAv8-64 translation table format descriptors.
@@ -199,15 +200,74 @@ pub struct A64PTE(u64);
 
 impl A64PTE {
     const PHYS_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000; // bits 12..48
+    /// The bits of the tag (see [`GenericPTE::tag`]), the bits 55..59
+    /// reserved for software.
+    const TAG_SHIFT: u32 = 55;
+    const TAG_MASK: u64 = 0xf << Self::TAG_SHIFT;
+    /// The bits of a page or block descriptor written by
+    /// [`GenericPTE::set_flags`], [`GenericPTE::set_paddr`] and the other
+    /// setters. The others are kept, such as nG, GP and the bits reserved for
+    /// software.
+    const MANAGED: u64 = Self::PHYS_ADDR_MASK
+        | DescriptorAttr::VALID.bits()
+        | DescriptorAttr::NON_BLOCK.bits()
+        | DescriptorAttr::ATTR_INDX.bits()
+        | DescriptorAttr::AP_EL0.bits()
+        | DescriptorAttr::AP_RO.bits()
+        | DescriptorAttr::INNER.bits()
+        | DescriptorAttr::SHAREABLE.bits()
+        | DescriptorAttr::AF.bits()
+        | DescriptorAttr::DBM.bits()
+        | DescriptorAttr::CONTIGUOUS.bits()
+        | DescriptorAttr::PXN.bits()
+        | DescriptorAttr::UXN.bits();
 
     /// Creates an empty descriptor with all bits set to zero.
     pub const fn empty() -> Self {
         Self(0)
     }
+
+    /// Returns whether a table descriptor allows mappings with `flags` below
+    /// it, according to its APTable, UXNTable (XNTable) and PXNTable fields.
+    pub fn table_allows(&self, flags: MappingFlags) -> bool {
+        self.0 & Self::table_restrictions(flags).bits() == 0
+    }
+
+    /// The table descriptor fields that forbid the accesses in `flags`.
+    fn table_restrictions(flags: MappingFlags) -> DescriptorAttr {
+        let mut attr = DescriptorAttr::empty();
+        if flags.contains(MappingFlags::WRITE) {
+            attr |= DescriptorAttr::AP_NO_WRITE_TABLE;
+        }
+        #[cfg(not(feature = "arm-el2"))]
+        {
+            if flags.contains(MappingFlags::USER) {
+                attr |= DescriptorAttr::AP_NO_EL0_TABLE;
+                if flags.contains(MappingFlags::EXECUTE) {
+                    attr |= DescriptorAttr::XN_TABLE;
+                }
+            } else if flags.contains(MappingFlags::EXECUTE) {
+                attr |= DescriptorAttr::PXN_TABLE;
+            }
+        }
+        #[cfg(feature = "arm-el2")]
+        {
+            if flags.contains(MappingFlags::EXECUTE) {
+                attr |= DescriptorAttr::XN_TABLE;
+            }
+        }
+        attr
+    }
 }
 
 impl GenericPTE for A64PTE {
+    type ArchFlags = DescriptorAttr;
+
+    const CONTIGUOUS_HINT: bool = true;
+    const TAG_BITS: u32 = 4;
+
     fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
+        debug_check_huge(paddr, is_huge);
         let mut attr = DescriptorAttr::from(flags) | DescriptorAttr::AF;
         if !is_huge {
             attr |= DescriptorAttr::NON_BLOCK;
@@ -215,9 +275,36 @@ impl GenericPTE for A64PTE {
         Self(attr.bits() | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK))
     }
     fn new_table(paddr: PhysAddr) -> Self {
-        let attr = DescriptorAttr::NON_BLOCK | DescriptorAttr::VALID;
+        #[allow(unused_mut)]
+        let mut attr = DescriptorAttr::NON_BLOCK | DescriptorAttr::VALID;
+        // Forbid everything below, until it is allowed by `widen_table`.
+        #[cfg(feature = "arm-table-permissions")]
+        {
+            attr |= DescriptorAttr::AP_NO_WRITE_TABLE | DescriptorAttr::XN_TABLE;
+            #[cfg(not(feature = "arm-el2"))]
+            {
+                attr |= DescriptorAttr::AP_NO_EL0_TABLE | DescriptorAttr::PXN_TABLE;
+            }
+        }
         Self(attr.bits() | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK))
     }
+    fn widen_table(&mut self, flags: MappingFlags) -> bool {
+        let old = self.0;
+        self.0 &= !Self::table_restrictions(flags).bits();
+        self.0 != old
+    }
+    fn narrow_table(&mut self, flags: MappingFlags) -> bool {
+        let old = self.0;
+        // Without `arm-table-permissions`, the fields are cleared.
+        let fields = DescriptorAttr::AP_NO_WRITE_TABLE
+            | DescriptorAttr::AP_NO_EL0_TABLE
+            | DescriptorAttr::XN_TABLE
+            | DescriptorAttr::PXN_TABLE;
+        let initial = Self::new_table(self.paddr()).0 & fields.bits();
+        self.0 = (self.0 & !fields.bits()) | initial;
+        self.widen_table(flags);
+        self.0 != old
+    }
     fn paddr(&self) -> PhysAddr {
         PhysAddr::from((self.0 & Self::PHYS_ADDR_MASK) as usize)
     }
@@ -232,14 +319,33 @@ impl GenericPTE for A64PTE {
         if !is_huge {
             attr |= DescriptorAttr::NON_BLOCK;
         }
+        let kept = if flags.is_empty() {
+            0
+        } else {
+            self.unknown_bits() as u64
+        };
+        self.set_flags_arch(attr);
+        self.0 |= kept;
+    }
+
+    fn set_flags_arch(&mut self, attr: DescriptorAttr) {
         self.0 = (self.0 & Self::PHYS_ADDR_MASK) | attr.bits();
     }
 
     fn bits(self) -> usize {
         self.0 as usize
     }
+    fn from_bits(bits: usize) -> Self {
+        Self(bits as u64)
+    }
+    fn unknown_bits(&self) -> usize {
+        if !self.is_present() {
+            return 0;
+        }
+        (self.0 & !Self::MANAGED) as usize
+    }
     fn is_unused(&self) -> bool {
-        self.0 == 0
+        crate::is_cleared(self.0)
     }
     fn is_present(&self) -> bool {
         DescriptorAttr::from_bits_truncate(self.0).contains(DescriptorAttr::VALID)
@@ -265,10 +371,98 @@ impl GenericPTE for A64PTE {
         }
     }
     fn is_huge(&self) -> bool {
-        !DescriptorAttr::from_bits_truncate(self.0).contains(DescriptorAttr::NON_BLOCK)
+        // An unused entry lacks `NON_BLOCK` too, but maps nothing.
+        !self.is_unused()
+            && !DescriptorAttr::from_bits_truncate(self.0).contains(DescriptorAttr::NON_BLOCK)
     }
     fn clear(&mut self) {
-        self.0 = 0
+        self.0 = crate::CLEARED
+    }
+    fn is_contiguous(&self) -> bool {
+        DescriptorAttr::from_bits_truncate(self.0).contains(DescriptorAttr::CONTIGUOUS)
+    }
+    fn set_contiguous(&mut self, contiguous: bool) {
+        if contiguous {
+            self.0 |= DescriptorAttr::CONTIGUOUS.bits();
+        } else {
+            self.0 &= !DescriptorAttr::CONTIGUOUS.bits();
+        }
+    }
+    // EL1 cannot touch the pages of EL0 with PAN set, and never executes the
+    // pages writable at EL0.
+    #[cfg(not(feature = "arm-el2"))]
+    fn permits(&self, access: AccessType, ctx: AccessContext) -> bool {
+        let attr = DescriptorAttr::from_bits_truncate(self.0);
+        if !attr.contains(DescriptorAttr::VALID) {
+            return false;
+        }
+        let user_page = attr.contains(DescriptorAttr::AP_EL0);
+        let writable = !attr.contains(DescriptorAttr::AP_RO);
+        if ctx.user {
+            return match access {
+                AccessType::Read => user_page,
+                AccessType::Write => user_page && writable,
+                AccessType::Execute => !attr.contains(DescriptorAttr::UXN),
+            };
+        }
+        match access {
+            AccessType::Read => !user_page || ctx.kernel_accesses_user,
+            AccessType::Write => writable && (!user_page || ctx.kernel_accesses_user),
+            AccessType::Execute => !attr.contains(DescriptorAttr::PXN) && !(user_page && writable),
+        }
+    }
+    // EL2 has a single privilege level, without PAN on the pages of EL0.
+    #[cfg(feature = "arm-el2")]
+    fn permits(&self, access: AccessType, _ctx: AccessContext) -> bool {
+        let attr = DescriptorAttr::from_bits_truncate(self.0);
+        attr.contains(DescriptorAttr::VALID)
+            && match access {
+                AccessType::Read => true,
+                AccessType::Write => !attr.contains(DescriptorAttr::AP_RO),
+                AccessType::Execute => !attr.contains(DescriptorAttr::UXN),
+            }
+    }
+    // The hierarchical controls of the table descriptors, which are ignored
+    // with `TCR_ELx.HPD` set.
+    fn limited_by(&self, table: &Self) -> Self {
+        let table = DescriptorAttr::from_bits_truncate(table.0);
+        let mut pte = *self;
+        if table.contains(DescriptorAttr::AP_NO_EL0_TABLE) {
+            pte.0 &= !DescriptorAttr::AP_EL0.bits();
+        }
+        let limits = [
+            (DescriptorAttr::AP_NO_WRITE_TABLE, DescriptorAttr::AP_RO),
+            (DescriptorAttr::XN_TABLE, DescriptorAttr::UXN),
+            (DescriptorAttr::PXN_TABLE, DescriptorAttr::PXN),
+        ];
+        for (limit, flag) in limits {
+            if table.contains(limit) {
+                pte.0 |= flag.bits();
+            }
+        }
+        pte
+    }
+    fn tag(&self) -> u8 {
+        ((self.0 & Self::TAG_MASK) >> Self::TAG_SHIFT) as u8
+    }
+    fn set_tag(&mut self, tag: u8) {
+        self.0 = (self.0 & !Self::TAG_MASK) | ((tag as u64) << Self::TAG_SHIFT & Self::TAG_MASK);
+    }
+    // Bits 63:1 of an invalid descriptor are ignored at every level, so the
+    // common layout is used as is.
+    fn payload(&self) -> Option<NonPresentPayload> {
+        if self.is_present() {
+            return None;
+        }
+        NonPresentPayload::from_bits(self.0)
+    }
+    fn set_payload(&mut self, payload: NonPresentPayload) {
+        self.0 = payload.to_bits();
+    }
+    fn new_absent(absent: AbsentEntry) -> Self {
+        let mut pte = Self::empty();
+        pte.set_payload(absent.into());
+        pte
     }
 }
 
@@ -4,8 +4,11 @@
 //!
 //! <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#section-multi-level-page-table-structure-supported-by-page-walking>
 
-use crate::{GenericPTE, MappingFlags};
-use core::fmt;
+use crate::{
+    AbsentEntry, GenericPTE, MappingFlags, NonPresentPayload, SoftBitLayout, WpReasons,
+    check_soft_bits, debug_check_huge,
+};
+use core::{fmt, marker::PhantomData};
 use memory_addr::PhysAddr;
 
 bitflags::bitflags! {
@@ -40,7 +43,8 @@ bitflags::bitflags! {
         const MATL = 1 << 4;
         /// Memory Access Type High Bit
         const MATH = 1 << 5;
-        /// Designates a global mapping OR Whether the page is huge page.
+        /// Designates a global mapping (G) in the entries of 4K pages, or a
+        /// huge page (H) in directory entries.
         const GH = 1 << 6;
         /// Whether the physical page is exist.
         const P = 1 << 7;
@@ -50,7 +54,8 @@ bitflags::bitflags! {
         const RSW1 = 1 << 9;
         const RSW2 = 1 << 10;
         const RSW3 = 1 << 11;
-        /// Designates a global mapping when using huge page.
+        /// Designates a global mapping (HG) in the entries of huge pages, in
+        /// place of the lowest bit of the physical address.
         const G = 1 << 12;
         /// Whether the page is not readable.
         const NR = 1 << 61;
@@ -62,41 +67,43 @@ bitflags::bitflags! {
     }
 }
 
-impl From<PTEFlags> for MappingFlags {
-    fn from(f: PTEFlags) -> Self {
-        if !f.contains(PTEFlags::V) {
-            return Self::empty();
+impl PTEFlags {
+    /// Converts to [`MappingFlags`], reading the software flags from the bits
+    /// assigned by `L`.
+    pub fn to_mapping_flags<L: SoftBitLayout>(self) -> MappingFlags {
+        if !self.contains(Self::V) {
+            return MappingFlags::empty();
         }
-        let mut ret = Self::empty();
-        if !f.contains(PTEFlags::NR) {
-            ret |= Self::READ;
+        let mut ret = MappingFlags::empty();
+        if !self.contains(Self::NR) {
+            ret |= MappingFlags::READ;
         }
-        if f.contains(PTEFlags::W) {
-            ret |= Self::WRITE;
+        if self.contains(Self::W) {
+            ret |= MappingFlags::WRITE;
         }
-        if !f.contains(PTEFlags::NX) {
-            ret |= Self::EXECUTE;
+        if !self.contains(Self::NX) {
+            ret |= MappingFlags::EXECUTE;
         }
-        if f.contains(PTEFlags::PLVL | PTEFlags::PLVH) {
-            ret |= Self::USER;
+        if self.contains(Self::PLVL | Self::PLVH) {
+            ret |= MappingFlags::USER;
         }
-        if !f.contains(PTEFlags::MATL) {
-            if f.contains(PTEFlags::MATH) {
-                ret |= Self::UNCACHED;
+        if !self.contains(Self::MATL) {
+            if self.contains(Self::MATH) {
+                ret |= MappingFlags::UNCACHED;
             } else {
-                ret |= Self::DEVICE;
+                ret |= MappingFlags::DEVICE;
             }
         }
         #[cfg(feature = "COW")]
-        if f.contains(PTEFlags::RSW1) {
-            ret |= Self::COW;
+        if self.bits() & (1 << L::COW) != 0 {
+            ret |= MappingFlags::COW;
         }
         ret
     }
-}
 
-impl From<MappingFlags> for PTEFlags {
-    fn from(f: MappingFlags) -> Self {
+    /// Converts from [`MappingFlags`], storing the software flags in the bits
+    /// assigned by `L`.
+    pub fn from_mapping_flags<L: SoftBitLayout>(f: MappingFlags) -> Self {
         if f.is_empty() {
             return Self::empty();
         }
@@ -124,52 +131,146 @@ impl From<MappingFlags> for PTEFlags {
         }
         #[cfg(feature = "COW")]
         if f.contains(MappingFlags::COW) {
-            ret |= Self::RSW1;
+            ret |= Self::from_bits_retain(1 << L::COW);
         }
         ret
     }
 }
 
+impl From<PTEFlags> for MappingFlags {
+    fn from(f: PTEFlags) -> Self {
+        f.to_mapping_flags::<LA64SoftBits>()
+    }
+}
+
+impl From<MappingFlags> for PTEFlags {
+    fn from(f: MappingFlags) -> Self {
+        PTEFlags::from_mapping_flags::<LA64SoftBits>(f)
+    }
+}
+
+/// The default software bit layout of [`LA64PTE`]: COW is `RSW1` (bit 9),
+/// and the lock `RSW2` (bit 10).
+#[derive(Debug, Clone, Copy)]
+pub struct LA64SoftBits;
+
+impl SoftBitLayout for LA64SoftBits {
+    const COW: u32 = 9;
+    const LOCKED: Option<u32> = Some(10);
+}
+
 /// page table entry for loongarch64 system
-#[derive(Clone, Copy)]
+///
+/// The bits used for software flags are given by the layout `L`.
+///
+/// Huge pages live in directory entries, marked by the H bit (6), and their
+/// global bit HG takes bit 12 of the physical address, which is always clear
+/// in a 2M-aligned frame. As in the `pmd` entries of Linux, G and H share a
+/// bit, so an entry cannot tell a global 4K page from a huge page without its
+/// level. The entries created here never set G, and [`GenericPTE::is_huge`]
+/// and [`GenericPTE::paddr`] read bit 6 as H.
 #[repr(transparent)]
-pub struct LA64PTE(u64);
+pub struct LA64PTE<L: SoftBitLayout = LA64SoftBits>(u64, PhantomData<L>);
+
+impl<L: SoftBitLayout> Clone for LA64PTE<L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
 
-impl LA64PTE {
+impl<L: SoftBitLayout> Copy for LA64PTE<L> {}
+
+impl<L: SoftBitLayout> LA64PTE<L> {
     const PHYS_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000; // bits 12..48
+    /// The bits of [`WpReasons::DIRTY_LOG`] and [`WpReasons::SNAPSHOT`].
+    const WP_AT: [Option<u32>; 2] = [L::DIRTY_LOG, L::SNAPSHOT];
+    const SOFT_BITS: () = check_soft_bits(
+        &[Some(L::COW), L::LOCKED, L::DIRTY_LOG, L::SNAPSHOT],
+        PTEFlags::RSW1.bits() | PTEFlags::RSW2.bits() | PTEFlags::RSW3.bits(),
+    );
+    /// The bits of a leaf entry of any size written by
+    /// [`GenericPTE::set_flags`], [`GenericPTE::set_paddr`] and the dirty
+    /// setter. The others are kept, such as RPLV and the unused software bits.
+    const MANAGED: u64 = Self::PHYS_ADDR_MASK
+        | PTEFlags::V.bits()
+        | PTEFlags::D.bits()
+        | PTEFlags::PLVL.bits()
+        | PTEFlags::PLVH.bits()
+        | PTEFlags::MATL.bits()
+        | PTEFlags::MATH.bits()
+        | PTEFlags::GH.bits()
+        | PTEFlags::P.bits()
+        | PTEFlags::W.bits()
+        | PTEFlags::NR.bits()
+        | PTEFlags::NX.bits()
+        | if cfg!(feature = "COW") {
+            1 << L::COW
+        } else {
+            0
+        }
+        | WpReasons::all().to_entry(Self::WP_AT);
 
     /// Creates an empty descriptor with all bits set to zero.
     pub const fn empty() -> Self {
-        Self(0)
+        Self(0, PhantomData)
     }
-}
 
-impl GenericPTE for LA64PTE {
-    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
-        let mut flags = PTEFlags::from(flags);
+    /// Returns the mask of the physical address bits.
+    fn paddr_mask(is_huge: bool) -> u64 {
+        if is_huge {
+            Self::PHYS_ADDR_MASK & !PTEFlags::G.bits()
+        } else {
+            Self::PHYS_ADDR_MASK
+        }
+    }
+
+    fn arch_flags(flags: MappingFlags, is_huge: bool) -> PTEFlags {
+        let () = Self::SOFT_BITS;
+        let mut flags = PTEFlags::from_mapping_flags::<L>(flags);
         if is_huge {
             flags |= PTEFlags::GH;
         }
-        Self(flags.bits() | ((paddr.as_usize()) as u64 & Self::PHYS_ADDR_MASK))
+        flags
+    }
+}
+
+impl<L: SoftBitLayout> GenericPTE for LA64PTE<L> {
+    type ArchFlags = PTEFlags;
+
+    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
+        debug_check_huge(paddr, is_huge);
+        let flags = Self::arch_flags(flags, is_huge);
+        Self(
+            flags.bits() | (paddr.as_usize() as u64 & Self::paddr_mask(is_huge)),
+            PhantomData,
+        )
     }
     fn new_table(paddr: PhysAddr) -> Self {
-        Self((paddr.as_usize() as u64) & Self::PHYS_ADDR_MASK)
+        Self(
+            (paddr.as_usize() as u64) & Self::PHYS_ADDR_MASK,
+            PhantomData,
+        )
     }
     fn paddr(&self) -> PhysAddr {
-        PhysAddr::from((self.0 & Self::PHYS_ADDR_MASK) as usize)
+        PhysAddr::from((self.0 & Self::paddr_mask(self.is_huge())) as usize)
     }
     fn flags(&self) -> MappingFlags {
-        PTEFlags::from_bits_truncate(self.0).into()
+        PTEFlags::from_bits_truncate(self.0).to_mapping_flags::<L>()
     }
     fn set_paddr(&mut self, paddr: PhysAddr) {
-        self.0 = (self.0 & !Self::PHYS_ADDR_MASK) | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK)
+        let mask = Self::paddr_mask(self.is_huge());
+        self.0 = (self.0 & !mask) | (paddr.as_usize() as u64 & mask)
     }
     fn set_flags(&mut self, flags: MappingFlags, is_huge: bool) {
-        let mut flags: PTEFlags = PTEFlags::from(flags);
-        if is_huge {
-            flags |= PTEFlags::GH;
-        }
+        let (flags, reasons) = crate::withhold_write(flags, self.wp_reasons());
+        let kept = if flags.is_empty() {
+            0
+        } else {
+            self.unknown_bits() as u64
+        };
+        let flags = Self::arch_flags(flags, is_huge);
         self.set_flags_arch(flags);
+        self.0 |= kept | reasons.to_entry(Self::WP_AT);
     }
     fn set_flags_arch(&mut self, flags: PTEFlags) {
         self.0 = (self.0 & Self::PHYS_ADDR_MASK) | flags.bits();
@@ -177,8 +278,23 @@ impl GenericPTE for LA64PTE {
     fn bits(self) -> usize {
         self.0 as usize
     }
+    fn from_bits(bits: usize) -> Self {
+        Self(bits as u64, PhantomData)
+    }
+    fn unknown_bits(&self) -> usize {
+        if !self.is_present() {
+            return 0;
+        }
+        // HG is kept like G of the other formats.
+        let managed = if self.is_huge() {
+            Self::MANAGED & !PTEFlags::G.bits()
+        } else {
+            Self::MANAGED
+        };
+        (self.0 & !managed) as usize
+    }
     fn is_unused(&self) -> bool {
-        self.0 == 0
+        crate::is_cleared(self.0)
     }
     fn is_present(&self) -> bool {
         PTEFlags::from_bits_truncate(self.0).contains(PTEFlags::P)
@@ -196,19 +312,90 @@ impl GenericPTE for LA64PTE {
     fn is_accessed(&self) -> bool {
         true
     }
-    fn set_accessed(&mut self, accessed: bool) {
-        return;
-    }
+    fn set_accessed(&mut self, _accessed: bool) {}
     fn is_huge(&self) -> bool {
         PTEFlags::from_bits_truncate(self.0).contains(PTEFlags::GH)
     }
+    // Only HG is known: G of 4K pages reads as H.
+    fn is_global(&self) -> bool {
+        self.is_present() && self.is_huge() && self.0 & PTEFlags::G.bits() != 0
+    }
+
+    const LOCKABLE: bool = L::LOCKED.is_some();
+    fn is_locked(&self) -> bool {
+        match L::LOCKED {
+            Some(bit) => self.is_present() && self.0 & 1 << bit != 0,
+            None => false,
+        }
+    }
+    fn set_locked(&mut self, locked: bool) {
+        let Some(bit) = L::LOCKED else {
+            return;
+        };
+        if locked {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
+        }
+    }
+    const WP_REASONS: WpReasons = WpReasons::stored_at(Self::WP_AT);
+    fn wp_reasons(&self) -> WpReasons {
+        if !self.is_present() {
+            return WpReasons::empty();
+        }
+        WpReasons::from_entry(self.0, Self::WP_AT)
+    }
+    fn set_wp_reasons(&mut self, reasons: WpReasons) {
+        let reasons = reasons & Self::WP_REASONS;
+        let Some(writable) = crate::write_access(self.flags(), self.wp_reasons(), reasons) else {
+            return;
+        };
+        self.0 &=
+            !(WpReasons::all().to_entry(Self::WP_AT) | PTEFlags::W.bits() | PTEFlags::D.bits());
+        self.0 |= reasons.to_entry(Self::WP_AT);
+        if writable {
+            self.0 |= PTEFlags::W.bits() | PTEFlags::D.bits();
+        }
+    }
 
     fn clear(&mut self) {
-        self.0 = 0
+        self.0 = crate::CLEARED
+    }
+    // The TLB refill loads an entry with V clear without looking at its other
+    // bits, and the access faults. The common layout keeps V, P and GH clear,
+    // as the page walker takes a directory entry with GH set for a huge page.
+    fn payload(&self) -> Option<NonPresentPayload> {
+        if self.0 & (PTEFlags::V | PTEFlags::P).bits() != 0 {
+            return None;
+        }
+        NonPresentPayload::from_bits(self.0)
+    }
+    fn set_payload(&mut self, payload: NonPresentPayload) {
+        self.0 = payload.to_bits();
+    }
+    fn new_absent(absent: AbsentEntry) -> Self {
+        let mut pte = Self::empty();
+        pte.set_payload(absent.into());
+        pte
+    }
+
+    // NR and NX deny the reads and fetches at every privilege level, and a
+    // clear W the writes, so the entry stays present. The PLV and MAT bits of
+    // the kernel's cached memory leave no flags.
+    const PRESENT_INACCESSIBLE: bool = true;
+    fn set_inaccessible(&mut self) {
+        let huge = PTEFlags::from_bits_truncate(self.0) & PTEFlags::GH;
+        let none = PTEFlags::V | PTEFlags::P | PTEFlags::NR | PTEFlags::NX | PTEFlags::MATL;
+        self.set_flags_arch(none | huge);
+    }
+    fn inaccessible(&self) -> Option<PhysAddr> {
+        let flags = PTEFlags::from_bits_truncate(self.0);
+        let none = PTEFlags::V | PTEFlags::P | PTEFlags::NR | PTEFlags::NX;
+        (flags.contains(none) && !flags.contains(PTEFlags::W)).then(|| self.paddr())
     }
 }
 
-impl fmt::Debug for LA64PTE {
+impl<L: SoftBitLayout> fmt::Debug for LA64PTE<L> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut f = f.debug_struct("LA64PTE");
         f.field("raw", &self.0)
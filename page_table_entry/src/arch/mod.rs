@@ -1,15 +1,20 @@
-#[cfg(any(target_arch = "x86_64", doc))]
+#[cfg(any(target_arch = "x86_64", doc, feature = "all-formats"))]
 pub mod x86_64;
 
-#[cfg(any(target_arch = "riscv32", target_arch = "riscv64", doc))]
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    doc,
+    feature = "all-formats"
+))]
 pub mod riscv;
-#[cfg(any(target_arch = "riscv32", target_arch = "riscv64", doc))]
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
 pub use riscv::PTEFlags;
 
-#[cfg(any(target_arch = "aarch64", doc))]
+#[cfg(any(target_arch = "aarch64", doc, feature = "all-formats"))]
 pub mod aarch64;
 
-#[cfg(any(target_arch = "loongarch64", doc))]
+#[cfg(any(target_arch = "loongarch64", doc, feature = "all-formats"))]
 pub mod loongarch64;
-#[cfg(any(target_arch = "loongarch64", doc))]
+#[cfg(target_arch = "loongarch64")]
 pub use loongarch64::PTEFlags;
@@ -1,9 +1,12 @@
 //! RISC-V page table entries.
 
-use core::fmt;
+use core::{fmt, marker::PhantomData};
 use memory_addr::PhysAddr;
 
-use crate::{GenericPTE, MappingFlags};
+use crate::{
+    AbsentEntry, AccessContext, AccessType, GenericPTE, MappingFlags, NonPresentPayload,
+    SoftBitLayout, WpReasons, check_soft_bits, debug_check_huge,
+};
 
 bitflags::bitflags! {
     /// Page-table entry flags.
@@ -33,34 +36,36 @@ bitflags::bitflags! {
     }
 }
 
-impl From<PTEFlags> for MappingFlags {
-    fn from(f: PTEFlags) -> Self {
-        let mut ret = Self::empty();
-        if !f.contains(PTEFlags::V) {
+impl PTEFlags {
+    /// Converts to [`MappingFlags`], reading the software flags from the bits
+    /// assigned by `L`.
+    pub fn to_mapping_flags<L: SoftBitLayout>(self) -> MappingFlags {
+        let mut ret = MappingFlags::empty();
+        if !self.contains(Self::V) {
             return ret;
         }
-        if f.contains(PTEFlags::R) {
-            ret |= Self::READ;
+        if self.contains(Self::R) {
+            ret |= MappingFlags::READ;
         }
-        if f.contains(PTEFlags::W) {
-            ret |= Self::WRITE;
+        if self.contains(Self::W) {
+            ret |= MappingFlags::WRITE;
         }
-        if f.contains(PTEFlags::X) {
-            ret |= Self::EXECUTE;
+        if self.contains(Self::X) {
+            ret |= MappingFlags::EXECUTE;
         }
-        if f.contains(PTEFlags::U) {
-            ret |= Self::USER;
+        if self.contains(Self::U) {
+            ret |= MappingFlags::USER;
         }
         #[cfg(feature = "COW")]
-        if f.contains(PTEFlags::RSW1) {
-            ret |= Self::COW;
+        if self.bits() & (1 << L::COW) != 0 {
+            ret |= MappingFlags::COW;
         }
         ret
     }
-}
 
-impl From<MappingFlags> for PTEFlags {
-    fn from(f: MappingFlags) -> Self {
+    /// Converts from [`MappingFlags`], storing the software flags in the bits
+    /// assigned by `L`.
+    pub fn from_mapping_flags<L: SoftBitLayout>(f: MappingFlags) -> Self {
         if f.is_empty() {
             return Self::empty();
         }
@@ -79,60 +84,247 @@ impl From<MappingFlags> for PTEFlags {
         }
         #[cfg(feature = "COW")]
         if f.contains(MappingFlags::COW) {
-            ret |= Self::RSW1;
+            ret |= Self::from_bits_retain(1 << L::COW);
         }
         ret
     }
 }
 
+impl From<PTEFlags> for MappingFlags {
+    fn from(f: PTEFlags) -> Self {
+        f.to_mapping_flags::<Rv64SoftBits>()
+    }
+}
+
+impl From<MappingFlags> for PTEFlags {
+    fn from(f: MappingFlags) -> Self {
+        PTEFlags::from_mapping_flags::<Rv64SoftBits>(f)
+    }
+}
+
+/// The default software bit layout of [`Rv64PTE`]: COW is `RSW1` (bit 8),
+/// and the lock `RSW2` (bit 9).
+///
+/// Other layouts may also use bits 59 and 60 of Svrsw60t59b with the
+/// `riscv-svrsw60t59b` feature.
+#[derive(Debug, Clone, Copy)]
+pub struct Rv64SoftBits;
+
+impl SoftBitLayout for Rv64SoftBits {
+    const COW: u32 = 8;
+    const LOCKED: Option<u32> = Some(9);
+}
+
 /// Sv39 and Sv48 page table entry for RV64 systems.
-#[derive(Clone, Copy)]
+///
+/// The bits used for software flags are given by the layout `L`.
+///
+/// With the `riscv-strict-reserved` feature, for cores that fault on them,
+/// the setters clear the reserved bits 54..61 of present entries (but bits 59
+/// and 60 with the `riscv-svrsw60t59b` feature), with a warning, instead of
+/// keeping them like the other bits they do not manage. They are then not
+/// part of [`GenericPTE::unknown_bits`] either. The constructors never set
+/// them, but [`Rv64PTE::from_bits`] takes them as they are.
 #[repr(transparent)]
-pub struct Rv64PTE(u64);
+pub struct Rv64PTE<L: SoftBitLayout = Rv64SoftBits>(u64, PhantomData<L>);
 
-impl Rv64PTE {
+impl<L: SoftBitLayout> Clone for Rv64PTE<L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<L: SoftBitLayout> Copy for Rv64PTE<L> {}
+
+impl<L: SoftBitLayout> Rv64PTE<L> {
     const PHYS_ADDR_MASK: u64 = (1 << 54) - (1 << 10); // bits 10..54
+    /// The Svnapot bit of a leaf entry (bit 63).
+    const NAPOT: u64 = 1 << 63;
+    /// The low PPN bits of a 64K NAPOT entry.
+    const NAPOT_64K_MASK: u64 = 0b1111 << 10;
+    const NAPOT_64K: u64 = 0b1000 << 10;
+    /// Bits 59 and 60, reserved for software by Svrsw60t59b.
+    const RSW_HIGH: u64 = 0b11 << 59;
+    /// Bits 54..61, reserved for future standard use, but for the software
+    /// bits of Svrsw60t59b with the `riscv-svrsw60t59b` feature.
+    const RESERVED_MASK: u64 = ((1 << 61) - (1 << 54))
+        & if cfg!(feature = "riscv-svrsw60t59b") {
+            !Self::RSW_HIGH
+        } else {
+            !0
+        };
+    /// The flags only valid in leaf entries.
+    const LEAF_FLAGS: PTEFlags = PTEFlags::R
+        .union(PTEFlags::W)
+        .union(PTEFlags::X)
+        .union(PTEFlags::U)
+        .union(PTEFlags::A)
+        .union(PTEFlags::D);
+    /// The bits of [`WpReasons::DIRTY_LOG`] and [`WpReasons::SNAPSHOT`].
+    const WP_AT: [Option<u32>; 2] = [L::DIRTY_LOG, L::SNAPSHOT];
+    const SOFT_BITS: () = check_soft_bits(
+        &[Some(L::COW), L::LOCKED, L::DIRTY_LOG, L::SNAPSHOT],
+        (PTEFlags::RSW1.bits() | PTEFlags::RSW2.bits()) as u64
+            | if cfg!(feature = "riscv-svrsw60t59b") {
+                Self::RSW_HIGH
+            } else {
+                0
+            },
+    );
+    /// The bits of a leaf entry replaced by [`GenericPTE::set_flags_arch`]:
+    /// the flags of bits 0..10, and the software flags of `L`.
+    const FLAG_BITS: u64 = ((1 << 10) - 1)
+        | if cfg!(feature = "COW") {
+            1 << L::COW
+        } else {
+            0
+        };
+    /// The bits of a leaf entry written by [`GenericPTE::set_flags`],
+    /// [`GenericPTE::set_paddr`] and the other setters. The others are kept,
+    /// such as G, the memory type of Svpbmt and the unused software bits.
+    const MANAGED: u64 = Self::PHYS_ADDR_MASK
+        | Self::NAPOT
+        | (Self::LEAF_FLAGS.bits() | PTEFlags::V.bits()) as u64
+        | if cfg!(feature = "COW") {
+            1 << L::COW
+        } else {
+            0
+        }
+        | WpReasons::all().to_entry(Self::WP_AT);
 
     /// Creates an empty descriptor with all bits set to zero.
     pub const fn empty() -> Self {
-        Self(0)
+        Self(0, PhantomData)
+    }
+
+    /// Creates a descriptor from its raw bits, e.g. read from a page table
+    /// that was not built by this crate. See [`GenericPTE::is_well_formed`].
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits, PhantomData)
+    }
+
+    /// Clears the reserved bits of a present entry in strict mode, before it
+    /// is changed.
+    fn clear_reserved(&mut self) {
+        #[cfg(feature = "riscv-strict-reserved")]
+        if self.is_present() && self.0 & Self::RESERVED_MASK != 0 {
+            log::warn!(
+                "clearing the reserved bits {:#x} of {:#x}",
+                self.0 & Self::RESERVED_MASK,
+                self.0,
+            );
+            self.0 &= !Self::RESERVED_MASK;
+        }
+    }
+
+    fn arch_flags(flags: MappingFlags) -> PTEFlags {
+        let () = Self::SOFT_BITS;
+        PTEFlags::from_mapping_flags::<L>(flags) | PTEFlags::A | PTEFlags::D
     }
 }
 
-impl GenericPTE for Rv64PTE {
-    fn new_page(paddr: PhysAddr, flags: MappingFlags, _is_huge: bool) -> Self {
-        let flags = PTEFlags::from(flags) | PTEFlags::A | PTEFlags::D;
+impl<L: SoftBitLayout> GenericPTE for Rv64PTE<L> {
+    type ArchFlags = PTEFlags;
+
+    const CONTIGUOUS_HINT: bool = cfg!(feature = "riscv-svnapot");
+
+    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
+        debug_check_huge(paddr, is_huge);
+        let flags = Self::arch_flags(flags);
         debug_assert!(flags.intersects(PTEFlags::R | PTEFlags::X));
-        Self(flags.bits() as u64 | ((paddr.as_usize() >> 2) as u64 & Self::PHYS_ADDR_MASK))
+        Self(
+            flags.bits() as u64 | ((paddr.as_usize() >> 2) as u64 & Self::PHYS_ADDR_MASK),
+            PhantomData,
+        )
     }
     fn new_table(paddr: PhysAddr) -> Self {
-        Self(PTEFlags::V.bits() as u64 | ((paddr.as_usize() >> 2) as u64 & Self::PHYS_ADDR_MASK))
+        Self(
+            PTEFlags::V.bits() as u64 | ((paddr.as_usize() >> 2) as u64 & Self::PHYS_ADDR_MASK),
+            PhantomData,
+        )
     }
     fn paddr(&self) -> PhysAddr {
-        PhysAddr::from(((self.0 & Self::PHYS_ADDR_MASK) << 2) as usize)
+        let mut ppn = self.0 & Self::PHYS_ADDR_MASK;
+        if self.is_contiguous() {
+            ppn &= !Self::NAPOT_64K_MASK;
+        }
+        PhysAddr::from((ppn << 2) as usize)
     }
     fn flags(&self) -> MappingFlags {
-        PTEFlags::from_bits_truncate(self.0 as usize).into()
+        // Retained, as the software flags may be above the bits of `PTEFlags`.
+        PTEFlags::from_bits_retain(self.0 as usize).to_mapping_flags::<L>()
     }
     fn set_paddr(&mut self, paddr: PhysAddr) {
+        self.clear_reserved();
         self.0 = (self.0 & !Self::PHYS_ADDR_MASK)
             | ((paddr.as_usize() as u64 >> 2) & Self::PHYS_ADDR_MASK);
+        if self.is_contiguous() {
+            self.0 = (self.0 & !Self::NAPOT_64K_MASK) | Self::NAPOT_64K;
+        }
     }
     fn set_flags(&mut self, flags: MappingFlags, _is_huge: bool) {
-        let flags = PTEFlags::from(flags) | PTEFlags::A | PTEFlags::D;
+        if self.is_table() {
+            // Table entries have no permissions: setting R or X would turn
+            // the table into a leaf.
+            return;
+        }
+        self.clear_reserved();
+        let (flags, reasons) = crate::withhold_write(flags, self.wp_reasons());
+        let kept = self.unknown_bits() as u64;
+        let flags = Self::arch_flags(flags);
         debug_assert!(flags.intersects(PTEFlags::R | PTEFlags::X));
-        self.set_flags_arch(flags)
+        self.set_flags_arch(flags);
+        self.0 =
+            self.0 & !WpReasons::all().to_entry(Self::WP_AT) | kept | reasons.to_entry(Self::WP_AT);
     }
 
-    fn set_flags_arch(&mut self, flags: PTEFlags) {
-        self.0 = (self.0 & Self::PHYS_ADDR_MASK) | flags.bits() as u64;
+    fn set_flags_arch(&mut self, mut flags: PTEFlags) {
+        self.clear_reserved();
+        if self.is_table() {
+            // D, A, and U are reserved in non-leaf entries.
+            flags -= Self::LEAF_FLAGS;
+            flags |= PTEFlags::V;
+        }
+        self.0 = (self.0 & !Self::FLAG_BITS) | (flags.bits() as u64 & Self::FLAG_BITS);
     }
 
     fn bits(self) -> usize {
         self.0 as usize
     }
+    fn from_bits(bits: usize) -> Self {
+        Self(bits as u64, PhantomData)
+    }
+    fn unknown_bits(&self) -> usize {
+        if !self.is_present() || self.is_table() {
+            return 0;
+        }
+        let reserved = if cfg!(feature = "riscv-strict-reserved") {
+            Self::RESERVED_MASK
+        } else {
+            0
+        };
+        (self.0 & !Self::MANAGED & !reserved) as usize
+    }
+    // The N bit of Svnapot is reserved unless the `riscv-svnapot` feature is
+    // enabled, and then only 64K leaf entries may set it.
+    fn is_well_formed(&self) -> bool {
+        if !self.is_present() {
+            return true;
+        }
+        if self.0 & Self::RESERVED_MASK != 0 {
+            return false;
+        }
+        match self.0 & Self::NAPOT {
+            0 => true,
+            _ => {
+                cfg!(feature = "riscv-svnapot")
+                    && self.is_huge()
+                    && self.0 & Self::NAPOT_64K_MASK == Self::NAPOT_64K
+            }
+        }
+    }
     fn is_unused(&self) -> bool {
-        self.0 == 0
+        crate::is_cleared(self.0)
     }
     fn is_present(&self) -> bool {
         PTEFlags::from_bits_truncate(self.0 as usize).contains(PTEFlags::V)
@@ -141,6 +333,7 @@ impl GenericPTE for Rv64PTE {
         PTEFlags::from_bits_truncate(self.0 as usize).contains(PTEFlags::D)
     }
     fn set_dirty(&mut self, dirty: bool) {
+        self.clear_reserved();
         if dirty {
             self.0 |= PTEFlags::D.bits() as u64;
         } else {
@@ -151,6 +344,7 @@ impl GenericPTE for Rv64PTE {
         PTEFlags::from_bits_truncate(self.0 as usize).contains(PTEFlags::A)
     }
     fn set_accessed(&mut self, accessed: bool) {
+        self.clear_reserved();
         if accessed {
             self.0 |= PTEFlags::A.bits() as u64;
         } else {
@@ -160,12 +354,115 @@ impl GenericPTE for Rv64PTE {
     fn is_huge(&self) -> bool {
         PTEFlags::from_bits_truncate(self.0 as usize).intersects(PTEFlags::R | PTEFlags::X)
     }
+    fn is_table(&self) -> bool {
+        let flags = PTEFlags::from_bits_truncate(self.0 as usize);
+        flags.contains(PTEFlags::V) && !flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X)
+    }
+    // S-mode never executes the pages of U-mode, and only reads and writes
+    // them with `sstatus.SUM` set. `sstatus.MXR` makes the executable pages
+    // readable.
+    fn permits(&self, access: AccessType, ctx: AccessContext) -> bool {
+        let flags = PTEFlags::from_bits_truncate(self.0 as usize);
+        let leaf = flags.intersects(PTEFlags::R | PTEFlags::X);
+        // W without R is reserved, and faults on any access.
+        let reserved = flags.contains(PTEFlags::W) && !flags.contains(PTEFlags::R);
+        if !flags.contains(PTEFlags::V) || !leaf || reserved {
+            return false;
+        }
+        let allowed = match (ctx.user, flags.contains(PTEFlags::U)) {
+            (true, user_page) => user_page,
+            (false, false) => true,
+            (false, true) => access != AccessType::Execute && ctx.kernel_accesses_user,
+        };
+        if !allowed {
+            return false;
+        }
+        match access {
+            AccessType::Read => {
+                flags.contains(PTEFlags::R)
+                    || (ctx.executable_readable && flags.contains(PTEFlags::X))
+            }
+            AccessType::Write => flags.contains(PTEFlags::W),
+            AccessType::Execute => flags.contains(PTEFlags::X),
+        }
+    }
     fn clear(&mut self) {
-        self.0 = 0
+        self.0 = crate::CLEARED
+    }
+    fn is_contiguous(&self) -> bool {
+        Self::CONTIGUOUS_HINT && self.is_present() && self.0 & Self::NAPOT != 0
+    }
+    fn set_contiguous(&mut self, contiguous: bool) {
+        if !Self::CONTIGUOUS_HINT {
+            return;
+        }
+        self.clear_reserved();
+        // All 16 entries of a NAPOT group are identical: the low PPN bits
+        // encode the size instead of the frame.
+        self.0 &= !Self::NAPOT_64K_MASK;
+        if contiguous {
+            self.0 |= Self::NAPOT | Self::NAPOT_64K;
+        } else {
+            self.0 &= !Self::NAPOT;
+        }
+    }
+
+    const LOCKABLE: bool = L::LOCKED.is_some();
+    fn is_locked(&self) -> bool {
+        match L::LOCKED {
+            Some(bit) => self.is_present() && self.0 & 1 << bit != 0,
+            None => false,
+        }
+    }
+    fn set_locked(&mut self, locked: bool) {
+        let Some(bit) = L::LOCKED else {
+            return;
+        };
+        self.clear_reserved();
+        if locked {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
+        }
+    }
+    const WP_REASONS: WpReasons = WpReasons::stored_at(Self::WP_AT);
+    fn wp_reasons(&self) -> WpReasons {
+        if !self.is_present() {
+            return WpReasons::empty();
+        }
+        WpReasons::from_entry(self.0, Self::WP_AT)
+    }
+    fn set_wp_reasons(&mut self, reasons: WpReasons) {
+        let reasons = reasons & Self::WP_REASONS;
+        let Some(writable) = crate::write_access(self.flags(), self.wp_reasons(), reasons) else {
+            return;
+        };
+        self.clear_reserved();
+        self.0 &= !(WpReasons::all().to_entry(Self::WP_AT) | PTEFlags::W.bits() as u64);
+        self.0 |= reasons.to_entry(Self::WP_AT);
+        if writable {
+            self.0 |= PTEFlags::W.bits() as u64;
+        }
+    }
+    // All the bits but V are free for software when it is clear, including
+    // the PBMT and N bits, so the common layout is used as is.
+    fn payload(&self) -> Option<NonPresentPayload> {
+        if self.is_present() {
+            return None;
+        }
+        NonPresentPayload::from_bits(self.0)
+    }
+    fn set_payload(&mut self, payload: NonPresentPayload) {
+        self.0 = payload.to_bits();
+    }
+    fn new_absent(absent: AbsentEntry) -> Self {
+        let mut pte = Self::empty();
+        pte.set_payload(absent.into());
+        pte
     }
 }
 
-impl fmt::Debug for Rv64PTE {
+impl<L: SoftBitLayout> fmt::Debug for Rv64PTE<L> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut f = f.debug_struct("Rv64PTE");
         f.field("raw", &self.0)
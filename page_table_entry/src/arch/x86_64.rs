@@ -1,11 +1,12 @@
 //! x86 page table entries on 64-bit paging.
 
-use core::fmt;
+use core::{fmt, marker::PhantomData};
 use memory_addr::PhysAddr;
 
 pub use x86_64::structures::paging::page_table::PageTableFlags as PTF;
 
-use crate::{GenericPTE, MappingFlags};
+use crate::{AbsentEntry, AccessContext, AccessType, GenericPTE, MappingFlags};
+use crate::{NonPresentPayload, debug_check_huge};
 
 impl From<PTF> for MappingFlags {
     fn from(f: PTF) -> Self {
@@ -51,54 +52,402 @@ impl From<MappingFlags> for PTF {
     }
 }
 
+/// An x86 memory type, which can be programmed in an entry of the page
+/// attribute table (PAT).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MemType {
+    /// Uncacheable (UC), e.g. for device memory.
+    Uncacheable = 0,
+    /// Write-combining (WC).
+    WriteCombining = 1,
+    /// Write-through (WT).
+    WriteThrough = 4,
+    /// Write-protected (WP).
+    WriteProtected = 5,
+    /// Write-back (WB), for normal memory.
+    WriteBack = 6,
+    /// Uncached (UC-), which the MTRRs can turn into write-combining.
+    UncachedMinus = 7,
+}
+
+/// The memory types programmed in the 8 entries of the page attribute table
+/// (the `IA32_PAT` MSR).
+///
+/// The PWT, PCD and PAT bits of a leaf entry select one of these entries.
+/// [`X64PTE`] takes a layout as a type parameter to choose the bits for the
+/// memory type requested by [`MappingFlags`]:
+///
+/// - The first [`MemType::Uncacheable`] entry for [`MappingFlags::DEVICE`].
+/// - [`MemType::WriteCombining`] for [`MappingFlags::UNCACHED`], or the last
+///   [`MemType::Uncacheable`] entry if the layout has none.
+/// - [`MemType::WriteBack`] otherwise.
+///
+/// [`GenericPTE::flags`] reads the first [`MemType::Uncacheable`] entry back
+/// as [`MappingFlags::DEVICE`], and the other uncached entries (UC, WC and
+/// UC-) as [`MappingFlags::UNCACHED`]. With a single UC entry and no WC one,
+/// uncached pages then read back as device memory.
+///
+/// The layout must contain [`MemType::WriteBack`] and [`MemType::Uncacheable`],
+/// which is checked at compile time when the entry type is used.
+pub trait PatLayout: Send + Sync + 'static {
+    /// The memory type of each entry.
+    const ENTRIES: [MemType; 8];
+
+    /// Returns the value to program in the `IA32_PAT` MSR for this layout.
+    fn msr_value() -> u64 {
+        Self::ENTRIES
+            .iter()
+            .enumerate()
+            .fold(0, |msr, (i, &ty)| msr | (ty as u64) << (i * 8))
+    }
+}
+
+/// The default layout of the page attribute table after reset, which most
+/// firmware keeps: WB, WT, UC-, UC, repeated.
+#[derive(Debug, Clone, Copy)]
+pub struct DefaultPat;
+
+impl PatLayout for DefaultPat {
+    const ENTRIES: [MemType; 8] = {
+        use MemType::*;
+        [
+            WriteBack,
+            WriteThrough,
+            UncachedMinus,
+            Uncacheable,
+            WriteBack,
+            WriteThrough,
+            UncachedMinus,
+            Uncacheable,
+        ]
+    };
+}
+
+/// The layout of the page attribute table programmed by Linux: WB, WC, UC-,
+/// UC, WB, WP, UC-, WT.
+#[derive(Debug, Clone, Copy)]
+pub struct LinuxPat;
+
+impl PatLayout for LinuxPat {
+    const ENTRIES: [MemType; 8] = {
+        use MemType::*;
+        [
+            WriteBack,
+            WriteCombining,
+            UncachedMinus,
+            Uncacheable,
+            WriteBack,
+            WriteProtected,
+            UncachedMinus,
+            WriteThrough,
+        ]
+    };
+}
+
 /// An x86_64 page table entry.
-#[derive(Clone, Copy)]
+///
+/// The memory types are encoded as given by the PAT layout `P`. Bit 7 is the
+/// page size bit above the last level, and the PAT bit of 4K pages, so
+/// [`GenericPTE::paddr`] and [`GenericPTE::flags`] read the entries with it
+/// as huge pages: the leaf entries of the last level are read with
+/// [`GenericPTE::leaf_paddr`] and [`GenericPTE::leaf_flags`].
+///
+/// `NX` tells whether `IA32_EFER.NXE` is set. When it is clear, the XD bit
+/// (63) is reserved and a present entry with it set faults on any access, so
+/// the entries never set it: pages without [`MappingFlags::EXECUTE`] are
+/// marked with an ignored bit instead, for [`GenericPTE::flags`] to read them
+/// back as mapped. The hardware still executes them, as
+/// [`GenericPTE::permits`] reports.
 #[repr(transparent)]
-pub struct X64PTE(u64);
+pub struct X64PTE<P: PatLayout = DefaultPat, const NX: bool = true>(u64, PhantomData<P>);
 
-impl X64PTE {
+impl<P: PatLayout, const NX: bool> Clone for X64PTE<P, NX> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: PatLayout, const NX: bool> Copy for X64PTE<P, NX> {}
+
+impl<P: PatLayout, const NX: bool> X64PTE<P, NX> {
     const PHYS_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000; // bits 12..52
+    const PWT: u64 = 1 << 3;
+    const PCD: u64 = 1 << 4;
+    /// The PAT bit of 4K pages, which is the page size bit in the entries
+    /// above the last level.
+    const PAT_4K: u64 = 1 << 7;
+    /// The PAT bit of 2M and 1G pages, the lowest bit of the address of 4K
+    /// pages and tables.
+    const PAT_HUGE: u64 = 1 << 12;
+    /// The bits of the tag (see [`GenericPTE::tag`]), among the bits 52..59
+    /// ignored by the hardware.
+    const TAG_SHIFT: u32 = 52;
+    const TAG_MASK: u64 = 0xf << Self::TAG_SHIFT;
+    /// The lock of leaf entries (see [`GenericPTE::is_locked`]), another bit
+    /// ignored by the hardware.
+    const LOCKED: u64 = 1 << 58;
+    /// Set instead of XD in the leaf entries without
+    /// [`MappingFlags::EXECUTE`] when `NX` is false.
+    const NO_EXECUTE_SW: u64 = 1 << 57;
+    /// The XD bit if it is available.
+    const NO_EXECUTE: u64 = if NX { PTF::NO_EXECUTE.bits() } else { 0 };
+    /// The bits of a leaf entry of any size written by
+    /// [`GenericPTE::set_flags`], [`GenericPTE::set_paddr`] and the accessed
+    /// and dirty setters. The others are kept, such as G and the protection
+    /// key.
+    const MANAGED: u64 = Self::PHYS_ADDR_MASK
+        | PTF::PRESENT.bits()
+        | PTF::WRITABLE.bits()
+        | PTF::USER_ACCESSIBLE.bits()
+        | Self::PWT
+        | Self::PCD
+        | PTF::ACCESSED.bits()
+        | PTF::DIRTY.bits()
+        | Self::PAT_4K
+        | Self::NO_EXECUTE
+        | if NX { 0 } else { Self::NO_EXECUTE_SW };
+    const LAYOUT: () = {
+        assert!(
+            Self::index_of(MemType::WriteBack).is_some(),
+            "the PAT layout has no write-back entry"
+        );
+        assert!(
+            Self::index_of(MemType::Uncacheable).is_some(),
+            "the PAT layout has no uncacheable entry"
+        );
+    };
 
     /// Creates an empty descriptor with all bits set to zero.
     pub const fn empty() -> Self {
-        Self(0)
+        Self(0, PhantomData)
     }
-}
 
-impl GenericPTE for X64PTE {
-    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
-        let mut flags = PTF::from(flags);
+    /// Returns the memory type of a leaf entry, which maps a huge page if
+    /// `is_huge` is `true` (see [`GenericPTE::leaf_paddr`]).
+    pub fn mem_type(&self, is_huge: bool) -> MemType {
+        P::ENTRIES[self.pat_index(is_huge)]
+    }
+
+    /// Sets the memory type of a leaf entry, which maps a huge page if
+    /// `is_huge` is `true`, with any memory type of the layout (e.g.
+    /// [`MemType::WriteThrough`]).
+    ///
+    /// Returns `false` and leaves the entry unchanged if the layout has no
+    /// entry for `ty`.
+    pub fn set_mem_type(&mut self, ty: MemType, is_huge: bool) -> bool {
+        let Some(index) = Self::index_of(ty) else {
+            return false;
+        };
+        let clear = Self::PWT | Self::PCD;
+        let clear = if is_huge {
+            clear | Self::PAT_HUGE
+        } else {
+            clear | Self::PAT_4K
+        };
+        self.0 = (self.0 & !clear) | Self::mem_type_bits(index, is_huge);
+        true
+    }
+
+    /// Returns the entry of the layout selected by the PWT, PCD and PAT bits
+    /// of a leaf entry.
+    fn pat_index(&self, is_huge: bool) -> usize {
+        let pat = if is_huge {
+            Self::PAT_HUGE
+        } else {
+            Self::PAT_4K
+        };
+        ((self.0 & pat != 0) as usize) << 2
+            | ((self.0 & Self::PCD != 0) as usize) << 1
+            | (self.0 & Self::PWT != 0) as usize
+    }
+
+    /// Returns the first entry of the layout with the memory type `ty`.
+    const fn index_of(ty: MemType) -> Option<usize> {
+        let mut i = 0;
+        while i < P::ENTRIES.len() {
+            if P::ENTRIES[i] as u8 == ty as u8 {
+                return Some(i);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Returns the last entry of the layout with the memory type `ty`.
+    const fn last_index_of(ty: MemType) -> Option<usize> {
+        let mut i = P::ENTRIES.len();
+        while i > 0 {
+            i -= 1;
+            if P::ENTRIES[i] as u8 == ty as u8 {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Returns the PWT, PCD and PAT bits selecting the entry `index` of the
+    /// layout.
+    fn mem_type_bits(index: usize, is_huge: bool) -> u64 {
+        let mut bits = 0;
+        if index & 1 != 0 {
+            bits |= Self::PWT;
+        }
+        if index & 2 != 0 {
+            bits |= Self::PCD;
+        }
+        if index & 4 != 0 {
+            bits |= if is_huge {
+                Self::PAT_HUGE
+            } else {
+                Self::PAT_4K
+            };
+        }
+        bits
+    }
+
+    /// Returns the attribute bits of a leaf entry with `flags`.
+    fn leaf_bits(flags: MappingFlags, is_huge: bool) -> u64 {
+        let () = Self::LAYOUT;
+        let mut attr = PTF::from(flags) - (PTF::NO_CACHE | PTF::WRITE_THROUGH);
+        if attr.is_empty() {
+            return 0;
+        }
         if is_huge {
-            flags |= PTF::HUGE_PAGE;
+            attr |= PTF::HUGE_PAGE;
+        }
+        let mut bits = 0;
+        if !NX && attr.contains(PTF::NO_EXECUTE) {
+            attr -= PTF::NO_EXECUTE;
+            bits |= Self::NO_EXECUTE_SW;
+        }
+        let index = if flags.contains(MappingFlags::DEVICE) {
+            Self::index_of(MemType::Uncacheable)
+        } else if flags.contains(MappingFlags::UNCACHED) {
+            Self::index_of(MemType::WriteCombining).or(Self::last_index_of(MemType::Uncacheable))
+        } else {
+            Self::index_of(MemType::WriteBack)
+        };
+        bits | attr.bits() | Self::mem_type_bits(index.unwrap(), is_huge)
+    }
+
+    /// Returns the mask of the physical address bits.
+    fn paddr_mask(is_huge: bool) -> u64 {
+        if is_huge {
+            Self::PHYS_ADDR_MASK & !Self::PAT_HUGE
+        } else {
+            Self::PHYS_ADDR_MASK
         }
-        Self(flags.bits() | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK))
+    }
+}
+
+impl<P: PatLayout, const NX: bool> GenericPTE for X64PTE<P, NX> {
+    type ArchFlags = PTF;
+
+    const TAG_BITS: u32 = 4;
+    const LOCKABLE: bool = true;
+
+    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
+        debug_check_huge(paddr, is_huge);
+        let paddr = paddr.as_usize() as u64 & Self::paddr_mask(is_huge);
+        Self(Self::leaf_bits(flags, is_huge) | paddr, PhantomData)
     }
     fn new_table(paddr: PhysAddr) -> Self {
-        let flags = PTF::PRESENT | PTF::WRITABLE | PTF::USER_ACCESSIBLE;
-        Self(flags.bits() | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK))
+        // The permissions are added by `widen_table` as needed below.
+        let flags = PTF::PRESENT.bits() | Self::NO_EXECUTE;
+        Self(
+            flags | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK),
+            PhantomData,
+        )
+    }
+    fn widen_table(&mut self, flags: MappingFlags) -> bool {
+        let old = self.0;
+        if flags.contains(MappingFlags::WRITE) {
+            self.0 |= PTF::WRITABLE.bits();
+        }
+        if flags.contains(MappingFlags::USER) {
+            self.0 |= PTF::USER_ACCESSIBLE.bits();
+        }
+        if flags.contains(MappingFlags::EXECUTE) {
+            self.0 &= !PTF::NO_EXECUTE.bits();
+        }
+        self.0 != old
+    }
+    fn narrow_table(&mut self, flags: MappingFlags) -> bool {
+        let old = self.0;
+        self.0 &= !(PTF::WRITABLE | PTF::USER_ACCESSIBLE | PTF::NO_EXECUTE).bits();
+        self.0 |= Self::NO_EXECUTE;
+        self.widen_table(flags);
+        self.0 != old
     }
     fn paddr(&self) -> PhysAddr {
-        PhysAddr::from((self.0 & Self::PHYS_ADDR_MASK) as usize)
+        self.leaf_paddr(self.is_huge())
     }
     fn flags(&self) -> MappingFlags {
-        PTF::from_bits_truncate(self.0).into()
+        self.leaf_flags(self.is_huge())
     }
     fn set_paddr(&mut self, paddr: PhysAddr) {
-        self.0 = (self.0 & !Self::PHYS_ADDR_MASK) | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK)
+        self.set_leaf_paddr(paddr, self.is_huge())
     }
-    fn set_flags(&mut self, flags: MappingFlags, is_huge: bool) {
-        let mut flags = PTF::from(flags);
-        if is_huge {
-            flags |= PTF::HUGE_PAGE;
+    fn leaf_paddr(&self, is_huge: bool) -> PhysAddr {
+        PhysAddr::from((self.0 & Self::paddr_mask(is_huge)) as usize)
+    }
+    fn leaf_flags(&self, is_huge: bool) -> MappingFlags {
+        let attr = PTF::from_bits_truncate(self.0) - (PTF::NO_CACHE | PTF::WRITE_THROUGH);
+        let mut flags = MappingFlags::from(attr);
+        if flags.is_empty() {
+            return flags;
+        }
+        if !NX && self.0 & Self::NO_EXECUTE_SW != 0 {
+            flags -= MappingFlags::EXECUTE;
         }
+        let index = self.pat_index(is_huge);
+        match P::ENTRIES[index] {
+            _ if Some(index) == Self::index_of(MemType::Uncacheable) => {
+                flags | MappingFlags::DEVICE
+            }
+            MemType::Uncacheable | MemType::WriteCombining | MemType::UncachedMinus => {
+                flags | MappingFlags::UNCACHED
+            }
+            _ => flags,
+        }
+    }
+    fn set_leaf_paddr(&mut self, paddr: PhysAddr, is_huge: bool) {
+        let mask = Self::paddr_mask(is_huge);
+        self.0 = (self.0 & !mask) | (paddr.as_usize() as u64 & mask)
+    }
+    fn set_flags(&mut self, flags: MappingFlags, is_huge: bool) {
+        let paddr = self.0 & Self::paddr_mask(is_huge);
+        let kept = if flags.is_empty() {
+            0
+        } else {
+            self.unknown_bits() as u64
+        };
+        self.0 = Self::leaf_bits(flags, is_huge) | kept | paddr;
+    }
+
+    fn set_flags_arch(&mut self, flags: PTF) {
         self.0 = (self.0 & Self::PHYS_ADDR_MASK) | flags.bits()
     }
 
     fn bits(self) -> usize {
         self.0 as usize
     }
+    fn from_bits(bits: usize) -> Self {
+        Self(bits as u64, PhantomData)
+    }
+    fn unknown_bits(&self) -> usize {
+        if !self.is_present() {
+            return 0;
+        }
+        (self.0 & !Self::MANAGED) as usize
+    }
+    // Only XD is checked, when it is reserved.
+    fn is_well_formed(&self) -> bool {
+        NX || !self.is_present() || self.0 & PTF::NO_EXECUTE.bits() == 0
+    }
     fn is_unused(&self) -> bool {
-        self.0 == 0
+        crate::is_cleared(self.0)
     }
     fn is_present(&self) -> bool {
         PTF::from_bits_truncate(self.0).contains(PTF::PRESENT)
@@ -123,15 +472,105 @@ impl GenericPTE for X64PTE {
             self.0 &= !PTF::ACCESSED.bits();
         }
     }
+    // Only above the last level, where bit 7 is the page size bit rather
+    // than the PAT bit.
     fn is_huge(&self) -> bool {
-        PTF::from_bits_truncate(self.0).contains(PTF::HUGE_PAGE)
+        self.0 & PTF::HUGE_PAGE.bits() != 0
     }
     fn clear(&mut self) {
-        self.0 = 0
+        self.0 = crate::CLEARED
+    }
+    fn is_global(&self) -> bool {
+        PTF::from_bits_truncate(self.0).contains(PTF::PRESENT | PTF::GLOBAL)
+    }
+    fn tag(&self) -> u8 {
+        ((self.0 & Self::TAG_MASK) >> Self::TAG_SHIFT) as u8
+    }
+    fn set_tag(&mut self, tag: u8) {
+        self.0 = (self.0 & !Self::TAG_MASK) | ((tag as u64) << Self::TAG_SHIFT & Self::TAG_MASK);
+    }
+    fn is_locked(&self) -> bool {
+        self.is_present() && self.0 & Self::LOCKED != 0
+    }
+    fn set_locked(&mut self, locked: bool) {
+        if locked {
+            self.0 |= Self::LOCKED;
+        } else {
+            self.0 &= !Self::LOCKED;
+        }
+    }
+    // Reads are always allowed and writes need R/W. The kernel cannot touch
+    // user pages with SMAP and `EFLAGS.AC` clear, nor execute them with SMEP,
+    // and writes to read-only pages only with `CR0.WP` clear.
+    fn permits(&self, access: AccessType, ctx: AccessContext) -> bool {
+        let flags = PTF::from_bits_truncate(self.0);
+        if !flags.contains(PTF::PRESENT) {
+            return false;
+        }
+        let user_page = flags.contains(PTF::USER_ACCESSIBLE);
+        let writable = flags.contains(PTF::WRITABLE);
+        // Without `NX`, even the pages mapped without `EXECUTE`.
+        let executable = !NX || !flags.contains(PTF::NO_EXECUTE);
+        if ctx.user {
+            return user_page
+                && match access {
+                    AccessType::Read => true,
+                    AccessType::Write => writable,
+                    AccessType::Execute => executable,
+                };
+        }
+        match access {
+            AccessType::Read => !user_page || ctx.kernel_accesses_user,
+            AccessType::Write => {
+                (!user_page || ctx.kernel_accesses_user) && (writable || !ctx.write_protect)
+            }
+            AccessType::Execute => executable && (!user_page || ctx.kernel_executes_user),
+        }
+    }
+    // U/S and R/W must be set at every level, and XD at none.
+    fn limited_by(&self, table: &Self) -> Self {
+        let table = PTF::from_bits_truncate(table.0);
+        let mut pte = *self;
+        for flag in [PTF::USER_ACCESSIBLE, PTF::WRITABLE] {
+            if !table.contains(flag) {
+                pte.0 &= !flag.bits();
+            }
+        }
+        if table.contains(PTF::NO_EXECUTE) {
+            pte.0 |= PTF::NO_EXECUTE.bits();
+        }
+        pte
+    }
+    // All the bits but P are ignored when it is clear, without reserved bit
+    // checks, so XD may be set even if `IA32_EFER.NXE` is clear. But the
+    // address bits may still be used speculatively to read the L1 data cache
+    // (L1TF), so they are stored inverted for the entries carrying data: a
+    // small payload then points above the physical memory.
+    fn payload(&self) -> Option<NonPresentPayload> {
+        if self.is_present() {
+            return None;
+        }
+        match NonPresentPayload::from_bits(self.0) {
+            Some(payload @ (NonPresentPayload::Empty | NonPresentPayload::Poisoned)) => {
+                Some(payload)
+            }
+            _ => AbsentEntry::from_bits(self.0 ^ Self::PHYS_ADDR_MASK).map(Into::into),
+        }
+    }
+    fn set_payload(&mut self, payload: NonPresentPayload) {
+        self.0 = match payload.absent() {
+            Some(absent) => absent.to_bits() ^ Self::PHYS_ADDR_MASK,
+            None => payload.to_bits(),
+        };
+    }
+    fn new_absent(absent: AbsentEntry) -> Self {
+        let mut pte = Self::empty();
+        pte.set_payload(absent.into());
+        pte
     }
 }
 
-impl fmt::Debug for X64PTE {
+impl<P: PatLayout, const NX: bool> fmt::Debug for X64PTE<P, NX> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut f = f.debug_struct("X64PTE");
         f.field("raw", &self.0)
@@ -0,0 +1,277 @@
+//! Page table entries stored in a given byte order, e.g. the tables of a
+//! big-endian machine or a dump taken on a machine of the other endianness.
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use memory_addr::PhysAddr;
+
+use crate::{
+    AbsentEntry, AccessContext, AccessType, GenericPTE, MappingFlags, NonPresentPayload, WpReasons,
+};
+
+/// The order of the bytes of an entry in the memory of a page table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// The least significant byte first.
+    Little,
+    /// The most significant byte first.
+    Big,
+}
+
+impl ByteOrder {
+    /// The byte order of the target.
+    pub const NATIVE: Self = if cfg!(target_endian = "big") {
+        Self::Big
+    } else {
+        Self::Little
+    };
+
+    /// Returns the value of the bits `raw` stored in this order, as read from
+    /// memory by the target. It compiles to nothing for the native order.
+    pub const fn to_native(self, raw: u64) -> u64 {
+        match self {
+            Self::Little => u64::from_le(raw),
+            Self::Big => u64::from_be(raw),
+        }
+    }
+
+    /// Returns the bits to store so that memory holds `bits` in this order.
+    pub const fn from_native(self, bits: u64) -> u64 {
+        match self {
+            Self::Little => bits.to_le(),
+            Self::Big => bits.to_be(),
+        }
+    }
+
+    /// Returns whether both orders are the same, in const contexts.
+    pub const fn is(self, other: Self) -> bool {
+        self as u8 == other as u8
+    }
+}
+
+/// The byte order of an [`EndianPTE`], as a type.
+pub trait Endianness: 'static {
+    /// The byte order.
+    const ORDER: ByteOrder;
+}
+
+/// Little-endian entries, as read by the MMU of every supported architecture
+/// in its usual configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct LittleEndian;
+
+impl Endianness for LittleEndian {
+    const ORDER: ByteOrder = ByteOrder::Little;
+}
+
+/// Big-endian entries.
+#[derive(Debug, Clone, Copy)]
+pub struct BigEndian;
+
+impl Endianness for BigEndian {
+    const ORDER: ByteOrder = ByteOrder::Big;
+}
+
+/// An entry of the format `PTE` stored in the byte order `E`, which may not
+/// be the one of the target.
+///
+/// Every method loads the entry from the stored bytes, converting them to
+/// the native order, and those changing it store it back in the order `E`. The
+/// conversions compile to nothing when `E` is the native order, but the
+/// entries of `PTE` are then the same and should be used instead.
+/// [`GenericPTE::bits`] and [`GenericPTE::from_bits`] take the native value,
+/// and [`GenericPTE::to_bytes`] in the order `E` returns the stored bytes.
+#[repr(transparent)]
+pub struct EndianPTE<PTE, E> {
+    raw: u64,
+    _phantom: PhantomData<fn() -> (PTE, E)>,
+}
+
+impl<PTE: GenericPTE, E: Endianness> EndianPTE<PTE, E> {
+    /// Stores `pte` in the order `E`.
+    pub fn new(pte: PTE) -> Self {
+        Self::from_native(pte.bits() as u64)
+    }
+
+    /// Returns the entry, in the native order.
+    pub fn get(self) -> PTE {
+        PTE::from_bits(E::ORDER.to_native(self.raw) as usize)
+    }
+
+    /// Returns the bits as they are stored in memory.
+    pub const fn raw(self) -> u64 {
+        self.raw
+    }
+
+    const fn from_native(bits: u64) -> Self {
+        Self {
+            raw: E::ORDER.from_native(bits),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Runs `f` on the entry in the native order, and stores it back.
+    fn update<R>(&mut self, f: impl FnOnce(&mut PTE) -> R) -> R {
+        let mut pte = self.get();
+        let ret = f(&mut pte);
+        *self = Self::new(pte);
+        ret
+    }
+}
+
+impl<PTE, E> Clone for EndianPTE<PTE, E> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<PTE, E> Copy for EndianPTE<PTE, E> {}
+
+impl<PTE: GenericPTE, E: Endianness> fmt::Debug for EndianPTE<PTE, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({:?}-endian)", self.get(), E::ORDER)
+    }
+}
+
+impl<PTE: GenericPTE, E: Endianness> GenericPTE for EndianPTE<PTE, E> {
+    type ArchFlags = PTE::ArchFlags;
+
+    const CONTIGUOUS_HINT: bool = PTE::CONTIGUOUS_HINT;
+    const TAG_BITS: u32 = PTE::TAG_BITS;
+    const LOCKABLE: bool = PTE::LOCKABLE;
+    const WP_REASONS: WpReasons = PTE::WP_REASONS;
+    const BYTE_ORDER: ByteOrder = E::ORDER;
+
+    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
+        Self::new(PTE::new_page(paddr, flags, is_huge))
+    }
+    fn new_table(paddr: PhysAddr) -> Self {
+        Self::new(PTE::new_table(paddr))
+    }
+    fn widen_table(&mut self, flags: MappingFlags) -> bool {
+        self.update(|pte| pte.widen_table(flags))
+    }
+    fn narrow_table(&mut self, flags: MappingFlags) -> bool {
+        self.update(|pte| pte.narrow_table(flags))
+    }
+    fn paddr(&self) -> PhysAddr {
+        self.get().paddr()
+    }
+    fn flags(&self) -> MappingFlags {
+        self.get().flags()
+    }
+    fn set_paddr(&mut self, paddr: PhysAddr) {
+        self.update(|pte| pte.set_paddr(paddr))
+    }
+    fn leaf_paddr(&self, is_huge: bool) -> PhysAddr {
+        self.get().leaf_paddr(is_huge)
+    }
+    fn leaf_flags(&self, is_huge: bool) -> MappingFlags {
+        self.get().leaf_flags(is_huge)
+    }
+    fn set_leaf_paddr(&mut self, paddr: PhysAddr, is_huge: bool) {
+        self.update(|pte| pte.set_leaf_paddr(paddr, is_huge))
+    }
+    fn set_flags(&mut self, flags: MappingFlags, is_huge: bool) {
+        self.update(|pte| pte.set_flags(flags, is_huge))
+    }
+    fn set_flags_arch(&mut self, flags: Self::ArchFlags) {
+        self.update(|pte| pte.set_flags_arch(flags))
+    }
+    fn bits(self) -> usize {
+        self.get().bits()
+    }
+    fn from_bits(bits: usize) -> Self {
+        Self::from_native(bits as u64)
+    }
+    fn unknown_bits(&self) -> usize {
+        self.get().unknown_bits()
+    }
+    fn is_well_formed(&self) -> bool {
+        self.get().is_well_formed()
+    }
+    fn is_unused(&self) -> bool {
+        self.get().is_unused()
+    }
+    fn is_present(&self) -> bool {
+        self.get().is_present()
+    }
+    fn is_dirty(&self) -> bool {
+        self.get().is_dirty()
+    }
+    fn set_dirty(&mut self, dirty: bool) {
+        self.update(|pte| pte.set_dirty(dirty))
+    }
+    fn is_accessed(&self) -> bool {
+        self.get().is_accessed()
+    }
+    fn set_accessed(&mut self, accessed: bool) {
+        self.update(|pte| pte.set_accessed(accessed))
+    }
+    fn is_huge(&self) -> bool {
+        self.get().is_huge()
+    }
+    fn is_table(&self) -> bool {
+        self.get().is_table()
+    }
+    fn clear(&mut self) {
+        self.update(|pte| pte.clear())
+    }
+    fn is_poisoned(&self) -> bool {
+        self.get().is_poisoned()
+    }
+    fn is_contiguous(&self) -> bool {
+        self.get().is_contiguous()
+    }
+    fn set_contiguous(&mut self, contiguous: bool) {
+        self.update(|pte| pte.set_contiguous(contiguous))
+    }
+    fn permits(&self, access: AccessType, ctx: AccessContext) -> bool {
+        self.get().permits(access, ctx)
+    }
+    fn limited_by(&self, table: &Self) -> Self {
+        Self::new(self.get().limited_by(&table.get()))
+    }
+    fn is_global(&self) -> bool {
+        self.get().is_global()
+    }
+    fn tag(&self) -> u8 {
+        self.get().tag()
+    }
+    fn set_tag(&mut self, tag: u8) {
+        self.update(|pte| pte.set_tag(tag))
+    }
+    fn is_locked(&self) -> bool {
+        self.get().is_locked()
+    }
+    fn set_locked(&mut self, locked: bool) {
+        self.update(|pte| pte.set_locked(locked))
+    }
+    fn wp_reasons(&self) -> WpReasons {
+        self.get().wp_reasons()
+    }
+    fn set_wp_reasons(&mut self, reasons: WpReasons) {
+        self.update(|pte| pte.set_wp_reasons(reasons))
+    }
+    fn payload(&self) -> Option<NonPresentPayload> {
+        self.get().payload()
+    }
+    fn set_payload(&mut self, payload: NonPresentPayload) {
+        self.update(|pte| pte.set_payload(payload))
+    }
+    fn new_absent(absent: AbsentEntry) -> Self {
+        Self::new(PTE::new_absent(absent))
+    }
+    fn absent(&self) -> Option<AbsentEntry> {
+        self.get().absent()
+    }
+
+    const PRESENT_INACCESSIBLE: bool = PTE::PRESENT_INACCESSIBLE;
+    fn set_inaccessible(&mut self) {
+        self.update(|pte| pte.set_inaccessible())
+    }
+    fn inaccessible(&self) -> Option<PhysAddr> {
+        self.get().inaccessible()
+    }
+}
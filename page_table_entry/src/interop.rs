@@ -0,0 +1,157 @@
+//! Conversions between [`MappingFlags`] and the permissions of ELF segments,
+//! as found in the `p_flags` field of a program header by loaders such as the
+//! `object` or `xmas-elf` crates, and from page table entries to the entries
+//! of the Linux `pagemap` file.
+
+use crate::{GenericPTE, MappingFlags, NonPresentPayload};
+
+/// The permissions of an ELF segment, i.e. the raw `p_flags` of its program
+/// header.
+///
+/// Only [`ElfFlags::PF_R`], [`ElfFlags::PF_W`] and [`ElfFlags::PF_X`] are
+/// converted, the OS and processor specific bits are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ElfFlags(pub u32);
+
+/// The choices made when converting [`ElfFlags`] into [`MappingFlags`].
+///
+/// The default converts the permissions as they are.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ElfPolicy {
+    /// Makes executable segments readable, as most architectures cannot map
+    /// pages that can be executed but not read.
+    pub read_if_execute: bool,
+    /// Adds [`MappingFlags::USER`], for segments of user programs.
+    pub user: bool,
+}
+
+impl ElfFlags {
+    /// The segment is executable.
+    pub const PF_X: u32 = 1;
+    /// The segment is writable.
+    pub const PF_W: u32 = 2;
+    /// The segment is readable.
+    pub const PF_R: u32 = 4;
+
+    /// Converts into [`MappingFlags`], following `policy`.
+    pub const fn to_mapping_flags(self, policy: ElfPolicy) -> MappingFlags {
+        let mut flags = MappingFlags::empty();
+        if self.0 & Self::PF_R != 0 || (policy.read_if_execute && self.0 & Self::PF_X != 0) {
+            flags = flags.union(MappingFlags::READ);
+        }
+        if self.0 & Self::PF_W != 0 {
+            flags = flags.union(MappingFlags::WRITE);
+        }
+        if self.0 & Self::PF_X != 0 {
+            flags = flags.union(MappingFlags::EXECUTE);
+        }
+        if policy.user {
+            flags = flags.union(MappingFlags::USER);
+        }
+        flags
+    }
+
+    /// Converts the permissions of `flags`, ignoring the other flags.
+    pub const fn from_mapping_flags(flags: MappingFlags) -> Self {
+        let mut bits = 0;
+        if flags.contains(MappingFlags::READ) {
+            bits |= Self::PF_R;
+        }
+        if flags.contains(MappingFlags::WRITE) {
+            bits |= Self::PF_W;
+        }
+        if flags.contains(MappingFlags::EXECUTE) {
+            bits |= Self::PF_X;
+        }
+        Self(bits)
+    }
+}
+
+/// Converts with the default [`ElfPolicy`].
+impl From<ElfFlags> for MappingFlags {
+    fn from(flags: ElfFlags) -> Self {
+        flags.to_mapping_flags(ElfPolicy::default())
+    }
+}
+
+impl From<MappingFlags> for ElfFlags {
+    fn from(flags: MappingFlags) -> Self {
+        Self::from_mapping_flags(flags)
+    }
+}
+
+/// An entry of the Linux `/proc/<pid>/pagemap` file, describing a 4K page of
+/// a process.
+///
+/// The layout is the one of
+/// <https://docs.kernel.org/admin-guide/mm/pagemap.html>. Only the bits that
+/// a page table entry tells are set by [`PagemapEntry::from_pte`]: whether
+/// the page is mapped is known, but not whether it is exclusive to the
+/// process, nor whether a present page is backed by a file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PagemapEntry(pub u64);
+
+impl PagemapEntry {
+    /// Bits 0..55: the page frame number of a present page.
+    pub const PFN_MASK: u64 = (1 << 55) - 1;
+    /// Bits 0..5: the swap type of a swapped page, followed by its swap
+    /// offset up to bit 55.
+    pub const SWAP_TYPE_MASK: u64 = (1 << 5) - 1;
+    /// The page was written since the soft-dirty bits were cleared.
+    pub const SOFT_DIRTY: u64 = 1 << 55;
+    /// The page is mapped only by this process.
+    pub const EXCLUSIVE: u64 = 1 << 56;
+    /// The page is backed by a file, or is shared anonymous memory.
+    pub const FILE: u64 = 1 << 61;
+    /// The page is swapped out.
+    pub const SWAPPED: u64 = 1 << 62;
+    /// The page is present.
+    pub const PRESENT: u64 = 1 << 63;
+
+    /// Describes the 4K page at `offset` in the page mapped by `entry`, which
+    /// may be a huge page.
+    ///
+    /// - A present page has its frame number, and is soft-dirty if the dirty
+    ///   bit of the entry is set (see [`GenericPTE::is_dirty`]).
+    /// - A swapped page ([`NonPresentPayload::Swap`]) has its slot in bits
+    ///   0..55, which are the swap type and offset if the slot is a Linux
+    ///   swap entry. Higher bits of the slot are dropped.
+    /// - A page to be loaded on demand ([`NonPresentPayload::FileToken`]) is
+    ///   only [`PagemapEntry::FILE`].
+    /// - Other entries are zero, like the pages that are not mapped.
+    pub fn from_pte<PTE: GenericPTE>(entry: &PTE, offset: usize) -> Self {
+        if entry.is_present() {
+            let pfn = (entry.paddr().as_usize() + offset) as u64 >> 12;
+            let soft_dirty = if entry.is_dirty() {
+                Self::SOFT_DIRTY
+            } else {
+                0
+            };
+            return Self(Self::PRESENT | soft_dirty | pfn & Self::PFN_MASK);
+        }
+        match entry.payload() {
+            Some(NonPresentPayload::Swap(slot)) => Self(Self::SWAPPED | slot & Self::PFN_MASK),
+            Some(NonPresentPayload::FileToken(_)) => Self(Self::FILE),
+            _ => Self(0),
+        }
+    }
+
+    /// Returns the page frame number of a present page.
+    pub const fn pfn(self) -> Option<u64> {
+        if self.0 & Self::PRESENT != 0 {
+            Some(self.0 & Self::PFN_MASK)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the swap type and offset of a swapped page.
+    pub const fn swap(self) -> Option<(u64, u64)> {
+        if self.0 & Self::SWAPPED != 0 {
+            let slot = self.0 & Self::PFN_MASK;
+            Some((slot & Self::SWAP_TYPE_MASK, slot >> 5))
+        } else {
+            None
+        }
+    }
+}
@@ -1,18 +1,22 @@
 #![cfg_attr(not(test), no_std)]
-#![cfg_attr(doc, feature(doc_auto_cfg))]
+#![cfg_attr(doc, feature(doc_cfg))]
 #![doc = include_str!("../README.md")]
 
 mod arch;
+mod endian;
+#[cfg(feature = "interop")]
+pub mod interop;
 
 use core::fmt::{self, Debug, Display};
 use memory_addr::PhysAddr;
 
 pub use self::arch::*;
+pub use self::endian::{BigEndian, ByteOrder, EndianPTE, Endianness, LittleEndian};
 
 bitflags::bitflags! {
     /// Generic page table entry flags that indicate the corresponding mapped
     /// memory region permissions and attributes.
-    #[derive(Clone, Copy, PartialEq)]
+    #[derive(Clone, Copy, Default, PartialEq, Eq)]
     pub struct MappingFlags: usize {
         /// The memory is readable.
         const READ          = 1 << 0;
@@ -34,18 +38,110 @@ bitflags::bitflags! {
 }
 
 impl MappingFlags {
+    /// Read-only kernel data.
+    pub const fn kernel_r() -> Self {
+        Self::READ
+    }
+
+    /// Writable kernel data.
+    pub const fn kernel_rw() -> Self {
+        Self::READ.union(Self::WRITE)
+    }
+
+    /// Kernel code.
+    pub const fn kernel_rx() -> Self {
+        Self::READ.union(Self::EXECUTE)
+    }
+
+    /// Read-only user data.
+    pub const fn user_r() -> Self {
+        Self::READ.union(Self::USER)
+    }
+
+    /// Writable user data.
+    pub const fn user_rw() -> Self {
+        Self::kernel_rw().union(Self::USER)
+    }
+
+    /// User code.
+    pub const fn user_rx() -> Self {
+        Self::kernel_rx().union(Self::USER)
+    }
+
+    /// User memory with all permissions, e.g. for a JIT that does not switch
+    /// between [`user_rw`](Self::user_rw) and [`user_rx`](Self::user_rx).
+    pub const fn user_rwx() -> Self {
+        Self::user_rw().union(Self::EXECUTE)
+    }
+
+    /// Whether a mapping with these flags can be read from user mode if
+    /// `user` is `true`, or from the kernel otherwise.
+    ///
+    /// The kernel can access the memory of user mappings: architectural
+    /// protections against it (e.g. SMAP or PAN) are left to the kernel.
+    pub const fn readable_by(self, user: bool) -> bool {
+        self.contains(Self::READ) && (!user || self.contains(Self::USER))
+    }
+
+    /// Whether a mapping with these flags can be written from user mode if
+    /// `user` is `true`, or from the kernel otherwise, like
+    /// [`readable_by`](Self::readable_by).
+    ///
+    /// A copy-on-write mapping is not writable until the fault is resolved
+    /// (see [`write_needs_copy`](Self::write_needs_copy)), even if
+    /// [`WRITE`](Self::WRITE) is also set.
+    pub const fn writable_by(self, user: bool) -> bool {
+        #[cfg(feature = "COW")]
+        if self.contains(Self::COW) {
+            return false;
+        }
+        self.contains(Self::WRITE) && (!user || self.contains(Self::USER))
+    }
+
+    /// Whether a mapping with these flags can be executed from user mode if
+    /// `user` is `true`, or from the kernel otherwise.
+    ///
+    /// Unlike data accesses, the kernel never executes user mappings, and the
+    /// user mode only executes them.
+    pub const fn executable_by(self, user: bool) -> bool {
+        self.contains(Self::EXECUTE) && self.contains(Self::USER) == user
+    }
+
+    /// Whether a write from user mode if `user` is `true`, or from the kernel
+    /// otherwise, to a mapping with these flags is a copy-on-write fault: the
+    /// write is allowed once the page is copied and the flags are
+    /// [`resolve_cow`](Self::resolve_cow)d.
     #[cfg(feature = "COW")]
-    pub fn mark_cow(flags: Self) -> Self {
-        let mut flags = flags.clone();
+    pub const fn write_needs_copy(self, user: bool) -> bool {
+        self.contains(Self::COW) && (!user || self.contains(Self::USER))
+    }
+
+    /// Returns the flags to use for a copy-on-write share of a mapping with
+    /// these flags.
+    ///
+    /// Writable mappings lose [`WRITE`](Self::WRITE) and gain
+    /// [`COW`](Self::COW); other mappings are kept as is. Returns [`None`] for
+    /// mappings that must not be copied on write, i.e. device or uncached
+    /// memory, which has to stay shared.
+    #[cfg(feature = "COW")]
+    pub fn cow_of(self) -> Option<Self> {
+        if self.intersects(Self::DEVICE | Self::UNCACHED) {
+            return None;
+        }
+        let mut flags = self;
         if flags.contains(Self::WRITE) {
             flags.remove(Self::WRITE);
             flags.insert(Self::COW);
         }
-        flags
+        Some(flags)
     }
+
+    /// The inverse of [`MappingFlags::cow_of`]: restores
+    /// [`WRITE`](Self::WRITE) and clears [`COW`](Self::COW), once the page
+    /// has been copied.
     #[cfg(feature = "COW")]
-    pub fn unmark_cow(flags: Self) -> Self {
-        let mut flags = flags.clone();
+    pub fn resolve_cow(self) -> Self {
+        let mut flags = self;
         if flags.contains(Self::COW) {
             flags.remove(Self::COW);
             flags.insert(Self::WRITE);
@@ -53,8 +149,19 @@ impl MappingFlags {
         flags
     }
 
+    #[cfg(feature = "COW")]
+    #[deprecated(note = "use `MappingFlags::cow_of`, which excludes device memory")]
+    pub fn mark_cow(flags: Self) -> Self {
+        flags.cow_of().unwrap_or(flags)
+    }
+    #[cfg(feature = "COW")]
+    #[deprecated(note = "use `MappingFlags::resolve_cow`")]
+    pub fn unmark_cow(flags: Self) -> Self {
+        flags.resolve_cow()
+    }
+
     pub fn protect(&self, flags: Self) -> Self {
-        let mut flags = flags.clone();
+        let mut flags = flags;
         #[cfg(feature = "COW")]
         {
             flags |= *self & Self::COW;
@@ -64,6 +171,205 @@ impl MappingFlags {
     }
 }
 
+/// The kind of a memory access, for [`GenericPTE::permits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    /// A data read.
+    Read,
+    /// A data write.
+    Write,
+    /// An instruction fetch.
+    Execute,
+}
+
+/// The privilege of a memory access and the global controls of the CPU that
+/// change the permissions of the pages, for [`GenericPTE::permits`].
+///
+/// Each format only looks at the controls of its architecture.
+/// [`AccessContext::user`] and [`AccessContext::kernel`] give the strictest
+/// settings, with which kernels usually run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessContext {
+    /// Whether the access is made from user mode (x86 CPL 3, RISC-V U-mode,
+    /// AArch64 EL0, LoongArch PLV3). The unprivileged loads and stores of
+    /// the kernel (AArch64 `LDTR` and `STTR` without UAO) are user accesses.
+    pub user: bool,
+    /// Whether the kernel may read and write the pages of user mode: x86
+    /// SMAP disabled or `EFLAGS.AC` set, RISC-V `sstatus.SUM` set, AArch64
+    /// `PSTATE.PAN` clear. LoongArch has no such control.
+    pub kernel_accesses_user: bool,
+    /// Whether the kernel may execute the pages of user mode: x86 SMEP
+    /// disabled. RISC-V never allows it, AArch64 leaves it to the PXN bit,
+    /// and LoongArch always allows it.
+    pub kernel_executes_user: bool,
+    /// Whether writes of the kernel to read-only pages fault: x86 `CR0.WP`
+    /// set. They always fault on the other architectures.
+    pub write_protect: bool,
+    /// Whether the executable pages can be read: RISC-V `sstatus.MXR` set.
+    /// Only RISC-V has this control.
+    pub executable_readable: bool,
+}
+
+impl AccessContext {
+    /// An access from user mode.
+    pub const fn user() -> Self {
+        Self {
+            user: true,
+            kernel_accesses_user: false,
+            kernel_executes_user: false,
+            write_protect: true,
+            executable_readable: false,
+        }
+    }
+
+    /// An access from the kernel, which can neither access nor execute the
+    /// pages of user mode, nor write to read-only pages.
+    pub const fn kernel() -> Self {
+        Self {
+            user: false,
+            ..Self::user()
+        }
+    }
+}
+
+/// Assignment of the logical software flags of [`MappingFlags`] to the bits of
+/// a page table entry that are reserved for software.
+///
+/// Entry types that store software flags take a layout as a type parameter,
+/// which defaults to the assignment used by this crate (e.g.
+/// [`riscv::Rv64SoftBits`]). A kernel that already uses some of these bits for
+/// other purposes can provide its own layout.
+///
+/// Every flag must be a bit reserved for software by the architecture, and no
+/// two flags may share a bit. This is checked at compile time when the entry
+/// type is used.
+pub trait SoftBitLayout: Send + Sync + 'static {
+    /// Bit index used for [`MappingFlags::COW`].
+    const COW: u32;
+    /// Bit index used for the lock of leaf entries (see
+    /// [`GenericPTE::is_locked`]), or [`None`] if they cannot be locked, the
+    /// default.
+    const LOCKED: Option<u32> = None;
+    /// Bit index used for [`WpReasons::DIRTY_LOG`], or [`None`], the default.
+    const DIRTY_LOG: Option<u32> = None;
+    /// Bit index used for [`WpReasons::SNAPSHOT`], or [`None`], the default.
+    const SNAPSHOT: Option<u32> = None;
+}
+
+bitflags::bitflags! {
+    /// The reasons for which a leaf entry is write-protected although its
+    /// mapping is writable, stored in bits reserved for software (see
+    /// [`GenericPTE::wp_reasons`]).
+    ///
+    /// Each mechanism that removes write access to catch the writes to a page
+    /// sets its own reason, and clears only that one once the write is
+    /// caught: write access comes back when no reason is left. Copy-on-write
+    /// is a third reason, carried by [`MappingFlags::COW`].
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct WpReasons: u8 {
+        /// The writes are logged, e.g. for the live migration of a guest.
+        const DIRTY_LOG = 1 << 0;
+        /// The pages are saved before they are first written, e.g. for a
+        /// snapshot.
+        const SNAPSHOT = 1 << 1;
+    }
+}
+
+#[allow(dead_code)]
+impl WpReasons {
+    /// Returns the reasons stored in the entry `bits`, with the bit indices
+    /// `at` of [`WpReasons::DIRTY_LOG`] and [`WpReasons::SNAPSHOT`].
+    const fn from_entry(bits: u64, at: [Option<u32>; 2]) -> Self {
+        let mut reasons = 0;
+        let mut i = 0;
+        while i < at.len() {
+            match at[i] {
+                Some(bit) if bits & 1 << bit != 0 => reasons |= 1 << i,
+                _ => {}
+            }
+            i += 1;
+        }
+        Self::from_bits_truncate(reasons)
+    }
+
+    /// Returns the bits of an entry storing these reasons at `at`, or all
+    /// the bits that can store them for [`WpReasons::all`].
+    const fn to_entry(self, at: [Option<u32>; 2]) -> u64 {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < at.len() {
+            match at[i] {
+                Some(bit) if self.bits() & 1 << i != 0 => bits |= 1 << bit,
+                _ => {}
+            }
+            i += 1;
+        }
+        bits
+    }
+
+    /// Returns the reasons that can be stored at `at`.
+    const fn stored_at(at: [Option<u32>; 2]) -> Self {
+        Self::from_entry(Self::all().to_entry(at), at)
+    }
+}
+
+/// Returns the flags to set in a leaf entry with the write-protect reasons
+/// `reasons` instead of `flags`, and the reasons it keeps: write access is
+/// withheld while there are reasons, which are dropped with a read-only
+/// mapping.
+#[allow(dead_code)]
+const fn withhold_write(flags: MappingFlags, reasons: WpReasons) -> (MappingFlags, WpReasons) {
+    if reasons.is_empty() {
+        (flags, reasons)
+    } else if flags.contains(MappingFlags::WRITE) || is_cow(flags) {
+        (flags.difference(MappingFlags::WRITE), reasons)
+    } else {
+        (flags, WpReasons::empty())
+    }
+}
+
+/// Returns whether a leaf entry with `flags` and the write-protect reasons
+/// `old` has write access once its reasons are `new`, or [`None`] if the
+/// mapping is read-only and cannot be write-protected.
+#[allow(dead_code)]
+const fn write_access(flags: MappingFlags, old: WpReasons, new: WpReasons) -> Option<bool> {
+    if old.is_empty() && !flags.contains(MappingFlags::WRITE) && !is_cow(flags) {
+        return None;
+    }
+    Some(new.is_empty() && !is_cow(flags))
+}
+
+#[allow(dead_code)]
+const fn is_cow(flags: MappingFlags) -> bool {
+    #[cfg(feature = "COW")]
+    if flags.contains(MappingFlags::COW) {
+        return true;
+    }
+    let _ = flags;
+    false
+}
+
+/// Panics (at compile time when used in a constant) if the bits in `bits`
+/// are not all in `allowed` or are not distinct. [`None`] stands for a flag
+/// without a bit.
+#[allow(dead_code)]
+const fn check_soft_bits(bits: &[Option<u32>], allowed: u64) {
+    let mut used = 0u64;
+    let mut i = 0;
+    while i < bits.len() {
+        let Some(bit) = bits[i] else {
+            i += 1;
+            continue;
+        };
+        assert!(bit < 64, "software bit out of range");
+        let mask = 1 << bit;
+        assert!(allowed & mask != 0, "bit is not reserved for software");
+        assert!(used & mask == 0, "two software flags share a bit");
+        used |= mask;
+        i += 1;
+    }
+}
+
 impl Debug for MappingFlags {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         Debug::fmt(&self.0, f)
@@ -94,14 +400,85 @@ impl Display for MappingFlags {
     }
 }
 
+/// The raw bits written by [`GenericPTE::clear`] with the `debug-poison`
+/// feature, instead of zero.
+///
+/// The valid/present bits of all architectures are clear, and it is not an
+/// [`AbsentEntry`], so a translation fault on a poisoned entry tells that the
+/// page was unmapped rather than never mapped.
+pub const POISON: u64 = 0xdead_beef << 16;
+
+/// The raw bits of a cleared entry.
+const CLEARED: u64 = if cfg!(feature = "debug-poison") {
+    POISON
+} else {
+    0
+};
+
+/// Whether the raw bits of an entry are zero or [`POISON`], with or without
+/// the `debug-poison` feature: [`NonPresentPayload::Poisoned`] can always be
+/// written.
+const fn is_cleared(bits: u64) -> bool {
+    bits == 0 || bits == POISON
+}
+
+/// The size of the smallest huge page of every format.
+#[allow(dead_code)]
+const MIN_HUGE_PAGE_SIZE: usize = 0x20_0000;
+
+/// Panics in debug builds if `paddr` cannot be the frame of a huge page of
+/// any size.
+///
+/// The low bits of the frame of a huge page are not ignored by the hardware:
+/// they are reserved (RISC-V, AArch64) or hold other fields (the PAT bit of
+/// x86, the global bit of LoongArch), so they must not be masked off silently.
+#[allow(dead_code)]
+fn debug_check_huge(paddr: PhysAddr, is_huge: bool) {
+    debug_assert!(
+        !is_huge || paddr.as_usize().is_multiple_of(MIN_HUGE_PAGE_SIZE),
+        "misaligned frame of a huge page: {:#x}",
+        paddr.as_usize()
+    );
+}
+
 /// A generic page table entry.
 ///
 /// All architecture-specific page table entry types implement this trait.
 pub trait GenericPTE: Debug + Clone + Copy + Sync + Send + Sized {
+    /// The architecture-specific flags type of this entry.
+    type ArchFlags;
+
+    /// Whether 4K leaf entries support the contiguous hint, which lets a
+    /// naturally aligned group of 16 entries be cached as one 64K TLB entry
+    /// (e.g. the AArch64 contiguous bit).
+    const CONTIGUOUS_HINT: bool = false;
+
     /// Creates a page table entry point to a terminate page or block.
+    ///
+    /// `paddr` must be aligned to the size of the page. The entry does not
+    /// know the size of a huge page, so debug builds only check that its
+    /// frame is aligned to 2M, and the page table checks the rest.
     fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self;
     /// Creates a page table entry point to a next level page table.
     fn new_table(paddr: PhysAddr) -> Self;
+    /// Widens the permissions of a table entry created by
+    /// [`GenericPTE::new_table`], so that it allows the accesses in `flags`
+    /// to the mappings below it.
+    ///
+    /// Returns whether the entry was changed. The default does nothing, for
+    /// formats without permissions in table entries.
+    fn widen_table(&mut self, _flags: MappingFlags) -> bool {
+        false
+    }
+    /// Resets the permissions of a table entry to those of
+    /// [`GenericPTE::new_table`], then widens them to allow `flags`, e.g.
+    /// the union of the flags of the mappings below it.
+    ///
+    /// Returns whether the entry was changed. The default does nothing, like
+    /// [`GenericPTE::widen_table`].
+    fn narrow_table(&mut self, _flags: MappingFlags) -> bool {
+        false
+    }
 
     /// Returns the physical address mapped by this entry.
     fn paddr(&self) -> PhysAddr;
@@ -110,15 +487,81 @@ pub trait GenericPTE: Debug + Clone + Copy + Sync + Send + Sized {
 
     /// Set mapped physical address of the entry.
     fn set_paddr(&mut self, paddr: PhysAddr);
+    /// Returns the physical address mapped by this leaf entry, which maps a
+    /// huge page if `is_huge` is `true`, i.e. if it is above the last level.
+    ///
+    /// The default is [`GenericPTE::paddr`], for formats whose leaf entries
+    /// tell their size apart. Formats that need the level (x86, where the
+    /// page size bit of huge pages is the PAT bit of 4K pages) override it,
+    /// and read the entry as [`GenericPTE::is_huge`] says in
+    /// [`GenericPTE::paddr`].
+    fn leaf_paddr(&self, _is_huge: bool) -> PhysAddr {
+        self.paddr()
+    }
+    /// Returns the flags of this leaf entry, which maps a huge page if
+    /// `is_huge` is `true`, like [`GenericPTE::leaf_paddr`].
+    fn leaf_flags(&self, _is_huge: bool) -> MappingFlags {
+        self.flags()
+    }
+    /// Sets the physical address mapped by this leaf entry, which maps a huge
+    /// page if `is_huge` is `true`, like [`GenericPTE::leaf_paddr`].
+    fn set_leaf_paddr(&mut self, paddr: PhysAddr, _is_huge: bool) {
+        self.set_paddr(paddr)
+    }
     /// Set flags of the entry.
+    ///
+    /// The flags are the ones of a leaf entry. Formats whose table entries
+    /// are told apart by their bits (see [`GenericPTE::is_table`]) leave
+    /// table entries unchanged. The bits of a present entry returned by
+    /// [`GenericPTE::unknown_bits`] are kept, unless `flags` is empty.
     fn set_flags(&mut self, flags: MappingFlags, is_huge: bool);
 
     /// Set flags with arch specific implementation.
-    fn set_flags_arch(&mut self, flags: PTEFlags);
+    ///
+    /// Formats whose table entries are told apart by their bits keep them
+    /// table entries, dropping the flags only valid in leaf entries.
+    fn set_flags_arch(&mut self, flags: Self::ArchFlags);
+
+    /// The order of the bytes of the entries in memory, i.e. of the memory
+    /// of the tables. The default is the order of the target, which
+    /// [`EndianPTE`] changes.
+    const BYTE_ORDER: ByteOrder = ByteOrder::NATIVE;
 
     /// Returns the raw bits of this entry.
     fn bits(self) -> usize;
-    /// Returns whether this entry is zero.
+    /// Creates an entry from its raw bits, as returned by
+    /// [`GenericPTE::bits`], e.g. read from a page table that was not built
+    /// by this crate. See [`GenericPTE::is_well_formed`].
+    fn from_bits(bits: usize) -> Self;
+    /// Returns the bytes of this entry in the given byte order, e.g. to write
+    /// a dump of the tables for a machine of the other endianness.
+    fn to_bytes(self, order: ByteOrder) -> [u8; 8] {
+        order.from_native(self.bits() as u64).to_ne_bytes()
+    }
+    /// Creates an entry from its bytes in the given byte order, e.g. read
+    /// from a dump of the tables of a machine of the other endianness.
+    fn from_bytes(bytes: [u8; 8], order: ByteOrder) -> Self {
+        Self::from_bits(order.to_native(u64::from_ne_bytes(bytes)) as usize)
+    }
+    /// Returns the bits of this present leaf entry that this crate neither
+    /// sets nor reads into [`MappingFlags`], e.g. a global bit, a memory type
+    /// of Svpbmt or bits reserved for software, written by firmware or
+    /// another kernel.
+    ///
+    /// They are kept by [`GenericPTE::set_flags`] and
+    /// [`GenericPTE::set_paddr`]. Non-present entries belong to software and
+    /// have none. The default is `0`, for formats that model all their bits.
+    fn unknown_bits(&self) -> usize {
+        0
+    }
+    /// Returns whether the bits of this entry reserved by the architecture are
+    /// clear, e.g. for an entry read from a table built by firmware.
+    ///
+    /// The default is `true`, for formats whose reserved bits are not checked.
+    fn is_well_formed(&self) -> bool {
+        true
+    }
+    /// Returns whether this entry is zero or [`POISON`].
     fn is_unused(&self) -> bool;
     /// Returns whether this entry flag indicates present.
     fn is_present(&self) -> bool;
@@ -132,7 +575,379 @@ pub trait GenericPTE: Debug + Clone + Copy + Sync + Send + Sized {
     fn set_accessed(&mut self, accessed: bool);
     /// For non-last level translation, returns whether this entry maps to a
     /// huge frame.
+    ///
+    /// The answer for the last level is meaningless: its leaf entries map 4K
+    /// pages, and their bits may mean something else there (see
+    /// [`GenericPTE::leaf_paddr`]).
     fn is_huge(&self) -> bool;
-    /// Set this entry to zero.
+    /// For non-last level translation, returns whether this entry points to
+    /// a next-level table.
+    ///
+    /// The default is neither unused nor huge, as table entries are not
+    /// marked present on every architecture. Formats whose table entries
+    /// differ from leaf entries at every level (RISC-V) override it, and
+    /// then also answer for the last level.
+    fn is_table(&self) -> bool {
+        !self.is_unused() && !self.is_huge()
+    }
+    /// Set this entry to zero, or to [`POISON`] with the `debug-poison`
+    /// feature.
     fn clear(&mut self);
+    /// Returns whether this entry is [`POISON`], i.e. was cleared with the
+    /// `debug-poison` feature or holds [`NonPresentPayload::Poisoned`].
+    fn is_poisoned(&self) -> bool {
+        self.bits() as u64 == POISON
+    }
+
+    /// Returns whether this 4K leaf entry has the contiguous hint.
+    ///
+    /// The physical address of such an entry may be the start of the group
+    /// rather than its own frame (e.g. with the RISC-V Svnapot extension).
+    fn is_contiguous(&self) -> bool {
+        false
+    }
+    /// Sets or clears the contiguous hint of a 4K leaf entry, after its
+    /// physical address and flags are set. The physical address must be set
+    /// again after the hint is cleared.
+    ///
+    /// All 16 entries of a group must have it, and map consecutive frames
+    /// with the same flags. Does nothing if [`GenericPTE::CONTIGUOUS_HINT`] is
+    /// `false`.
+    fn set_contiguous(&mut self, _contiguous: bool) {}
+
+    /// Returns whether the architecture allows an access of type `access` in
+    /// the context `ctx` to the page of this leaf entry, or whether it faults.
+    ///
+    /// It is `false` if the entry is not present. The entries of the tables
+    /// above can restrict the access further (see
+    /// [`GenericPTE::limited_by`]), and copy-on-write pages are read-only.
+    /// The default checks the flags of the entry for an architecture without
+    /// global controls, where [`MappingFlags::USER`] only restricts user
+    /// mode.
+    fn permits(&self, access: AccessType, ctx: AccessContext) -> bool {
+        let flags = self.flags();
+        if !self.is_present() || (ctx.user && !flags.contains(MappingFlags::USER)) {
+            return false;
+        }
+        flags.contains(match access {
+            AccessType::Read => MappingFlags::READ,
+            AccessType::Write => MappingFlags::WRITE,
+            AccessType::Execute => MappingFlags::EXECUTE,
+        })
+    }
+    /// Returns this leaf entry with the permissions left by the table entry
+    /// `table` above it, for [`GenericPTE::permits`].
+    ///
+    /// The default returns the entry unchanged, for formats without
+    /// permissions in table entries.
+    fn limited_by(&self, _table: &Self) -> Self {
+        *self
+    }
+
+    /// Returns whether this leaf entry is a global mapping, whose TLB entries
+    /// survive address space switches (e.g. the x86 `G` bit).
+    ///
+    /// The default is `false`, for formats where this needs no special care
+    /// when flushing the TLB.
+    fn is_global(&self) -> bool {
+        false
+    }
+
+    /// The number of bits of the tag of a leaf entry (see
+    /// [`GenericPTE::tag`]), at most 4.
+    ///
+    /// The default is `0`, for formats that have no bits reserved for
+    /// software to spare: their entries drop the tags.
+    const TAG_BITS: u32 = 0;
+    /// Returns the tag of this present leaf entry, a small number chosen by
+    /// software (e.g. the subsystem that mapped the page) and ignored by the
+    /// hardware. It is `0` unless set by [`GenericPTE::set_tag`].
+    ///
+    /// The tag is stored in bits reserved for software, which
+    /// [`GenericPTE::unknown_bits`] returns: it is kept by
+    /// [`GenericPTE::set_flags`] and [`GenericPTE::set_paddr`].
+    fn tag(&self) -> u8 {
+        0
+    }
+    /// Sets the tag of this present leaf entry, truncated to
+    /// [`GenericPTE::TAG_BITS`] bits. The default does nothing.
+    fn set_tag(&mut self, _tag: u8) {}
+
+    /// Whether leaf entries can be locked (see [`GenericPTE::is_locked`]).
+    ///
+    /// The default is `false`, for formats that have no bit reserved for
+    /// software to spare.
+    const LOCKABLE: bool = false;
+    /// Returns whether this present leaf entry is locked, e.g. by `mlock`:
+    /// its page is neither reclaimed nor unmapped without being forced. It is
+    /// `false` unless set by [`GenericPTE::set_locked`].
+    ///
+    /// Like the tag, the lock is stored in a bit reserved for software, which
+    /// is kept by [`GenericPTE::set_flags`] and [`GenericPTE::set_paddr`].
+    fn is_locked(&self) -> bool {
+        false
+    }
+    /// Locks or unlocks this present leaf entry. The default does nothing.
+    fn set_locked(&mut self, _locked: bool) {}
+
+    /// The write-protect reasons that leaf entries can store (see
+    /// [`GenericPTE::wp_reasons`]).
+    ///
+    /// The default is none, for formats that have no bits reserved for
+    /// software to spare.
+    const WP_REASONS: WpReasons = WpReasons::empty();
+    /// Returns the reasons for which this present leaf entry is
+    /// write-protected although its mapping is writable. They are empty
+    /// unless set by [`GenericPTE::set_wp_reasons`].
+    ///
+    /// While there are reasons, [`GenericPTE::set_flags`] withholds
+    /// [`MappingFlags::WRITE`], or drops the reasons if the new flags are
+    /// neither writable nor copy-on-write. Like the lock, they are stored in
+    /// bits reserved for software.
+    fn wp_reasons(&self) -> WpReasons {
+        WpReasons::empty()
+    }
+    /// Sets the write-protect reasons of this present leaf entry, truncated
+    /// to [`GenericPTE::WP_REASONS`]: the page is not writable while there
+    /// are reasons, and becomes writable again when the last one is cleared,
+    /// unless the mapping is copy-on-write.
+    ///
+    /// Does nothing for a read-only mapping, i.e. without reasons that is
+    /// neither writable nor copy-on-write. The default does nothing.
+    fn set_wp_reasons(&mut self, _reasons: WpReasons) {}
+
+    /// Returns what this entry holds if it is not present, or [`None`] if it
+    /// is present or holds bits not written by [`GenericPTE::set_payload`].
+    fn payload(&self) -> Option<NonPresentPayload>;
+    /// Makes this entry a non-present entry holding `payload`, in the layout
+    /// of [`NonPresentPayload`] for this format.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload does not fit in [`AbsentEntry::PAYLOAD_BITS`].
+    fn set_payload(&mut self, payload: NonPresentPayload);
+
+    /// Creates a non-present entry carrying the given payload.
+    fn new_absent(absent: AbsentEntry) -> Self;
+    /// Returns the payload of a non-present entry created by
+    /// [`GenericPTE::new_absent`], or [`None`] for other entries.
+    fn absent(&self) -> Option<AbsentEntry> {
+        self.payload()?.absent()
+    }
+
+    /// Whether [`GenericPTE::set_inaccessible`] keeps the entry present, and
+    /// so also works for huge pages.
+    const PRESENT_INACCESSIBLE: bool = false;
+
+    /// Makes this leaf entry deny every access but keep its frame, like
+    /// `mprotect(PROT_NONE)`. [`GenericPTE::flags`] then returns no flags, and
+    /// [`GenericPTE::inaccessible`] the frame, to map it again.
+    ///
+    /// The default makes it a non-present entry holding
+    /// [`AbsentEntry::Inaccessible`], for the formats where a present entry
+    /// always allows some access. It keeps only the frame, and not the bits
+    /// telling huge pages apart, so it must only be used for 4K pages.
+    fn set_inaccessible(&mut self) {
+        let frame = self.leaf_paddr(false).as_usize() as u64 >> 12;
+        self.set_payload(NonPresentPayload::Inaccessible(frame));
+    }
+    /// Returns the frame of an entry made inaccessible by
+    /// [`GenericPTE::set_inaccessible`], or [`None`] for other entries.
+    fn inaccessible(&self) -> Option<PhysAddr> {
+        match self.absent()? {
+            AbsentEntry::Inaccessible(frame) => Some(PhysAddr::from((frame << 12) as usize)),
+            _ => None,
+        }
+    }
+}
+
+/// What a non-present page table entry holds, read and written with
+/// [`GenericPTE::payload`] and [`GenericPTE::set_payload`].
+///
+/// It is the only owner of the bits of non-present entries, so the features
+/// storing data in them cannot conflict. The common layout is the one of
+/// [`NonPresentPayload::to_bits`]: zero, [`POISON`], or an [`AbsentEntry`].
+/// It only uses bits that the hardware ignores in a non-present entry of
+/// every architecture, which each format documents, and never sets the
+/// valid/present bit nor the bits telling huge pages apart. A format may
+/// store it differently, e.g. x86 inverts the address bits of an
+/// [`AbsentEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonPresentPayload {
+    /// Nothing: a zero entry, never mapped or cleared.
+    Empty,
+    /// A swapped out page, e.g. its swap slot.
+    Swap(u64),
+    /// A page to be loaded on demand, e.g. an offset in a file.
+    FileToken(u64),
+    /// A page of a region reserved with the given flags, to be mapped on the
+    /// first access.
+    Reserved(MappingFlags),
+    /// A guard page of an allocation, with the page number of the first page
+    /// of the allocation.
+    Guard(u64),
+    /// A page mapped without any access, with the number of its frame.
+    Inaccessible(u64),
+    /// An entry cleared with the `debug-poison` feature, i.e. [`POISON`].
+    Poisoned,
+}
+
+impl NonPresentPayload {
+    /// Returns the [`AbsentEntry`] this is, or [`None`] if it carries no data.
+    pub const fn absent(self) -> Option<AbsentEntry> {
+        match self {
+            Self::Empty | Self::Poisoned => None,
+            Self::Swap(slot) => Some(AbsentEntry::Swap(slot)),
+            Self::FileToken(token) => Some(AbsentEntry::File(token)),
+            Self::Reserved(flags) => Some(AbsentEntry::Reserved(flags)),
+            Self::Guard(page) => Some(AbsentEntry::Guard(page)),
+            Self::Inaccessible(frame) => Some(AbsentEntry::Inaccessible(frame)),
+        }
+    }
+
+    /// Encodes into the raw bits of a page table entry, in the common layout.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload does not fit in [`AbsentEntry::PAYLOAD_BITS`].
+    pub const fn to_bits(self) -> u64 {
+        match self.absent() {
+            Some(absent) => absent.to_bits(),
+            None if matches!(self, Self::Poisoned) => POISON,
+            None => 0,
+        }
+    }
+
+    /// Decodes from the raw bits of a page table entry in the common layout,
+    /// returns [`None`] if they do not represent a non-present entry.
+    pub const fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Self::Empty),
+            POISON => Some(Self::Poisoned),
+            _ => match AbsentEntry::from_bits(bits) {
+                Some(absent) => Some(absent.into_payload()),
+                None => None,
+            },
+        }
+    }
+}
+
+impl From<AbsentEntry> for NonPresentPayload {
+    fn from(absent: AbsentEntry) -> Self {
+        absent.into_payload()
+    }
+}
+
+/// The software payload of a non-present page table entry, i.e. a
+/// [`NonPresentPayload`] carrying data.
+///
+/// In the common layout, the valid/present bits are clear, bits 4..6 hold a
+/// type tag, and bits 8..64 hold the payload. All other bits are zero. The
+/// guard pages share the tag of the reserved pages, and set the top bit of
+/// the payload, which no [`MappingFlags`] use. The inaccessible pages have
+/// the tag `0`, i.e. no type, and also set the top bit, which tells them from
+/// a zero or poisoned entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbsentEntry {
+    /// A swapped out page, e.g. its swap slot (tag `1`).
+    Swap(u64),
+    /// A page to be loaded on demand, e.g. an offset in a file (tag `2`).
+    File(u64),
+    /// A page of a region reserved with the given flags (tag `3`).
+    Reserved(MappingFlags),
+    /// A guard page of an allocation, which must stay unmapped, with the page
+    /// number of the first page of the allocation, i.e. its address shifted
+    /// right by 12 (tag `3`).
+    Guard(u64),
+    /// A page mapped without any access, like with `PROT_NONE`, with the
+    /// number of its frame, i.e. its address shifted right by 12 (tag `0`).
+    /// See [`GenericPTE::set_inaccessible`].
+    Inaccessible(u64),
+}
+
+impl AbsentEntry {
+    /// The number of bits available for the payload.
+    pub const PAYLOAD_BITS: u32 = 56;
+
+    const TAG_SHIFT: u32 = 4;
+    const TAG_MASK: u64 = 0b11 << Self::TAG_SHIFT;
+    const LOW_MASK: u64 = (1 << (64 - Self::PAYLOAD_BITS)) - 1;
+    /// Tells [`AbsentEntry::Guard`] from [`AbsentEntry::Reserved`], and
+    /// [`AbsentEntry::Inaccessible`] from the entries without payload.
+    const MARKER: u64 = 1 << (Self::PAYLOAD_BITS - 1);
+
+    /// Returns the payload, the bits of the flags for
+    /// [`AbsentEntry::Reserved`].
+    pub const fn payload(self) -> u64 {
+        match self {
+            Self::Swap(payload)
+            | Self::File(payload)
+            | Self::Guard(payload)
+            | Self::Inaccessible(payload) => payload,
+            Self::Reserved(flags) => flags.bits() as u64,
+        }
+    }
+
+    const fn into_payload(self) -> NonPresentPayload {
+        match self {
+            Self::Swap(slot) => NonPresentPayload::Swap(slot),
+            Self::File(token) => NonPresentPayload::FileToken(token),
+            Self::Reserved(flags) => NonPresentPayload::Reserved(flags),
+            Self::Guard(page) => NonPresentPayload::Guard(page),
+            Self::Inaccessible(frame) => NonPresentPayload::Inaccessible(frame),
+        }
+    }
+
+    /// Encodes into the raw bits of a page table entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload does not fit in [`AbsentEntry::PAYLOAD_BITS`],
+    /// or in one bit less for [`AbsentEntry::Guard`] and
+    /// [`AbsentEntry::Inaccessible`].
+    pub const fn to_bits(self) -> u64 {
+        let tag = match self {
+            Self::Inaccessible(_) => 0,
+            Self::Swap(_) => 1,
+            Self::File(_) => 2,
+            Self::Reserved(_) | Self::Guard(_) => 3,
+        };
+        let mut payload = self.payload();
+        match self {
+            Self::Guard(_) => {
+                assert!(payload & Self::MARKER == 0, "guarded page number too large");
+                payload |= Self::MARKER;
+            }
+            Self::Inaccessible(_) => {
+                assert!(payload & Self::MARKER == 0, "frame number too large");
+                payload |= Self::MARKER;
+            }
+            _ => {}
+        }
+        assert!(
+            payload >> Self::PAYLOAD_BITS == 0,
+            "payload of a non-present entry too large"
+        );
+        (payload << (64 - Self::PAYLOAD_BITS)) | (tag << Self::TAG_SHIFT)
+    }
+
+    /// Decodes from the raw bits of a page table entry, returns [`None`] if
+    /// they do not represent an absent entry.
+    pub const fn from_bits(bits: u64) -> Option<Self> {
+        if bits & Self::LOW_MASK & !Self::TAG_MASK != 0 {
+            return None;
+        }
+        let payload = bits >> (64 - Self::PAYLOAD_BITS);
+        match (bits & Self::TAG_MASK) >> Self::TAG_SHIFT {
+            0 if payload & Self::MARKER != 0 => Some(Self::Inaccessible(payload & !Self::MARKER)),
+            1 => Some(Self::Swap(payload)),
+            2 => Some(Self::File(payload)),
+            3 if payload & Self::MARKER != 0 => Some(Self::Guard(payload & !Self::MARKER)),
+            3 => match MappingFlags::from_bits(payload as usize) {
+                Some(flags) => Some(Self::Reserved(flags)),
+                None => None,
+            },
+            _ => None,
+        }
+    }
 }
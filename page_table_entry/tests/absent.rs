@@ -0,0 +1,192 @@
+//! The encoding of non-present entries with a payload, on every architecture.
+
+use memory_addr::PhysAddr;
+use page_table_entry::{AbsentEntry, GenericPTE, MappingFlags, NonPresentPayload, POISON};
+
+const MAX: u64 = (1 << AbsentEntry::PAYLOAD_BITS) - 1;
+
+/// Every payload, with the largest values.
+fn payloads() -> Vec<NonPresentPayload> {
+    let mut payloads = vec![NonPresentPayload::Empty, NonPresentPayload::Poisoned];
+    for payload in [0, 1, 0x1234_5678, MAX] {
+        payloads.push(NonPresentPayload::Swap(payload));
+        payloads.push(NonPresentPayload::FileToken(payload));
+    }
+    for flags in [MappingFlags::empty(), MappingFlags::all()] {
+        payloads.push(NonPresentPayload::Reserved(flags));
+    }
+    for page in [0, 0x1234_5678, MAX >> 1] {
+        payloads.push(NonPresentPayload::Guard(page));
+        payloads.push(NonPresentPayload::Inaccessible(page));
+    }
+    payloads
+}
+
+/// Checks the entries of `PTE` holding a payload, which must keep the bits of
+/// `kept` clear.
+fn check<PTE: GenericPTE>(kept: u64) {
+    for payload in payloads() {
+        let mut pte = PTE::new_page(PhysAddr::from(0x1000), MappingFlags::READ, false);
+        pte.set_payload(payload);
+        assert!(!pte.is_present(), "{:?}", payload);
+        assert_eq!(pte.bits() as u64 & kept, 0, "{:?}", payload);
+        assert_eq!(pte.payload(), Some(payload));
+        assert_eq!(pte.absent(), payload.absent());
+        let unused = matches!(
+            payload,
+            NonPresentPayload::Empty | NonPresentPayload::Poisoned
+        );
+        assert_eq!(pte.is_unused(), unused, "{:?}", payload);
+        if let Some(absent) = payload.absent() {
+            assert_eq!(PTE::new_absent(absent).bits(), pte.bits());
+        }
+    }
+    // Distinct kinds never decode as each other.
+    assert_ne!(
+        PTE::new_absent(AbsentEntry::Swap(7)).bits(),
+        PTE::new_absent(AbsentEntry::File(7)).bits()
+    );
+    let page = PTE::new_page(PhysAddr::from(0x1000), MappingFlags::READ, false);
+    assert_eq!(page.absent(), None);
+    assert_eq!(page.payload(), None);
+    assert_eq!(PTE::new_table(PhysAddr::from(0x2000)).payload(), None);
+    let mut cleared = PTE::new_absent(AbsentEntry::File(1));
+    cleared.clear();
+    assert_eq!(cleared.absent(), None);
+    let empty = if cfg!(feature = "debug-poison") {
+        NonPresentPayload::Poisoned
+    } else {
+        NonPresentPayload::Empty
+    };
+    assert_eq!(cleared.payload(), Some(empty));
+}
+
+/// Checks that `PTE` uses the common layout of the payloads.
+fn check_common<PTE: GenericPTE>() {
+    for payload in payloads() {
+        let mut pte = PTE::new_page(PhysAddr::from(0x1000), MappingFlags::READ, false);
+        pte.set_payload(payload);
+        assert_eq!(pte.bits() as u64, payload.to_bits());
+        assert_eq!(
+            NonPresentPayload::from_bits(pte.bits() as u64),
+            Some(payload)
+        );
+    }
+}
+
+#[test]
+fn tags() {
+    // Tag 0 is an empty entry.
+    assert_eq!(AbsentEntry::from_bits(0), None);
+    assert_eq!(AbsentEntry::from_bits(1 << 8), None);
+    assert_eq!(
+        AbsentEntry::from_bits(0b01 << 4),
+        Some(AbsentEntry::Swap(0))
+    );
+    assert_eq!(
+        AbsentEntry::from_bits(0b10 << 4),
+        Some(AbsentEntry::File(0))
+    );
+    assert_eq!(
+        AbsentEntry::from_bits(0b11 << 4 | 0b11 << 8),
+        Some(AbsentEntry::Reserved(
+            MappingFlags::READ | MappingFlags::WRITE
+        ))
+    );
+    // The top bit of the payload tells guard pages from reserved ones.
+    assert_eq!(
+        AbsentEntry::from_bits(0b11 << 4 | 1 << 63 | 7 << 8),
+        Some(AbsentEntry::Guard(7))
+    );
+    assert_eq!(AbsentEntry::Guard(0).to_bits(), 0b11 << 4 | 1 << 63);
+    // And the inaccessible pages from empty ones.
+    assert_eq!(
+        AbsentEntry::from_bits(1 << 63 | 7 << 8),
+        Some(AbsentEntry::Inaccessible(7))
+    );
+    assert_eq!(AbsentEntry::Inaccessible(0).to_bits(), 1 << 63);
+    // Only known flags are reserved.
+    assert_eq!(AbsentEntry::from_bits(0b11 << 4 | 1 << 60), None);
+    // Any other low bit set means it is not an absent entry.
+    for bit in [0, 1, 2, 3, 6, 7] {
+        assert_eq!(AbsentEntry::from_bits(0b10 << 4 | 1 << bit), None);
+    }
+}
+
+#[test]
+fn common_layout() {
+    assert_eq!(NonPresentPayload::Empty.to_bits(), 0);
+    assert_eq!(NonPresentPayload::Poisoned.to_bits(), POISON);
+    assert_eq!(
+        NonPresentPayload::from_bits(POISON),
+        Some(NonPresentPayload::Poisoned)
+    );
+    assert_eq!(
+        NonPresentPayload::from_bits(0b01 << 4 | 7 << 8),
+        Some(NonPresentPayload::Swap(7))
+    );
+    assert_eq!(
+        NonPresentPayload::from(AbsentEntry::File(7)),
+        NonPresentPayload::FileToken(7)
+    );
+    // Not a payload.
+    assert_eq!(NonPresentPayload::from_bits(1 << 8), None);
+}
+
+#[test]
+#[should_panic]
+fn payload_too_large() {
+    let _ = AbsentEntry::File(MAX + 1).to_bits();
+}
+
+#[test]
+#[should_panic]
+fn guarded_page_too_large() {
+    let _ = AbsentEntry::Guard(1 << (AbsentEntry::PAYLOAD_BITS - 1)).to_bits();
+}
+
+#[test]
+#[should_panic]
+fn inaccessible_frame_too_large() {
+    let _ = AbsentEntry::Inaccessible(1 << (AbsentEntry::PAYLOAD_BITS - 1)).to_bits();
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+#[test]
+fn x86_64() {
+    use page_table_entry::x86_64::X64PTE;
+
+    // P, and PS which tells huge pages apart.
+    check::<X64PTE>(1 | 1 << 7);
+    // The address bits of a small payload point above 2^51, out of the
+    // physical memory (L1TF).
+    for absent in [AbsentEntry::Swap(0), AbsentEntry::File(0x1234_5678)] {
+        let bits = <X64PTE>::new_absent(absent).bits() as u64;
+        assert_ne!(bits & 1 << 51, 0, "{:?}", absent);
+        assert_eq!(bits ^ 0x000f_ffff_ffff_f000, absent.to_bits());
+    }
+}
+
+#[cfg(any(target_arch = "aarch64", feature = "all-formats"))]
+#[test]
+fn aarch64() {
+    // The valid bit.
+    check::<page_table_entry::aarch64::A64PTE>(1);
+    check_common::<page_table_entry::aarch64::A64PTE>();
+}
+
+#[cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+#[test]
+fn riscv() {
+    // V, and R, W, and X which tell leaves from tables.
+    check::<page_table_entry::riscv::Rv64PTE>(0b1111);
+    check_common::<page_table_entry::riscv::Rv64PTE>();
+}
+
+#[cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+#[test]
+fn loongarch64() {
+    // V, GH which the page walker reads in directory entries, and P.
+    check::<page_table_entry::loongarch64::LA64PTE>(1 | 1 << 6 | 1 << 7);
+    check_common::<page_table_entry::loongarch64::LA64PTE>();
+}
@@ -0,0 +1,320 @@
+//! The permissions of leaf entries combined with the global controls of the
+//! CPU, checked against the rules of each architecture for every combination
+//! of permission bits, access type and context.
+
+use memory_addr::PhysAddr;
+use page_table_entry::{AccessContext, AccessType, GenericPTE, MappingFlags};
+
+const ACCESSES: [AccessType; 3] = [AccessType::Read, AccessType::Write, AccessType::Execute];
+
+fn from_bits<PTE: GenericPTE>(bits: u64) -> PTE {
+    unsafe { core::mem::transmute_copy(&bits) }
+}
+
+fn bit(bits: u64, n: u32) -> bool {
+    bits & 1 << n != 0
+}
+
+/// All the 32 contexts.
+fn contexts() -> impl Iterator<Item = AccessContext> {
+    (0..32u64).map(|c| AccessContext {
+        user: bit(c, 0),
+        kernel_accesses_user: bit(c, 1),
+        kernel_executes_user: bit(c, 2),
+        write_protect: bit(c, 3),
+        executable_readable: bit(c, 4),
+    })
+}
+
+/// Checks [`GenericPTE::permits`] for the entries with each combination of
+/// the bits `perms` set, on the frame at 1G, against `rule`.
+fn check<PTE: GenericPTE>(perms: &[u64], rule: impl Fn(u64, AccessType, AccessContext) -> bool) {
+    for combination in 0..1u64 << perms.len() {
+        let bits = (0..perms.len())
+            .filter(|&i| bit(combination, i as u32))
+            .fold(0x4000_0000, |bits, i| bits | perms[i]);
+        let pte: PTE = from_bits(bits);
+        for access in ACCESSES {
+            for ctx in contexts() {
+                assert_eq!(
+                    pte.permits(access, ctx),
+                    rule(bits, access, ctx),
+                    "{bits:#x} {access:?} {ctx:?}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn strict_contexts() {
+    let user = AccessContext::user();
+    assert!(user.user && user.write_protect);
+    assert!(!user.kernel_accesses_user && !user.kernel_executes_user);
+    assert!(!user.executable_readable);
+    assert_eq!(
+        AccessContext::kernel(),
+        AccessContext {
+            user: false,
+            ..user
+        }
+    );
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+mod x86_64 {
+    use super::*;
+    use page_table_entry::x86_64::{PTF, X64PTE};
+
+    const P: u64 = PTF::PRESENT.bits();
+    const W: u64 = PTF::WRITABLE.bits();
+    const U: u64 = PTF::USER_ACCESSIBLE.bits();
+    const NX: u64 = PTF::NO_EXECUTE.bits();
+
+    #[test]
+    fn rules() {
+        check::<X64PTE>(&[P, W, U, NX], |bits, access, ctx| {
+            let (w, u, nx) = (bits & W != 0, bits & U != 0, bits & NX != 0);
+            let fault = bits & P == 0
+                || if ctx.user {
+                    !u || (access == AccessType::Write && !w)
+                        || (access == AccessType::Execute && nx)
+                } else {
+                    match access {
+                        // SMAP
+                        AccessType::Read => u && !ctx.kernel_accesses_user,
+                        // SMAP and CR0.WP
+                        AccessType::Write => {
+                            (u && !ctx.kernel_accesses_user) || (!w && ctx.write_protect)
+                        }
+                        // SMEP
+                        AccessType::Execute => nx || (u && !ctx.kernel_executes_user),
+                    }
+                };
+            !fault
+        });
+    }
+
+    #[test]
+    fn limited_by_tables() {
+        let user_rw = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        let pte = <X64PTE>::new_page(
+            PhysAddr::from(0x1000),
+            user_rw | MappingFlags::EXECUTE,
+            false,
+        );
+        let write = AccessType::Write;
+        let open: X64PTE = from_bits(P | W | U | 0x2000);
+        assert_eq!(pte.limited_by(&open).bits(), pte.bits());
+        for (table, access) in [
+            (P, write),
+            (P | W, AccessType::Read),
+            (P | W | U | NX, AccessType::Execute),
+        ] {
+            let limited = pte.limited_by(&from_bits(table | 0x2000));
+            assert!(pte.permits(access, AccessContext::user()));
+            assert!(!limited.permits(access, AccessContext::user()));
+        }
+        // Supervisor writes to the pages below a read-only table.
+        let kernel = <X64PTE>::new_page(
+            PhysAddr::from(0x1000),
+            MappingFlags::READ | MappingFlags::WRITE,
+            false,
+        );
+        let limited = kernel.limited_by(&from_bits(P | 0x2000));
+        assert!(!limited.permits(write, AccessContext::kernel()));
+        let ctx = AccessContext {
+            write_protect: false,
+            ..AccessContext::kernel()
+        };
+        assert!(limited.permits(write, ctx));
+    }
+}
+
+#[cfg(any(target_arch = "aarch64", feature = "all-formats"))]
+mod aarch64 {
+    use super::*;
+    use page_table_entry::aarch64::{A64PTE, DescriptorAttr};
+
+    const VALID: u64 = DescriptorAttr::VALID.bits();
+    const EL0: u64 = DescriptorAttr::AP_EL0.bits();
+    const RO: u64 = DescriptorAttr::AP_RO.bits();
+    const PXN: u64 = DescriptorAttr::PXN.bits();
+    const UXN: u64 = DescriptorAttr::UXN.bits();
+
+    #[cfg(not(feature = "arm-el2"))]
+    #[test]
+    fn rules() {
+        check::<A64PTE>(&[VALID, EL0, RO, PXN, UXN], |bits, access, ctx| {
+            let (el0, ro) = (bits & EL0 != 0, bits & RO != 0);
+            // PAN, set when the kernel may not access user pages.
+            let pan = !ctx.kernel_accesses_user;
+            let fault = bits & VALID == 0
+                || if ctx.user {
+                    match access {
+                        AccessType::Read => !el0,
+                        AccessType::Write => !el0 || ro,
+                        AccessType::Execute => bits & UXN != 0,
+                    }
+                } else {
+                    match access {
+                        AccessType::Read => el0 && pan,
+                        AccessType::Write => ro || (el0 && pan),
+                        // Pages writable at EL0 are never executed at EL1.
+                        AccessType::Execute => bits & PXN != 0 || (el0 && !ro),
+                    }
+                };
+            !fault
+        });
+    }
+
+    #[cfg(feature = "arm-el2")]
+    #[test]
+    fn rules() {
+        check::<A64PTE>(&[VALID, EL0, RO, PXN, UXN], |bits, access, _| {
+            let fault = bits & VALID == 0
+                || match access {
+                    AccessType::Read => false,
+                    AccessType::Write => bits & RO != 0,
+                    AccessType::Execute => bits & UXN != 0,
+                };
+            !fault
+        });
+    }
+
+    #[cfg(not(feature = "arm-el2"))]
+    #[test]
+    fn limited_by_tables() {
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        let user = A64PTE::new_page(PhysAddr::from(0x1000), flags, false);
+        let code = A64PTE::new_page(
+            PhysAddr::from(0x1000),
+            MappingFlags::READ | MappingFlags::EXECUTE,
+            false,
+        );
+        let table =
+            |limits: DescriptorAttr| -> A64PTE { from_bits(VALID | limits.bits() | 0x2000) };
+        assert_eq!(
+            user.limited_by(&table(DescriptorAttr::empty())).bits(),
+            user.bits()
+        );
+        let cases = [
+            (
+                user,
+                DescriptorAttr::AP_NO_EL0_TABLE,
+                AccessType::Read,
+                AccessContext::user(),
+            ),
+            (
+                user,
+                DescriptorAttr::AP_NO_WRITE_TABLE,
+                AccessType::Write,
+                AccessContext::user(),
+            ),
+            (
+                code,
+                DescriptorAttr::PXN_TABLE,
+                AccessType::Execute,
+                AccessContext::kernel(),
+            ),
+        ];
+        for (pte, limits, access, ctx) in cases {
+            assert!(pte.permits(access, ctx));
+            assert!(!pte.limited_by(&table(limits)).permits(access, ctx));
+        }
+        // No EL0 access below: the kernel reads the page whatever PAN.
+        let limited = user.limited_by(&table(DescriptorAttr::AP_NO_EL0_TABLE));
+        assert!(!user.permits(AccessType::Read, AccessContext::kernel()));
+        assert!(limited.permits(AccessType::Read, AccessContext::kernel()));
+    }
+}
+
+#[cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+mod riscv {
+    use super::*;
+    use page_table_entry::riscv::{PTEFlags, Rv64PTE};
+
+    const V: u64 = PTEFlags::V.bits() as u64;
+    const R: u64 = PTEFlags::R.bits() as u64;
+    const W: u64 = PTEFlags::W.bits() as u64;
+    const X: u64 = PTEFlags::X.bits() as u64;
+    const U: u64 = PTEFlags::U.bits() as u64;
+
+    #[test]
+    fn rules() {
+        check::<Rv64PTE>(&[V, R, W, X, U], |bits, access, ctx| {
+            let (r, w, x, u) = (bits & R != 0, bits & W != 0, bits & X != 0, bits & U != 0);
+            // Invalid, a pointer to the next level, or reserved.
+            let fault = bits & V == 0
+                || !(r || x)
+                || (w && !r)
+                || if ctx.user {
+                    !u
+                } else {
+                    // SUM
+                    u && (access == AccessType::Execute || !ctx.kernel_accesses_user)
+                }
+                || match access {
+                    // MXR
+                    AccessType::Read => !r && !(x && ctx.executable_readable),
+                    AccessType::Write => !w,
+                    AccessType::Execute => !x,
+                };
+            !fault
+        });
+    }
+
+    #[cfg(feature = "COW")]
+    #[test]
+    fn cow_is_read_only() {
+        let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+        let pte = <Rv64PTE>::new_page(PhysAddr::from(0x1000), flags.cow_of().unwrap(), false);
+        assert!(pte.permits(AccessType::Read, AccessContext::user()));
+        assert!(!pte.permits(AccessType::Write, AccessContext::user()));
+    }
+}
+
+#[cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+mod loongarch64 {
+    use super::*;
+    use page_table_entry::loongarch64::LA64PTE;
+
+    const PERMS: [MappingFlags; 4] = [
+        MappingFlags::READ,
+        MappingFlags::WRITE,
+        MappingFlags::EXECUTE,
+        MappingFlags::USER,
+    ];
+
+    // Without global controls, the kernel accesses all the pages.
+    #[test]
+    fn rules() {
+        for combination in 1..16u64 {
+            let flags = (0..4)
+                .filter(|&i| bit(combination, i))
+                .fold(MappingFlags::empty(), |flags, i| flags | PERMS[i as usize]);
+            if flags == MappingFlags::USER {
+                continue;
+            }
+            let pte = <LA64PTE>::new_page(PhysAddr::from(0x1000), flags, false);
+            for access in ACCESSES {
+                let needed = match access {
+                    AccessType::Read => MappingFlags::READ,
+                    AccessType::Write => MappingFlags::WRITE,
+                    AccessType::Execute => MappingFlags::EXECUTE,
+                };
+                for ctx in contexts() {
+                    let allowed =
+                        flags.contains(needed) && (!ctx.user || flags.contains(MappingFlags::USER));
+                    assert_eq!(
+                        pte.permits(access, ctx),
+                        allowed,
+                        "{flags:?} {access:?} {ctx:?}"
+                    );
+                }
+            }
+        }
+        let absent = <LA64PTE>::new_page(PhysAddr::from(0x1000), MappingFlags::empty(), false);
+        assert!(!absent.permits(AccessType::Read, AccessContext::kernel()));
+    }
+}
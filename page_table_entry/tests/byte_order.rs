@@ -0,0 +1,64 @@
+//! The bytes of entries in either byte order, and entries stored in a byte
+//! order other than the one of the target.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::PhysAddr;
+use page_table_entry::riscv::Rv64PTE;
+use page_table_entry::x86_64::X64PTE;
+use page_table_entry::{
+    AbsentEntry, BigEndian, ByteOrder, EndianPTE, GenericPTE, LittleEndian, MappingFlags,
+};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn check_bytes<PTE: GenericPTE>() {
+    let pte = PTE::new_page(PhysAddr::from(0x1234_5000), RW, false);
+    let bits = pte.bits() as u64;
+    assert_eq!(pte.to_bytes(ByteOrder::Little), bits.to_le_bytes());
+    assert_eq!(pte.to_bytes(ByteOrder::Big), bits.to_be_bytes());
+    for order in [ByteOrder::Little, ByteOrder::Big] {
+        let read = PTE::from_bytes(pte.to_bytes(order), order);
+        assert_eq!(read.bits(), pte.bits());
+        assert_eq!(read.flags(), RW);
+    }
+    assert_eq!(PTE::from_bits(pte.bits()).paddr(), pte.paddr());
+}
+
+#[test]
+fn bytes() {
+    check_bytes::<X64PTE>();
+    check_bytes::<Rv64PTE>();
+    check_bytes::<page_table_entry::aarch64::A64PTE>();
+    check_bytes::<page_table_entry::loongarch64::LA64PTE>();
+    // Byte-swapped entries are still in the native order for `bits`.
+    check_bytes::<EndianPTE<Rv64PTE, BigEndian>>();
+}
+
+#[test]
+fn stored_swapped() {
+    type Big = EndianPTE<Rv64PTE, BigEndian>;
+    let paddr = PhysAddr::from(0x8000_0000);
+    let mut pte = Big::new_page(paddr, RW, false);
+    let native = <Rv64PTE>::new_page(paddr, RW, false);
+    assert_eq!(Big::BYTE_ORDER, ByteOrder::Big);
+    assert_eq!(pte.raw(), (native.bits() as u64).to_be());
+    assert_eq!(pte.bits(), native.bits());
+    assert_eq!(pte.get().bits(), native.bits());
+
+    pte.set_flags(MappingFlags::READ, false);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    assert!(pte.is_present());
+    assert_eq!(pte.to_bytes(ByteOrder::Big), pte.raw().to_ne_bytes());
+
+    let absent = Big::new_absent(AbsentEntry::Swap(7));
+    assert_eq!(absent.absent(), Some(AbsentEntry::Swap(7)));
+    pte.clear();
+    assert!(pte.is_unused());
+
+    // The native order is stored as it is.
+    let same = EndianPTE::<Rv64PTE, LittleEndian>::new(native);
+    if ByteOrder::NATIVE == ByteOrder::Little {
+        assert_eq!(same.raw(), native.bits() as u64);
+    }
+}
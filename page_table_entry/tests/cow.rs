@@ -0,0 +1,49 @@
+//! The copy-on-write transformation over every combination of flags.
+
+#![cfg(feature = "COW")]
+
+use page_table_entry::MappingFlags;
+
+fn all_flags() -> impl Iterator<Item = MappingFlags> {
+    (0..=MappingFlags::all().bits()).filter_map(MappingFlags::from_bits)
+}
+
+#[test]
+fn cow_of_matrix() {
+    for flags in all_flags() {
+        let cow = flags.cow_of();
+        if flags.intersects(MappingFlags::DEVICE | MappingFlags::UNCACHED) {
+            assert_eq!(cow, None, "{:?}", flags);
+            continue;
+        }
+        let cow = cow.unwrap();
+        assert!(!cow.contains(MappingFlags::WRITE), "{:?}", flags);
+        assert_eq!(
+            cow.contains(MappingFlags::COW),
+            flags.intersects(MappingFlags::WRITE | MappingFlags::COW),
+            "{:?}",
+            flags
+        );
+        // Everything else is preserved.
+        let other = !(MappingFlags::WRITE | MappingFlags::COW);
+        assert_eq!(cow & other, flags & other, "{:?}", flags);
+        // Sharing an already shared mapping again changes nothing.
+        assert_eq!(cow.cow_of(), Some(cow), "{:?}", flags);
+    }
+}
+
+#[test]
+fn resolve_cow_is_inverse() {
+    for flags in all_flags() {
+        let resolved = flags.resolve_cow();
+        assert!(!resolved.contains(MappingFlags::COW), "{:?}", flags);
+        if let Some(cow) = flags.cow_of()
+            && !flags.contains(MappingFlags::COW)
+        {
+            assert_eq!(cow.resolve_cow(), flags, "{:?}", flags);
+        }
+        if !flags.contains(MappingFlags::COW) {
+            assert_eq!(resolved, flags, "{:?}", flags);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Checks that the frame of a huge page is not truncated to fit the entry in
+//! debug builds.
+
+use std::panic::catch_unwind;
+
+use memory_addr::PhysAddr;
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn check<PTE: GenericPTE>() {
+    for paddr in [0x20_0000, 0x4000_0000, 0x1_0060_0000] {
+        let huge = PTE::new_page(PhysAddr::from(paddr), FLAGS, true);
+        assert_eq!(huge.paddr(), PhysAddr::from(paddr));
+    }
+    // Only the frames of huge pages must be aligned to 2M.
+    let page = PTE::new_page(PhysAddr::from(0x20_1000), FLAGS, false);
+    assert_eq!(page.paddr(), PhysAddr::from(0x20_1000));
+    // Bit 12 is the PAT bit of x86 huge pages, and the global bit of
+    // LoongArch ones.
+    for paddr in [0x20_1000, 0x20_2000, 0x30_0000] {
+        let result = catch_unwind(|| PTE::new_page(PhysAddr::from(paddr), FLAGS, true));
+        assert_eq!(result.is_err(), cfg!(debug_assertions), "{:#x}", paddr);
+    }
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+#[test]
+fn x86_64() {
+    check::<page_table_entry::x86_64::X64PTE>();
+}
+
+#[cfg(any(target_arch = "aarch64", feature = "all-formats"))]
+#[test]
+fn aarch64() {
+    check::<page_table_entry::aarch64::A64PTE>();
+}
+
+#[cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+#[test]
+fn riscv() {
+    check::<page_table_entry::riscv::Rv64PTE>();
+}
+
+#[cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+#[test]
+fn loongarch64() {
+    check::<page_table_entry::loongarch64::LA64PTE>();
+}
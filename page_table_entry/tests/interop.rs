@@ -0,0 +1,78 @@
+//! Converting the permissions of ELF segments to and from `MappingFlags`.
+
+#![cfg(feature = "interop")]
+
+use page_table_entry::MappingFlags;
+use page_table_entry::interop::{ElfFlags, ElfPolicy};
+
+const R: MappingFlags = MappingFlags::READ;
+const W: MappingFlags = MappingFlags::WRITE;
+const X: MappingFlags = MappingFlags::EXECUTE;
+
+/// The flags of each of the 8 `p_flags` combinations, with the default policy.
+fn exact(p_flags: u32) -> MappingFlags {
+    [
+        MappingFlags::empty(),
+        X,
+        W,
+        W | X,
+        R,
+        R | X,
+        R | W,
+        R | W | X,
+    ][p_flags as usize]
+}
+
+#[test]
+fn both_directions() {
+    for p_flags in 0..8 {
+        let flags = MappingFlags::from(ElfFlags(p_flags));
+        assert_eq!(flags, exact(p_flags), "{p_flags:#o}");
+        assert_eq!(ElfFlags::from(flags), ElfFlags(p_flags));
+    }
+    assert_eq!(ElfFlags::PF_R | ElfFlags::PF_W | ElfFlags::PF_X, 7);
+}
+
+#[test]
+fn policies() {
+    let policies = [
+        ElfPolicy::default(),
+        ElfPolicy {
+            read_if_execute: true,
+            user: false,
+        },
+        ElfPolicy {
+            read_if_execute: false,
+            user: true,
+        },
+        ElfPolicy {
+            read_if_execute: true,
+            user: true,
+        },
+    ];
+    for policy in policies {
+        for p_flags in 0..8 {
+            let mut expected = exact(p_flags);
+            if policy.read_if_execute && expected.contains(X) {
+                expected |= R;
+            }
+            if policy.user {
+                expected |= MappingFlags::USER;
+            }
+            let flags = ElfFlags(p_flags).to_mapping_flags(policy);
+            assert_eq!(flags, expected, "{p_flags:#o} {policy:?}");
+        }
+    }
+    // Execute-only stays so by default.
+    assert_eq!(MappingFlags::from(ElfFlags(ElfFlags::PF_X)), X);
+}
+
+#[test]
+fn other_bits_ignored() {
+    // The OS and processor specific bits (PF_MASKOS, PF_MASKPROC).
+    let flags = ElfFlags(0xfff0_0000 | ElfFlags::PF_R);
+    assert_eq!(MappingFlags::from(flags), R);
+    let all = R | W | X | MappingFlags::USER | MappingFlags::DEVICE | MappingFlags::UNCACHED;
+    assert_eq!(ElfFlags::from(all), ElfFlags(7));
+    assert_eq!(ElfFlags::from(MappingFlags::USER), ElfFlags(0));
+}
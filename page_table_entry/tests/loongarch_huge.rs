@@ -0,0 +1,77 @@
+//! The LoongArch entries of huge pages, checked against the `pmd` values
+//! built by Linux (`arch/loongarch/include/asm/pgtable{,-bits}.h`).
+
+#![cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::loongarch64::{LA64PTE, PTEFlags};
+use page_table_entry::{GenericPTE, MappingFlags};
+
+type Pte = LA64PTE;
+
+const RWX: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::EXECUTE);
+
+fn from_bits(bits: u64) -> Pte {
+    unsafe { core::mem::transmute(bits) }
+}
+
+/// `PAGE_KERNEL`: `_PAGE_PRESENT | __READABLE | __WRITEABLE | _PAGE_GLOBAL |
+/// _PAGE_KERN | _CACHE_CC`.
+const PAGE_KERNEL: u64 = 0x1d3;
+/// `pmd_mkhuge(PAGE_KERNEL)`, which moves G to HG and sets H.
+const PMD_KERNEL: u64 = 0x11d3;
+
+#[test]
+fn linux_kernel_pmd() {
+    let pte = from_bits(0x9000_0000 | PMD_KERNEL);
+    assert!(pte.is_present() && pte.is_huge() && !pte.is_table());
+    assert!(pte.is_global());
+    assert_eq!(pte.paddr(), PhysAddr::from(0x9000_0000));
+    assert_eq!(pte.flags(), RWX);
+    assert_eq!(pte.unknown_bits() as u64, PTEFlags::G.bits());
+
+    // The same entry without HG.
+    let new = Pte::new_page(PhysAddr::from(0x9000_0000), RWX, true);
+    assert_eq!(
+        new.bits() as u64,
+        0x9000_0000 | (PMD_KERNEL & !PTEFlags::G.bits())
+    );
+    assert!(!new.is_global());
+}
+
+#[test]
+fn hg_kept() {
+    let mut pte = from_bits(0x9000_0000 | PMD_KERNEL);
+    pte.set_paddr(PhysAddr::from(0x4000_0000));
+    assert_eq!(pte.bits() as u64, 0x4000_0000 | PMD_KERNEL);
+    pte.set_flags(MappingFlags::READ, true);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    assert_eq!(pte.paddr(), PhysAddr::from(0x4000_0000));
+    assert!(pte.is_global());
+}
+
+#[test]
+fn linux_user_pte() {
+    // `PAGE_SHARED` made young and dirty: `_PAGE_PRESENT | _PAGE_WRITE |
+    // _PAGE_USER | _CACHE_CC | _PAGE_VALID | _PAGE_DIRTY`.
+    let pte = from_bits(0x1234_5000 | 0x19f);
+    assert!(!pte.is_huge() && !pte.is_global());
+    assert_eq!(pte.paddr(), PhysAddr::from(0x1234_5000));
+    assert_eq!(pte.flags(), RWX | MappingFlags::USER);
+    // Bit 12 is part of the frame of 4K pages.
+    let pte = Pte::new_page(PhysAddr::from(0x1234_5000), RWX | MappingFlags::USER, false);
+    assert_eq!(pte.bits() as u64, 0x1234_5000 | 0x19f);
+}
+
+#[test]
+fn kernel_pte() {
+    // `PAGE_KERNEL` without G, which would read as H.
+    let pte = Pte::new_page(PhysAddr::from(0x1000), RWX, false);
+    assert_eq!(
+        pte.bits() as u64,
+        0x1000 | (PAGE_KERNEL & !PTEFlags::GH.bits())
+    );
+    assert!(!pte.is_huge());
+}
@@ -0,0 +1,76 @@
+//! The Svnapot encoding of RISC-V leaf entries.
+
+#![cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::riscv::Rv64PTE;
+use page_table_entry::{GenericPTE, MappingFlags};
+
+type Pte = Rv64PTE;
+
+const PADDR: usize = 0x8765_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const NAPOT: u64 = 1 << 63;
+
+fn napot_bits() -> u64 {
+    let pte = Pte::new_page(PhysAddr::from(PADDR), FLAGS, false);
+    pte.bits() as u64 | NAPOT | (0b1000 << 10)
+}
+
+#[test]
+fn plain_entries_are_well_formed() {
+    let pte = Pte::new_page(PhysAddr::from(PADDR + 0x3000), FLAGS, false);
+    assert!(pte.is_well_formed());
+    assert!(!pte.is_contiguous());
+    assert!(Pte::new_table(PhysAddr::from(PADDR)).is_well_formed());
+    assert!(!Pte::from_bits(pte.bits() as u64 | (1 << 54)).is_well_formed());
+}
+
+#[cfg(not(feature = "riscv-svnapot"))]
+#[test]
+fn napot_reserved_when_disabled() {
+    let pte = Pte::from_bits(napot_bits());
+    assert!(!pte.is_well_formed());
+    assert!(!pte.is_contiguous());
+
+    let mut pte = Pte::new_page(PhysAddr::from(PADDR), FLAGS, false);
+    pte.set_contiguous(true);
+    assert_eq!(pte.bits() as u64 & NAPOT, 0);
+}
+
+#[cfg(feature = "riscv-svnapot")]
+#[test]
+fn napot_group() {
+    // All 16 members of a group are encoded the same way.
+    for i in 0..16 {
+        let paddr = PhysAddr::from(PADDR + i * 0x1000);
+        let mut pte = Pte::new_page(paddr, FLAGS, false);
+        pte.set_contiguous(true);
+        assert_eq!(pte.bits() as u64, napot_bits());
+        assert!(pte.is_contiguous() && pte.is_well_formed());
+        assert_eq!(pte.paddr(), PhysAddr::from(PADDR));
+        assert_eq!(pte.flags(), FLAGS);
+
+        // Changing the flags keeps the encoding.
+        pte.set_flags(MappingFlags::READ, false);
+        assert!(pte.is_contiguous());
+        assert_eq!(pte.paddr(), PhysAddr::from(PADDR));
+
+        pte.set_contiguous(false);
+        pte.set_paddr(paddr);
+        assert!(!pte.is_contiguous() && pte.is_well_formed());
+        assert_eq!(pte.paddr(), paddr);
+    }
+}
+
+#[cfg(feature = "riscv-svnapot")]
+#[test]
+fn napot_validation() {
+    assert!(Pte::from_bits(napot_bits()).is_well_formed());
+    // Only the 64K encoding exists.
+    let other_size = napot_bits() & !(0b1111 << 10) | (0b0100 << 10);
+    assert!(!Pte::from_bits(other_size).is_well_formed());
+    // N is reserved in table entries.
+    let table = Pte::new_table(PhysAddr::from(PADDR));
+    assert!(!Pte::from_bits(table.bits() as u64 | NAPOT).is_well_formed());
+}
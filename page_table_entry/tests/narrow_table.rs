@@ -0,0 +1,64 @@
+//! Narrowing the permissions of table entries back to the least allowing
+//! some flags.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::PhysAddr;
+use page_table_entry::aarch64::A64PTE;
+use page_table_entry::riscv::Rv64PTE;
+use page_table_entry::x86_64::{DefaultPat, PTF, X64PTE};
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const PADDR: usize = 0x1234_5000;
+
+fn all_flags() -> impl Iterator<Item = MappingFlags> {
+    let used =
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE | MappingFlags::USER;
+    (0..=used.bits()).filter_map(MappingFlags::from_bits)
+}
+
+/// Checks that narrowing an entry allowing everything gives the entry
+/// created for `flags`, whatever `flags`.
+fn narrow_matrix<PTE: GenericPTE>() {
+    let all = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE | MappingFlags::USER;
+    for flags in all_flags() {
+        let mut expected = PTE::new_table(PhysAddr::from(PADDR));
+        expected.widen_table(flags);
+        let mut table = PTE::new_table(PhysAddr::from(PADDR));
+        table.widen_table(all);
+        let wide = table.bits();
+        let changed = table.narrow_table(flags);
+        assert_eq!(table.bits(), expected.bits(), "{:?}", flags);
+        assert_eq!(changed, wide != expected.bits(), "{:?}", flags);
+        assert!(!table.narrow_table(flags), "{:?}", flags);
+        assert_eq!(table.paddr(), PhysAddr::from(PADDR));
+    }
+}
+
+#[test]
+fn x86_64() {
+    narrow_matrix::<X64PTE>();
+    narrow_matrix::<X64PTE<DefaultPat, false>>();
+
+    // The accessed bit set by the walker is kept.
+    let mut table = <X64PTE>::new_table(PhysAddr::from(PADDR));
+    table.widen_table(MappingFlags::WRITE | MappingFlags::USER);
+    let flags = PTF::from_bits_truncate(table.bits() as u64);
+    table.set_flags_arch(flags | PTF::ACCESSED);
+    assert!(table.narrow_table(MappingFlags::READ));
+    let flags = PTF::from_bits_truncate(table.bits() as u64);
+    assert_eq!(flags, PTF::PRESENT | PTF::ACCESSED | PTF::NO_EXECUTE);
+}
+
+#[test]
+fn aarch64() {
+    narrow_matrix::<A64PTE>();
+}
+
+#[test]
+fn no_table_permissions() {
+    let mut table = <Rv64PTE>::new_table(PhysAddr::from(PADDR));
+    let bits = table.bits();
+    assert!(!table.narrow_table(MappingFlags::empty()));
+    assert_eq!(table.bits(), bits);
+}
@@ -0,0 +1,113 @@
+//! The x86 memory types encoded with a PAT layout.
+
+#![cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::x86_64::{DefaultPat, LinuxPat, MemType, PatLayout, X64PTE};
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const PWT: usize = 1 << 3;
+const PCD: usize = 1 << 4;
+const PAT_4K: usize = 1 << 7;
+const PAT_HUGE: usize = 1 << 12;
+const CACHE_4K: usize = PWT | PCD | PAT_4K;
+
+#[test]
+fn msr_values() {
+    assert_eq!(DefaultPat::msr_value(), 0x0007_0406_0007_0406);
+    assert_eq!(LinuxPat::msr_value(), 0x0407_0506_0007_0106);
+}
+
+#[test]
+fn flags_select_memory_types() {
+    let paddr = PhysAddr::from(0x1234_5000);
+    let cases: [(MappingFlags, usize, usize); 3] = [
+        (RW, 0, 0),
+        (RW | MappingFlags::DEVICE, PCD | PWT, PCD | PWT),
+        // The last UC entry with the default layout, WC with the Linux one.
+        (RW | MappingFlags::UNCACHED, PAT_4K | PCD | PWT, PWT),
+    ];
+    for (flags, default, linux) in cases {
+        let pte: X64PTE<DefaultPat> = X64PTE::new_page(paddr, flags, false);
+        assert_eq!(pte.bits() & CACHE_4K, default, "{:?}", flags);
+        assert_eq!(pte.leaf_flags(false), flags, "{:?}", flags);
+        assert_eq!(pte.leaf_paddr(false), paddr);
+        let pte: X64PTE<LinuxPat> = X64PTE::new_page(paddr, flags, false);
+        assert_eq!(pte.bits() & CACHE_4K, linux, "{:?}", flags);
+        assert_eq!(pte.leaf_flags(false), flags, "{:?}", flags);
+        assert_eq!(pte.leaf_paddr(false), paddr);
+    }
+    let pte: X64PTE<DefaultPat> = X64PTE::new_page(paddr, RW | MappingFlags::UNCACHED, false);
+    assert_eq!(pte.mem_type(false), MemType::Uncacheable);
+    let pte: X64PTE<LinuxPat> = X64PTE::new_page(paddr, RW | MappingFlags::UNCACHED, false);
+    assert_eq!(pte.mem_type(false), MemType::WriteCombining);
+}
+
+#[test]
+fn pat_bit_of_4k_pages() {
+    // Bit 12 is part of the address of a 4K page.
+    let paddr = PhysAddr::from(0x1234_5000);
+    let mut pte: X64PTE<LinuxPat> = X64PTE::new_page(paddr, RW, false);
+    assert!(pte.set_mem_type(MemType::WriteThrough, false));
+    assert_eq!(pte.bits() & CACHE_4K, PAT_4K | PCD | PWT);
+    assert_eq!(pte.mem_type(false), MemType::WriteThrough);
+    assert_eq!(pte.leaf_paddr(false), paddr);
+    assert_eq!(pte.leaf_flags(false), RW);
+
+    pte.set_leaf_paddr(PhysAddr::from(0x1234_6000), false);
+    assert_eq!(pte.leaf_paddr(false), PhysAddr::from(0x1234_6000));
+    assert_eq!(pte.mem_type(false), MemType::WriteThrough);
+    // New flags select the memory type again.
+    pte.set_flags(MappingFlags::READ, false);
+    assert_eq!(pte.bits() & CACHE_4K, 0);
+    assert_eq!(pte.mem_type(false), MemType::WriteBack);
+    assert_eq!(pte.leaf_paddr(false), PhysAddr::from(0x1234_6000));
+}
+
+#[test]
+fn pat_bit_of_huge_pages() {
+    let paddr = PhysAddr::from(0x4000_0000);
+    let mut pte: X64PTE<LinuxPat> = X64PTE::new_page(paddr, RW, true);
+    assert!(pte.set_mem_type(MemType::WriteProtected, true));
+    assert_eq!(pte.bits() & (PAT_HUGE | PCD | PWT), PAT_HUGE | PWT);
+    assert!(pte.is_huge());
+    assert_eq!(pte.mem_type(true), MemType::WriteProtected);
+    assert_eq!(pte.paddr(), paddr);
+
+    pte.set_paddr(PhysAddr::from(0x4020_0000));
+    assert_eq!(pte.paddr(), PhysAddr::from(0x4020_0000));
+    assert_eq!(pte.mem_type(true), MemType::WriteProtected);
+    pte.set_flags(RW | MappingFlags::DEVICE, true);
+    assert_eq!(pte.bits() & (PAT_HUGE | PCD | PWT), PCD | PWT);
+    assert_eq!(pte.paddr(), PhysAddr::from(0x4020_0000));
+    assert_eq!(pte.flags(), RW | MappingFlags::DEVICE);
+}
+
+#[test]
+fn missing_memory_types() {
+    let mut pte: X64PTE = X64PTE::new_page(PhysAddr::from(0x1000), RW, false);
+    assert!(!pte.set_mem_type(MemType::WriteCombining, false));
+    assert_eq!(pte.mem_type(false), MemType::WriteBack);
+    assert!(pte.set_mem_type(MemType::UncachedMinus, false));
+    assert_eq!(pte.flags(), RW | MappingFlags::UNCACHED);
+}
+
+#[test]
+fn page_size_bit_decides_above_last_level() {
+    // Bit 9 is ignored by the hardware, and must not hide the page size bit.
+    const PRESENT: usize = 1 << 0;
+    const WRITABLE: usize = 1 << 1;
+    const BIT_9: usize = 1 << 9;
+    let bits = 0x4020_0000 | PRESENT | WRITABLE | PAT_4K | BIT_9;
+    let pte = <X64PTE as GenericPTE>::from_bits(bits);
+    assert!(pte.is_huge());
+    assert!(!pte.is_table());
+    assert_eq!(pte.paddr(), PhysAddr::from(0x4020_0000));
+    assert_eq!(pte.flags() - MappingFlags::EXECUTE, RW);
+    assert_eq!(pte.mem_type(true), MemType::WriteBack);
+
+    // The same bits in a 4K entry select the PAT entry 4.
+    assert_eq!(pte.leaf_paddr(false), PhysAddr::from(0x4020_0000));
+    assert_eq!(pte.mem_type(false), DefaultPat::ENTRIES[4]);
+}
@@ -0,0 +1,113 @@
+//! The permission checks of [`MappingFlags`] over every combination of flags
+//! and privilege.
+
+use page_table_entry::MappingFlags;
+
+fn all_flags() -> impl Iterator<Item = MappingFlags> {
+    (0..=MappingFlags::all().bits()).filter_map(MappingFlags::from_bits)
+}
+
+#[cfg(feature = "COW")]
+fn is_cow(flags: MappingFlags) -> bool {
+    flags.contains(MappingFlags::COW)
+}
+
+#[cfg(not(feature = "COW"))]
+fn is_cow(_flags: MappingFlags) -> bool {
+    false
+}
+
+#[test]
+fn truth_table() {
+    for flags in all_flags() {
+        let [r, w, x, u] = [
+            MappingFlags::READ,
+            MappingFlags::WRITE,
+            MappingFlags::EXECUTE,
+            MappingFlags::USER,
+        ]
+        .map(|flag| flags.contains(flag));
+        let cow = is_cow(flags);
+        // Kernel accesses.
+        assert_eq!(flags.readable_by(false), r, "{:?}", flags);
+        assert_eq!(flags.writable_by(false), w && !cow, "{:?}", flags);
+        assert_eq!(flags.executable_by(false), x && !u, "{:?}", flags);
+        // User accesses.
+        assert_eq!(flags.readable_by(true), r && u, "{:?}", flags);
+        assert_eq!(flags.writable_by(true), w && u && !cow, "{:?}", flags);
+        assert_eq!(flags.executable_by(true), x && u, "{:?}", flags);
+        // The memory type does not matter.
+        let attrs = MappingFlags::DEVICE | MappingFlags::UNCACHED;
+        for user in [false, true] {
+            let plain = flags - attrs;
+            assert_eq!(flags.readable_by(user), plain.readable_by(user));
+            assert_eq!(flags.writable_by(user), plain.writable_by(user));
+            assert_eq!(flags.executable_by(user), plain.executable_by(user));
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "COW")]
+fn cow_writes_need_a_copy() {
+    for flags in all_flags() {
+        for user in [false, true] {
+            let needs_copy = flags.write_needs_copy(user);
+            assert_eq!(
+                needs_copy,
+                flags.contains(MappingFlags::COW) && (!user || flags.contains(MappingFlags::USER)),
+                "{:?} {}",
+                flags,
+                user
+            );
+            // A needed copy makes the write allowed.
+            if needs_copy {
+                assert!(!flags.writable_by(user), "{:?} {}", flags, user);
+                assert!(
+                    flags.resolve_cow().writable_by(user),
+                    "{:?} {}",
+                    flags,
+                    user
+                );
+            }
+        }
+        // Sharing a mapping turns its writes into copies.
+        if let Some(cow) = flags.cow_of() {
+            for user in [false, true] {
+                assert_eq!(
+                    cow.write_needs_copy(user),
+                    flags.writable_by(user) || flags.write_needs_copy(user),
+                    "{:?} {}",
+                    flags,
+                    user
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn constructors() {
+    let [r, w, x, u] = [
+        MappingFlags::READ,
+        MappingFlags::WRITE,
+        MappingFlags::EXECUTE,
+        MappingFlags::USER,
+    ];
+    assert_eq!(MappingFlags::kernel_r(), r);
+    assert_eq!(MappingFlags::kernel_rw(), r | w);
+    assert_eq!(MappingFlags::kernel_rx(), r | x);
+    assert_eq!(MappingFlags::user_r(), r | u);
+    assert_eq!(MappingFlags::user_rw(), r | w | u);
+    assert_eq!(MappingFlags::user_rx(), r | x | u);
+    assert_eq!(MappingFlags::user_rwx(), r | w | x | u);
+
+    // Kernel code is not executable from user mode, and user code not from
+    // the kernel.
+    assert!(MappingFlags::kernel_rx().executable_by(false));
+    assert!(!MappingFlags::kernel_rx().executable_by(true));
+    assert!(!MappingFlags::user_rx().executable_by(false));
+    assert!(MappingFlags::user_rx().executable_by(true));
+    assert!(MappingFlags::user_rw().writable_by(false));
+    assert!(!MappingFlags::user_r().writable_by(true));
+}
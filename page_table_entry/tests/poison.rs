@@ -0,0 +1,61 @@
+//! Cleared entries with and without the `debug-poison` feature, on every
+//! architecture.
+
+use memory_addr::PhysAddr;
+use page_table_entry::{AbsentEntry, GenericPTE, MappingFlags, POISON};
+
+fn check<PTE: GenericPTE>() {
+    let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE;
+    for mut pte in [
+        PTE::new_page(PhysAddr::from(0x1000), flags | MappingFlags::USER, false),
+        PTE::new_page(PhysAddr::from(0x20_0000), flags, true),
+        PTE::new_table(PhysAddr::from(0x2000)),
+        PTE::new_absent(AbsentEntry::Swap(1)),
+    ] {
+        pte.clear();
+        // Never valid, even as a table or a huge page.
+        assert!(!pte.is_present(), "{:#x}", pte.bits());
+        assert!(pte.is_unused() && pte.absent().is_none());
+        assert!(pte.flags().is_empty());
+        if cfg!(feature = "debug-poison") {
+            assert_eq!(pte.bits() as u64, POISON);
+            assert!(pte.is_poisoned());
+        } else {
+            assert_eq!(pte.bits(), 0);
+            assert!(!pte.is_poisoned());
+        }
+    }
+    assert!(!PTE::new_page(PhysAddr::from(0x1000), flags, false).is_poisoned());
+}
+
+#[test]
+fn encoding() {
+    // The valid/present bits of all architectures are bit 0, and the low
+    // bits of absent entries are clear.
+    assert_eq!(POISON & 0xff, 0);
+    assert_eq!(AbsentEntry::from_bits(POISON), None);
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+#[test]
+fn x86_64() {
+    check::<page_table_entry::x86_64::X64PTE>();
+}
+
+#[cfg(any(target_arch = "aarch64", feature = "all-formats"))]
+#[test]
+fn aarch64() {
+    check::<page_table_entry::aarch64::A64PTE>();
+}
+
+#[cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+#[test]
+fn riscv() {
+    check::<page_table_entry::riscv::Rv64PTE>();
+}
+
+#[cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+#[test]
+fn loongarch64() {
+    check::<page_table_entry::loongarch64::LA64PTE>();
+}
@@ -0,0 +1,103 @@
+//! The high bits of RISC-V leaf entries: the memory type of Svpbmt, the N bit
+//! of Svnapot and the software bits 59 and 60 of Svrsw60t59b, which the flag
+//! changes keep.
+
+#![cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::riscv::{PTEFlags, Rv64PTE};
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+/// The NC memory type of Svpbmt.
+const PBMT_NC: u64 = 1 << 61;
+const RSW_59: u64 = 1 << 59;
+const RSW_60: u64 = 1 << 60;
+const N: u64 = 1 << 63;
+/// Bits 59 and 60, unless they are reserved and cleared in strict mode.
+const RSW_KEPT: u64 = if cfg!(all(
+    feature = "riscv-strict-reserved",
+    not(feature = "riscv-svrsw60t59b")
+)) {
+    0
+} else {
+    RSW_59 | RSW_60
+};
+
+fn with_bits(pte: Rv64PTE, bits: u64) -> Rv64PTE {
+    <Rv64PTE>::from_bits(pte.bits() as u64 | bits)
+}
+
+#[test]
+fn kept_by_flag_changes() {
+    let high = PBMT_NC | RSW_KEPT;
+    let adopted = PBMT_NC | RSW_59 | RSW_60;
+    let mut pte = with_bits(
+        <Rv64PTE>::new_page(PhysAddr::from(0x8000_0000), RW, false),
+        adopted,
+    );
+    assert_eq!(pte.flags(), RW);
+
+    pte.set_flags(RX, false);
+    assert_eq!(pte.bits() as u64 & high, high);
+    assert_eq!(pte.flags(), RX);
+    pte.set_flags_arch(PTEFlags::V | PTEFlags::R | PTEFlags::A);
+    assert_eq!(pte.bits() as u64 & high, high);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    pte.set_paddr(PhysAddr::from(0x9000_0000));
+    assert_eq!(pte.bits() as u64 & high, high);
+    assert_eq!(pte.paddr(), PhysAddr::from(0x9000_0000));
+
+    // The N bit of a 64K entry, which the crate manages.
+    let napot = <Rv64PTE>::new_page(PhysAddr::from(0x8000_0000), RW, false).bits() as u64
+        | N
+        | 0b1000 << 10;
+    let mut pte = <Rv64PTE>::from_bits(napot | RSW_60);
+    pte.set_flags_arch(PTEFlags::V | PTEFlags::R | PTEFlags::A);
+    assert_eq!(pte.bits() as u64 & (N | RSW_60), N | RSW_60 & RSW_KEPT);
+    assert_eq!(pte.bits() as u64 & (0b1111 << 10), 0b1000 << 10);
+}
+
+#[test]
+fn kept_in_table_entries() {
+    let mut pte = with_bits(<Rv64PTE>::new_table(PhysAddr::from(0x8000_0000)), RSW_60);
+    pte.set_flags_arch(PTEFlags::V | PTEFlags::R | PTEFlags::U);
+    assert!(pte.is_table());
+    assert_eq!(pte.bits() as u64 & RSW_60, RSW_60 & RSW_KEPT);
+}
+
+#[cfg(not(feature = "riscv-svrsw60t59b"))]
+#[test]
+fn reserved_when_disabled() {
+    let pte = <Rv64PTE>::new_page(PhysAddr::from(0x8000_0000), RW, false);
+    assert!(!with_bits(pte, RSW_60).is_well_formed());
+    assert_eq!(
+        with_bits(pte, RSW_60).unknown_bits() as u64,
+        RSW_KEPT & RSW_60
+    );
+}
+
+#[cfg(all(feature = "riscv-svrsw60t59b", feature = "COW"))]
+#[test]
+fn software_bits() {
+    use page_table_entry::SoftBitLayout;
+
+    struct High;
+
+    impl SoftBitLayout for High {
+        const COW: u32 = 59;
+    }
+
+    let pte = <Rv64PTE>::new_page(PhysAddr::from(0x8000_0000), RW, false);
+    assert!(with_bits(pte, RSW_59 | RSW_60).is_well_formed());
+
+    let cow = MappingFlags::READ | MappingFlags::COW;
+    let mut pte = Rv64PTE::<High>::new_page(PhysAddr::from(0x8000_0000), cow, false);
+    assert_eq!(pte.bits() as u64 & (RSW_59 | 1 << 8), RSW_59);
+    assert_eq!(pte.flags(), cow);
+    assert_eq!(pte.unknown_bits(), 0);
+    pte.set_flags(RW, false);
+    assert_eq!(pte.bits() as u64 & RSW_59, 0);
+    assert_eq!(pte.flags(), RW);
+}
@@ -0,0 +1,99 @@
+//! The strict mode of RISC-V entries, in which no setter leaves the reserved
+//! bits 54..61 set.
+
+#![cfg(all(
+    any(target_arch = "riscv64", feature = "all-formats"),
+    feature = "riscv-strict-reserved"
+))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::riscv::{PTEFlags, Rv64PTE};
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RESERVED: u64 = if cfg!(feature = "riscv-svrsw60t59b") {
+    0b1_1111 << 54
+} else {
+    0b111_1111 << 54
+};
+/// The NC memory type of Svpbmt, which is not reserved.
+const KEPT: u64 = 1 << 61;
+
+fn reserved(pte: &Rv64PTE) -> u64 {
+    pte.bits() as u64 & RESERVED
+}
+
+/// A 4K leaf entry, a huge one and a table entry, adopted with all the
+/// reserved bits set.
+fn adopted() -> [Rv64PTE; 3] {
+    let paddr = PhysAddr::from(0x8000_0000);
+    [
+        <Rv64PTE>::new_page(paddr, RW, false),
+        <Rv64PTE>::new_page(paddr, RW, true),
+        <Rv64PTE>::new_table(paddr),
+    ]
+    .map(|pte| <Rv64PTE>::from_bits(pte.bits() as u64 | RESERVED | KEPT))
+}
+
+#[test]
+fn constructors() {
+    for flags in [RW, MappingFlags::READ | MappingFlags::EXECUTE] {
+        let paddr = PhysAddr::from(usize::MAX & !0xfff);
+        for pte in [
+            <Rv64PTE>::new_page(paddr, flags, false),
+            <Rv64PTE>::new_page(PhysAddr::from(0x4000_0000), flags, true),
+            <Rv64PTE>::new_table(paddr),
+        ] {
+            assert_eq!(reserved(&pte), 0);
+            assert!(pte.is_well_formed());
+        }
+    }
+}
+
+#[test]
+fn every_setter() {
+    type Setter = fn(&mut Rv64PTE);
+    let setters: [(&str, Setter); 7] = [
+        ("set_paddr", |pte| {
+            pte.set_paddr(PhysAddr::from(0x9000_0000))
+        }),
+        ("set_flags", |pte| pte.set_flags(MappingFlags::READ, false)),
+        ("set_flags_arch", |pte| {
+            pte.set_flags_arch(PTEFlags::V | PTEFlags::R | PTEFlags::A)
+        }),
+        ("set_dirty", |pte| pte.set_dirty(false)),
+        ("set_accessed", |pte| pte.set_accessed(true)),
+        ("set_contiguous", |pte| pte.set_contiguous(false)),
+        ("set_tag", |pte| pte.set_tag(1)),
+    ];
+    for (name, set) in setters {
+        for (i, mut pte) in adopted().into_iter().enumerate() {
+            assert!(!pte.is_well_formed());
+            set(&mut pte);
+            // Setters without effect leave the entry as it is.
+            let changed = match name {
+                "set_tag" => false,
+                "set_contiguous" => cfg!(feature = "riscv-svnapot"),
+                "set_flags" => i < 2,
+                _ => true,
+            };
+            assert_eq!(reserved(&pte) == 0, changed, "{name} of entry {i}");
+            assert_eq!(pte.bits() as u64 & KEPT, KEPT, "{name} of entry {i}");
+        }
+    }
+}
+
+#[test]
+fn not_unknown() {
+    let [page, huge, _] = adopted();
+    assert_eq!(page.unknown_bits() as u64, KEPT);
+    assert_eq!(huge.unknown_bits() as u64, KEPT);
+}
+
+#[test]
+fn software_owns_absent_entries() {
+    let mut pte = <Rv64PTE>::from_bits(RESERVED);
+    assert!(pte.is_well_formed());
+    pte.set_dirty(true);
+    assert_eq!(reserved(&pte), RESERVED);
+}
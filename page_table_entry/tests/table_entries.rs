@@ -0,0 +1,81 @@
+//! Telling table entries from leaf entries, and keeping them tables when
+//! their flags are set.
+
+use memory_addr::PhysAddr;
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const TABLE: usize = 0x1234_5000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn check<PTE: GenericPTE>() {
+    let table = PTE::new_table(PhysAddr::from(TABLE));
+    assert!(table.is_table());
+    let huge = PTE::new_page(PhysAddr::from(0x20_0000), FLAGS, true);
+    assert!(!huge.is_table());
+    let mut empty = PTE::new_table(PhysAddr::from(TABLE));
+    empty.clear();
+    assert!(!empty.is_table());
+    // Nor a huge page, at any level.
+    assert!(!empty.is_huge());
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+#[test]
+fn x86_64() {
+    check::<page_table_entry::x86_64::X64PTE>();
+}
+
+#[cfg(any(target_arch = "aarch64", feature = "all-formats"))]
+#[test]
+fn aarch64() {
+    check::<page_table_entry::aarch64::A64PTE>();
+}
+
+#[cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+#[test]
+fn loongarch64() {
+    check::<page_table_entry::loongarch64::LA64PTE>();
+}
+
+#[cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+mod riscv {
+    use super::*;
+    use page_table_entry::riscv::{PTEFlags, Rv64PTE};
+
+    type Pte = Rv64PTE;
+
+    #[test]
+    fn entries() {
+        check::<Pte>();
+        // Leaves are told apart at the last level too.
+        let page = Pte::new_page(PhysAddr::from(0x1000), FLAGS, false);
+        assert!(!page.is_table());
+        let exec = MappingFlags::EXECUTE;
+        assert!(!Pte::new_page(PhysAddr::from(0x1000), exec, false).is_table());
+    }
+
+    #[test]
+    fn set_flags_keeps_tables() {
+        let table = Pte::new_table(PhysAddr::from(TABLE));
+        let all = FLAGS | MappingFlags::EXECUTE | MappingFlags::USER;
+        for flags in [MappingFlags::READ, FLAGS, all] {
+            for is_huge in [false, true] {
+                let mut entry = table;
+                entry.set_flags(flags, is_huge);
+                assert_eq!(entry.bits(), table.bits(), "{:?}", flags);
+            }
+        }
+
+        // Only the flags valid in non-leaf entries are kept.
+        let mut entry = table;
+        entry.set_flags_arch(PTEFlags::R | PTEFlags::W | PTEFlags::A | PTEFlags::G);
+        assert!(entry.is_table());
+        assert_eq!(entry.paddr(), PhysAddr::from(TABLE));
+        assert_eq!(entry.bits() & 0xff, (PTEFlags::V | PTEFlags::G).bits());
+
+        // Leaves are still updated.
+        let mut page = Pte::new_page(PhysAddr::from(0x1000), FLAGS, false);
+        page.set_flags(MappingFlags::READ | MappingFlags::EXECUTE, false);
+        assert_eq!(page.flags(), MappingFlags::READ | MappingFlags::EXECUTE);
+    }
+}
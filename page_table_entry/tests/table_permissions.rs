@@ -0,0 +1,65 @@
+//! The hierarchical permissions of AArch64 table descriptors.
+
+#![cfg(all(
+    any(target_arch = "aarch64", feature = "all-formats"),
+    feature = "arm-table-permissions"
+))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::aarch64::A64PTE;
+use page_table_entry::{GenericPTE, MappingFlags};
+
+const PADDR: usize = 0x1234_5000;
+
+fn all_flags() -> impl Iterator<Item = MappingFlags> {
+    let used =
+        MappingFlags::READ | MappingFlags::WRITE | MappingFlags::EXECUTE | MappingFlags::USER;
+    (0..=used.bits()).filter_map(MappingFlags::from_bits)
+}
+
+#[test]
+fn new_table_forbids_everything() {
+    let table = A64PTE::new_table(PhysAddr::from(PADDR));
+    assert!(table.table_allows(MappingFlags::READ));
+    assert!(!table.table_allows(MappingFlags::READ | MappingFlags::WRITE));
+    assert!(!table.table_allows(MappingFlags::READ | MappingFlags::EXECUTE));
+    #[cfg(not(feature = "arm-el2"))]
+    {
+        assert!(!table.table_allows(MappingFlags::READ | MappingFlags::USER));
+        let user_exec = MappingFlags::READ | MappingFlags::USER | MappingFlags::EXECUTE;
+        assert!(!table.table_allows(user_exec));
+    }
+}
+
+#[test]
+fn widen_matrix() {
+    for flags in all_flags() {
+        let mut table = A64PTE::new_table(PhysAddr::from(PADDR));
+        let changed = table.widen_table(flags);
+        assert!(table.table_allows(flags), "{:?}", flags);
+        assert!(!table.widen_table(flags), "{:?}", flags);
+        // Only the permission fields change.
+        assert!(table.is_present() && !table.is_huge(), "{:?}", flags);
+        assert_eq!(table.paddr(), PhysAddr::from(PADDR));
+        assert_eq!(
+            changed,
+            !A64PTE::new_table(PhysAddr::from(PADDR)).table_allows(flags),
+            "{:?}",
+            flags
+        );
+    }
+}
+
+#[cfg(not(feature = "arm-el2"))]
+#[test]
+fn kernel_and_user_execute_are_separate() {
+    let mut table = A64PTE::new_table(PhysAddr::from(PADDR));
+    table.widen_table(MappingFlags::READ | MappingFlags::EXECUTE);
+    assert!(!table.table_allows(MappingFlags::READ | MappingFlags::USER));
+    table.widen_table(MappingFlags::READ | MappingFlags::USER);
+    let user_exec = MappingFlags::READ | MappingFlags::USER | MappingFlags::EXECUTE;
+    assert!(!table.table_allows(user_exec));
+    table.widen_table(user_exec);
+    assert!(table.table_allows(user_exec));
+    assert!(table.table_allows(MappingFlags::READ | MappingFlags::EXECUTE));
+}
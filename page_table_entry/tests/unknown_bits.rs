@@ -0,0 +1,63 @@
+//! The bits of leaf entries not modeled by the crate, which are reported and
+//! kept when the entries are changed.
+
+use memory_addr::PhysAddr;
+use page_table_entry::{AbsentEntry, GenericPTE, MappingFlags};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+
+fn from_bits<PTE: GenericPTE>(bits: u64) -> PTE {
+    unsafe { core::mem::transmute_copy(&bits) }
+}
+
+/// Checks that the bits in `foreign`, set by someone else in a leaf entry,
+/// survive the changes of its flags and its frame.
+fn check<PTE: GenericPTE>(foreign: u64) {
+    for is_huge in [false, true] {
+        let pte = PTE::new_page(PhysAddr::from(0x4000_0000), RW, is_huge);
+        assert_eq!(pte.unknown_bits(), 0);
+        let mut pte: PTE = from_bits(pte.bits() as u64 | foreign);
+        assert_eq!(pte.unknown_bits() as u64, foreign);
+        assert_eq!(pte.flags(), PTE::new_page(pte.paddr(), RW, is_huge).flags());
+
+        pte.set_flags(RX, is_huge);
+        let expected = PTE::new_page(PhysAddr::from(0x4000_0000), RX, is_huge);
+        assert_eq!(pte.bits() as u64, expected.bits() as u64 | foreign);
+        pte.set_paddr(PhysAddr::from(0x8000_0000));
+        assert_eq!(pte.paddr(), PhysAddr::from(0x8000_0000));
+        assert_eq!(pte.flags(), expected.flags());
+        assert_eq!(pte.unknown_bits() as u64, foreign);
+    }
+    // The bits of non-present entries belong to software.
+    let absent = PTE::new_absent(AbsentEntry::Swap(u64::MAX >> 8));
+    assert_eq!(absent.unknown_bits(), 0);
+}
+
+#[cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+#[test]
+fn x86_64() {
+    // G, two bits ignored by the hardware and a protection key.
+    check::<page_table_entry::x86_64::X64PTE>(1 << 8 | 1 << 10 | 1 << 52 | 0b1010 << 59);
+}
+
+#[cfg(any(target_arch = "aarch64", feature = "all-formats"))]
+#[test]
+fn aarch64() {
+    // nG, GP and a bit reserved for software.
+    check::<page_table_entry::aarch64::A64PTE>(1 << 11 | 1 << 50 | 1 << 55);
+}
+
+#[cfg(any(target_arch = "riscv64", feature = "all-formats"))]
+#[test]
+fn riscv() {
+    // G, the NC memory type of Svpbmt and RSW2.
+    check::<page_table_entry::riscv::Rv64PTE>(1 << 5 | 1 << 61 | 1 << 9);
+}
+
+#[cfg(any(target_arch = "loongarch64", feature = "all-formats"))]
+#[test]
+fn loongarch64() {
+    // RPLV and a bit reserved for software.
+    check::<page_table_entry::loongarch64::LA64PTE>(1 << 63 | 1 << 10);
+}
@@ -0,0 +1,130 @@
+//! The reasons for which writable leaf entries are write-protected, stored in
+//! the software bits given by the layout.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::PhysAddr;
+use page_table_entry::loongarch64::{LA64PTE, PTEFlags};
+use page_table_entry::riscv::Rv64PTE;
+use page_table_entry::{GenericPTE, MappingFlags, SoftBitLayout, WpReasons};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const COW: MappingFlags = MappingFlags::READ.union(MappingFlags::COW);
+
+/// Every bit reserved for software on LoongArch.
+struct LA64Reasons;
+
+impl SoftBitLayout for LA64Reasons {
+    const COW: u32 = 9;
+    const DIRTY_LOG: Option<u32> = Some(10);
+    const SNAPSHOT: Option<u32> = Some(11);
+}
+
+/// Room for one reason on RISC-V, without Svrsw60t59b.
+struct Rv64Reasons;
+
+impl SoftBitLayout for Rv64Reasons {
+    const COW: u32 = 8;
+    const DIRTY_LOG: Option<u32> = Some(9);
+}
+
+type Pte = LA64PTE<LA64Reasons>;
+
+fn page(flags: MappingFlags) -> Pte {
+    Pte::new_page(PhysAddr::from(0x8000_0000), flags, false)
+}
+
+#[test]
+fn supported_reasons() {
+    assert_eq!(Pte::WP_REASONS, WpReasons::all());
+    assert_eq!(Rv64PTE::<Rv64Reasons>::WP_REASONS, WpReasons::DIRTY_LOG);
+    assert_eq!(
+        LA64PTE::<page_table_entry::loongarch64::LA64SoftBits>::WP_REASONS,
+        WpReasons::empty()
+    );
+    assert_eq!(
+        Rv64PTE::<page_table_entry::riscv::Rv64SoftBits>::WP_REASONS,
+        WpReasons::empty()
+    );
+}
+
+#[test]
+fn write_withheld_until_all_cleared() {
+    let mut pte = page(RW);
+    pte.set_wp_reasons(WpReasons::all());
+    assert_eq!(pte.wp_reasons(), WpReasons::all());
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    // W and D both give write access.
+    assert_eq!(pte.bits() as u64 & (PTEFlags::W | PTEFlags::D).bits(), 0);
+    assert_eq!(pte.bits() as u64 & 0xc00, 0xc00);
+
+    pte.set_wp_reasons(WpReasons::SNAPSHOT);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    pte.set_wp_reasons(WpReasons::empty());
+    assert_eq!(pte.flags(), RW);
+    assert_eq!(pte.bits(), page(RW).bits());
+}
+
+#[test]
+fn set_flags_keeps_reasons() {
+    let mut pte = page(RW);
+    pte.set_wp_reasons(WpReasons::DIRTY_LOG);
+    pte.set_flags(RW | MappingFlags::EXECUTE, false);
+    assert_eq!(pte.flags(), MappingFlags::READ | MappingFlags::EXECUTE);
+    assert_eq!(pte.wp_reasons(), WpReasons::DIRTY_LOG);
+    pte.set_wp_reasons(WpReasons::empty());
+    assert_eq!(pte.flags(), RW | MappingFlags::EXECUTE);
+
+    // A read-only mapping drops them.
+    pte.set_wp_reasons(WpReasons::DIRTY_LOG);
+    pte.set_flags(MappingFlags::READ, false);
+    assert_eq!(pte.wp_reasons(), WpReasons::empty());
+    pte.set_flags(RW, false);
+    assert_eq!(pte.flags(), RW);
+}
+
+#[test]
+fn read_only_pages_ignored() {
+    let mut pte = page(MappingFlags::READ);
+    pte.set_wp_reasons(WpReasons::all());
+    assert_eq!(pte.wp_reasons(), WpReasons::empty());
+    assert_eq!(pte.bits(), page(MappingFlags::READ).bits());
+    // Nor are the absent entries.
+    let mut pte = Pte::empty();
+    pte.set_wp_reasons(WpReasons::all());
+    assert!(pte.is_unused());
+}
+
+#[test]
+fn copy_on_write() {
+    let mut pte = page(COW);
+    pte.set_wp_reasons(WpReasons::SNAPSHOT);
+    assert_eq!(pte.wp_reasons(), WpReasons::SNAPSHOT);
+    // Resolving the copy-on-write fault leaves the page protected.
+    pte.set_flags(RW, false);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    pte.set_wp_reasons(WpReasons::empty());
+    assert_eq!(pte.flags(), RW);
+
+    // Clearing the last reason of a copy-on-write page grants no write.
+    let mut pte = page(COW);
+    pte.set_wp_reasons(WpReasons::DIRTY_LOG);
+    pte.set_wp_reasons(WpReasons::empty());
+    assert_eq!(pte.flags(), COW);
+}
+
+#[test]
+fn truncated_to_the_layout() {
+    let mut pte = Rv64PTE::<Rv64Reasons>::new_page(PhysAddr::from(0x8000_0000), RW, false);
+    pte.set_wp_reasons(WpReasons::SNAPSHOT);
+    assert_eq!(pte.wp_reasons(), WpReasons::empty());
+    assert_eq!(pte.flags(), RW);
+    pte.set_wp_reasons(WpReasons::all());
+    assert_eq!(pte.wp_reasons(), WpReasons::DIRTY_LOG);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    assert_eq!(pte.bits() & 1 << 9, 1 << 9);
+    pte.set_flags(RW, false);
+    assert_eq!(pte.wp_reasons(), WpReasons::DIRTY_LOG);
+    pte.set_wp_reasons(WpReasons::empty());
+    assert_eq!(pte.flags(), RW);
+}
@@ -0,0 +1,71 @@
+//! x86 entries for cores running with `IA32_EFER.NXE` clear, which never set
+//! the reserved XD bit.
+
+#![cfg(any(target_arch = "x86_64", feature = "all-formats"))]
+
+use memory_addr::PhysAddr;
+use page_table_entry::x86_64::{DefaultPat, X64PTE};
+use page_table_entry::{AccessContext, AccessType, GenericPTE, MappingFlags};
+
+type NoNxPTE = X64PTE<DefaultPat, false>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+const XD: usize = 1 << 63;
+
+fn from_bits<PTE: GenericPTE>(bits: usize) -> PTE {
+    unsafe { core::mem::transmute_copy(&(bits as u64)) }
+}
+
+#[test]
+fn leaves_without_xd() {
+    let paddr = PhysAddr::from(0x20_0000);
+    for is_huge in [false, true] {
+        for flags in [RW, RX, MappingFlags::READ | MappingFlags::USER] {
+            let pte = NoNxPTE::new_page(paddr, flags, is_huge);
+            assert_eq!(pte.bits() & XD, 0);
+            assert_eq!(pte.flags(), flags);
+            assert_eq!(pte.paddr(), paddr);
+            assert_eq!(pte.unknown_bits(), 0);
+            assert!(pte.is_well_formed());
+        }
+    }
+    // XD is set as before when it is available.
+    let pte = X64PTE::<DefaultPat>::new_page(paddr, RW, false);
+    assert_eq!(pte.bits() & XD, XD);
+    assert_eq!(pte.flags(), RW);
+
+    let mut pte = NoNxPTE::new_page(paddr, RW, false);
+    pte.set_flags(RX, false);
+    assert_eq!(pte.flags(), RX);
+    pte.set_flags(MappingFlags::READ, false);
+    assert_eq!(pte.flags(), MappingFlags::READ);
+    assert_eq!(pte.bits() & XD, 0);
+}
+
+#[test]
+fn tables_without_xd() {
+    let mut table = NoNxPTE::new_table(PhysAddr::from(0x1000));
+    assert_eq!(table.bits() & XD, 0);
+    assert!(!table.widen_table(MappingFlags::EXECUTE));
+
+    let pte = NoNxPTE::new_page(PhysAddr::from(0x2000), RW, false);
+    assert_eq!(pte.limited_by(&table).bits() & XD, 0);
+}
+
+#[test]
+fn executable_anyway() {
+    let pte = NoNxPTE::new_page(PhysAddr::from(0x2000), RW, false);
+    assert!(pte.permits(AccessType::Execute, AccessContext::kernel()));
+    let pte = X64PTE::<DefaultPat>::new_page(PhysAddr::from(0x2000), RW, false);
+    assert!(!pte.permits(AccessType::Execute, AccessContext::kernel()));
+}
+
+#[test]
+fn reserved_xd() {
+    let pte = NoNxPTE::new_page(PhysAddr::from(0x2000), RX, false);
+    let adopted: NoNxPTE = from_bits(pte.bits() | XD);
+    assert!(!adopted.is_well_formed());
+    let adopted: X64PTE = from_bits(pte.bits() | XD);
+    assert!(adopted.is_well_formed());
+}
@@ -0,0 +1,179 @@
+//! Benchmarks of the page table operations, run on the host with the mock
+//! handler.
+//!
+//! Run with `cargo bench -p page_table_multiarch`, optionally followed by
+//! `-- <filter>` to only run the benchmarks whose name contains `<filter>`.
+//! Each benchmark is run a few times, and the best time per page is reported.
+
+use std::cell::Cell;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::MockPageTable;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const PADDR: usize = 0x1_0000_0000;
+const SIZE: usize = PageSize::Size1G as usize;
+const PAGES: usize = SIZE / PageSize::Size4K as usize;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RUNS: usize = 5;
+
+fn vaddr(i: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + i * PageSize::Size4K as usize)
+}
+
+fn populated(allow_huge: bool) -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map_region(
+        VirtAddr::from(VADDR),
+        |va| PhysAddr::from(va.as_usize() - VADDR + PADDR),
+        SIZE,
+        FLAGS,
+        allow_huge,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    pt
+}
+
+/// Runs `routine` on a fresh input from `setup` [`RUNS`] times, and prints
+/// the best time per page. Only `routine` is timed.
+fn bench<I, O>(name: &str, mut setup: impl FnMut() -> I, mut routine: impl FnMut(I) -> O) {
+    if std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .is_some_and(|filter| !name.contains(&filter))
+    {
+        return;
+    }
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let input = setup();
+        let start = Instant::now();
+        let output = black_box(routine(black_box(input)));
+        best = best.min(start.elapsed());
+        drop(output);
+    }
+    println!(
+        "{:<24} {:>10.2?} total {:>8.2} ns/page",
+        name,
+        best,
+        best.as_nanos() as f64 / PAGES as f64
+    );
+}
+
+fn main() {
+    println!("1G region, {} 4K pages", PAGES);
+
+    bench(
+        "map/per_page",
+        || PageTable::try_new().unwrap(),
+        |mut pt| {
+            for i in 0..PAGES {
+                let paddr = PhysAddr::from(PADDR + i * PageSize::Size4K as usize);
+                pt.map(vaddr(i), paddr, PageSize::Size4K, FLAGS)
+                    .unwrap()
+                    .ignore();
+            }
+            pt
+        },
+    );
+    bench("map/map_region", || (), |_| populated(false));
+    bench("map/map_region_huge", || (), |_| populated(true));
+
+    bench(
+        "unmap/per_page",
+        || populated(false),
+        |mut pt| {
+            for i in 0..PAGES {
+                pt.unmap(vaddr(i)).unwrap().2.ignore();
+            }
+            pt
+        },
+    );
+    bench(
+        "unmap/unmap_region",
+        || populated(false),
+        |mut pt| {
+            pt.unmap_region(VirtAddr::from(VADDR), SIZE, false)
+                .unwrap()
+                .ignore();
+            pt
+        },
+    );
+
+    // Two pages in different tables mapped to a frame after another, in
+    // turn, like the fixmap and highmem slots of a kernel. The walk cache
+    // only holds the tables of one of them.
+    let slots = [vaddr(0), VirtAddr::from(VADDR + SIZE)];
+    bench(
+        "remap_slot/map_unmap",
+        || PageTable::try_new().unwrap(),
+        |mut pt| {
+            for i in 0..PAGES {
+                let paddr = PhysAddr::from(PADDR + i * PageSize::Size4K as usize);
+                let vaddr = slots[i % 2];
+                pt.map(vaddr, paddr, PageSize::Size4K, FLAGS)
+                    .unwrap()
+                    .ignore();
+                pt.unmap(vaddr).unwrap().2.ignore();
+            }
+            pt
+        },
+    );
+    bench(
+        "remap_slot/fixed_slot",
+        || {
+            let mut pt = PageTable::try_new().unwrap();
+            let slots = slots.map(|vaddr| pt.fixed_slot(vaddr, FLAGS).unwrap());
+            (pt, slots)
+        },
+        |(mut pt, slots)| {
+            for i in 0..PAGES {
+                let paddr = PhysAddr::from(PADDR + i * PageSize::Size4K as usize);
+                let slot = &slots[i % 2];
+                slot.set(&mut pt, paddr, FLAGS).unwrap().ignore();
+                slot.clear(&mut pt).unwrap().1.ignore();
+            }
+            pt
+        },
+    );
+
+    let pt = populated(false);
+    bench(
+        "read/query",
+        || (),
+        |_| {
+            for i in 0..PAGES {
+                pt.query(vaddr(i)).unwrap();
+            }
+        },
+    );
+    bench(
+        "read/is_accessed",
+        || (),
+        |_| {
+            (0..PAGES)
+                .filter(|&i| pt.is_accessed(vaddr(i)).unwrap())
+                .count()
+        },
+    );
+    bench(
+        "read/walk",
+        || (),
+        |_| {
+            let dirty = Cell::new(0);
+            let count =
+                |_, _, _, entry: &X64PTE| dirty.set(dirty.get() + entry.is_dirty() as usize);
+            pt.walk(usize::MAX, Some(&count), None).unwrap();
+            dirty.get()
+        },
+    );
+}
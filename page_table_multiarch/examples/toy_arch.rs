@@ -0,0 +1,249 @@
+//! An architecture defined outside this crate: a toy 3-level format with its
+//! own entries, metadata and TLB maintenance, used through [`PageTable64`]
+//! like the architectures of the crate.
+//!
+//! Run with `cargo run -p page_table_multiarch --example toy_arch`.
+
+use std::alloc::{Layout, alloc_zeroed, dealloc};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering, fence};
+
+use memory_addr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
+use page_table_entry::{AbsentEntry, GenericPTE, MappingFlags, NonPresentPayload, POISON};
+use page_table_multiarch::{PageSize, PageTable64, PageTableInfo, PagingHandler, PagingMetaData};
+
+/// The entries of the toy format.
+///
+/// Bit 0 is the valid bit, bits 1..4 the read, write and execute
+/// permissions, bits 4 and 5 the accessed and dirty bits, bit 6 the user
+/// bit, and bit 7 tells a huge page from a table above the last level.
+/// Bits 12..48 hold the physical address. Non-present entries use the
+/// common layout of [`NonPresentPayload`].
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct ToyPTE(u64);
+
+impl ToyPTE {
+    const V: u64 = 1 << 0;
+    const R: u64 = 1 << 1;
+    const W: u64 = 1 << 2;
+    const X: u64 = 1 << 3;
+    const A: u64 = 1 << 4;
+    const D: u64 = 1 << 5;
+    const U: u64 = 1 << 6;
+    const H: u64 = 1 << 7;
+    const FLAGS_MASK: u64 = Self::R | Self::W | Self::X | Self::U;
+    const PHYS_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+    fn flag_bits(flags: MappingFlags) -> u64 {
+        [
+            (MappingFlags::READ, Self::R),
+            (MappingFlags::WRITE, Self::W),
+            (MappingFlags::EXECUTE, Self::X),
+            (MappingFlags::USER, Self::U),
+        ]
+        .into_iter()
+        .filter(|(flag, _)| flags.contains(*flag))
+        .fold(0, |bits, (_, bit)| bits | bit)
+    }
+
+    fn set_bit(&mut self, bit: u64, set: bool) {
+        match set {
+            true => self.0 |= bit,
+            false => self.0 &= !bit,
+        }
+    }
+}
+
+impl GenericPTE for ToyPTE {
+    type ArchFlags = u64;
+
+    fn new_page(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> Self {
+        let mut pte = Self(paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK);
+        pte.set_flags(flags, is_huge);
+        pte
+    }
+    fn new_table(paddr: PhysAddr) -> Self {
+        Self(Self::V | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK))
+    }
+    fn paddr(&self) -> PhysAddr {
+        PhysAddr::from((self.0 & Self::PHYS_ADDR_MASK) as usize)
+    }
+    fn flags(&self) -> MappingFlags {
+        if !self.is_present() {
+            return MappingFlags::empty();
+        }
+        [
+            (Self::R, MappingFlags::READ),
+            (Self::W, MappingFlags::WRITE),
+            (Self::X, MappingFlags::EXECUTE),
+            (Self::U, MappingFlags::USER),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| self.0 & bit != 0)
+        .fold(MappingFlags::empty(), |flags, (_, flag)| flags | flag)
+    }
+    fn set_paddr(&mut self, paddr: PhysAddr) {
+        self.0 =
+            (self.0 & !Self::PHYS_ADDR_MASK) | (paddr.as_usize() as u64 & Self::PHYS_ADDR_MASK);
+    }
+    fn set_flags(&mut self, flags: MappingFlags, is_huge: bool) {
+        if self.is_table() {
+            return;
+        }
+        let mut bits = self.0 & Self::PHYS_ADDR_MASK;
+        if !flags.is_empty() {
+            // No access faults: the accessed and dirty bits start set.
+            bits |= Self::V | Self::A | Self::D | Self::flag_bits(flags);
+            if is_huge {
+                bits |= Self::H;
+            }
+        }
+        self.0 = bits;
+    }
+    fn set_flags_arch(&mut self, flags: u64) {
+        self.0 = (self.0 & !Self::FLAGS_MASK) | (flags & Self::FLAGS_MASK);
+    }
+    fn bits(self) -> usize {
+        self.0 as usize
+    }
+    fn from_bits(bits: usize) -> Self {
+        Self(bits as u64)
+    }
+    fn is_unused(&self) -> bool {
+        self.0 == 0 || self.0 == POISON
+    }
+    fn is_present(&self) -> bool {
+        self.0 & Self::V != 0
+    }
+    fn is_dirty(&self) -> bool {
+        self.0 & Self::D != 0
+    }
+    fn set_dirty(&mut self, dirty: bool) {
+        self.set_bit(Self::D, dirty);
+    }
+    fn is_accessed(&self) -> bool {
+        self.0 & Self::A != 0
+    }
+    fn set_accessed(&mut self, accessed: bool) {
+        self.set_bit(Self::A, accessed);
+    }
+    fn is_huge(&self) -> bool {
+        self.is_present() && self.0 & Self::H != 0
+    }
+    fn is_table(&self) -> bool {
+        self.is_present() && !self.is_huge()
+    }
+    fn clear(&mut self) {
+        self.0 = 0;
+    }
+    fn payload(&self) -> Option<NonPresentPayload> {
+        if self.is_present() {
+            return None;
+        }
+        NonPresentPayload::from_bits(self.0)
+    }
+    fn set_payload(&mut self, payload: NonPresentPayload) {
+        self.0 = payload.to_bits();
+    }
+    fn new_absent(absent: AbsentEntry) -> Self {
+        Self(absent.to_bits())
+    }
+}
+
+impl fmt::Debug for ToyPTE {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ToyPTE")
+            .field("raw", &self.0)
+            .field("paddr", &self.paddr())
+            .field("flags", &self.flags())
+            .finish()
+    }
+}
+
+/// The number of TLB flushes, standing for the flush instructions of the
+/// toy architecture.
+static FLUSHES: AtomicUsize = AtomicUsize::new(0);
+
+/// The metadata of the toy format: 3 levels translating 39-bit addresses,
+/// like RISC-V Sv39.
+struct ToyMetaData;
+
+impl PagingMetaData for ToyMetaData {
+    const LEVELS: usize = 3;
+    const PA_MAX_BITS: usize = 48;
+    const VA_MAX_BITS: usize = 39;
+    const ARCH_NAME: &'static str = "toy";
+
+    type VirtAddr = VirtAddr;
+
+    fn flush_tlb(vaddr: Option<VirtAddr>) {
+        println!("flush_tlb({vaddr:?})");
+        FLUSHES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn fence_after_update() {
+        fence(Ordering::SeqCst);
+    }
+}
+
+/// Frames from the heap of the host, whose physical addresses are their
+/// virtual ones.
+struct HeapHandler;
+
+impl HeapHandler {
+    const LAYOUT: Layout = match Layout::from_size_align(PAGE_SIZE_4K, PAGE_SIZE_4K) {
+        Ok(layout) => layout,
+        Err(_) => panic!(),
+    };
+}
+
+impl PagingHandler for HeapHandler {
+    fn alloc_frame() -> Option<PhysAddr> {
+        let ptr = unsafe { alloc_zeroed(Self::LAYOUT) };
+        (!ptr.is_null()).then(|| PhysAddr::from(ptr as usize))
+    }
+
+    fn dealloc_frame(paddr: PhysAddr) {
+        unsafe { dealloc(paddr.as_usize() as *mut u8, Self::LAYOUT) }
+    }
+
+    fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+        VirtAddr::from(paddr.as_usize())
+    }
+}
+
+type ToyPageTable = PageTable64<ToyMetaData, ToyPTE, HeapHandler>;
+
+fn main() {
+    let mut pt = ToyPageTable::try_new().unwrap();
+    println!("{} page table at {:#x}", pt.arch_name(), pt.root_paddr());
+
+    let rw = MappingFlags::READ | MappingFlags::WRITE;
+    let vaddr = VirtAddr::from(0x4000_0000);
+    pt.map_region(
+        vaddr,
+        |va| PhysAddr::from(va.as_usize()),
+        0x20_1000,
+        rw,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        pt.query(vaddr + 0x1234),
+        Ok((PhysAddr::from(0x4000_1234), rw, PageSize::Size2M))
+    );
+    let page = vaddr + 0x20_0000;
+    assert_eq!(pt.query(page).unwrap().2, PageSize::Size4K);
+
+    // The changes to existing mappings are flushed with the toy operations.
+    let (_, tlb) = pt.protect(page, MappingFlags::READ).unwrap();
+    tlb.flush();
+    pt.unmap_region(vaddr, 0x20_1000, false)
+        .unwrap()
+        .flush_all();
+    assert_eq!(FLUSHES.load(Ordering::Relaxed), 2);
+    assert!(pt.query(vaddr).is_err());
+}
@@ -1,9 +1,8 @@
 //! AArch64 specific page table structures.
 
-use core::arch::asm;
 use page_table_entry::aarch64::A64PTE;
 
-use crate::{PageTable64, PagingMetaData};
+use crate::{PageSize, PageTable64, PagingMetaData};
 
 /// Metadata of AArch64 page tables.
 pub struct A64PagingMetaData;
@@ -12,6 +11,19 @@ impl PagingMetaData for A64PagingMetaData {
     const LEVELS: usize = 4;
     const PA_MAX_BITS: usize = 48;
     const VA_MAX_BITS: usize = 48;
+    const ARCH_NAME: &'static str = "aarch64";
+    // The lower half, translated from TTBR0_EL1, ends at 2^48, and the
+    // upper one, from TTBR1_EL1, starts at 0xffff_0000_0000_0000.
+    const USER_VA_END: usize = 1 << Self::VA_MAX_BITS;
+    // Translations that generate a Translation fault are never cached.
+    const TLB_CACHES_INVALID: bool = false;
+    // Required by the architecture to avoid TLB conflict aborts.
+    const BREAK_BEFORE_MAKE: bool = true;
+    // Level 0 descriptors cannot be blocks with 4K granules.
+    const MAX_PAGE_SIZE: PageSize = PageSize::Size1G;
+    // Like Linux, up to a table worth of `tlbi vaae1is` broadcasts before
+    // the whole TLB is invalidated instead.
+    const FLUSH_PAGES_THRESHOLD: usize = 512;
     type VirtAddr = memory_addr::VirtAddr;
 
     fn vaddr_is_valid(vaddr: usize) -> bool {
@@ -21,18 +33,43 @@ impl PagingMetaData for A64PagingMetaData {
 
     #[inline]
     fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
+        hw::flush_tlb(vaddr)
+    }
+
+    #[inline]
+    fn fence_after_update() {
+        hw::fence_after_update()
+    }
+}
+
+/// AArch64 VMSAv8-64 translation table.
+pub type A64PageTable<H> = PageTable64<A64PagingMetaData, A64PTE, H>;
+
+/// The TLB maintenance instructions.
+#[cfg(target_arch = "aarch64")]
+mod hw {
+    use core::arch::asm;
+
+    #[inline]
+    pub fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
         unsafe {
             if let Some(vaddr) = vaddr {
                 // TLB Invalidate by VA, All ASID, EL1, Inner Shareable
                 // va[55:12] => reg[43:0]
-                asm!("tlbi vaae1is, {}; dsb sy; isb", in(reg) ((vaddr.as_usize() >> 12) & 0xFFF_FFFF_FFFF))
+                asm!("dsb ishst; tlbi vaae1is, {}; dsb sy; isb", in(reg) ((vaddr.as_usize() >> 12) & 0xFFF_FFFF_FFFF))
             } else {
                 // TLB Invalidate by VMID, All at stage 1, EL1
-                asm!("tlbi vmalle1; dsb sy; isb")
+                asm!("dsb ishst; tlbi vmalle1; dsb sy; isb")
             }
         }
     }
+
+    #[inline]
+    pub fn fence_after_update() {
+        // The walker must observe the descriptors before the next access.
+        unsafe { asm!("dsb ishst; isb") }
+    }
 }
 
-/// AArch64 VMSAv8-64 translation table.
-pub type A64PageTable<H> = PageTable64<A64PagingMetaData, A64PTE, H>;
+#[cfg(not(target_arch = "aarch64"))]
+use super::foreign as hw;
@@ -1,7 +1,6 @@
 //! LoongArch64 specific page table structures.
 
 use crate::{PageTable64, PagingMetaData};
-use core::arch::asm;
 use page_table_entry::loongarch64::LA64PTE;
 
 /// Metadata of LoongArch64 page tables.
@@ -44,12 +43,40 @@ impl PagingMetaData for LA64MetaData {
     const LEVELS: usize = 4;
     const PA_MAX_BITS: usize = 48;
     const VA_MAX_BITS: usize = 48;
+    const ARCH_NAME: &'static str = "loongarch64";
+    // A flush of a single page flushes the entire TLB for now.
+    const FLUSH_PAGES_THRESHOLD: usize = 1;
     type VirtAddr = memory_addr::VirtAddr;
 
     #[inline]
     fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
+        hw::flush_tlb(vaddr)
+    }
+
+    #[inline]
+    fn fence_after_update() {
+        hw::fence_after_update()
+    }
+}
+
+/// loongarch64 page table
+///
+/// <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#section-multi-level-page-table-structure-supported-by-page-walking>
+///
+/// 4 levels:
+///
+/// using page table dir3, dir2, dir1 and pt, ignore dir4
+pub type LA64PageTable<I> = PageTable64<LA64MetaData, LA64PTE, I>;
+
+/// The TLB maintenance instructions.
+#[cfg(target_arch = "loongarch64")]
+mod hw {
+    use core::arch::asm;
+
+    #[inline]
+    pub fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
         unsafe {
-            if let Some(vaddr) = vaddr {
+            if let Some(_vaddr) = vaddr {
                 // <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#_dbar>
                 //
                 // Only after all previous load/store access operations are completely
@@ -75,13 +102,12 @@ impl PagingMetaData for LA64MetaData {
             }
         }
     }
+
+    #[inline]
+    pub fn fence_after_update() {
+        unsafe { asm!("dbar 0") }
+    }
 }
 
-/// loongarch64 page table
-///
-/// <https://loongson.github.io/LoongArch-Documentation/LoongArch-Vol1-EN.html#section-multi-level-page-table-structure-supported-by-page-walking>
-///
-/// 4 levels:
-///
-/// using page table dir3, dir2, dir1 and pt, ignore dir4
-pub type LA64PageTable<I> = PageTable64<LA64MetaData, LA64PTE, I>;
+#[cfg(not(target_arch = "loongarch64"))]
+use super::foreign as hw;
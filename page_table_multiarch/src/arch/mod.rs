@@ -1,11 +1,37 @@
-#[cfg(any(target_arch = "x86_64", doc))]
+#[cfg(any(target_arch = "x86_64", doc, feature = "all-formats"))]
 pub mod x86_64;
 
-#[cfg(any(target_arch = "riscv32", target_arch = "riscv64", doc))]
+#[cfg(any(
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    doc,
+    feature = "all-formats"
+))]
 pub mod riscv;
 
-#[cfg(any(target_arch = "aarch64", doc))]
+#[cfg(any(target_arch = "aarch64", doc, feature = "all-formats"))]
 pub mod aarch64;
 
-#[cfg(any(target_arch = "loongarch64", doc))]
+#[cfg(any(target_arch = "loongarch64", doc, feature = "all-formats"))]
 pub mod loongarch64;
+
+/// The TLB maintenance of the architectures other than the host's.
+///
+/// Their page tables can still be built or inspected, e.g. for a guest image
+/// or from a memory dump, but there is no TLB of theirs to flush: the flushes
+/// do nothing, and the fences are compiler fences.
+#[allow(dead_code)]
+mod foreign {
+    use core::sync::atomic::{Ordering, compiler_fence};
+
+    #[inline]
+    pub fn flush_tlb<A>(_vaddr: Option<A>) {}
+
+    #[inline]
+    pub fn flush_tlb_global() {}
+
+    #[inline]
+    pub fn fence_after_update() {
+        compiler_fence(Ordering::SeqCst)
+    }
+}
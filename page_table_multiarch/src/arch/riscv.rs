@@ -1,29 +1,57 @@
 //! RISC-V specific page table structures.
 
-use crate::{PageTable64, PagingMetaData};
+use crate::{GuestPhysAddr, PageSize, PageTable64, PagingMetaData};
 use page_table_entry::riscv::Rv64PTE;
 
-#[inline]
-fn riscv_flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
-    unsafe {
-        if let Some(vaddr) = vaddr {
-            riscv::asm::sfence_vma(0, vaddr.as_usize())
-        } else {
-            riscv::asm::sfence_vma_all();
-        }
-    }
-}
+// The number of `sfence.vma` of single pages above which Linux flushes the
+// entire TLB.
+const SV_FLUSH_PAGES_THRESHOLD: usize = 64;
 
 /// A virtual address that can be used in RISC-V Sv39 and Sv48 page tables.
 pub trait SvVirtAddr: memory_addr::MemoryAddr + Send + Sync {
+    /// Whether the addresses are sign-extended, with a lower and an upper
+    /// half, or are all in the lower one.
+    const SIGN_EXTENDED: bool = true;
+
     /// Flush the TLB.
     fn flush_tlb(vaddr: Option<Self>);
+
+    /// Whether `vaddr` is valid in a page table translating `va_bits` bits.
+    ///
+    /// The default requires the bits above to be sign-extended.
+    #[inline]
+    fn is_valid(vaddr: usize, va_bits: usize) -> bool {
+        let top_mask = usize::MAX << (va_bits - 1);
+        (vaddr & top_mask) == 0 || (vaddr & top_mask) == top_mask
+    }
 }
 
 impl SvVirtAddr for memory_addr::VirtAddr {
     #[inline]
     fn flush_tlb(vaddr: Option<Self>) {
-        riscv_flush_tlb(vaddr.map(|vaddr| vaddr.into()))
+        hw::flush_tlb(vaddr)
+    }
+}
+
+/// Guest physical addresses of G-stage page tables, translated for all the
+/// VMIDs.
+///
+/// Only the guest physical addresses below the size of the Sv39 or Sv48
+/// address space are supported: the wider root tables of Sv39x4 and Sv48x4
+/// are not. The leaf entries of G-stage page tables must have
+/// [`MappingFlags::USER`](crate::MappingFlags::USER).
+impl SvVirtAddr for GuestPhysAddr {
+    // All of them are user addresses, like the leaf entries.
+    const SIGN_EXTENDED: bool = false;
+
+    #[inline]
+    fn flush_tlb(gpa: Option<Self>) {
+        hw::flush_guest_tlb(gpa)
+    }
+
+    #[inline]
+    fn is_valid(gpa: usize, va_bits: usize) -> bool {
+        gpa >> va_bits == 0
     }
 }
 
@@ -41,8 +69,16 @@ impl<VA: SvVirtAddr> PagingMetaData for Sv39MetaData<VA> {
     const LEVELS: usize = 3;
     const PA_MAX_BITS: usize = 56;
     const VA_MAX_BITS: usize = 39;
+    const ARCH_NAME: &'static str = "riscv64 (Sv39)";
+    const USER_VA_END: usize = 1 << (Self::VA_MAX_BITS - VA::SIGN_EXTENDED as usize);
+    const FLUSH_PAGES_THRESHOLD: usize = SV_FLUSH_PAGES_THRESHOLD;
     type VirtAddr = VA;
 
+    #[inline]
+    fn vaddr_is_valid(vaddr: usize) -> bool {
+        VA::is_valid(vaddr, Self::VA_MAX_BITS)
+    }
+
     #[inline]
     fn flush_tlb(vaddr: Option<VA>) {
         <VA as SvVirtAddr>::flush_tlb(vaddr);
@@ -53,8 +89,18 @@ impl<VA: SvVirtAddr> PagingMetaData for Sv48MetaData<VA> {
     const LEVELS: usize = 4;
     const PA_MAX_BITS: usize = 56;
     const VA_MAX_BITS: usize = 48;
+    const ARCH_NAME: &'static str = "riscv64 (Sv48)";
+    const USER_VA_END: usize = 1 << (Self::VA_MAX_BITS - VA::SIGN_EXTENDED as usize);
+    const FLUSH_PAGES_THRESHOLD: usize = SV_FLUSH_PAGES_THRESHOLD;
+    // Leaves are allowed at every level, including 512G terapages.
+    const MAX_PAGE_SIZE: PageSize = PageSize::Size512G;
     type VirtAddr = VA;
 
+    #[inline]
+    fn vaddr_is_valid(vaddr: usize) -> bool {
+        VA::is_valid(vaddr, Self::VA_MAX_BITS)
+    }
+
     #[inline]
     fn flush_tlb(vaddr: Option<VA>) {
         <VA as SvVirtAddr>::flush_tlb(vaddr);
@@ -66,3 +112,45 @@ pub type Sv39PageTable<H> = PageTable64<Sv39MetaData<memory_addr::VirtAddr>, Rv6
 
 /// Sv48: Page-Based 48-bit (4 levels) Virtual-Memory System.
 pub type Sv48PageTable<H> = PageTable64<Sv48MetaData<memory_addr::VirtAddr>, Rv64PTE, H>;
+
+/// Sv39 G-stage page table, translating guest physical addresses.
+pub type Sv39GuestPageTable<H> = PageTable64<Sv39MetaData<GuestPhysAddr>, Rv64PTE, H>;
+
+/// Sv48 G-stage page table, translating guest physical addresses.
+pub type Sv48GuestPageTable<H> = PageTable64<Sv48MetaData<GuestPhysAddr>, Rv64PTE, H>;
+
+/// The TLB maintenance instructions. The page walker is only ordered after
+/// the stores by `sfence.vma`, so the default compiler fence is kept for
+/// `fence_after_update`.
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+mod hw {
+    use crate::GuestPhysAddr;
+
+    #[inline]
+    pub fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
+        unsafe {
+            if let Some(vaddr) = vaddr {
+                riscv::asm::sfence_vma(0, vaddr.as_usize())
+            } else {
+                riscv::asm::sfence_vma_all();
+            }
+        }
+    }
+
+    /// `hfence.gvma`, encoded for assemblers without the H extension.
+    #[inline]
+    pub fn flush_guest_tlb(gpa: Option<GuestPhysAddr>) {
+        unsafe {
+            if let Some(gpa) = gpa {
+                core::arch::asm!(".insn r 0x73, 0, 0x31, x0, {}, x0", in(reg) gpa.as_usize() >> 2)
+            } else {
+                core::arch::asm!(".insn r 0x73, 0, 0x31, x0, x0, x0")
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+mod hw {
+    pub use super::super::foreign::{flush_tlb, flush_tlb as flush_guest_tlb};
+}
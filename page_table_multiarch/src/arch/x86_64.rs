@@ -1,6 +1,6 @@
 //! x86 specific page table structures.
 
-use crate::{PageTable64, PagingMetaData};
+use crate::{PageSize, PageTable64, PagingMetaData};
 use page_table_entry::x86_64::X64PTE;
 
 /// metadata of x86_64 page tables.
@@ -10,10 +10,39 @@ impl PagingMetaData for X64PagingMetaData {
     const LEVELS: usize = 4;
     const PA_MAX_BITS: usize = 52;
     const VA_MAX_BITS: usize = 48;
+    const ARCH_NAME: &'static str = "x86_64";
+    // Non-present entries are never cached (SDM Vol. 3A, 4.10.2.3).
+    const TLB_CACHES_INVALID: bool = false;
+    // PML4 entries cannot map pages.
+    const MAX_PAGE_SIZE: PageSize = PageSize::Size1G;
+    // The single page flush ceiling of Linux: beyond it, reloading `CR3`
+    // (only flushing the current PCID) and refilling the TLB is cheaper than
+    // one `invlpg` per page.
+    const FLUSH_PAGES_THRESHOLD: usize = 33;
     type VirtAddr = memory_addr::VirtAddr;
 
     #[inline]
     fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
+        hw::flush_tlb(vaddr)
+    }
+
+    /// Toggles `CR4.PGE`, which flushes the global entries too, including the
+    /// ones of all PCIDs. If global pages are disabled, reloading `CR3` is
+    /// enough.
+    #[inline]
+    fn flush_tlb_global() {
+        hw::flush_tlb_global()
+    }
+}
+
+/// x86_64 page table.
+pub type X64PageTable<H> = PageTable64<X64PagingMetaData, X64PTE, H>;
+
+/// The TLB maintenance instructions.
+#[cfg(target_arch = "x86_64")]
+mod hw {
+    #[inline]
+    pub fn flush_tlb(vaddr: Option<memory_addr::VirtAddr>) {
         unsafe {
             if let Some(vaddr) = vaddr {
                 x86::tlb::flush(vaddr.into());
@@ -22,7 +51,21 @@ impl PagingMetaData for X64PagingMetaData {
             }
         }
     }
+
+    #[inline]
+    pub fn flush_tlb_global() {
+        use x86::controlregs::{Cr4, cr4, cr4_write};
+        unsafe {
+            let cr4 = cr4();
+            if cr4.contains(Cr4::CR4_ENABLE_GLOBAL_PAGES) {
+                cr4_write(cr4 - Cr4::CR4_ENABLE_GLOBAL_PAGES);
+                cr4_write(cr4);
+            } else {
+                x86::tlb::flush_all();
+            }
+        }
+    }
 }
 
-/// x86_64 page table.
-pub type X64PageTable<H> = PageTable64<X64PagingMetaData, X64PTE, H>;
+#[cfg(not(target_arch = "x86_64"))]
+use super::foreign as hw;
@@ -1,552 +1,7760 @@
-use crate::{GenericPTE, PagingHandler, PagingMetaData};
-use crate::{MappingFlags, PageSize, PagingError, PagingResult, TlbFlush, TlbFlushAll};
+#[cfg(feature = "trace")]
+use crate::OpStats;
+use crate::SharedFixedMapping;
+use crate::StepStatus;
+use crate::WorkingSet;
+use crate::info::{self, DebugRegions};
+#[cfg(feature = "interop")]
+use crate::interop::PagemapEntry;
+use crate::{
+    AbsentEntry, IdleBits, NonPresentPayload, ProtectedPages, QuotaKind, RangeSummary, TlbFlush,
+    TlbFlushAll,
+};
+use crate::{AccessContext, AccessType, AccessVerdict, AccessedDirtyPolicy, AnySpace};
+use crate::{AllocOptions, CacheOp, PresentBitmap, RegionSink, WpReasons};
+use crate::{AnyPageTable, GenericPTE, Mapping, PageTableInfo, PagingHandler};
+use crate::{ChangeJournal, ChangeOrigin, ChangeRecord, CloneAction, CowSpace, ElfSegment};
+use crate::{FlushedPages, HugeCowPolicy, MappingFlags, PageSize, PagingError, PagingResult};
+use crate::{MemoryType, PageTableObserver, PagingMetaData, RegionCursor, SharedSpace, SpaceKind};
+use core::cell::Cell;
+use core::fmt;
 use core::marker::PhantomData;
-use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, PhysAddrRange};
 
-const ENTRY_COUNT: usize = 512;
+/// The maximum number of levels of a [`PageTable64`].
+pub const MAX_LEVELS: usize = 4;
 
-const fn p4_index(vaddr: usize) -> usize {
-    (vaddr >> (12 + 27)) & (ENTRY_COUNT - 1)
+/// The value of [`PageTable64::active`] until the first call to
+/// [`PageTable64::set_active`].
+const UNTRACKED: usize = usize::MAX;
+
+/// The number of 4K entries in a group with the contiguous hint.
+const CONTIGUOUS_ENTRIES: usize = PageSize::Size64K as usize / PAGE_SIZE_4K;
+
+/// The pages mapped by [`PageTable64::map_pages`]: the physical address and
+/// the flags of each page, whether the flags may differ between pages, and
+/// whether huge pages are allowed.
+type MappedPages<'a, G, F> = (&'a G, &'a mut F, bool, bool);
+
+/// Returns the position of the index of the entries at `level` in the
+/// addresses of `M`, which is also the log2 of the size they map.
+pub(crate) const fn level_shift<M: PagingMetaData>(level: usize) -> usize {
+    let mut shift = 12;
+    let mut below = level + 1;
+    while below < M::LEVELS {
+        shift += M::INDEX_BITS[below] as usize;
+        below += 1;
+    }
+    shift
 }
 
-const fn p3_index(vaddr: usize) -> usize {
-    (vaddr >> (12 + 18)) & (ENTRY_COUNT - 1)
+/// Returns the number of entries of the tables at `level`.
+pub(crate) const fn table_entries<M: PagingMetaData>(level: usize) -> usize {
+    1 << M::INDEX_BITS[level]
 }
 
-const fn p2_index(vaddr: usize) -> usize {
-    (vaddr >> (12 + 9)) & (ENTRY_COUNT - 1)
+/// The most recently used last-level page tables.
+///
+/// Consecutive operations usually hit the same P1 (or P2) table, so caching
+/// them avoids re-walking the upper levels. The cache is only filled by
+/// operations that take `&mut self`, and must be cleared whenever an entry
+/// pointing to a table (at any level) is modified.
+///
+/// Each table is cached with the flags that the table entries above it are
+/// known to allow (see [`GenericPTE::widen_table`]), so that mapping pages
+/// with these flags does not need to walk the upper levels either.
+#[cfg(feature = "walk-cache")]
+struct WalkCache<M> {
+    /// `(prefix, paddr, allowed)` of the last used P1 table, where `prefix`
+    /// is the part of the address above the entries of the P2 table.
+    p1: Option<(usize, PhysAddr, MappingFlags)>,
+    /// `(prefix, paddr, allowed)` of the last used P2 table, where `prefix`
+    /// is the part of the address above the entries of the P3 table.
+    p2: Option<(usize, PhysAddr, MappingFlags)>,
+    _phantom: PhantomData<M>,
 }
 
-const fn p1_index(vaddr: usize) -> usize {
-    (vaddr >> 12) & (ENTRY_COUNT - 1)
+#[cfg(feature = "walk-cache")]
+impl<M: PagingMetaData> WalkCache<M> {
+    const fn new() -> Self {
+        Self {
+            p1: None,
+            p2: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the prefixes of `vaddr` that identify the P1 and P2 tables.
+    const fn prefixes(vaddr: usize) -> (usize, usize) {
+        (
+            vaddr >> level_shift::<M>(M::LEVELS - 2),
+            vaddr >> level_shift::<M>(M::LEVELS - 3),
+        )
+    }
+
+    /// Returns the cached table that contains the entries of `page_size`
+    /// pages covering `vaddr`.
+    fn get(&self, vaddr: usize, page_size: PageSize) -> Option<PhysAddr> {
+        self.get_allowing(vaddr, page_size, MappingFlags::empty())
+    }
+
+    /// Like [`WalkCache::get`], but only if the table entries above allow
+    /// `flags`.
+    fn get_allowing(
+        &self,
+        vaddr: usize,
+        page_size: PageSize,
+        flags: MappingFlags,
+    ) -> Option<PhysAddr> {
+        let (p1, p2) = Self::prefixes(vaddr);
+        let (slot, prefix) = match page_size {
+            PageSize::Size4K | PageSize::Size64K => (self.p1, p1),
+            PageSize::Size2M => (self.p2, p2),
+            PageSize::Size1G | PageSize::Size512G => return None,
+        };
+        slot.filter(|&(p, _, allowed)| p == prefix && allowed.contains(flags))
+            .map(|(_, paddr, _)| paddr)
+    }
+
+    /// Caches the table at `paddr`, whose table entries above allow `flags`
+    /// (in addition to those already known if it was cached before).
+    fn set(&mut self, vaddr: usize, page_size: PageSize, paddr: PhysAddr, flags: MappingFlags) {
+        let (p1, p2) = Self::prefixes(vaddr);
+        let (slot, prefix) = match page_size {
+            PageSize::Size4K | PageSize::Size64K => (&mut self.p1, p1),
+            PageSize::Size2M => (&mut self.p2, p2),
+            PageSize::Size1G | PageSize::Size512G => return,
+        };
+        let allowed = match *slot {
+            Some((p, table, allowed)) if p == prefix && table == paddr => allowed | flags,
+            _ => flags,
+        };
+        *slot = Some((prefix, paddr, allowed));
+    }
+
+    fn clear(&mut self) {
+        *self = Self::new();
+    }
 }
 
-/// A generic page table struct for 64-bit platform.
+/// A no-op walk cache, when the `walk-cache` feature is disabled.
+#[cfg(not(feature = "walk-cache"))]
+struct WalkCache<M>(PhantomData<M>);
+
+#[cfg(not(feature = "walk-cache"))]
+impl<M> WalkCache<M> {
+    const fn new() -> Self {
+        Self(PhantomData)
+    }
+
+    #[inline(always)]
+    fn get(&self, _vaddr: usize, _page_size: PageSize) -> Option<PhysAddr> {
+        None
+    }
+
+    #[inline(always)]
+    fn get_allowing(
+        &self,
+        _vaddr: usize,
+        _page_size: PageSize,
+        _flags: MappingFlags,
+    ) -> Option<PhysAddr> {
+        None
+    }
+
+    #[inline(always)]
+    fn set(&mut self, _vaddr: usize, _page_size: PageSize, _paddr: PhysAddr, _flags: MappingFlags) {
+    }
+
+    #[inline(always)]
+    fn clear(&mut self) {}
+}
+
+/// The counters of [`OpStats`], with the `trace` feature.
+#[cfg(feature = "trace")]
+struct Stats {
+    entries_read: AtomicU64,
+    entries_written: AtomicU64,
+    frames_allocated: AtomicU64,
+    frames_freed: AtomicU64,
+    subtrees_skipped: AtomicU64,
+    huge_mappings_used: AtomicU64,
+}
+
+#[cfg(feature = "trace")]
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            entries_read: AtomicU64::new(0),
+            entries_written: AtomicU64::new(0),
+            frames_allocated: AtomicU64::new(0),
+            frames_freed: AtomicU64::new(0),
+            subtrees_skipped: AtomicU64::new(0),
+            huge_mappings_used: AtomicU64::new(0),
+        }
+    }
+
+    fn read(&self, entries: usize) {
+        self.entries_read
+            .fetch_add(entries as u64, Ordering::Relaxed);
+    }
+
+    fn written(&self, entries: usize) {
+        self.entries_written
+            .fetch_add(entries as u64, Ordering::Relaxed);
+    }
+
+    fn allocated(&self) {
+        self.frames_allocated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn freed(&self, frames: usize) {
+        self.frames_freed
+            .fetch_add(frames as u64, Ordering::Relaxed);
+    }
+
+    fn skipped(&self) {
+        self.subtrees_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn huge(&self) {
+        self.huge_mappings_used.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the counters, and resets them if `reset` is `true`.
+    fn get(&self, reset: bool) -> OpStats {
+        let get = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+        OpStats {
+            entries_read: get(&self.entries_read),
+            entries_written: get(&self.entries_written),
+            frames_allocated: get(&self.frames_allocated),
+            frames_freed: get(&self.frames_freed),
+            subtrees_skipped: get(&self.subtrees_skipped),
+            huge_mappings_used: get(&self.huge_mappings_used),
+        }
+    }
+}
+
+/// No counters, when the `trace` feature is disabled.
+#[cfg(not(feature = "trace"))]
+struct Stats;
+
+#[cfg(not(feature = "trace"))]
+impl Stats {
+    const fn new() -> Self {
+        Self
+    }
+
+    #[inline(always)]
+    fn read(&self, _entries: usize) {}
+
+    #[inline(always)]
+    fn written(&self, _entries: usize) {}
+
+    #[inline(always)]
+    fn allocated(&self) {}
+
+    #[inline(always)]
+    fn freed(&self, _frames: usize) {}
+
+    #[inline(always)]
+    fn skipped(&self) {}
+
+    #[inline(always)]
+    fn huge(&self) {}
+}
+
+impl Stats {
+    /// Counts an entry examined by a recursive walk, and the subtree skipped
+    /// below it if it is an unused entry above the last level.
+    #[inline(always)]
+    fn visit(&self, unused: bool, upper: bool) {
+        self.read(1);
+        if unused && upper {
+            self.skipped();
+        }
+    }
+}
+
+/// The journal set by [`PageTable64::set_journal`], owned by the page table.
+#[cfg(feature = "alloc")]
+type JournalSink = alloc::boxed::Box<dyn ChangeJournal>;
+#[cfg(not(feature = "alloc"))]
+type JournalSink = Unset;
+
+/// The observer set by [`PageTable64::set_observer`], owned by the page
+/// table.
+#[cfg(feature = "alloc")]
+type ObserverSink = alloc::boxed::Box<dyn PageTableObserver>;
+#[cfg(not(feature = "alloc"))]
+type ObserverSink = Unset;
+
+/// Stands for the journal and the observer of a [`PageTable64`] without the
+/// `alloc` feature, where none can be set.
+#[cfg(not(feature = "alloc"))]
+enum Unset {}
+
+#[cfg(not(feature = "alloc"))]
+impl ChangeJournal for Unset {
+    fn record(&mut self, _record: ChangeRecord) -> bool {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "alloc"))]
+impl PageTableObserver for Unset {}
+
+/// The journal set by [`PageTable64::set_journal`], and the last changes,
+/// which are held back to be merged with the next ones.
 ///
-/// It also tracks all intermediate level tables. They will be deallocated
-/// When the [`PageTable64`] itself is dropped.
-pub struct PageTable64<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> {
-    root_paddr: PhysAddr,
-    _phantom: PhantomData<(M, PTE, H)>,
+/// As every change of an entry goes through it, it also holds the counters
+/// of the `trace` feature, and the observer set by
+/// [`PageTable64::set_observer`] with the change of the mappings held back
+/// for it.
+struct Journal {
+    sink: Option<JournalSink>,
+    /// Whether `sink` dropped a record.
+    full: bool,
+    /// Whether the changes are held back until the end of a region operation.
+    batch: bool,
+    pending: Option<PendingChange>,
+    stats: Stats,
+    observer: Option<ObserverSink>,
+    /// Whether the changes undo the ones of a failed operation.
+    rolling_back: bool,
+    /// The changes held back for this page table, then for the child of
+    /// [`PageTable64::clone_cow`], which are made page by page in turns.
+    observed: [Option<ObservedChange>; 2],
 }
 
-impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> PageTable64<M, PTE, H> {
-    /// Creates a new page table instance or returns the error.
+/// How the pages of an [`ObservedChange`] changed.
+#[derive(PartialEq, Clone, Copy)]
+enum ObservedKind {
+    Mapped(MappingFlags, PageSize),
+    Unmapped,
+    Protected(MappingFlags, MappingFlags),
+}
+
+/// Consecutive pages changed the same way, not reported to the observer yet.
+struct ObservedChange {
+    kind: ObservedKind,
+    vaddr: usize,
+    size: usize,
+    origin: ChangeOrigin,
+}
+
+/// Consecutive changed entries of the same level, not recorded yet.
+struct PendingChange {
+    vaddr: usize,
+    level: usize,
+    /// The size of the memory mapped through each entry.
+    size: usize,
+    count: usize,
+    /// The `(old, new)` bits of a single entry.
+    bits: Option<(u64, u64)>,
+}
+
+impl Journal {
+    const fn new() -> Self {
+        Self {
+            sink: None,
+            full: false,
+            batch: false,
+            pending: None,
+            stats: Stats::new(),
+            observer: None,
+            rolling_back: false,
+            observed: [None, None],
+        }
+    }
+
+    /// Adds the change of the `size` bytes of pages at `vaddr`, of the child
+    /// page table of [`PageTable64::clone_cow`] if `child` is set, for the
+    /// observer.
+    fn observe(&mut self, kind: ObservedKind, vaddr: usize, size: usize, child: bool) {
+        let origin = ChangeOrigin {
+            rolled_back: self.rolling_back,
+            child,
+        };
+        let slot = child as usize;
+        if let Some(observed) = &mut self.observed[slot]
+            && observed.kind == kind
+            && observed.origin == origin
+            && observed.vaddr.wrapping_add(observed.size) == vaddr
+        {
+            observed.size += size;
+            return;
+        }
+        self.report_slot(slot);
+        self.observed[slot] = Some(ObservedChange {
+            kind,
+            vaddr,
+            size,
+            origin,
+        });
+    }
+
+    /// Reports the changes held back to the observer.
+    fn report(&mut self) {
+        self.report_slot(0);
+        self.report_slot(1);
+    }
+
+    fn report_slot(&mut self, slot: usize) {
+        let (Some(observer), Some(observed)) = (&mut self.observer, self.observed[slot].take())
+        else {
+            return;
+        };
+        let ObservedChange {
+            kind,
+            vaddr,
+            size,
+            origin,
+        } = observed;
+        match kind {
+            ObservedKind::Mapped(flags, page_size) => {
+                observer.mapped(vaddr, size, flags, page_size, origin)
+            }
+            ObservedKind::Unmapped => observer.unmapped(vaddr, size, origin),
+            ObservedKind::Protected(old, new) => observer.protected(vaddr, size, old, new, origin),
+        }
+    }
+
+    /// Adds the change of the `count` entries of `size` bytes at `level` from
+    /// the one of `vaddr`, with the `(old, new)` bits of a single entry.
+    fn add(
+        &mut self,
+        vaddr: usize,
+        level: usize,
+        size: usize,
+        count: usize,
+        bits: Option<(u64, u64)>,
+    ) {
+        self.stats.written(count);
+        if self.sink.is_none() || self.full {
+            return;
+        }
+        if let Some(pending) = &mut self.pending
+            && pending.level == level
+            && pending.vaddr.wrapping_add(pending.count * pending.size) == vaddr
+        {
+            pending.count += count;
+            return;
+        }
+        self.flush();
+        if self.full {
+            return;
+        }
+        self.pending = Some(PendingChange {
+            vaddr,
+            level,
+            size,
+            count,
+            bits,
+        });
+    }
+
+    /// Records the pending changes and reports the one held back for the
+    /// observer at the end of an operation, unless it is part of a region
+    /// operation.
+    fn end(&mut self) {
+        if !self.batch {
+            self.flush();
+            self.report();
+        }
+    }
+
+    fn flush(&mut self) {
+        let Some(PendingChange {
+            vaddr,
+            level,
+            count,
+            bits,
+            ..
+        }) = self.pending.take()
+        else {
+            return;
+        };
+        let record = match bits {
+            Some((old, new)) if count == 1 => ChangeRecord::Entry {
+                vaddr,
+                level,
+                old,
+                new,
+            },
+            _ => ChangeRecord::Range {
+                vaddr,
+                level,
+                count,
+            },
+        };
+        if let Some(sink) = &mut self.sink
+            && !sink.record(record)
+        {
+            self.full = true;
+        }
+    }
+}
+
+/// The region walked by [`PageTable64::export_present_bitmap`].
+struct PresentWalk {
+    range: (usize, usize),
+    granule: usize,
+    include_absent: bool,
+}
+
+/// The state of a scan of [`PageTable64::estimate_working_set`].
+struct Scan<'a, I: IdleBits + ?Sized> {
+    /// The region, with the low bits of the addresses only.
+    range: (usize, usize),
+    split: bool,
+    idle: &'a mut I,
+    set: WorkingSet,
+    /// Whether an entry changed, which needs a flush.
+    changed: bool,
+    global: bool,
+}
+
+impl<I: IdleBits + ?Sized> Scan<'_, I> {
+    /// Counts the page of `size` bytes at `page`, and updates the idle
+    /// counters of its 4K pages in the region.
+    fn count(&mut self, page: usize, size: usize, accessed: bool) {
+        if accessed {
+            self.set.accessed += size;
+        } else {
+            self.set.idle += size;
+        }
+        let first = page.max(self.range.0);
+        let end = (page + size).min(self.range.1);
+        for index in (first - self.range.0) / PAGE_SIZE_4K..(end - self.range.0) / PAGE_SIZE_4K {
+            let idle = if accessed {
+                0
+            } else {
+                self.idle.idle(index).saturating_add(1)
+            };
+            self.idle.set_idle(index, idle);
+        }
+    }
+}
+
+/// A subtree of tables of a [`PageTable64`], exported by
+/// [`PageTable64::export_subtree`] to be linked into other page tables with
+/// [`PageTable64::import_subtree`].
+///
+/// The page tables it is linked into point to the same table frames, so the
+/// mappings in it are changed for all of them at once. Each page table
+/// holds a reference to each table frame of the subtree, maintained with
+/// [`PagingHandler::frame_shared`] and [`PagingHandler::frame_unshared`],
+/// the exporting one holding the initial reference. The handler frees the
+/// frames once the last reference is dropped.
+pub struct SharedSubtree<M: PagingMetaData, PTE: GenericPTE, K: SpaceKind = AnySpace> {
+    entry: PTE,
+    level: usize,
+    /// The root of the exporting page table.
+    owner: PhysAddr,
+    _phantom: PhantomData<(M, K)>,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, K: SpaceKind> SharedSubtree<M, PTE, K> {
+    /// Returns the physical address of the top table of the subtree.
+    pub fn table_paddr(&self) -> PhysAddr {
+        self.entry.paddr()
+    }
+
+    /// Returns the size of the region mapped by the subtree, to which the
+    /// addresses it is linked at are aligned.
+    pub const fn size(&self) -> usize {
+        1 << level_shift::<M>(self.level)
+    }
+}
+
+/// A subtree of tables unlinked from a [`PageTable64`] by
+/// [`PageTable64::detach_subtree`], whose pages and tables are yet to be
+/// freed.
+///
+/// No page table points to the tables anymore, so they can be walked and
+/// freed by [`DetachedSubtree::reclaim`] without holding the page table,
+/// e.g. by a background task tearing down an address space. Dropping it
+/// without reclaiming it leaks the tables.
+#[must_use]
+pub struct DetachedSubtree<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> {
+    entry: PTE,
+    level: usize,
+    vaddr: usize,
+    _phantom: PhantomData<(M, fn() -> H)>,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> DetachedSubtree<M, PTE, H> {
+    /// Returns the physical address of the top table of the subtree.
+    pub fn table_paddr(&self) -> PhysAddr {
+        self.entry.paddr()
+    }
+
+    /// Returns the region that the subtree mapped.
+    pub fn region(&self) -> (M::VirtAddr, usize) {
+        let size = 1 << level_shift::<M>(self.level);
+        (PageTable64::<M, PTE, H>::sign_extended(self.vaddr), size)
+    }
+
+    /// Calls `f` with the address, frame, size and flags of each page that
+    /// the subtree mapped, so that the caller frees the frames it owns, and
+    /// frees the tables, children first.
     ///
-    /// It will allocate a new page for the root page table.
-    pub fn try_new() -> PagingResult<Self> {
-        let root_paddr = Self::alloc_table()?;
-        Ok(Self {
-            root_paddr,
-            _phantom: PhantomData,
-        })
+    /// Like [`PageTable64::unmap`], [`PagingHandler::frame_unshared`] is
+    /// called for the copy-on-write pages before `f`. The pages of a group
+    /// with the contiguous hint are reported one by one.
+    ///
+    /// The TLB flush returned by [`PageTable64::detach_subtree`] must be done
+    /// before, since the CPUs may still use the tables until then.
+    pub fn reclaim(self, mut f: impl FnMut(M::VirtAddr, PhysAddr, PageSize, MappingFlags)) {
+        PageTable64::<M, PTE, H>::reclaim_subtree(
+            self.entry.paddr(),
+            self.level + 1,
+            self.vaddr,
+            &mut f,
+        );
+    }
+}
+
+/// A window during which the writable pages of a region of a
+/// [`PageTable64`] are write-protected, to find the pages written meanwhile,
+/// e.g. for a snapshot: the first write to each page faults, and the page is
+/// saved before it is made writable again.
+///
+/// [`ProtectSession::begin`] write-protects the region like
+/// [`PageTable64::enable_dirty_log`], [`ProtectSession::fault`] resolves the
+/// write faults, and [`ProtectSession::end`] makes the pages that were not
+/// written writable again. The pages that were read-only, including the
+/// copy-on-write pages, are left alone: their write faults go to
+/// [`PageTable64::handle_cow_fault`] or to the caller as usual.
+///
+/// Only [`MappingFlags::WRITE`] is removed from the pages, so their original
+/// flags are the current ones with it. The session only keeps which pages it
+/// protected, in a [`ProtectedPages`] store provided by the caller (e.g. a
+/// bitmap of one `u64` per 64 pages), rather than in the software bits of
+/// the entries, which some formats lack or the kernel already uses. Writable
+/// huge pages are split into 4K pages, and groups with the contiguous hint
+/// broken up, which [`ProtectSession::end`] does not undo.
+///
+/// If the entries store [`WpReasons::SNAPSHOT`] (see
+/// [`GenericPTE::WP_REASONS`]), the session protects the pages with that
+/// reason instead, so that it combines with dirty logging and copy-on-write:
+/// the copy-on-write pages are protected too, and the session only clears
+/// its own reason.
+///
+/// The session belongs to the page table it began on. The mappings of the
+/// region may be changed by other operations during the session: the pages
+/// unmapped or made writable meanwhile are skipped, but
+/// [`ProtectSession::end`] adds write access to the other pages it
+/// protected, whatever their flags have become.
+pub struct ProtectSession<'a, S: ProtectedPages + ?Sized> {
+    root: PhysAddr,
+    start: usize,
+    pages: usize,
+    protected: &'a mut S,
+    faulted: usize,
+}
+
+impl<'a, S: ProtectedPages + ?Sized> ProtectSession<'a, S> {
+    /// Write-protects the writable pages of the `size` bytes at `start` in
+    /// `pt`, recording them in `protected`.
+    ///
+    /// The returned flush must be done before the region is used, for the
+    /// writes to fault. `start` and `size` must be aligned to 4K, otherwise
+    /// it returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned).
+    /// If a table cannot be allocated to split a huge page, it returns
+    /// [`Err(PagingError::NoMemory)`](PagingError::NoMemory) after making
+    /// the pages protected so far writable again.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `protected` does not cover the region.
+    pub fn begin<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        pt: &mut PageTable64<M, PTE, H, K>,
+        start: M::VirtAddr,
+        size: usize,
+        protected: &'a mut S,
+    ) -> PagingResult<(Self, TlbFlushAll<M>)> {
+        trace!(
+            "begin_protect_session({:#x}): [{:#x}, {:#x})",
+            pt.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        let pages = size / PAGE_SIZE_4K;
+        for index in 0..pages {
+            protected.set_protected(index, false);
+        }
+        let session = Self {
+            root: pt.root_paddr(),
+            start: start.into(),
+            pages,
+            protected,
+            faulted: 0,
+        };
+        let result = pt.write_protect_region(start, size, WpReasons::SNAPSHOT, |vaddr| {
+            let index = (vaddr.into() - session.start) / PAGE_SIZE_4K;
+            session.protected.set_protected(index, true);
+        });
+        match result {
+            Ok(tlb) => Ok((session, tlb)),
+            Err(e) => {
+                pt.rolling_back(|pt| session.end(pt))?.1.ignore();
+                Err(e)
+            }
+        }
+    }
+
+    /// Resolves a write fault on the page containing `vaddr`, protected by
+    /// the session: calls `save` with the address of the page and its frame,
+    /// e.g. to copy its contents aside, then makes it writable again.
+    ///
+    /// Like [`PageTable64::handle_dirty_log_fault`], the returned flush is
+    /// only needed if the page was already writable. Returns
+    /// [`Err(PagingError::NotLogged)`](PagingError::NotLogged) if the page is
+    /// not protected by the session, e.g. because it was restored already.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pt` is not the page table of the session.
+    pub fn fault<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        &mut self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+        vaddr: M::VirtAddr,
+        save: impl FnOnce(M::VirtAddr, PhysAddr),
+    ) -> PagingResult<TlbFlush<M>> {
+        assert_eq!(
+            pt.root_paddr(),
+            self.root,
+            "not the page table of the session"
+        );
+        let index = vaddr.into().wrapping_sub(self.start) / PAGE_SIZE_4K;
+        if index >= self.pages || !self.protected.is_protected(index) {
+            return Err(PagingError::NotLogged);
+        }
+        let vaddr = vaddr.align_down_4k();
+        let (paddr, flags, _) = pt.query(vaddr)?;
+        save(vaddr, paddr);
+        let tlb = if PTE::WP_REASONS.contains(WpReasons::SNAPSHOT) {
+            pt.resolve_wp_fault(vaddr, WpReasons::SNAPSHOT, true)?.1
+        } else {
+            pt.handle_dirty_log_fault(vaddr, flags | MappingFlags::WRITE, |_| {})?
+        };
+        self.protected.set_protected(index, false);
+        self.faulted += 1;
+        Ok(tlb)
+    }
+
+    /// Ends the session: makes the pages still protected writable again, and
+    /// returns the number of pages resolved by [`ProtectSession::fault`].
+    ///
+    /// The returned flush is only needed for the stale read-only TLB entries
+    /// of the pages, which would otherwise cause spurious faults.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pt` is not the page table of the session.
+    pub fn end<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        assert_eq!(
+            pt.root_paddr(),
+            self.root,
+            "not the page table of the session"
+        );
+        trace!(
+            "end_protect_session({:#x}): {:#x}, {} faulted",
+            pt.root_paddr(),
+            self.start,
+            self.faulted,
+        );
+        for index in 0..self.pages {
+            if !self.protected.is_protected(index) {
+                continue;
+            }
+            let vaddr = (self.start + index * PAGE_SIZE_4K).into();
+            if PTE::WP_REASONS.contains(WpReasons::SNAPSHOT) {
+                if let Ok((_, tlb)) = pt.resolve_wp_fault(vaddr, WpReasons::SNAPSHOT, false) {
+                    tlb.ignore();
+                }
+                self.protected.set_protected(index, false);
+                continue;
+            }
+            match pt.query(vaddr) {
+                Ok((_, flags, PageSize::Size4K)) if !flags.contains(MappingFlags::WRITE) => {
+                    pt.protect(vaddr, flags | MappingFlags::WRITE)?.1.ignore();
+                }
+                _ => {}
+            }
+            self.protected.set_protected(index, false);
+        }
+        Ok((
+            self.faulted,
+            TlbFlushAll::new().with_generation(pt.generation),
+        ))
+    }
+}
+
+/// A reserved 4K page whose mapping changes often, e.g. a fixmap or highmem
+/// slot that temporarily maps arbitrary frames, created by
+/// [`PageTable64::fixed_slot`].
+///
+/// The walk to the entry of the page is done once, when the slot is created,
+/// which also creates the tables on the way. [`FixedSlot::set`] and
+/// [`FixedSlot::clear`] then write the entry in place, with atomic stores,
+/// without walking or allocating.
+///
+/// The slot belongs to the page table it was created on. It becomes stale
+/// when tables are unlinked from the page table, by
+/// [`PageTable64::release_subtree`], [`PageTable64::copy_from`] or
+/// [`PageTable64::clear_copy_range`], even if its own table stays: its
+/// methods then return [`Err(PagingError::StaleSlot)`](PagingError::StaleSlot)
+/// and it must be created again. Tables are otherwise only freed with the
+/// page table.
+pub struct FixedSlot<M: PagingMetaData> {
+    root: PhysAddr,
+    vaddr: M::VirtAddr,
+    /// The last-level table holding the entry.
+    table: PhysAddr,
+    /// The flags allowed by the table entries above.
+    allowed: MappingFlags,
+    /// The number of unlinks of the page table when the slot was created.
+    unlinks: u64,
+}
+
+impl<M: PagingMetaData> FixedSlot<M> {
+    /// Returns the address of the page of the slot.
+    pub fn vaddr(&self) -> M::VirtAddr {
+        self.vaddr
+    }
+
+    /// Maps the page of the slot to the 4K frame at `paddr`, replacing its
+    /// mapping if any.
+    ///
+    /// The checks of [`PageTable64::map`] apply, and a replaced mapping is
+    /// changed like in [`PageTable64::remap`], including break-before-make.
+    /// Flags not allowed by the table entries above the slot, i.e. not given
+    /// to [`PageTable64::fixed_slot`], widen them, which walks the tables.
+    ///
+    /// The returned flush is only needed if a mapping was replaced, or the
+    /// tables were widened.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pt` is not the page table of the slot.
+    pub fn set<PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        &self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+        paddr: PhysAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlush<M>> {
+        let result = self.set_inner(pt, paddr, flags);
+        PageTable64::<M, PTE, H, K>::reported("set_fixed_slot", self.vaddr.into(), result)
+    }
+
+    /// [`FixedSlot::set`], without reporting its errors.
+    fn set_inner<PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        &self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+        paddr: PhysAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlush<M>> {
+        let entry = self.entry(pt)?;
+        PageTable64::<M, PTE, H, K>::check_space(self.vaddr, flags)?;
+        pt.check_wx(self.vaddr, flags)?;
+        PageTable64::<M, PTE, H, K>::check_paddr(paddr, PageSize::Size4K)?;
+        PageTable64::<M, PTE, H, K>::check_memory_type(paddr, PageSize::Size4K, flags)?;
+        if entry.is_contiguous() {
+            let bbm = pt.needs_bbm();
+            PageTable64::<M, PTE, H, K>::break_contiguous(&mut pt.journal, entry, self.vaddr, bbm);
+        }
+        let old = *entry;
+        let new = PageTable64::<M, PTE, H, K>::new_leaf(paddr, flags, false);
+        let (used, limit) = (pt.mapped_bytes, pt.max_mapped_bytes);
+        let mapped =
+            PageTable64::<M, PTE, H, K>::mapped_after(&old, &new, PageSize::Size4K, used, limit)
+                .inspect_err(|_| pt.journal.end())?;
+        let tlb = if old.is_present() {
+            let tlb = PageTable64::<M, PTE, H, K>::update_leaf(
+                entry,
+                old,
+                new,
+                self.vaddr,
+                pt.needs_bbm(),
+                false,
+            );
+            if old.leaf_flags(false).contains(MappingFlags::COW) {
+                let paddr = old.leaf_paddr(false);
+                PageTable64::<M, PTE, H, K>::frame_unshared(paddr, PageSize::Size4K);
+            }
+            tlb
+        } else {
+            PageTable64::<M, PTE, H, K>::swap_leaf(entry, new);
+            TlbFlush::new_mapping(self.vaddr)
+        };
+        PageTable64::<M, PTE, H, K>::note(&mut pt.journal, self.vaddr, M::LEVELS - 1, old, new);
+        pt.mapped_bytes = mapped;
+        let tlb = if !self.allowed.contains(flags)
+            && pt.widen_tables(self.vaddr, PageSize::Size4K, flags)
+        {
+            tlb.merge(TlbFlush::new(self.vaddr))
+        } else {
+            tlb
+        };
+        Ok(pt.stamp(tlb))
+    }
+
+    /// Unmaps the page of the slot, like [`PageTable64::unmap`], and returns
+    /// the frame it was mapped to.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// page is not mapped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pt` is not the page table of the slot.
+    pub fn clear<PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        &self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+    ) -> PagingResult<(PhysAddr, TlbFlush<M>)> {
+        let result = self.clear_inner(pt);
+        PageTable64::<M, PTE, H, K>::reported("clear_fixed_slot", self.vaddr.into(), result)
+    }
+
+    /// [`FixedSlot::clear`], without reporting its errors.
+    fn clear_inner<PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        &self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+    ) -> PagingResult<(PhysAddr, TlbFlush<M>)> {
+        let entry = self.entry(pt)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        if entry.is_contiguous() {
+            let bbm = pt.needs_bbm();
+            PageTable64::<M, PTE, H, K>::break_contiguous(&mut pt.journal, entry, self.vaddr, bbm);
+        }
+        let mut new = *entry;
+        new.clear();
+        let old = PageTable64::<M, PTE, H, K>::swap_leaf(entry, new);
+        PageTable64::<M, PTE, H, K>::note(&mut pt.journal, self.vaddr, M::LEVELS - 1, old, new);
+        if old.leaf_flags(false).contains(MappingFlags::COW) {
+            PageTable64::<M, PTE, H, K>::frame_unshared(old.leaf_paddr(false), PageSize::Size4K);
+        }
+        pt.mapped_bytes = pt.mapped_bytes.saturating_sub(PAGE_SIZE_4K);
+        let tlb = TlbFlush::new(self.vaddr).with_global(old.is_global());
+        Ok((old.leaf_paddr(false), pt.stamp(tlb)))
+    }
+
+    /// Returns the entry of the slot in `pt`, if the slot is not stale.
+    fn entry<'a, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        &self,
+        pt: &mut PageTable64<M, PTE, H, K>,
+    ) -> PagingResult<&'a mut PTE> {
+        assert_eq!(pt.root_paddr(), self.root, "not the page table of the slot");
+        if pt.unlinks != self.unlinks {
+            return Err(PagingError::StaleSlot);
+        }
+        let index = PageTable64::<M, PTE, H, K>::index_of(self.vaddr.into(), M::LEVELS - 1);
+        Ok(&mut pt.table_of_mut(self.table, M::LEVELS - 1)[index])
+    }
+}
+
+/// A 4K frame given to [`PageTable64::build_trampoline_table`] to hold one of
+/// the tables it builds, as it allocates none.
+pub struct PhysFrameSlot<'a> {
+    paddr: PhysAddr,
+    /// The memory of the frame, as seen by the code building the table.
+    frame: &'a mut [u64; 512],
+    used: bool,
+}
+
+impl<'a> PhysFrameSlot<'a> {
+    /// Creates a slot for the frame at `paddr`, whose memory is `frame` where
+    /// the table is built, e.g. a static buffer or the frame mapped by the
+    /// early boot code.
+    pub fn new(paddr: PhysAddr, frame: &'a mut [u64; 512]) -> Self {
+        Self {
+            paddr,
+            frame,
+            used: false,
+        }
+    }
+
+    /// Returns the physical address of the frame.
+    pub const fn paddr(&self) -> PhysAddr {
+        self.paddr
+    }
+
+    /// Returns whether a table was built in the frame, which must then be
+    /// kept while the table is in use.
+    pub const fn is_used(&self) -> bool {
+        self.used
+    }
+}
+
+/// The number of operations a [`Transaction`] can stage.
+const MAX_STAGED_OPS: usize = 8;
+
+/// A leaf entry saved by a [`Transaction`], to write it back if the
+/// transaction fails. The buffer given to [`PageTable64::transaction`] can be
+/// filled with [`SavedEntry::default`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SavedEntry {
+    vaddr: usize,
+    level: usize,
+    bits: u64,
+}
+
+/// How an operation staged in a [`Transaction`] changes its region.
+#[derive(Clone, Copy)]
+enum StagedKind {
+    Unmap,
+    Protect(MappingFlags),
+    Map {
+        paddr: PhysAddr,
+        flags: MappingFlags,
+        allow_huge: bool,
+    },
+}
+
+/// An operation staged in a [`Transaction`], on the region from `start` to
+/// `last` included.
+#[derive(Clone, Copy)]
+struct StagedOp {
+    kind: StagedKind,
+    start: usize,
+    last: usize,
+    /// The range of its entries in the saved entries, empty for maps.
+    saved: (usize, usize),
+}
+
+impl StagedOp {
+    const fn overlaps(&self, start: usize, last: usize) -> bool {
+        self.start <= last && start <= self.last
+    }
+
+    /// Whether the pages of the region are unmapped by the operation.
+    const fn unmaps(&self, start: usize, last: usize) -> bool {
+        matches!(self.kind, StagedKind::Unmap) && self.start <= start && last <= self.last
+    }
+}
+
+/// Changes of several regions of a [`PageTable64`] made all together or not
+/// at all, e.g. for an `mmap` with `MAP_FIXED` replacing parts of several
+/// mappings: the old pages are unmapped, then the new ones mapped, and if
+/// mapping fails, the old pages are mapped again.
+///
+/// The operations are only staged by [`Transaction::unmap_region`],
+/// [`Transaction::protect_region`] and [`Transaction::map_region`], which
+/// check them against the page table as the earlier operations will leave
+/// it, and save the entries they will change. Nothing changes until
+/// [`Transaction::commit`] applies them in order. If one fails, the pages
+/// mapped so far are unmapped, and the saved entries written back, with
+/// their frames and all their bits. Dropping the transaction discards the
+/// staged operations.
+///
+/// The entries are saved in a buffer given by the caller, so that nothing is
+/// allocated, and at most [`Transaction::MAX_OPS`] operations can be staged.
+/// Their regions may not overlap, except a region mapped over pages unmapped
+/// by an earlier operation.
+pub struct Transaction<'a, M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> {
+    pt: &'a mut PageTable64<M, PTE, H, K>,
+    saved: &'a mut [SavedEntry],
+    ops: [Option<StagedOp>; MAX_STAGED_OPS],
+    len: usize,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>
+    Transaction<'_, M, PTE, H, K>
+{
+    /// The maximum number of operations of a transaction.
+    pub const MAX_OPS: usize = MAX_STAGED_OPS;
+
+    /// Stages the unmapping of the `size` bytes at `vaddr`. Unlike
+    /// [`PageTable64::unmap_region`], the holes are skipped, and the entries
+    /// carrying an [`AbsentEntry`] are cleared too. A group with the
+    /// contiguous hint is broken up like in [`PageTable64::unmap`].
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// the region is not aligned to 4K,
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if it covers only a part of a huge page,
+    /// [`Err(PagingError::Locked)`](PagingError::Locked) if a page is locked
+    /// by [`PageTable64::lock_region`],
+    /// [`Err(PagingError::Overlapping)`](PagingError::Overlapping) if the
+    /// region overlaps the one of an earlier operation, and
+    /// [`Err(PagingError::NoMemory)`](PagingError::NoMemory) if there is no
+    /// room left for the operation or its saved entries. The operation is
+    /// then not staged, but the earlier ones are kept.
+    pub fn unmap_region(&mut self, vaddr: M::VirtAddr, size: usize) -> PagingResult {
+        let result = self.stage(vaddr.into(), size, StagedKind::Unmap);
+        PageTable64::<M, PTE, H, K>::reported("transaction_unmap_region", vaddr.into(), result)
+    }
+
+    /// Stages the update of the flags of the pages in the `size` bytes at
+    /// `vaddr`, skipping the holes. It fails like
+    /// [`Transaction::unmap_region`], except for the locked pages, and if
+    /// `flags` do not belong to the address space (see [`SpaceKind`]) or
+    /// break W^X (see [`PageTable64::enforce_wx`]).
+    pub fn protect_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> PagingResult {
+        let result = self.stage(vaddr.into(), size, StagedKind::Protect(flags));
+        PageTable64::<M, PTE, H, K>::reported("transaction_protect_region", vaddr.into(), result)
+    }
+
+    /// Stages the mapping of the `size` bytes at `vaddr` to the ones at
+    /// `paddr`, like [`PageTable64::map_region`].
+    ///
+    /// The region must be free once the earlier operations are applied,
+    /// otherwise it returns
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped).
+    /// Whether the pages can be mapped, e.g. within the limits of
+    /// [`PageTable64::set_limits`], is only known when committing.
+    pub fn map_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult {
+        let kind = StagedKind::Map {
+            paddr,
+            flags,
+            allow_huge,
+        };
+        let result = self.stage(vaddr.into(), size, kind);
+        PageTable64::<M, PTE, H, K>::reported("transaction_map_region", vaddr.into(), result)
+    }
+
+    /// Applies the staged operations in order.
+    ///
+    /// If one fails, the changes made so far are undone in reverse order, the
+    /// entire TLB is flushed with [`PageTable64::flush_all`], and the error
+    /// is returned.
+    pub fn commit(self) -> PagingResult<TlbFlushAll<M>> {
+        let ops = &self.ops[..self.len];
+        let vaddr = ops.first().copied().flatten().map_or(0, |op| op.start);
+        let result = self.pt.batched(|pt| pt.commit_staged(ops, self.saved));
+        PageTable64::<M, PTE, H, K>::reported("transaction_commit", vaddr, result)
+    }
+
+    fn stage(&mut self, start: usize, size: usize, kind: StagedKind) -> PagingResult {
+        if size == 0 {
+            return Ok(());
+        }
+        if self.len == MAX_STAGED_OPS {
+            return Err(PagingError::NoMemory);
+        }
+        let staged = &self.ops[..self.len];
+        let used = staged.last().copied().flatten().map_or(0, |op| op.saved.1);
+        let op = self.pt.stage(staged, self.saved, used, start, size, kind)?;
+        self.ops[self.len] = Some(op);
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// A generic page table struct for 64-bit platform.
+///
+/// It also tracks all intermediate level tables. They will be deallocated
+/// When the [`PageTable64`] itself is dropped.
+///
+/// The methods taking `&self` (e.g. [`PageTable64::query`] and
+/// [`PageTable64::walk`]) read the entries with atomic loads, never through
+/// references to the table memory. They may run while the hardware updates
+/// the accessed and dirty bits, or while another CPU modifies the tables. In
+/// that case, each entry is read as a whole, either before or after each
+/// write, but a walk may see a mix of old and new entries. To follow a new
+/// table, the writer must make its contents visible before the entry pointing
+/// to it (e.g. with a release fence).
+///
+/// The page table is [`Send`] and [`Sync`] whatever `H` and `K`, which are
+/// only used for their associated functions. Sharing `&PageTable64` between
+/// CPUs is sound: the methods taking `&self` only update atomics. Changes
+/// need `&mut PageTable64`, so a table shared for changes needs a lock, e.g.
+/// with the `LockedPageTable` of the `locked` feature, which also queries and
+/// handles faults without taking it.
+///
+/// `K` is the kind of address space it maps (see [`SpaceKind`]). The default
+/// [`AnySpace`] accepts every mapping.
+pub struct PageTable64<
+    M: PagingMetaData,
+    PTE: GenericPTE,
+    H: PagingHandler,
+    K: SpaceKind = AnySpace,
+> {
+    root_paddr: PhysAddr,
+    walk_cache: WalkCache<M>,
+    /// Incremented by every change that needs a TLB flush.
+    generation: u64,
+    /// The generation at the last [`PageTable64::flush_all`].
+    flushed: AtomicU64,
+    /// The generation of the last change to a global mapping.
+    global_generation: u64,
+    /// Incremented whenever tables are unlinked, which makes the
+    /// [`FixedSlot`]s stale.
+    unlinks: u64,
+    mapped_bytes: usize,
+    table_frames: usize,
+    max_mapped_bytes: usize,
+    max_table_frames: usize,
+    huge_cow: HugeCowPolicy,
+    /// Whether mappings both writable and executable are refused, see
+    /// [`PageTable64::enforce_wx`].
+    enforce_wx: bool,
+    journal: Journal,
+    /// The number of CPUs using the table, as reported by
+    /// [`PageTable64::set_active`], or [`UNTRACKED`] before the first report.
+    active: AtomicUsize,
+    _phantom: PhantomData<(M, PTE)>,
+    /// Only their associated functions are used, so they do not make the
+    /// page table `!Send` or `!Sync`.
+    _functions: PhantomData<fn() -> (H, K)>,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> PageTable64<M, PTE, H, K> {
+    /// Creates a new page table instance or returns the error.
+    ///
+    /// It will allocate a new page for the root page table.
+    pub fn try_new() -> PagingResult<Self> {
+        const {
+            assert!(
+                M::BYTE_ORDER.is(PTE::BYTE_ORDER),
+                "the entries are not in the byte order of the metadata"
+            )
+        };
+        let root_paddr = Self::alloc_table(0)?;
+        Ok(Self {
+            root_paddr,
+            walk_cache: WalkCache::new(),
+            generation: 0,
+            flushed: AtomicU64::new(0),
+            global_generation: 0,
+            unlinks: 0,
+            mapped_bytes: 0,
+            table_frames: Self::table_frames_at(0),
+            max_mapped_bytes: usize::MAX,
+            max_table_frames: usize::MAX,
+            huge_cow: HugeCowPolicy::Split,
+            enforce_wx: false,
+            journal: Journal::new(),
+            active: AtomicUsize::new(UNTRACKED),
+            _phantom: PhantomData,
+            _functions: PhantomData,
+        })
+    }
+
+    /// Returns the physical address of the root page table.
+    pub const fn root_paddr(&self) -> PhysAddr {
+        self.root_paddr
+    }
+
+    /// Returns the generation of the page table.
+    ///
+    /// It starts at `0` and is incremented by every change that needs a TLB
+    /// flush, i.e. that returns a [`TlbFlush`] or [`TlbFlushAll`] which does
+    /// something, and by [`PageTable64::copy_from`] and
+    /// [`PageTable64::clear_copy_range`]. New mappings that need no flush (see
+    /// [`PagingMetaData::TLB_CACHES_INVALID`]) leave it unchanged.
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Flushes the entire TLB, and records that all changes up to the current
+    /// generation have taken effect.
+    ///
+    /// Pending flushes of older generations then do nothing in
+    /// [`TlbFlush::flush_if_current`] and [`TlbFlushAll::flush_if_current`].
+    /// If a global mapping changed since the last flush, the entries of global
+    /// mappings are flushed too, with [`PagingMetaData::flush_tlb_global`].
+    pub fn flush_all(&self) {
+        if self.global_generation > self.flushed_generation() {
+            M::flush_tlb_global();
+        } else {
+            M::flush_tlb(None);
+        }
+        self.flushed.store(self.generation, Ordering::Relaxed);
+    }
+
+    pub(crate) fn flushed_generation(&self) -> u64 {
+        self.flushed.load(Ordering::Relaxed)
+    }
+
+    /// Reports that the table was loaded on a CPU (e.g. into `CR3` or
+    /// `satp`) if `active` is `true`, or unloaded from it otherwise.
+    ///
+    /// The table counts the CPUs using it. Until the first report, its
+    /// activity is unknown, and it is handled as both possibly active and
+    /// droppable, as before tracking existed. Once tracked:
+    ///
+    /// - Dropping an active table leaks its frames instead of freeing them
+    ///   while the hardware may still walk them, and panics with debug
+    ///   assertions.
+    /// - Break-before-make (see [`PagingMetaData::BREAK_BEFORE_MAKE`]) is
+    ///   skipped on an inactive table, with the flushes it needs.
+    ///
+    /// A CPU must report the unload only after the TLB entries of the table
+    /// are flushed from it (e.g. when its ASID is invalidated), since a later
+    /// load may otherwise see stale entries conflicting with the new ones.
+    /// The flushes returned by the operations are still left to the caller.
+    pub fn set_active(&self, active: bool) {
+        let update = |count| {
+            Some(match (count, active) {
+                (UNTRACKED, true) => 1,
+                (UNTRACKED, false) => 0,
+                (count, true) => count + 1,
+                (count, false) => count.saturating_sub(1),
+            })
+        };
+        let prev = self
+            .active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, update);
+        debug_assert!(
+            active || prev != Ok(0),
+            "page table {:#x} unloaded more times than loaded",
+            self.root_paddr,
+        );
+    }
+
+    /// Whether the table is loaded on a CPU, as reported by
+    /// [`PageTable64::set_active`]. It is `false` before the first report.
+    pub fn is_active(&self) -> bool {
+        !matches!(self.active.load(Ordering::Acquire), 0 | UNTRACKED)
+    }
+
+    /// Whether leaves must be changed with break-before-make: if the metadata
+    /// requires it and the table is not known to be inactive.
+    fn needs_bbm(&self) -> bool {
+        M::BREAK_BEFORE_MAKE && self.active.load(Ordering::Acquire) != 0
+    }
+
+    /// Limits the size of the memory mapped by this page table, and the number
+    /// of frames used for its tables (including the root table).
+    ///
+    /// Operations that would exceed a limit fail with
+    /// [`PagingError::QuotaExceeded`] before changing anything. Huge pages
+    /// count with their full size. Pass [`usize::MAX`] for no limit, which is
+    /// the default. Usage above a new limit is kept, but cannot grow.
+    ///
+    /// The mappings and tables reached through entries copied by
+    /// [`PageTable64::copy_from`] are not accounted.
+    pub fn set_limits(&mut self, max_mapped_bytes: usize, max_table_frames: usize) {
+        self.max_mapped_bytes = max_mapped_bytes;
+        self.max_table_frames = max_table_frames;
+    }
+
+    /// Sets how [`PageTable64::handle_cow_fault`] resolves faults on huge
+    /// pages. The default is [`HugeCowPolicy::Split`].
+    pub fn set_huge_cow_policy(&mut self, policy: HugeCowPolicy) {
+        self.huge_cow = policy;
+    }
+
+    /// Sets whether mappings that are both writable and executable (W^X) are
+    /// refused, which is off by default.
+    ///
+    /// When enforced, [`PageTable64::map`], [`PageTable64::remap`],
+    /// [`PageTable64::protect`] and the operations built on them (e.g.
+    /// [`PageTable64::map_region`], [`PageTable64::protect_region`], the
+    /// [`Transaction`]s and the [`FixedSlot`]s) fail with
+    /// [`Err(PagingError::WxViolation)`](PagingError::WxViolation) for flags
+    /// with [`MappingFlags::EXECUTE`] and either [`MappingFlags::WRITE`] or
+    /// [`MappingFlags::COW`], which becomes writable on the first write.
+    /// This covers making an executable page writable, and a writable one
+    /// executable. [`PageTable64::allow_wx`] lifts it for an explicit
+    /// exception, e.g. a JIT.
+    ///
+    /// Mappings that existed before are kept, and only checked when their
+    /// flags change. The faults that give write access back to a page that
+    /// had it (e.g. [`PageTable64::handle_cow_fault`] or
+    /// [`PageTable64::handle_dirty_log_fault`]) are not checked either, nor
+    /// is [`PageTable64::clone_cow`], which clones the W+X mappings like the
+    /// others: the child gets the setting, and its copy-on-write pages
+    /// become W+X again when written.
+    pub fn enforce_wx(&mut self, enforce: bool) {
+        self.enforce_wx = enforce;
+    }
+
+    /// Returns whether W^X is enforced (see [`PageTable64::enforce_wx`]).
+    pub const fn wx_enforced(&self) -> bool {
+        self.enforce_wx
+    }
+
+    /// Runs `f` on the page table with W^X lifted (see
+    /// [`PageTable64::enforce_wx`]), so that it can create writable and
+    /// executable mappings, and enforces it again afterwards if it was.
+    pub fn allow_wx<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let enforce = core::mem::replace(&mut self.enforce_wx, false);
+        let ret = f(self);
+        self.enforce_wx |= enforce;
+        ret
+    }
+
+    /// Sets the journal that records the changes of the entries from now on,
+    /// e.g. to keep a shadow page table in sync, and returns the previous
+    /// one. The page table owns it until [`PageTable64::take_journal`].
+    ///
+    /// Every operation that writes entries records them in the journal before
+    /// returning, including the table entries and the entries written while
+    /// splitting huge pages or breaking up contiguous groups. Changes of
+    /// consecutive entries at the same level, e.g. by a region operation or
+    /// [`PageTable64::clone_cow`], are merged into
+    /// [`ChangeRecord::Range`]s. The changes made by the hardware (the
+    /// accessed and dirty bits) are not recorded. Writes that leave an entry
+    /// as is may be skipped.
+    ///
+    /// Once the journal drops a record, no more records are passed to it, and
+    /// setting it again (after the consumer resynchronized) resumes
+    /// recording.
+    #[cfg(feature = "alloc")]
+    pub fn set_journal(
+        &mut self,
+        journal: alloc::boxed::Box<dyn ChangeJournal>,
+    ) -> Option<alloc::boxed::Box<dyn ChangeJournal>> {
+        let old = self.take_journal();
+        self.journal.sink = Some(journal);
+        old
+    }
+
+    /// Stops recording the changes of the entries, and returns the journal
+    /// set by [`PageTable64::set_journal`], after passing it the changes
+    /// held back.
+    #[cfg(feature = "alloc")]
+    pub fn take_journal(&mut self) -> Option<alloc::boxed::Box<dyn ChangeJournal>> {
+        self.journal.flush();
+        self.journal.full = false;
+        self.journal.sink.take()
+    }
+
+    /// Sets the observer that is told about the changes of the mappings from
+    /// now on, e.g. to keep the VMAs in sync with the page table, and
+    /// returns the previous one. The page table owns it until
+    /// [`PageTable64::take_observer`].
+    ///
+    /// Every operation reports the pages it mapped, unmapped or protected
+    /// (see [`PageTableObserver`]) before returning, including the ones it
+    /// changed before failing. A region operation reports each run of
+    /// consecutive pages changed the same way once. A page remapped to
+    /// another frame is reported unmapped, then mapped. The pages that a
+    /// failing operation changes back, e.g. [`PageTable64::map_alloc`] or
+    /// [`PageTable64::apply_shared`], are reported again with
+    /// [`ChangeOrigin::rolled_back`]. [`PageTable64::clone_cow`] reports the
+    /// pages made copy-on-write here, then the pages of the child with
+    /// [`ChangeOrigin::child`].
+    ///
+    /// Like the journal (see [`PageTable64::set_journal`]), it is not told
+    /// about the changes made by the hardware, and the changes of the flags
+    /// that [`MappingFlags`] does not hold, e.g. tags and locks. The pages
+    /// of the subtrees linked by [`PageTable64::import_subtree`] are not
+    /// reported either, as they change with the page table that exported
+    /// them. Without an observer, a single check is added to each change.
+    #[cfg(feature = "alloc")]
+    pub fn set_observer(
+        &mut self,
+        observer: alloc::boxed::Box<dyn PageTableObserver>,
+    ) -> Option<alloc::boxed::Box<dyn PageTableObserver>> {
+        let old = self.take_observer();
+        self.journal.observer = Some(observer);
+        old
+    }
+
+    /// Stops reporting the changes of the mappings, and returns the observer
+    /// set by [`PageTable64::set_observer`], after telling it about the
+    /// changes held back.
+    #[cfg(feature = "alloc")]
+    pub fn take_observer(&mut self) -> Option<alloc::boxed::Box<dyn PageTableObserver>> {
+        self.journal.report();
+        self.journal.observer.take()
+    }
+
+    /// Returns the size of the memory mapped by this page table, in bytes.
+    pub const fn mapped_bytes(&self) -> usize {
+        self.mapped_bytes
+    }
+
+    /// Returns the number of frames used for the tables, including the root
+    /// table.
+    pub const fn table_frames(&self) -> usize {
+        self.table_frames
+    }
+
+    /// Returns the counters of the work done by the operations since the page
+    /// table was created or the counters were last taken.
+    ///
+    /// They are only kept with the `trace` feature, and are meant to compare
+    /// the cost of operations in tests and benchmarks.
+    #[cfg(feature = "trace")]
+    pub fn stats(&self) -> OpStats {
+        self.journal.stats.get(false)
+    }
+
+    /// Returns the counters like [`PageTable64::stats`], and resets them.
+    #[cfg(feature = "trace")]
+    pub fn take_stats(&self) -> OpStats {
+        self.journal.stats.get(true)
+    }
+
+    /// Returns the number of entries in use at each level, starting with the
+    /// root: tables and present leaves, as reached by [`PageTable64::walk`].
+    ///
+    /// The levels below [`PagingMetaData::LEVELS`] are always zero. It is
+    /// computed by walking the whole page table, in O(mapped size).
+    pub fn level_occupancy(&self) -> [usize; MAX_LEVELS] {
+        let counts = [const { Cell::new(0) }; MAX_LEVELS];
+        let count = |level: usize, _, _, _: &PTE| counts[level].set(counts[level].get() + 1);
+        let _ = self.walk(usize::MAX, Some(&count), None);
+        counts.map(Cell::into_inner)
+    }
+
+    /// Returns how full the table at `level` (starting with the root) that
+    /// covers `vaddr` is, as the number of entries in use and the number of
+    /// entries of the table, e.g. to place new mappings where the tables
+    /// already exist.
+    ///
+    /// The entries in use are those that are not [unused](GenericPTE::is_unused),
+    /// including absent entries that carry a payload. If `vaddr` is mapped by a
+    /// huge page above `level`, the table is reported as full. Returns [`None`]
+    /// if the table does not exist, or if `level` is not below
+    /// [`PagingMetaData::LEVELS`].
+    ///
+    /// This reads at most one entry per level above, and the entries of the
+    /// table.
+    pub fn table_occupancy_at(&self, vaddr: M::VirtAddr, level: usize) -> Option<(usize, usize)> {
+        if level >= M::LEVELS {
+            return None;
+        }
+        let vaddr: usize = vaddr.into();
+        let entries = table_entries::<M>(level);
+        let mut table = self.root_paddr;
+        for level in 0..level {
+            let entry = Self::load_entry(table, Self::index_of(vaddr, level));
+            if entry.is_unused() {
+                return None;
+            }
+            if entry.is_huge() {
+                return Some((entries, entries));
+            }
+            table = entry.paddr();
+        }
+        let used = (0..entries)
+            .filter(|&i| !Self::load_entry(table, i).is_unused())
+            .count();
+        Some((used, entries))
+    }
+
+    /// Returns the entries in use in the root table, with their indices.
+    ///
+    /// The entries are read when the iterator reaches them.
+    pub fn root_entries(&self) -> impl Iterator<Item = (usize, PTE)> + '_ {
+        (0..table_entries::<M>(0))
+            .map(|i| (i, Self::load_entry(self.root_paddr, i)))
+            .filter(|(_, entry)| !entry.is_unused())
+    }
+
+    /// Maps a virtual page to a physical frame with the given `page_size`
+    /// and mapping `flags`.
+    ///
+    /// The virtual page starts with `vaddr`, aligned down to `page_size`, and
+    /// the physical frame starts with `target`, which must be aligned to
+    /// `page_size`: a misaligned `target` is rejected with
+    /// [`Err(PagingError::InvalidPaddr)`](PagingError::InvalidPaddr) rather
+    /// than aligned down.
+    ///
+    /// Returns [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped)
+    /// if the mapping is already present. A non-present entry carrying an
+    /// [`AbsentEntry`](crate::AbsentEntry) is replaced, except for the guard
+    /// pages of [`PageTable64::map_alloc_with`], for which it returns
+    /// [`Err(PagingError::Guarded)`](PagingError::Guarded).
+    ///
+    /// The intermediate table entries are widened to allow `flags` if their
+    /// format has permissions (see [`GenericPTE::widen_table`]).
+    ///
+    /// As no mapping is replaced, the returned [`TlbFlush`] only needs to do
+    /// anything if [`PagingMetaData::TLB_CACHES_INVALID`] is `true`, or if an
+    /// existing table entry was widened.
+    ///
+    /// [`PageSize::Size64K`] maps a group of 16 4K entries with the contiguous
+    /// hint, or returns
+    /// [`Err(PagingError::UnsupportedPageSize)`](PagingError::UnsupportedPageSize)
+    /// if the entry format has none. Pages larger than
+    /// [`PagingMetaData::MAX_PAGE_SIZE`] are rejected the same way.
+    ///
+    /// Returns [`Err(PagingError::InvalidPaddr)`](PagingError::InvalidPaddr)
+    /// if `target` is not aligned to `page_size` or the page is not valid for
+    /// [`PagingMetaData::paddr_is_valid`],
+    /// [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if the kind
+    /// of address space rejects the mapping (see [`SpaceKind`]), and
+    /// [`Err(PagingError::AttributeConflict)`](PagingError::AttributeConflict)
+    /// if the frame must be mapped with another memory type (see
+    /// [`PagingHandler::memory_type_of`]).
+    ///
+    /// Returns
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// with the start and level of the huge page if `vaddr` is inside a page
+    /// larger than `page_size`; the huge page is left untouched.
+    pub fn map(
+        &mut self,
+        vaddr: M::VirtAddr,
+        target: PhysAddr,
+        page_size: PageSize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlush<M>> {
+        let result = self.map_inner(vaddr, target, page_size, flags);
+        Self::reported("map", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map`], without reporting its errors.
+    fn map_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        target: PhysAddr,
+        page_size: PageSize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlush<M>> {
+        if !Self::page_size_supported(page_size) {
+            return Err(PagingError::UnsupportedPageSize);
+        }
+        Self::check_space(vaddr, flags)?;
+        self.check_wx(vaddr, flags)?;
+        Self::check_paddr(target, page_size)?;
+        Self::check_memory_type(target, page_size, flags)?;
+        if page_size == PageSize::Size64K {
+            return self.map_contiguous(vaddr, target, flags);
+        }
+        let mapped = Self::check_quota(
+            QuotaKind::MappedBytes,
+            self.mapped_bytes,
+            self.max_mapped_bytes,
+            page_size as usize,
+        )?;
+        let (entry, widened) = self.get_entry_mut_or_create(vaddr, page_size, flags)?;
+        let err = match entry.absent() {
+            _ if entry.is_unused() => None,
+            None | Some(AbsentEntry::Inaccessible(_)) => Some(PagingError::AlreadyMapped),
+            Some(AbsentEntry::Guard(owner)) => Some(Self::guarded(owner)),
+            Some(_) => None,
+        };
+        if let Some(err) = err {
+            // The tables above may have been widened.
+            self.journal.end();
+            return Err(err);
+        }
+        let old = *entry;
+        *entry = Self::new_leaf(target, flags, page_size.is_huge());
+        let new = *entry;
+        self.mapped_bytes = mapped;
+        let level = Self::leaf_level(page_size);
+        Self::note(&mut self.journal, vaddr, level, old, new);
+        if page_size.is_huge() {
+            self.journal.stats.huge();
+        }
+        let tlb = if widened {
+            TlbFlush::new(vaddr)
+        } else {
+            TlbFlush::new_mapping(vaddr)
+        };
+        Ok(self.stamp(tlb))
+    }
+
+    /// Remap the mapping starts with `vaddr`, updates both the physical address
+    /// and flags.
+    ///
+    /// Returns the page size of the mapping.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// intermediate level tables of the mapping is not present.
+    ///
+    /// If [`PagingMetaData::BREAK_BEFORE_MAKE`] is `true` (AArch64) and a valid
+    /// mapping is changed to another output address or memory type, the entry
+    /// is invalidated and the TLB flushed for `vaddr` before the new entry is
+    /// written, unless the table is known to be inactive (see
+    /// [`PageTable64::set_active`]).
+    ///
+    /// A page in a group with the contiguous hint is first split from the
+    /// group (see [`PageTable64::unmap`]).
+    ///
+    /// Returns [`Err(PagingError::InvalidPaddr)`](PagingError::InvalidPaddr)
+    /// if `paddr` is not aligned to the page size or the page is not valid
+    /// for [`PagingMetaData::paddr_is_valid`],
+    /// [`Err(PagingError::AttributeConflict)`](PagingError::AttributeConflict)
+    /// like [`PageTable64::map`], and
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if `vaddr` is inside a huge page but not its start.
+    pub fn remap(
+        &mut self,
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<(PageSize, TlbFlush<M>)> {
+        let result = self.remap_inner(vaddr, paddr, flags);
+        Self::reported("remap", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::remap`], without reporting its errors.
+    fn remap_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<(PageSize, TlbFlush<M>)> {
+        self.check_wx(vaddr, flags)?;
+        let (used, limit) = (self.mapped_bytes, self.max_mapped_bytes);
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        Self::check_page_start(vaddr, size)?;
+        Self::check_paddr(paddr, size)?;
+        Self::check_memory_type(paddr, size, flags)?;
+        if entry.is_contiguous() {
+            let bbm = self.needs_bbm();
+            Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+        }
+        let old = *entry;
+        let mut new = old;
+        new.set_leaf_paddr(paddr, size.is_huge());
+        new.set_flags(flags, size.is_huge());
+        Self::keep_ad_bits(&old, &mut new, flags, size.is_huge());
+        // The group may have been broken up already.
+        let mapped = Self::mapped_after(entry, &new, size, used, limit)
+            .inspect_err(|_| self.journal.end())?;
+        let tlb = Self::update_leaf(entry, old, new, vaddr, self.needs_bbm(), size.is_huge());
+        Self::note(
+            &mut self.journal,
+            vaddr,
+            Self::leaf_level(size),
+            old,
+            *entry,
+        );
+        self.mapped_bytes = mapped;
+        // Widening the tables after the leaf entry only delays the access.
+        let tlb = if self.widen_tables(vaddr, size, flags) {
+            tlb.merge(TlbFlush::new(vaddr))
+        } else {
+            tlb
+        };
+        Ok((size, self.stamp(tlb)))
+    }
+
+    /// Updates the flags of the mapping starts with `vaddr`.
+    ///
+    /// Returns the page size of the mapping.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present.
+    ///
+    /// Empty `flags` make the page inaccessible, like `mprotect(PROT_NONE)`:
+    /// every access faults, but the page keeps its frame, which
+    /// [`PageTable64::query`] reports with empty flags and
+    /// [`PageTable64::unmap`] returns, and a later `protect` with other flags
+    /// maps it again. The entry is non-present on the formats where a present
+    /// page always allows some access (see [`GenericPTE::set_inaccessible`]),
+    /// which then return
+    /// [`Err(PagingError::UnsupportedPageSize)`](PagingError::UnsupportedPageSize)
+    /// for a huge page.
+    ///
+    /// Changing the memory type follows break-before-make like
+    /// [`PageTable64::remap`], and a page in a group with the contiguous hint
+    /// is first split from the group.
+    ///
+    /// The new flags are checked like for [`PageTable64::map`]. Returns
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if `vaddr` is inside a huge page but not its start.
+    pub fn protect(
+        &mut self,
+        vaddr: M::VirtAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<(PageSize, TlbFlush<M>)> {
+        let result = self.protect_inner(vaddr, flags);
+        Self::reported("protect", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::protect`], without reporting its errors.
+    fn protect_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<(PageSize, TlbFlush<M>)> {
+        Self::check_space(vaddr, flags)?;
+        self.check_wx(vaddr, flags)?;
+        let (used, limit) = (self.mapped_bytes, self.max_mapped_bytes);
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !Self::holds_frame(entry) {
+            return Err(PagingError::NotMapped);
+        }
+        Self::check_page_start(vaddr, size)?;
+        if flags.is_empty() && size.is_huge() && !PTE::PRESENT_INACCESSIBLE {
+            return Err(PagingError::UnsupportedPageSize);
+        }
+        let paddr = Self::leaf_paddr(entry, vaddr.into(), size.is_huge());
+        Self::check_memory_type(paddr, size, flags)?;
+        if entry.is_contiguous() {
+            let bbm = self.needs_bbm();
+            Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+        }
+        let old = *entry;
+        let mut new = old;
+        match old.inaccessible() {
+            None if flags.is_empty() => new.set_inaccessible(),
+            None => new.set_flags(flags, size.is_huge()),
+            Some(_) if flags.is_empty() => {}
+            // The payload holds nothing but the frame.
+            Some(paddr) if !old.is_present() => new = Self::new_leaf(paddr, flags, size.is_huge()),
+            Some(_) => new.set_flags(flags, size.is_huge()),
+        }
+        if new.is_present() {
+            Self::keep_ad_bits(&old, &mut new, flags, size.is_huge());
+        }
+        // The group may have been broken up already.
+        let mapped = Self::mapped_after(entry, &new, size, used, limit)
+            .inspect_err(|_| self.journal.end())?;
+        let tlb = Self::update_leaf(entry, old, new, vaddr, self.needs_bbm(), size.is_huge());
+        Self::note(
+            &mut self.journal,
+            vaddr,
+            Self::leaf_level(size),
+            old,
+            *entry,
+        );
+        self.mapped_bytes = mapped;
+        // Widening the tables after the leaf entry only delays the access.
+        let tlb = if self.widen_tables(vaddr, size, flags) {
+            tlb.merge(TlbFlush::new(vaddr))
+        } else {
+            tlb
+        };
+        Ok((size, self.stamp(tlb)))
+    }
+
+    /// Unmaps the mapping starts with `vaddr`.
+    ///
+    /// If the mapping is copy-on-write, [`PagingHandler::frame_unshared`] is
+    /// called for its page.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present.
+    ///
+    /// A 4K page in a group with the contiguous hint is unmapped alone: the
+    /// hint is first cleared from the whole group. If
+    /// [`PagingMetaData::BREAK_BEFORE_MAKE`] is `true` and the table is not
+    /// known to be inactive, the group is invalidated and the TLB flushed for
+    /// all its pages before.
+    ///
+    /// Returns
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if `vaddr` is inside a huge page but not its start, instead of
+    /// unmapping the whole huge page, and
+    /// [`Err(PagingError::Locked)`](PagingError::Locked) if the page is
+    /// locked by [`PageTable64::lock_region`].
+    pub fn unmap(&mut self, vaddr: M::VirtAddr) -> PagingResult<(PhysAddr, PageSize, TlbFlush<M>)> {
+        let result = self.unmap_inner(vaddr, false);
+        Self::reported("unmap", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::unmap`], without reporting its errors. Locked pages
+    /// are unmapped too if `force` is `true`.
+    fn unmap_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        force: bool,
+    ) -> PagingResult<(PhysAddr, PageSize, TlbFlush<M>)> {
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        let level = Self::leaf_level(size);
+        Self::check_page_start(vaddr, size)?;
+        if entry.is_locked() && !force {
+            return Err(PagingError::Locked);
+        }
+        if !Self::holds_frame(entry) {
+            let old = *entry;
+            entry.clear();
+            Self::note(&mut self.journal, vaddr, level, old, *entry);
+            self.journal.end();
+            return Err(PagingError::NotMapped);
+        }
+        if entry.is_contiguous() {
+            let bbm = self.needs_bbm();
+            Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+        }
+        let old = *entry;
+        let paddr = entry.inaccessible();
+        let paddr = paddr.unwrap_or_else(|| entry.leaf_paddr(size.is_huge()));
+        let cow = entry.leaf_flags(size.is_huge()).contains(MappingFlags::COW);
+        let global = entry.is_global();
+        entry.clear();
+        Self::note(&mut self.journal, vaddr, level, old, *entry);
+        if cow {
+            Self::frame_unshared(paddr, size);
+        }
+        // Mappings under tables from `copy_from` are not accounted.
+        self.mapped_bytes = self.mapped_bytes.saturating_sub(size as usize);
+        let tlb = TlbFlush::new(vaddr).with_global(global);
+        Ok((paddr, size, self.stamp(tlb)))
+    }
+
+    /// Creates a [`FixedSlot`] for the 4K page at `vaddr`, creating the tables
+    /// on the way to its entry.
+    ///
+    /// The table entries on the way are widened to allow `flags`, the widest
+    /// flags the slot will be set with, like in [`PageTable64::map`]. As for
+    /// [`PageTable64::protect`], widening existing table entries needs no
+    /// flush, since stale TLB entries only delay the access. A mapping of the
+    /// page, if any, is kept.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `vaddr` is not aligned to 4K, and
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if it is inside a huge page.
+    pub fn fixed_slot(
+        &mut self,
+        vaddr: M::VirtAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<FixedSlot<M>> {
+        let result = self.fixed_slot_inner(vaddr, flags);
+        Self::reported("fixed_slot", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::fixed_slot`], without reporting its errors.
+    fn fixed_slot_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<FixedSlot<M>> {
+        trace!("fixed_slot({:#x}): {:#x}", self.root_paddr(), vaddr.into());
+        if !PageSize::Size4K.is_aligned(vaddr.into()) {
+            return Err(PagingError::NotAligned);
+        }
+        Self::check_range(vaddr.into(), PAGE_SIZE_4K)?;
+        let result = self.get_entry_mut_or_create(vaddr, PageSize::Size4K, flags);
+        self.end_update();
+        result?;
+        let mut table = self.root_paddr;
+        for level in 0..M::LEVELS - 1 {
+            let entry = Self::load_entry(table, Self::index_of(vaddr.into(), level));
+            table = Self::next_table(&entry, vaddr.into(), level)?;
+        }
+        Ok(FixedSlot {
+            root: self.root_paddr,
+            vaddr,
+            table,
+            allowed: flags,
+            unlinks: self.unlinks,
+        })
+    }
+
+    /// Queries the mapping that contains `vaddr`.
+    ///
+    /// Returns the physical address that `vaddr` itself translates to (the
+    /// base of the frame plus the offset of `vaddr` in the page, also for
+    /// huge pages), the mapping flags, and the page size. The base of the
+    /// frame is the address aligned down to the page size. A group of 4K
+    /// entries with the contiguous hint is reported as [`PageSize::Size64K`].
+    /// A page made inaccessible by [`PageTable64::protect`] is reported with
+    /// empty flags, which no other mapping has.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present, or
+    /// [`Err(PagingError::Absent)`](PagingError::Absent) if its entry carries
+    /// a payload (see [`PageTable64::set_absent_token`]).
+    pub fn query(&self, vaddr: M::VirtAddr) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
+        let (entry, size) = self.get_entry(vaddr)?;
+        Self::translate(&entry, size, vaddr.into())
+    }
+
+    /// Tells whether an access of type `access` to `vaddr` in the context
+    /// `ctx` would fault, as the MMU decides: with the permissions left by the
+    /// table entries above the leaf (see [`GenericPTE::limited_by`]) and the
+    /// global controls of the CPU (see [`GenericPTE::permits`]).
+    ///
+    /// The faults caused by a clear accessed or dirty bit (see
+    /// [`PageTable64::handle_access_fault`]) are not reported.
+    pub fn check_access(
+        &self,
+        vaddr: M::VirtAddr,
+        access: AccessType,
+        ctx: AccessContext,
+    ) -> AccessVerdict {
+        let vaddr: usize = vaddr.into();
+        let mut tables = [Self::pte_from_bits(0); MAX_LEVELS];
+        let mut table = self.root_paddr();
+        for level in 0..M::LEVELS {
+            let entry = Self::load_entry(table, Self::index_of(vaddr, level));
+            let size = Self::leaf_size(level);
+            if level == M::LEVELS - 1 || entry.is_huge() && Self::page_size_supported(size) {
+                if entry.inaccessible().is_some() {
+                    return AccessVerdict::Denied;
+                } else if !entry.is_present() {
+                    return AccessVerdict::NotMapped;
+                }
+                let limited = |leaf: PTE| tables[..level].iter().fold(leaf, |l, t| l.limited_by(t));
+                if limited(entry).permits(access, ctx) {
+                    return AccessVerdict::Allowed;
+                }
+                // Tell whether the copy would be allowed the write.
+                let flags = entry.leaf_flags(size.is_huge());
+                if access == AccessType::Write && flags.contains(MappingFlags::COW) {
+                    let mut copied = entry;
+                    copied.set_flags(flags.resolve_cow(), size.is_huge());
+                    if limited(copied).permits(access, ctx) {
+                        return AccessVerdict::CopyOnWrite;
+                    }
+                }
+                return AccessVerdict::Denied;
+            }
+            match Self::next_table(&entry, vaddr, level) {
+                Ok(next) => table = next,
+                Err(_) => return AccessVerdict::NotMapped,
+            }
+            tables[level] = entry;
+        }
+        unreachable!()
+    }
+
+    /// Returns the number of bytes from `vaddr`, at most `max_len`, that are
+    /// mapped by present pages with all the `required` flags, e.g. to know
+    /// how far a string can be copied from user space.
+    ///
+    /// The bytes end at the first page that is not mapped, not present, or
+    /// lacks one of the flags, or at the end of the half of the address space
+    /// of `vaddr`. The pages are found in a single walk of the tables, which
+    /// steps over huge pages at once. As with [`PageTable64::query`], the
+    /// flags are the ones of the leaf entries.
+    pub fn mapped_prefix_len(
+        &self,
+        vaddr: M::VirtAddr,
+        max_len: usize,
+        required: MappingFlags,
+    ) -> usize {
+        let vaddr: usize = vaddr.into();
+        if max_len == 0 || !M::vaddr_is_valid(vaddr) {
+            return 0;
+        }
+        let (from, half_end) = Self::half_of(vaddr);
+        let end = from.saturating_add(max_len).min(half_end);
+        let stop = Self::first_hole(self.root_paddr(), 0, 0, (from, end), required);
+        stop.unwrap_or(end) - from
+    }
+
+    /// Returns the number of bytes from `vaddr` that can be mapped without
+    /// replacing anything, e.g. to size an `mmap` placed there.
+    ///
+    /// The bytes end at the first entry that is not unused (a present page,
+    /// or a non-present one carrying a payload such as a guard page of
+    /// [`PageTable64::map_alloc_with`]), or at the end of the half of
+    /// the address space of `vaddr`, and are at most the mapped bytes left
+    /// under the limit of [`PageTable64::set_limits`]. The tables needed to
+    /// map them are not counted. The entries are found in a single walk of
+    /// the tables, which steps over the unused entries of every level at
+    /// once.
+    ///
+    /// It is `0` if `vaddr` is not aligned to 4K or not in the address space.
+    pub fn max_mappable_at(&self, vaddr: M::VirtAddr) -> usize {
+        let vaddr: usize = vaddr.into();
+        if !PageSize::Size4K.is_aligned(vaddr) || !M::vaddr_is_valid(vaddr) {
+            return 0;
+        }
+        let quota = self.max_mapped_bytes.saturating_sub(self.mapped_bytes);
+        let (from, half_end) = Self::half_of(vaddr);
+        let end = from
+            .saturating_add(quota & !(PAGE_SIZE_4K - 1))
+            .min(half_end);
+        let stop = Self::first_used(self.root_paddr(), 0, 0, (from, end));
+        stop.unwrap_or(end) - from
+    }
+
+    /// Returns the largest run of unused entries in the region, as its start
+    /// and size, e.g. for a best-fit placement of a new mapping.
+    ///
+    /// The entries are unused as for [`PageTable64::max_mappable_at`], but
+    /// the limits of [`PageTable64::set_limits`] are not taken into account.
+    /// The first run is returned if several have the largest size, and the
+    /// size is `0` if no entry of the region is unused. As with
+    /// [`PageTable64::summarize`], only the tables overlapping the region are
+    /// visited.
+    ///
+    /// `start` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Invalid
+    /// regions are handled like in [`PageTable64::map_region`].
+    pub fn max_free_gap(
+        &self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<(M::VirtAddr, usize)> {
+        if size == 0 {
+            return Ok((start, 0));
+        }
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
+        }
+        let from = start.into() & Self::va_mask();
+        let end = from + size;
+        let mut cursor = from;
+        let mut gap = (from, 0);
+        Self::largest_gap(self.root_paddr(), 0, 0, (from, end), &mut cursor, &mut gap);
+        if end - cursor > gap.1 {
+            gap = (cursor, end - cursor);
+        }
+        Ok((Self::sign_extended(gap.0), gap.1))
+    }
+
+    /// Stores `token` in the non-present 4K entry of `vaddr`, as a
+    /// [`NonPresentPayload::FileToken`], e.g. for demand paging of file mappings.
+    ///
+    /// The token can be read back by [`PageTable64::absent_token`] or
+    /// [`PageTable64::query`], and is replaced by a later
+    /// [`PageTable64::map`]. No TLB flush is needed.
+    ///
+    /// Returns [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped)
+    /// if the page is mapped, or
+    /// [`Err(PagingError::Guarded)`](PagingError::Guarded) if it is a guard
+    /// page of [`PageTable64::map_alloc_with`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `token` does not fit in
+    /// [`AbsentEntry::PAYLOAD_BITS`](crate::AbsentEntry::PAYLOAD_BITS).
+    pub fn set_absent_token(&mut self, vaddr: M::VirtAddr, token: u64) -> PagingResult {
+        let (entry, _) =
+            self.get_entry_mut_or_create(vaddr, PageSize::Size4K, MappingFlags::empty())?;
+        let err = match entry.absent() {
+            _ if Self::holds_frame(entry) => Some(PagingError::AlreadyMapped),
+            Some(AbsentEntry::Guard(owner)) => Some(Self::guarded(owner)),
+            _ => None,
+        };
+        if let Some(err) = err {
+            self.journal.end();
+            return Err(err);
+        }
+        let old = *entry;
+        entry.set_payload(NonPresentPayload::FileToken(token));
+        let new = *entry;
+        Self::note(&mut self.journal, vaddr, M::LEVELS - 1, old, new);
+        self.end_update();
+        Ok(())
+    }
+
+    /// Returns the token stored by [`PageTable64::set_absent_token`] for
+    /// `vaddr`, if any.
+    pub fn absent_token(&self, vaddr: M::VirtAddr) -> Option<u64> {
+        match self.get_entry(vaddr).ok()?.0.payload()? {
+            NonPresentPayload::FileToken(token) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Returns the entry of the Linux `pagemap` file describing the 4K page
+    /// containing `vaddr`, as given by
+    /// [`PagemapEntry::from_pte`](crate::interop::PagemapEntry::from_pte).
+    /// It is zero if nothing is mapped there.
+    #[cfg(feature = "interop")]
+    pub fn pagemap_entry(&self, vaddr: M::VirtAddr) -> u64 {
+        let vaddr: usize = vaddr.into();
+        match self.get_entry(vaddr.into()) {
+            Ok((entry, size)) => {
+                let offset = size.align_offset(vaddr) & !(PAGE_SIZE_4K - 1);
+                PagemapEntry::from_pte(&entry, offset).0
+            }
+            Err(_) => 0,
+        }
+    }
+
+    /// Fills `out` with the `pagemap` entries of the 4K pages from `start`,
+    /// like [`PageTable64::pagemap_entry`], walking the tables once for each
+    /// huge page.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `start` is not aligned to 4K, and
+    /// [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr) if the
+    /// pages are not all in the address space.
+    #[cfg(feature = "interop")]
+    pub fn fill_pagemap(&self, start: M::VirtAddr, out: &mut [u64]) -> PagingResult {
+        let start: usize = start.into();
+        if !PageSize::Size4K.is_aligned(start) {
+            return Err(PagingError::NotAligned);
+        }
+        if out.is_empty() {
+            return Ok(());
+        }
+        Self::check_range(start, out.len() * PAGE_SIZE_4K)?;
+        let mut i = 0;
+        while i < out.len() {
+            let vaddr = start + i * PAGE_SIZE_4K;
+            let Ok((entry, size)) = self.get_entry(vaddr.into()) else {
+                out[i] = 0;
+                i += 1;
+                continue;
+            };
+            let mut offset = size.align_offset(vaddr);
+            while offset < size as usize && i < out.len() {
+                out[i] = PagemapEntry::from_pte(&entry, offset).0;
+                offset += PAGE_SIZE_4K;
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps a contiguous virtual memory region to a contiguous physical memory
+    /// region with the given mapping `flags`.
+    ///
+    /// The virtual and physical memory regions start with `vaddr` and `paddr`
+    /// respectively. The region size is `size`. The addresses and `size` must
+    /// be aligned to 4K, otherwise it will return [`Err(PagingError::NotAligned)`].
+    ///
+    /// When `allow_huge` is true, it will try to map the region with huge pages
+    /// if possible, then with groups of 4K pages with the contiguous hint
+    /// ([`PageSize::Size64K`]) if the entry format supports it. Otherwise, it
+    /// will map the region with 4K pages.
+    ///
+    /// When `flush_tlb_by_page` is true, it will flush the TLB immediately after
+    /// mapping each page. Otherwise, the TLB flush should by handled by the caller.
+    /// Like [`PageTable64::map`], no flush is needed at all if
+    /// [`PagingMetaData::TLB_CACHES_INVALID`] is `false`.
+    ///
+    /// Large regions can be mapped in steps with
+    /// [`PageTable64::map_region_step`].
+    ///
+    /// An empty region maps nothing and needs no flush. A region that is not
+    /// in one half of the address space returns
+    /// [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr).
+    ///
+    /// [`Err(PagingError::NotAligned)`]: PagingError::NotAligned
+    pub fn map_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+        flush_tlb_by_page: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result =
+            self.map_region_inner(vaddr, get_paddr, size, flags, allow_huge, flush_tlb_by_page);
+        Self::reported("map_region", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map_region`], without reporting its errors.
+    fn map_region_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+        flush_tlb_by_page: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        if size == 0 {
+            return Ok(self.empty_flush());
+        }
+        let mut cursor = RegionCursor::new(vaddr, size);
+        trace!(
+            "map_region({:#x}): [{:#x}, {:#x}) {:?}",
+            self.root_paddr(),
+            cursor.next,
+            cursor.end,
+            flags,
+        );
+        let pages = (&get_paddr, &mut |_| flags, false, allow_huge);
+        let pages = self.map_pages(&mut cursor, pages, flush_tlb_by_page, usize::MAX)?;
+        // The generation was incremented by each page if needed.
+        Ok(TlbFlushAll::new_mappings()
+            .with_generation(self.generation)
+            .with_pages(pages))
+    }
+
+    /// Maps a region like [`PageTable64::map_region`], with the flags of
+    /// each 4K page given by `flags_for`, e.g. for a segment whose pages have
+    /// different permissions.
+    ///
+    /// With `allow_huge`, a huge page or a group of 4K pages with the
+    /// contiguous hint is only used where `flags_for` gives the same flags
+    /// for all its 4K pages. `flags_for` may be called more than once for a
+    /// page. The TLB flush is left to the caller.
+    pub fn map_region_with(
+        &mut self,
+        vaddr: M::VirtAddr,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        size: usize,
+        flags_for: impl FnMut(M::VirtAddr) -> MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.map_region_with_inner(vaddr, get_paddr, size, flags_for, allow_huge);
+        Self::reported("map_region_with", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map_region_with`], without reporting its errors.
+    fn map_region_with_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        size: usize,
+        mut flags_for: impl FnMut(M::VirtAddr) -> MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        if size == 0 {
+            return Ok(self.empty_flush());
+        }
+        let mut cursor = RegionCursor::new(vaddr, size);
+        trace!(
+            "map_region_with({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            cursor.next,
+            cursor.end,
+        );
+        let pages = (&get_paddr, &mut flags_for, true, allow_huge);
+        let pages = self.map_pages(&mut cursor, pages, false, usize::MAX)?;
+        Ok(TlbFlushAll::new_mappings()
+            .with_generation(self.generation)
+            .with_pages(pages))
+    }
+
+    /// Maps at most `budget` pages of the region of `cursor` like
+    /// [`PageTable64::map_region`], and moves the cursor past them.
+    ///
+    /// The caller can release locks or enable interrupts between two steps,
+    /// and flush the TLB for each step or once at the end (see
+    /// [`TlbFlushAll::merge`]). On error, the cursor is left at the page
+    /// that could not be mapped.
+    pub fn map_region_step(
+        &mut self,
+        cursor: &mut RegionCursor,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        flags: MappingFlags,
+        allow_huge: bool,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let result = self.map_region_step_inner(cursor, get_paddr, flags, allow_huge, budget);
+        Self::reported("map_region_step", cursor.next, result)
+    }
+
+    /// [`PageTable64::map_region_step`], without reporting its errors.
+    fn map_region_step_inner(
+        &mut self,
+        cursor: &mut RegionCursor,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        flags: MappingFlags,
+        allow_huge: bool,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let pages = (&get_paddr, &mut |_| flags, false, allow_huge);
+        let pages = self.map_pages(cursor, pages, false, budget)?;
+        let tlb = TlbFlushAll::new_mappings()
+            .with_generation(self.generation)
+            .with_pages(pages);
+        Ok((cursor.status(), tlb))
+    }
+
+    /// Maps the byte range `[vaddr, vaddr + len)` to the physical memory
+    /// starting at `paddr`, e.g. for a segment of an ELF file.
+    ///
+    /// The range is rounded to whole 4K pages: the start is aligned down and
+    /// the end is aligned up, and `paddr` is aligned down by the same amount
+    /// as `vaddr`. Every page that contains at least one byte of the range is
+    /// mapped, and an empty range maps nothing. The pages are mapped like
+    /// [`PageTable64::map_region`] with `allow_huge` set, and the TLB flush is
+    /// left to the caller.
+    ///
+    /// `vaddr` and `paddr` must have the same offset in their 4K page, since
+    /// the mapping cannot move bytes within a page. Otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). If the
+    /// rounded range overflows the address space, it returns
+    /// [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr) like
+    /// [`PageTable64::map_region`].
+    pub fn map_region_bytes(
+        &mut self,
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        len: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.map_region_bytes_inner(vaddr, paddr, len, flags);
+        Self::reported("map_region_bytes", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map_region_bytes`], without reporting its errors.
+    fn map_region_bytes_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        len: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let start: usize = vaddr.into();
+        let offset = PageSize::Size4K.align_offset(start);
+        if offset != paddr.align_offset_4k() {
+            return Err(PagingError::NotAligned);
+        }
+        if len == 0 {
+            return Ok(self.empty_flush());
+        }
+        let end = start
+            .checked_add(len)
+            .and_then(|end| end.checked_next_multiple_of(PageSize::Size4K as usize))
+            .ok_or(PagingError::InvalidVaddr(start))?;
+        let start = start - offset;
+        let base = paddr.as_usize() - offset;
+        self.map_region_inner(
+            start.into(),
+            |va| {
+                let va: usize = va.into();
+                PhysAddr::from(base + (va - start))
+            },
+            end - start,
+            flags,
+            true,
+            false,
+        )
+    }
+
+    /// Maps the loadable segments of an ELF file, with the flags given by
+    /// [`ElfSegment::mapping_flags`] and `extra_flags` (e.g.
+    /// [`MappingFlags::USER`]).
+    ///
+    /// The pages holding only file data are mapped to the file with
+    /// [`PageTable64::map_region_bytes`]. The pages after the file data, up
+    /// to `memsz`, are mapped to new zeroed frames from
+    /// [`PagingHandler::alloc_frame`]. If a page holds both the end of the
+    /// file data and zeros, it is mapped to a new frame where the file data
+    /// is copied, since sharing the file page would expose the bytes that
+    /// follow the segment in the file (and let writes reach the file when the
+    /// segment is writable). `filesz` is capped to `memsz`.
+    ///
+    /// The new frames are owned by the caller, who frees them after
+    /// unmapping. Segments must not overlap. On error, the segments and pages
+    /// mapped so far are left mapped, and the TLB flush is left to the caller
+    /// in any case.
+    pub fn map_elf_segments(
+        &mut self,
+        segments: impl IntoIterator<Item = ElfSegment>,
+        extra_flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        for segment in segments {
+            let result = self.map_elf_segment(&segment, extra_flags);
+            Self::reported("map_elf_segments", segment.vaddr, result)?;
+        }
+        Ok(TlbFlushAll::new_mappings().with_generation(self.generation))
+    }
+
+    /// Maps the region to new zeroed frames, e.g. for anonymous memory, and
+    /// returns the number of bytes mapped by huge pages with the flush.
+    ///
+    /// If `allow_huge` is `true`, each 2M-aligned block of the region is
+    /// mapped by a 2M page where supported, with contiguous frames from
+    /// [`PagingHandler::alloc_frames`]. When they cannot be allocated, the
+    /// block is mapped by 4K pages with frames from
+    /// [`PagingHandler::alloc_frame`], so that the sizes of the pages follow
+    /// what the allocator can give. The pages before the first aligned block
+    /// and after the last one are 4K.
+    ///
+    /// The frames are owned by the caller, who frees them after unmapping,
+    /// with [`PagingHandler::dealloc_frames`] for the huge pages.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `vaddr` or `size` is not aligned to 4K,
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped) if a
+    /// page of the region is mapped, and
+    /// [`Err(PagingError::QuotaExceeded)`](PagingError::QuotaExceeded) if
+    /// the region is larger than the mapped memory left by the limit (see
+    /// [`PageTable64::set_limits`]), before allocating anything. On other
+    /// errors, e.g. when a frame or table cannot be allocated, the pages
+    /// mapped so far are unmapped and flushed, and their frames freed. The
+    /// tables created stay, as they are only freed with the page table.
+    pub fn map_alloc(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        let options = AllocOptions::new().allow_huge(allow_huge);
+        let result = self.batched(|pt| pt.map_alloc_inner(vaddr, size, flags, options));
+        Self::reported("map_alloc", vaddr.into(), result)
+    }
+
+    /// Maps the region to new zeroed frames like [`PageTable64::map_alloc`],
+    /// with the `options`, e.g. guard pages to debug the overflows of an
+    /// allocator.
+    ///
+    /// The guard pages given by [`AllocOptions::guarded`] must be unused:
+    /// neither mapped nor carrying an [`AbsentEntry`]. They are marked with
+    /// an [`AbsentEntry::Guard`] naming the allocation once it is mapped, so
+    /// that nothing else needs to remember them. Mapping a page over them
+    /// (e.g. with [`PageTable64::map`] or another allocation) then fails with
+    /// [`Err(PagingError::Guarded)`](PagingError::Guarded) and the start of
+    /// the allocation, and they count as used for
+    /// [`PageTable64::max_mappable_at`] and [`PageTable64::max_free_gap`].
+    ///
+    /// [`PageTable64::unmap_region`] of a region starting at the allocation
+    /// clears the guard pages before it, and those right after the region,
+    /// i.e. all of them when the whole allocation is unmapped.
+    ///
+    /// It fails like [`PageTable64::map_alloc`], with
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped) if a
+    /// guard page is used, or
+    /// [`Err(PagingError::Guarded)`](PagingError::Guarded) if it is a guard
+    /// page of another allocation. The region and its guard pages must be in
+    /// the address space, otherwise it returns
+    /// [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr).
+    pub fn map_alloc_with(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        options: AllocOptions,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        let result = self.batched(|pt| pt.map_alloc_inner(vaddr, size, flags, options));
+        Self::reported("map_alloc_with", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map_alloc_with`], without reporting its errors.
+    fn map_alloc_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        options: AllocOptions,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        let start: usize = vaddr.into();
+        trace!(
+            "map_alloc({:#x}): [{:#x}, {:#x}) {:?}",
+            self.root_paddr(),
+            start,
+            start.wrapping_add(size),
+            flags,
+        );
+        if !PageSize::Size4K.is_aligned(start | size) {
+            return Err(PagingError::NotAligned);
+        }
+        if size == 0 {
+            return Ok((0, self.empty_flush()));
+        }
+        Self::check_range(start, size)?;
+        // The guard pages are in the same half of the address space.
+        let guarded = options
+            .guard_before
+            .checked_mul(PAGE_SIZE_4K)
+            .and_then(|before| {
+                let total = options.guard_after.checked_mul(PAGE_SIZE_4K)?;
+                let total = total.checked_add(before)?.checked_add(size)?;
+                let first = start.checked_sub(before)?;
+                Self::check_range(first, total).ok()?;
+                Some((first, start.wrapping_add(size)))
+            });
+        let guards = guarded.ok_or(PagingError::InvalidVaddr(start))?;
+        if AnyPageTable::next_mapping(self, start).is_some_and(|m| m.vaddr < start + size) {
+            return Err(PagingError::AlreadyMapped);
+        }
+        self.check_guards(guards.0, options.guard_before)?;
+        self.check_guards(guards.1, options.guard_after)?;
+        Self::check_quota(
+            QuotaKind::MappedBytes,
+            self.mapped_bytes,
+            self.max_mapped_bytes,
+            size,
+        )?;
+        let huge = options.allow_huge && Self::page_size_supported(PageSize::Size2M);
+        let mut huge_bytes = 0;
+        let mut off = 0;
+        while off < size {
+            let vaddr = start + off;
+            let page = if huge
+                && PageSize::Size2M.is_aligned(vaddr)
+                && size - off >= PageSize::Size2M as usize
+            {
+                PageSize::Size2M
+            } else {
+                PageSize::Size4K
+            };
+            match self.map_new_frames(vaddr, page, flags) {
+                Ok(PageSize::Size4K) => off += PAGE_SIZE_4K,
+                Ok(page) => {
+                    huge_bytes += page as usize;
+                    off += page as usize;
+                }
+                Err(e) => {
+                    self.rolling_back(|pt| pt.unmap_allocated(start, off));
+                    return Err(e);
+                }
+            }
+        }
+        let owner = Self::guard_owner(start);
+        let result = self
+            .set_guards(guards.0, options.guard_before, owner)
+            .and_then(|_| self.set_guards(guards.1, options.guard_after, owner));
+        if let Err(e) = result {
+            self.rolling_back(|pt| {
+                pt.unmap_allocated(start, size);
+                pt.clear_guards(start, size);
+            });
+            return Err(e);
+        }
+        let tlb = TlbFlushAll::new_mappings().with_generation(self.generation);
+        Ok((huge_bytes, tlb))
+    }
+
+    /// Maps the pages of `shared`, which are mapped at the same address in
+    /// many page tables, e.g. the vDSO of every process.
+    ///
+    /// Either all the pages are mapped, or none: it returns
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped) if
+    /// one of them is mapped or carries an [`AbsentEntry`](crate::AbsentEntry)
+    /// already, and unmaps the pages mapped so far on other errors.
+    /// `shared.vaddr` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned).
+    ///
+    /// # Panics
+    ///
+    /// Panics if a page is writable or copy-on-write: the pages stay shared,
+    /// which [`PageTable64::clone_cow`] only does for read-only mappings.
+    pub fn apply_shared(&mut self, shared: &SharedFixedMapping) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.batched(|pt| pt.apply_shared_inner(shared));
+        Self::reported("apply_shared", shared.vaddr, result)
+    }
+
+    /// [`PageTable64::apply_shared`], without reporting its errors.
+    fn apply_shared_inner(&mut self, shared: &SharedFixedMapping) -> PagingResult<TlbFlushAll<M>> {
+        trace!(
+            "apply_shared({:#x}): {:#x} {:?}",
+            self.root_paddr(),
+            shared.vaddr,
+            shared.pages,
+        );
+        if !PageSize::Size4K.is_aligned(shared.vaddr) {
+            return Err(PagingError::NotAligned);
+        }
+        Self::check_range(shared.vaddr, shared.pages.len() * PAGE_SIZE_4K)?;
+        for (i, &(_, flags)) in shared.pages.iter().enumerate() {
+            assert!(
+                !flags.intersects(MappingFlags::WRITE | MappingFlags::COW),
+                "shared page {i} is writable",
+            );
+            match self.query((shared.vaddr + i * PAGE_SIZE_4K).into()) {
+                Err(PagingError::NotMapped) => {}
+                Ok(_) | Err(PagingError::Absent(_)) => return Err(PagingError::AlreadyMapped),
+                Err(e) => return Err(e),
+            }
+        }
+        for (i, &(paddr, flags)) in shared.pages.iter().enumerate() {
+            let vaddr = (shared.vaddr + i * PAGE_SIZE_4K).into();
+            match self.map_inner(vaddr, paddr, PageSize::Size4K, flags) {
+                // A new mapping, flushed with the others.
+                Ok(tlb) => tlb.ignore(),
+                Err(e) => {
+                    let mapped = i * PAGE_SIZE_4K;
+                    self.rolling_back(|pt| {
+                        pt.unmap_region_inner(shared.vaddr.into(), mapped, false, false)
+                    })?
+                    .ignore();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(TlbFlushAll::new_mappings().with_generation(self.generation))
+    }
+
+    /// Unmaps the pages of `shared` mapped by [`PageTable64::apply_shared`].
+    ///
+    /// The frames are not freed. Returns
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) without
+    /// unmapping anything if a page is not mapped to its frame.
+    pub fn remove_shared(&mut self, shared: &SharedFixedMapping) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.remove_shared_inner(shared);
+        Self::reported("remove_shared", shared.vaddr, result)
+    }
+
+    /// [`PageTable64::remove_shared`], without reporting its errors.
+    fn remove_shared_inner(&mut self, shared: &SharedFixedMapping) -> PagingResult<TlbFlushAll<M>> {
+        trace!(
+            "remove_shared({:#x}): {:#x}",
+            self.root_paddr(),
+            shared.vaddr,
+        );
+        Self::check_range(shared.vaddr, shared.pages.len() * PAGE_SIZE_4K)?;
+        for (i, &(paddr, _)) in shared.pages.iter().enumerate() {
+            match self.query((shared.vaddr + i * PAGE_SIZE_4K).into()) {
+                Ok((mapped, _, PageSize::Size4K)) if mapped == paddr => {}
+                _ => return Err(PagingError::NotMapped),
+            }
+        }
+        self.unmap_region_inner(
+            shared.vaddr.into(),
+            shared.pages.len() * PAGE_SIZE_4K,
+            false,
+            false,
+        )
+    }
+
+    /// Maps the `size` bytes at `vaddr` to the frames mapped at `existing`,
+    /// with the same flags, e.g. to map a ring buffer twice back to back, or
+    /// the kernel image at a randomized address (see
+    /// [`PageTable64::unmap_alias`]).
+    ///
+    /// The pages are chosen like in [`PageTable64::map_region`] with
+    /// `allow_huge` set, so a huge page is aliased with smaller pages where
+    /// `vaddr` is not aligned like `existing`. The aliases are then
+    /// independent mappings of the same frames:
+    ///
+    /// - Each is changed or unmapped on its own. [`PageTable64::unmap`]
+    ///   returns the frame for each of them, so it can only be freed with the
+    ///   last one, and [`PageTable64::unmap_paddr_range`] removes them all.
+    /// - The frames of copy-on-write pages get one more reference with
+    ///   [`PagingHandler::frame_shared`], which a write fault in either alias
+    ///   drops, like the pages shared by [`PageTable64::clone_cow`]. Other
+    ///   frames are not counted.
+    /// - [`PageTable64::clone_cow`] makes each alias of a writable page
+    ///   copy-on-write on its own, so they stop aliasing after a write fault.
+    ///   Aliases that must stay shared in a copy have to be mapped there
+    ///   again instead.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// an address or `size` is not aligned to 4K,
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if a page of
+    /// `existing` is not mapped, and
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped) if a
+    /// page of the alias is mapped already. Nothing is mapped then, and the
+    /// pages mapped so far are unmapped on other errors. The TLB flush is
+    /// left to the caller.
+    pub fn map_alias(
+        &mut self,
+        vaddr: M::VirtAddr,
+        existing: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.batched(|pt| pt.map_alias_inner(vaddr, existing, size));
+        Self::reported("map_alias", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map_alias`], without reporting its errors.
+    fn map_alias_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        existing: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let (start, source): (usize, usize) = (vaddr.into(), existing.into());
+        trace!(
+            "map_alias({:#x}): [{:#x}, {:#x}) -> {:#x}",
+            self.root_paddr(),
+            start,
+            start.wrapping_add(size),
+            source,
+        );
+        if !PageSize::Size4K.is_aligned(start | source | size) {
+            return Err(PagingError::NotAligned);
+        }
+        if size == 0 {
+            return Ok(self.empty_flush());
+        }
+        Self::check_range(start, size)?;
+        Self::check_range(source, size)?;
+        if AnyPageTable::next_mapping(self, start).is_some_and(|m| m.vaddr < start + size) {
+            return Err(PagingError::AlreadyMapped);
+        }
+        let mut off = 0;
+        while off < size {
+            let (_, _, page) = self
+                .query((source + off).into())
+                .map_err(|_| PagingError::NotMapped)?;
+            off += page as usize - page.align_offset(source + off);
+        }
+
+        let mut widened = false;
+        let mut off = 0;
+        while off < size {
+            let (paddr, flags, page) = self.query((source + off).into())?;
+            let len = (page as usize - page.align_offset(source + off)).min(size - off);
+            let alias = start + off;
+            let get_paddr = |va: M::VirtAddr| paddr.add(va.into() - alias);
+            match self.map_region_inner(alias.into(), get_paddr, len, flags, true, false) {
+                Ok(tlb) => {
+                    widened |= tlb.is_needed();
+                    tlb.ignore();
+                }
+                Err(e) => {
+                    self.rolling_back(|pt| pt.unmap_region_inner(vaddr, off + len, false, false))?
+                        .ignore();
+                    return Err(e);
+                }
+            }
+            off += len;
+        }
+        self.count_cow_frames(source, size, Self::frame_shared);
+        let tlb = if widened {
+            TlbFlushAll::new()
+        } else {
+            TlbFlushAll::new_mappings()
+        };
+        Ok(tlb.with_generation(self.generation))
+    }
+
+    /// Unmaps the `size` bytes at `vaddr`, which are aliased at `alias` by
+    /// [`PageTable64::map_alias`], e.g. the kernel image at its link address
+    /// once it runs at its randomized address.
+    ///
+    /// Every page of `vaddr` must be mapped to the same frame as the page at
+    /// the same offset from `alias`, with the same flags, so that the frames
+    /// stay mapped. The page sizes may differ, as `alias` may not be aligned
+    /// like `vaddr`. The reference that `map_alias` took on the frames of the
+    /// copy-on-write pages is dropped with [`PagingHandler::frame_unshared`].
+    /// Locked pages are unmapped too, as their frames stay mapped.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// an address or `size` is not aligned to 4K,
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if a page is
+    /// not aliased, and
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if a huge page at `vaddr` crosses the bounds of the range. Nothing is
+    /// unmapped then.
+    pub fn unmap_alias(
+        &mut self,
+        vaddr: M::VirtAddr,
+        alias: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.unmap_alias_inner(vaddr, alias, size);
+        Self::reported("unmap_alias", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::unmap_alias`], without reporting its errors.
+    fn unmap_alias_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        alias: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let (start, alias): (usize, usize) = (vaddr.into(), alias.into());
+        trace!(
+            "unmap_alias({:#x}): [{:#x}, {:#x}) -> {:#x}",
+            self.root_paddr(),
+            start,
+            start.wrapping_add(size),
+            alias,
+        );
+        if !PageSize::Size4K.is_aligned(alias) {
+            return Err(PagingError::NotAligned);
+        }
+        self.check_mapped_pages(start, size, None)?;
+        Self::check_range(alias, size)?;
+        let mut off = 0;
+        while off < size {
+            let (paddr, flags, page) = self.query((start + off).into())?;
+            let (aliased, alias_flags, alias_page) = self
+                .query((alias + off).into())
+                .map_err(|_| PagingError::NotMapped)?;
+            if aliased != paddr || alias_flags != flags {
+                return Err(PagingError::NotMapped);
+            }
+            let len = page as usize - page.align_offset(start + off);
+            off += len.min(alias_page as usize - alias_page.align_offset(alias + off));
+        }
+        self.count_cow_frames(start, size, Self::frame_unshared);
+        self.unmap_region_inner(vaddr, size, false, true)
+    }
+
+    /// Calls `count` ([`PagingHandler::frame_shared`] or
+    /// [`PagingHandler::frame_unshared`]) for the frames of the
+    /// copy-on-write pages in the `size` bytes at `vaddr`, once for each leaf
+    /// entry like in [`PageTable64::clone_cow`].
+    fn count_cow_frames(&self, vaddr: usize, size: usize, count: fn(PhysAddr, PageSize)) {
+        let mut off = 0;
+        while off < size {
+            let (entry, page) = self.get_entry((vaddr + off).into()).unwrap();
+            if entry.leaf_flags(page.is_huge()).contains(MappingFlags::COW) {
+                let base = (vaddr + off) & !(page as usize - 1);
+                count(Self::leaf_paddr(&entry, base, page.is_huge()), page);
+            }
+            off += page as usize - page.align_offset(vaddr + off);
+        }
+    }
+
+    /// Maps a contiguous virtual memory region to the zero frame given by
+    /// [`PagingHandler::zero_frame`], e.g. for anonymous memory that has not
+    /// been written yet.
+    ///
+    /// Every 4K page is mapped with the flags given by
+    /// [`MappingFlags::cow_of`], so writable mappings become copy-on-write and
+    /// get a private page in [`PageTable64::handle_cow_fault`]. If `cow_of`
+    /// excludes `flags`, the pages are mapped read-only. The reference
+    /// counting callbacks are never called for the zero frame.
+    ///
+    /// `vaddr` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Returns
+    /// [`Err(PagingError::NoMemory)`](PagingError::NoMemory) if the handler
+    /// has no zero frame.
+    pub fn map_zero_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.map_zero_region_inner(vaddr, size, flags);
+        Self::reported("map_zero_region", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::map_zero_region`], without reporting its errors.
+    fn map_zero_region_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let zero = H::zero_frame().ok_or(PagingError::NoMemory)?;
+        let flags = flags.cow_of().unwrap_or(flags - MappingFlags::WRITE);
+        self.map_region_inner(vaddr, |_| zero, size, flags, false, false)
+    }
+
+    /// Unmaps a contiguous virtual memory region.
+    ///
+    /// The region must be mapped before using [`PageTable64::map_region`], or
+    /// unexpected behaviors may occur. It can deal with huge pages automatically.
+    ///
+    /// When `flush_tlb_by_page` is true, it will flush the TLB immediately after
+    /// mapping each page. Otherwise, the TLB flush should by handled by the caller.
+    ///
+    /// Large regions can be unmapped in steps with
+    /// [`PageTable64::unmap_region_step`], and every page with
+    /// [`PageTable64::unmap_all`]. The guard pages of an allocation of
+    /// [`PageTable64::map_alloc_with`] starting at `vaddr` are cleared with
+    /// it.
+    ///
+    /// Returns [`Err(PagingError::Locked)`](PagingError::Locked) at the first
+    /// page locked by [`PageTable64::lock_region`], like at a hole, after
+    /// unmapping the pages before it. [`PageTable64::force_unmap_region`]
+    /// unmaps the locked pages too.
+    ///
+    /// Empty and invalid regions are handled like in
+    /// [`PageTable64::map_region`].
+    pub fn unmap_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flush_tlb_by_page: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.unmap_region_inner(vaddr, size, flush_tlb_by_page, false);
+        Self::reported("unmap_region", vaddr.into(), result)
+    }
+
+    /// Unmaps a contiguous virtual memory region like
+    /// [`PageTable64::unmap_region`], including the pages locked by
+    /// [`PageTable64::lock_region`], e.g. when the process that locked them
+    /// exits.
+    pub fn force_unmap_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flush_tlb_by_page: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.unmap_region_inner(vaddr, size, flush_tlb_by_page, true);
+        Self::reported("force_unmap_region", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::unmap_region`], without reporting its errors. Locked
+    /// pages are unmapped too if `force` is `true`.
+    fn unmap_region_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flush_tlb_by_page: bool,
+        force: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        if size == 0 {
+            return Ok(self.empty_flush());
+        }
+        let mut cursor = RegionCursor::new(vaddr, size);
+        trace!(
+            "unmap_region({:#x}) [{:#x}, {:#x})",
+            self.root_paddr(),
+            cursor.next,
+            cursor.end,
+        );
+        let generation = self.generation;
+        let pages = self.unmap_pages(&mut cursor, flush_tlb_by_page, force, usize::MAX)?;
+        self.batched(|pt| pt.clear_guards(vaddr.into(), size));
+        Ok(self.region_flush(generation, pages))
+    }
+
+    /// Unmaps every page, e.g. to tear down an address space.
+    ///
+    /// Unlike [`PageTable64::unmap_region`] over the whole address space, the
+    /// holes are skipped. The pages are removed like in
+    /// [`PageTable64::unmap_paddr_range`], in O(mapped size), and the tables
+    /// are kept until the page table is dropped. The entire TLB must be
+    /// flushed.
+    pub fn unmap_all(&mut self) -> PagingResult<TlbFlushAll<M>> {
+        let all = PhysAddrRange::new(PhysAddr::from(0), PhysAddr::from(usize::MAX));
+        self.unmap_paddr_range(all, |_, _| {})
+    }
+
+    /// Unmaps at most `budget` pages of the region of `cursor` like
+    /// [`PageTable64::unmap_region`], and moves the cursor past them.
+    ///
+    /// The TLB flush of each step is needed before the frames unmapped by
+    /// that step can be reused. See [`PageTable64::map_region_step`].
+    pub fn unmap_region_step(
+        &mut self,
+        cursor: &mut RegionCursor,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let result = self.unmap_region_step_inner(cursor, budget);
+        Self::reported("unmap_region_step", cursor.next, result)
+    }
+
+    /// [`PageTable64::unmap_region_step`], without reporting its errors.
+    fn unmap_region_step_inner(
+        &mut self,
+        cursor: &mut RegionCursor,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let generation = self.generation;
+        let pages = self.unmap_pages(cursor, false, false, budget)?;
+        Ok((cursor.status(), self.region_flush(generation, pages)))
+    }
+
+    /// Updates mapping flags of a contiguous virtual memory region.
+    ///
+    /// The region must be mapped before using [`PageTable64::map_region`], or
+    /// unexpected behaviors may occur. It can deal with huge pages automatically.
+    ///
+    /// When `flush_tlb_by_page` is true, it will flush the TLB immediately after
+    /// mapping each page. Otherwise, the TLB flush should by handled by the caller.
+    ///
+    /// Large regions can be updated in steps with
+    /// [`PageTable64::protect_region_step`].
+    ///
+    /// Empty and invalid regions are handled like in
+    /// [`PageTable64::map_region`].
+    pub fn protect_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        flush_tlb_by_page: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.protect_region_inner(vaddr, size, flags, flush_tlb_by_page);
+        Self::reported("protect_region", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::protect_region`], without reporting its errors.
+    fn protect_region_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        flush_tlb_by_page: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        if size == 0 {
+            return Ok(self.empty_flush());
+        }
+        let mut cursor = RegionCursor::new(vaddr, size);
+        trace!(
+            "protect_region({:#x}) [{:#x}, {:#x}) {:?}",
+            self.root_paddr(),
+            cursor.next,
+            cursor.end,
+            flags,
+        );
+        let generation = self.generation;
+        let pages = self.protect_pages(&mut cursor, flags, flush_tlb_by_page, usize::MAX)?;
+        Ok(self.region_flush(generation, pages))
+    }
+
+    /// Updates the flags of at most `budget` pages of the region of `cursor`
+    /// like [`PageTable64::protect_region`], and moves the cursor past them.
+    ///
+    /// See [`PageTable64::map_region_step`].
+    pub fn protect_region_step(
+        &mut self,
+        cursor: &mut RegionCursor,
+        flags: MappingFlags,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let result = self.protect_region_step_inner(cursor, flags, budget);
+        Self::reported("protect_region_step", cursor.next, result)
+    }
+
+    /// [`PageTable64::protect_region_step`], without reporting its errors.
+    fn protect_region_step_inner(
+        &mut self,
+        cursor: &mut RegionCursor,
+        flags: MappingFlags,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let generation = self.generation;
+        let pages = self.protect_pages(cursor, flags, false, budget)?;
+        Ok((cursor.status(), self.region_flush(generation, pages)))
+    }
+
+    /// Changes the memory type of the pages mapped in the region to `ty`,
+    /// keeping their other flags, e.g. when the BAR of an unbound device is
+    /// reused as normal memory.
+    ///
+    /// Changing the memory attributes of a live mapping needs the same steps
+    /// on every architecture, whatever [`PagingMetaData::BREAK_BEFORE_MAKE`]:
+    /// each page of another type is made invalid and its TLB entry flushed
+    /// with [`PagingMetaData::flush_tlb`], its frames are passed to
+    /// [`PagingHandler::cache_maintain`] if their cacheability changes, and
+    /// only then is it mapped with `ty`. `flush_tlb` must therefore reach
+    /// every CPU that may use the page table, and the returned flush is only
+    /// needed when it does not.
+    ///
+    /// Holes and the pages that already have `ty` are skipped, and groups
+    /// with the contiguous hint are broken up. Returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if the
+    /// region is not aligned to 4K,
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if it covers only a part of a huge page to retype, and
+    /// [`Err(PagingError::AttributeConflict)`](PagingError::AttributeConflict)
+    /// if [`PagingHandler::memory_type_of`] requires another type for a
+    /// frame. These are checked before any page changes.
+    pub fn retype_region(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        ty: MemoryType,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = self.retype_region_inner(start, size, ty);
+        Self::reported("retype_region", start.into(), result)
+    }
+
+    /// [`PageTable64::retype_region`], without reporting its errors.
+    fn retype_region_inner(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        ty: MemoryType,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        trace!(
+            "retype_region({:#x}): [{:#x}, {:#x}) {:?}",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+            ty,
+        );
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
+        }
+        if size == 0 {
+            return Ok(self.empty_flush());
+        }
+        // Only the low bits of the addresses are used to walk the tables.
+        let va_mask = Self::va_mask();
+        let range = (start.into() & va_mask, (start.into() & va_mask) + size);
+        let mut global = false;
+        let root = self.table_of_mut(self.root_paddr, 0);
+        self.retype_recursive(root, 0, 0, range, ty, false, &mut global)?;
+        self.retype_recursive(root, 0, 0, range, ty, true, &mut global)?;
+        self.end_update();
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
+        }
+        Ok(TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global))
+    }
+
+    /// Recomputes the permissions of every table entry from the mappings
+    /// below it (see [`GenericPTE::narrow_table`]), e.g. to drop those left
+    /// by mappings since unmapped or protected, or allowed by tables built
+    /// before [`GenericPTE::widen_table`] was relied on.
+    ///
+    /// Each entry is rewritten atomically, keeping the bits set by the
+    /// hardware meanwhile, and still allows every mapping below it, so the
+    /// page table may be active: no translation changes, and as no output
+    /// address does either, AArch64 needs no break-before-make. The returned
+    /// flush drops the old table entries cached by the walker. Tables shared
+    /// with other page tables (see [`PageTable64::copy_from`]) change for
+    /// them too, but their TLBs are not flushed.
+    ///
+    /// Returns the number of table entries changed, which is always 0 for
+    /// formats without permissions in table entries.
+    pub fn recompute_table_permissions(&mut self) -> (usize, TlbFlushAll<M>) {
+        trace!("recompute_table_permissions({:#x})", self.root_paddr());
+        let root = self.table_of_mut(self.root_paddr, 0);
+        let (mut changed, mut global) = (0, false);
+        self.narrow_tables_recursive(root, 0, 0, &mut changed, &mut global);
+        self.end_update();
+        if changed == 0 {
+            return (0, TlbFlushAll::unneeded(self.generation));
+        }
+        self.walk_cache.clear();
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
+        }
+        let tlb = TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global);
+        (changed, tlb)
+    }
+
+    /// Starts a [`Transaction`] on the page table, which saves the entries
+    /// its operations change in `saved`.
+    pub fn transaction<'a>(
+        &'a mut self,
+        saved: &'a mut [SavedEntry],
+    ) -> Transaction<'a, M, PTE, H, K> {
+        Transaction {
+            pt: self,
+            saved,
+            ops: [None; MAX_STAGED_OPS],
+            len: 0,
+        }
+    }
+
+    /// Unmaps every page whose frame overlaps `paddrs`, e.g. before the
+    /// physical memory is removed.
+    ///
+    /// `f` is called with the virtual address and the size of each removed
+    /// page. A huge page that only partially overlaps `paddrs` is first split
+    /// into pages of the next smaller size, recursively, so that the part
+    /// outside of `paddrs` stays mapped. The same goes for a group of 4K
+    /// entries with the contiguous hint, which is broken up like in
+    /// [`PageTable64::unmap`]. The smaller pages get the flags of the huge
+    /// page ([`GenericPTE::flags`]) and its bits unknown to this crate
+    /// ([`GenericPTE::unknown_bits`]), but not the other bits that
+    /// [`MappingFlags`] cannot express. The huge page is invalid while it is
+    /// split.
+    ///
+    /// Like [`PageTable64::unmap`], [`PagingHandler::frame_unshared`] is
+    /// called for the removed copy-on-write pages. When a copy-on-write huge
+    /// page is split, [`PagingHandler::frame_shared`] is called for each
+    /// smaller page before `frame_unshared` is called for the huge page.
+    ///
+    /// No reverse mapping from frames to virtual addresses is kept, so this
+    /// walks the whole page table, in O(mapped size).
+    ///
+    /// Splitting allocates tables, within the limit of table frames. If it
+    /// fails, the pages removed so far stay removed, and the caller must flush
+    /// the entire TLB.
+    pub fn unmap_paddr_range(
+        &mut self,
+        paddrs: PhysAddrRange,
+        mut f: impl FnMut(M::VirtAddr, PageSize),
+    ) -> PagingResult<TlbFlushAll<M>> {
+        trace!(
+            "unmap_paddr_range({:#x}): {:#x?}",
+            self.root_paddr(),
+            paddrs,
+        );
+        if paddrs.is_empty() {
+            return Ok(TlbFlushAll::new().with_generation(self.generation));
+        }
+        let mut global = false;
+        let root = self.table_of_mut(self.root_paddr, 0);
+        let result = self.unmap_paddr_recursive(root, 0, 0, paddrs, &mut f, &mut global);
+        self.end_update();
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
+        }
+        result?;
+        Ok(TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global))
+    }
+
+    /// Walk the page table recursively.
+    ///
+    /// When reaching a page table entry, call `pre_func` and `post_func` on the
+    /// entry if they are provided. The max number of enumerations in one table
+    /// is limited by `limit`. `pre_func` and `post_func` are called before and
+    /// after recursively walking the page table.
+    ///
+    /// The arguments of `*_func` are:
+    /// - Current level (starts with `0`): `usize`
+    /// - The index of the entry in the current-level table: `usize`
+    /// - The virtual address that is mapped to the entry: `M::VirtAddr`
+    /// - A copy of the entry, read when it was reached: [`&PTE`](GenericPTE)
+    ///
+    /// The leaf entries are read with [`GenericPTE::leaf_paddr`] and
+    /// [`GenericPTE::leaf_flags`], passing whether the level is above the
+    /// last one: [`GenericPTE::is_huge`] only tells the entries above it
+    /// apart.
+    pub fn walk<F>(&self, limit: usize, pre_func: Option<&F>, post_func: Option<&F>) -> PagingResult
+    where
+        F: Fn(usize, usize, M::VirtAddr, &PTE),
+    {
+        self.walk_recursive(self.root_paddr(), 0, 0.into(), limit, pre_func, post_func)
+    }
+
+    /// Passes the mappings of the region of `size` bytes from `start` to
+    /// `sink`, coalesced into [`MappedRegion`](crate::MappedRegion)s, e.g. to
+    /// rebuild the memory areas of an address space from its page table.
+    ///
+    /// The regions are passed in the order of the addresses, do not overlap,
+    /// and are as large as possible: consecutive mappings are merged as long
+    /// as they have the same flags and map consecutive frames, whatever the
+    /// sizes of their pages. So the regions only depend on what is mapped, not
+    /// on the pages used. A page partly in the region is cut to the part in
+    /// it. Each region gets the generation of the page table.
+    ///
+    /// The search stops early if `sink` returns
+    /// [`ControlFlow::Break`](core::ops::ControlFlow::Break).
+    ///
+    /// Returns [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr)
+    /// if the region is not in the address space, like
+    /// [`PageTable64::map_region`].
+    pub fn collect_regions(
+        &self,
+        start: M::VirtAddr,
+        size: usize,
+        sink: &mut impl RegionSink,
+    ) -> PagingResult {
+        let start: usize = start.into();
+        Self::check_range(start, size)?;
+        if size == 0 {
+            return Ok(());
+        }
+        info::collect_regions(self, start, start + (size - 1), sink);
+        Ok(())
+    }
+
+    fn top_level_idx_range(&self, start: M::VirtAddr, size: usize) -> (usize, usize) {
+        let start_idx = Self::index_of(start.into(), 0);
+        let end_idx = Self::index_of(start.into() + size - 1, 0) + 1;
+        assert!(start_idx < table_entries::<M>(0));
+        assert!(end_idx <= table_entries::<M>(0));
+        (start_idx, end_idx)
+    }
+
+    /// Copy entries from another page table within the given virtual memory range.
+    ///
+    /// The other page table must be of a [`SharedSpace`] kind, e.g. a
+    /// [`KernelSpace`](crate::KernelSpace) template whose upper half is copied
+    /// into every user page table.
+    pub fn copy_from<S: SharedSpace>(
+        &mut self,
+        other: &PageTable64<M, PTE, H, S>,
+        start: M::VirtAddr,
+        size: usize,
+    ) {
+        self.link_top_level(other, start, size);
+    }
+
+    /// Copies the top-level entries of `other` for the region, so that it is
+    /// mapped through the same tables, for [`PageTable64::copy_from`] and
+    /// the page tables kept in sync by the crate.
+    pub(crate) fn link_top_level<S: SpaceKind>(
+        &mut self,
+        other: &PageTable64<M, PTE, H, S>,
+        start: M::VirtAddr,
+        size: usize,
+    ) {
+        if size == 0 {
+            return;
+        }
+        self.walk_cache.clear();
+        self.generation += 1;
+        self.unlinks += 1;
+        let dst_table = self.table_of_mut(self.root_paddr, 0);
+        let (start_idx, end_idx) = self.top_level_idx_range(start, size);
+        for (i, entry) in dst_table
+            .iter_mut()
+            .enumerate()
+            .take(end_idx)
+            .skip(start_idx)
+        {
+            let old = *entry;
+            *entry = Self::load_entry(other.root_paddr, i);
+            let vaddr = Self::sign_extended(i * Self::entry_size(0));
+            Self::note(&mut self.journal, vaddr, 0, old, *entry);
+        }
+        self.end_update();
+    }
+
+    /// Undoes the copy of entries from another page table within the given
+    /// virtual memory range.
+    ///
+    /// This is the inverse operation of [`PageTable64::copy_from`]. It might be
+    /// useful when you need to drop a page table, but some of its entries are
+    /// copied from another actively used page table, so you need to unlink the
+    /// shared entries first.
+    pub fn clear_copy_range(&mut self, start: M::VirtAddr, size: usize) {
+        if size == 0 {
+            return;
+        }
+        self.walk_cache.clear();
+        self.generation += 1;
+        self.unlinks += 1;
+        let table = self.table_of_mut(self.root_paddr, 0);
+        let (start_idx, end_idx) = self.top_level_idx_range(start, size);
+        for (i, pte) in table.iter_mut().enumerate().take(end_idx).skip(start_idx) {
+            let old = *pte;
+            pte.clear();
+            let vaddr = Self::sign_extended(i * Self::entry_size(0));
+            Self::note(&mut self.journal, vaddr, 0, old, *pte);
+        }
+        self.end_update();
+    }
+
+    /// Builds a small page table in the given frames, e.g. for the trampoline
+    /// code that starts a secondary CPU or jumps to the kernel of `kexec`,
+    /// and returns the physical address of its root table.
+    ///
+    /// Each `(paddr, size, flags)` of `identity` is identity-mapped, with the
+    /// largest pages that fit. The top-level entries of `extra` for its
+    /// region, if any, are then copied like in [`PageTable64::copy_from`],
+    /// e.g. for the mappings of the kernel to jump to: the table goes through
+    /// the tables of `extra` there, which must outlive it.
+    ///
+    /// Nothing is allocated and [`PagingHandler`] is not used, but to read
+    /// the root table of `extra`. The tables are taken from the unused slots
+    /// of `frames` in order, and marked used (see
+    /// [`PhysFrameSlot::is_used`]). The table is not a [`PageTable64`]: it is
+    /// never changed, and the caller reuses the frames once it is no longer
+    /// loaded.
+    ///
+    /// Returns [`Err(PagingError::NoMemory)`](PagingError::NoMemory) if the
+    /// frames run out,
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped) if the
+    /// identity mappings overlap each other or the region of `extra`, and
+    /// [`Err(PagingError::UnsupportedPageSize)`](PagingError::UnsupportedPageSize)
+    /// if the tables are larger than 4K (see [`PagingMetaData::INDEX_BITS`]).
+    /// The addresses and sizes must be aligned to 4K, and are checked like in
+    /// [`PageTable64::map_region`]. No frame is used then.
+    pub fn build_trampoline_table(
+        frames: &mut [PhysFrameSlot<'_>],
+        identity: &[(PhysAddr, usize, MappingFlags)],
+        extra: Option<(&Self, M::VirtAddr, usize)>,
+    ) -> PagingResult<PhysAddr> {
+        let result = Self::build_trampoline_inner(frames, identity, extra);
+        if result.is_err() {
+            let used = frames.iter().filter(|slot| slot.used).count();
+            warn!("failed to build a trampoline table in {} frames", used);
+            frames.iter_mut().for_each(|slot| slot.used = false);
+        }
+        result
+    }
+
+    /// [`PageTable64::build_trampoline_table`], without releasing the frames
+    /// on failure.
+    fn build_trampoline_inner(
+        frames: &mut [PhysFrameSlot<'_>],
+        identity: &[(PhysAddr, usize, MappingFlags)],
+        extra: Option<(&Self, M::VirtAddr, usize)>,
+    ) -> PagingResult<PhysAddr> {
+        if (0..M::LEVELS).any(|level| Self::table_bytes(level) > PAGE_SIZE_4K) {
+            return Err(PagingError::UnsupportedPageSize);
+        }
+        let root = Self::take_slot(frames)?;
+        for &(paddr, size, flags) in identity {
+            let start = paddr.as_usize();
+            if !paddr.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+                return Err(PagingError::NotAligned);
+            }
+            Self::check_range(start, size)?;
+            let mut off = 0;
+            while off < size {
+                let page = match Self::block_size(start + off, paddr + off, size - off, true) {
+                    // The 16 entries of a group are not worth it here.
+                    PageSize::Size64K => PageSize::Size4K,
+                    page => page,
+                };
+                Self::check_paddr(paddr + off, page)?;
+                Self::trampoline_page(frames, root, paddr + off, page, flags)?;
+                off += page as usize;
+            }
+        }
+        if let Some((other, start, size)) = extra.filter(|&(_, _, size)| size > 0) {
+            Self::check_range(start.into(), size)?;
+            let (start_idx, end_idx) = other.top_level_idx_range(start, size);
+            let table = Self::slot_table(&mut frames[root], 0);
+            for (i, entry) in table.iter_mut().enumerate().take(end_idx).skip(start_idx) {
+                if !entry.is_unused() {
+                    return Err(PagingError::AlreadyMapped);
+                }
+                *entry = Self::load_entry(other.root_paddr, i);
+            }
+        }
+        Ok(frames[root].paddr)
+    }
+
+    /// Identity-maps the page of `size` at `paddr` in the trampoline table
+    /// whose root is in the slot `root`, creating the tables on the way.
+    fn trampoline_page(
+        frames: &mut [PhysFrameSlot<'_>],
+        root: usize,
+        paddr: PhysAddr,
+        size: PageSize,
+        flags: MappingFlags,
+    ) -> PagingResult {
+        let vaddr = paddr.as_usize();
+        let leaf_level = Self::leaf_level(size);
+        let mut slot = root;
+        for level in 0..leaf_level {
+            let index = Self::index_of(vaddr, level);
+            let mut entry = Self::slot_table(&mut frames[slot], level)[index];
+            let next = if entry.is_unused() {
+                let next = Self::take_slot(frames)?;
+                entry = PTE::new_table(frames[next].paddr);
+                next
+            } else if entry.is_huge() {
+                return Err(PagingError::AlreadyMapped);
+            } else {
+                frames
+                    .iter()
+                    .position(|frame| frame.used && frame.paddr == entry.paddr())
+                    .ok_or(PagingError::AlreadyMapped)?
+            };
+            entry.widen_table(flags);
+            Self::slot_table(&mut frames[slot], level)[index] = entry;
+            slot = next;
+        }
+        let entry =
+            &mut Self::slot_table(&mut frames[slot], leaf_level)[Self::index_of(vaddr, leaf_level)];
+        if !entry.is_unused() {
+            return Err(PagingError::AlreadyMapped);
+        }
+        *entry = PTE::new_page(paddr, flags, size.is_huge());
+        Ok(())
+    }
+
+    /// Takes the first unused slot of `frames` for a table, and clears it.
+    fn take_slot(frames: &mut [PhysFrameSlot<'_>]) -> PagingResult<usize> {
+        let index = frames
+            .iter()
+            .position(|slot| !slot.used)
+            .ok_or(PagingError::NoMemory)?;
+        let slot = &mut frames[index];
+        Self::check_paddr(slot.paddr, PageSize::Size4K)?;
+        slot.frame.fill(0);
+        slot.used = true;
+        Ok(index)
+    }
+
+    /// Returns the table at `level` built in `slot`.
+    fn slot_table<'a>(slot: &'a mut PhysFrameSlot<'_>, level: usize) -> &'a mut [PTE] {
+        let entries = table_entries::<M>(level);
+        // The tables fit in 4K, and the entries are 64-bit.
+        unsafe { core::slice::from_raw_parts_mut(slot.frame.as_mut_ptr().cast(), entries) }
+    }
+
+    /// Exports the subtree of tables mapping the region of `size` bytes from
+    /// `start`, so that other page tables can share it with
+    /// [`PageTable64::import_subtree`].
+    ///
+    /// `size` must be the size of the region mapped by an entry pointing to a
+    /// table (e.g. 2M or 1G with 4 levels), and `start` aligned to it.
+    /// Nothing is changed: this page table keeps the initial reference to
+    /// the tables, and must release it with [`PageTable64::release_subtree`]
+    /// like the importing ones. Its tables and mappings stay accounted to it
+    /// until then.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `start` or `size` is not a boundary of tables,
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if there is no
+    /// table for the region, and
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if it is mapped by a huge page.
+    pub fn export_subtree(
+        &self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<SharedSubtree<M, PTE, K>> {
+        let level = Self::subtree_level(start, size)?;
+        let vaddr: usize = start.into();
+        let mut table = self.root_paddr;
+        for level in 0..level {
+            let entry = Self::load_entry(table, Self::index_of(vaddr, level));
+            table = Self::next_table(&entry, vaddr, level)?;
+        }
+        let entry = Self::load_entry(table, Self::index_of(vaddr, level));
+        Self::next_table(&entry, vaddr, level)?;
+        Ok(SharedSubtree {
+            entry,
+            level,
+            owner: self.root_paddr,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Links `subtree` at `vaddr`, which must be aligned to its size, creating
+    /// the tables above it if needed.
+    ///
+    /// [`PagingHandler::frame_shared`] is called for each table frame of the
+    /// subtree. The subtree is not accounted to this page table, only the
+    /// tables created above it.
+    ///
+    /// The mappings can then be changed through any of the page tables
+    /// sharing the subtree, which changes them for all of them: the returned
+    /// flushes only cover the page table that made the change, and the
+    /// others must be flushed by the caller. Tables must not be added to the
+    /// subtree after it is shared (e.g. by mapping 4K pages in a huge page),
+    /// since they would not be counted.
+    ///
+    /// The subtree must be released with [`PageTable64::release_subtree`]
+    /// before this page table is dropped, which would free its tables.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `vaddr` is not aligned, and
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped) if
+    /// there is a mapping or a table at `vaddr` already.
+    pub fn import_subtree(
+        &mut self,
+        vaddr: M::VirtAddr,
+        subtree: &SharedSubtree<M, PTE, K>,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let level = Self::subtree_level(vaddr, subtree.size())?;
+        let vaddr: usize = vaddr.into();
+        let flags = subtree.entry.flags();
+        let mut widened = false;
+        let mut table = self.table_of_mut(self.root_paddr, 0);
+        for level in 0..level {
+            let entry = &mut table[Self::index_of(vaddr, level)];
+            table = self.next_table_mut_or_create(entry, vaddr, level, flags, &mut widened)?;
+        }
+        let entry = &mut table[Self::index_of(vaddr, level)];
+        if !entry.is_unused() {
+            // The tables above may have been created.
+            self.journal.end();
+            return Err(PagingError::AlreadyMapped);
+        }
+        let journal = &mut self.journal;
+        Self::visit_subtree(
+            subtree.table_paddr(),
+            level + 1,
+            vaddr,
+            &mut |paddr, level, vaddr| {
+                H::frame_shared(paddr, PageSize::Size4K);
+                let count = table_entries::<M>(level);
+                Self::note_range(journal, Self::sign_extended(vaddr), level, count);
+            },
+        );
+        let old = *entry;
+        *entry = subtree.entry;
+        Self::note(
+            &mut self.journal,
+            Self::sign_extended(vaddr),
+            level,
+            old,
+            *entry,
+        );
+        self.walk_cache.clear();
+        self.end_update();
+        let tlb = if widened {
+            TlbFlushAll::new()
+        } else {
+            TlbFlushAll::new_mappings()
+        };
+        if tlb.is_needed() {
+            self.generation += 1;
+        }
+        Ok(tlb.with_generation(self.generation))
+    }
+
+    /// Unlinks `subtree` from `vaddr`, where it was exported or imported.
+    ///
+    /// [`PagingHandler::frame_unshared`] is called for each table frame of the
+    /// subtree, children first, and the tables are no longer accessed after
+    /// that, so the handler can free each frame whose last reference is
+    /// dropped. If this page table exported the subtree, its tables and
+    /// mappings are no longer accounted to it.
+    ///
+    /// The page tables sharing a subtree can release it in any order.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `vaddr` is not aligned, and
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if `subtree` is
+    /// not linked at `vaddr`.
+    pub fn release_subtree(
+        &mut self,
+        vaddr: M::VirtAddr,
+        subtree: &SharedSubtree<M, PTE, K>,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let level = Self::subtree_level(vaddr, subtree.size())?;
+        let vaddr: usize = vaddr.into();
+        let mut table = self.table_of_mut(self.root_paddr, 0);
+        for level in 0..level {
+            table = self.next_table_mut(&table[Self::index_of(vaddr, level)], vaddr, level)?;
+        }
+        let entry = &mut table[Self::index_of(vaddr, level)];
+        if Self::next_table(entry, vaddr, level) != Ok(subtree.table_paddr()) {
+            return Err(PagingError::NotMapped);
+        }
+        let old = *entry;
+        entry.clear();
+        Self::note(
+            &mut self.journal,
+            Self::sign_extended(vaddr),
+            level,
+            old,
+            *entry,
+        );
+        self.walk_cache.clear();
+        self.unlinks += 1;
+        self.end_update();
+
+        let mut tables = 0;
+        let (bytes, global) = Self::visit_subtree(
+            subtree.table_paddr(),
+            level + 1,
+            vaddr,
+            &mut |paddr, level, _| {
+                H::frame_unshared(paddr, PageSize::Size4K);
+                tables += Self::table_frames_at(level);
+            },
+        );
+        if subtree.owner == self.root_paddr {
+            self.table_frames = self.table_frames.saturating_sub(tables);
+            self.journal.stats.freed(tables);
+            self.mapped_bytes = self.mapped_bytes.saturating_sub(bytes);
+        }
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
+        }
+        Ok(TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global))
+    }
+
+    /// Unlinks the subtree of tables below the entry of `vaddr` at `level`,
+    /// e.g. to tear down a large part of an address space, and returns it to
+    /// be freed later with [`DetachedSubtree::reclaim`].
+    ///
+    /// Only that entry is cleared, so that the pages are not unmapped one by
+    /// one: the tables are read once to account for the pages and tables
+    /// removed, but nothing else is written, journaled or flushed per page.
+    /// The returned flush is for the entire TLB, and must be done before the
+    /// subtree is reclaimed. The pages locked by
+    /// [`PageTable64::lock_region`] are removed too, like with
+    /// [`PageTable64::force_unmap_region`].
+    ///
+    /// The subtree must not contain tables linked by
+    /// [`PageTable64::import_subtree`] or [`PageTable64::copy_from`], which
+    /// would be freed with it: they must be released or cleared first.
+    ///
+    /// Returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned) if
+    /// `level` is not that of an entry pointing to a table, or `vaddr` is not
+    /// aligned to the region of the entry,
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if there is no
+    /// table there, and
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage)
+    /// if it is mapped by a huge page.
+    pub fn detach_subtree(
+        &mut self,
+        vaddr: M::VirtAddr,
+        level: usize,
+    ) -> PagingResult<(DetachedSubtree<M, PTE, H>, TlbFlushAll<M>)> {
+        let result = self.detach_subtree_inner(vaddr, level);
+        Self::reported("detach_subtree", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::detach_subtree`], without reporting its errors.
+    fn detach_subtree_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        level: usize,
+    ) -> PagingResult<(DetachedSubtree<M, PTE, H>, TlbFlushAll<M>)> {
+        if level >= M::LEVELS - 1 {
+            return Err(PagingError::NotAligned);
+        }
+        Self::subtree_level(vaddr, Self::entry_size(level))?;
+        let vaddr: usize = vaddr.into();
+        let mut table = self.table_of_mut(self.root_paddr, 0);
+        for level in 0..level {
+            table = self.next_table_mut(&table[Self::index_of(vaddr, level)], vaddr, level)?;
+        }
+        let entry = &mut table[Self::index_of(vaddr, level)];
+        Self::next_table(entry, vaddr, level)?;
+        let old = *entry;
+        entry.clear();
+        Self::note(
+            &mut self.journal,
+            Self::sign_extended(vaddr),
+            level,
+            old,
+            *entry,
+        );
+        self.walk_cache.clear();
+        self.unlinks += 1;
+        self.end_update();
+
+        let mut tables = 0;
+        let (bytes, global) =
+            Self::visit_subtree(old.paddr(), level + 1, vaddr, &mut |_, level, _| {
+                tables += Self::table_frames_at(level);
+            });
+        self.table_frames = self.table_frames.saturating_sub(tables);
+        self.journal.stats.freed(tables);
+        self.mapped_bytes = self.mapped_bytes.saturating_sub(bytes);
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
+        }
+        let subtree = DetachedSubtree {
+            entry: old,
+            level,
+            vaddr: vaddr & Self::va_mask(),
+            _phantom: PhantomData,
+        };
+        let tlb = TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global);
+        Ok((subtree, tlb))
+    }
+
+    /// Maps the region like [`PageTable64::map_region`] in every page table
+    /// of `tables`, e.g. a kernel mapping replicated in the page table of each
+    /// process, where the top-level entries cannot be shared.
+    ///
+    /// Either the region is mapped in all the page tables, or in none: if
+    /// mapping fails in one of them, the pages mapped so far are unmapped
+    /// from every page table, their flushes are done with
+    /// [`TlbFlushAll::flush_with_threshold`], and the error is returned.
+    ///
+    /// A page table that reaches the region through the same tables as an
+    /// earlier one in `tables`, e.g. because it imported a subtree exported
+    /// by it (see [`PageTable64::import_subtree`]), is skipped: the change is
+    /// already visible through it. A region only partly in a shared subtree is
+    /// mapped through each page table, and fails with
+    /// [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped).
+    ///
+    /// The returned flush merges the flushes of all the page tables (see
+    /// [`TlbFlushAll::merge`]). It must be done on every CPU using one of
+    /// them, and not with [`TlbFlushAll::flush_if_current`], as its
+    /// generation is the highest of the page tables.
+    pub fn broadcast_map(
+        tables: &mut [&mut Self],
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = Self::broadcast_map_inner(tables, vaddr, paddr, size, flags, allow_huge);
+        Self::reported("broadcast_map", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::broadcast_map`], without reporting its errors.
+    fn broadcast_map_inner(
+        tables: &mut [&mut Self],
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let start: usize = vaddr.into();
+        trace!(
+            "broadcast_map({} tables): [{:#x}, {:#x}) -> {:#x} {:?}",
+            tables.len(),
+            start,
+            start.wrapping_add(size),
+            paddr,
+            flags,
+        );
+        let get_paddr = |va: M::VirtAddr| paddr.add(va.into() - start);
+        let mut tlb = Self::unchanged(0);
+        for i in 0..tables.len() {
+            if size == 0 || Self::shares_region(tables, i, start, size) {
+                continue;
+            }
+            let mut cursor = RegionCursor::new(start, size);
+            let pages = (&get_paddr, &mut |_| flags, false, allow_huge);
+            match tables[i].map_pages(&mut cursor, pages, false, usize::MAX) {
+                Ok(pages) => {
+                    let mapped = TlbFlushAll::new_mappings()
+                        .with_generation(tables[i].generation)
+                        .with_pages(pages);
+                    tlb = tlb.merge(mapped);
+                }
+                Err(e) => {
+                    let mapped = cursor.next.wrapping_sub(start);
+                    let unmap = |pt: &mut Self, size| {
+                        pt.rolling_back(|pt| pt.unmap_region_inner(vaddr, size, false, false))
+                    };
+                    tlb = tlb.merge(unmap(tables[i], mapped)?);
+                    for j in 0..i {
+                        if !Self::shares_region(tables, j, start, size) {
+                            tlb = tlb.merge(unmap(tables[j], size)?);
+                        }
+                    }
+                    tlb.flush_with_threshold(M::FLUSH_PAGES_THRESHOLD);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(tlb)
+    }
+
+    /// Unmaps the region like [`PageTable64::unmap_region`] in every page
+    /// table of `tables`, skipping the ones that share it like
+    /// [`PageTable64::broadcast_map`].
+    ///
+    /// Every page table is checked before any is changed, so that an error
+    /// leaves them all unchanged: every page of the region must be mapped,
+    /// otherwise it returns
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped), and the
+    /// region must not start or end inside a huge page, otherwise it returns
+    /// [`Err(PagingError::MappedToHugePage)`](PagingError::MappedToHugePage).
+    /// The returned flush is like the one of [`PageTable64::broadcast_map`].
+    pub fn broadcast_unmap(
+        tables: &mut [&mut Self],
+        vaddr: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = Self::broadcast_unmap_inner(tables, vaddr, size);
+        Self::reported("broadcast_unmap", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::broadcast_unmap`], without reporting its errors.
+    fn broadcast_unmap_inner(
+        tables: &mut [&mut Self],
+        vaddr: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let start: usize = vaddr.into();
+        trace!(
+            "broadcast_unmap({} tables): [{:#x}, {:#x})",
+            tables.len(),
+            start,
+            start.wrapping_add(size),
+        );
+        for pt in tables.iter() {
+            pt.check_mapped_pages(start, size, None)?;
+        }
+        let mut tlb = Self::unchanged(0);
+        for i in 0..tables.len() {
+            if size > 0 && !Self::shares_region(tables, i, start, size) {
+                tlb = tlb.merge(tables[i].unmap_region_inner(vaddr, size, false, false)?);
+            }
+        }
+        Ok(tlb)
+    }
+
+    /// Updates the flags of the region like [`PageTable64::protect_region`]
+    /// in every page table of `tables`, skipping the ones that share it like
+    /// [`PageTable64::broadcast_map`].
+    ///
+    /// Every page table is checked before any is changed like in
+    /// [`PageTable64::broadcast_unmap`], including the checks of the new
+    /// flags done by [`PageTable64::protect`] for each page.
+    pub fn broadcast_protect(
+        tables: &mut [&mut Self],
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let result = Self::broadcast_protect_inner(tables, vaddr, size, flags);
+        Self::reported("broadcast_protect", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::broadcast_protect`], without reporting its errors.
+    fn broadcast_protect_inner(
+        tables: &mut [&mut Self],
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let start: usize = vaddr.into();
+        trace!(
+            "broadcast_protect({} tables): [{:#x}, {:#x}) {:?}",
+            tables.len(),
+            start,
+            start.wrapping_add(size),
+            flags,
+        );
+        for pt in tables.iter() {
+            pt.check_mapped_pages(start, size, Some(flags))?;
+        }
+        let mut tlb = Self::unchanged(0);
+        for i in 0..tables.len() {
+            if size > 0 && !Self::shares_region(tables, i, start, size) {
+                tlb = tlb.merge(tables[i].protect_region_inner(vaddr, size, flags, false)?);
+            }
+        }
+        Ok(tlb)
+    }
+
+    /// Returns whether `tables[i]` reaches the region of `size` bytes from
+    /// `start` through the same table as one of the page tables before it.
+    fn shares_region(tables: &[&mut Self], i: usize, start: usize, size: usize) -> bool {
+        let table = tables[i].region_table(start, size);
+        tables[..i]
+            .iter()
+            .any(|pt| pt.region_table(start, size) == table)
+    }
+
+    /// Returns the lowest table whose entries cover the whole non-empty
+    /// region of `size` bytes from `start`.
+    fn region_table(&self, start: usize, size: usize) -> PhysAddr {
+        let last = start.wrapping_add(size - 1);
+        let mut table = self.root_paddr;
+        for level in 0..M::LEVELS - 1 {
+            let shift = level_shift::<M>(level);
+            if start >> shift != last >> shift {
+                break;
+            }
+            let entry = Self::load_entry(table, Self::index_of(start, level));
+            match Self::next_table(&entry, start, level) {
+                Ok(next) => table = next,
+                Err(_) => break,
+            }
+        }
+        table
+    }
+
+    /// Checks that every page of the region of `size` bytes from `start` is
+    /// mapped by a page inside the region, and accepts `flags` if given, like
+    /// [`PageTable64::unmap`] and [`PageTable64::protect`] would.
+    pub(crate) fn check_mapped_pages(
+        &self,
+        start: usize,
+        size: usize,
+        flags: Option<MappingFlags>,
+    ) -> PagingResult {
+        if !PageSize::Size4K.is_aligned(start | size) {
+            return Err(PagingError::NotAligned);
+        }
+        Self::check_range(start, size)?;
+        let mut off = 0;
+        while off < size {
+            let vaddr = start + off;
+            let (entry, page) = self.get_entry(vaddr.into())?;
+            if !entry.is_present() {
+                return Err(PagingError::NotMapped);
+            }
+            Self::check_page_start(vaddr.into(), page)?;
+            if page as usize > size - off {
+                return Err(Self::huge_page_at(vaddr, Self::leaf_level(page)));
+            }
+            if let Some(flags) = flags {
+                Self::check_space(vaddr.into(), flags)?;
+                self.check_wx(vaddr.into(), flags)?;
+                let paddr = Self::leaf_paddr(&entry, vaddr, page.is_huge());
+                Self::check_memory_type(paddr, page, flags)?;
+            }
+            off += page as usize;
+        }
+        Ok(())
+    }
+
+    /// Resolves a write fault on the copy-on-write page containing `vaddr`.
+    ///
+    /// `copy` is called with the physical address and the size of the shared
+    /// page, and returns the page to use from now on, usually a private copy.
+    /// If the shared page is [`PagingHandler::zero_frame`], there is nothing
+    /// to copy and `copy` only needs to allocate a zeroed page. `copy` may
+    /// also return the same page (e.g. if it is no longer shared). The
+    /// mapping is then made writable again, as given by
+    /// [`MappingFlags::resolve_cow`]. If a different page is returned,
+    /// [`PagingHandler::frame_unshared`] is called for the old one.
+    ///
+    /// With the default [`HugeCowPolicy::Split`], a huge page is first split
+    /// into 4K pages, which stay copy-on-write and share its frames like in
+    /// [`PageTable64::unmap_paddr_range`], and `copy` is only called for the
+    /// 4K page containing `vaddr`. The split takes tables within the limit of
+    /// table frames, and is kept if the fault fails afterwards. With
+    /// [`HugeCowPolicy::CopyWhole`], `copy` is called for the huge page.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present, [`Err(PagingError::NotCow)`](PagingError::NotCow)
+    /// if it is not copy-on-write, and
+    /// [`Err(PagingError::NoMemory)`](PagingError::NoMemory) if `copy` returns
+    /// [`None`]. If `copy` returns an invalid page (see [`PageTable64::map`]),
+    /// [`Err(PagingError::InvalidPaddr)`](PagingError::InvalidPaddr) is
+    /// returned and the page is left to the caller.
+    pub fn handle_cow_fault(
+        &mut self,
+        vaddr: M::VirtAddr,
+        copy: impl FnOnce(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<TlbFlush<M>> {
+        let result = self.handle_cow_fault_inner(vaddr, copy);
+        Self::reported("handle_cow_fault", vaddr.into(), result)
+    }
+
+    /// [`PageTable64::handle_cow_fault`], without reporting its errors.
+    fn handle_cow_fault_inner(
+        &mut self,
+        vaddr: M::VirtAddr,
+        copy: impl FnOnce(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<TlbFlush<M>> {
+        let (mut entry, mut size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        let flags = entry.leaf_flags(size.is_huge());
+        if !flags.contains(MappingFlags::COW) {
+            return Err(PagingError::NotCow);
+        }
+        if size.is_huge() && self.huge_cow == HugeCowPolicy::Split {
+            for level in Self::leaf_level(size)..M::LEVELS - 1 {
+                let base = vaddr.align_down(Self::leaf_size(level));
+                let table = self.split_huge(entry, level, base)?;
+                entry = &mut table[Self::index_of(vaddr.into(), level + 1)];
+            }
+            size = PageSize::Size4K;
+        }
+        let old = Self::leaf_paddr(entry, vaddr.into(), size.is_huge());
+        let new_paddr = copy(old, size).ok_or(PagingError::NoMemory)?;
+        Self::check_paddr(new_paddr, size)?;
+        if entry.is_contiguous() {
+            let bbm = self.needs_bbm();
+            Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+        }
+        let old_entry = *entry;
+        let mut new = old_entry;
+        new.set_leaf_paddr(new_paddr, size.is_huge());
+        new.set_flags(flags.resolve_cow(), size.is_huge());
+        if M::AD_POLICY != AccessedDirtyPolicy::AlwaysSet {
+            // The faulting write accesses the page right away.
+            new.set_accessed(true);
+            new.set_dirty(true);
+        }
+        let bbm = self.needs_bbm();
+        let tlb = Self::update_leaf(entry, old_entry, new, vaddr, bbm, size.is_huge());
+        let level = Self::leaf_level(size);
+        Self::note(&mut self.journal, vaddr, level, old_entry, *entry);
+        if new_paddr != old {
+            Self::frame_unshared(old, size);
+        }
+        Ok(self.stamp(tlb))
+    }
+
+    /// Resolves a fault on the page containing `vaddr` caused by a clear
+    /// accessed or dirty bit, with [`AccessedDirtyPolicy::SoftwareManaged`]:
+    /// sets the accessed bit, and the dirty bit if `write` is `true` and the
+    /// page is writable.
+    ///
+    /// A write to a read-only page is a permission fault, which is left to
+    /// the caller: the dirty bit stays clear.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present.
+    pub fn handle_access_fault(
+        &mut self,
+        vaddr: M::VirtAddr,
+        write: bool,
+    ) -> PagingResult<TlbFlush<M>> {
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        let old = *entry;
+        let mut new = old;
+        new.set_accessed(true);
+        if write && old.leaf_flags(size.is_huge()).contains(MappingFlags::WRITE) {
+            new.set_dirty(true);
+        }
+        Self::write_leaf(entry, old, new);
+        Self::note(
+            &mut self.journal,
+            vaddr,
+            Self::leaf_level(size),
+            old,
+            *entry,
+        );
+        let tlb = TlbFlush::new(vaddr).with_global(old.is_global());
+        Ok(self.stamp(tlb))
+    }
+
+    /// Starts logging the writes to the region, e.g. to the guest memory of a
+    /// stage-2 page table during live migration: write-protects every
+    /// writable page of the region.
+    ///
+    /// Writes are logged per 4K page. Writable huge pages that overlap the
+    /// region are split into 4K pages like in
+    /// [`PageTable64::unmap_paddr_range`], and groups with the contiguous hint
+    /// are broken up. Read-only pages are left as is. Nothing merges the split
+    /// pages back, so the region should not be mapped again with huge pages
+    /// while logging is active.
+    ///
+    /// A write to a logged page faults, and is resolved by
+    /// [`PageTable64::handle_dirty_log_fault`]. The writes are then collected
+    /// with [`PageTable64::collect_and_rearm`], and logging ends with
+    /// [`PageTable64::disable_dirty_log`].
+    ///
+    /// If the entries store [`WpReasons::DIRTY_LOG`] (see
+    /// [`GenericPTE::WP_REASONS`]), the pages are protected by setting the
+    /// reason, and logging combines with the other reasons to write-protect
+    /// them: the copy-on-write pages and those write-protected by a
+    /// [`ProtectSession`] are logged too, and each fault clears only its own
+    /// reason. Otherwise [`MappingFlags::WRITE`] is removed from the writable
+    /// pages, and logging ends by making the region writable again with
+    /// [`PageTable64::protect_region`].
+    ///
+    /// `start` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Splitting
+    /// allocates tables, within the limit of table frames. If it fails, the
+    /// pages protected so far stay protected, and the caller must flush the
+    /// entire TLB.
+    pub fn enable_dirty_log(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        trace!(
+            "enable_dirty_log({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        self.write_protect_region(start, size, WpReasons::DIRTY_LOG, |_| {})
+    }
+
+    /// Resolves a write fault on the page containing `vaddr`, which was
+    /// write-protected by [`PageTable64::enable_dirty_log`] or
+    /// [`PageTable64::collect_and_rearm`]: makes the page writable again with
+    /// `flags`, and calls `mark` with its address, e.g. to set its bit in a
+    /// dirty bitmap.
+    ///
+    /// `flags` are the flags of the page when it is not logged. They must
+    /// contain [`MappingFlags::WRITE`], and the page must have the same flags
+    /// without it, so that a page that is read-only for another reason is
+    /// never made writable. If the page already has `flags`, the fault was
+    /// caused by a stale TLB entry (e.g. another CPU resolved it first):
+    /// `mark` is not called, and the returned flush drops the TLB entry.
+    ///
+    /// If the entries store [`WpReasons::DIRTY_LOG`], the page is logged if
+    /// it has the reason, whatever its flags, and `flags` is not used. Only
+    /// that reason is cleared: the page stays write-protected if it has
+    /// others, or if it is copy-on-write, and its next write faults again for
+    /// them.
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present, and
+    /// [`Err(PagingError::NotLogged)`](PagingError::NotLogged) if the page is
+    /// not a logged 4K page.
+    pub fn handle_dirty_log_fault(
+        &mut self,
+        vaddr: M::VirtAddr,
+        flags: MappingFlags,
+        mark: impl FnOnce(M::VirtAddr),
+    ) -> PagingResult<TlbFlush<M>> {
+        if PTE::WP_REASONS.contains(WpReasons::DIRTY_LOG) {
+            let (cleared, tlb) = self.resolve_wp_fault(vaddr, WpReasons::DIRTY_LOG, true)?;
+            if cleared {
+                mark(vaddr.align_down_4k());
+            }
+            return Ok(tlb);
+        }
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        let vaddr = vaddr.align_down_4k();
+        if size != PageSize::Size4K || entry.is_contiguous() || !flags.contains(MappingFlags::WRITE)
+        {
+            return Err(PagingError::NotLogged);
+        }
+        let current = entry.leaf_flags(false);
+        if current == flags {
+            return Ok(TlbFlush::new(vaddr).with_global(entry.is_global()));
+        }
+        if current != flags - MappingFlags::WRITE {
+            return Err(PagingError::NotLogged);
+        }
+        let old = *entry;
+        let mut new = old;
+        new.set_flags(flags, false);
+        Self::keep_ad_bits(&old, &mut new, flags, false);
+        if M::AD_POLICY != AccessedDirtyPolicy::AlwaysSet {
+            // The faulting write accesses the page right away.
+            new.set_accessed(true);
+            new.set_dirty(true);
+        }
+        let tlb = Self::update_leaf(entry, old, new, vaddr, self.needs_bbm(), false);
+        Self::note(&mut self.journal, vaddr, M::LEVELS - 1, old, *entry);
+        mark(vaddr);
+        Ok(self.stamp(tlb))
+    }
+
+    /// Clears `reason` from the write-protect reasons of the page containing
+    /// `vaddr`, for a write fault if `write` is `true`, and returns whether
+    /// it had the reason.
+    ///
+    /// A page without the reason but with write access only needs its stale
+    /// TLB entry dropped. Returns
+    /// [`Err(PagingError::NotLogged)`](PagingError::NotLogged) if it has
+    /// neither.
+    fn resolve_wp_fault(
+        &mut self,
+        vaddr: M::VirtAddr,
+        reason: WpReasons,
+        write: bool,
+    ) -> PagingResult<(bool, TlbFlush<M>)> {
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        let vaddr = vaddr.align_down_4k();
+        let old = *entry;
+        if !old.wp_reasons().contains(reason) {
+            if !old.leaf_flags(size.is_huge()).contains(MappingFlags::WRITE) {
+                return Err(PagingError::NotLogged);
+            }
+            return Ok((false, TlbFlush::new(vaddr).with_global(old.is_global())));
+        }
+        let mut new = old;
+        new.set_wp_reasons(old.wp_reasons() - reason);
+        let writable = new.leaf_flags(size.is_huge()).contains(MappingFlags::WRITE);
+        if write && writable && M::AD_POLICY != AccessedDirtyPolicy::AlwaysSet {
+            // The faulting write accesses the page right away.
+            new.set_accessed(true);
+            new.set_dirty(true);
+        }
+        let tlb = Self::update_leaf(entry, old, new, vaddr, self.needs_bbm(), size.is_huge());
+        Self::note(
+            &mut self.journal,
+            vaddr,
+            Self::leaf_level(size),
+            old,
+            *entry,
+        );
+        Ok((true, self.stamp(tlb)))
+    }
+
+    /// Write-protects again the pages of the region written since logging
+    /// started or since the last call, and calls `f` with the address of each
+    /// of them.
+    ///
+    /// Every writable page of the region is reported, or every page without
+    /// [`WpReasons::DIRTY_LOG`] that may be written if the entries store it,
+    /// so this also covers
+    /// pages made writable by other means than
+    /// [`PageTable64::handle_dirty_log_fault`] (e.g.
+    /// [`PageTable64::handle_cow_fault`] or [`PageTable64::protect`]), and
+    /// splits writable huge pages mapped in the region meanwhile. The pages
+    /// already passed to the `mark` callback of the fault handler are reported
+    /// again.
+    ///
+    /// The returned flush must be done before the contents of the reported
+    /// pages are read: until then, a CPU may still write through a stale
+    /// writable TLB entry without faulting, and the write would be lost.
+    ///
+    /// See [`PageTable64::enable_dirty_log`] for the arguments and errors.
+    pub fn collect_and_rearm(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        f: impl FnMut(M::VirtAddr),
+    ) -> PagingResult<TlbFlushAll<M>> {
+        trace!(
+            "collect_and_rearm({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        self.write_protect_region(start, size, WpReasons::DIRTY_LOG, f)
+    }
+
+    /// Stops logging the writes to the region: clears
+    /// [`WpReasons::DIRTY_LOG`] from its pages, which get write access back
+    /// unless they are write-protected for other reasons.
+    ///
+    /// Returns the number of pages changed. The returned flush is only needed
+    /// for the stale read-only TLB entries of the pages, which would
+    /// otherwise cause spurious faults.
+    ///
+    /// Entries that cannot store the reason (see [`GenericPTE::WP_REASONS`])
+    /// are left as is: logging then ends by making the region writable again
+    /// with [`PageTable64::protect_region`]. See
+    /// [`PageTable64::enable_dirty_log`] for the arguments and errors.
+    pub fn disable_dirty_log(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        trace!(
+            "disable_dirty_log({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        self.set_bits_region(start, size, false, |entry| {
+            entry.set_wp_reasons(entry.wp_reasons() - WpReasons::DIRTY_LOG)
+        })
+    }
+
+    /// Returns the reasons for which the page containing `vaddr` is
+    /// write-protected although it may be written (see
+    /// [`GenericPTE::wp_reasons`]).
+    ///
+    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
+    /// mapping is not present.
+    pub fn wp_reasons(&self, vaddr: M::VirtAddr) -> PagingResult<WpReasons> {
+        let (entry, _) = self.get_entry(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        Ok(entry.wp_reasons())
+    }
+
+    pub fn is_dirty(&self, vaddr: M::VirtAddr) -> PagingResult<bool> {
+        let (entry, _) = self.get_entry(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+
+        Ok(entry.is_dirty())
+    }
+
+    pub fn set_dirty(&mut self, vaddr: M::VirtAddr, dirty: bool) -> PagingResult<()> {
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+
+        let old = *entry;
+        let mut new = old;
+        new.set_dirty(dirty);
+        Self::write_leaf(entry, old, new);
+        Self::note(
+            &mut self.journal,
+            vaddr,
+            Self::leaf_level(size),
+            old,
+            *entry,
+        );
+        self.end_update();
+        Ok(())
+    }
+
+    pub fn is_accessed(&self, vaddr: M::VirtAddr) -> PagingResult<bool> {
+        let (entry, _) = self.get_entry(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+
+        Ok(entry.is_accessed())
+    }
+
+    pub fn set_accessed(&mut self, vaddr: M::VirtAddr, accessed: bool) -> PagingResult<()> {
+        let (entry, size) = self.get_entry_mut(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+
+        let old = *entry;
+        let mut new = old;
+        new.set_accessed(accessed);
+        Self::write_leaf(entry, old, new);
+        Self::note(
+            &mut self.journal,
+            vaddr,
+            Self::leaf_level(size),
+            old,
+            *entry,
+        );
+        self.end_update();
+        Ok(())
+    }
+
+    /// Sets or clears the dirty bit of every page mapped in the region, e.g.
+    /// to mark the target of a DMA transfer dirty before the device writes
+    /// it.
+    ///
+    /// Holes are skipped, and huge pages and groups with the contiguous hint
+    /// overlapping the region are changed whole. The other bits are kept,
+    /// including an accessed bit set by the hardware meanwhile.
+    ///
+    /// Returns the number of leaf entries changed. After clearing the bit, the
+    /// returned flush must be done before the hardware sets it again on the
+    /// next write; setting it needs no flush.
+    ///
+    /// `start` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Empty and
+    /// invalid regions are handled like in [`PageTable64::map_region`].
+    pub fn set_dirty_region(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        dirty: bool,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        trace!(
+            "set_dirty_region({:#x}): [{:#x}, {:#x}) {}",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+            dirty,
+        );
+        self.set_bits_region(start, size, dirty, |entry| entry.set_dirty(dirty))
+    }
+
+    /// Sets or clears the accessed bit of every page mapped in the region,
+    /// like [`PageTable64::set_dirty_region`].
+    pub fn set_accessed_region(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        accessed: bool,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        trace!(
+            "set_accessed_region({:#x}): [{:#x}, {:#x}) {}",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+            accessed,
+        );
+        self.set_bits_region(start, size, accessed, |entry| entry.set_accessed(accessed))
+    }
+
+    /// Returns the tag of the page mapped at `vaddr` (see
+    /// [`GenericPTE::tag`]), or
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if it is not
+    /// mapped.
+    pub fn tag(&self, vaddr: M::VirtAddr) -> PagingResult<u8> {
+        let (entry, _) = self.get_entry(vaddr)?;
+        if !entry.is_present() {
+            return Err(PagingError::NotMapped);
+        }
+        Ok(entry.tag())
+    }
+
+    /// Sets the tag of every page mapped in the region to `tag`, e.g. the
+    /// subsystem that mapped it, to attribute leaked mappings.
+    ///
+    /// Holes are skipped, and huge pages and groups with the contiguous hint
+    /// overlapping the region are tagged whole. The tags are kept when the
+    /// pages are protected, shared by [`PageTable64::clone_cow`], copied on
+    /// write, or split; pages mapped again start with tag `0`. The hardware
+    /// ignores them, so no TLB flush is needed.
+    ///
+    /// Tags are truncated to [`GenericPTE::TAG_BITS`] bits, with a warning
+    /// if bits are dropped: formats without room for tags drop them all.
+    ///
+    /// Returns the number of leaf entries changed. `start` and `size` must
+    /// be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Empty and
+    /// invalid regions are handled like in [`PageTable64::map_region`].
+    pub fn set_tag_region(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        tag: u8,
+    ) -> PagingResult<usize> {
+        trace!(
+            "set_tag_region({:#x}): [{:#x}, {:#x}) {}",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+            tag,
+        );
+        if u32::from(tag) >> PTE::TAG_BITS != 0 {
+            warn!(
+                "tag {} does not fit in the {} bits of the entries",
+                tag,
+                PTE::TAG_BITS
+            );
+        }
+        let (changed, tlb) = self.set_bits_region(start, size, true, |entry| entry.set_tag(tag))?;
+        tlb.ignore();
+        Ok(changed)
+    }
+
+    /// Returns the number of bytes mapped with each tag (see
+    /// [`PageTable64::set_tag_region`]), indexed by tag.
+    pub fn mapped_bytes_by_tag(&self) -> [usize; 16] {
+        let bytes: [Cell<usize>; 16] = core::array::from_fn(|_| Cell::new(0));
+        let _ = self.walk(
+            usize::MAX,
+            Some(&|level, _, _, entry: &PTE| {
+                if (level == M::LEVELS - 1 || entry.is_huge()) && entry.is_present() {
+                    let tag = &bytes[entry.tag() as usize];
+                    tag.set(tag.get() + Self::leaf_size(level) as usize);
+                }
+            }),
+            None,
+        );
+        bytes.map(Cell::into_inner)
+    }
+
+    /// Summarizes the pages of the region in a single walk, e.g. to decide
+    /// whether an `mprotect` has copy-on-write mappings to handle, or whether
+    /// `msync` has dirty pages to write back.
+    ///
+    /// Only the tables overlapping the region are visited, and huge pages are
+    /// accounted for at once. The flags are the ones of the leaf entries, as
+    /// with [`PageTable64::query`].
+    ///
+    /// `start` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Invalid
+    /// regions are handled like in [`PageTable64::map_region`], and an empty
+    /// one has an empty summary.
+    pub fn summarize(&self, start: M::VirtAddr, size: usize) -> PagingResult<RangeSummary> {
+        trace!(
+            "summarize({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        if size == 0 {
+            return Ok(RangeSummary::default());
+        }
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
+        }
+        let from = start.into() & Self::va_mask();
+        let mut summary = RangeSummary {
+            flags_intersection: MappingFlags::all(),
+            ..Default::default()
+        };
+        self.summarize_recursive(self.root_paddr(), 0, 0, (from, from + size), &mut summary);
+        summary.holes = size / PAGE_SIZE_4K - summary.present_pages;
+        if summary.present_pages == 0 {
+            summary.flags_intersection = MappingFlags::empty();
+        }
+        Ok(summary)
+    }
+
+    /// Fills `out` with a bitmap of the granules of `granularity` in the
+    /// region that a present leaf entry maps at least partly, e.g. for a
+    /// [`PresentBitmap`] checked by a fault handler without the lock of the
+    /// address space.
+    ///
+    /// A huge page sets the bits of all the granules it overlaps in the
+    /// region, and a granule is set even if only one 4K page of it is mapped.
+    /// If `include_absent` is `true`, the entries carrying an
+    /// [`AbsentEntry`] (e.g. reserved pages, to be mapped on the first
+    /// access) count as present, except for the guard pages of
+    /// [`PageTable64::map_alloc_with`]. The bits are laid out as for
+    /// [`PresentBitmap`], and the words of `out` after the
+    /// [`PresentBitmap::words`] of the region are left unchanged. The words
+    /// are cleared before the walk, so that a [`PresentBitmap`] reading them
+    /// meanwhile may miss pages until it returns. As with
+    /// [`PageTable64::summarize`], only the tables overlapping the region are
+    /// visited, and not the ones in granules already set.
+    ///
+    /// `start` and `size` must be aligned to `granularity`, otherwise it
+    /// returns [`Err(PagingError::NotAligned)`](PagingError::NotAligned). If
+    /// `out` is too short, it returns
+    /// [`Err(PagingError::BufferTooSmall)`](PagingError::BufferTooSmall) with
+    /// the number of words needed. Invalid regions are handled like in
+    /// [`PageTable64::map_region`].
+    pub fn export_present_bitmap(
+        &self,
+        start: M::VirtAddr,
+        size: usize,
+        granularity: PageSize,
+        include_absent: bool,
+        out: &[AtomicU64],
+    ) -> PagingResult {
+        Self::check_range(start.into(), size)?;
+        if !granularity.is_aligned(start.into() | size) {
+            return Err(PagingError::NotAligned);
+        }
+        let words = PresentBitmap::words(size, granularity);
+        let out = out
+            .get(..words)
+            .ok_or(PagingError::BufferTooSmall { needed: words })?;
+        for word in out {
+            word.store(0, Ordering::Relaxed);
+        }
+        if size == 0 {
+            return Ok(());
+        }
+        let from = start.into() & Self::va_mask();
+        let walk = PresentWalk {
+            range: (from, from + size),
+            granule: granularity as usize,
+            include_absent,
+        };
+        self.present_recursive(self.root_paddr(), 0, 0, &walk, out);
+        Ok(())
+    }
+
+    /// Locks the pages of the region in memory, e.g. for `mlock`, after
+    /// mapping the ones that are not present yet.
+    ///
+    /// Every 4K entry of the region carrying an
+    /// [`AbsentEntry`](crate::AbsentEntry) (reserved, swapped out or to be
+    /// loaded from a file) is first mapped like with [`PageTable64::map`], to
+    /// the frame and with the flags that `populate` returns for its address
+    /// and payload, e.g. a frame read back from swap. Then every page of the
+    /// region is locked (see [`GenericPTE::is_locked`]): huge pages and groups
+    /// with the contiguous hint overlapping the region are locked whole.
+    ///
+    /// Locked pages are reported apart by
+    /// [`PageTable64::estimate_working_set`], and [`PageTable64::unmap`],
+    /// [`PageTable64::unmap_region`] and [`PageTable64::unmap_region_step`]
+    /// return [`Err(PagingError::Locked)`](PagingError::Locked) for them. They
+    /// are unmapped by [`PageTable64::force_unmap_region`],
+    /// [`PageTable64::unmap_all`] and [`PageTable64::unmap_paddr_range`]. The
+    /// locks are kept when the pages are protected, but not copied by
+    /// [`PageTable64::clone_cow`]. The hardware ignores them.
+    ///
+    /// Returns the number of leaf entries locked, and the flush of the pages
+    /// mapped, only needed if [`PagingMetaData::TLB_CACHES_INVALID`] is
+    /// `true`. If the entry format cannot lock pages
+    /// ([`GenericPTE::LOCKABLE`]), they are only mapped, with a warning.
+    ///
+    /// If a page is neither mapped nor absent, it returns
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped), and if
+    /// `populate` returns [`None`],
+    /// [`Err(PagingError::NoMemory)`](PagingError::NoMemory): no page is
+    /// locked then, but the pages mapped before stay mapped, and the whole TLB
+    /// must be flushed if [`PagingMetaData::TLB_CACHES_INVALID`] is `true`.
+    /// `start` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Empty and
+    /// invalid regions are handled like in [`PageTable64::map_region`].
+    pub fn lock_region(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        populate: impl FnMut(M::VirtAddr, AbsentEntry) -> Option<(PhysAddr, MappingFlags)>,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        let result = self.batched(|pt| pt.lock_region_inner(start, size, populate));
+        Self::reported("lock_region", start.into(), result)
+    }
+
+    /// [`PageTable64::lock_region`], without reporting its errors.
+    fn lock_region_inner(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        mut populate: impl FnMut(M::VirtAddr, AbsentEntry) -> Option<(PhysAddr, MappingFlags)>,
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
+        trace!(
+            "lock_region({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        if size == 0 {
+            return Ok((0, self.empty_flush()));
+        }
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
+        }
+        if !PTE::LOCKABLE {
+            warn!("the entries have no bit to lock pages");
+        }
+        // All the pages are mapped before any is locked, so that a failure
+        // leaves nothing to unlock.
+        let mut needed = false;
+        let mut off = 0;
+        while off < size {
+            let vaddr = start.into() + off;
+            let (entry, page) = self.get_entry(vaddr.into())?;
+            if !entry.is_present() {
+                let absent = entry.absent().ok_or(PagingError::NotMapped)?;
+                let (paddr, flags) = populate(vaddr.into(), absent).ok_or(PagingError::NoMemory)?;
+                let tlb = self.map_inner(vaddr.into(), paddr, PageSize::Size4K, flags)?;
+                needed |= tlb.is_needed();
+                tlb.ignore();
+            }
+            off += page as usize - page.align_offset(vaddr);
+        }
+        let (locked, tlb) =
+            self.set_bits_region(start, size, true, |entry| entry.set_locked(true))?;
+        tlb.ignore();
+        let tlb = if needed {
+            TlbFlushAll::new()
+        } else {
+            TlbFlushAll::unneeded(0)
+        };
+        Ok((locked, tlb.with_generation(self.generation)))
+    }
+
+    /// Unlocks the pages of the region locked by [`PageTable64::lock_region`].
+    ///
+    /// Holes are skipped, and huge pages and groups with the contiguous hint
+    /// overlapping the region are unlocked whole. No TLB flush is needed.
+    ///
+    /// Returns the number of leaf entries unlocked. `start` and `size` must
+    /// be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Empty and
+    /// invalid regions are handled like in [`PageTable64::map_region`].
+    pub fn unlock_region(&mut self, start: M::VirtAddr, size: usize) -> PagingResult<usize> {
+        trace!(
+            "unlock_region({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+        );
+        let (unlocked, tlb) =
+            self.set_bits_region(start, size, true, |entry| entry.set_locked(false))?;
+        tlb.ignore();
+        Ok(unlocked)
+    }
+
+    /// Returns the number of bytes locked by [`PageTable64::lock_region`].
+    pub fn locked_bytes(&self) -> usize {
+        let bytes = Cell::new(0);
+        let _ = self.walk(
+            usize::MAX,
+            Some(&|level, _, _, entry: &PTE| {
+                if (level == M::LEVELS - 1 || entry.is_huge()) && entry.is_locked() {
+                    bytes.set(bytes.get() + Self::leaf_size(level) as usize);
+                }
+            }),
+            None,
+        );
+        bytes.get()
+    }
+
+    /// Scans the accessed bits of the pages mapped in the region to estimate
+    /// its working set, and clears them for the next scan.
+    ///
+    /// The idle counter in `idle` of each 4K page mapped is reset if the page
+    /// was accessed since the previous scan, and incremented otherwise,
+    /// saturating. The counters of the holes are left as they are. Huge pages
+    /// and groups with the contiguous hint are one page unless `split` is
+    /// set, in which case they are split into 4K pages (which needs new
+    /// tables) to be sampled one by one from the next scan on.
+    ///
+    /// The pages locked by [`PageTable64::lock_region`] are reported apart
+    /// in [`WorkingSet::locked`]: their accessed bits and idle counters are
+    /// left as they are, and they are not split.
+    ///
+    /// The returned flush must be done before the next scan: the TLB entries
+    /// of the pages cleared allow accessing them without setting the bit
+    /// again, so they would be counted idle. It is only needed if a bit was
+    /// cleared or a page split, and the result depends only on the bits in
+    /// the tables, not on the order the pages are scanned in.
+    ///
+    /// `start` and `size` must be aligned to 4K, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Empty and
+    /// invalid regions are handled like in [`PageTable64::map_region`]. If a
+    /// table cannot be allocated to split a page, it returns
+    /// [`Err(PagingError::NoMemory)`](PagingError::NoMemory) with the pages
+    /// before it scanned, so the whole TLB must be flushed before the next
+    /// scan.
+    pub fn estimate_working_set(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        split: bool,
+        idle: &mut (impl IdleBits + ?Sized),
+    ) -> PagingResult<(WorkingSet, TlbFlushAll<M>)> {
+        trace!(
+            "estimate_working_set({:#x}): [{:#x}, {:#x}) split: {}",
+            self.root_paddr(),
+            start.into(),
+            start.into().wrapping_add(size),
+            split,
+        );
+        if size == 0 {
+            return Ok((WorkingSet::default(), self.empty_flush()));
+        }
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
+        }
+        let va_mask = Self::va_mask();
+        let mut scan = Scan {
+            range: (start.into() & va_mask, (start.into() & va_mask) + size),
+            split,
+            idle,
+            set: WorkingSet::default(),
+            changed: false,
+            global: false,
+        };
+        let root = self.table_of_mut(self.root_paddr, 0);
+        let result = self.scan_recursive(root, 0, 0, &mut scan);
+        self.end_update();
+        result?;
+        if !scan.changed {
+            return Ok((scan.set, TlbFlushAll::unneeded(self.generation)));
+        }
+        self.generation += 1;
+        if scan.global {
+            self.global_generation = self.generation;
+        }
+        let tlb = TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(scan.global);
+        Ok((scan.set, tlb))
+    }
+}
+
+// Private implements.
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: CowSpace> PageTable64<M, PTE, H, K> {
+    /// Creates a new page table that shares the mappings of this one within
+    /// the given virtual memory region copy-on-write, e.g. for `fork`.
+    ///
+    /// Writable mappings in the region are made read-only and marked
+    /// [`MappingFlags::COW`] in both page tables, as given by
+    /// [`MappingFlags::cow_of`], and [`PagingHandler::frame_shared`] is called
+    /// once for each of their pages. Other mappings (read-only, or excluded by
+    /// `cow_of` like device memory) are copied as is. The copies are not
+    /// locked (see [`PageTable64::lock_region`]).
+    ///
+    /// `start` and `size` must be aligned to 4K, and the region must not cut
+    /// through a huge page, otherwise it returns
+    /// [`Err(PagingError::NotAligned)`](PagingError::NotAligned). Large
+    /// regions can be shared in steps with [`PageTable64::clone_cow_step`].
+    pub fn clone_cow(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<(Self, TlbFlushAll<M>)> {
+        let result = self.clone_cow_inner(start, size);
+        Self::reported("clone_cow", start.into(), result)
+    }
+
+    /// [`PageTable64::clone_cow`], without reporting its errors.
+    fn clone_cow_inner(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<(Self, TlbFlushAll<M>)> {
+        self.clone_cow_filtered_inner(start, size, |_, _, _| CloneAction::Cow, |_, _| None)
+    }
+
+    /// Like [`PageTable64::clone_cow`], but clones each mapping of the region
+    /// as `filter` tells, e.g. for a `posix_spawn` that only keeps a few
+    /// regions in the child.
+    ///
+    /// `filter` is called with the address, the size and the flags of each
+    /// page, and `copy` with the physical address and the size of each page
+    /// to copy with [`CloneAction::Copy`], returning the private copy like in
+    /// [`PageTable64::handle_cow_fault`]. Only the pages cloned with
+    /// [`CloneAction::Cow`] are made copy-on-write in this page table.
+    ///
+    /// The pages shared or copied lose the contiguous hint in the child; a
+    /// read-only contiguous group cloned with [`CloneAction::Cow`] keeps it,
+    /// so its pages should all get that action.
+    ///
+    /// Returns [`Err(PagingError::NoMemory)`](PagingError::NoMemory) if
+    /// `copy` returns [`None`], and the errors of [`PageTable64::clone_cow`].
+    /// On error, the references taken are dropped, and the copies made so far
+    /// are left to the caller.
+    pub fn clone_cow_filtered(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        filter: impl FnMut(M::VirtAddr, PageSize, MappingFlags) -> CloneAction,
+        copy: impl FnMut(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<(Self, TlbFlushAll<M>)> {
+        let result = self.clone_cow_filtered_inner(start, size, filter, copy);
+        Self::reported("clone_cow_filtered", start.into(), result)
+    }
+
+    /// [`PageTable64::clone_cow_filtered`], without reporting its errors.
+    fn clone_cow_filtered_inner(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        mut filter: impl FnMut(M::VirtAddr, PageSize, MappingFlags) -> CloneAction,
+        mut copy: impl FnMut(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<(Self, TlbFlushAll<M>)> {
+        let mut cursor = RegionCursor::new(start, size);
+        trace!(
+            "clone_cow({:#x}): [{:#x}, {:#x})",
+            self.root_paddr(),
+            cursor.next,
+            cursor.end,
+        );
+        self.check_cow_region(&cursor)?;
+        let mut child = Self::try_new()?;
+        child.set_limits(self.max_mapped_bytes, self.max_table_frames);
+        child.enforce_wx = self.enforce_wx;
+        if size == 0 {
+            return Ok((child, TlbFlushAll::new()));
+        }
+        let budget = usize::MAX;
+        let result = self.clone_cow_pages(&mut child, &mut cursor, budget, &mut filter, &mut copy);
+        if let Err(e) = result {
+            // Drop the references taken so far.
+            let _ = child.walk(
+                usize::MAX,
+                Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+                    let is_huge = level < M::LEVELS - 1;
+                    if (!is_huge || entry.is_huge())
+                        && entry.leaf_flags(is_huge).contains(MappingFlags::COW)
+                    {
+                        let paddr = Self::leaf_paddr(entry, vaddr.into(), is_huge);
+                        Self::frame_unshared(paddr, Self::leaf_size(level));
+                    }
+                }),
+                None,
+            );
+            return Err(e);
+        }
+        self.generation += 1;
+        Ok((child, TlbFlushAll::new().with_generation(self.generation)))
+    }
+
+    /// Shares at most `budget` pages of the region of `cursor` with `child`
+    /// like [`PageTable64::clone_cow`], and moves the cursor past them.
+    ///
+    /// `child` is usually a new page table, given to every step. On error,
+    /// the pages shared so far stay mapped in `child`, and unmapping them
+    /// drops their references. See [`PageTable64::map_region_step`].
+    pub fn clone_cow_step(
+        &mut self,
+        child: &mut Self,
+        cursor: &mut RegionCursor,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        let result = self.clone_cow_step_inner(child, cursor, budget);
+        Self::reported("clone_cow_step", cursor.next, result)
+    }
+
+    /// [`PageTable64::clone_cow_step`], without reporting its errors.
+    fn clone_cow_step_inner(
+        &mut self,
+        child: &mut Self,
+        cursor: &mut RegionCursor,
+        budget: usize,
+    ) -> PagingResult<(StepStatus, TlbFlushAll<M>)> {
+        self.check_cow_region(cursor)?;
+        let mut filter = |_, _, _| CloneAction::Cow;
+        self.clone_cow_pages(child, cursor, budget, &mut filter, &mut |_, _| None)?;
+        self.generation += 1;
+        let tlb = TlbFlushAll::new().with_generation(self.generation);
+        Ok((cursor.status(), tlb))
+    }
+
+    /// Checks that the region of `cursor` is aligned to 4K, and does not cut
+    /// through a huge page.
+    fn check_cow_region(&self, cursor: &RegionCursor) -> PagingResult {
+        for vaddr in [cursor.next, cursor.end] {
+            if !PageSize::Size4K.is_aligned(vaddr) {
+                return Err(PagingError::NotAligned);
+            }
+            if let Ok((_, _, page_size)) = self.query(vaddr.into())
+                && !page_size.is_aligned(vaddr)
+            {
+                return Err(PagingError::NotAligned);
+            }
+        }
+        Ok(())
+    }
+
+    /// Shares at most `budget` pages from `cursor` with `child`, as `filter`
+    /// tells.
+    fn clone_cow_pages(
+        &mut self,
+        child: &mut Self,
+        cursor: &mut RegionCursor,
+        mut budget: usize,
+        filter: &mut impl FnMut(M::VirtAddr, PageSize, MappingFlags) -> CloneAction,
+        copy: &mut impl FnMut(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult {
+        // Only the low bits of the addresses are used to walk the tables.
+        let va_mask = Self::va_mask();
+        let range = (
+            cursor.next & va_mask,
+            (cursor.next & va_mask) + cursor.remaining(),
+        );
+        let src = self.table_of_mut(self.root_paddr, 0);
+        let dst = child.table_of_mut(child.root_paddr, 0);
+        let journal = &mut self.journal;
+        let result =
+            child.clone_cow_recursive(src, dst, 0, 0, range, &mut budget, journal, filter, copy);
+        // The entries of this page table were made read-only.
+        self.end_update();
+        child.journal.end();
+        cursor.next = match result? {
+            Some(stop) => cursor.next + (stop - range.0),
+            None => cursor.end,
+        };
+        Ok(())
+    }
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> PageTable64<M, PTE, H, K> {
+    /// Allocates a frame that an entry can hold.
+    fn alloc_checked_frame() -> PagingResult<PhysAddr> {
+        let paddr = H::alloc_frame().ok_or(PagingError::NoMemory)?;
+        if let Err(e) = Self::check_paddr(paddr, PageSize::Size4K) {
+            H::dealloc_frame(paddr);
+            return Err(e);
+        }
+        Ok(paddr)
+    }
+
+    /// Allocates a page table frame filled with zeros.
+    fn alloc_zeroed_frame() -> PagingResult<PhysAddr> {
+        let paddr = Self::alloc_checked_frame()?;
+        let ptr = H::phys_to_virt(paddr).as_mut_ptr();
+        unsafe { core::ptr::write_bytes(ptr, 0, PAGE_SIZE_4K) };
+        Ok(paddr)
+    }
+
+    /// Allocates an empty table for `level`, from
+    /// [`PagingHandler::alloc_table`] if it is larger than a frame.
+    fn alloc_table(level: usize) -> PagingResult<PhysAddr> {
+        let bytes = Self::table_bytes(level);
+        if bytes <= PAGE_SIZE_4K {
+            return Self::alloc_zeroed_frame();
+        }
+        let paddr = H::alloc_table(bytes).ok_or(PagingError::NoMemory)?;
+        if let Err(e) = Self::check_paddr(paddr, PageSize::Size4K) {
+            H::dealloc_table(paddr, bytes);
+            return Err(e);
+        }
+        let ptr = H::phys_to_virt(paddr).as_mut_ptr();
+        unsafe { core::ptr::write_bytes(ptr, 0, bytes) };
+        Ok(paddr)
+    }
+
+    /// Frees a table allocated by [`PageTable64::alloc_table`] for `level`.
+    fn dealloc_table(paddr: PhysAddr, level: usize) {
+        let bytes = Self::table_bytes(level);
+        if bytes <= PAGE_SIZE_4K {
+            H::dealloc_frame(paddr);
+        } else {
+            H::dealloc_table(paddr, bytes);
+        }
+    }
+
+    /// Allocates a table for `level` within the limit of table frames.
+    fn alloc_table_counted(&mut self, level: usize) -> PagingResult<PhysAddr> {
+        let frames = Self::check_quota(
+            QuotaKind::TableFrames,
+            self.table_frames,
+            self.max_table_frames,
+            Self::table_frames_at(level),
+        )?;
+        let paddr = Self::alloc_table(level)?;
+        self.table_frames = frames;
+        self.journal.stats.allocated();
+        Ok(paddr)
+    }
+
+    /// Checks that the remaining region of `cursor` is in one half of the
+    /// address space, where the addresses are valid for
+    /// [`PagingMetaData::vaddr_is_valid`] and have the same bits above
+    /// [`PagingMetaData::VA_MAX_BITS`].
+    fn check_region(cursor: &RegionCursor) -> PagingResult {
+        Self::check_range(cursor.next, cursor.remaining())
+    }
+
+    /// Checks the region of `size` bytes from `start` like
+    /// [`PageTable64::check_region`].
+    fn check_range(start: usize, size: usize) -> PagingResult {
+        let Some(last) = size.checked_sub(1) else {
+            return Ok(());
+        };
+        match start.checked_add(last) {
+            Some(last)
+                if M::vaddr_is_valid(start)
+                    && M::vaddr_is_valid(last)
+                    && start >> M::VA_MAX_BITS == last >> M::VA_MAX_BITS =>
+            {
+                Ok(())
+            }
+            _ => Err(PagingError::InvalidVaddr(start)),
+        }
+    }
+
+    /// Runs the region operation `f`, whose changes are recorded together in
+    /// the journal at the end, or at the end of the region operation it is
+    /// part of.
+    fn batched<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        let batch = core::mem::replace(&mut self.journal.batch, true);
+        let result = f(self);
+        self.journal.batch = batch;
+        self.journal.end();
+        result
+    }
+
+    /// Runs `f`, which undoes the changes of a failed operation, reporting
+    /// them to the observer as rolled back.
+    fn rolling_back<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.journal.rolling_back = true;
+        let result = f(self);
+        self.journal.rolling_back = false;
+        result
+    }
+
+    /// Maps at most `budget` pages from `cursor`, choosing their size like
+    /// [`PageTable64::map_region`], or like [`PageTable64::map_region_with`]
+    /// if the flags may differ between pages. Returns the pages mapped.
+    fn map_pages(
+        &mut self,
+        cursor: &mut RegionCursor,
+        pages: MappedPages<
+            '_,
+            impl Fn(M::VirtAddr) -> PhysAddr,
+            impl FnMut(M::VirtAddr) -> MappingFlags,
+        >,
+        flush_tlb_by_page: bool,
+        budget: usize,
+    ) -> PagingResult<FlushedPages> {
+        let (get_paddr, flags_for, per_page, allow_huge) = pages;
+        Self::check_region(cursor)?;
+        if !PageSize::Size4K.is_aligned(cursor.next) || !PageSize::Size4K.is_aligned(cursor.end) {
+            return Err(PagingError::NotAligned);
+        }
+        let (start, mut step, mut pages) = (cursor.next, usize::MAX, 0);
+        self.batched(|pt| {
+            for _ in 0..budget {
+                if cursor.is_done() {
+                    break;
+                }
+                let (vaddr_usize, size) = (cursor.next, cursor.remaining());
+                let vaddr = vaddr_usize.into();
+                let paddr = get_paddr(vaddr);
+                let flags = flags_for(vaddr);
+                let mut page_size = Self::block_size(vaddr_usize, paddr, size, allow_huge);
+                if per_page && page_size != PageSize::Size4K {
+                    // Only the pages of the block with the same flags.
+                    let mut run = PAGE_SIZE_4K;
+                    while run < page_size as usize && flags_for((vaddr_usize + run).into()) == flags
+                    {
+                        run += PAGE_SIZE_4K;
+                    }
+                    page_size = Self::block_size(vaddr_usize, paddr, run, allow_huge);
+                }
+                let tlb = pt
+                    .map_inner(vaddr, paddr, page_size, flags)
+                    .inspect_err(|e| {
+                        error!(
+                            "failed to map page: {:#x?}({:?}) -> {:#x?}, {:?}",
+                            vaddr_usize, page_size, paddr, e
+                        )
+                    })?;
+                if flush_tlb_by_page {
+                    tlb.flush();
+                } else {
+                    tlb.ignore();
+                }
+                // Wraps to 0 after the last page of the address space.
+                cursor.next = cursor.next.wrapping_add(page_size as usize);
+                step = step.min(page_size as usize);
+                pages += 1;
+            }
+            Ok(FlushedPages::new(
+                start,
+                cursor.next.wrapping_sub(start),
+                step,
+                pages,
+            ))
+        })
+    }
+
+    /// Returns the largest page that maps `vaddr` to `paddr` in at most
+    /// `size` bytes, or 4K if `allow_huge` is false.
+    fn block_size(vaddr: usize, paddr: PhysAddr, size: usize, allow_huge: bool) -> PageSize {
+        if !allow_huge {
+            return PageSize::Size4K;
+        }
+        let fits = |page_size: PageSize| {
+            page_size.is_aligned(vaddr) && paddr.is_aligned(page_size) && size >= page_size as usize
+        };
+        let huge = [PageSize::Size512G, PageSize::Size1G, PageSize::Size2M];
+        match huge
+            .into_iter()
+            .find(|&page_size| Self::page_size_supported(page_size) && fits(page_size))
+        {
+            Some(page_size) => page_size,
+            None if PTE::CONTIGUOUS_HINT && fits(PageSize::Size64K) => PageSize::Size64K,
+            None => PageSize::Size4K,
+        }
+    }
+
+    /// Unmaps at most `budget` pages from `cursor`, and returns them. Locked
+    /// pages are unmapped too if `force` is `true`.
+    fn unmap_pages(
+        &mut self,
+        cursor: &mut RegionCursor,
+        flush_tlb_by_page: bool,
+        force: bool,
+        budget: usize,
+    ) -> PagingResult<FlushedPages> {
+        Self::check_region(cursor)?;
+        let (start, mut step, mut pages) = (cursor.next, usize::MAX, 0);
+        self.batched(|pt| {
+            for _ in 0..budget {
+                if cursor.is_done() {
+                    break;
+                }
+                let vaddr_usize = cursor.next;
+                let (_, page_size, tlb) =
+                    pt.unmap_inner(vaddr_usize.into(), force).inspect_err(|e| {
+                        error!("failed to unmap page: {:#x?}, {:?}", vaddr_usize, e)
+                    })?;
+                if flush_tlb_by_page {
+                    tlb.flush();
+                } else {
+                    tlb.ignore();
+                }
+
+                assert!(page_size.is_aligned(vaddr_usize));
+                assert!(page_size as usize <= cursor.remaining());
+                // Wraps to 0 after the last page of the address space.
+                cursor.next = cursor.next.wrapping_add(page_size as usize);
+                step = step.min(page_size as usize);
+                pages += 1;
+            }
+            Ok(FlushedPages::new(
+                start,
+                cursor.next.wrapping_sub(start),
+                step,
+                pages,
+            ))
+        })
+    }
+
+    /// Updates the flags of at most `budget` pages from `cursor`, and
+    /// returns them.
+    fn protect_pages(
+        &mut self,
+        cursor: &mut RegionCursor,
+        flags: MappingFlags,
+        flush_tlb_by_page: bool,
+        budget: usize,
+    ) -> PagingResult<FlushedPages> {
+        Self::check_region(cursor)?;
+        let (start, mut step, mut pages) = (cursor.next, usize::MAX, 0);
+        self.batched(|pt| {
+            for _ in 0..budget {
+                if cursor.is_done() {
+                    break;
+                }
+                let vaddr_usize = cursor.next;
+                let (page_size, tlb) =
+                    pt.protect_inner(vaddr_usize.into(), flags)
+                        .inspect_err(|e| {
+                            error!("failed to protect page: {:#x?}, {:?}", vaddr_usize, e)
+                        })?;
+                if flush_tlb_by_page {
+                    tlb.flush();
+                } else {
+                    tlb.ignore();
+                }
+
+                assert!(page_size.is_aligned(vaddr_usize));
+                assert!(page_size as usize <= cursor.remaining());
+                // Wraps to 0 after the last page of the address space.
+                cursor.next = cursor.next.wrapping_add(page_size as usize);
+                step = step.min(page_size as usize);
+                pages += 1;
+            }
+            Ok(FlushedPages::new(
+                start,
+                cursor.next.wrapping_sub(start),
+                step,
+                pages,
+            ))
+        })
+    }
+
+    /// Returns the flush of an empty region, which changed no pages.
+    pub(crate) fn empty_flush(&self) -> TlbFlushAll<M> {
+        Self::unchanged(self.generation)
+    }
+
+    /// Returns the flush of no pages at `generation`.
+    fn unchanged(generation: u64) -> TlbFlushAll<M> {
+        TlbFlushAll::unneeded(generation).with_pages(FlushedPages::new(0, 0, usize::MAX, 0))
+    }
+
+    /// Returns the flush of the `pages` changed one by one since
+    /// `generation`, each incrementing it.
+    fn region_flush(&self, generation: u64, pages: FlushedPages) -> TlbFlushAll<M> {
+        let global = self.global_generation > generation;
+        TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global)
+            .with_pages(pages)
+    }
+
+    /// Checks an operation of a [`Transaction`] on the `size` bytes at
+    /// `start`, after the `staged` ones, and saves the entries it changes in
+    /// `saved` from `used`.
+    fn stage(
+        &self,
+        staged: &[Option<StagedOp>],
+        saved: &mut [SavedEntry],
+        used: usize,
+        start: usize,
+        size: usize,
+        kind: StagedKind,
+    ) -> PagingResult<StagedOp> {
+        Self::check_range(start, size)?;
+        if !PageSize::Size4K.is_aligned(start | size) {
+            return Err(PagingError::NotAligned);
+        }
+        let map = match kind {
+            StagedKind::Unmap => false,
+            StagedKind::Protect(flags) => {
+                Self::check_space(start.into(), flags)?;
+                self.check_wx(start.into(), flags)?;
+                false
+            }
+            StagedKind::Map { paddr, flags, .. } => {
+                Self::check_space(start.into(), flags)?;
+                self.check_wx(start.into(), flags)?;
+                Self::check_paddr(paddr, PageSize::Size4K)?;
+                true
+            }
+        };
+        let last = start + (size - 1);
+        let staged = staged.iter().flatten();
+        for op in staged.clone() {
+            if op.overlaps(start, last) && !(map && matches!(op.kind, StagedKind::Unmap)) {
+                return Err(PagingError::Overlapping);
+            }
+        }
+        // Only the low bits of the addresses are used to walk the tables.
+        let low = start & Self::va_mask();
+        let mut range = (low, low + size);
+        if !map && PTE::CONTIGUOUS_HINT {
+            // The other entries of the groups lose the hint, which is
+            // written back with them.
+            let group = PageSize::Size64K as usize;
+            range = (range.0 & !(group - 1), range.1.next_multiple_of(group));
+        }
+        let mut count = used;
+        let mut save = |vaddr: usize, level: usize, entry: PTE| {
+            let slot = saved.get_mut(count).ok_or(PagingError::NoMemory)?;
+            *slot = SavedEntry {
+                vaddr,
+                level,
+                bits: Self::pte_bits(entry),
+            };
+            count += 1;
+            Ok(())
+        };
+        Self::visit_leaves(self.root_paddr, 0, 0, range, &mut |vaddr, level, entry| {
+            let entry_last = vaddr + (Self::entry_size(level) - 1);
+            if map {
+                return match staged.clone().any(|op| op.unmaps(vaddr, entry_last)) {
+                    true => Ok(()),
+                    false => Err(PagingError::AlreadyMapped),
+                };
+            }
+            if vaddr > last || entry_last < start {
+                if entry.is_contiguous() {
+                    save(vaddr, level, entry)?;
+                }
+                return Ok(());
+            }
+            if vaddr < start || entry_last > last {
+                return Err(Self::huge_page_at(vaddr, level));
+            }
+            match kind {
+                StagedKind::Unmap if entry.is_locked() => Err(PagingError::Locked),
+                StagedKind::Protect(_) if !entry.is_present() => Ok(()),
+                _ => save(vaddr, level, entry),
+            }
+        })?;
+        Ok(StagedOp {
+            kind,
+            start,
+            last,
+            saved: (used, count),
+        })
+    }
+
+    /// Applies the operations of a [`Transaction`] in order, and undoes their
+    /// changes if one fails.
+    fn commit_staged(
+        &mut self,
+        ops: &[Option<StagedOp>],
+        saved: &mut [SavedEntry],
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let mut tlb: Option<TlbFlushAll<M>> = None;
+        for (i, op) in ops.iter().flatten().enumerate() {
+            match self.apply_staged(op, saved) {
+                Ok(op_tlb) => {
+                    tlb = Some(match tlb {
+                        Some(tlb) => tlb.merge(op_tlb),
+                        None => op_tlb,
+                    })
+                }
+                Err(err) => {
+                    if let Some(tlb) = tlb {
+                        tlb.ignore();
+                    }
+                    self.rolling_back(|pt| {
+                        for op in ops[..=i].iter().flatten().rev() {
+                            pt.undo_staged(op, saved);
+                        }
+                    });
+                    self.end_update();
+                    self.flush_all();
+                    return Err(err);
+                }
+            }
+        }
+        Ok(tlb.unwrap_or_else(|| TlbFlushAll::unneeded(self.generation)))
+    }
+
+    /// Applies an operation of a [`Transaction`].
+    fn apply_staged(
+        &mut self,
+        op: &StagedOp,
+        saved: &mut [SavedEntry],
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let entries = &mut saved[op.saved.0..op.saved.1];
+        // The hardware may have set the accessed and dirty bits since.
+        for saved in entries.iter_mut() {
+            saved.bits = Self::pte_bits(self.get_entry(saved.vaddr.into())?.0);
+        }
+        let (start, size) = (op.start, op.last - op.start + 1);
+        let generation = self.generation;
+        let (mut step, mut pages) = (usize::MAX, 0);
+        let in_region = |saved: &&mut SavedEntry| op.start <= saved.vaddr && saved.vaddr <= op.last;
+        match op.kind {
+            StagedKind::Map {
+                paddr,
+                flags,
+                allow_huge,
+            } => {
+                let get_paddr = |vaddr: M::VirtAddr| paddr.add(vaddr.into() - start);
+                return self.map_region_inner(
+                    start.into(),
+                    get_paddr,
+                    size,
+                    flags,
+                    allow_huge,
+                    false,
+                );
+            }
+            StagedKind::Unmap => {
+                for saved in entries.iter_mut().filter(in_region) {
+                    match self.unmap_inner(saved.vaddr.into(), false) {
+                        Ok((_, _, tlb)) => tlb.ignore(),
+                        // The entry carried a payload, and was cleared.
+                        Err(PagingError::NotMapped) => continue,
+                        Err(err) => return Err(err),
+                    }
+                    step = step.min(Self::entry_size(saved.level));
+                    pages += 1;
+                }
+            }
+            StagedKind::Protect(flags) => {
+                for saved in entries.iter_mut().filter(in_region) {
+                    let (_, tlb) = self.protect_inner(saved.vaddr.into(), flags)?;
+                    tlb.ignore();
+                    step = step.min(Self::entry_size(saved.level));
+                    pages += 1;
+                }
+            }
+        }
+        let pages = FlushedPages::new(start, size, step, pages);
+        Ok(self.region_flush(generation, pages))
+    }
+
+    /// Undoes the changes of an operation of a [`Transaction`], applied
+    /// entirely or in part.
+    fn undo_staged(&mut self, op: &StagedOp, saved: &[SavedEntry]) {
+        if !matches!(op.kind, StagedKind::Map { .. }) {
+            for saved in saved[op.saved.0..op.saved.1].iter().rev() {
+                self.restore_entry(saved);
+            }
+            return;
+        }
+        // The region was free before, so every page in it was mapped by the
+        // operation.
+        let mut vaddr = op.start;
+        while let Some(mapping) =
+            AnyPageTable::next_mapping(self, vaddr).filter(|mapping| mapping.vaddr <= op.last)
+        {
+            let (_, size, tlb) = self.unmap_inner(mapping.vaddr.into(), false).unwrap();
+            tlb.ignore();
+            match mapping.vaddr.checked_add(size as usize) {
+                Some(next) => vaddr = next,
+                None => break,
+            }
+        }
+    }
+
+    /// Writes back an entry saved by a [`Transaction`], after freeing the
+    /// tables that replaced it if it is a huge page, which map nothing
+    /// anymore.
+    fn restore_entry(&mut self, saved: &SavedEntry) {
+        let (vaddr, level) = (saved.vaddr.into(), saved.level);
+        let size = Self::leaf_size(level);
+        let (entry, _) = self
+            .get_entry_mut_or_create(vaddr, size, MappingFlags::empty())
+            .expect("the tables above a saved entry are kept");
+        if level < M::LEVELS - 1 && entry.is_table() {
+            let (old, table) = (*entry, entry.paddr());
+            entry.clear();
+            Self::note(&mut self.journal, vaddr, level, old, *entry);
+            self.walk_cache.clear();
+            self.unlinks += 1;
+            if self.needs_bbm() {
+                M::flush_tlb(Some(vaddr));
+            }
+            let mut tables = 0;
+            Self::visit_subtree(table, level + 1, 0, &mut |paddr, level, _| {
+                Self::dealloc_table(paddr, level);
+                tables += Self::table_frames_at(level);
+            });
+            self.table_frames -= tables;
+            self.journal.stats.freed(tables);
+        }
+        let old = *entry;
+        let mut new = Self::pte_from_bits(saved.bits);
+        let is_huge = size.is_huge();
+        if old.is_present() && old.leaf_paddr(is_huge) == new.leaf_paddr(is_huge) {
+            Self::merge_hardware_bits(&Self::pte_from_bits(saved.bits), &old, &mut new);
+        }
+        Self::update_leaf(entry, old, new, vaddr, self.needs_bbm(), is_huge).ignore();
+        Self::note(&mut self.journal, vaddr, level, old, *entry);
+        if !old.is_present() && new.is_present() {
+            self.mapped_bytes += size as usize;
+            if new.leaf_flags(is_huge).contains(MappingFlags::COW) {
+                Self::frame_shared(new.leaf_paddr(is_huge), size);
+            }
+        }
+    }
+
+    /// Calls `f` with the address, level and value of each leaf entry in use
+    /// (present or carrying a payload) of the subtree of `table` that
+    /// overlaps `range`. `table` is at `level` and covers the region from
+    /// `table_vaddr`.
+    fn visit_leaves(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        f: &mut impl FnMut(usize, usize, PTE) -> PagingResult,
+    ) -> PagingResult {
+        let entry_size = Self::entry_size(level);
+        for i in 0..table_entries::<M>(level) {
+            let vaddr = table_vaddr + i * entry_size;
+            if vaddr + entry_size <= range.0 || vaddr >= range.1 {
+                continue;
+            }
+            let entry = Self::load_entry(table, i);
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                Self::visit_leaves(entry.paddr(), level + 1, vaddr, range, f)?;
+            } else {
+                f(Self::sign_extended(vaddr).into(), level, entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Maps a segment for [`PageTable64::map_elf_segments`].
+    fn map_elf_segment(&mut self, segment: &ElfSegment, extra_flags: MappingFlags) -> PagingResult {
+        let flags = segment.mapping_flags() | extra_flags;
+        let filesz = segment.filesz.min(segment.memsz);
+        trace!(
+            "map_elf_segment({:#x}): {:#x?} {:?}",
+            self.root_paddr(),
+            segment,
+            flags,
+        );
+        if segment.memsz == filesz {
+            self.map_region_bytes_inner(segment.vaddr.into(), segment.paddr, filesz, flags)?
+                .ignore();
+            return Ok(());
+        }
+        let end = segment
+            .vaddr
+            .checked_add(segment.memsz)
+            .and_then(|end| end.checked_next_multiple_of(PAGE_SIZE_4K))
+            .ok_or(PagingError::NotAligned)?;
+        let file_end = segment.vaddr + filesz;
+        // The bytes of file data in the page where it ends.
+        let partial = PageSize::Size4K.align_offset(file_end);
+        let mut vaddr = file_end - partial;
+        if filesz > 0 {
+            // The pages filled with file data only, which also checks
+            // that `vaddr` and `paddr` have the same page offset.
+            let shared = vaddr.saturating_sub(segment.vaddr);
+            self.map_region_bytes_inner(segment.vaddr.into(), segment.paddr, shared, flags)?
+                .ignore();
+            if partial > 0 {
+                let data = PhysAddr::from(segment.paddr.as_usize() + filesz - partial);
+                self.map_private_page(vaddr, flags, Some((data, partial)))?;
+                vaddr += PAGE_SIZE_4K;
+            }
+        }
+        while vaddr < end {
+            self.map_private_page(vaddr, flags, None)?;
+            vaddr += PAGE_SIZE_4K;
+        }
+        Ok(())
+    }
+
+    /// Maps the 4K page at `vaddr` to a new zeroed frame, where the `len`
+    /// bytes at the start of the frame `data` are copied first if given.
+    fn map_private_page(
+        &mut self,
+        vaddr: usize,
+        flags: MappingFlags,
+        data: Option<(PhysAddr, usize)>,
+    ) -> PagingResult {
+        let paddr = Self::alloc_checked_frame()?;
+        H::with_frame_mapped(paddr, |frame| {
+            let len = match data {
+                Some((data, len)) => {
+                    H::with_frame_mapped(data, |src| frame[..len].copy_from_slice(&src[..len]));
+                    len
+                }
+                None => 0,
+            };
+            frame[len..].fill(0);
+        });
+        match self.map_inner(vaddr.into(), paddr, PageSize::Size4K, flags) {
+            Ok(tlb) => {
+                tlb.ignore();
+                Ok(())
+            }
+            Err(e) => {
+                H::dealloc_frame(paddr);
+                Err(e)
+            }
+        }
+    }
+
+    /// Maps a page of `size` at `vaddr` to new zeroed frames, for
+    /// [`PageTable64::map_alloc`], and returns its size: 4K if the frames of
+    /// a huge page cannot be allocated.
+    fn map_new_frames(
+        &mut self,
+        vaddr: usize,
+        size: PageSize,
+        flags: MappingFlags,
+    ) -> PagingResult<PageSize> {
+        let count = size as usize / PAGE_SIZE_4K;
+        let frames = match size {
+            PageSize::Size4K => None,
+            _ => H::alloc_frames(count, size as usize),
+        };
+        let huge = match frames {
+            Some(paddr) if Self::check_paddr(paddr, size).is_ok() => Some(paddr),
+            Some(paddr) => {
+                H::dealloc_frames(paddr, count);
+                None
+            }
+            None => None,
+        };
+        let (paddr, size) = match huge {
+            Some(paddr) => (paddr, size),
+            None => (Self::alloc_checked_frame()?, PageSize::Size4K),
+        };
+        for off in (0..size as usize).step_by(PAGE_SIZE_4K) {
+            H::with_frame_mapped(paddr.add(off), |frame| frame.fill(0));
+        }
+        match self.map_inner(vaddr.into(), paddr, size, flags) {
+            Ok(tlb) => {
+                tlb.ignore();
+                Ok(size)
+            }
+            Err(e) => {
+                Self::dealloc_data(paddr, size);
+                Err(e)
+            }
+        }
+    }
+
+    /// Frees the frames of a page mapped by [`PageTable64::map_alloc`].
+    fn dealloc_data(paddr: PhysAddr, size: PageSize) {
+        match size {
+            PageSize::Size4K => H::dealloc_frame(paddr),
+            _ => H::dealloc_frames(paddr, size as usize / PAGE_SIZE_4K),
+        }
+    }
+
+    /// Returns the page number naming the allocation at `start` in its guard
+    /// pages.
+    fn guard_owner(start: usize) -> u64 {
+        ((start & Self::va_mask()) >> 12) as u64
+    }
+
+    /// Returns the error for a guard page of the allocation `owner`.
+    fn guarded(owner: u64) -> PagingError {
+        PagingError::Guarded(Self::sign_extended((owner as usize) << 12).into())
+    }
+
+    /// Checks that the `count` guard pages from `start` are unused, for
+    /// [`PageTable64::map_alloc_with`].
+    fn check_guards(&self, start: usize, count: usize) -> PagingResult {
+        for i in 0..count {
+            match self.get_entry((start + i * PAGE_SIZE_4K).into()) {
+                Ok((entry, _)) if !entry.is_unused() => {
+                    return Err(match entry.absent() {
+                        Some(AbsentEntry::Guard(owner)) => Self::guarded(owner),
+                        _ => PagingError::AlreadyMapped,
+                    });
+                }
+                Ok(_) | Err(PagingError::NotMapped) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the `count` pages from `start` as guard pages of the allocation
+    /// `owner`.
+    fn set_guards(&mut self, start: usize, count: usize, owner: u64) -> PagingResult {
+        for i in 0..count {
+            let vaddr = (start + i * PAGE_SIZE_4K).into();
+            let (entry, _) =
+                self.get_entry_mut_or_create(vaddr, PageSize::Size4K, MappingFlags::empty())?;
+            let old = *entry;
+            entry.set_payload(NonPresentPayload::Guard(owner));
+            Self::note(&mut self.journal, vaddr, M::LEVELS - 1, old, *entry);
+        }
+        Ok(())
+    }
+
+    /// Clears the guard pages of the allocation at `start` before it, and
+    /// those after the `size` bytes from `start`, once they are unmapped.
+    fn clear_guards(&mut self, start: usize, size: usize) {
+        let owner = Self::guard_owner(start);
+        let mut vaddr = start;
+        while vaddr >= PAGE_SIZE_4K && self.clear_guard(vaddr - PAGE_SIZE_4K, owner) {
+            vaddr -= PAGE_SIZE_4K;
+        }
+        let mut vaddr = start.wrapping_add(size);
+        while vaddr != 0 && self.clear_guard(vaddr, owner) {
+            vaddr = vaddr.wrapping_add(PAGE_SIZE_4K);
+        }
+    }
+
+    /// Clears the entry of `vaddr` if it is a guard page of the allocation
+    /// `owner`, and returns whether it was.
+    fn clear_guard(&mut self, vaddr: usize, owner: u64) -> bool {
+        if Self::check_range(vaddr, PAGE_SIZE_4K).is_err() {
+            return false;
+        }
+        let Ok((entry, _)) = self.get_entry_mut(vaddr.into()) else {
+            return false;
+        };
+        if entry.absent() != Some(AbsentEntry::Guard(owner)) {
+            return false;
+        }
+        let old = *entry;
+        entry.clear();
+        Self::note(
+            &mut self.journal,
+            M::VirtAddr::from(vaddr),
+            M::LEVELS - 1,
+            old,
+            *entry,
+        );
+        true
+    }
+
+    /// Unmaps the pages mapped by [`PageTable64::map_alloc`] in the region,
+    /// flushing them before freeing their frames.
+    fn unmap_allocated(&mut self, start: usize, size: usize) {
+        let mut vaddr = start;
+        while vaddr < start + size {
+            let (paddr, page, tlb) = self.unmap_inner(vaddr.into(), false).unwrap();
+            tlb.flush();
+            Self::dealloc_data(paddr, page);
+            vaddr += page as usize;
+        }
+    }
+
+    /// Creates a leaf entry with [`GenericPTE::new_page`], whose accessed and
+    /// dirty bits are cleared unless they are always set (see
+    /// [`PagingMetaData::AD_POLICY`]).
+    fn new_leaf(paddr: PhysAddr, flags: MappingFlags, is_huge: bool) -> PTE {
+        let mut pte = PTE::new_page(paddr, flags, is_huge);
+        if M::AD_POLICY != AccessedDirtyPolicy::AlwaysSet {
+            pte.set_accessed(false);
+            pte.set_dirty(false);
+        }
+        pte
+    }
+
+    /// Sets the accessed and dirty bits of `new`, whose flags were just set to
+    /// `flags`, unless they are always set: the bits of `old` are kept if it
+    /// maps the same frame, and the dirty bit only if the page stays
+    /// writable. Both are leaf entries, of a huge page if `is_huge` is set.
+    fn keep_ad_bits(old: &PTE, new: &mut PTE, flags: MappingFlags, is_huge: bool) {
+        if M::AD_POLICY == AccessedDirtyPolicy::AlwaysSet {
+            return;
+        }
+        let same_frame = old.is_present() && old.leaf_paddr(is_huge) == new.leaf_paddr(is_huge);
+        new.set_accessed(same_frame && old.is_accessed());
+        // The new entry may withhold write access for its write-protect
+        // reasons.
+        let writable = flags.contains(MappingFlags::WRITE)
+            && new.leaf_flags(is_huge).contains(MappingFlags::WRITE);
+        new.set_dirty(same_frame && old.is_dirty() && writable);
+    }
+
+    /// Checks that an entry can hold `paddr` as the start of a page of `size`,
+    /// instead of truncating it, and that the whole page is valid for
+    /// [`PagingMetaData::paddr_is_valid`].
+    fn check_paddr(paddr: PhysAddr, size: PageSize) -> PagingResult {
+        let start = paddr.as_usize();
+        let valid = |paddr| paddr <= M::PA_MAX_ADDR && M::paddr_is_valid(paddr);
+        let last = start.saturating_add(size as usize - 1);
+        if !valid(start) || !valid(last) || !paddr.is_aligned(size) {
+            return Err(PagingError::InvalidPaddr(paddr));
+        }
+        Ok(())
+    }
+
+    /// Checks that a mapping of the page of `size` at `paddr` with `flags` has
+    /// the memory type required by [`PagingHandler::memory_type_of`].
+    ///
+    /// Empty flags map nothing, so they are not checked.
+    fn check_memory_type(paddr: PhysAddr, size: PageSize, flags: MappingFlags) -> PagingResult {
+        match H::memory_type_of(paddr, size) {
+            Some(ty) if !flags.is_empty() && ty != MemoryType::of(flags) => {
+                Err(PagingError::AttributeConflict(paddr))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that the kind of address space accepts a mapping at `vaddr`
+    /// with `flags`.
+    fn check_space(vaddr: M::VirtAddr, flags: MappingFlags) -> PagingResult {
+        K::check_mapping::<M>(vaddr.into(), flags)
+    }
+
+    /// Checks that a mapping at `vaddr` with `flags` is not both writable
+    /// and executable, if W^X is enforced.
+    fn check_wx(&self, vaddr: M::VirtAddr, flags: MappingFlags) -> PagingResult {
+        let writable = flags.intersects(MappingFlags::WRITE | MappingFlags::COW);
+        if self.enforce_wx && writable && flags.contains(MappingFlags::EXECUTE) {
+            return Err(PagingError::WxViolation(vaddr.into()));
+        }
+        Ok(())
+    }
+
+    /// Reports the error of the operation `op` at `vaddr`, if any, to
+    /// [`PagingHandler::on_error`].
+    fn reported<T>(op: &'static str, vaddr: usize, result: PagingResult<T>) -> PagingResult<T> {
+        if let Err(err) = &result {
+            H::on_error(op, vaddr, err);
+        }
+        result
+    }
+
+    /// Returns the usage of `kind` after adding `amount` to `used`, or an
+    /// error if it exceeds `limit`.
+    fn check_quota(
+        kind: QuotaKind,
+        used: usize,
+        limit: usize,
+        amount: usize,
+    ) -> PagingResult<usize> {
+        let requested = used.saturating_add(amount);
+        if requested > limit {
+            return Err(PagingError::QuotaExceeded {
+                kind,
+                limit,
+                requested,
+            });
+        }
+        Ok(requested)
+    }
+
+    /// Returns the mapped bytes after replacing the leaf `old` of `size` with
+    /// `new`, if within `limit`.
+    fn mapped_after(
+        old: &PTE,
+        new: &PTE,
+        size: PageSize,
+        used: usize,
+        limit: usize,
+    ) -> PagingResult<usize> {
+        match (Self::holds_frame(old), Self::holds_frame(new)) {
+            (false, true) => Self::check_quota(QuotaKind::MappedBytes, used, limit, size as usize),
+            (true, false) => Ok(used.saturating_sub(size as usize)),
+            _ => Ok(used),
+        }
+    }
+
+    /// Maps `target` at `vaddr` with a group of 4K entries that have the
+    /// contiguous hint, like [`PageTable64::map`] with [`PageSize::Size64K`].
+    fn map_contiguous(
+        &mut self,
+        vaddr: M::VirtAddr,
+        target: PhysAddr,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlush<M>> {
+        if !PTE::CONTIGUOUS_HINT {
+            return Err(PagingError::UnsupportedPageSize);
+        }
+        let mapped = Self::check_quota(
+            QuotaKind::MappedBytes,
+            self.mapped_bytes,
+            self.max_mapped_bytes,
+            PageSize::Size64K as usize,
+        )?;
+        let vaddr = vaddr.align_down(PageSize::Size64K);
+        let (entry, widened) = self.get_entry_mut_or_create(vaddr, PageSize::Size4K, flags)?;
+        let group = Self::contiguous_group(entry, vaddr);
+        let err = group.iter().find_map(|entry| match entry.absent() {
+            _ if entry.is_unused() => None,
+            None | Some(AbsentEntry::Inaccessible(_)) => Some(PagingError::AlreadyMapped),
+            Some(AbsentEntry::Guard(owner)) => Some(Self::guarded(owner)),
+            Some(_) => None,
+        });
+        if let Some(err) = err {
+            self.journal.end();
+            return Err(err);
+        }
+        let level = M::LEVELS - 1;
+        for (i, entry) in group.iter_mut().enumerate() {
+            let mut new = Self::new_leaf(target.add(i * PAGE_SIZE_4K), flags, false);
+            new.set_contiguous(true);
+            let old = core::mem::replace(entry, new);
+            let page = vaddr.into() + i * PAGE_SIZE_4K;
+            Self::observe(&mut self.journal, page, level, old, new, false);
+        }
+        self.mapped_bytes = mapped;
+        Self::note_range(&mut self.journal, vaddr, level, CONTIGUOUS_ENTRIES);
+        let tlb = if widened {
+            TlbFlush::new(vaddr)
+        } else {
+            TlbFlush::new_mapping(vaddr)
+        };
+        Ok(self.stamp(tlb.with_pages(CONTIGUOUS_ENTRIES)))
+    }
+
+    /// Returns the group of entries with the contiguous hint that contains the
+    /// 4K leaf `entry` of `vaddr`.
+    fn contiguous_group<'a>(entry: &mut PTE, vaddr: M::VirtAddr) -> &'a mut [PTE] {
+        let index = Self::index_of(vaddr.into(), M::LEVELS - 1) % CONTIGUOUS_ENTRIES;
+        // The group is in the same table as `entry`.
+        let first = unsafe { (entry as *mut PTE).sub(index) };
+        unsafe { core::slice::from_raw_parts_mut(first, CONTIGUOUS_ENTRIES) }
+    }
+
+    /// Returns the frame mapped by the leaf `entry` of `vaddr`, of a huge page
+    /// if `is_huge` is set, which may be in a group with the contiguous hint.
+    fn leaf_paddr(entry: &PTE, vaddr: usize, is_huge: bool) -> PhysAddr {
+        if entry.is_contiguous() {
+            let off = PageSize::Size64K.align_offset(vaddr);
+            entry
+                .leaf_paddr(false)
+                .align_down(PageSize::Size64K)
+                .add(off)
+        } else {
+            entry
+                .inaccessible()
+                .unwrap_or_else(|| entry.leaf_paddr(is_huge))
+        }
+    }
+
+    /// Whether the leaf `entry` maps a frame, which it keeps when it is made
+    /// inaccessible without being present.
+    fn holds_frame(entry: &PTE) -> bool {
+        entry.is_present() || entry.inaccessible().is_some()
+    }
+
+    /// Clears the contiguous hint of the group containing the 4K leaf `entry`
+    /// of `vaddr`, before one of its entries is changed, and records it in
+    /// `journal`.
+    ///
+    /// If `bbm` is set (see [`PageTable64::needs_bbm`]), the whole group is
+    /// invalidated and the TLB flushed for all its pages first. Otherwise, the
+    /// caller's flush of `vaddr` also drops the TLB entry of the group.
+    fn break_contiguous(journal: &mut Journal, entry: &mut PTE, vaddr: M::VirtAddr, bbm: bool) {
+        let base = vaddr.align_down(PageSize::Size64K);
+        Self::note_range(journal, base, M::LEVELS - 1, CONTIGUOUS_ENTRIES);
+        let group = Self::contiguous_group(entry, vaddr);
+        let mut old: [PTE; CONTIGUOUS_ENTRIES] = core::array::from_fn(|i| group[i]);
+        if bbm {
+            for (entry, old) in group.iter_mut().zip(&mut old) {
+                let mut invalid = *entry;
+                invalid.clear();
+                // Keep the bits set by the hardware until the entry is invalid.
+                *old = Self::swap_leaf(entry, invalid);
+            }
+            for i in 0..CONTIGUOUS_ENTRIES {
+                M::flush_tlb(Some(base.add(i * PAGE_SIZE_4K)));
+            }
+        }
+        let paddr = old[0].leaf_paddr(false).align_down(PageSize::Size64K);
+        for (i, (entry, old)) in group.iter_mut().zip(old).enumerate() {
+            let mut new = old;
+            new.set_contiguous(false);
+            new.set_leaf_paddr(paddr.add(i * PAGE_SIZE_4K), false);
+            if bbm {
+                unsafe { core::ptr::write_volatile(entry, new) };
+            } else {
+                Self::write_leaf(entry, old, new);
+            }
+        }
+    }
+
+    /// Replaces the leaf `entry` of `vaddr`, read as `old`, with `new`,
+    /// following break-before-make if `bbm` is set. The entries map a huge
+    /// page if `is_huge` is set.
+    ///
+    /// If the frame is the same, the accessed and dirty bits that the hardware
+    /// set since `old` was read are kept.
+    fn update_leaf(
+        entry: &mut PTE,
+        old: PTE,
+        mut new: PTE,
+        vaddr: M::VirtAddr,
+        bbm: bool,
+        is_huge: bool,
+    ) -> TlbFlush<M> {
+        let same_frame = old.leaf_paddr(is_huge) == new.leaf_paddr(is_huge);
+        let needs_break = bbm && old.is_present() && {
+            let memory_type = MappingFlags::DEVICE | MappingFlags::UNCACHED;
+            let (old_flags, new_flags) = (old.leaf_flags(is_huge), new.leaf_flags(is_huge));
+            !same_frame || old_flags & memory_type != new_flags & memory_type
+        };
+        if !needs_break {
+            if same_frame {
+                Self::write_leaf(entry, old, new);
+            } else {
+                Self::swap_leaf(entry, new);
+            }
+            return TlbFlush::new(vaddr).with_global(old.is_global());
+        }
+        let mut invalid = old;
+        invalid.clear();
+        // The invalid entry must be observable by the page table walker before
+        // the TLB is flushed, and the flush must complete before the new entry
+        // is written.
+        let last = Self::swap_leaf(entry, invalid);
+        if same_frame {
+            Self::merge_hardware_bits(&old, &last, &mut new);
+        }
+        M::flush_tlb(Some(vaddr));
+        unsafe { core::ptr::write_volatile(entry, new) };
+        TlbFlush::new_mapping(vaddr)
+    }
+
+    /// Writes `new` to the leaf `entry`, which was read as `old`.
+    ///
+    /// The hardware may set the accessed and dirty bits of a valid entry at
+    /// any time, so this is a compare-and-swap, retried after merging the bits
+    /// set since `old` was read into `new`.
+    fn write_leaf(entry: &mut PTE, mut old: PTE, mut new: PTE) {
+        let bits = Self::atomic_entry(entry);
+        loop {
+            #[cfg(feature = "mock")]
+            crate::mock::race_leaf_write(bits);
+            let result = bits.compare_exchange(
+                Self::pte_bits(old),
+                Self::pte_bits(new),
+                Ordering::Release,
+                Ordering::Relaxed,
+            );
+            match result {
+                Ok(_) => return,
+                Err(current) => {
+                    let current = Self::pte_from_bits(current);
+                    Self::merge_hardware_bits(&old, &current, &mut new);
+                    old = current;
+                }
+            }
+        }
+    }
+
+    /// Writes `new` to the leaf `entry`, and returns the last value of the
+    /// entry, including the bits set by the hardware.
+    fn swap_leaf(entry: &mut PTE, new: PTE) -> PTE {
+        let bits = Self::atomic_entry(entry);
+        #[cfg(feature = "mock")]
+        crate::mock::race_leaf_write(bits);
+        Self::pte_from_bits(bits.swap(Self::pte_bits(new), Ordering::Release))
+    }
+
+    /// Sets the accessed and dirty bits of `new` that are set in `current`
+    /// but not in `old`.
+    fn merge_hardware_bits(old: &PTE, current: &PTE, new: &mut PTE) {
+        if current.is_dirty() && !old.is_dirty() {
+            new.set_dirty(true);
+        }
+        if current.is_accessed() && !old.is_accessed() {
+            new.set_accessed(true);
+        }
+    }
+
+    fn atomic_entry(entry: &mut PTE) -> &AtomicU64 {
+        const { assert!(core::mem::size_of::<PTE>() == core::mem::size_of::<u64>()) };
+        unsafe { &*(entry as *mut PTE as *const AtomicU64) }
+    }
+
+    fn pte_bits(pte: PTE) -> u64 {
+        unsafe { core::mem::transmute_copy(&pte) }
+    }
+
+    fn pte_from_bits(bits: u64) -> PTE {
+        const { assert!(core::mem::size_of::<PTE>() == core::mem::size_of::<u64>()) };
+        unsafe { core::mem::transmute_copy(&bits) }
+    }
+
+    /// Records the pending changes in the journal and fences them, at the end
+    /// of an operation.
+    fn end_update(&mut self) {
+        self.journal.end();
+        M::fence_after_update();
+    }
+
+    /// Returns the size of the memory mapped through an entry at `level`.
+    const fn entry_size(level: usize) -> usize {
+        1 << level_shift::<M>(level)
+    }
+
+    /// Returns the offset of `vaddr` in the region covered by the root table,
+    /// and the end of the half of the address space containing it.
+    fn half_of(vaddr: usize) -> (usize, usize) {
+        let span = 1usize << M::VA_MAX_BITS;
+        let from = vaddr & (span - 1);
+        if from < span / 2 && !M::vaddr_is_valid(span / 2) {
+            (from, span / 2)
+        } else {
+            (from, span)
+        }
+    }
+
+    /// Returns the mask of the bits of the addresses translated by the page
+    /// table.
+    const fn va_mask() -> usize {
+        (1 << (level_shift::<M>(0) + M::INDEX_BITS[0] as usize)) - 1
+    }
+
+    /// Returns the size in bytes of a table at `level`.
+    const fn table_bytes(level: usize) -> usize {
+        table_entries::<M>(level) * core::mem::size_of::<PTE>()
+    }
+
+    /// Returns the number of frames accounted for a table at `level`.
+    const fn table_frames_at(level: usize) -> usize {
+        Self::table_bytes(level).div_ceil(PAGE_SIZE_4K)
+    }
+
+    /// Returns the level of the leaf entries of pages of `size`.
+    const fn leaf_level(size: PageSize) -> usize {
+        match size {
+            PageSize::Size4K | PageSize::Size64K => M::LEVELS - 1,
+            PageSize::Size2M => M::LEVELS - 2,
+            PageSize::Size1G => M::LEVELS - 3,
+            PageSize::Size512G => M::LEVELS - 4,
+        }
+    }
+
+    /// Records in `journal` the change of the entry of `vaddr` at `level`
+    /// from `old` to `new`, if any.
+    fn note(journal: &mut Journal, vaddr: impl Into<usize>, level: usize, old: PTE, new: PTE) {
+        let bits = (Self::pte_bits(old), Self::pte_bits(new));
+        if bits.0 != bits.1 {
+            let size = Self::entry_size(level);
+            let vaddr = vaddr.into() & !(size - 1);
+            journal.add(vaddr, level, size, 1, Some(bits));
+            Self::observe(journal, vaddr, level, old, new, false);
+        }
+    }
+
+    /// Reports the change of the entry of `vaddr` at `level` from `old` to
+    /// `new` to the observer of `journal`, if any, as a change of the child
+    /// page table of [`PageTable64::clone_cow`] if `child` is set.
+    ///
+    /// Only the changes of the leaves are reported: a huge page split into a
+    /// table of pages mapping the same frames is not a change of the mappings.
+    #[inline]
+    fn observe(journal: &mut Journal, vaddr: usize, level: usize, old: PTE, new: PTE, child: bool) {
+        if journal.observer.is_none() {
+            return;
+        }
+        let leaf =
+            |entry: &PTE| Self::holds_frame(entry) && (level == M::LEVELS - 1 || entry.is_huge());
+        let table = |entry: &PTE| level < M::LEVELS - 1 && entry.is_table();
+        if table(&old) || table(&new) {
+            return;
+        }
+        let size = Self::entry_size(level);
+        let page_size = |entry: &PTE| {
+            if entry.is_contiguous() {
+                PageSize::Size64K
+            } else {
+                Self::leaf_size(level)
+            }
+        };
+        let is_huge = level < M::LEVELS - 1;
+        let (old_flags, new_flags) = (old.leaf_flags(is_huge), new.leaf_flags(is_huge));
+        let mapped = ObservedKind::Mapped(new_flags, page_size(&new));
+        match (leaf(&old), leaf(&new)) {
+            (false, false) => {}
+            (false, true) => journal.observe(mapped, vaddr, size, child),
+            (true, false) => journal.observe(ObservedKind::Unmapped, vaddr, size, child),
+            (true, true) => {
+                let paddr = |entry: &PTE| Self::leaf_paddr(entry, vaddr, is_huge);
+                if paddr(&old) != paddr(&new) {
+                    journal.observe(ObservedKind::Unmapped, vaddr, size, child);
+                    journal.observe(mapped, vaddr, size, child);
+                } else if old_flags != new_flags {
+                    let protected = ObservedKind::Protected(old_flags, new_flags);
+                    journal.observe(protected, vaddr, size, child);
+                }
+            }
+        }
     }
 
-    /// Returns the physical address of the root page table.
-    pub const fn root_paddr(&self) -> PhysAddr {
-        self.root_paddr
+    /// Records in `journal` the change of the `count` entries at `level` from
+    /// the one of `vaddr`.
+    fn note_range(journal: &mut Journal, vaddr: impl Into<usize>, level: usize, count: usize) {
+        let size = Self::entry_size(level);
+        journal.add(vaddr.into() & !(size - 1), level, size, count, None);
     }
 
-    /// Maps a virtual page to a physical frame with the given `page_size`
-    /// and mapping `flags`.
-    ///
-    /// The virtual page starts with `vaddr`, amd the physical frame starts with
-    /// `target`. If the addresses is not aligned to the page size, they will be
-    /// aligned down automatically.
+    /// Stamps `tlb` with the generation, after incrementing it if the flush is
+    /// needed.
     ///
-    /// Returns [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped)
-    /// if the mapping is already present.
-    pub fn map(
-        &mut self,
-        vaddr: M::VirtAddr,
-        target: PhysAddr,
-        page_size: PageSize,
-        flags: MappingFlags,
-    ) -> PagingResult<TlbFlush<M>> {
-        let entry = self.get_entry_mut_or_create(vaddr, page_size)?;
-        if !entry.is_unused() {
-            return Err(PagingError::AlreadyMapped);
+    /// It ends every operation on a single page, so it also fences the
+    /// changes (see [`PagingMetaData::fence_after_update`]).
+    fn stamp(&mut self, tlb: TlbFlush<M>) -> TlbFlush<M> {
+        self.end_update();
+        if tlb.is_needed() {
+            self.generation += 1;
         }
-        *entry = GenericPTE::new_page(target.align_down(page_size), flags, page_size.is_huge());
-        Ok(TlbFlush::new(vaddr))
+        if tlb.is_global() {
+            self.global_generation = self.generation;
+        }
+        tlb.with_generation(self.generation)
     }
 
-    /// Remap the mapping starts with `vaddr`, updates both the physical address
-    /// and flags.
-    ///
-    /// Returns the page size of the mapping.
-    ///
-    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
-    /// intermediate level tables of the mapping is not present.
-    pub fn remap(
-        &mut self,
-        vaddr: M::VirtAddr,
-        paddr: PhysAddr,
-        flags: MappingFlags,
-    ) -> PagingResult<(PageSize, TlbFlush<M>)> {
-        let (entry, size) = self.get_entry_mut(vaddr)?;
-        entry.set_paddr(paddr);
-        entry.set_flags(flags, size.is_huge());
-        Ok((size, TlbFlush::new(vaddr)))
+    /// Calls [`PagingHandler::frame_shared`], unless `paddr` is the zero frame.
+    fn frame_shared(paddr: PhysAddr, size: PageSize) {
+        if H::zero_frame() != Some(paddr) {
+            H::frame_shared(paddr, size);
+        }
     }
 
-    /// Updates the flags of the mapping starts with `vaddr`.
-    ///
-    /// Returns the page size of the mapping.
-    ///
-    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
-    /// mapping is not present.
-    pub fn protect(
-        &mut self,
-        vaddr: M::VirtAddr,
-        flags: MappingFlags,
-    ) -> PagingResult<(PageSize, TlbFlush<M>)> {
-        let (entry, size) = self.get_entry_mut(vaddr)?;
-        if !entry.is_present() {
-            return Err(PagingError::NotMapped);
+    /// Calls [`PagingHandler::frame_unshared`], unless `paddr` is the zero
+    /// frame.
+    fn frame_unshared(paddr: PhysAddr, size: PageSize) {
+        if H::zero_frame() != Some(paddr) {
+            H::frame_unshared(paddr, size);
         }
-        entry.set_flags(flags, size.is_huge());
-        Ok((size, TlbFlush::new(vaddr)))
     }
 
-    /// Unmaps the mapping starts with `vaddr`.
-    ///
-    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
-    /// mapping is not present.
-    pub fn unmap(&mut self, vaddr: M::VirtAddr) -> PagingResult<(PhysAddr, PageSize, TlbFlush<M>)> {
-        let (entry, size) = self.get_entry_mut(vaddr)?;
-        if !entry.is_present() {
-            entry.clear();
-            return Err(PagingError::NotMapped);
+    /// The size of the page mapped by a leaf entry at `level`.
+    fn leaf_size(level: usize) -> PageSize {
+        match M::LEVELS - 1 - level {
+            0 => PageSize::Size4K,
+            1 => PageSize::Size2M,
+            2 => PageSize::Size1G,
+            _ => PageSize::Size512G,
         }
-        let paddr = entry.paddr();
-        entry.clear();
-        Ok((paddr, size, TlbFlush::new(vaddr)))
     }
 
-    /// Queries the result of the mapping starts with `vaddr`.
+    /// Whether leaf entries can map `page_size` pages (see
+    /// [`PagingMetaData::MAX_PAGE_SIZE`]).
     ///
-    /// Returns the physical address of the target frame, mapping flags, and
-    /// the page size.
-    ///
-    /// Returns [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if the
-    /// mapping is not present.
-    pub fn query(&self, vaddr: M::VirtAddr) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
-        let (entry, size) = self.get_entry(vaddr)?;
-        if !entry.is_present() {
-            return Err(PagingError::NotMapped);
+    /// Huge pages also need the tables below their level to have 512 entries
+    /// (see [`PagingMetaData::INDEX_BITS`]), so that they map `page_size` and
+    /// can be split into pages of the next smaller size.
+    const fn page_size_supported(page_size: PageSize) -> bool {
+        let above_leaf = match page_size {
+            PageSize::Size4K | PageSize::Size64K => return true,
+            PageSize::Size2M => 1,
+            PageSize::Size1G => 2,
+            PageSize::Size512G => 3,
+        };
+        if page_size as usize > M::MAX_PAGE_SIZE as usize || above_leaf >= M::LEVELS {
+            return false;
         }
-        let off = size.align_offset(vaddr.into());
-        Ok((entry.paddr().add(off), entry.flags(), size))
+        let mut level = M::LEVELS - above_leaf;
+        while level < M::LEVELS {
+            if M::INDEX_BITS[level] != 9 {
+                return false;
+            }
+            level += 1;
+        }
+        true
     }
 
-    /// Maps a contiguous virtual memory region to a contiguous physical memory
-    /// region with the given mapping `flags`.
-    ///
-    /// The virtual and physical memory regions start with `vaddr` and `paddr`
-    /// respectively. The region size is `size`. The addresses and `size` must
-    /// be aligned to 4K, otherwise it will return [`Err(PagingError::NotAligned)`].
+    /// Shares the leaves of `src` that fall into `range` with `dst`, which
+    /// are tables of the same `level` covering the region from `table_vaddr`.
     ///
-    /// When `allow_huge` is true, it will try to map the region with huge pages
-    /// if possible. Otherwise, it will map the region with 4K pages.
+    /// `self` is the page table of `dst`, whose usage is accounted, and
+    /// `journal` the one of the page table of `src`.
     ///
-    /// When `flush_tlb_by_page` is true, it will flush the TLB immediately after
-    /// mapping each page. Otherwise, the TLB flush should by handled by the caller.
+    /// Each leaf is cloned as `filter` tells, and `copy` gives the pages of
+    /// [`CloneAction::Copy`] (see [`PageTable64::clone_cow_filtered`]).
     ///
-    /// [`Err(PagingError::NotAligned)`]: PagingError::NotAligned
-    pub fn map_region(
+    /// At most `budget` leaves are shared. If more remain, returns the
+    /// address of the first one.
+    #[allow(clippy::too_many_arguments)]
+    fn clone_cow_recursive(
         &mut self,
-        vaddr: M::VirtAddr,
-        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
-        size: usize,
-        flags: MappingFlags,
-        allow_huge: bool,
-        flush_tlb_by_page: bool,
-    ) -> PagingResult<TlbFlushAll<M>> {
-        let mut vaddr_usize: usize = vaddr.into();
-        let mut size = size;
-        if !PageSize::Size4K.is_aligned(vaddr_usize) || !PageSize::Size4K.is_aligned(size) {
-            return Err(PagingError::NotAligned);
-        }
-        trace!(
-            "map_region({:#x}): [{:#x}, {:#x}) {:?}",
-            self.root_paddr(),
-            vaddr_usize,
-            vaddr_usize + size,
-            flags,
-        );
-        while size > 0 {
-            let vaddr = vaddr_usize.into();
-            let paddr = get_paddr(vaddr);
-            let page_size = if allow_huge {
-                if PageSize::Size1G.is_aligned(vaddr_usize)
-                    && paddr.is_aligned(PageSize::Size1G)
-                    && size >= PageSize::Size1G as usize
-                {
-                    PageSize::Size1G
-                } else if PageSize::Size2M.is_aligned(vaddr_usize)
-                    && paddr.is_aligned(PageSize::Size2M)
-                    && size >= PageSize::Size2M as usize
-                {
-                    PageSize::Size2M
-                } else {
-                    PageSize::Size4K
+        src: &mut [PTE],
+        dst: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        budget: &mut usize,
+        journal: &mut Journal,
+        filter: &mut impl FnMut(M::VirtAddr, PageSize, MappingFlags) -> CloneAction,
+        copy: &mut impl FnMut(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<Option<usize>> {
+        let entry_size = Self::entry_size(level);
+        for (i, (entry, dst_entry)) in src.iter_mut().zip(dst.iter_mut()).enumerate() {
+            let vaddr = table_vaddr + i * entry_size;
+            if vaddr + entry_size <= range.0 || vaddr >= range.1 {
+                continue;
+            }
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let src_next = Self::table_of_paddr(entry.paddr(), level + 1);
+                if dst_entry.is_unused() {
+                    // Keep the permissions of the source table entry.
+                    let paddr = self.alloc_table_counted(level + 1)?;
+                    let old = *dst_entry;
+                    *dst_entry = *entry;
+                    dst_entry.set_paddr(paddr);
+                    let sign_extended = Self::sign_extended(vaddr);
+                    Self::note(&mut self.journal, sign_extended, level, old, *dst_entry);
                 }
-            } else {
-                PageSize::Size4K
-            };
-            let tlb = self.map(vaddr, paddr, page_size, flags).inspect_err(|e| {
-                error!(
-                    "failed to map page: {:#x?}({:?}) -> {:#x?}, {:?}",
-                    vaddr_usize, page_size, paddr, e
-                )
-            })?;
-            if flush_tlb_by_page {
-                M::flush_tlb(Some(vaddr));
+                let dst_next = Self::table_of_paddr(dst_entry.paddr(), level + 1);
+                let stop = self.clone_cow_recursive(
+                    src_next,
+                    dst_next,
+                    level + 1,
+                    vaddr,
+                    range,
+                    budget,
+                    journal,
+                    filter,
+                    copy,
+                )?;
+                if stop.is_some() {
+                    return Ok(stop);
+                }
+                continue;
             }
-            if flush_tlb_by_page {
-                tlb.flush();
-            } else {
-                tlb.ignore();
+            if !entry.is_present() {
+                continue;
+            }
+            if *budget == 0 {
+                return Ok(Some(vaddr));
             }
+            *budget -= 1;
+            let size = Self::leaf_size(level);
+            let flags = entry.leaf_flags(size.is_huge());
+            let action = filter(Self::sign_extended(vaddr), size, flags);
+            if action == CloneAction::Skip {
+                continue;
+            }
+            self.mapped_bytes = Self::check_quota(
+                QuotaKind::MappedBytes,
+                self.mapped_bytes,
+                self.max_mapped_bytes,
+                size as usize,
+            )?;
+            let mut new = *entry;
+            match action {
+                CloneAction::Cow => {
+                    if let Some(cow) = flags.cow_of().filter(|f| f.contains(MappingFlags::COW)) {
+                        if cow != flags {
+                            if entry.is_contiguous() {
+                                // The parent may be active: its activity is not known here.
+                                let bbm = M::BREAK_BEFORE_MAKE;
+                                let vaddr = Self::sign_extended(vaddr);
+                                Self::break_contiguous(journal, entry, vaddr, bbm);
+                            }
+                            let old = *entry;
+                            let mut new = old;
+                            new.set_flags(cow, size.is_huge());
+                            Self::write_leaf(entry, old, new);
+                            Self::note(journal, Self::sign_extended(vaddr), level, old, *entry);
+                        }
+                        Self::frame_shared(Self::leaf_paddr(entry, vaddr, size.is_huge()), size);
+                    }
+                    new = *entry;
+                }
+                CloneAction::Share => {
+                    let paddr = Self::leaf_paddr(entry, vaddr, size.is_huge());
+                    // A page that is copy-on-write already gets one more reference.
+                    if flags.contains(MappingFlags::COW) {
+                        Self::frame_shared(paddr, size);
+                    }
+                    new.set_contiguous(false);
+                    new.set_leaf_paddr(paddr, size.is_huge());
+                }
+                CloneAction::Copy => {
+                    let paddr = Self::leaf_paddr(entry, vaddr, size.is_huge());
+                    let paddr = copy(paddr, size).ok_or(PagingError::NoMemory)?;
+                    Self::check_paddr(paddr, size)?;
+                    new.set_contiguous(false);
+                    new.set_leaf_paddr(paddr, size.is_huge());
+                    new.set_flags(flags.resolve_cow(), size.is_huge());
+                }
+                CloneAction::Skip => unreachable!(),
+            }
+            // Locks are not inherited, like `mlock` across `fork`.
+            new.set_locked(false);
+            let old = *dst_entry;
+            *dst_entry = new;
+            let sign_extended = Self::sign_extended(vaddr);
+            Self::note(&mut self.journal, sign_extended, level, old, new);
+            // The child has no observer yet.
+            Self::observe(journal, sign_extended.into(), level, old, new, true);
+        }
+        Ok(None)
+    }
 
-            vaddr_usize += page_size as usize;
-            size -= page_size as usize;
+    /// Returns the level of the entry pointing to the subtree of tables that
+    /// maps the region of `size` bytes from `vaddr`.
+    fn subtree_level(vaddr: M::VirtAddr, size: usize) -> PagingResult<usize> {
+        let level = (0..M::LEVELS - 1)
+            .find(|&level| Self::entry_size(level) == size)
+            .ok_or(PagingError::NotAligned)?;
+        if !vaddr.is_aligned(size) {
+            return Err(PagingError::NotAligned);
         }
-        Ok(TlbFlushAll::new())
+        Ok(level)
     }
 
-    /// Unmaps a contiguous virtual memory region.
+    /// Returns the index of the entry of `vaddr` in its table at `level`.
+    const fn index_of(vaddr: usize, level: usize) -> usize {
+        (vaddr >> level_shift::<M>(level)) & (table_entries::<M>(level) - 1)
+    }
+
+    /// Calls `f` with each table of the subtree of `table`, which is at
+    /// `level` and covers the region from `table_vaddr`, with its level and
+    /// address. The tables below a table are visited before it.
     ///
-    /// The region must be mapped before using [`PageTable64::map_region`], or
-    /// unexpected behaviors may occur. It can deal with huge pages automatically.
+    /// Returns the size of the memory mapped by the subtree, and whether it
+    /// has a global mapping.
+    fn visit_subtree(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        f: &mut impl FnMut(PhysAddr, usize, usize),
+    ) -> (usize, bool) {
+        let (mut bytes, mut global) = (0, false);
+        for i in 0..table_entries::<M>(level) {
+            let entry = Self::load_entry(table, i);
+            let vaddr = table_vaddr + i * Self::entry_size(level);
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let (sub_bytes, sub_global) =
+                    Self::visit_subtree(entry.paddr(), level + 1, vaddr, f);
+                bytes += sub_bytes;
+                global |= sub_global;
+            } else if entry.is_present() {
+                bytes += Self::entry_size(level);
+                global |= entry.is_global();
+            }
+        }
+        f(table, level, table_vaddr);
+        (bytes, global)
+    }
+
+    /// Calls `f` with each page of the subtree of `table`, which is at `level`
+    /// and covers the region from `table_vaddr`, and frees the tables, for
+    /// [`DetachedSubtree::reclaim`].
+    fn reclaim_subtree(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        f: &mut impl FnMut(M::VirtAddr, PhysAddr, PageSize, MappingFlags),
+    ) {
+        for i in 0..table_entries::<M>(level) {
+            let entry = Self::load_entry(table, i);
+            let vaddr = table_vaddr + i * Self::entry_size(level);
+            if level < M::LEVELS - 1 && entry.is_table() {
+                Self::reclaim_subtree(entry.paddr(), level + 1, vaddr, f);
+            } else if entry.is_present() {
+                let size = Self::leaf_size(level);
+                let (paddr, flags) = (
+                    entry.leaf_paddr(size.is_huge()),
+                    entry.leaf_flags(size.is_huge()),
+                );
+                if flags.contains(MappingFlags::COW) {
+                    Self::frame_unshared(paddr, size);
+                }
+                f(Self::sign_extended(vaddr), paddr, size, flags);
+            }
+        }
+        Self::dealloc_table(table, level);
+    }
+
+    /// Sign-extends the address of an entry found by walking the tables,
+    /// which only gives the low bits, unless the address is valid as is
+    /// (e.g. a [`GuestPhysAddr`](crate::GuestPhysAddr)).
+    fn sign_extended(vaddr: usize) -> M::VirtAddr {
+        let high = !((1usize << M::VA_MAX_BITS) - 1);
+        if vaddr >> (M::VA_MAX_BITS - 1) == 0 || M::vaddr_is_valid(vaddr) {
+            vaddr.into()
+        } else {
+            (vaddr | high).into()
+        }
+    }
+
+    /// Unmaps the pages mapped through `table` whose frame overlaps `paddrs`,
+    /// for [`PageTable64::unmap_paddr_range`]. `table` is at `level` and
+    /// covers the region from `table_vaddr`.
     ///
-    /// When `flush_tlb_by_page` is true, it will flush the TLB immediately after
-    /// mapping each page. Otherwise, the TLB flush should by handled by the caller.
-    pub fn unmap_region(
+    /// `global` is set if a global mapping is changed.
+    fn unmap_paddr_recursive(
         &mut self,
-        vaddr: M::VirtAddr,
-        size: usize,
-        flush_tlb_by_page: bool,
-    ) -> PagingResult<TlbFlushAll<M>> {
-        let mut vaddr_usize: usize = vaddr.into();
-        let mut size = size;
-        trace!(
-            "unmap_region({:#x}) [{:#x}, {:#x})",
-            self.root_paddr(),
-            vaddr_usize,
-            vaddr_usize + size,
-        );
-        while size > 0 {
-            let vaddr = vaddr_usize.into();
-            let (_, page_size, tlb) = self
-                .unmap(vaddr)
-                .inspect_err(|e| error!("failed to unmap page: {:#x?}, {:?}", vaddr_usize, e))?;
-            if flush_tlb_by_page {
-                tlb.flush();
+        table: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        paddrs: PhysAddrRange,
+        f: &mut impl FnMut(M::VirtAddr, PageSize),
+        global: &mut bool,
+    ) -> PagingResult {
+        let entry_size = Self::entry_size(level);
+        for (i, entry) in table.iter_mut().enumerate() {
+            let table_vaddr = table_vaddr + i * entry_size;
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let next = Self::table_of_paddr(entry.paddr(), level + 1);
+                self.unmap_paddr_recursive(next, level + 1, table_vaddr, paddrs, f, global)?;
+                continue;
+            }
+            if !entry.is_present() {
+                continue;
+            }
+            let vaddr = Self::sign_extended(table_vaddr);
+            let (mut paddr, mut size) = match entry.is_contiguous() {
+                true => (
+                    entry.leaf_paddr(false).align_down(PageSize::Size64K),
+                    PageSize::Size64K,
+                ),
+                false => {
+                    let size = Self::leaf_size(level);
+                    (entry.leaf_paddr(size.is_huge()), size)
+                }
+            };
+            let mut frames = PhysAddrRange::from_start_size(paddr, size as usize);
+            if !frames.overlaps(paddrs) {
+                continue;
+            }
+            if entry.is_contiguous() && !paddrs.contains_range(frames) {
+                // The other entries of the group are handled after this one.
+                let bbm = self.needs_bbm();
+                Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+                (paddr, size) = (entry.leaf_paddr(false), PageSize::Size4K);
+                frames = PhysAddrRange::from_start_size(paddr, PAGE_SIZE_4K);
+                if !frames.overlaps(paddrs) {
+                    continue;
+                }
+            }
+            *global |= entry.is_global();
+            if !paddrs.contains_range(frames) {
+                let next = self.split_huge(entry, level, vaddr)?;
+                self.unmap_paddr_recursive(next, level + 1, table_vaddr, paddrs, f, global)?;
+                continue;
+            }
+            let is_huge = level < M::LEVELS - 1;
+            let cow = entry.leaf_flags(is_huge).contains(MappingFlags::COW);
+            if entry.is_contiguous() {
+                let base = vaddr.align_down(PageSize::Size64K);
+                let group = Self::contiguous_group(entry, vaddr);
+                for (i, entry) in group.iter_mut().enumerate() {
+                    let old = *entry;
+                    entry.clear();
+                    let page = base.into() + i * PAGE_SIZE_4K;
+                    Self::observe(&mut self.journal, page, level, old, *entry, false);
+                }
+                Self::note_range(&mut self.journal, base, level, CONTIGUOUS_ENTRIES);
             } else {
-                tlb.ignore();
+                let old = *entry;
+                entry.clear();
+                Self::note(&mut self.journal, vaddr, level, old, *entry);
             }
-
-            assert!(page_size.is_aligned(vaddr_usize));
-            assert!(page_size as usize <= size);
-            vaddr_usize += page_size as usize;
-            size -= page_size as usize;
+            if cow {
+                // The pages of a group are shared one by one.
+                let page = Self::leaf_size(level) as usize;
+                for off in (0..size as usize).step_by(page) {
+                    Self::frame_unshared(paddr.add(off), Self::leaf_size(level));
+                }
+            }
+            self.mapped_bytes = self.mapped_bytes.saturating_sub(size as usize);
+            f(vaddr, size);
         }
-        Ok(TlbFlushAll::new())
+        Ok(())
     }
 
-    /// Updates mapping flags of a contiguous virtual memory region.
-    ///
-    /// The region must be mapped before using [`PageTable64::map_region`], or
-    /// unexpected behaviors may occur. It can deal with huge pages automatically.
+    /// Write-protects the writable pages of the region, split to 4K, and calls
+    /// `f` with the address of each of them.
     ///
-    /// When `flush_tlb_by_page` is true, it will flush the TLB immediately after
-    /// mapping each page. Otherwise, the TLB flush should by handled by the caller.
-    pub fn protect_region(
+    /// If the entries can store `reason`, it is added to the pages that may be
+    /// written and lack it, including the copy-on-write pages and those
+    /// write-protected for other reasons. Otherwise only the pages with
+    /// [`MappingFlags::WRITE`] are protected, by removing it.
+    fn write_protect_region(
         &mut self,
-        vaddr: M::VirtAddr,
+        start: M::VirtAddr,
         size: usize,
-        flags: MappingFlags,
-        flush_tlb_by_page: bool,
+        reason: WpReasons,
+        mut f: impl FnMut(M::VirtAddr),
     ) -> PagingResult<TlbFlushAll<M>> {
-        let mut vaddr_usize: usize = vaddr.into();
-        let mut size = size;
-        trace!(
-            "protect_region({:#x}) [{:#x}, {:#x}) {:?}",
-            self.root_paddr(),
-            vaddr_usize,
-            vaddr_usize + size,
-            flags,
-        );
-        while size > 0 {
-            let vaddr = vaddr_usize.into();
-            let (page_size, tlb) = self
-                .protect(vaddr, flags)
-                .inspect_err(|e| error!("failed to protect page: {:#x?}, {:?}", vaddr_usize, e))?;
-            if flush_tlb_by_page {
-                tlb.flush();
-            } else {
-                tlb.ignore();
-            }
-
-            assert!(page_size.is_aligned(vaddr_usize));
-            assert!(page_size as usize <= size);
-            vaddr_usize += page_size as usize;
-            size -= page_size as usize;
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
+        }
+        if size == 0 {
+            return Ok(TlbFlushAll::new().with_generation(self.generation));
+        }
+        // Only the low bits of the addresses are used to walk the tables.
+        let va_mask = Self::va_mask();
+        let range = (start.into() & va_mask, (start.into() & va_mask) + size);
+        let mut global = false;
+        let root = self.table_of_mut(self.root_paddr, 0);
+        let result = self.write_protect_recursive(root, 0, 0, range, reason, &mut f, &mut global);
+        self.end_update();
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
         }
-        Ok(TlbFlushAll::new())
+        result?;
+        Ok(TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global))
     }
 
-    /// Walk the page table recursively.
-    ///
-    /// When reaching a page table entry, call `pre_func` and `post_func` on the
-    /// entry if they are provided. The max number of enumerations in one table
-    /// is limited by `limit`. `pre_func` and `post_func` are called before and
-    /// after recursively walking the page table.
-    ///
-    /// The arguments of `*_func` are:
-    /// - Current level (starts with `0`): `usize`
-    /// - The index of the entry in the current-level table: `usize`
-    /// - The virtual address that is mapped to the entry: `M::VirtAddr`
-    /// - The reference of the entry: [`&PTE`](GenericPTE)
-    pub fn walk<F>(&self, limit: usize, pre_func: Option<&F>, post_func: Option<&F>) -> PagingResult
-    where
-        F: Fn(usize, usize, M::VirtAddr, &PTE),
-    {
-        self.walk_recursive(
-            self.table_of(self.root_paddr()),
-            0,
-            0.into(),
-            limit,
-            pre_func,
-            post_func,
-        )
+    #[allow(clippy::too_many_arguments)]
+    fn write_protect_recursive(
+        &mut self,
+        table: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        reason: WpReasons,
+        f: &mut impl FnMut(M::VirtAddr),
+        global: &mut bool,
+    ) -> PagingResult {
+        let entry_size = Self::entry_size(level);
+        for (i, entry) in table.iter_mut().enumerate() {
+            let table_vaddr = table_vaddr + i * entry_size;
+            if table_vaddr + entry_size <= range.0 || table_vaddr >= range.1 {
+                continue;
+            }
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let next = Self::table_of_paddr(entry.paddr(), level + 1);
+                self.write_protect_recursive(
+                    next,
+                    level + 1,
+                    table_vaddr,
+                    range,
+                    reason,
+                    f,
+                    global,
+                )?;
+                continue;
+            }
+            let flags = entry.leaf_flags(level < M::LEVELS - 1);
+            let tracked = PTE::WP_REASONS.contains(reason);
+            let reasons = entry.wp_reasons();
+            let protect = if tracked {
+                !reasons.contains(reason)
+                    && (flags.intersects(MappingFlags::WRITE | MappingFlags::COW)
+                        || !reasons.is_empty())
+            } else {
+                flags.contains(MappingFlags::WRITE)
+            };
+            if !entry.is_present() || !protect {
+                continue;
+            }
+            let vaddr = Self::sign_extended(table_vaddr);
+            *global |= entry.is_global();
+            if level < M::LEVELS - 1 {
+                let next = self.split_huge(entry, level, vaddr)?;
+                self.write_protect_recursive(
+                    next,
+                    level + 1,
+                    table_vaddr,
+                    range,
+                    reason,
+                    f,
+                    global,
+                )?;
+                continue;
+            }
+            if entry.is_contiguous() {
+                let bbm = self.needs_bbm();
+                Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+            }
+            let old = *entry;
+            let mut new = old;
+            if tracked {
+                new.set_wp_reasons(reasons | reason);
+            } else {
+                new.set_flags(flags - MappingFlags::WRITE, false);
+                Self::keep_ad_bits(&old, &mut new, flags - MappingFlags::WRITE, false);
+            }
+            Self::write_leaf(entry, old, new);
+            Self::note(&mut self.journal, vaddr, level, old, *entry);
+            f(vaddr);
+        }
+        Ok(())
     }
 
-    fn top_level_idx_range(&self, start: M::VirtAddr, size: usize) -> (usize, usize) {
-        let index_fn = if M::LEVELS == 3 {
-            p3_index
-        } else if M::LEVELS == 4 {
-            p4_index
-        } else {
-            unreachable!()
-        };
-        let start_idx = index_fn(start.into());
-        let end_idx = index_fn(start.into() + size - 1) + 1;
-        assert!(start_idx < ENTRY_COUNT);
-        assert!(end_idx <= ENTRY_COUNT);
-        (start_idx, end_idx)
+    /// Retypes the leaves of the region for [`PageTable64::retype_region`], or
+    /// only checks that they can be if `apply` is `false`.
+    #[allow(clippy::too_many_arguments)]
+    fn retype_recursive(
+        &mut self,
+        table: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        ty: MemoryType,
+        apply: bool,
+        global: &mut bool,
+    ) -> PagingResult {
+        let entry_size = Self::entry_size(level);
+        for (i, entry) in table.iter_mut().enumerate() {
+            let table_vaddr = table_vaddr + i * entry_size;
+            if table_vaddr + entry_size <= range.0 || table_vaddr >= range.1 {
+                continue;
+            }
+            if apply {
+                self.journal
+                    .stats
+                    .visit(entry.is_unused(), level < M::LEVELS - 1);
+            }
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let next = Self::table_of_paddr(entry.paddr(), level + 1);
+                self.retype_recursive(next, level + 1, table_vaddr, range, ty, apply, global)?;
+                continue;
+            }
+            let size = Self::leaf_size(level);
+            let old_flags = entry.leaf_flags(size.is_huge());
+            let old_type = MemoryType::of(old_flags);
+            if !entry.is_present() || old_type == ty {
+                continue;
+            }
+            let vaddr = Self::sign_extended(table_vaddr);
+            if table_vaddr < range.0 || table_vaddr + entry_size > range.1 {
+                return Err(Self::huge_page_at(vaddr.into(), level));
+            }
+            let mut flags = old_flags - (MappingFlags::DEVICE | MappingFlags::UNCACHED);
+            flags |= match ty {
+                MemoryType::Normal => MappingFlags::empty(),
+                MemoryType::Uncached => MappingFlags::UNCACHED,
+                MemoryType::Device => MappingFlags::DEVICE,
+            };
+            if !apply {
+                let paddr = Self::leaf_paddr(entry, table_vaddr, size.is_huge());
+                Self::check_memory_type(paddr, size, flags)?;
+                continue;
+            }
+            *global |= entry.is_global();
+            if entry.is_contiguous() {
+                let bbm = self.needs_bbm();
+                Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+            }
+            let old = *entry;
+            let mut invalid = old;
+            invalid.clear();
+            // The walker must not see the page until it has its new type, and
+            // the caches are cleaned while no mapping of that page is live.
+            let last = Self::swap_leaf(entry, invalid);
+            M::flush_tlb(Some(vaddr));
+            let op = match (old_type, ty) {
+                (MemoryType::Normal, _) => Some(CacheOp::CleanInvalidate),
+                (_, MemoryType::Normal) => Some(CacheOp::Invalidate),
+                _ => None,
+            };
+            if let Some(op) = op {
+                H::cache_maintain(old.leaf_paddr(size.is_huge()), size as usize, op);
+            }
+            let mut new = last;
+            new.set_flags(flags, size.is_huge());
+            Self::keep_ad_bits(&last, &mut new, flags, size.is_huge());
+            unsafe { core::ptr::write_volatile(entry, new) };
+            Self::note(&mut self.journal, vaddr, level, old, new);
+        }
+        Ok(())
     }
 
-    /// Copy entries from another page table within the given virtual memory range.
-    pub fn copy_from(&mut self, other: &Self, start: M::VirtAddr, size: usize) {
-        if size == 0 {
-            return;
+    /// Narrows the table entries in `table` and the tables below it for
+    /// [`PageTable64::recompute_table_permissions`], counting them in
+    /// `changed` and setting `global` if one of them covers a global page.
+    /// Returns the union of the flags of the mappings below `table`, and
+    /// whether one of them is global.
+    fn narrow_tables_recursive(
+        &mut self,
+        table: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        changed: &mut usize,
+        global: &mut bool,
+    ) -> (MappingFlags, bool) {
+        let entry_size = Self::entry_size(level);
+        let mut below = MappingFlags::empty();
+        let mut below_global = false;
+        for (i, entry) in table.iter_mut().enumerate() {
+            let table_vaddr = table_vaddr + i * entry_size;
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            if !(level < M::LEVELS - 1 && entry.is_table()) {
+                if entry.is_present() {
+                    below |= entry.leaf_flags(level < M::LEVELS - 1);
+                    below_global |= entry.is_global();
+                }
+                continue;
+            }
+            let next = Self::table_of_paddr(entry.paddr(), level + 1);
+            let (flags, next_global) =
+                self.narrow_tables_recursive(next, level + 1, table_vaddr, changed, global);
+            below |= flags;
+            below_global |= next_global;
+            // The hardware may set the accessed bit of the entry meanwhile.
+            let result = Self::atomic_entry(entry).fetch_update(
+                Ordering::Release,
+                Ordering::Relaxed,
+                |bits| {
+                    let mut new = Self::pte_from_bits(bits);
+                    new.narrow_table(flags).then(|| Self::pte_bits(new))
+                },
+            );
+            if let Ok(old) = result {
+                let vaddr = Self::sign_extended(table_vaddr);
+                Self::note(
+                    &mut self.journal,
+                    vaddr,
+                    level,
+                    Self::pte_from_bits(old),
+                    *entry,
+                );
+                *changed += 1;
+                *global |= next_global;
+            }
         }
-        let src_table = self.table_of(other.root_paddr);
-        let dst_table = self.table_of_mut(self.root_paddr);
-        let (start_idx, end_idx) = self.top_level_idx_range(start, size);
-        dst_table[start_idx..end_idx].copy_from_slice(&src_table[start_idx..end_idx]);
+        (below, below_global)
     }
 
-    /// Undoes the copy of entries from another page table within the given
-    /// virtual memory range.
-    ///
-    /// This is the inverse operation of [`PageTable64::copy_from`]. It might be
-    /// useful when you need to drop a page table, but some of its entries are
-    /// copied from another actively used page table, so you need to unlink the
-    /// shared entries first.
-    pub fn clear_copy_range(&mut self, start: M::VirtAddr, size: usize) {
+    /// Changes the leaves of the region with `set`, for
+    /// [`PageTable64::set_dirty_region`] and
+    /// [`PageTable64::set_accessed_region`]. The TLB must be flushed unless
+    /// the bit is `set`.
+    fn set_bits_region(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+        set: bool,
+        f: impl Fn(&mut PTE),
+    ) -> PagingResult<(usize, TlbFlushAll<M>)> {
         if size == 0 {
-            return;
+            return Ok((0, TlbFlushAll::unneeded(self.generation)));
+        }
+        Self::check_range(start.into(), size)?;
+        if !start.is_aligned_4k() || !PageSize::Size4K.is_aligned(size) {
+            return Err(PagingError::NotAligned);
         }
-        let table = self.table_of_mut(self.root_paddr);
-        let (start_idx, end_idx) = self.top_level_idx_range(start, size);
-        for pte in &mut table[start_idx..end_idx] {
-            pte.clear();
+        // Only the low bits of the addresses are used to walk the tables.
+        let va_mask = Self::va_mask();
+        let range = (start.into() & va_mask, (start.into() & va_mask) + size);
+        let mut global = false;
+        let root = self.table_of_mut(self.root_paddr, 0);
+        let changed = self.set_bits_recursive(root, 0, 0, range, &f, &mut global);
+        self.end_update();
+        if set || changed == 0 {
+            return Ok((changed, TlbFlushAll::unneeded(self.generation)));
         }
+        self.generation += 1;
+        if global {
+            self.global_generation = self.generation;
+        }
+        let tlb = TlbFlushAll::new()
+            .with_generation(self.generation)
+            .with_global(global);
+        Ok((changed, tlb))
     }
 
-    pub fn is_dirty(&self, vaddr: M::VirtAddr) -> PagingResult<bool> {
-        let (entry, _) = self.get_entry(vaddr)?;
-        if !entry.is_present() {
-            return Err(PagingError::NotMapped);
+    fn set_bits_recursive(
+        &mut self,
+        table: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        f: &impl Fn(&mut PTE),
+        global: &mut bool,
+    ) -> usize {
+        let entry_size = Self::entry_size(level);
+        let mut changed = 0;
+        for (i, entry) in table.iter_mut().enumerate() {
+            let table_vaddr = table_vaddr + i * entry_size;
+            // The whole group of a contiguous entry is one page.
+            let (page, page_size) = if entry.is_contiguous() {
+                let size = PageSize::Size64K as usize;
+                (table_vaddr & !(size - 1), size)
+            } else {
+                (table_vaddr, entry_size)
+            };
+            if page + page_size <= range.0 || page >= range.1 {
+                continue;
+            }
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let next = Self::table_of_paddr(entry.paddr(), level + 1);
+                changed += self.set_bits_recursive(next, level + 1, table_vaddr, range, f, global);
+                continue;
+            }
+            if !entry.is_present() {
+                continue;
+            }
+            let old = *entry;
+            let mut new = old;
+            f(&mut new);
+            if Self::pte_bits(new) == Self::pte_bits(old) {
+                continue;
+            }
+            Self::write_leaf(entry, old, new);
+            Self::note(
+                &mut self.journal,
+                Self::sign_extended(table_vaddr),
+                level,
+                old,
+                *entry,
+            );
+            *global |= old.is_global();
+            changed += 1;
         }
-
-        Ok(entry.is_dirty())
+        changed
     }
 
-    pub fn set_dirty(&mut self, vaddr: M::VirtAddr, dirty: bool) -> PagingResult<()> {
-        let (entry, _) = self.get_entry_mut(vaddr)?;
-        if !entry.is_present() {
-            return Err(PagingError::NotMapped);
+    fn scan_recursive<I: IdleBits + ?Sized>(
+        &mut self,
+        table: &mut [PTE],
+        level: usize,
+        table_vaddr: usize,
+        scan: &mut Scan<'_, I>,
+    ) -> PagingResult {
+        let entry_size = Self::entry_size(level);
+        // The end of the group with the contiguous hint just split, which was
+        // scanned whole.
+        let mut split_end = 0;
+        for (i, entry) in table.iter_mut().enumerate() {
+            let table_vaddr = table_vaddr + i * entry_size;
+            if table_vaddr + entry_size <= scan.range.0
+                || table_vaddr >= scan.range.1
+                || table_vaddr < split_end
+            {
+                continue;
+            }
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let next = Self::table_of_paddr(entry.paddr(), level + 1);
+                self.scan_recursive(next, level + 1, table_vaddr, scan)?;
+                continue;
+            }
+            if !entry.is_present() {
+                continue;
+            }
+            if entry.is_locked() {
+                // Locked pages are not reclaimed, so they are only counted. A
+                // group with the contiguous hint is counted from its first
+                // entry in the region.
+                if !entry.is_contiguous() {
+                    scan.set.locked += entry_size;
+                } else {
+                    let size = PageSize::Size64K as usize;
+                    if table_vaddr == (table_vaddr & !(size - 1)).max(scan.range.0) {
+                        scan.set.locked += size;
+                    }
+                }
+                continue;
+            }
+            let vaddr = Self::sign_extended(table_vaddr);
+            if scan.split && level < M::LEVELS - 1 {
+                scan.global |= entry.is_global();
+                let next = self.split_huge(entry, level, vaddr)?;
+                scan.changed = true;
+                self.scan_recursive(next, level + 1, table_vaddr, scan)?;
+                continue;
+            }
+            let contiguous = entry.is_contiguous();
+            let (page, size, leaves) = if contiguous {
+                // The whole group is one page, scanned from its first entry in
+                // the region: the hardware may set the bit of any of them.
+                let size = PageSize::Size64K as usize;
+                let page = table_vaddr & !(size - 1);
+                if table_vaddr != page.max(scan.range.0) {
+                    continue;
+                }
+                (page, size, Self::contiguous_group(entry, vaddr))
+            } else {
+                (table_vaddr, entry_size, core::slice::from_mut(entry))
+            };
+            let mut accessed = false;
+            for (j, leaf) in leaves.iter_mut().enumerate() {
+                let old = *leaf;
+                if !old.is_accessed() {
+                    continue;
+                }
+                let mut new = old;
+                new.set_accessed(false);
+                Self::write_leaf(leaf, old, new);
+                Self::note(
+                    &mut self.journal,
+                    Self::sign_extended(page + j * PAGE_SIZE_4K),
+                    level,
+                    old,
+                    *leaf,
+                );
+                scan.changed = true;
+                scan.global |= old.is_global();
+                accessed = true;
+            }
+            scan.count(page, size, accessed);
+            if scan.split && contiguous {
+                let bbm = self.needs_bbm();
+                Self::break_contiguous(&mut self.journal, entry, vaddr, bbm);
+                scan.changed = true;
+                split_end = page + size;
+            }
         }
-
-        entry.set_dirty(dirty);
         Ok(())
     }
 
-    pub fn is_accessed(&self, vaddr: M::VirtAddr) -> PagingResult<bool> {
-        let (entry, _) = self.get_entry(vaddr)?;
-        if !entry.is_present() {
-            return Err(PagingError::NotMapped);
+    /// Replaces the huge leaf `entry` of `vaddr` at `level` with a new table
+    /// of pages of the next smaller size, which map the same frames. Returns
+    /// the new table.
+    fn split_huge<'a>(
+        &mut self,
+        entry: &mut PTE,
+        level: usize,
+        vaddr: M::VirtAddr,
+    ) -> PagingResult<&'a mut [PTE]> {
+        let paddr = self.alloc_table_counted(level + 1)?;
+        self.walk_cache.clear();
+        let mut invalid = *entry;
+        invalid.clear();
+        // Keep the bits set by the hardware until the entry is invalid.
+        let old = Self::swap_leaf(entry, invalid);
+        if self.needs_bbm() {
+            M::flush_tlb(Some(vaddr));
         }
-
-        Ok(entry.is_accessed())
-    }
-
-    pub fn set_accessed(&mut self, vaddr: M::VirtAddr, accessed: bool) -> PagingResult<()> {
-        let (entry, _) = self.get_entry_mut(vaddr)?;
-        if !entry.is_present() {
-            return Err(PagingError::NotMapped);
+        let (size, flags) = (Self::leaf_size(level + 1), old.flags());
+        // The bits not modeled by this crate are at the same place in the
+        // entries of every size.
+        let unknown = old.unknown_bits() as u64;
+        let table = self.table_of_mut(paddr, level + 1);
+        for (i, page) in table.iter_mut().enumerate() {
+            let mut new = PTE::new_page(old.paddr().add(i * size as usize), flags, size.is_huge());
+            new.set_dirty(old.is_dirty());
+            new.set_accessed(old.is_accessed());
+            *page = Self::pte_from_bits(Self::pte_bits(new) | unknown);
         }
+        if flags.contains(MappingFlags::COW) {
+            for page in table.iter() {
+                Self::frame_shared(page.leaf_paddr(size.is_huge()), size);
+            }
+            Self::frame_unshared(old.paddr(), Self::leaf_size(level));
+        }
+        let mut new = PTE::new_table(paddr);
+        new.widen_table(flags);
+        unsafe { core::ptr::write_volatile(entry, new) };
+        Self::note(&mut self.journal, vaddr, level, old, new);
+        let count = table_entries::<M>(level + 1);
+        Self::note_range(&mut self.journal, vaddr, level + 1, count);
+        Ok(table)
+    }
 
-        entry.set_accessed(accessed);
-        Ok(())
+    fn table_of_paddr<'a>(paddr: PhysAddr, level: usize) -> &'a mut [PTE] {
+        let ptr = H::phys_to_virt(paddr).as_mut_ptr() as _;
+        unsafe { core::slice::from_raw_parts_mut(ptr, table_entries::<M>(level)) }
     }
-}
 
-// Private implements.
-impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> PageTable64<M, PTE, H> {
-    fn alloc_table() -> PagingResult<PhysAddr> {
-        if let Some(paddr) = H::alloc_frame() {
-            let ptr = H::phys_to_virt(paddr).as_mut_ptr();
-            unsafe { core::ptr::write_bytes(ptr, 0, PAGE_SIZE_4K) };
-            Ok(paddr)
-        } else {
-            Err(PagingError::NoMemory)
-        }
+    /// Reads the entry `index` of the table at `table`.
+    ///
+    /// The entry is loaded atomically instead of through a reference, as the
+    /// table may be in use: the hardware sets the accessed and dirty bits of
+    /// its entries, and other CPUs may modify it concurrently.
+    fn load_entry(table: PhysAddr, index: usize) -> PTE {
+        Self::pte_from_bits(Self::entry_at(table, index).load(Ordering::Acquire))
     }
 
-    fn table_of<'a>(&self, paddr: PhysAddr) -> &'a [PTE] {
-        let ptr = H::phys_to_virt(paddr).as_ptr() as _;
-        unsafe { core::slice::from_raw_parts(ptr, ENTRY_COUNT) }
+    /// Returns the entry `index` of the table at `table`, for atomic accesses.
+    fn entry_at<'a>(table: PhysAddr, index: usize) -> &'a AtomicU64 {
+        let ptr = H::phys_to_virt(table).as_ptr() as *const AtomicU64;
+        unsafe { &*ptr.add(index) }
     }
 
-    fn table_of_mut<'a>(&mut self, paddr: PhysAddr) -> &'a mut [PTE] {
+    fn table_of_mut<'a>(&mut self, paddr: PhysAddr, level: usize) -> &'a mut [PTE] {
         let ptr = H::phys_to_virt(paddr).as_mut_ptr() as _;
-        unsafe { core::slice::from_raw_parts_mut(ptr, ENTRY_COUNT) }
+        unsafe { core::slice::from_raw_parts_mut(ptr, table_entries::<M>(level)) }
     }
 
-    fn next_table<'a>(&self, entry: &PTE) -> PagingResult<&'a [PTE]> {
-        if entry.paddr().as_usize() == 0 {
+    /// Returns the physical address of the next-level table of `entry`, the
+    /// entry of `vaddr` at `level`.
+    ///
+    /// A huge page is never taken for a table: its frame would be written as
+    /// one.
+    fn next_table(entry: &PTE, vaddr: usize, level: usize) -> PagingResult<PhysAddr> {
+        if entry.is_unused() || entry.paddr().as_usize() == 0 {
             Err(PagingError::NotMapped)
         } else if entry.is_huge() {
-            Err(PagingError::MappedToHugePage)
+            Err(Self::huge_page_at(vaddr, level))
         } else {
-            Ok(self.table_of(entry.paddr()))
+            Ok(entry.paddr())
         }
     }
 
-    fn next_table_mut<'a>(&mut self, entry: &PTE) -> PagingResult<&'a mut [PTE]> {
-        if entry.paddr().as_usize() == 0 {
-            Err(PagingError::NotMapped)
-        } else if entry.is_huge() {
-            Err(PagingError::MappedToHugePage)
-        } else {
-            Ok(self.table_of_mut(entry.paddr()))
-        }
+    fn next_table_mut<'a>(
+        &mut self,
+        entry: &PTE,
+        vaddr: usize,
+        level: usize,
+    ) -> PagingResult<&'a mut [PTE]> {
+        let paddr = Self::next_table(entry, vaddr, level)?;
+        Ok(self.table_of_mut(paddr, level + 1))
     }
 
-    fn next_table_mut_or_create<'a>(&mut self, entry: &mut PTE) -> PagingResult<&'a mut [PTE]> {
-        if entry.is_unused() {
-            let paddr = Self::alloc_table()?;
-            *entry = GenericPTE::new_table(paddr);
-            Ok(self.table_of_mut(paddr))
-        } else {
-            self.next_table_mut(entry)
+    /// Returns the error for the huge page of `vaddr` at `level`.
+    fn huge_page_at(vaddr: usize, level: usize) -> PagingError {
+        PagingError::MappedToHugePage {
+            vaddr: vaddr & !(Self::entry_size(level) - 1),
+            level,
         }
     }
 
-    fn get_entry(&self, vaddr: M::VirtAddr) -> PagingResult<(&PTE, PageSize)> {
+    /// Checks that `vaddr` is the start of the page of `size` mapping it.
+    fn check_page_start(vaddr: M::VirtAddr, size: PageSize) -> PagingResult {
         let vaddr: usize = vaddr.into();
-        let p3 = if M::LEVELS == 3 {
-            self.table_of(self.root_paddr())
-        } else if M::LEVELS == 4 {
-            let p4 = self.table_of(self.root_paddr());
-            let p4e = &p4[p4_index(vaddr)];
-            self.next_table(p4e)?
+        if size.is_huge() && !size.is_aligned(vaddr) {
+            return Err(Self::huge_page_at(vaddr, Self::leaf_level(size)));
+        }
+        Ok(())
+    }
+
+    /// Returns the table `entry` points to, after creating it if needed.
+    ///
+    /// `entry` is widened to allow `flags`, and `widened` set if an existing
+    /// entry was changed.
+    fn next_table_mut_or_create<'a>(
+        &mut self,
+        entry: &mut PTE,
+        vaddr: usize,
+        level: usize,
+        flags: MappingFlags,
+        widened: &mut bool,
+    ) -> PagingResult<&'a mut [PTE]> {
+        let old = *entry;
+        let table = if entry.is_unused() {
+            let paddr = self.alloc_table_counted(level + 1)?;
+            *entry = GenericPTE::new_table(paddr);
+            entry.widen_table(flags);
+            self.table_of_mut(paddr, level + 1)
         } else {
-            unreachable!()
+            let table = self.next_table_mut(entry, vaddr, level)?;
+            *widened |= entry.widen_table(flags);
+            table
         };
-        let p3e = &p3[p3_index(vaddr)];
-        if p3e.is_huge() {
-            return Ok((p3e, PageSize::Size1G));
+        Self::note(&mut self.journal, vaddr, level, old, *entry);
+        Ok(table)
+    }
+
+    /// Translates `vaddr` with its leaf `entry`, of a page of `size`, like
+    /// [`PageTable64::query`].
+    fn translate(
+        entry: &PTE,
+        size: PageSize,
+        vaddr: usize,
+    ) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
+        if !Self::holds_frame(entry) {
+            return Err(entry
+                .absent()
+                .map_or(PagingError::NotMapped, PagingError::Absent));
         }
+        let size = match size {
+            PageSize::Size4K if entry.is_contiguous() => PageSize::Size64K,
+            size => size,
+        };
+        let off = size.align_offset(vaddr);
+        let is_huge = size.is_huge();
+        let paddr = entry.inaccessible();
+        let paddr = paddr.unwrap_or_else(|| entry.leaf_paddr(is_huge));
+        Ok((
+            paddr.align_down(size).add(off),
+            entry.leaf_flags(is_huge),
+            size,
+        ))
+    }
 
-        let p2 = self.next_table(p3e)?;
-        let p2e = &p2[p2_index(vaddr)];
+    fn get_entry(&self, vaddr: M::VirtAddr) -> PagingResult<(PTE, PageSize)> {
+        let vaddr: usize = vaddr.into();
+        if let Some(p1) = self.walk_cache.get(vaddr, PageSize::Size4K) {
+            self.journal.stats.read(1);
+            return Ok((
+                Self::load_entry(p1, Self::index_of(vaddr, M::LEVELS - 1)),
+                PageSize::Size4K,
+            ));
+        }
+        let p2 = match self.walk_cache.get(vaddr, PageSize::Size2M) {
+            Some(p2) => p2,
+            None => {
+                let p3 = if M::LEVELS == 3 {
+                    self.root_paddr()
+                } else if M::LEVELS == 4 {
+                    self.journal.stats.read(1);
+                    let p4e = Self::load_entry(self.root_paddr(), Self::index_of(vaddr, 0));
+                    if Self::page_size_supported(PageSize::Size512G) && p4e.is_huge() {
+                        return Ok((p4e, PageSize::Size512G));
+                    }
+                    Self::next_table(&p4e, vaddr, 0)?
+                } else {
+                    unreachable!()
+                };
+                self.journal.stats.read(1);
+                let p3e = Self::load_entry(p3, Self::index_of(vaddr, M::LEVELS - 3));
+                if p3e.is_huge() {
+                    return Ok((p3e, PageSize::Size1G));
+                }
+                Self::next_table(&p3e, vaddr, M::LEVELS - 3)?
+            }
+        };
+        self.journal.stats.read(1);
+        let p2e = Self::load_entry(p2, Self::index_of(vaddr, M::LEVELS - 2));
         if p2e.is_huge() {
             return Ok((p2e, PageSize::Size2M));
         }
 
-        let p1 = self.next_table(p2e)?;
-        let p1e = &p1[p1_index(vaddr)];
-        Ok((p1e, PageSize::Size4K))
+        let p1 = Self::next_table(&p2e, vaddr, M::LEVELS - 2)?;
+        self.journal.stats.read(1);
+        Ok((
+            Self::load_entry(p1, Self::index_of(vaddr, M::LEVELS - 1)),
+            PageSize::Size4K,
+        ))
     }
 
-    fn get_entry_mut(&mut self, vaddr: M::VirtAddr) -> PagingResult<(&mut PTE, PageSize)> {
+    fn get_entry_mut<'a>(&mut self, vaddr: M::VirtAddr) -> PagingResult<(&'a mut PTE, PageSize)> {
         let vaddr: usize = vaddr.into();
-        let p3 = if M::LEVELS == 3 {
-            self.table_of_mut(self.root_paddr())
-        } else if M::LEVELS == 4 {
-            let p4 = self.table_of_mut(self.root_paddr());
-            let p4e = &mut p4[p4_index(vaddr)];
-            self.next_table_mut(p4e)?
-        } else {
-            unreachable!()
-        };
-        let p3e = &mut p3[p3_index(vaddr)];
-        if p3e.is_huge() {
-            return Ok((p3e, PageSize::Size1G));
+        if let Some(p1) = self.walk_cache.get(vaddr, PageSize::Size4K) {
+            self.journal.stats.read(1);
+            return Ok((
+                &mut self.table_of_mut(p1, M::LEVELS - 1)[Self::index_of(vaddr, M::LEVELS - 1)],
+                PageSize::Size4K,
+            ));
         }
-
-        let p2 = self.next_table_mut(p3e)?;
-        let p2e = &mut p2[p2_index(vaddr)];
+        let p2 = match self.walk_cache.get(vaddr, PageSize::Size2M) {
+            Some(p2) => self.table_of_mut(p2, M::LEVELS - 2),
+            None => {
+                let p3 = if M::LEVELS == 3 {
+                    self.table_of_mut(self.root_paddr(), 0)
+                } else if M::LEVELS == 4 {
+                    let p4 = self.table_of_mut(self.root_paddr(), 0);
+                    let p4e = &mut p4[Self::index_of(vaddr, 0)];
+                    self.journal.stats.read(1);
+                    if Self::page_size_supported(PageSize::Size512G) && p4e.is_huge() {
+                        return Ok((p4e, PageSize::Size512G));
+                    }
+                    self.next_table_mut(p4e, vaddr, 0)?
+                } else {
+                    unreachable!()
+                };
+                let p3e = &mut p3[Self::index_of(vaddr, M::LEVELS - 3)];
+                self.journal.stats.read(1);
+                if p3e.is_huge() {
+                    return Ok((p3e, PageSize::Size1G));
+                }
+                let p2 = self.next_table_mut(p3e, vaddr, M::LEVELS - 3)?;
+                let none = MappingFlags::empty();
+                self.walk_cache
+                    .set(vaddr, PageSize::Size2M, p3e.paddr(), none);
+                p2
+            }
+        };
+        let p2e = &mut p2[Self::index_of(vaddr, M::LEVELS - 2)];
+        self.journal.stats.read(1);
         if p2e.is_huge() {
             return Ok((p2e, PageSize::Size2M));
         }
 
-        let p1 = self.next_table_mut(p2e)?;
-        let p1e = &mut p1[p1_index(vaddr)];
+        let p1 = self.next_table_mut(p2e, vaddr, M::LEVELS - 2)?;
+        let none = MappingFlags::empty();
+        self.walk_cache
+            .set(vaddr, PageSize::Size4K, p2e.paddr(), none);
+        let p1e = &mut p1[Self::index_of(vaddr, M::LEVELS - 1)];
+        self.journal.stats.read(1);
         Ok((p1e, PageSize::Size4K))
     }
 
-    fn get_entry_mut_or_create(
+    /// Returns the `page_size` leaf entry of `vaddr`, creating the tables on
+    /// the way. The table entries on the way are widened to allow `flags`.
+    ///
+    /// Also returns whether an existing table entry was widened, which needs a
+    /// TLB flush.
+    fn get_entry_mut_or_create<'a>(
+        &mut self,
+        vaddr: M::VirtAddr,
+        page_size: PageSize,
+        flags: MappingFlags,
+    ) -> PagingResult<(&'a mut PTE, bool)> {
+        let vaddr: usize = vaddr.into();
+        if let Some(p1) = self.walk_cache.get_allowing(vaddr, page_size, flags) {
+            let level = match page_size {
+                PageSize::Size4K => M::LEVELS - 1,
+                _ => M::LEVELS - 2,
+            };
+            self.journal.stats.read(1);
+            let table = self.table_of_mut(p1, level);
+            return Ok((&mut table[Self::index_of(vaddr, level)], false));
+        }
+        let mut widened = false;
+        let cached_p2 = match page_size {
+            PageSize::Size1G | PageSize::Size512G => None,
+            _ => self.walk_cache.get_allowing(vaddr, PageSize::Size2M, flags),
+        };
+        let p2 = match cached_p2 {
+            Some(p2) => self.table_of_mut(p2, M::LEVELS - 2),
+            None => {
+                let p3 = if M::LEVELS == 3 {
+                    self.table_of_mut(self.root_paddr(), 0)
+                } else if M::LEVELS == 4 {
+                    let p4 = self.table_of_mut(self.root_paddr(), 0);
+                    let p4e = &mut p4[Self::index_of(vaddr, 0)];
+                    self.journal.stats.read(1);
+                    if page_size == PageSize::Size512G {
+                        return Ok((p4e, widened));
+                    }
+                    self.next_table_mut_or_create(p4e, vaddr, 0, flags, &mut widened)?
+                } else {
+                    unreachable!()
+                };
+                let p3e = &mut p3[Self::index_of(vaddr, M::LEVELS - 3)];
+                self.journal.stats.read(1);
+                if page_size == PageSize::Size1G {
+                    return Ok((p3e, widened));
+                }
+                let p2 =
+                    self.next_table_mut_or_create(p3e, vaddr, M::LEVELS - 3, flags, &mut widened)?;
+                self.walk_cache
+                    .set(vaddr, PageSize::Size2M, p3e.paddr(), flags);
+                p2
+            }
+        };
+        let p2e = &mut p2[Self::index_of(vaddr, M::LEVELS - 2)];
+        self.journal.stats.read(1);
+        if page_size == PageSize::Size2M {
+            return Ok((p2e, widened));
+        }
+
+        let p1 = self.next_table_mut_or_create(p2e, vaddr, M::LEVELS - 2, flags, &mut widened)?;
+        self.walk_cache
+            .set(vaddr, PageSize::Size4K, p2e.paddr(), flags);
+        let p1e = &mut p1[Self::index_of(vaddr, M::LEVELS - 1)];
+        self.journal.stats.read(1);
+        Ok((p1e, widened))
+    }
+
+    /// Widens the table entries above the `page_size` leaf entry of `vaddr`
+    /// to allow `flags`, like [`PageTable64::get_entry_mut_or_create`] for
+    /// existing mappings. Returns whether an entry was changed.
+    fn widen_tables(
         &mut self,
         vaddr: M::VirtAddr,
         page_size: PageSize,
-    ) -> PagingResult<&mut PTE> {
+        flags: MappingFlags,
+    ) -> bool {
         let vaddr: usize = vaddr.into();
-        let p3 = if M::LEVELS == 3 {
-            self.table_of_mut(self.root_paddr())
-        } else if M::LEVELS == 4 {
-            let p4 = self.table_of_mut(self.root_paddr());
-            let p4e = &mut p4[p4_index(vaddr)];
-            self.next_table_mut_or_create(p4e)?
+        if self
+            .walk_cache
+            .get_allowing(vaddr, page_size, flags)
+            .is_some()
+        {
+            return false;
+        }
+        let leaf_level = Self::leaf_level(page_size);
+        let mut widened = false;
+        let mut paddr = self.root_paddr;
+        for level in 0..leaf_level {
+            let entry = &mut self.table_of_mut(paddr, level)[Self::index_of(vaddr, level)];
+            let old = *entry;
+            widened |= entry.widen_table(flags);
+            Self::note(&mut self.journal, vaddr, level, old, *entry);
+            paddr = entry.paddr();
+        }
+        self.walk_cache.set(vaddr, page_size, paddr, flags);
+        widened
+    }
+
+    /// Returns the first present leaf of `table` and the tables below it
+    /// that maps `from` or a later address, with its address and level.
+    /// `table` is at `level` and covers the region from `table_vaddr`.
+    fn next_leaf(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        from: usize,
+    ) -> Option<(usize, PTE, usize)> {
+        let first = if from > table_vaddr {
+            Self::index_of(from, level)
         } else {
-            unreachable!()
+            0
         };
-        let p3e = &mut p3[p3_index(vaddr)];
-        if page_size == PageSize::Size1G {
-            return Ok(p3e);
+        for i in first..table_entries::<M>(level) {
+            let entry = Self::load_entry(table, i);
+            let vaddr = table_vaddr + i * Self::entry_size(level);
+            // Table entries are not marked present on LoongArch.
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let next = Self::next_table(&entry, vaddr, level).ok()?;
+                if let Some(leaf) = Self::next_leaf(next, level + 1, vaddr, from) {
+                    return Some(leaf);
+                }
+            } else if entry.is_present() {
+                return Some((vaddr, entry, level));
+            }
         }
+        None
+    }
 
-        let p2 = self.next_table_mut_or_create(p3e)?;
-        let p2e = &mut p2[p2_index(vaddr)];
-        if page_size == PageSize::Size2M {
-            return Ok(p2e);
+    /// Returns the first address of `range` that is not mapped by a present
+    /// leaf with the `required` flags in `table` and the tables below it, if
+    /// any. `table` is at `level` and covers the region from `table_vaddr`.
+    fn first_hole(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        required: MappingFlags,
+    ) -> Option<usize> {
+        let (from, end) = range;
+        let first = if from > table_vaddr {
+            Self::index_of(from, level)
+        } else {
+            0
+        };
+        for i in first..table_entries::<M>(level) {
+            let vaddr = table_vaddr + i * Self::entry_size(level);
+            if vaddr >= end {
+                break;
+            }
+            let entry = Self::load_entry(table, i);
+            // Table entries are not marked present on LoongArch.
+            if level < M::LEVELS - 1 && entry.is_table() {
+                let Ok(next) = Self::next_table(&entry, vaddr, level) else {
+                    return Some(vaddr.max(from));
+                };
+                if let Some(hole) = Self::first_hole(next, level + 1, vaddr, range, required) {
+                    return Some(hole);
+                }
+            } else if !entry.is_present()
+                || !entry.leaf_flags(level < M::LEVELS - 1).contains(required)
+            {
+                return Some(vaddr.max(from));
+            }
+        }
+        None
+    }
+
+    /// Returns the first address of `range` covered by an entry of `table` or
+    /// the tables below it that is not unused, if any. `table` is at `level`
+    /// and covers the region from `table_vaddr`.
+    fn first_used(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+    ) -> Option<usize> {
+        let (from, end) = range;
+        let first = if from > table_vaddr {
+            Self::index_of(from, level)
+        } else {
+            0
+        };
+        for i in first..table_entries::<M>(level) {
+            let vaddr = table_vaddr + i * Self::entry_size(level);
+            if vaddr >= end {
+                break;
+            }
+            let entry = Self::load_entry(table, i);
+            if entry.is_unused() {
+                continue;
+            }
+            match Self::table_below(&entry, vaddr, level) {
+                Some(next) => {
+                    if let Some(used) = Self::first_used(next, level + 1, vaddr, range) {
+                        return Some(used);
+                    }
+                }
+                None => return Some(vaddr.max(from)),
+            }
+        }
+        None
+    }
+
+    /// Returns the table `entry` at `level` points to, if it is not a leaf.
+    fn table_below(entry: &PTE, vaddr: usize, level: usize) -> Option<PhysAddr> {
+        // Table entries are not marked present on LoongArch.
+        if level < M::LEVELS - 1 && entry.is_table() {
+            Self::next_table(entry, vaddr, level).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Updates `gap`, the largest run of unused entries found so far as its
+    /// start and size, with the entries of `table` and the tables below it in
+    /// `range`, for [`PageTable64::max_free_gap`]. `cursor` is the end of the
+    /// last entry found that is not unused. `table` is at `level` and covers
+    /// the region from `table_vaddr`.
+    fn largest_gap(
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        cursor: &mut usize,
+        gap: &mut (usize, usize),
+    ) {
+        let (from, end) = range;
+        let first = if from > table_vaddr {
+            Self::index_of(from, level)
+        } else {
+            0
+        };
+        for i in first..table_entries::<M>(level) {
+            let vaddr = table_vaddr + i * Self::entry_size(level);
+            if vaddr >= end {
+                break;
+            }
+            let entry = Self::load_entry(table, i);
+            if entry.is_unused() {
+                continue;
+            }
+            if let Some(next) = Self::table_below(&entry, vaddr, level) {
+                Self::largest_gap(next, level + 1, vaddr, range, cursor, gap);
+                continue;
+            }
+            let start = vaddr.max(from);
+            if start - *cursor > gap.1 {
+                *gap = (*cursor, start - *cursor);
+            }
+            *cursor = (vaddr + Self::entry_size(level)).min(end);
+        }
+    }
+
+    /// Sets the bits in `out` of the granules of `walk` mapped by `table` and
+    /// the tables below it, for [`PageTable64::export_present_bitmap`].
+    /// `table` is at `level` and covers the region from `table_vaddr`.
+    fn present_recursive(
+        &self,
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        walk: &PresentWalk,
+        out: &[AtomicU64],
+    ) {
+        let (from, end) = walk.range;
+        let entry_size = Self::entry_size(level);
+        let first = if from > table_vaddr {
+            Self::index_of(from, level)
+        } else {
+            0
+        };
+        for i in first..table_entries::<M>(level) {
+            let vaddr = table_vaddr + i * entry_size;
+            if vaddr >= end {
+                break;
+            }
+            let entry = Self::load_entry(table, i);
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            if entry.is_unused() {
+                continue;
+            }
+            let first = (vaddr.max(from) - from) / walk.granule;
+            if let Some(next) = Self::table_below(&entry, vaddr, level) {
+                // The whole table is in a granule already set.
+                let set = out[first / 64].load(Ordering::Relaxed) & 1 << (first % 64) != 0;
+                if !(set && entry_size <= walk.granule) {
+                    self.present_recursive(next, level + 1, vaddr, walk, out);
+                }
+                continue;
+            }
+            let counted = Self::holds_frame(&entry)
+                || walk.include_absent
+                    && entry
+                        .absent()
+                        .is_some_and(|absent| !matches!(absent, AbsentEntry::Guard(_)));
+            if counted {
+                let last = ((vaddr + entry_size).min(end) - 1 - from) / walk.granule;
+                PresentBitmap::fill(out, first, last);
+            }
         }
+    }
 
-        let p1 = self.next_table_mut_or_create(p2e)?;
-        let p1e = &mut p1[p1_index(vaddr)];
-        Ok(p1e)
+    /// Adds the present leaves of `table` and the tables below it in `range`
+    /// to `summary`, for [`PageTable64::summarize`]. `table` is at `level`
+    /// and covers the region from `table_vaddr`.
+    fn summarize_recursive(
+        &self,
+        table: PhysAddr,
+        level: usize,
+        table_vaddr: usize,
+        range: (usize, usize),
+        summary: &mut RangeSummary,
+    ) {
+        let (from, end) = range;
+        let entry_size = Self::entry_size(level);
+        let first = if from > table_vaddr {
+            Self::index_of(from, level)
+        } else {
+            0
+        };
+        for i in first..table_entries::<M>(level) {
+            let vaddr = table_vaddr + i * entry_size;
+            if vaddr >= end {
+                break;
+            }
+            let entry = Self::load_entry(table, i);
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            // Table entries are not marked present on LoongArch.
+            if level < M::LEVELS - 1 && entry.is_table() {
+                if let Ok(next) = Self::next_table(&entry, vaddr, level) {
+                    self.summarize_recursive(next, level + 1, vaddr, range, summary);
+                }
+                continue;
+            }
+            if !entry.is_present() {
+                continue;
+            }
+            let bytes = (vaddr + entry_size).min(end) - vaddr.max(from);
+            let flags = entry.leaf_flags(level < M::LEVELS - 1);
+            summary.present_pages += bytes / PAGE_SIZE_4K;
+            summary.flags_union |= flags;
+            summary.flags_intersection &= flags;
+            summary.any_huge |= level < M::LEVELS - 1;
+            summary.any_cow |= flags.contains(MappingFlags::COW);
+            summary.any_dirty |= entry.is_dirty();
+        }
     }
 
     fn walk_recursive<F>(
         &self,
-        table: &[PTE],
+        table: PhysAddr,
         level: usize,
         start_vaddr: M::VirtAddr,
         limit: usize,
@@ -558,16 +7766,22 @@ impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> PageTable64<M, PTE, H
     {
         let start_vaddr_usize: usize = start_vaddr.into();
         let mut n = 0;
-        for (i, entry) in table.iter().enumerate() {
-            let vaddr_usize = start_vaddr_usize + (i << (12 + (M::LEVELS - 1 - level) * 9));
+        for i in 0..table_entries::<M>(level) {
+            let entry = &Self::load_entry(table, i);
+            self.journal
+                .stats
+                .visit(entry.is_unused(), level < M::LEVELS - 1);
+            let vaddr_usize = start_vaddr_usize + i * Self::entry_size(level);
             let vaddr = vaddr_usize.into();
 
-            if entry.is_present() {
+            // Table entries are not marked present on LoongArch.
+            let is_table = level < M::LEVELS - 1 && entry.is_table();
+            if entry.is_present() || is_table {
                 if let Some(func) = pre_func {
                     func(level, i, vaddr, entry);
                 }
-                if level < M::LEVELS - 1 && !entry.is_huge() {
-                    let table_entry = self.next_table(entry)?;
+                if is_table {
+                    let table_entry = Self::next_table(entry, vaddr_usize, level)?;
                     self.walk_recursive(table_entry, level + 1, vaddr, limit, pre_func, post_func)?;
                 }
                 if let Some(func) = post_func {
@@ -583,18 +7797,325 @@ impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> PageTable64<M, PTE, H
     }
 }
 
-impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> Drop for PageTable64<M, PTE, H> {
+/// The generation of the flushes of changes made without the lock of a
+/// [`LockedPageTable`](crate::LockedPageTable), which are not counted: it is
+/// never older than a full flush, so they are never skipped.
+#[cfg(feature = "locked")]
+const UNCOUNTED: u64 = u64::MAX;
+
+/// The operations of [`LockedPageTable`](crate::LockedPageTable) done without
+/// its lock, on the tables under `root`.
+///
+/// They hold no reference to the page table, which the holder of the lock
+/// may change meanwhile: the entries are only loaded and replaced atomically.
+/// Their changes are not recorded in the journal, the generation or the
+/// statistics.
+#[cfg(feature = "locked")]
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> PageTable64<M, PTE, H, K> {
+    /// Like [`PageTable64::query`].
+    pub(crate) fn query_at(
+        root: PhysAddr,
+        vaddr: M::VirtAddr,
+    ) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
+        let (_, entry, size) = Self::load_leaf(root, vaddr.into())?;
+        Self::translate(&entry, size, vaddr.into())
+    }
+
+    /// Like [`PageTable64::handle_access_fault`], starting over if the entry
+    /// changes before it is replaced.
+    pub(crate) fn access_fault_at(
+        root: PhysAddr,
+        vaddr: M::VirtAddr,
+        write: bool,
+    ) -> PagingResult<TlbFlush<M>> {
+        loop {
+            let (bits, old, size) = Self::load_leaf(root, vaddr.into())?;
+            if !old.is_present() {
+                return Err(PagingError::NotMapped);
+            }
+            let mut new = old;
+            new.set_accessed(true);
+            if write && old.leaf_flags(size.is_huge()).contains(MappingFlags::WRITE) {
+                new.set_dirty(true);
+            }
+            if Self::replace_leaf(bits, old, new).is_ok() {
+                let tlb = TlbFlush::new(vaddr).with_global(old.is_global());
+                return Ok(tlb.with_generation(UNCOUNTED));
+            }
+        }
+    }
+
+    /// Like [`PageTable64::handle_cow_fault`], but fails with
+    /// [`PagingError::Contended`] if the entry changes before it is replaced,
+    /// leaving the page returned by `copy` to the caller.
+    ///
+    /// `copy` is given back if the page is huge, as it may have to be split
+    /// (see [`HugeCowPolicy`]), or if the entry is in a group with the
+    /// contiguous hint, whose entries cannot be replaced at once.
+    pub(crate) fn cow_fault_at<F: FnOnce(PhysAddr, PageSize) -> Option<PhysAddr>>(
+        root: PhysAddr,
+        vaddr: M::VirtAddr,
+        copy: F,
+    ) -> Result<PagingResult<TlbFlush<M>>, F> {
+        let (bits, old, size) = match Self::load_leaf(root, vaddr.into()) {
+            Ok(leaf) => leaf,
+            Err(e) => return Ok(Err(e)),
+        };
+        if !old.is_present() {
+            return Ok(Err(PagingError::NotMapped));
+        }
+        let flags = old.leaf_flags(size.is_huge());
+        if !flags.contains(MappingFlags::COW) {
+            return Ok(Err(PagingError::NotCow));
+        }
+        if size.is_huge() || old.is_contiguous() {
+            return Err(copy);
+        }
+        let old_paddr = Self::leaf_paddr(&old, vaddr.into(), size.is_huge());
+        let Some(new_paddr) = copy(old_paddr, size) else {
+            return Ok(Err(PagingError::NoMemory));
+        };
+        if let Err(e) = Self::check_paddr(new_paddr, size) {
+            return Ok(Err(e));
+        }
+        let mut new = old;
+        new.set_leaf_paddr(new_paddr, size.is_huge());
+        new.set_flags(flags.resolve_cow(), size.is_huge());
+        if M::AD_POLICY != AccessedDirtyPolicy::AlwaysSet {
+            new.set_accessed(true);
+            new.set_dirty(true);
+        }
+        // Whether the table is active is only known under the lock, so a new
+        // frame is always mapped with break-before-make if needed.
+        let tlb = if M::BREAK_BEFORE_MAKE && new_paddr != old_paddr {
+            let mut invalid = old;
+            invalid.clear();
+            let last = match Self::replace_leaf(bits, old, invalid) {
+                Ok(last) => last,
+                Err(e) => return Ok(Err(e)),
+            };
+            Self::merge_hardware_bits(&old, &last, &mut new);
+            M::flush_tlb(Some(vaddr));
+            // The holder of the lock may have mapped the invalid entry since.
+            let made = bits.compare_exchange(
+                Self::pte_bits(invalid),
+                Self::pte_bits(new),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            if made.is_err() {
+                Self::frame_unshared(old_paddr, size);
+                return Ok(Err(PagingError::Contended));
+            }
+            TlbFlush::new_mapping(vaddr)
+        } else {
+            if let Err(e) = Self::replace_leaf(bits, old, new) {
+                return Ok(Err(e));
+            }
+            TlbFlush::new(vaddr).with_global(old.is_global())
+        };
+        if new_paddr != old_paddr {
+            Self::frame_unshared(old_paddr, size);
+        }
+        Ok(Ok(tlb.with_generation(UNCOUNTED)))
+    }
+
+    /// Returns the leaf entry of `vaddr` under `root` for atomic accesses,
+    /// with its value and the size of its page.
+    fn load_leaf<'a>(root: PhysAddr, vaddr: usize) -> PagingResult<(&'a AtomicU64, PTE, PageSize)> {
+        let mut table = root;
+        for level in 0..M::LEVELS {
+            let bits = Self::entry_at(table, Self::index_of(vaddr, level));
+            let entry = Self::pte_from_bits(bits.load(Ordering::Acquire));
+            let size = Self::leaf_size(level);
+            if level == M::LEVELS - 1 || entry.is_huge() && Self::page_size_supported(size) {
+                return Ok((bits, entry, size));
+            }
+            table = Self::next_table(&entry, vaddr, level)?;
+        }
+        unreachable!()
+    }
+
+    /// Replaces the leaf `bits`, read as `old`, with `new`, and returns the
+    /// last value of the entry.
+    ///
+    /// Like in [`PageTable64::write_leaf`], the accessed and dirty bits set
+    /// since `old` was read are merged into `new`. Other changes make it fail
+    /// with [`PagingError::Contended`].
+    fn replace_leaf(bits: &AtomicU64, mut old: PTE, mut new: PTE) -> PagingResult<PTE> {
+        let without_hardware_bits = |mut pte: PTE| {
+            pte.set_accessed(false);
+            pte.set_dirty(false);
+            Self::pte_bits(pte)
+        };
+        loop {
+            #[cfg(feature = "mock")]
+            crate::mock::race_leaf_write(bits);
+            let result = bits.compare_exchange(
+                Self::pte_bits(old),
+                Self::pte_bits(new),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            match result {
+                Ok(_) => return Ok(old),
+                Err(current) => {
+                    let current = Self::pte_from_bits(current);
+                    if without_hardware_bits(current) != without_hardware_bits(old) {
+                        return Err(PagingError::Contended);
+                    }
+                    Self::merge_hardware_bits(&old, &current, &mut new);
+                    old = current;
+                }
+            }
+        }
+    }
+}
+
+/// The number of mapped regions in the [`Debug`](fmt::Debug) output of a
+/// [`PageTable64`], and in its alternate form.
+const DEBUG_REGIONS: (usize, usize) = (4, 16);
+
+/// A summary of the page table on a single line: its architecture, root,
+/// generation, counters (and statistics with the `trace` feature), and its
+/// first mapped regions, coalesced like by
+/// [`MappingCursor`](crate::MappingCursor).
+///
+/// The alternate form (`{:#?}`) is on several lines, and adds the number of
+/// entries in use at each level (see [`PageTable64::level_occupancy`]) and
+/// more regions. Either is bounded, does not allocate, and only reads the
+/// entries atomically, so that it can be printed when panicking, including
+/// for a page table in use.
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> fmt::Debug
+    for PageTable64<M, PTE, H, K>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut s = f.debug_struct("PageTable64");
+        s.field("arch", &M::ARCH_NAME)
+            .field("root", &format_args!("{:#x}", self.root_paddr))
+            .field("generation", &self.generation)
+            .field("mapped_bytes", &format_args!("{:#x}", self.mapped_bytes))
+            .field("table_frames", &self.table_frames);
+        #[cfg(feature = "trace")]
+        s.field("stats", &self.stats());
+        let limit = if alternate {
+            s.field("level_occupancy", &&self.level_occupancy()[..M::LEVELS]);
+            DEBUG_REGIONS.1
+        } else {
+            DEBUG_REGIONS.0
+        };
+        s.field("regions", &DebugRegions { table: self, limit });
+        s.finish()
+    }
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> PageTableInfo
+    for PageTable64<M, PTE, H, K>
+{
+    fn levels(&self) -> usize {
+        M::LEVELS
+    }
+
+    fn va_bits(&self) -> usize {
+        M::VA_MAX_BITS
+    }
+
+    fn pa_bits(&self) -> usize {
+        M::PA_MAX_BITS
+    }
+
+    fn page_sizes(&self) -> &'static [PageSize] {
+        use PageSize::*;
+        static WITH_CONTIGUOUS: [PageSize; 5] = [Size4K, Size64K, Size2M, Size1G, Size512G];
+        static WITHOUT_CONTIGUOUS: [PageSize; 4] = [Size4K, Size2M, Size1G, Size512G];
+        let sizes: &'static [PageSize] = if PTE::CONTIGUOUS_HINT {
+            &WITH_CONTIGUOUS
+        } else {
+            &WITHOUT_CONTIGUOUS
+        };
+        let count = sizes
+            .iter()
+            .take_while(|&&size| Self::page_size_supported(size))
+            .count();
+        &sizes[..count]
+    }
+
+    fn arch_name(&self) -> &'static str {
+        M::ARCH_NAME
+    }
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> AnyPageTable
+    for PageTable64<M, PTE, H, K>
+{
+    fn root_paddr(&self) -> PhysAddr {
+        PageTable64::root_paddr(self)
+    }
+
+    fn query(&self, vaddr: usize) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
+        PageTable64::query(self, vaddr.into())
+    }
+
+    fn next_mapping(&self, vaddr: usize) -> Option<Mapping> {
+        // The offset of `vaddr` in the region covered by the root table. The
+        // addresses between the halves start the upper one.
+        let span = 1usize << M::VA_MAX_BITS;
+        let from = if vaddr < span {
+            vaddr
+        } else if M::vaddr_is_valid(vaddr) {
+            vaddr & (span - 1)
+        } else if vaddr < Self::sign_extended(span / 2).into() {
+            span / 2
+        } else {
+            return None;
+        };
+        let (start, entry, level) = Self::next_leaf(self.root_paddr(), 0, 0, from)?;
+        let (start, size) = if entry.is_contiguous() {
+            let start = start & !(PageSize::Size64K as usize - 1);
+            (start, PageSize::Size64K)
+        } else {
+            (start, Self::leaf_size(level))
+        };
+        Some(Mapping {
+            vaddr: Self::sign_extended(start).into(),
+            paddr: Self::leaf_paddr(&entry, start, size.is_huge()),
+            flags: entry.leaf_flags(size.is_huge()),
+            size,
+        })
+    }
+    fn generation(&self) -> u64 {
+        PageTable64::generation(self)
+    }
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind> Drop
+    for PageTable64<M, PTE, H, K>
+{
     fn drop(&mut self) {
+        let active = self.is_active();
+        debug_assert!(
+            !active,
+            "page table {:#x} dropped while active",
+            self.root_paddr
+        );
+        if active {
+            error!(
+                "page table {:#x} dropped while active, leaking its tables",
+                self.root_paddr,
+            );
+            return;
+        }
         // don't free the entries in last level, they are not array.
         let _ = self.walk(
             usize::MAX,
             None,
             Some(&|level, _index, _vaddr, entry: &PTE| {
-                if level < M::LEVELS - 1 && entry.is_present() && !entry.is_huge() {
-                    H::dealloc_frame(entry.paddr());
+                if level < M::LEVELS - 1 && entry.is_table() {
+                    Self::dealloc_table(entry.paddr(), level + 1);
                 }
             }),
         );
-        H::dealloc_frame(self.root_paddr());
+        Self::dealloc_table(self.root_paddr(), 0);
     }
 }
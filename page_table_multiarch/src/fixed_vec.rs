@@ -0,0 +1,160 @@
+//! Lists returned in buffers provided by the caller, without allocating.
+
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::{fmt, ptr};
+
+use crate::{PagingError, PagingResult};
+
+/// A vector of at most a fixed number of elements, stored in a buffer
+/// provided by the caller, for the operations returning lists.
+///
+/// The operations push all their elements with [`FixedVec::push`]. Those that
+/// do not fit are counted, and the operation then fails with
+/// [`PagingError::BufferTooSmall`], giving the length the buffer needs. The
+/// vector keeps the elements that fit.
+///
+/// With the `alloc` feature, [`collect_vec`] runs such an operation into a
+/// `Vec`.
+pub struct FixedVec<'a, T> {
+    buf: &'a mut [MaybeUninit<T>],
+    len: usize,
+    /// The number of elements pushed when the buffer was full.
+    missing: usize,
+}
+
+impl<'a, T> FixedVec<'a, T> {
+    /// Creates an empty vector in `buf`.
+    pub const fn new(buf: &'a mut [MaybeUninit<T>]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            missing: 0,
+        }
+    }
+
+    /// The number of elements the buffer can hold.
+    pub const fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The number of elements stored.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether no element is stored.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of elements pushed since the vector was created or
+    /// cleared, including those that did not fit.
+    pub const fn needed(&self) -> usize {
+        self.len + self.missing
+    }
+
+    /// Appends `value` if the buffer has room, and returns whether it did.
+    /// Otherwise `value` is dropped and counted by [`FixedVec::needed`].
+    pub fn push(&mut self, value: T) -> bool {
+        match self.buf.get_mut(self.len) {
+            Some(slot) => {
+                slot.write(value);
+                self.len += 1;
+                true
+            }
+            None => {
+                self.missing += 1;
+                false
+            }
+        }
+    }
+
+    /// Removes all the elements, and forgets the ones that did not fit.
+    pub fn clear(&mut self) {
+        let len = core::mem::take(&mut self.len);
+        self.missing = 0;
+        // SAFETY: the first `len` elements are initialized, and no longer
+        // part of the vector.
+        unsafe { ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.as_mut_ptr(), len)) }
+    }
+
+    /// Fails with [`PagingError::BufferTooSmall`] if an element did not fit.
+    pub const fn check(&self) -> PagingResult {
+        if self.missing == 0 {
+            Ok(())
+        } else {
+            Err(PagingError::BufferTooSmall {
+                needed: self.needed(),
+            })
+        }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.as_mut_ptr().cast()
+    }
+
+    /// Returns the length, leaving the elements in the buffer.
+    #[cfg(feature = "alloc")]
+    fn into_len(self) -> usize {
+        let len = self.len;
+        core::mem::forget(self);
+        len
+    }
+}
+
+impl<T> Deref for FixedVec<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: the first `len` elements are initialized.
+        unsafe { core::slice::from_raw_parts(self.buf.as_ptr().cast(), self.len) }
+    }
+}
+
+impl<T> DerefMut for FixedVec<'_, T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: the first `len` elements are initialized.
+        unsafe { core::slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for FixedVec<'_, T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FixedVec<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// Runs `fill`, an operation returning a list in a [`FixedVec`], into a
+/// `Vec`, and returns the list with the result of `fill`.
+///
+/// `fill` first runs with an empty buffer, and again with the length it
+/// needs for as long as it fails with [`PagingError::BufferTooSmall`].
+#[cfg(feature = "alloc")]
+pub fn collect_vec<T, R>(
+    mut fill: impl FnMut(&mut FixedVec<'_, T>) -> PagingResult<R>,
+) -> PagingResult<(alloc::vec::Vec<T>, R)> {
+    let mut vec = alloc::vec::Vec::new();
+    loop {
+        let mut out = FixedVec::new(vec.spare_capacity_mut());
+        match fill(&mut out) {
+            Ok(ret) => {
+                let len = out.into_len();
+                // SAFETY: `out` initialized the first `len` elements.
+                unsafe { vec.set_len(len) };
+                return Ok((vec, ret));
+            }
+            Err(PagingError::BufferTooSmall { needed }) => {
+                drop(out);
+                vec.reserve_exact(needed);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
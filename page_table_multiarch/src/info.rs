@@ -0,0 +1,368 @@
+//! A view of page tables that does not depend on their generic parameters,
+//! for tools inspecting page tables of several architectures.
+
+use core::fmt;
+use core::ops::ControlFlow;
+
+use memory_addr::{MemoryAddr, PhysAddr};
+
+use crate::{FixedVec, MappingFlags, PageSize, PagingResult};
+
+/// The layout of a page table, queried at runtime.
+///
+/// It is implemented by every [`PageTable64`](crate::PageTable64), from the
+/// constants of its [`PagingMetaData`](crate::PagingMetaData) and entry
+/// format, and can be used as a trait object.
+pub trait PageTableInfo {
+    /// The number of levels of the page table (see
+    /// [`PagingMetaData::LEVELS`](crate::PagingMetaData::LEVELS)).
+    fn levels(&self) -> usize;
+
+    /// The number of bits of the virtual addresses translated by the page
+    /// table (see
+    /// [`PagingMetaData::VA_MAX_BITS`](crate::PagingMetaData::VA_MAX_BITS)).
+    fn va_bits(&self) -> usize;
+
+    /// The number of bits of the physical addresses it maps to (see
+    /// [`PagingMetaData::PA_MAX_BITS`](crate::PagingMetaData::PA_MAX_BITS)).
+    fn pa_bits(&self) -> usize;
+
+    /// The sizes of the pages it can map, from the smallest.
+    fn page_sizes(&self) -> &'static [PageSize];
+
+    /// The name of the architecture (see
+    /// [`PagingMetaData::ARCH_NAME`](crate::PagingMetaData::ARCH_NAME)).
+    fn arch_name(&self) -> &'static str;
+}
+
+/// A mapping found by [`AnyPageTable::next_mapping`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Mapping {
+    /// The first virtual address of the page, sign-extended if the page table
+    /// translates virtual addresses.
+    pub vaddr: usize,
+    /// The physical address of the page.
+    pub paddr: PhysAddr,
+    /// The flags of the mapping.
+    pub flags: MappingFlags,
+    /// The size of the page. A group of 4K entries with the contiguous hint
+    /// is one [`PageSize::Size64K`] page.
+    pub size: PageSize,
+}
+
+impl Mapping {
+    /// The address after the last byte of the page, or [`None`] if the page
+    /// ends at the top of the address space.
+    pub const fn end(&self) -> Option<usize> {
+        self.vaddr.checked_add(self.size as usize)
+    }
+}
+
+/// A read-only view of a page table whose metadata, entry format, and
+/// handler are erased, e.g. `&dyn AnyPageTable`.
+///
+/// The addresses are plain `usize`s instead of
+/// [`PagingMetaData::VirtAddr`](crate::PagingMetaData::VirtAddr). Changing
+/// the mappings requires the [`PageTable64`](crate::PageTable64) itself.
+pub trait AnyPageTable: PageTableInfo {
+    /// The physical address of the root table.
+    fn root_paddr(&self) -> PhysAddr;
+
+    /// Queries the mapping of `vaddr`, like
+    /// [`PageTable64::query`](crate::PageTable64::query): the physical
+    /// address is the translation of `vaddr` itself, not the base of the
+    /// frame.
+    fn query(&self, vaddr: usize) -> PagingResult<(PhysAddr, MappingFlags, PageSize)>;
+
+    /// Returns the first mapping containing `vaddr` or after it, in the order
+    /// of the addresses.
+    fn next_mapping(&self, vaddr: usize) -> Option<Mapping>;
+
+    /// The generation of the page table (see
+    /// [`PageTable64::generation`](crate::PageTable64::generation)).
+    fn generation(&self) -> u64;
+}
+
+impl<'a> dyn AnyPageTable + 'a {
+    /// Returns an iterator over the mappings, in the order of the addresses.
+    pub fn iter(&self) -> Mappings<'_, 'a> {
+        Mappings {
+            table: self,
+            next: Some(0),
+        }
+    }
+
+    /// Writes the layout of the page table, then one line per mapping with
+    /// its addresses, size, and flags.
+    pub fn dump(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(
+            out,
+            "{}: {} levels, {}-bit VA, {}-bit PA, root {:#x}",
+            self.arch_name(),
+            self.levels(),
+            self.va_bits(),
+            self.pa_bits(),
+            self.root_paddr(),
+        )?;
+        for mapping in self.iter() {
+            writeln!(
+                out,
+                "{:#018x} -> {:#x} {:?} {:?}",
+                mapping.vaddr, mapping.paddr, mapping.size, mapping.flags,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An iterator over the mappings of an [`AnyPageTable`], returned by its
+/// `iter` method.
+pub struct Mappings<'t, 'a> {
+    table: &'t (dyn AnyPageTable + 'a),
+    next: Option<usize>,
+}
+
+impl Iterator for Mappings<'_, '_> {
+    type Item = Mapping;
+
+    fn next(&mut self) -> Option<Mapping> {
+        let mapping = self.table.next_mapping(self.next?)?;
+        self.next = mapping.end();
+        Some(mapping)
+    }
+}
+
+/// Consecutive mappings with the same flags, of consecutive physical
+/// addresses, returned by [`MappingCursor::next_region`] and
+/// [`PageTable64::collect_regions`](crate::PageTable64::collect_regions).
+///
+/// The mappings are coalesced whatever their page sizes, e.g. a 2M page
+/// followed by 4K pages with the same flags that map the frames after its
+/// own is one region, but the holes and any change of flags or gap in the
+/// physical addresses end it.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct MappedRegion {
+    /// The first virtual address of the region, sign-extended like
+    /// [`Mapping::vaddr`].
+    pub vaddr: usize,
+    /// The physical address of the first byte of the region.
+    pub paddr: PhysAddr,
+    /// The size of the region in bytes.
+    pub size: usize,
+    /// The flags of the mappings.
+    pub flags: MappingFlags,
+    /// The generation of the page table when the region was read.
+    pub generation: u64,
+}
+
+impl MappedRegion {
+    /// The address after the last byte of the region, or [`None`] if the
+    /// region ends at the top of the address space.
+    pub const fn end(&self) -> Option<usize> {
+        self.vaddr.checked_add(self.size)
+    }
+
+    /// Appends `next` if it continues the region, and returns whether it
+    /// did.
+    fn extend(&mut self, next: &MappedRegion) -> bool {
+        let continues = self.end() == Some(next.vaddr)
+            && self.paddr.as_usize().checked_add(self.size) == Some(next.paddr.as_usize())
+            && self.flags == next.flags;
+        if continues {
+            self.size += next.size;
+        }
+        continues
+    }
+}
+
+/// Reads the mappings of an [`AnyPageTable`] as [`MappedRegion`]s, in the
+/// order of the addresses, from any address.
+///
+/// Each region is found with [`AnyPageTable::next_mapping`] from the end of
+/// the previous one, so a cursor can be dropped and created again later at
+/// the end of the last region read without reading the ones before it, e.g.
+/// to report the regions in chunks. The page table may change in between:
+/// the cursor then goes on with the mappings after the last region, as they
+/// are at that time. [`MappingCursor::resume`] also merges the mappings that
+/// continue the last region, if the page table has not changed.
+pub struct MappingCursor<'t, 'a> {
+    table: &'t (dyn AnyPageTable + 'a),
+    next: Option<usize>,
+    pending: Option<MappedRegion>,
+}
+
+impl<'t, 'a> MappingCursor<'t, 'a> {
+    /// Creates a cursor at the regions containing `vaddr` or after it.
+    ///
+    /// A region containing `vaddr` starts at its first mapping containing
+    /// `vaddr` or after it.
+    pub fn new(table: &'t (dyn AnyPageTable + 'a), vaddr: usize) -> Self {
+        Self {
+            table,
+            next: Some(vaddr),
+            pending: None,
+        }
+    }
+
+    /// Creates a cursor after `last`, the last region read by an earlier
+    /// cursor.
+    ///
+    /// If the page table has the generation of `last`, the first region
+    /// returned is `last` itself, extended by the mappings that continue it
+    /// (if any), so that the caller can report a region split across chunks
+    /// as one. Otherwise `last` may be outdated, and the first region is the
+    /// one after it, as for [`MappingCursor::new`].
+    pub fn resume(table: &'t (dyn AnyPageTable + 'a), last: &MappedRegion) -> Self {
+        let pending = (table.generation() == last.generation).then_some(*last);
+        Self {
+            table,
+            next: last.end(),
+            pending,
+        }
+    }
+
+    /// Returns the next region, or [`None`] at the end of the address space.
+    pub fn next_region(&mut self) -> Option<MappedRegion> {
+        while let Some(vaddr) = self.next {
+            let Some(mapping) = self.table.next_mapping(vaddr) else {
+                self.next = None;
+                break;
+            };
+            self.next = mapping.end();
+            let region = MappedRegion {
+                vaddr: mapping.vaddr,
+                paddr: mapping.paddr,
+                size: mapping.size as usize,
+                flags: mapping.flags,
+                generation: self.table.generation(),
+            };
+            match &mut self.pending {
+                Some(pending) => {
+                    if !pending.extend(&region) {
+                        return self.pending.replace(region);
+                    }
+                }
+                None => self.pending = Some(region),
+            }
+        }
+        self.pending.take()
+    }
+}
+
+impl Iterator for MappingCursor<'_, '_> {
+    type Item = MappedRegion;
+
+    fn next(&mut self) -> Option<MappedRegion> {
+        self.next_region()
+    }
+}
+
+/// Receives the regions found by
+/// [`PageTable64::collect_regions`](crate::PageTable64::collect_regions),
+/// e.g. to build the list of memory areas of an address space adopted from
+/// a page table.
+///
+/// It is implemented by closures, by [`FixedVec`] (which counts the regions
+/// that do not fit, see [`FixedVec::check`]), and by `Vec` with the `alloc`
+/// feature.
+pub trait RegionSink {
+    /// Receives the next region. Returning [`ControlFlow::Break`] stops the
+    /// search, e.g. once the regions of interest are found.
+    fn push(&mut self, region: MappedRegion) -> ControlFlow<()>;
+}
+
+impl<F: FnMut(MappedRegion) -> ControlFlow<()>> RegionSink for F {
+    fn push(&mut self, region: MappedRegion) -> ControlFlow<()> {
+        self(region)
+    }
+}
+
+impl RegionSink for FixedVec<'_, MappedRegion> {
+    fn push(&mut self, region: MappedRegion) -> ControlFlow<()> {
+        FixedVec::push(self, region);
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl RegionSink for alloc::vec::Vec<MappedRegion> {
+    fn push(&mut self, region: MappedRegion) -> ControlFlow<()> {
+        alloc::vec::Vec::push(self, region);
+        ControlFlow::Continue(())
+    }
+}
+
+/// Passes the regions of `table` in `[start, last]` to `sink`, for
+/// [`PageTable64::collect_regions`](crate::PageTable64::collect_regions).
+pub(crate) fn collect_regions(
+    table: &dyn AnyPageTable,
+    start: usize,
+    last: usize,
+    sink: &mut impl RegionSink,
+) {
+    let generation = table.generation();
+    let mut next = Some(start);
+    let mut pending: Option<MappedRegion> = None;
+    while let Some(vaddr) = next {
+        let Some(mapping) = table.next_mapping(vaddr) else {
+            break;
+        };
+        if mapping.vaddr > last {
+            break;
+        }
+        next = mapping.end().filter(|&end| end <= last);
+        // Only the part of the page in the range.
+        let skip = start.saturating_sub(mapping.vaddr);
+        let end = mapping.vaddr + (mapping.size as usize - 1);
+        let region = MappedRegion {
+            vaddr: mapping.vaddr + skip,
+            paddr: mapping.paddr.add(skip),
+            size: end.min(last) - mapping.vaddr - skip + 1,
+            flags: mapping.flags,
+            generation,
+        };
+        if pending
+            .as_mut()
+            .is_some_and(|pending| pending.extend(&region))
+        {
+            continue;
+        }
+        let done = pending.replace(region);
+        if done.is_some_and(|done| sink.push(done).is_break()) {
+            return;
+        }
+    }
+    if let Some(done) = pending {
+        let _ = sink.push(done);
+    }
+}
+
+/// The first `limit` regions of a page table read by a [`MappingCursor`], for
+/// the [`Debug`](fmt::Debug) output of
+/// [`PageTable64`](crate::PageTable64), followed by `..` if there are more.
+///
+/// Like the cursor, it neither allocates nor panics.
+pub(crate) struct DebugRegions<'t, 'a> {
+    pub table: &'t (dyn AnyPageTable + 'a),
+    pub limit: usize,
+}
+
+impl fmt::Debug for DebugRegions<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut cursor = MappingCursor::new(self.table, 0);
+        let mut list = f.debug_list();
+        for region in cursor.by_ref().take(self.limit) {
+            list.entry(&format_args!(
+                "{:#x}..{:#x} -> {:#x} {:?}",
+                region.vaddr,
+                region.vaddr.wrapping_add(region.size),
+                region.paddr,
+                region.flags,
+            ));
+        }
+        if cursor.next_region().is_some() {
+            list.entry(&format_args!(".."));
+        }
+        list.finish()
+    }
+}
@@ -1,22 +1,55 @@
 #![cfg_attr(not(test), no_std)]
-#![cfg_attr(doc, feature(doc_auto_cfg))]
+#![cfg_attr(doc, feature(doc_cfg))]
 #![doc = include_str!("../README.md")]
 
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 mod arch;
 mod bits64;
+mod fixed_vec;
+mod info;
+#[cfg(feature = "locked")]
+mod locked;
+mod pti;
+mod space;
+
+#[cfg(feature = "mock")]
+pub mod mock;
 
+use core::sync::atomic::{AtomicU64, Ordering, compiler_fence};
 use core::{fmt::Debug, marker::PhantomData};
 
-use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+use memory_addr::{AddrRange, MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
 
 pub use self::arch::*;
-pub use self::bits64::PageTable64;
+pub use self::bits64::{
+    DetachedSubtree, FixedSlot, MAX_LEVELS, PageTable64, PhysFrameSlot, ProtectSession, SavedEntry,
+    SharedSubtree, Transaction,
+};
+pub use self::fixed_vec::FixedVec;
+#[cfg(feature = "alloc")]
+pub use self::fixed_vec::collect_vec;
+pub use self::info::{
+    AnyPageTable, MappedRegion, Mapping, MappingCursor, Mappings, PageTableInfo, RegionSink,
+};
+#[cfg(feature = "locked")]
+pub use self::locked::LockedPageTable;
+pub use self::pti::PtiPair;
+pub use self::space::{AnySpace, CowSpace, KernelSpace, SharedSpace, SpaceKind, UserSpace};
 
+pub use page_table_entry::NonPresentPayload;
+pub use page_table_entry::WpReasons;
+#[cfg(feature = "interop")]
+#[doc(no_inline)]
+pub use page_table_entry::interop;
 #[doc(no_inline)]
-pub use page_table_entry::{GenericPTE, MappingFlags};
+pub use page_table_entry::{AbsentEntry, AccessContext, AccessType, GenericPTE, MappingFlags};
+#[doc(no_inline)]
+pub use page_table_entry::{BigEndian, ByteOrder, EndianPTE, LittleEndian};
 
 /// The error type for page table operation failures.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -29,9 +62,691 @@ pub enum PagingError {
     NotMapped,
     /// The mapping is already present.
     AlreadyMapped,
-    /// The page table entry represents a huge page, but the target physical
-    /// frame is 4K in size.
-    MappedToHugePage,
+    /// A huge page is mapped where a table is needed (e.g. to map a smaller
+    /// page in it), or the address is not the start of the huge page.
+    MappedToHugePage {
+        /// The start of the huge page.
+        vaddr: usize,
+        /// The level of its entry, starting with the root.
+        level: usize,
+    },
+    /// The mapping is not copy-on-write.
+    NotCow,
+    /// The page size is not supported by the page table entry format, or is
+    /// larger than [`PagingMetaData::MAX_PAGE_SIZE`].
+    UnsupportedPageSize,
+    /// The physical address is not valid for
+    /// [`PagingMetaData::paddr_is_valid`] (by default, beyond
+    /// [`PagingMetaData::PA_MAX_BITS`]), or not aligned to the page size.
+    InvalidPaddr(PhysAddr),
+    /// The region starting at the virtual address is not in the address space
+    /// of [`PagingMetaData::VA_MAX_BITS`], or crosses the gap between its
+    /// lower and upper halves.
+    InvalidVaddr(usize),
+    /// The mapping is not present, but its entry carries a payload.
+    Absent(AbsentEntry),
+    /// The mapping does not belong to the kind of address space of the page
+    /// table (see [`SpaceKind`]).
+    WrongSpace,
+    /// The operation would exceed a limit set by [`PageTable64::set_limits`].
+    QuotaExceeded {
+        /// The limited resource.
+        kind: QuotaKind,
+        /// The limit of the resource.
+        limit: usize,
+        /// The total usage the operation would have reached.
+        requested: usize,
+    },
+    /// The mapping of the frame at the address would have another memory type
+    /// than the one required by [`PagingHandler::memory_type_of`].
+    AttributeConflict(PhysAddr),
+    /// The mapping is not a 4K page write-protected for dirty logging (see
+    /// [`PageTable64::enable_dirty_log`]).
+    NotLogged,
+    /// The entry was changed by another CPU while it was being replaced
+    /// without a lock, by the `LockedPageTable` of the `locked` feature. The
+    /// operation can be retried.
+    Contended,
+    /// Tables were unlinked from the page table since the [`FixedSlot`] was
+    /// created, so its table may be gone.
+    StaleSlot,
+    /// The page is locked by [`PageTable64::lock_region`], and can only be
+    /// unmapped by force.
+    Locked,
+    /// The region of an operation staged in a [`Transaction`] overlaps the
+    /// one of an earlier operation of the transaction, other than a region
+    /// mapped over pages it unmaps.
+    Overlapping,
+    /// The buffer of a [`FixedVec`] is too small for the list returned by
+    /// the operation, or the words given to
+    /// [`PageTable64::export_present_bitmap`] for its bitmap.
+    BufferTooSmall {
+        /// The number of elements of the list, or of words of the bitmap.
+        needed: usize,
+    },
+    /// The page is a guard page of the allocation made by
+    /// [`PageTable64::map_alloc_with`] at the virtual address, and must stay
+    /// unmapped while the allocation is mapped.
+    Guarded(usize),
+    /// The mapping at the virtual address would be both writable and
+    /// executable, which the page table refuses (see
+    /// [`PageTable64::enforce_wx`]).
+    WxViolation(usize),
+}
+
+/// The resources of a page table that can be limited by
+/// [`PageTable64::set_limits`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum QuotaKind {
+    /// The size of the mapped memory, in bytes.
+    MappedBytes,
+    /// The number of frames used for the page tables, including the root.
+    TableFrames,
+}
+
+/// The memory type of a mapping, as given by [`MappingFlags::DEVICE`] and
+/// [`MappingFlags::UNCACHED`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MemoryType {
+    /// Normal cached memory, with neither flag.
+    Normal,
+    /// Normal uncached memory, with [`MappingFlags::UNCACHED`].
+    Uncached,
+    /// Device memory, with [`MappingFlags::DEVICE`], whether or not
+    /// [`MappingFlags::UNCACHED`] is also set.
+    Device,
+}
+
+impl MemoryType {
+    /// Returns the memory type of a mapping with `flags`.
+    pub const fn of(flags: MappingFlags) -> Self {
+        if flags.contains(MappingFlags::DEVICE) {
+            Self::Device
+        } else if flags.contains(MappingFlags::UNCACHED) {
+            Self::Uncached
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+/// A cache maintenance operation requested by [`PageTable64::retype_region`]
+/// from [`PagingHandler::cache_maintain`], on frames whose cacheability
+/// changes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CacheOp {
+    /// Writes the dirty lines back to memory and invalidates every line, for
+    /// normal memory that is no longer cacheable: the writes made through the
+    /// old mapping reach memory, and no line outlives it.
+    CleanInvalidate,
+    /// Invalidates the lines without writing them back, for memory that
+    /// becomes normal cacheable memory: its contents are in memory, and lines
+    /// filled earlier (e.g. speculatively, through another cacheable mapping)
+    /// would hide them.
+    Invalidate,
+}
+
+/// How [`PageTable64::map_alloc_with`] maps an allocation, starting from
+/// [`AllocOptions::new`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct AllocOptions {
+    pub(crate) allow_huge: bool,
+    pub(crate) guard_before: usize,
+    pub(crate) guard_after: usize,
+}
+
+impl AllocOptions {
+    /// 4K pages only, without guard pages.
+    pub const fn new() -> Self {
+        Self {
+            allow_huge: false,
+            guard_before: 0,
+            guard_after: 0,
+        }
+    }
+
+    /// Maps the 2M-aligned blocks with 2M pages where possible, like
+    /// `allow_huge` of [`PageTable64::map_alloc`].
+    pub const fn allow_huge(mut self, allow_huge: bool) -> Self {
+        self.allow_huge = allow_huge;
+        self
+    }
+
+    /// Surrounds the allocation with `before` 4K guard pages before it and
+    /// `after` after it, which stay unmapped to catch overflows.
+    pub const fn guarded(mut self, before: usize, after: usize) -> Self {
+        self.guard_before = before;
+        self.guard_after = after;
+        self
+    }
+}
+
+/// A loadable segment of an ELF file, as described by its program header,
+/// for [`PageTable64::map_elf_segments`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ElfSegment {
+    /// The virtual address of the segment (`p_vaddr`).
+    pub vaddr: usize,
+    /// The size of the segment in memory (`p_memsz`).
+    pub memsz: usize,
+    /// The size of the segment in the file (`p_filesz`).
+    pub filesz: usize,
+    /// The physical address of the segment data, i.e. of the byte at
+    /// `p_offset` in the file. The file must be contiguous in physical memory
+    /// from the start of the page containing it.
+    pub paddr: PhysAddr,
+    /// The permissions of the segment (`p_flags`).
+    pub flags: u32,
+}
+
+impl ElfSegment {
+    /// The segment is executable.
+    pub const PF_X: u32 = 1;
+    /// The segment is writable.
+    pub const PF_W: u32 = 2;
+    /// The segment is readable.
+    pub const PF_R: u32 = 4;
+
+    /// Converts the permissions of the segment into [`MappingFlags`].
+    pub const fn mapping_flags(&self) -> MappingFlags {
+        let mut flags = MappingFlags::empty();
+        if self.flags & Self::PF_R != 0 {
+            flags = flags.union(MappingFlags::READ);
+        }
+        if self.flags & Self::PF_W != 0 {
+            flags = flags.union(MappingFlags::WRITE);
+        }
+        if self.flags & Self::PF_X != 0 {
+            flags = flags.union(MappingFlags::EXECUTE);
+        }
+        flags
+    }
+}
+
+/// Pages mapped at the same virtual address into many page tables with
+/// [`PageTable64::apply_shared`], e.g. the vDSO text and data of every
+/// process.
+///
+/// The frames belong to the caller: they are not reference counted, stay
+/// shared by [`PageTable64::clone_cow`] as they are read-only, and are not
+/// freed when unmapped.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SharedFixedMapping<'a> {
+    /// The virtual address of the first page.
+    pub vaddr: usize,
+    /// The frame and the flags of each 4K page from `vaddr`, which must not
+    /// be writable.
+    pub pages: &'a [(PhysAddr, MappingFlags)],
+}
+
+/// Who sets the accessed and dirty bits of the leaf entries, as given by
+/// [`PagingMetaData::AD_POLICY`].
+///
+/// [`PageTable64::is_accessed`] and [`PageTable64::is_dirty`] only tell
+/// whether a page was accessed or written since it was mapped if the bits
+/// start clear, i.e. with [`AccessedDirtyPolicy::HardwareManaged`] or
+/// [`AccessedDirtyPolicy::SoftwareManaged`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccessedDirtyPolicy {
+    /// New entries get the bits set by [`GenericPTE::new_page`], so that no
+    /// access faults for them on cores that do not set them in hardware: the
+    /// A and D bits on RISC-V, the access flag on AArch64, and the D bit of
+    /// writable pages on LoongArch. x86 sets none, since the hardware always
+    /// manages them. This is safe everywhere, and the default.
+    AlwaysSet,
+    /// New entries have both bits clear, and the hardware sets them on
+    /// access. Only safe on x86, on RISC-V with Svadu, and on AArch64 with
+    /// hardware management of the access flag (`TCR_ELx.HA`); a write to a
+    /// page with a clear D bit faults on LoongArch.
+    HardwareManaged,
+    /// New entries have both bits clear, and the resulting faults are
+    /// resolved with [`PageTable64::handle_access_fault`].
+    SoftwareManaged,
+}
+
+/// How [`PageTable64::handle_cow_fault`] resolves a write fault on a huge
+/// copy-on-write page, as set by [`PageTable64::set_huge_cow_policy`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum HugeCowPolicy {
+    /// Splits the huge page into 4K pages that stay shared, and copies only
+    /// the one written to. The default, so that writing a byte after a fork
+    /// does not copy 2M.
+    #[default]
+    Split,
+    /// Copies the whole huge page, which keeps the memory mapped with huge
+    /// pages, like transparent huge pages on Linux.
+    CopyWhole,
+}
+
+/// How [`PageTable64::clone_cow_filtered`] clones a page into the child.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CloneAction {
+    /// Shares the page copy-on-write, like [`PageTable64::clone_cow`].
+    Cow,
+    /// Maps the same page into the child as is, e.g. for read-only or shared
+    /// memory: writable pages stay writable in both page tables.
+    Share,
+    /// Leaves the page unmapped in the child.
+    Skip,
+    /// Maps a private copy of the page into the child right away.
+    Copy,
+}
+
+/// Whether an access would fault, as told by [`PageTable64::check_access`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccessVerdict {
+    /// The access is allowed.
+    Allowed,
+    /// The page is not mapped, or its entry carries a payload.
+    NotMapped,
+    /// A write to a copy-on-write page, allowed once the fault is resolved
+    /// with [`PageTable64::handle_cow_fault`].
+    CopyOnWrite,
+    /// The permissions of the mapping or the global controls of the CPU
+    /// forbid the access.
+    Denied,
+}
+
+/// The progress of a region operation done in steps, e.g. with
+/// [`PageTable64::unmap_region_step`].
+///
+/// It only records the addresses that remain, so it stays valid whatever
+/// happens to the page table between two steps: each step walks the tables
+/// again from the root. The end of a region ending at the top of the address
+/// space wraps to 0.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RegionCursor {
+    pub(crate) next: usize,
+    pub(crate) end: usize,
+}
+
+impl RegionCursor {
+    /// Creates a cursor over the region of `size` bytes from `start`.
+    pub fn new(start: impl Into<usize>, size: usize) -> Self {
+        let next = start.into();
+        Self {
+            next,
+            end: next.wrapping_add(size),
+        }
+    }
+
+    /// Returns the address where the next step starts.
+    pub const fn next(&self) -> usize {
+        self.next
+    }
+
+    /// Returns the number of bytes that remain.
+    pub const fn remaining(&self) -> usize {
+        self.end.wrapping_sub(self.next)
+    }
+
+    /// Whether the whole region has been processed.
+    pub const fn is_done(&self) -> bool {
+        self.next == self.end
+    }
+
+    pub(crate) const fn status(&self) -> StepStatus {
+        if self.is_done() {
+            StepStatus::Done
+        } else {
+            StepStatus::Pending
+        }
+    }
+}
+
+/// The result of a step of a region operation (see [`RegionCursor`]).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepStatus {
+    /// Part of the region remains, for the next steps.
+    Pending,
+    /// The whole region has been processed.
+    Done,
+}
+
+/// The idle counters of the 4K pages of a region, kept by the caller of
+/// [`PageTable64::estimate_working_set`] between two scans.
+///
+/// The counter of a page is the number of scans in a row that found it not
+/// accessed. The page `index` is the one at `index * 4K` bytes from the start
+/// of the region.
+pub trait IdleBits {
+    /// Returns the idle counter of the page `index`.
+    fn idle(&self, index: usize) -> u8;
+    /// Sets the idle counter of the page `index`.
+    fn set_idle(&mut self, index: usize, idle: u8);
+}
+
+/// One counter per page, which must cover the whole region.
+impl IdleBits for [u8] {
+    fn idle(&self, index: usize) -> u8 {
+        self[index]
+    }
+
+    fn set_idle(&mut self, index: usize, idle: u8) {
+        self[index] = idle;
+    }
+}
+
+/// The result of a scan of [`PageTable64::estimate_working_set`], in bytes.
+///
+/// Huge pages and groups with the contiguous hint count as their full size,
+/// even if only part of them is in the region.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct WorkingSet {
+    /// The pages accessed since the previous scan.
+    pub accessed: usize,
+    /// The pages mapped but not accessed since the previous scan.
+    pub idle: usize,
+    /// The pages locked by [`PageTable64::lock_region`], which are not
+    /// scanned.
+    pub locked: usize,
+}
+
+/// A summary of the pages of a region, returned by
+/// [`PageTable64::summarize`].
+///
+/// Pages are counted in 4K pages within the region: a huge page counts for
+/// the part of it in the region.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct RangeSummary {
+    /// The pages mapped by present leaf entries.
+    pub present_pages: usize,
+    /// The pages not mapped, including the ones whose entry carries an
+    /// [`AbsentEntry`]. With `present_pages`, they make up the whole region.
+    pub holes: usize,
+    /// The union of the flags of the present leaf entries.
+    pub flags_union: MappingFlags,
+    /// The flags common to all the present leaf entries, empty if there is
+    /// none.
+    pub flags_intersection: MappingFlags,
+    /// Whether a present leaf entry is a huge page. Groups with the
+    /// contiguous hint are made of 4K entries, and do not count.
+    pub any_huge: bool,
+    /// Whether a present leaf entry has [`MappingFlags::COW`].
+    pub any_cow: bool,
+    /// Whether a present leaf entry is dirty, as set by the hardware or
+    /// [`PageTable64::set_dirty`].
+    pub any_dirty: bool,
+}
+
+/// The pages of a region write-protected by a [`ProtectSession`] and not
+/// restored yet, kept by the caller for the session.
+///
+/// The page `index` is the one at `index * 4K` bytes from the start of the
+/// region.
+pub trait ProtectedPages {
+    /// Returns whether the page `index` is protected.
+    fn is_protected(&self, index: usize) -> bool;
+    /// Sets whether the page `index` is protected.
+    fn set_protected(&mut self, index: usize, protected: bool);
+}
+
+/// One bit per page, which must cover the whole region.
+impl ProtectedPages for [u64] {
+    fn is_protected(&self, index: usize) -> bool {
+        self[index / 64] & 1 << (index % 64) != 0
+    }
+
+    fn set_protected(&mut self, index: usize, protected: bool) {
+        if protected {
+            self[index / 64] |= 1 << (index % 64);
+        } else {
+            self[index / 64] &= !(1 << (index % 64));
+        }
+    }
+}
+
+/// A bitmap of the granules of a region that are mapped, filled by
+/// [`PageTable64::export_present_bitmap`], e.g. to tell wild pointers apart
+/// in a fault handler before taking the lock of the address space.
+///
+/// The granule `index` is the one at `index * granularity` bytes from the
+/// start of the region, and its bit is bit `index % 64` of the word
+/// `index / 64`. The words are atomic, so that the bitmap can be checked
+/// while the page table changes it.
+///
+/// Set as the observer of the page table (see
+/// [`PageTable64::set_observer`]), e.g. over words in a `static` that the
+/// fault handler reads through another one, it keeps the bitmap current as
+/// pages are mapped and unmapped. A granule is then cleared when all of it is
+/// unmapped at once, so that the bitmap may keep granules in which a part
+/// was unmapped, as if they were still mapped, until it is exported again.
+/// It is only told about present pages: the entries carrying an
+/// [`AbsentEntry`] are not counted, and a page swapped out is unmapped. The
+/// changes of the child of [`PageTable64::clone_cow`] are ignored.
+#[derive(Debug)]
+pub struct PresentBitmap<'a> {
+    start: usize,
+    size: usize,
+    granularity: PageSize,
+    bits: &'a [AtomicU64],
+}
+
+impl<'a> PresentBitmap<'a> {
+    /// Wraps the bitmap exported by [`PageTable64::export_present_bitmap`]
+    /// for the `size` bytes at `start` (sign-extended), with one bit per
+    /// granule of `granularity`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is too short for the region.
+    pub fn new(start: usize, size: usize, granularity: PageSize, bits: &'a [AtomicU64]) -> Self {
+        assert!(
+            bits.len() >= Self::words(size, granularity),
+            "the bitmap is too short for the region"
+        );
+        Self {
+            start,
+            size,
+            granularity,
+            bits,
+        }
+    }
+
+    /// Returns the number of words of the bitmap of `size` bytes with one bit
+    /// per granule of `granularity`.
+    pub const fn words(size: usize, granularity: PageSize) -> usize {
+        (size / granularity as usize).div_ceil(64)
+    }
+
+    /// Returns whether the granule containing `vaddr` is mapped. It is
+    /// `false` outside of the region.
+    pub fn contains(&self, vaddr: usize) -> bool {
+        let offset = vaddr.wrapping_sub(self.start);
+        offset < self.size && {
+            let index = offset / self.granularity as usize;
+            self.bits[index / 64].load(Ordering::Relaxed) & 1 << (index % 64) != 0
+        }
+    }
+
+    /// Returns the words of the bitmap.
+    pub fn bits(&self) -> &'a [AtomicU64] {
+        self.bits
+    }
+
+    /// Sets the bits of the granules from `first` to `last` included.
+    pub(crate) fn fill(bits: &[AtomicU64], first: usize, last: usize) {
+        for index in first..=last {
+            bits[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the granules of the region overlapped by the `size` bytes at
+    /// `vaddr`, as the first and last one, and whether they are covered whole.
+    fn granules(&self, vaddr: usize, size: usize) -> Option<(usize, usize, bool, bool)> {
+        let granule = self.granularity as usize;
+        // Without overflows at the end of the address space.
+        let start = self.start as u128;
+        let from = (vaddr as u128).max(start);
+        let end = (vaddr as u128 + size as u128).min(start + self.size as u128);
+        if from >= end {
+            return None;
+        }
+        let (from, end) = ((from - start) as usize, (end - start) as usize);
+        let whole = (from.is_multiple_of(granule), end.is_multiple_of(granule));
+        Some((from / granule, (end - 1) / granule, whole.0, whole.1))
+    }
+}
+
+impl PageTableObserver for PresentBitmap<'_> {
+    fn mapped(
+        &mut self,
+        vaddr: usize,
+        size: usize,
+        _flags: MappingFlags,
+        _page_size: PageSize,
+        origin: ChangeOrigin,
+    ) {
+        if let Some((first, last, ..)) = self.granules(vaddr, size).filter(|_| !origin.child) {
+            Self::fill(self.bits, first, last);
+        }
+    }
+
+    fn unmapped(&mut self, vaddr: usize, size: usize, origin: ChangeOrigin) {
+        let Some((first, last, whole_first, whole_last)) = self.granules(vaddr, size) else {
+            return;
+        };
+        if origin.child {
+            return;
+        }
+        let first = first + !whole_first as usize;
+        let last = if whole_last { last + 1 } else { last };
+        for index in first..last {
+            self.bits[index / 64].fetch_and(!(1 << (index % 64)), Ordering::Relaxed);
+        }
+    }
+}
+
+/// The work done by the operations of a [`PageTable64`], returned by
+/// [`PageTable64::stats`] with the `trace` feature.
+#[cfg(feature = "trace")]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct OpStats {
+    /// The entries read while walking the tables, at every level.
+    pub entries_read: u64,
+    /// The entries changed, as passed to the [`ChangeJournal`] whether one is
+    /// set or not.
+    pub entries_written: u64,
+    /// The table frames allocated, not counting the root table.
+    pub frames_allocated: u64,
+    /// The table frames freed before the page table is dropped, by
+    /// [`PageTable64::release_subtree`].
+    pub frames_freed: u64,
+    /// The unused entries above the last level whose subtree a walk over a
+    /// region skipped.
+    pub subtrees_skipped: u64,
+    /// The huge pages mapped.
+    pub huge_mappings_used: u64,
+}
+
+/// A change of entries of a [`PageTable64`], passed to its [`ChangeJournal`].
+///
+/// The levels start with the root table, and the addresses are the first
+/// virtual addresses mapped through the entries.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChangeRecord {
+    /// The entry of `vaddr` at `level` changed from the raw bits `old` to
+    /// `new`.
+    Entry {
+        /// The virtual address of the entry.
+        vaddr: usize,
+        /// The level of the entry.
+        level: usize,
+        /// The raw bits of the entry before the change.
+        old: u64,
+        /// The raw bits of the entry after the change, as written by the
+        /// page table, i.e. in the order of
+        /// [`PagingMetaData::BYTE_ORDER`].
+        new: u64,
+    },
+    /// The `count` consecutive entries at `level` from the entry of `vaddr`
+    /// changed, e.g. when a huge page is split, and must be read back from
+    /// the tables.
+    Range {
+        /// The virtual address of the first entry.
+        vaddr: usize,
+        /// The level of the entries.
+        level: usize,
+        /// The number of entries.
+        count: usize,
+    },
+}
+
+/// A journal of the changes of the entries of a [`PageTable64`], e.g. to
+/// keep a shadow page table in sync (see [`PageTable64::set_journal`]).
+pub trait ChangeJournal: Send + Sync {
+    /// Records a change.
+    ///
+    /// Returns `false` if the record is dropped, e.g. because the journal is
+    /// full. No more records are then passed until a journal is set again,
+    /// and the consumer must resynchronize with the whole page table.
+    fn record(&mut self, record: ChangeRecord) -> bool;
+}
+
+/// Where a change reported to a [`PageTableObserver`] comes from.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ChangeOrigin {
+    /// The change undoes an earlier change of the same operation, which
+    /// failed, e.g. the pages mapped by [`PageTable64::map_alloc`] before it
+    /// ran out of memory.
+    pub rolled_back: bool,
+    /// The change was made to the child page table of
+    /// [`PageTable64::clone_cow`], which has no observer yet, instead of this
+    /// one.
+    pub child: bool,
+}
+
+/// An observer of the changes of the mappings of a [`PageTable64`], e.g. to
+/// keep the VMAs of an address space in sync, or to check that they agree
+/// (see [`PageTable64::set_observer`]).
+///
+/// The changes are reported after they are made, in order, with the
+/// sign-extended address and the size in bytes of the pages changed.
+/// Consecutive pages changed the same way by an operation are reported
+/// together. The defaults do nothing.
+pub trait PageTableObserver: Send + Sync {
+    /// The pages were mapped with `flags`, by pages of `page_size`.
+    #[inline]
+    fn mapped(
+        &mut self,
+        _vaddr: usize,
+        _size: usize,
+        _flags: MappingFlags,
+        _page_size: PageSize,
+        _origin: ChangeOrigin,
+    ) {
+    }
+
+    /// The pages were unmapped.
+    #[inline]
+    fn unmapped(&mut self, _vaddr: usize, _size: usize, _origin: ChangeOrigin) {}
+
+    /// The flags of the pages changed from `old` to `new`.
+    #[inline]
+    fn protected(
+        &mut self,
+        _vaddr: usize,
+        _size: usize,
+        _old: MappingFlags,
+        _new: MappingFlags,
+        _origin: ChangeOrigin,
+    ) {
+    }
+}
+
+memory_addr::def_usize_addr! {
+    /// A guest physical address, translated by the stage-2 page tables of a
+    /// hypervisor (EPT, NPT, or the RISC-V G-stage).
+    ///
+    /// Using it as [`PagingMetaData::VirtAddr`] keeps the guest physical
+    /// addresses apart from the host virtual ones: a [`PageTable64`] for the
+    /// guest memory does not accept a [`VirtAddr`] by accident. Unlike
+    /// virtual addresses, guest physical addresses are not sign-extended.
+    pub type GuestPhysAddr;
+}
+
+memory_addr::def_usize_addr_formatter! {
+    GuestPhysAddr = "GPA:{}";
 }
 
 /// The specialized `Result` type for page table operations.
@@ -47,17 +762,101 @@ pub trait PagingMetaData: Sync + Send {
     /// The maximum number of bits of virtual address.
     const VA_MAX_BITS: usize;
 
+    /// The name of the architecture, reported by
+    /// [`PageTableInfo::arch_name`]. The default is `"unknown"`.
+    const ARCH_NAME: &'static str = "unknown";
+
     /// The maximum physical address.
     const PA_MAX_ADDR: usize = (1 << Self::PA_MAX_BITS) - 1;
 
+    /// The order of the bytes of the entries as read by the MMU, e.g.
+    /// [`ByteOrder::Big`] for a big-endian machine, or for a dump of its
+    /// tables read on a little-endian one.
+    ///
+    /// It must be the [`GenericPTE::BYTE_ORDER`] of the entries, i.e. the
+    /// entries are an [`EndianPTE`] if it is not the order of the target,
+    /// which [`PageTable64::try_new`] checks at compile time. The default is
+    /// the order of the target, whose entries are loaded and stored as they
+    /// are.
+    const BYTE_ORDER: ByteOrder = ByteOrder::NATIVE;
+
+    /// The end of the user addresses, mapped from `0` (see
+    /// [`PagingMetaData::is_user_vaddr`]), which are accepted by
+    /// [`UserSpace`] and rejected by [`KernelSpace`].
+    ///
+    /// The default is the lower half of the sign-extended addresses,
+    /// `1 << (VA_MAX_BITS - 1)`, e.g. `0x0000_8000_0000_0000` on x86_64 and
+    /// `1 << 38` with Sv39. The kernel addresses then start at the sign
+    /// extension of the same bit.
+    const USER_VA_END: usize = 1 << (Self::VA_MAX_BITS - 1);
+
+    /// The number of bits of the virtual address that index the tables of
+    /// each level, from the root table (`INDEX_BITS[0]`) down. Only the first
+    /// [`PagingMetaData::LEVELS`] elements are used.
+    ///
+    /// A table at level `i` has `1 << INDEX_BITS[i]` entries, and is
+    /// allocated with [`PagingHandler::alloc_table`] if it does not fit in a
+    /// 4K frame, e.g. the 2048-entry root table of RISC-V Sv39x4. Huge pages
+    /// are only mapped at the levels above which all the tables have 512
+    /// entries, so that they have one of the sizes of [`PageSize`]. The
+    /// default is 9 bits (512 entries) at every level.
+    const INDEX_BITS: [u8; MAX_LEVELS] = [9; MAX_LEVELS];
+
+    /// Whether the TLB may cache non-present entries.
+    ///
+    /// If it may not, mapping a previously unmapped page does not require a
+    /// TLB flush, and [`TlbFlush`]/[`TlbFlushAll`] returned by operations that
+    /// only create new mappings do nothing. The default is `true`, which is
+    /// always safe.
+    const TLB_CACHES_INVALID: bool = true;
+
+    /// Whether a valid leaf entry must go through an invalid state before its
+    /// output address or memory attributes can be changed (break-before-make).
+    ///
+    /// If `true`, [`PageTable64::remap`] and [`PageTable64::protect`] write an
+    /// invalid entry and flush the TLB for the page themselves before writing
+    /// the new entry. The default is `false`, where the entry is overwritten
+    /// in one write and the flush is left to the caller.
+    const BREAK_BEFORE_MAKE: bool = false;
+
+    /// Who sets the accessed and dirty bits of the leaf entries.
+    ///
+    /// Unless it is [`AccessedDirtyPolicy::AlwaysSet`] (the default), new
+    /// mappings start with both bits clear, and [`PageTable64::protect`] and
+    /// [`PageTable64::remap`] keep the bits of the same frame (the dirty bit
+    /// only if the page stays writable).
+    const AD_POLICY: AccessedDirtyPolicy = AccessedDirtyPolicy::AlwaysSet;
+
+    /// The largest page that a leaf entry can map.
+    ///
+    /// Larger pages are rejected by [`PageTable64::map`] and never chosen by
+    /// [`PageTable64::map_region`]. The default is [`PageSize::Size1G`], as
+    /// most hardware has no leaf entries in the root table of 4-level page
+    /// tables.
+    const MAX_PAGE_SIZE: PageSize = PageSize::Size1G;
+
+    /// The number of flushes of single pages above which flushing the entire
+    /// TLB is expected to be cheaper, for [`TlbFlushAll::flush_with_threshold`].
+    ///
+    /// Callers can pass another threshold, e.g. from measurements. The
+    /// default is `32`.
+    const FLUSH_PAGES_THRESHOLD: usize = 32;
+
     /// The virtual address to be translated in this page table.
     ///
     /// This associated type allows more flexible use of page tables structs like [`PageTable64`],
-    /// for example, to implement EPTs.
+    /// for example, to implement EPTs with [`GuestPhysAddr`], so that host
+    /// virtual addresses cannot be passed to them.
     type VirtAddr: MemoryAddr;
     // (^)it can be converted from/to usize and it's trivially copyable
 
     /// Whether a given physical address is valid.
+    ///
+    /// [`PageTable64`] rejects the frames it does not accept, including the
+    /// tables it allocates. It can be overridden to narrow the range at
+    /// runtime, e.g. to the width reported by the CPU at boot, or with
+    /// [`NarrowPaddr`] at compile time. The default accepts the addresses up
+    /// to [`PagingMetaData::PA_MAX_ADDR`].
     #[inline]
     fn paddr_is_valid(paddr: usize) -> bool {
         paddr <= Self::PA_MAX_ADDR // default
@@ -71,11 +870,135 @@ pub trait PagingMetaData: Sync + Send {
         (vaddr & top_mask) == 0 || (vaddr & top_mask) == top_mask
     }
 
+    /// Whether `vaddr` is a user address: valid, and below
+    /// [`PagingMetaData::USER_VA_END`].
+    #[inline]
+    fn is_user_vaddr(vaddr: usize) -> bool {
+        vaddr < Self::USER_VA_END && Self::vaddr_is_valid(vaddr)
+    }
+
+    /// Whether `vaddr` is a kernel address: valid, and at or above the
+    /// start of the kernel addresses, `USER_VA_END.wrapping_neg()` (e.g.
+    /// `0xffff_8000_0000_0000` on x86_64). The addresses in the hole between
+    /// the two are neither user nor kernel addresses.
+    #[inline]
+    fn is_kernel_vaddr(vaddr: usize) -> bool {
+        vaddr >= Self::USER_VA_END.wrapping_neg() && Self::vaddr_is_valid(vaddr)
+    }
+
+    /// Returns the range of the user addresses (see
+    /// [`PagingMetaData::is_user_vaddr`]).
+    #[inline]
+    fn user_va_range() -> AddrRange<Self::VirtAddr> {
+        AddrRange::new(0.into(), Self::USER_VA_END.into())
+    }
+
+    /// Returns the range of the kernel addresses (see
+    /// [`PagingMetaData::is_kernel_vaddr`]).
+    ///
+    /// The range is half-open, so it ends at `usize::MAX`, without the last
+    /// byte of the address space, which is a kernel address too.
+    #[inline]
+    fn kernel_va_range() -> AddrRange<Self::VirtAddr> {
+        AddrRange::new(Self::USER_VA_END.wrapping_neg().into(), usize::MAX.into())
+    }
+
     /// Flushes the TLB.
     ///
     /// If `vaddr` is [`None`], flushes the entire TLB. Otherwise, flushes the TLB
     /// entry at the given virtual address.
     fn flush_tlb(vaddr: Option<Self::VirtAddr>);
+
+    /// Flushes the entire TLB, including the entries of global mappings (see
+    /// [`GenericPTE::is_global`]) that `flush_tlb(None)` may keep.
+    ///
+    /// It is used instead of [`PagingMetaData::flush_tlb`] when a global
+    /// mapping changed since the last full flush. The default calls
+    /// `flush_tlb(None)`.
+    #[inline]
+    fn flush_tlb_global() {
+        Self::flush_tlb(None)
+    }
+
+    /// Orders the preceding writes to the entries before later accesses
+    /// through the hardware page walker.
+    ///
+    /// It is called at the end of every operation of [`PageTable64`] that
+    /// changes entries, whether or not the caller flushes the TLB. The
+    /// default is a compiler fence, which is enough where the walker observes
+    /// the stores of the CPU in order (x86).
+    #[inline]
+    fn fence_after_update() {
+        compiler_fence(Ordering::SeqCst)
+    }
+}
+
+/// The metadata `M` with at most `PA_BITS` bits of physical address, for
+/// platforms implementing fewer than the architecture allows.
+///
+/// [`PagingMetaData::PA_MAX_BITS`] is clamped to the one of `M`, and every
+/// other item is the one of `M`. Frames beyond the narrowed range are then
+/// rejected when they are mapped, instead of faulting when accessed.
+pub struct NarrowPaddr<M: PagingMetaData, const PA_BITS: usize>(PhantomData<M>);
+
+impl<M: PagingMetaData, const PA_BITS: usize> PagingMetaData for NarrowPaddr<M, PA_BITS> {
+    const LEVELS: usize = M::LEVELS;
+    const PA_MAX_BITS: usize = if PA_BITS < M::PA_MAX_BITS {
+        PA_BITS
+    } else {
+        M::PA_MAX_BITS
+    };
+    const VA_MAX_BITS: usize = M::VA_MAX_BITS;
+    const ARCH_NAME: &'static str = M::ARCH_NAME;
+    const USER_VA_END: usize = M::USER_VA_END;
+    const PA_MAX_ADDR: usize = if Self::PA_MAX_BITS < M::PA_MAX_BITS {
+        (1 << Self::PA_MAX_BITS) - 1
+    } else {
+        M::PA_MAX_ADDR
+    };
+    const INDEX_BITS: [u8; MAX_LEVELS] = M::INDEX_BITS;
+    const TLB_CACHES_INVALID: bool = M::TLB_CACHES_INVALID;
+    const BREAK_BEFORE_MAKE: bool = M::BREAK_BEFORE_MAKE;
+    const AD_POLICY: AccessedDirtyPolicy = M::AD_POLICY;
+    const BYTE_ORDER: ByteOrder = M::BYTE_ORDER;
+    const MAX_PAGE_SIZE: PageSize = M::MAX_PAGE_SIZE;
+    const FLUSH_PAGES_THRESHOLD: usize = M::FLUSH_PAGES_THRESHOLD;
+    type VirtAddr = M::VirtAddr;
+
+    #[inline]
+    fn paddr_is_valid(paddr: usize) -> bool {
+        paddr <= Self::PA_MAX_ADDR && M::paddr_is_valid(paddr)
+    }
+
+    #[inline]
+    fn vaddr_is_valid(vaddr: usize) -> bool {
+        M::vaddr_is_valid(vaddr)
+    }
+
+    #[inline]
+    fn is_user_vaddr(vaddr: usize) -> bool {
+        M::is_user_vaddr(vaddr)
+    }
+
+    #[inline]
+    fn is_kernel_vaddr(vaddr: usize) -> bool {
+        M::is_kernel_vaddr(vaddr)
+    }
+
+    #[inline]
+    fn flush_tlb(vaddr: Option<M::VirtAddr>) {
+        M::flush_tlb(vaddr)
+    }
+
+    #[inline]
+    fn flush_tlb_global() {
+        M::flush_tlb_global()
+    }
+
+    #[inline]
+    fn fence_after_update() {
+        M::fence_after_update()
+    }
 }
 
 /// The low-level **OS-dependent** helpers that must be provided for
@@ -85,28 +1008,181 @@ pub trait PagingHandler: Sized {
     fn alloc_frame() -> Option<PhysAddr>;
     /// Request to free a allocated physical frame.
     fn dealloc_frame(paddr: PhysAddr);
+
+    /// Request to allocate `size` contiguous bytes aligned to `size`, for a
+    /// page table larger than 4K (see [`PagingMetaData::INDEX_BITS`]).
+    ///
+    /// Smaller tables are allocated with [`PagingHandler::alloc_frame`]. The
+    /// default fails, which is enough for the architectures whose tables are
+    /// all 4K.
+    #[inline]
+    fn alloc_table(_size: usize) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Request to free a table of `size` bytes allocated by
+    /// [`PagingHandler::alloc_table`]. The default does nothing.
+    #[inline]
+    fn dealloc_table(_paddr: PhysAddr, _size: usize) {}
+
+    /// Request to allocate `count` contiguous 4K frames, the first aligned to
+    /// `align` bytes, for the huge pages of [`PageTable64::map_alloc`].
+    ///
+    /// The default fails, so that the pages are backed by frames from
+    /// [`PagingHandler::alloc_frame`].
+    #[inline]
+    fn alloc_frames(_count: usize, _align: usize) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Request to free the `count` frames allocated by
+    /// [`PagingHandler::alloc_frames`] at `paddr`. The default frees them one
+    /// by one with [`PagingHandler::dealloc_frame`].
+    #[inline]
+    fn dealloc_frames(paddr: PhysAddr, count: usize) {
+        for i in 0..count {
+            Self::dealloc_frame(paddr.add(i * PAGE_SIZE_4K));
+        }
+    }
     /// Returns a virtual address that maps to the given physical address.
     ///
     /// Used to access the physical memory directly in page table implementation.
     fn phys_to_virt(paddr: PhysAddr) -> VirtAddr;
+
+    /// Calls `f` with the contents of the 4K frame at `paddr`.
+    ///
+    /// It is used for the data frames that [`PageTable64`] fills, e.g. the
+    /// zeroed pages of [`PageTable64::map_elf_segments`], while the page
+    /// tables are always accessed with [`PagingHandler::phys_to_virt`]. `f`
+    /// may itself access another frame this way, to copy from it. The default
+    /// uses [`PagingHandler::phys_to_virt`]; systems without a mapping of all
+    /// the physical memory can instead map the frame in a reserved slot for
+    /// the duration of `f`.
+    #[inline]
+    fn with_frame_mapped<R>(paddr: PhysAddr, f: impl FnOnce(&mut [u8; PAGE_SIZE_4K]) -> R) -> R {
+        let ptr = Self::phys_to_virt(paddr).as_mut_ptr() as *mut [u8; PAGE_SIZE_4K];
+        f(unsafe { &mut *ptr })
+    }
+
+    /// Called when the page at `paddr` gets one more copy-on-write mapping,
+    /// by [`PageTable64::clone_cow`].
+    ///
+    /// Together with [`PagingHandler::frame_unshared`], it can be used to
+    /// maintain the reference counts of shared pages: a page starts with one
+    /// reference from its original mapping, and can be freed once the count
+    /// drops to zero. The default does nothing.
+    #[inline]
+    fn frame_shared(_paddr: PhysAddr, _size: PageSize) {}
+
+    /// Called when a copy-on-write mapping of the page at `paddr` goes away,
+    /// i.e. when it is unmapped, or replaced by a private copy in
+    /// [`PageTable64::handle_cow_fault`].
+    ///
+    /// They are also called with [`PageSize::Size4K`] for the table frames of
+    /// a [`SharedSubtree`], when a page table links or unlinks it. A table
+    /// frame whose count drops to zero is no longer used by any page table,
+    /// and must be freed by the handler.
+    ///
+    /// The default does nothing.
+    #[inline]
+    fn frame_unshared(_paddr: PhysAddr, _size: PageSize) {}
+
+    /// Returns the physical address of a 4K frame filled with zeros, which
+    /// [`PageTable64::map_zero_region`] maps copy-on-write.
+    ///
+    /// The zero frame is never reported to [`PagingHandler::frame_shared`] or
+    /// [`PagingHandler::frame_unshared`]. The default is [`None`].
+    #[inline]
+    fn zero_frame() -> Option<PhysAddr> {
+        None
+    }
+
+    /// Returns the memory type that every mapping of the frames in
+    /// `[paddr, paddr + size)` must have, or [`None`] if any type is fine.
+    ///
+    /// Mapping a frame with different memory types at the same time (e.g.
+    /// cached and device) aliases its attributes, which is undefined on
+    /// AArch64 and loses coherency on x86. [`PageTable64::map`],
+    /// [`PageTable64::remap`] and [`PageTable64::protect`] refuse mappings of
+    /// another type with
+    /// [`Err(PagingError::AttributeConflict)`](PagingError::AttributeConflict).
+    /// The default is [`None`], which disables the check.
+    #[inline]
+    fn memory_type_of(_paddr: PhysAddr, _size: PageSize) -> Option<MemoryType> {
+        None
+    }
+
+    /// Performs the cache maintenance `op` on the `size` bytes of physical
+    /// memory at `paddr`, for [`PageTable64::retype_region`].
+    ///
+    /// It is called while the page is unmapped, after its TLB entry is
+    /// flushed and before it is mapped with the new memory type. The frames
+    /// are reached by physical address, or through another mapping such as
+    /// the one of [`PagingHandler::phys_to_virt`]. Per architecture:
+    ///
+    /// - AArch64: `DC CIVAC` or `DC IVAC` on every line to the point of
+    ///   coherency, followed by `DSB SY`. This is required: the architecture
+    ///   does not keep mismatched attributes coherent.
+    /// - x86_64: `CLFLUSH` (or `CLFLUSHOPT` then `SFENCE`) on every line, or
+    ///   `WBINVD` for large ranges. There is no invalidation without
+    ///   write-back, so both operations are done this way. Processors that
+    ///   self-snoop (`CPUID.01H:EDX.SS`) may skip it.
+    /// - RISC-V: `cbo.flush` or `cbo.inval` of Zicbom on every block, where
+    ///   the platform has non-coherent caches.
+    /// - LoongArch: `cacop` with the hit write-back-invalidate or hit
+    ///   invalidate operation on every cache level, for the uncached (SUC and
+    ///   WUC) memory types.
+    ///
+    /// The default does nothing, which is enough where the hardware keeps the
+    /// caches coherent across memory types.
+    fn cache_maintain(_paddr: PhysAddr, _size: usize, _op: CacheOp) {}
+
+    /// Called when an operation of [`PageTable64`] that changes the mappings
+    /// fails, e.g. to count the errors by operation and kind: the methods
+    /// that map, unmap or protect pages (including `remap`, the `broadcast_*`
+    /// methods, `map_alloc` and `apply_shared`), [`PageTable64::clone_cow`]
+    /// (also filtered) and [`PageTable64::handle_cow_fault`], with their
+    /// steps.
+    ///
+    /// `op` is the name of the method, and `vaddr` the address it was given:
+    /// the page, the start of the region, or the segment (for
+    /// [`PageTable64::map_elf_segments`]) that failed. For the steps of the
+    /// region operations (see [`RegionCursor`]), it is the address where the
+    /// next step starts.
+    ///
+    /// It is called once per failing call, after the changes are rolled back
+    /// if the operation does it, and never on success. The operations that
+    /// use others internally report their errors as their own. The default
+    /// does nothing.
+    #[inline]
+    fn on_error(_op: &'static str, _vaddr: usize, _err: &PagingError) {}
 }
 
 /// The page sizes supported by the hardware page table.
+// `PageTable64` is only built for 64-bit targets.
+#[allow(clippy::enum_clike_unportable_variant)]
 #[repr(usize)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum PageSize {
     /// Size of 4 kilobytes (2<sup>12</sup> bytes).
     Size4K = 0x1000,
+    /// Size of 64 kilobytes (2<sup>16</sup> bytes), mapped by 16 4K entries
+    /// with the contiguous hint (see [`GenericPTE::CONTIGUOUS_HINT`]).
+    Size64K = 0x1_0000,
     /// Size of 2 megabytes (2<sup>21</sup> bytes).
     Size2M = 0x20_0000,
     /// Size of 1 gigabytes (2<sup>30</sup> bytes).
     Size1G = 0x4000_0000,
+    /// Size of 512 gigabytes (2<sup>39</sup> bytes), mapped by an entry of
+    /// the root table of 4-level page tables where the hardware allows it
+    /// (see [`PagingMetaData::MAX_PAGE_SIZE`]).
+    Size512G = 0x80_0000_0000,
 }
 
 impl PageSize {
     /// Whether this page size is considered huge (larger than 4K).
     pub const fn is_huge(self) -> bool {
-        matches!(self, Self::Size1G | Self::Size2M)
+        matches!(self, Self::Size512G | Self::Size1G | Self::Size2M)
     }
 
     /// Checks whether a given address or size is aligned to the page size.
@@ -132,21 +1208,135 @@ impl From<PageSize> for usize {
 /// The caller can call [`TlbFlush::flush`] to flush TLB entries related to
 /// the given virtual address, or call [`TlbFlush::ignore`] if it knowns the
 /// TLB will be flushed later.
+///
+/// If the operation only created a new mapping and the TLB cannot hold stale
+/// entries for it (see [`PagingMetaData::TLB_CACHES_INVALID`]), flushing does
+/// nothing.
+///
+/// The flush also records the [generation](PageTable64::generation) of the
+/// page table it came from, so that a deferred flush can be skipped with
+/// [`TlbFlush::flush_if_current`] if the whole TLB has been flushed since.
+///
+/// If the old mapping was global (see [`GenericPTE::is_global`]),
+/// [`TlbFlush::is_global`] is `true`. Flushing by address is still enough
+/// (e.g. `invlpg` on x86), but switching the address space is not.
+///
+/// With the `debug-flush` feature, dropping the flush without calling
+/// [`TlbFlush::flush`] or [`TlbFlush::ignore`] panics.
 #[must_use]
-pub struct TlbFlush<M: PagingMetaData>(M::VirtAddr, PhantomData<M>);
+pub struct TlbFlush<M: PagingMetaData>(Option<M::VirtAddr>, usize, u64, bool, PhantomData<M>);
 
 impl<M: PagingMetaData> TlbFlush<M> {
     pub(crate) const fn new(vaddr: M::VirtAddr) -> Self {
-        Self(vaddr, PhantomData)
+        Self(Some(vaddr), 1, 0, false, PhantomData)
+    }
+
+    /// Creates the result of mapping a previously unmapped page at `vaddr`.
+    pub(crate) const fn new_mapping(vaddr: M::VirtAddr) -> Self {
+        if M::TLB_CACHES_INVALID {
+            Self::new(vaddr)
+        } else {
+            Self(None, 0, 0, false, PhantomData)
+        }
+    }
+
+    /// Whether flushing does anything, i.e. whether the TLB may still hold
+    /// stale entries for the change.
+    pub const fn is_needed(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Extends the flush to the `pages` 4K pages starting at the address.
+    pub(crate) const fn with_pages(mut self, pages: usize) -> Self {
+        self.1 = pages;
+        self
+    }
+
+    pub(crate) const fn with_generation(mut self, generation: u64) -> Self {
+        self.2 = generation;
+        self
+    }
+
+    /// Marks the flush as changing a global mapping if `global` is `true`.
+    pub(crate) const fn with_global(mut self, global: bool) -> Self {
+        self.3 |= global;
+        self
+    }
+
+    /// Returns the generation of the page table after the change.
+    pub const fn generation(&self) -> u64 {
+        self.2
+    }
+
+    /// Whether the changed mapping was global.
+    pub const fn is_global(&self) -> bool {
+        self.3
+    }
+
+    /// Combines two flushes into one that covers both changes.
+    ///
+    /// The result flushes every page from the lowest to the highest address
+    /// of the two, so flushes of distant addresses are better collected in
+    /// a [`TlbFlushAll`].
+    pub fn merge(mut self, other: Self) -> Self {
+        let range = |f: &Self| {
+            f.0.map(|vaddr| {
+                // The last byte, as the last page may end the address space.
+                let start: usize = vaddr.into();
+                (start, start + (f.1 * PAGE_SIZE_4K - 1))
+            })
+        };
+        if let Some((start, last)) = match (range(&self), range(&other)) {
+            (Some(a), Some(b)) => Some((a.0.min(b.0), a.1.max(b.1))),
+            (a, b) => a.or(b),
+        } {
+            self.0 = Some(start.into());
+            self.1 = (last - start) / PAGE_SIZE_4K + 1;
+        }
+        self.2 = self.2.max(other.2);
+        self.3 |= other.3;
+        other.ignore();
+        self
     }
 
     /// Don't flush the TLB and silence the “must be used” warning.
-    pub fn ignore(self) {}
+    // Only implements `Drop` with the `debug-flush` feature.
+    #[allow(clippy::forget_non_drop)]
+    pub fn ignore(self) {
+        core::mem::forget(self)
+    }
 
     /// Flush the the TLB by the given virtual address to ensure the mapping
     /// changes take effect.
     pub fn flush(self) {
-        M::flush_tlb(Some(self.0))
+        if let Some(vaddr) = self.0 {
+            for i in 0..self.1 {
+                M::flush_tlb(Some(vaddr.add(i * PAGE_SIZE_4K)))
+            }
+        }
+        self.ignore()
+    }
+
+    /// Flushes like [`TlbFlush::flush`], unless the entire TLB has been
+    /// flushed through `pt` (see [`PageTable64::flush_all`]) since the change.
+    ///
+    /// `pt` must be the page table that returned this flush.
+    pub fn flush_if_current<PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        self,
+        pt: &PageTable64<M, PTE, H, K>,
+    ) {
+        if self.2 > pt.flushed_generation() {
+            self.flush()
+        } else {
+            self.ignore()
+        }
+    }
+}
+
+#[cfg(feature = "debug-flush")]
+impl<M: PagingMetaData> Drop for TlbFlush<M> {
+    fn drop(&mut self) {
+        panic!("TLB flush dropped without calling `flush` or `ignore`")
     }
 }
 
@@ -154,19 +1344,215 @@ impl<M: PagingMetaData> TlbFlush<M> {
 ///
 /// The caller can call [`TlbFlushAll::flush_all`] to flush the entire TLB, or call
 /// [`TlbFlushAll::ignore`] if it knowns the TLB will be flushed later.
+///
+/// If the operation only created new mappings and the TLB cannot hold stale
+/// entries for them (see [`PagingMetaData::TLB_CACHES_INVALID`]), flushing
+/// does nothing.
+///
+/// Like [`TlbFlush`], it records the generation of the page table, and
+/// whether a global mapping changed. In that case, the entire TLB is flushed
+/// with [`PagingMetaData::flush_tlb_global`]. It also panics when dropped
+/// unused with the `debug-flush` feature.
 #[must_use]
-pub struct TlbFlushAll<M: PagingMetaData>(PhantomData<M>);
+pub struct TlbFlushAll<M: PagingMetaData>(bool, u64, bool, Option<FlushedPages>, PhantomData<M>);
+
+/// The pages changed by region operations, for
+/// [`TlbFlushAll::flush_with_threshold`].
+#[derive(Clone, Copy)]
+pub(crate) struct FlushedPages {
+    /// The start of the first page.
+    pub(crate) start: usize,
+    /// The last byte of the last page, which may end the address space.
+    pub(crate) last: usize,
+    /// The size of the smallest page.
+    pub(crate) step: usize,
+    pub(crate) pages: usize,
+    pub(crate) ranges: usize,
+}
+
+impl FlushedPages {
+    /// The pages of `size` bytes from `start`, the smallest of `step` bytes.
+    pub(crate) const fn new(start: usize, size: usize, step: usize, pages: usize) -> Self {
+        Self {
+            start,
+            last: start.wrapping_add(size).wrapping_sub(1),
+            step,
+            pages,
+            ranges: if pages == 0 { 0 } else { 1 },
+        }
+    }
+
+    /// The number of flushes of single pages needed to flush all the pages,
+    /// the smallest page size apart.
+    const fn flushes(&self) -> usize {
+        if self.pages == 0 {
+            0
+        } else {
+            (self.last - self.start) / self.step + 1
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        if self.pages == 0 {
+            return other;
+        } else if other.pages == 0 {
+            return self;
+        }
+        let touching =
+            self.last.wrapping_add(1) == other.start || other.last.wrapping_add(1) == self.start;
+        Self {
+            start: self.start.min(other.start),
+            last: self.last.max(other.last),
+            step: self.step.min(other.step),
+            pages: self.pages + other.pages,
+            ranges: self.ranges + other.ranges - touching as usize,
+        }
+    }
+}
 
 impl<M: PagingMetaData> TlbFlushAll<M> {
     pub(crate) const fn new() -> Self {
-        Self(PhantomData)
+        Self(true, 0, false, None, PhantomData)
+    }
+
+    /// Creates the result of mapping previously unmapped pages.
+    pub(crate) const fn new_mappings() -> Self {
+        Self(M::TLB_CACHES_INVALID, 0, false, None, PhantomData)
+    }
+
+    /// Creates the result of changes that need no flush, e.g. setting the
+    /// accessed or dirty bits.
+    pub(crate) const fn unneeded(generation: u64) -> Self {
+        Self(false, generation, false, None, PhantomData)
+    }
+
+    /// Records the pages that were changed.
+    pub(crate) const fn with_pages(mut self, pages: FlushedPages) -> Self {
+        self.3 = Some(pages);
+        self
+    }
+
+    pub(crate) const fn with_generation(mut self, generation: u64) -> Self {
+        self.1 = generation;
+        self
+    }
+
+    /// Marks the flush as changing a global mapping if `global` is `true`.
+    pub(crate) const fn with_global(mut self, global: bool) -> Self {
+        self.2 |= global;
+        self
+    }
+
+    /// Whether flushing does anything, i.e. whether the TLB may still hold
+    /// stale entries for the changes.
+    pub const fn is_needed(&self) -> bool {
+        self.0 || self.2
+    }
+
+    /// Returns the generation of the page table after the changes.
+    pub const fn generation(&self) -> u64 {
+        self.1
+    }
+
+    /// Whether a global mapping was changed.
+    pub const fn is_global(&self) -> bool {
+        self.2
+    }
+
+    /// Returns the number of pages changed, if known.
+    ///
+    /// It is only known for the region operations, e.g.
+    /// [`PageTable64::unmap_region`] and its steps, and flushes merging them.
+    pub const fn pages(&self) -> Option<usize> {
+        match &self.3 {
+            Some(pages) => Some(pages.pages),
+            None => None,
+        }
+    }
+
+    /// Returns the number of contiguous ranges of the pages changed, if
+    /// known (see [`TlbFlushAll::pages`]).
+    ///
+    /// Each region operation changes a single range. Merging the flushes of
+    /// adjacent ranges gives one range.
+    pub const fn contiguous_ranges(&self) -> Option<usize> {
+        match &self.3 {
+            Some(pages) => Some(pages.ranges),
+            None => None,
+        }
+    }
+
+    /// Combines two flushes into one that covers both changes.
+    pub fn merge(mut self, other: Self) -> Self {
+        self.0 |= other.0;
+        self.1 = self.1.max(other.1);
+        self.2 |= other.2;
+        self.3 = match (self.3, other.3) {
+            (Some(pages), Some(other)) => Some(pages.merge(other)),
+            _ => None,
+        };
+        other.ignore();
+        self
+    }
+
+    /// Flushes the changed pages one by one if it takes at most `threshold`
+    /// flushes, or the entire TLB like [`TlbFlushAll::flush_all`] otherwise.
+    ///
+    /// [`PagingMetaData::FLUSH_PAGES_THRESHOLD`] is the default threshold of
+    /// each architecture. The pages are flushed with
+    /// [`PagingMetaData::flush_tlb`] every smallest page size from the start
+    /// of the first page to the end of the last one, which also flushes the
+    /// global mappings among them. If the pages are not known (see
+    /// [`TlbFlushAll::pages`]), the entire TLB is flushed.
+    pub fn flush_with_threshold(self, threshold: usize) {
+        match self.3 {
+            Some(pages) if !self.is_needed() || pages.flushes() <= threshold => {
+                if self.is_needed() {
+                    for vaddr in (pages.start..=pages.last).step_by(pages.step) {
+                        M::flush_tlb(Some(vaddr.into()));
+                    }
+                }
+                self.ignore()
+            }
+            _ => self.flush_all(),
+        }
     }
 
     /// Don't flush the TLB and silence the “must be used” warning.
-    pub fn ignore(self) {}
+    // Only implements `Drop` with the `debug-flush` feature.
+    #[allow(clippy::forget_non_drop)]
+    pub fn ignore(self) {
+        core::mem::forget(self)
+    }
 
     /// Flush the entire TLB.
     pub fn flush_all(self) {
-        M::flush_tlb(None)
+        if self.2 {
+            M::flush_tlb_global()
+        } else if self.0 {
+            M::flush_tlb(None)
+        }
+        self.ignore()
+    }
+
+    /// Flushes the entire TLB through `pt` with [`PageTable64::flush_all`],
+    /// unless that already happened since the changes.
+    ///
+    /// `pt` must be the page table that returned this flush.
+    pub fn flush_if_current<PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+        self,
+        pt: &PageTable64<M, PTE, H, K>,
+    ) {
+        if self.0 && self.1 > pt.flushed_generation() {
+            pt.flush_all()
+        }
+        self.ignore()
+    }
+}
+
+#[cfg(feature = "debug-flush")]
+impl<M: PagingMetaData> Drop for TlbFlushAll<M> {
+    fn drop(&mut self) {
+        panic!("TLB flush dropped without calling `flush_all` or `ignore`")
     }
 }
@@ -0,0 +1,121 @@
+//! A page table behind a lock, queried and handling faults without it.
+
+use lock_api::{Mutex, MutexGuard, RawMutex};
+use memory_addr::PhysAddr;
+
+use crate::{AnySpace, GenericPTE, MappingFlags, PageSize, PageTable64, PagingHandler};
+use crate::{PagingMetaData, PagingResult, SpaceKind, TlbFlush};
+
+/// A [`PageTable64`] shared between CPUs, behind a lock of type `L` (see
+/// [`lock_api::RawMutex`]).
+///
+/// The mappings are changed through [`LockedPageTable::lock`], which gives
+/// exclusive access to the page table. Queries and the fault handlers do not
+/// take the lock, so that page faults on several CPUs are not serialized:
+///
+/// - [`LockedPageTable::query`] walks the tables with atomic loads, like
+///   [`PageTable64::query`].
+/// - [`LockedPageTable::handle_access_fault`] and
+///   [`LockedPageTable::handle_cow_fault`] replace the leaf entry with a
+///   compare-and-swap. If the holder of the lock changed the entry in the
+///   meantime, the first starts over, and the second fails with
+///   [`PagingError::Contended`](crate::PagingError::Contended).
+///
+/// A walk without the lock may follow a table that was just unlinked under
+/// the lock. This is sound as the tables of a [`PageTable64`] are only freed
+/// when it is dropped, except for the shared subtrees: the frames of a
+/// subtree released with [`PageTable64::release_subtree`] must not be reused
+/// before the walks that started before (e.g. until an RCU grace period
+/// ends).
+///
+/// The changes made without the lock are not recorded in the journal (see
+/// [`PageTable64::set_journal`]), the generation or the statistics of the
+/// page table. Their flushes are never skipped by
+/// [`TlbFlush::flush_if_current`].
+pub struct LockedPageTable<
+    M: PagingMetaData,
+    PTE: GenericPTE,
+    H: PagingHandler,
+    L: RawMutex,
+    K: SpaceKind = AnySpace,
+> {
+    root_paddr: PhysAddr,
+    inner: Mutex<L, PageTable64<M, PTE, H, K>>,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, L: RawMutex, K: SpaceKind>
+    LockedPageTable<M, PTE, H, L, K>
+{
+    /// Puts `table` behind a lock.
+    pub fn new(table: PageTable64<M, PTE, H, K>) -> Self {
+        Self {
+            root_paddr: table.root_paddr(),
+            inner: Mutex::new(table),
+        }
+    }
+
+    /// Returns the page table, removing the lock.
+    pub fn into_inner(self) -> PageTable64<M, PTE, H, K> {
+        self.inner.into_inner()
+    }
+
+    /// Returns the page table, which needs no lock with exclusive access.
+    pub fn get_mut(&mut self) -> &mut PageTable64<M, PTE, H, K> {
+        self.inner.get_mut()
+    }
+
+    /// Returns the physical address of the root page table.
+    pub const fn root_paddr(&self) -> PhysAddr {
+        self.root_paddr
+    }
+
+    /// Takes the lock, to change the mappings.
+    pub fn lock(&self) -> MutexGuard<'_, L, PageTable64<M, PTE, H, K>> {
+        self.inner.lock()
+    }
+
+    /// Takes the lock if it is free.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, L, PageTable64<M, PTE, H, K>>> {
+        self.inner.try_lock()
+    }
+
+    /// Queries the mapping of `vaddr` without the lock, like
+    /// [`PageTable64::query`].
+    ///
+    /// A query concurrent with a change under the lock sees the mapping
+    /// either before or after it.
+    pub fn query(&self, vaddr: M::VirtAddr) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
+        PageTable64::<M, PTE, H, K>::query_at(self.root_paddr, vaddr)
+    }
+
+    /// Resolves a fault caused by a clear accessed or dirty bit without the
+    /// lock, like [`PageTable64::handle_access_fault`].
+    pub fn handle_access_fault(
+        &self,
+        vaddr: M::VirtAddr,
+        write: bool,
+    ) -> PagingResult<TlbFlush<M>> {
+        PageTable64::<M, PTE, H, K>::access_fault_at(self.root_paddr, vaddr, write)
+    }
+
+    /// Resolves a write fault on a copy-on-write page, like
+    /// [`PageTable64::handle_cow_fault`].
+    ///
+    /// The lock is only taken for a huge page, which may be split, and for a
+    /// page in a group with the contiguous hint, which is broken up.
+    /// Otherwise, if the entry changes while `copy` runs,
+    /// e.g. because another CPU resolved the same fault, it returns
+    /// [`Err(PagingError::Contended)`](crate::PagingError::Contended) and the
+    /// page returned by `copy` is left to the caller. The fault can then be
+    /// retried, if the access still faults.
+    pub fn handle_cow_fault(
+        &self,
+        vaddr: M::VirtAddr,
+        copy: impl FnOnce(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<TlbFlush<M>> {
+        match PageTable64::<M, PTE, H, K>::cow_fault_at(self.root_paddr, vaddr, copy) {
+            Ok(result) => result,
+            Err(copy) => self.lock().handle_cow_fault(vaddr, copy),
+        }
+    }
+}
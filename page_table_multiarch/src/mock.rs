@@ -0,0 +1,762 @@
+//! Host-side test helpers for code built on [`PageTable64`].
+//!
+//! This module is only available with the `mock` feature, and requires `std`.
+//! It provides:
+//!
+//! - [`MockHandler`]: a [`PagingHandler`] backed by the host allocator, which
+//!   tracks every frame it hands out and can be told to fail a specific
+//!   allocation (fault injection).
+//! - [`MockMetaData`]: wraps the metadata of a real architecture, but records
+//!   TLB flushes and fences instead of executing privileged instructions. It
+//!   can also record the value of an entry at each flush, to check the order
+//!   in which entries are written.
+//! - [`ShadowModel`]: a simple reference model of the expected mappings, which
+//!   can be cross-checked against a real page table.
+//! - [`fuzz`]: operations decoded from arbitrary bytes, and a harness running
+//!   them against a [`ShadowModel`], for the fuzz target of the repository
+//!   and for replaying the inputs it finds.
+//!
+//! All the bookkeeping is thread-local, so tests running in parallel do not
+//! interfere with each other.
+
+extern crate std;
+
+pub mod fuzz;
+
+use core::{alloc::Layout, cell::RefCell, marker::PhantomData, sync::atomic::AtomicU64};
+use std::{collections::BTreeMap, vec::Vec};
+
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, PhysAddrRange, VirtAddr};
+
+use crate::bits64::{level_shift, table_entries};
+use crate::{
+    AccessedDirtyPolicy, ByteOrder, CacheOp, MAX_LEVELS, MemoryType, PagingError, PagingMetaData,
+};
+use crate::{AnySpace, GenericPTE, MappingFlags, PageSize, PageTable64, PagingHandler, SpaceKind};
+
+/// Frame allocation counters of [`MockHandler`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Number of successful calls to [`PagingHandler::alloc_frame`],
+    /// [`PagingHandler::alloc_table`] and [`PagingHandler::alloc_frames`].
+    pub allocated: usize,
+    /// Number of calls to [`PagingHandler::dealloc_frame`],
+    /// [`PagingHandler::dealloc_table`] and
+    /// [`PagingHandler::dealloc_frames`].
+    pub deallocated: usize,
+    /// Number of allocations refused by fault injection.
+    pub failed: usize,
+    /// Number of calls to [`PagingHandler::frame_shared`].
+    pub shared: usize,
+    /// Number of calls to [`PagingHandler::frame_unshared`].
+    pub unshared: usize,
+}
+
+#[derive(Default)]
+struct MockState {
+    stats: FrameStats,
+    /// The size of each live allocation.
+    live: BTreeMap<usize, usize>,
+    /// Number of mappings of each page that has been shared.
+    refs: BTreeMap<usize, usize>,
+    zero_frame: Option<PhysAddr>,
+    /// Memory types returned by [`PagingHandler::memory_type_of`].
+    memory_types: Vec<(PhysAddrRange, MemoryType)>,
+    /// Countdown to the next injected allocation failure.
+    fail_at: Option<usize>,
+    flushes: Vec<Option<usize>>,
+    /// Number of calls to [`PagingMetaData::flush_tlb_global`].
+    global_flushes: usize,
+    /// Number of calls to [`PagingMetaData::fence_after_update`].
+    fences: usize,
+    /// Host address of the entry recorded at each flush.
+    watch: Option<usize>,
+    watched: Vec<usize>,
+    /// Called before each atomic write of a leaf entry.
+    leaf_race: Option<fn(&AtomicU64)>,
+    /// The errors reported to [`PagingHandler::on_error`].
+    errors: Vec<(&'static str, usize, PagingError)>,
+    /// The calls to [`PagingHandler::cache_maintain`].
+    cache_ops: Vec<(PhysAddr, usize, CacheOp)>,
+}
+
+std::thread_local! {
+    static STATE: RefCell<MockState> = RefCell::new(MockState::default());
+}
+
+/// A [`PagingHandler`] that allocates frames from the host heap.
+///
+/// Physical addresses are identity-mapped to host virtual addresses. Freeing
+/// a frame that was not allocated by this handler (or freeing it twice)
+/// panics.
+pub struct MockHandler;
+
+impl MockHandler {
+    /// Resets the allocation counters, reference counts, zero frame, memory
+    /// types, reported errors and cache maintenance, and disarms fault
+    /// injection.
+    ///
+    /// Frames that are still live are kept track of.
+    pub fn reset() {
+        STATE.with_borrow_mut(|s| {
+            s.stats = FrameStats::default();
+            s.fail_at = None;
+            s.refs.clear();
+            s.zero_frame = None;
+            s.memory_types.clear();
+            s.flushes.clear();
+            s.global_flushes = 0;
+            s.fences = 0;
+            s.watch = None;
+            s.watched.clear();
+            s.leaf_race = None;
+            s.errors.clear();
+            s.cache_ops.clear();
+        })
+    }
+
+    /// Returns the allocation counters.
+    pub fn stats() -> FrameStats {
+        STATE.with_borrow(|s| s.stats)
+    }
+
+    /// Returns the number of frames that have been allocated but not freed.
+    pub fn live_frames() -> usize {
+        STATE.with_borrow(|s| s.live.len())
+    }
+
+    /// Makes the `n`-th allocation from now on (counting from `0`) fail.
+    ///
+    /// Only that single allocation fails, the following ones succeed again.
+    /// Passing [`None`] disarms the fault injection.
+    pub fn fail_alloc_at(n: Option<usize>) {
+        STATE.with_borrow_mut(|s| s.fail_at = n)
+    }
+
+    /// Calls `hook` with the raw bits of a leaf entry right before each
+    /// attempt to write it atomically, after the page table read it. This
+    /// simulates the hardware setting the accessed and dirty bits
+    /// concurrently.
+    ///
+    /// Passing [`None`] removes the hook.
+    pub fn race_leaf_writes(hook: Option<fn(&AtomicU64)>) {
+        STATE.with_borrow_mut(|s| s.leaf_race = hook)
+    }
+
+    /// Sets the frame returned by [`PagingHandler::zero_frame`].
+    pub fn set_zero_frame(paddr: Option<PhysAddr>) {
+        STATE.with_borrow_mut(|s| s.zero_frame = paddr)
+    }
+
+    /// Requires the frames in `paddrs` to be mapped with the memory type `ty`,
+    /// as returned by [`PagingHandler::memory_type_of`].
+    pub fn set_memory_type(paddrs: PhysAddrRange, ty: MemoryType) {
+        STATE.with_borrow_mut(|s| s.memory_types.push((paddrs, ty)))
+    }
+
+    /// Returns the errors reported to [`PagingHandler::on_error`] since the
+    /// last call, with the operations and addresses.
+    pub fn take_errors() -> Vec<(&'static str, usize, PagingError)> {
+        STATE.with_borrow_mut(|s| core::mem::take(&mut s.errors))
+    }
+
+    /// Returns the calls to [`PagingHandler::cache_maintain`] since the last
+    /// call.
+    pub fn take_cache_ops() -> Vec<(PhysAddr, usize, CacheOp)> {
+        STATE.with_borrow_mut(|s| core::mem::take(&mut s.cache_ops))
+    }
+
+    /// Returns the reference count of the page at `paddr`, as maintained by
+    /// [`PagingHandler::frame_shared`] and [`PagingHandler::frame_unshared`].
+    /// Pages start with one reference, and the frames allocated by the
+    /// handler are freed when their count drops to zero.
+    ///
+    /// Returns [`None`] if the page has never been shared or unshared.
+    pub fn refs(paddr: PhysAddr) -> Option<usize> {
+        STATE.with_borrow(|s| s.refs.get(&paddr.as_usize()).copied())
+    }
+
+    /// Allocates `size` bytes aligned to `size`.
+    fn alloc(size: usize) -> Option<PhysAddr> {
+        STATE.with_borrow_mut(|s| {
+            match s.fail_at {
+                Some(0) => {
+                    s.fail_at = None;
+                    s.stats.failed += 1;
+                    return None;
+                }
+                Some(ref mut n) => *n -= 1,
+                None => {}
+            }
+            let ptr = unsafe { std::alloc::alloc(Self::layout(size)) };
+            if ptr.is_null() {
+                return None;
+            }
+            s.stats.allocated += 1;
+            s.live.insert(ptr as usize, size);
+            Some(PhysAddr::from(ptr as usize))
+        })
+    }
+
+    /// Frees the allocation at `paddr`, after checking its size if given.
+    fn dealloc(paddr: PhysAddr, size: Option<usize>) {
+        let live = STATE.with_borrow_mut(|s| {
+            let live = s.live.remove(&paddr.as_usize());
+            assert!(
+                live.is_some(),
+                "freeing a frame not allocated by MockHandler: {:#x}",
+                paddr
+            );
+            s.stats.deallocated += 1;
+            live.unwrap()
+        });
+        if let Some(size) = size {
+            assert_eq!(live, size, "freeing {:#x} with the wrong size", paddr);
+        }
+        unsafe { std::alloc::dealloc(paddr.as_usize() as *mut u8, Self::layout(live)) };
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, size).unwrap()
+    }
+}
+
+impl PagingHandler for MockHandler {
+    fn alloc_frame() -> Option<PhysAddr> {
+        Self::alloc(PAGE_SIZE_4K)
+    }
+
+    fn dealloc_frame(paddr: PhysAddr) {
+        Self::dealloc(paddr, Some(PAGE_SIZE_4K))
+    }
+
+    fn alloc_table(size: usize) -> Option<PhysAddr> {
+        Self::alloc(size)
+    }
+
+    fn dealloc_table(paddr: PhysAddr, size: usize) {
+        Self::dealloc(paddr, Some(size))
+    }
+
+    /// Allocates the frames at once when their size is a power of two, to
+    /// which they are aligned.
+    fn alloc_frames(count: usize, align: usize) -> Option<PhysAddr> {
+        let size = count * PAGE_SIZE_4K;
+        if !size.is_power_of_two() || align > size {
+            return None;
+        }
+        Self::alloc(size)
+    }
+
+    fn dealloc_frames(paddr: PhysAddr, count: usize) {
+        Self::dealloc(paddr, Some(count * PAGE_SIZE_4K))
+    }
+
+    fn on_error(op: &'static str, vaddr: usize, err: &PagingError) {
+        STATE.with_borrow_mut(|s| s.errors.push((op, vaddr, *err)))
+    }
+
+    fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+        VirtAddr::from(paddr.as_usize())
+    }
+
+    fn frame_shared(paddr: PhysAddr, _size: PageSize) {
+        STATE.with_borrow_mut(|s| {
+            s.stats.shared += 1;
+            *s.refs.entry(paddr.as_usize()).or_insert(1) += 1;
+        })
+    }
+
+    fn zero_frame() -> Option<PhysAddr> {
+        STATE.with_borrow(|s| s.zero_frame)
+    }
+
+    fn memory_type_of(paddr: PhysAddr, size: PageSize) -> Option<MemoryType> {
+        let frames = PhysAddrRange::from_start_size(paddr, size as usize);
+        STATE.with_borrow(|s| {
+            s.memory_types
+                .iter()
+                .find(|(paddrs, _)| paddrs.overlaps(frames))
+                .map(|&(_, ty)| ty)
+        })
+    }
+
+    fn cache_maintain(paddr: PhysAddr, size: usize, op: CacheOp) {
+        STATE.with_borrow_mut(|s| s.cache_ops.push((paddr, size, op)))
+    }
+
+    fn frame_unshared(paddr: PhysAddr, _size: PageSize) {
+        let unused = STATE.with_borrow_mut(|s| {
+            s.stats.unshared += 1;
+            let count = s.refs.entry(paddr.as_usize()).or_insert(1);
+            assert!(
+                *count > 0,
+                "unsharing a page no longer mapped: {:#x}",
+                paddr
+            );
+            *count -= 1;
+            *count == 0 && s.live.contains_key(&paddr.as_usize())
+        });
+        // The frames allocated here are tables of a shared subtree.
+        if unused {
+            Self::dealloc(paddr, None);
+        }
+    }
+}
+
+/// Metadata that behaves like `M`, but records TLB flushes and fences
+/// instead of executing them, so that it can be used on the host.
+pub struct MockMetaData<M: PagingMetaData>(PhantomData<M>);
+
+impl<M: PagingMetaData> MockMetaData<M> {
+    /// Returns the TLB flushes recorded on the current thread and clears the
+    /// record. [`None`] represents a full flush.
+    pub fn take_flushes() -> Vec<Option<M::VirtAddr>> {
+        STATE.with_borrow_mut(|s| s.flushes.drain(..).map(|v| v.map(Into::into)).collect())
+    }
+
+    /// Returns the number of full flushes of the global entries recorded on
+    /// the current thread, which are not included in
+    /// [`MockMetaData::take_flushes`], and clears the count.
+    pub fn take_global_flushes() -> usize {
+        STATE.with_borrow_mut(|s| core::mem::take(&mut s.global_flushes))
+    }
+
+    /// Returns the number of fences after changes to the entries recorded on
+    /// the current thread, and clears the count.
+    pub fn take_fences() -> usize {
+        STATE.with_borrow_mut(|s| core::mem::take(&mut s.fences))
+    }
+
+    /// Starts recording the raw bits of `entry` at every TLB flush, or stops
+    /// recording if [`None`] is given.
+    ///
+    /// The entry is read through its address, so its table must not be freed
+    /// while being watched.
+    pub fn watch_entry<PTE: GenericPTE>(entry: Option<*const PTE>) {
+        STATE.with_borrow_mut(|s| s.watch = entry.map(|e| e as usize))
+    }
+
+    /// Returns the values of the watched entry recorded at each TLB flush and
+    /// clears the record.
+    pub fn take_watched() -> Vec<usize> {
+        STATE.with_borrow_mut(|s| s.watched.drain(..).collect())
+    }
+}
+
+impl<M: PagingMetaData> PagingMetaData for MockMetaData<M> {
+    const LEVELS: usize = M::LEVELS;
+    const PA_MAX_BITS: usize = M::PA_MAX_BITS;
+    const VA_MAX_BITS: usize = M::VA_MAX_BITS;
+    const ARCH_NAME: &'static str = M::ARCH_NAME;
+    const USER_VA_END: usize = M::USER_VA_END;
+    const PA_MAX_ADDR: usize = M::PA_MAX_ADDR;
+    const BYTE_ORDER: ByteOrder = M::BYTE_ORDER;
+    const INDEX_BITS: [u8; MAX_LEVELS] = M::INDEX_BITS;
+    const TLB_CACHES_INVALID: bool = M::TLB_CACHES_INVALID;
+    const BREAK_BEFORE_MAKE: bool = M::BREAK_BEFORE_MAKE;
+    const AD_POLICY: AccessedDirtyPolicy = M::AD_POLICY;
+    const MAX_PAGE_SIZE: PageSize = M::MAX_PAGE_SIZE;
+    const FLUSH_PAGES_THRESHOLD: usize = M::FLUSH_PAGES_THRESHOLD;
+    type VirtAddr = M::VirtAddr;
+
+    #[inline]
+    fn paddr_is_valid(paddr: usize) -> bool {
+        M::paddr_is_valid(paddr)
+    }
+
+    #[inline]
+    fn vaddr_is_valid(vaddr: usize) -> bool {
+        M::vaddr_is_valid(vaddr)
+    }
+
+    #[inline]
+    fn is_user_vaddr(vaddr: usize) -> bool {
+        M::is_user_vaddr(vaddr)
+    }
+
+    #[inline]
+    fn is_kernel_vaddr(vaddr: usize) -> bool {
+        M::is_kernel_vaddr(vaddr)
+    }
+
+    fn flush_tlb(vaddr: Option<M::VirtAddr>) {
+        STATE.with_borrow_mut(|s| {
+            s.flushes.push(vaddr.map(Into::into));
+            if let Some(addr) = s.watch {
+                let bits = unsafe { core::ptr::read_volatile(addr as *const usize) };
+                s.watched.push(bits);
+            }
+        })
+    }
+
+    fn flush_tlb_global() {
+        STATE.with_borrow_mut(|s| s.global_flushes += 1)
+    }
+
+    fn fence_after_update() {
+        STATE.with_borrow_mut(|s| s.fences += 1)
+    }
+}
+
+/// Calls the hook set by [`MockHandler::race_leaf_writes`].
+pub(crate) fn race_leaf_write(entry: &AtomicU64) {
+    if let Some(hook) = STATE.with_borrow(|s| s.leaf_race) {
+        hook(entry);
+    }
+}
+
+/// A page table using [`MockMetaData`] and [`MockHandler`].
+pub type MockPageTable<M, PTE, K = AnySpace> = PageTable64<MockMetaData<M>, PTE, MockHandler, K>;
+
+/// Returns the number of frames used by `pt` for page tables, including the
+/// root table.
+pub fn table_frames<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+    pt: &PageTable64<M, PTE, H, K>,
+) -> usize {
+    let frames = |level| (table_entries::<M>(level) * size_of::<PTE>()).div_ceil(PAGE_SIZE_4K);
+    let count = RefCell::new(frames(0));
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, _, entry: &PTE| {
+            if level < M::LEVELS - 1 && entry.is_table() {
+                *count.borrow_mut() += frames(level + 1);
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    count.into_inner()
+}
+
+/// Returns the start of every group of entries with the contiguous hint in
+/// `pt` that is inconsistent: some of its 16 entries are missing the hint, or
+/// they do not map consecutive frames with the same flags.
+pub fn check_contiguous<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+    pt: &PageTable64<M, PTE, H, K>,
+) -> Vec<usize> {
+    let group_size = PageSize::Size64K as usize;
+    let groups = RefCell::new(BTreeMap::<usize, Vec<(usize, PTE)>>::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+            if level == M::LEVELS - 1 {
+                let vaddr: usize = vaddr.into();
+                let base = vaddr & !(group_size - 1);
+                groups
+                    .borrow_mut()
+                    .entry(base)
+                    .or_default()
+                    .push((vaddr, *entry));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let consistent = |base: usize, entries: &[(usize, PTE)]| {
+        let (_, first) = entries[0];
+        let paddr = first.leaf_paddr(false).align_down(PageSize::Size64K);
+        entries.len() == group_size / PAGE_SIZE_4K
+            && entries.iter().all(|(vaddr, entry)| {
+                entry.is_contiguous()
+                    && entry.leaf_flags(false) == first.leaf_flags(false)
+                    // The entries may all hold the start of the group.
+                    && [paddr, paddr.add(vaddr - base)].contains(&entry.leaf_paddr(false))
+            })
+    };
+    groups
+        .into_inner()
+        .into_iter()
+        .filter(|(base, entries)| {
+            entries.iter().any(|(_, entry)| entry.is_contiguous()) && !consistent(*base, entries)
+        })
+        .map(|(base, _)| base)
+        .collect()
+}
+
+/// Returns the start of every huge page of `pt` whose frame is not aligned to
+/// its size.
+///
+/// [`PageTable64`] never creates them, but they may be written by other
+/// means. The hardware does not ignore the low bits of such a frame: RISC-V
+/// takes them as a reserved encoding, and other formats either fault or read
+/// other fields from them. Like for [`PageTable64::walk`], the addresses are
+/// not sign-extended.
+pub fn check_huge_pages<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+    pt: &PageTable64<M, PTE, H, K>,
+) -> Vec<usize> {
+    let misaligned = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+            let size = 1usize << level_shift::<M>(level);
+            if level < M::LEVELS - 1 && entry.is_huge() && !entry.paddr().is_aligned(size) {
+                misaligned.borrow_mut().push(vaddr.into());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    misaligned.into_inner()
+}
+
+/// Returns the virtual address and the unknown bits (see
+/// [`GenericPTE::unknown_bits`]) of every leaf entry of `pt` carrying bits
+/// not modeled by this crate, and logs a warning for each.
+///
+/// [`PageTable64`] never sets them, but keeps them in the entries it
+/// rewrites, e.g. in a table built by firmware. Like for
+/// [`PageTable64::walk`], the addresses are not sign-extended.
+pub fn check_unknown_bits<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+    pt: &PageTable64<M, PTE, H, K>,
+) -> Vec<(usize, usize)> {
+    let unknown = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+            let bits = entry.unknown_bits();
+            if (level == M::LEVELS - 1 || entry.is_huge()) && bits != 0 {
+                let vaddr: usize = vaddr.into();
+                log::warn!("unmodeled bits {bits:#x} in the entry of {vaddr:#x}: {entry:?}");
+                unknown.borrow_mut().push((vaddr, bits));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    unknown.into_inner()
+}
+
+/// Returns the level and the virtual address of every entry of `pt` that is
+/// not well-formed (see [`GenericPTE::is_well_formed`]), e.g. with reserved
+/// bits set by firmware, and logs a warning for each.
+///
+/// Like for [`PageTable64::walk`], the addresses are not sign-extended.
+pub fn check_well_formed<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+    pt: &PageTable64<M, PTE, H, K>,
+) -> Vec<(usize, usize)> {
+    let malformed = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+            if !entry.is_well_formed() {
+                let vaddr: usize = vaddr.into();
+                log::warn!("malformed entry of {vaddr:#x} at level {level}: {entry:?}");
+                malformed.borrow_mut().push((level, vaddr));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    malformed.into_inner()
+}
+
+/// Returns the level and the virtual address of every entry of `pt` that was
+/// cleared with the `debug-poison` feature (see [`GenericPTE::is_poisoned`]).
+///
+/// The walk skips these entries, since they are unused. Like for
+/// [`PageTable64::walk`], the addresses are not sign-extended.
+pub fn poisoned_entries<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler, K: SpaceKind>(
+    pt: &PageTable64<M, PTE, H, K>,
+) -> Vec<(usize, usize)> {
+    let poisoned = RefCell::new(Vec::new());
+    let scan = |table: PhysAddr, level: usize, start: usize| {
+        let table = H::phys_to_virt(table).as_ptr() as *const PTE;
+        for i in 0..table_entries::<M>(level) {
+            let entry = unsafe { core::ptr::read_volatile(table.add(i)) };
+            if entry.is_poisoned() {
+                let vaddr = start + (i << level_shift::<M>(level));
+                poisoned.borrow_mut().push((level, vaddr));
+            }
+        }
+    };
+    scan(pt.root_paddr(), 0, 0);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+            if level < M::LEVELS - 1 && entry.is_table() {
+                scan(entry.paddr(), level + 1, vaddr.into());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let mut poisoned = poisoned.into_inner();
+    poisoned.sort_unstable();
+    poisoned
+}
+
+/// A reference model of the mappings of a page table.
+///
+/// Every successful operation on the real page table should be mirrored into
+/// the model, then [`ShadowModel::check`] verifies that both agree.
+pub struct ShadowModel<M: PagingMetaData, PTE: GenericPTE> {
+    mappings: BTreeMap<usize, (PhysAddr, MappingFlags, PageSize)>,
+    _phantom: PhantomData<(M, PTE)>,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE> ShadowModel<M, PTE> {
+    /// Creates an empty model.
+    pub const fn new() -> Self {
+        Self {
+            mappings: BTreeMap::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the number of mappings (of any page size) in the model.
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    /// Whether the model has no mappings.
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Returns the mapping that covers `vaddr`, as `(start, paddr, flags, size)`.
+    pub fn lookup(
+        &self,
+        vaddr: M::VirtAddr,
+    ) -> Option<(M::VirtAddr, PhysAddr, MappingFlags, PageSize)> {
+        let vaddr: usize = vaddr.into();
+        let (&start, &(paddr, flags, size)) = self.mappings.range(..=vaddr).next_back()?;
+        (vaddr < start + size as usize).then(|| (start.into(), paddr, flags, size))
+    }
+
+    /// Whether any mapping overlaps with `[vaddr, vaddr + size)`.
+    pub fn overlaps(&self, vaddr: M::VirtAddr, size: usize) -> bool {
+        let start: usize = vaddr.into();
+        self.lookup(vaddr).is_some() || self.mappings.range(start..start + size).next().is_some()
+    }
+
+    /// Records a new mapping, as done by [`PageTable64::map`].
+    ///
+    /// Panics if it overlaps with an existing mapping, which means the real
+    /// page table accepted a conflicting mapping.
+    pub fn map(
+        &mut self,
+        vaddr: M::VirtAddr,
+        paddr: PhysAddr,
+        size: PageSize,
+        flags: MappingFlags,
+    ) {
+        let vaddr = vaddr.align_down(size);
+        assert!(
+            !self.overlaps(vaddr, size as usize),
+            "conflicting mapping at {:#x?} ({:?})",
+            vaddr.into(),
+            size
+        );
+        let paddr = paddr.align_down(size);
+        let flags = Self::normalize(paddr, flags, size);
+        self.mappings.insert(vaddr.into(), (paddr, flags, size));
+    }
+
+    /// Removes the mapping that covers `vaddr`, as done by
+    /// [`PageTable64::unmap`]. A [`PageSize::Size64K`] mapping is split into
+    /// 4K mappings first.
+    ///
+    /// Returns the physical address and size of the removed mapping.
+    pub fn unmap(&mut self, vaddr: M::VirtAddr) -> Option<(PhysAddr, PageSize)> {
+        self.split_contiguous(vaddr);
+        let (start, paddr, _, size) = self.lookup(vaddr)?;
+        self.mappings.remove(&start.into());
+        Some((paddr, size))
+    }
+
+    /// Updates the flags of the mapping that covers `vaddr`, as done by
+    /// [`PageTable64::protect`]. A [`PageSize::Size64K`] mapping is split into
+    /// 4K mappings first.
+    ///
+    /// Returns the size of the updated mapping.
+    pub fn protect(&mut self, vaddr: M::VirtAddr, flags: MappingFlags) -> Option<PageSize> {
+        self.split_contiguous(vaddr);
+        let (start, paddr, _, size) = self.lookup(vaddr)?;
+        let flags = Self::normalize(paddr, flags, size);
+        self.mappings.insert(start.into(), (paddr, flags, size));
+        Some(size)
+    }
+
+    /// Checks that `pt` contains exactly the mappings of the model.
+    ///
+    /// Panics with a description of the first difference.
+    pub fn check<H: PagingHandler, K: SpaceKind>(&self, pt: &PageTable64<M, PTE, H, K>) {
+        for (&start, &(paddr, flags, size)) in &self.mappings {
+            let last = size as usize - PAGE_SIZE_4K;
+            for off in [0, last / 2, last] {
+                let vaddr = start + off;
+                assert_eq!(
+                    pt.query(vaddr.into()),
+                    Ok((paddr.add(off), flags, size)),
+                    "query({:#x}) disagrees with the model",
+                    vaddr
+                );
+            }
+        }
+
+        let leaves = RefCell::new(0);
+        pt.walk(
+            usize::MAX,
+            Some(&|level, _, vaddr: M::VirtAddr, entry: &PTE| {
+                if level == M::LEVELS - 1 || entry.is_huge() {
+                    let vaddr: usize = vaddr.into();
+                    // A group with the contiguous hint is a single mapping.
+                    if entry.is_contiguous() && !PageSize::Size64K.is_aligned(vaddr) {
+                        return;
+                    }
+                    assert!(
+                        self.mappings.contains_key(&vaddr),
+                        "unexpected mapping at {:#x}: {:?}",
+                        vaddr,
+                        entry
+                    );
+                    *leaves.borrow_mut() += 1;
+                }
+            }),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            leaves.into_inner(),
+            self.mappings.len(),
+            "leaf count disagrees with the model"
+        );
+    }
+
+    /// Replaces the huge page or the [`PageSize::Size64K`] mapping covering
+    /// `vaddr` with 4K mappings of the same frames and flags, as done by
+    /// [`PageTable64::handle_cow_fault`].
+    pub fn split(&mut self, vaddr: M::VirtAddr) {
+        if let Some((start, paddr, flags, size)) = self.lookup(vaddr)
+            && size != PageSize::Size4K
+        {
+            let start: usize = start.into();
+            for off in (0..size as usize).step_by(PAGE_SIZE_4K) {
+                let paddr = paddr.add(off);
+                let flags = Self::normalize(paddr, flags, PageSize::Size4K);
+                self.mappings
+                    .insert(start + off, (paddr, flags, PageSize::Size4K));
+            }
+        }
+    }
+
+    /// Replaces a [`PageSize::Size64K`] mapping covering `vaddr` with 16 4K
+    /// mappings.
+    fn split_contiguous(&mut self, vaddr: M::VirtAddr) {
+        if let Some((.., PageSize::Size64K)) = self.lookup(vaddr) {
+            self.split(vaddr);
+        }
+    }
+
+    /// Returns the flags as they read back from an entry of type `PTE`.
+    fn normalize(paddr: PhysAddr, flags: MappingFlags, size: PageSize) -> MappingFlags {
+        PTE::new_page(paddr, flags, size.is_huge()).leaf_flags(size.is_huge())
+    }
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE> Default for ShadowModel<M, PTE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
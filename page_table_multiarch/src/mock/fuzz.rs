@@ -0,0 +1,669 @@
+//! Operations on a page table decoded from arbitrary bytes, and a harness
+//! checking them against a [`ShadowModel`].
+//!
+//! [`run`] is the body of the fuzz target in the `fuzz/` directory of the
+//! repository, and replays the inputs it finds in tests. Every operation
+//! stays in a window of [`WINDOW_PAGES`] 4K pages from [`WINDOW_BASE`], so
+//! that they collide often and each input runs quickly whatever its length.
+//!
+//! Each mapping gets frames of its own, never mapped before, so that the
+//! reference counts of [`MockHandler`] stay meaningful: pages only become
+//! copy-on-write through [`PageTable64::clone_cow`].
+
+extern crate std;
+
+use std::vec::Vec;
+
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
+
+use super::{
+    MockHandler, MockMetaData, MockPageTable, ShadowModel, check_contiguous, check_huge_pages,
+    table_frames,
+};
+use crate::{
+    GenericPTE, MappingFlags, PageSize, PageTable64, PagingError, PagingMetaData, PagingResult,
+    RegionCursor,
+};
+
+/// The start of the window of the operations, aligned to 1G.
+pub const WINDOW_BASE: usize = 0x4000_0000;
+/// The number of 4K pages of the window, i.e. 4 2M blocks.
+pub const WINDOW_PAGES: usize = 2048;
+/// The number of operations decoded from an input at most.
+pub const MAX_OPS: usize = 256;
+
+/// The start of the frames given to the mappings.
+const PADDR_BASE: usize = 0x8000_0000;
+
+/// The flags of the operations, indexed by a byte of the input. Empty flags
+/// are left out: a page made inaccessible is not seen by
+/// [`PageTable64::walk`], which [`ShadowModel::check`] relies on.
+pub const FLAGS: [MappingFlags; 5] = [
+    MappingFlags::READ,
+    MappingFlags::READ.union(MappingFlags::WRITE),
+    MappingFlags::READ.union(MappingFlags::EXECUTE),
+    MappingFlags::READ
+        .union(MappingFlags::WRITE)
+        .union(MappingFlags::USER),
+    MappingFlags::READ
+        .union(MappingFlags::WRITE)
+        .union(MappingFlags::EXECUTE),
+];
+
+/// The page sizes of [`Op::Map`], indexed by a byte of the input.
+const SIZES: [PageSize; 3] = [PageSize::Size4K, PageSize::Size64K, PageSize::Size2M];
+
+/// An operation on the page table of a [`Harness`].
+///
+/// Pages are given as indices of 4K pages in the window, and regions as a
+/// first page and a number of pages that stay in the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// [`PageTable64::map`] at the page, aligned down to `size`.
+    Map {
+        /// The page mapped.
+        page: usize,
+        /// The size of the page, one of [`PageSize::Size4K`],
+        /// [`PageSize::Size64K`] and [`PageSize::Size2M`].
+        size: PageSize,
+        /// The flags of the mapping, one of [`FLAGS`].
+        flags: MappingFlags,
+    },
+    /// [`PageTable64::unmap`].
+    Unmap {
+        /// The page unmapped.
+        page: usize,
+    },
+    /// [`PageTable64::protect`].
+    Protect {
+        /// The page protected.
+        page: usize,
+        /// The new flags, one of [`FLAGS`].
+        flags: MappingFlags,
+    },
+    /// [`PageTable64::map_region`], to consecutive frames aligned to 2M.
+    MapRegion {
+        /// The first page of the region.
+        page: usize,
+        /// The number of pages of the region.
+        pages: usize,
+        /// The flags of the mappings, one of [`FLAGS`].
+        flags: MappingFlags,
+        /// Whether huge pages may be used.
+        allow_huge: bool,
+    },
+    /// [`PageTable64::unmap_region`].
+    UnmapRegion {
+        /// The first page of the region.
+        page: usize,
+        /// The number of pages of the region.
+        pages: usize,
+    },
+    /// [`PageTable64::protect_region`].
+    ProtectRegion {
+        /// The first page of the region.
+        page: usize,
+        /// The number of pages of the region.
+        pages: usize,
+        /// The new flags, one of [`FLAGS`].
+        flags: MappingFlags,
+    },
+    /// [`PageTable64::clone_cow`], checking the child before dropping it.
+    CloneCow {
+        /// The first page of the region.
+        page: usize,
+        /// The number of pages of the region.
+        pages: usize,
+    },
+    /// [`PageTable64::handle_cow_fault`], copying to a new frame.
+    CowFault {
+        /// The page written to.
+        page: usize,
+    },
+}
+
+impl Op {
+    /// Appends the bytes that [`OpDecoder`] decodes to this operation, e.g.
+    /// to write the seeds of the fuzz target.
+    ///
+    /// Panics if the operation leaves the window or uses flags or a page size
+    /// that cannot be decoded.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let page = |out: &mut Vec<u8>, page: usize| {
+            assert!(page < WINDOW_PAGES, "page {page} out of the window");
+            out.extend_from_slice(&(page as u16).to_le_bytes());
+        };
+        let pages = |out: &mut Vec<u8>, first: usize, pages: usize| {
+            assert!(pages > 0 && first + pages <= WINDOW_PAGES);
+            out.extend_from_slice(&(pages as u16 - 1).to_le_bytes());
+        };
+        let flags = |out: &mut Vec<u8>, flags: MappingFlags| {
+            let index = FLAGS.iter().position(|&f| f == flags);
+            out.push(index.expect("flags that cannot be decoded") as u8);
+        };
+        match *self {
+            Self::Map {
+                page: first,
+                size,
+                flags: f,
+            } => {
+                out.push(0);
+                page(out, first);
+                let index = SIZES.iter().position(|&s| s == size);
+                out.push(index.expect("page size that cannot be decoded") as u8);
+                flags(out, f);
+            }
+            Self::Unmap { page: first } => {
+                out.push(1);
+                page(out, first);
+            }
+            Self::Protect {
+                page: first,
+                flags: f,
+            } => {
+                out.push(2);
+                page(out, first);
+                flags(out, f);
+            }
+            Self::MapRegion {
+                page: first,
+                pages: count,
+                flags: f,
+                allow_huge,
+            } => {
+                out.push(3);
+                page(out, first);
+                pages(out, first, count);
+                flags(out, f);
+                out.push(allow_huge as u8);
+            }
+            Self::UnmapRegion {
+                page: first,
+                pages: count,
+            } => {
+                out.push(4);
+                page(out, first);
+                pages(out, first, count);
+            }
+            Self::ProtectRegion {
+                page: first,
+                pages: count,
+                flags: f,
+            } => {
+                out.push(5);
+                page(out, first);
+                pages(out, first, count);
+                flags(out, f);
+            }
+            Self::CloneCow {
+                page: first,
+                pages: count,
+            } => {
+                out.push(6);
+                page(out, first);
+                pages(out, first, count);
+            }
+            Self::CowFault { page: first } => {
+                out.push(7);
+                page(out, first);
+            }
+        }
+    }
+}
+
+/// Encodes `ops` with [`Op::encode`].
+pub fn encode(ops: &[Op]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for op in ops {
+        op.encode(&mut out);
+    }
+    out
+}
+
+/// Decodes a byte stream into operations.
+///
+/// Each operation starts with a byte selecting it, followed by its fields: a
+/// page and a number of pages as 16-bit little-endian integers, taken modulo
+/// what fits in the window, and one byte for each of the flags (an index in
+/// [`FLAGS`]), the page size and `allow_huge`. Any input decodes to valid
+/// operations, and decoding stops at the first one that is cut short.
+pub struct OpDecoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> OpDecoder<'a> {
+    /// Creates a decoder of `bytes`.
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.bytes.split_first()?;
+        self.bytes = rest;
+        Some(byte)
+    }
+
+    fn u16(&mut self) -> Option<usize> {
+        Some(u16::from_le_bytes([self.byte()?, self.byte()?]) as usize)
+    }
+
+    fn page(&mut self) -> Option<usize> {
+        Some(self.u16()? % WINDOW_PAGES)
+    }
+
+    fn pages(&mut self, first: usize) -> Option<usize> {
+        Some(1 + self.u16()? % (WINDOW_PAGES - first))
+    }
+
+    fn flags(&mut self) -> Option<MappingFlags> {
+        Some(FLAGS[self.byte()? as usize % FLAGS.len()])
+    }
+}
+
+impl Iterator for OpDecoder<'_> {
+    type Item = Op;
+
+    fn next(&mut self) -> Option<Op> {
+        let op = match self.byte()? % 8 {
+            0 => Op::Map {
+                page: self.page()?,
+                size: SIZES[self.byte()? as usize % SIZES.len()],
+                flags: self.flags()?,
+            },
+            1 => Op::Unmap { page: self.page()? },
+            2 => Op::Protect {
+                page: self.page()?,
+                flags: self.flags()?,
+            },
+            3 => {
+                let page = self.page()?;
+                Op::MapRegion {
+                    page,
+                    pages: self.pages(page)?,
+                    flags: self.flags()?,
+                    allow_huge: self.byte()? & 1 != 0,
+                }
+            }
+            4 => {
+                let page = self.page()?;
+                Op::UnmapRegion {
+                    page,
+                    pages: self.pages(page)?,
+                }
+            }
+            5 => {
+                let page = self.page()?;
+                Op::ProtectRegion {
+                    page,
+                    pages: self.pages(page)?,
+                    flags: self.flags()?,
+                }
+            }
+            6 => {
+                let page = self.page()?;
+                Op::CloneCow {
+                    page,
+                    pages: self.pages(page)?,
+                }
+            }
+            _ => Op::CowFault { page: self.page()? },
+        };
+        Some(op)
+    }
+}
+
+/// Runs the operations decoded from `bytes`, at most [`MAX_OPS`] of them, on
+/// a new [`Harness`] checked after each one, then checks that every frame
+/// allocated by the page table has been freed.
+///
+/// Resets [`MockHandler`]. Panics at the first disagreement.
+pub fn run<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(bytes: &[u8]) {
+    let mut harness = Harness::<M, PTE>::new();
+    for op in OpDecoder::new(bytes).take(MAX_OPS) {
+        harness.apply(&op);
+        harness.check();
+    }
+    drop(harness);
+    let stats = MockHandler::stats();
+    assert_eq!(stats.allocated, stats.deallocated, "unbalanced allocations");
+    assert_eq!(MockHandler::live_frames(), 0, "leaked frames");
+}
+
+/// A page table and a [`ShadowModel`] of it, kept in sync by
+/// [`Harness::apply`].
+pub struct Harness<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE> {
+    pt: PageTable64<MockMetaData<M>, PTE, MockHandler>,
+    model: ShadowModel<MockMetaData<M>, PTE>,
+    /// The first frame never given to a mapping.
+    next_frame: usize,
+}
+
+impl<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE> Harness<M, PTE> {
+    /// Resets [`MockHandler`], and creates an empty page table.
+    pub fn new() -> Self {
+        MockHandler::reset();
+        Self {
+            pt: MockPageTable::try_new().unwrap(),
+            model: ShadowModel::new(),
+            next_frame: PADDR_BASE,
+        }
+    }
+
+    /// Returns the page table.
+    pub fn page_table(&self) -> &MockPageTable<M, PTE> {
+        &self.pt
+    }
+
+    /// Returns the model of the page table.
+    pub fn model(&self) -> &ShadowModel<MockMetaData<M>, PTE> {
+        &self.model
+    }
+
+    /// Runs `op` on the page table, checks that its outcome is the one
+    /// predicted by the model, and mirrors it into the model.
+    ///
+    /// The model cannot tell which empty tables are left behind by the
+    /// unmaps, so a huge page refused as already mapped in a free part of
+    /// the window is accepted. A region operation reaching a huge page that
+    /// crosses the end of the region is skipped: the region operations
+    /// require the whole huge page, and panic otherwise.
+    pub fn apply(&mut self, op: &Op) {
+        match *op {
+            Op::Map { page, size, flags } => self.map(va(page).align_down(size), size, flags),
+            Op::Unmap { page } => {
+                let vaddr = va(page);
+                match self.pt.unmap(vaddr) {
+                    Ok((paddr, size, tlb)) => {
+                        tlb.ignore();
+                        assert_eq!(self.model.unmap(vaddr), Some((paddr, size)));
+                    }
+                    Err(e) => self.check_refused(vaddr, e),
+                }
+            }
+            Op::Protect { page, flags } => {
+                let vaddr = va(page);
+                match self.pt.protect(vaddr, flags) {
+                    Ok((size, tlb)) => {
+                        tlb.ignore();
+                        assert_eq!(self.model.protect(vaddr, flags), Some(size));
+                    }
+                    Err(e) => self.check_refused(vaddr, e),
+                }
+            }
+            Op::MapRegion {
+                page,
+                pages,
+                flags,
+                allow_huge,
+            } => self.map_region(va(page), pages * PAGE_SIZE_4K, flags, allow_huge),
+            Op::UnmapRegion { page, pages } => {
+                let (start, size) = (va(page), pages * PAGE_SIZE_4K);
+                if self.cuts_huge_page(start, size) {
+                    return;
+                }
+                let result = self.pt.unmap_region(start, size, false);
+                self.mirror_region(
+                    start,
+                    size,
+                    result.map(|tlb| tlb.ignore()),
+                    |model, vaddr| model.unmap(vaddr).map(|(_, size)| size),
+                );
+            }
+            Op::ProtectRegion { page, pages, flags } => {
+                let (start, size) = (va(page), pages * PAGE_SIZE_4K);
+                if self.cuts_huge_page(start, size) {
+                    return;
+                }
+                let result = self.pt.protect_region(start, size, flags, false);
+                self.mirror_region(
+                    start,
+                    size,
+                    result.map(|tlb| tlb.ignore()),
+                    |model, vaddr| model.protect(vaddr, flags),
+                );
+            }
+            Op::CloneCow { page, pages } => self.clone_cow(va(page), pages * PAGE_SIZE_4K),
+            Op::CowFault { page } => self.cow_fault(va(page)),
+        }
+    }
+
+    /// Checks that the page table agrees with the model, owns every live
+    /// frame for its tables, and has consistent groups with the contiguous
+    /// hint and aligned huge pages.
+    pub fn check(&self) {
+        self.model.check(&self.pt);
+        assert_eq!(MockHandler::live_frames(), table_frames(&self.pt));
+        let groups = check_contiguous(&self.pt);
+        assert!(groups.is_empty(), "inconsistent groups at {groups:#x?}");
+        let huge_pages = check_huge_pages(&self.pt);
+        assert!(
+            huge_pages.is_empty(),
+            "misaligned huge pages at {huge_pages:#x?}"
+        );
+    }
+
+    /// Returns `size` bytes of frames never mapped before, aligned to `align`.
+    fn frames(&mut self, size: usize, align: PageSize) -> PhysAddr {
+        let paddr = self.next_frame.align_up(align as usize);
+        self.next_frame = paddr + size;
+        PhysAddr::from(paddr)
+    }
+
+    fn map(&mut self, vaddr: VirtAddr, size: PageSize, flags: MappingFlags) {
+        let paddr = self.frames(size as usize, size);
+        let result = self.pt.map(vaddr, paddr, size, flags);
+        let overlaps = self.model.overlaps(vaddr, size as usize);
+        match result {
+            Ok(tlb) => {
+                tlb.ignore();
+                assert!(size != PageSize::Size64K || PTE::CONTIGUOUS_HINT);
+                self.model.map(vaddr, paddr, size, flags);
+            }
+            Err(PagingError::AlreadyMapped | PagingError::MappedToHugePage { .. }) => assert!(
+                overlaps || size.is_huge(),
+                "map({vaddr:#x}, {size:?}) refused in a free part of the window"
+            ),
+            Err(PagingError::UnsupportedPageSize) => {
+                assert!(size == PageSize::Size64K && !PTE::CONTIGUOUS_HINT)
+            }
+            Err(e) => panic!("unexpected error of map({vaddr:#x}, {size:?}): {e:?}"),
+        }
+    }
+
+    /// Checks that the model tells why the page at `vaddr` could not be
+    /// unmapped or protected.
+    fn check_refused(&self, vaddr: VirtAddr, err: PagingError) {
+        match err {
+            PagingError::NotMapped => assert_eq!(self.model.lookup(vaddr), None),
+            // Only the start of a huge page is accepted.
+            PagingError::MappedToHugePage { vaddr: start, .. } => {
+                let (page, _, _, size) = self.model.lookup(vaddr).unwrap();
+                assert!(size.is_huge());
+                assert_eq!(page.as_usize(), start);
+                assert_ne!(vaddr.as_usize(), start);
+            }
+            e => panic!("unexpected error at {vaddr:#x}: {e:?}"),
+        }
+    }
+
+    /// Returns the page size that [`PageTable64::map_region`] uses at
+    /// `vaddr`, mapped to `paddr`, with `size` bytes left. 1G pages never fit
+    /// in the window, and every format supports 2M pages.
+    fn block_size(vaddr: VirtAddr, paddr: PhysAddr, size: usize, allow_huge: bool) -> PageSize {
+        let fits = |page_size: PageSize| {
+            vaddr.is_aligned(page_size) && paddr.is_aligned(page_size) && size >= page_size as usize
+        };
+        match () {
+            _ if allow_huge && fits(PageSize::Size2M) => PageSize::Size2M,
+            _ if allow_huge && PTE::CONTIGUOUS_HINT && fits(PageSize::Size64K) => PageSize::Size64K,
+            _ => PageSize::Size4K,
+        }
+    }
+
+    fn map_region(&mut self, start: VirtAddr, size: usize, flags: MappingFlags, allow_huge: bool) {
+        let base = self.frames(size, PageSize::Size2M);
+        let get_paddr = |vaddr: VirtAddr| base.add(vaddr - start);
+        // Tells how far it went on error.
+        let mut cursor = RegionCursor::new(start, size);
+        let result = self
+            .pt
+            .map_region_step(&mut cursor, get_paddr, flags, allow_huge, usize::MAX);
+        let end = start + size;
+        let mut vaddr = start;
+        while vaddr.as_usize() < cursor.next() {
+            let paddr = get_paddr(vaddr);
+            let page_size = Self::block_size(vaddr, paddr, end - vaddr, allow_huge);
+            self.model.map(vaddr, paddr, page_size, flags);
+            vaddr += page_size as usize;
+        }
+        assert_eq!(vaddr.as_usize(), cursor.next());
+        match result {
+            Ok((_, tlb)) => {
+                tlb.ignore();
+                assert_eq!(vaddr, end);
+            }
+            Err(PagingError::AlreadyMapped | PagingError::MappedToHugePage { .. }) => {
+                let page_size = Self::block_size(vaddr, get_paddr(vaddr), end - vaddr, allow_huge);
+                assert!(
+                    self.model.overlaps(vaddr, page_size as usize) || page_size.is_huge(),
+                    "map_region stopped at {vaddr:#x} ({page_size:?}) in a free part of the window"
+                );
+            }
+            Err(e) => panic!("unexpected error of map_region at {vaddr:#x}: {e:?}"),
+        }
+    }
+
+    /// Whether a region operation on `[start, start + size)` reaches a huge
+    /// page crossing the end of the region, before any page it refuses.
+    fn cuts_huge_page(&self, start: VirtAddr, size: usize) -> bool {
+        let (mut vaddr, end) = (start, start + size);
+        while vaddr < end {
+            match self.model.lookup(vaddr) {
+                Some((page, _, _, page_size)) if page_size.is_huge() && page == vaddr => {
+                    vaddr += page_size as usize;
+                    if vaddr > end {
+                        return true;
+                    }
+                }
+                Some((_, _, _, page_size)) if !page_size.is_huge() => vaddr += PAGE_SIZE_4K,
+                // Refused: a hole or the middle of a huge page.
+                _ => return false,
+            }
+        }
+        false
+    }
+
+    /// Mirrors a region operation that returned `result` into the model,
+    /// running `op` on each page like the page table does until the first
+    /// page it refuses, and checks that it stopped there for the same reason.
+    fn mirror_region(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        result: PagingResult,
+        mut op: impl FnMut(&mut ShadowModel<MockMetaData<M>, PTE>, VirtAddr) -> Option<PageSize>,
+    ) {
+        let (mut vaddr, end) = (start, start + size);
+        while vaddr < end {
+            match self.model.lookup(vaddr) {
+                Some((page, _, _, page_size)) if !page_size.is_huge() || page == vaddr => {
+                    vaddr += op(&mut self.model, vaddr).unwrap() as usize;
+                }
+                _ => break,
+            }
+        }
+        match result {
+            Ok(()) => assert_eq!(vaddr, end, "the region operation skipped {vaddr:#x}"),
+            Err(e) => {
+                assert!(
+                    vaddr < end,
+                    "the region operation failed after the region: {e:?}"
+                );
+                self.check_refused(vaddr, e);
+            }
+        }
+    }
+
+    fn clone_cow(&mut self, start: VirtAddr, size: usize) {
+        let end = start + size;
+        // The region must not cut through a huge page or a group with the
+        // contiguous hint.
+        let cuts = [start, end].into_iter().any(|vaddr| {
+            self.model
+                .lookup(vaddr)
+                .is_some_and(|(page, ..)| page != vaddr)
+        });
+        let (child_pt, tlb) = match self.pt.clone_cow(start, size) {
+            Ok(cloned) => cloned,
+            Err(PagingError::NotAligned) => return assert!(cuts),
+            Err(e) => panic!("unexpected error of clone_cow({start:#x}): {e:?}"),
+        };
+        tlb.ignore();
+        assert!(!cuts, "clone_cow({start:#x}, {size:#x}) cut through a page");
+
+        let mut child = ShadowModel::<MockMetaData<M>, PTE>::new();
+        let mut vaddr = start;
+        while vaddr < end {
+            let Some((_, _, flags, mut page_size)) = self.model.lookup(vaddr) else {
+                vaddr += PAGE_SIZE_4K;
+                continue;
+            };
+            if let Some(cow) = flags.cow_of()
+                && cow != flags
+            {
+                // Also breaks up a writable group with the contiguous hint.
+                page_size = self.model.protect(vaddr, cow).unwrap();
+            }
+            let (_, paddr, flags, _) = self.model.lookup(vaddr).unwrap();
+            child.map(vaddr, paddr, page_size, flags);
+            vaddr += page_size as usize;
+        }
+        child.check(&child_pt);
+        let groups = check_contiguous(&child_pt);
+        assert!(
+            groups.is_empty(),
+            "inconsistent groups in the child at {groups:#x?}"
+        );
+    }
+
+    fn cow_fault(&mut self, vaddr: VirtAddr) {
+        let new = self.frames(PAGE_SIZE_4K, PageSize::Size4K);
+        let expected = match self.model.lookup(vaddr) {
+            None => Err(PagingError::NotMapped),
+            Some((_, _, flags, _)) if !flags.contains(MappingFlags::COW) => {
+                Err(PagingError::NotCow)
+            }
+            Some((page, paddr, flags, _)) => Ok((paddr.add(vaddr - page), flags)),
+        };
+        let copied = expected.map(|(old, _)| old);
+        let result = self.pt.handle_cow_fault(vaddr, |old, size| {
+            assert_eq!(Ok((old, size)), copied.map(|old| (old, PageSize::Size4K)));
+            Some(new)
+        });
+        match (result, expected) {
+            (Ok(tlb), Ok((_, flags))) => {
+                tlb.ignore();
+                // A huge page is split first, and stays copy-on-write.
+                self.model.split(vaddr);
+                self.model.unmap(vaddr);
+                self.model
+                    .map(vaddr, new, PageSize::Size4K, flags.resolve_cow());
+            }
+            (result, expected) => assert_eq!(
+                result.map(|tlb| tlb.ignore()),
+                expected.map(|_| ()),
+                "handle_cow_fault({vaddr:#x})"
+            ),
+        }
+    }
+}
+
+impl<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE> Default for Harness<M, PTE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn va(page: usize) -> VirtAddr {
+    VirtAddr::from(WINDOW_BASE + page * PAGE_SIZE_4K)
+}
@@ -0,0 +1,410 @@
+//! The two page tables of a process with page table isolation.
+
+use memory_addr::{MemoryAddr, PhysAddr};
+
+use crate::{AnyPageTable, GenericPTE, Mapping, MappingFlags, PageSize, PageTable64, PagingError};
+use crate::{PagingHandler, PagingMetaData, PagingResult, SharedSpace, TlbFlush, TlbFlushAll};
+
+/// The pair of page tables of a process with page table isolation (e.g.
+/// against Meltdown): the kernel one maps everything, and the user one only
+/// the user half and the trampolines needed to enter and leave the kernel.
+///
+/// The entry code switches to the root at [`PtiPair::kernel_root_paddr`],
+/// and the exit code back to the one at [`PtiPair::user_root_paddr`].
+///
+/// The two page tables map the user half (the lower half, whose top bit is
+/// clear) through the same tables: the user one copies the top-level entries
+/// of the kernel one (like [`PageTable64::copy_from`]) after each change to
+/// the user half. So they are changed once, copy-on-write pages are shared
+/// and counted once whatever the page table taking the fault, and the tables
+/// and mappings of the user half are accounted to the kernel page table
+/// only.
+///
+/// The changes to the upper half are made in the kernel page table only,
+/// except for the trampolines: ranges of it mirrored into the user page
+/// table with [`PtiPair::map_trampoline`]. The changes made through the pair
+/// to a trampoline are applied to both page tables. Changes made otherwise,
+/// e.g. to the kernel template the upper half is copied from, are not
+/// mirrored.
+///
+/// The flushes returned for the user half are the ones of the kernel page
+/// table. With distinct address space identifiers for the two page tables
+/// (e.g. the PCIDs of x86_64), the entries of the user one must be flushed
+/// too, e.g. before returning to user space.
+pub struct PtiPair<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> {
+    kernel: PageTable64<M, PTE, H>,
+    user: PageTable64<M, PTE, H>,
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> PtiPair<M, PTE, H> {
+    /// The size of the user half, mapped from address `0`.
+    const USER_HALF: usize = 1 << (M::VA_MAX_BITS - 1);
+
+    /// Creates the two page tables, without mappings.
+    pub fn try_new() -> PagingResult<Self> {
+        Self::with_kernel(PageTable64::try_new()?)
+    }
+
+    /// Creates the user page table of `kernel`, without trampolines.
+    fn with_kernel(kernel: PageTable64<M, PTE, H>) -> PagingResult<Self> {
+        let mut user = PageTable64::try_new()?;
+        user.link_top_level(&kernel, 0.into(), Self::USER_HALF);
+        Ok(Self { kernel, user })
+    }
+
+    /// Returns the physical address of the root of the kernel page table,
+    /// used while running in the kernel.
+    pub const fn kernel_root_paddr(&self) -> PhysAddr {
+        self.kernel.root_paddr()
+    }
+
+    /// Returns the physical address of the root of the user page table,
+    /// used while running in user space.
+    pub const fn user_root_paddr(&self) -> PhysAddr {
+        self.user.root_paddr()
+    }
+
+    /// Returns the kernel page table, e.g. to query it.
+    pub const fn kernel(&self) -> &PageTable64<M, PTE, H> {
+        &self.kernel
+    }
+
+    /// Returns the user page table.
+    pub const fn user(&self) -> &PageTable64<M, PTE, H> {
+        &self.user
+    }
+
+    /// Maps the region like [`PageTable64::map_region`]: in both page tables
+    /// in the user half, in the kernel one only in the upper half.
+    ///
+    /// Returns [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr)
+    /// if the region is in both halves.
+    pub fn map_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        get_paddr: impl Fn(M::VirtAddr) -> PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let upper = Self::upper_half(vaddr, size)?;
+        let result = self
+            .kernel
+            .map_region(vaddr, get_paddr, size, flags, allow_huge, false);
+        if !upper {
+            self.sync(vaddr, size);
+        }
+        result
+    }
+
+    /// Unmaps the region like [`PageTable64::unmap_region`]. In the upper
+    /// half, the trampolines in it are unmapped from the user page table too.
+    ///
+    /// Returns [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr)
+    /// if the region is in both halves.
+    pub fn unmap_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let upper = Self::upper_half(vaddr, size)?;
+        let result = self.kernel.unmap_region(vaddr, size, false);
+        if !upper {
+            self.sync(vaddr, size);
+            return result;
+        }
+        let tlb = result?;
+        match self.mirror(vaddr, size, |user, start, size| {
+            user.unmap_region(start, size, false)
+        }) {
+            Ok(user) => Ok(tlb.merge(user)),
+            Err(err) => {
+                tlb.flush_all();
+                Err(err)
+            }
+        }
+    }
+
+    /// Changes the flags of the region like [`PageTable64::protect_region`].
+    /// In the upper half, the trampolines in it get the new flags in the user
+    /// page table too.
+    ///
+    /// Returns [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr)
+    /// if the region is in both halves.
+    pub fn protect_region(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let upper = Self::upper_half(vaddr, size)?;
+        let result = self.kernel.protect_region(vaddr, size, flags, false);
+        if !upper {
+            self.sync(vaddr, size);
+            return result;
+        }
+        let tlb = result?;
+        match self.mirror(vaddr, size, |user, start, size| {
+            user.protect_region(start, size, flags, false)
+        }) {
+            Ok(user) => Ok(tlb.merge(user)),
+            Err(err) => {
+                tlb.flush_all();
+                Err(err)
+            }
+        }
+    }
+
+    /// Handles a write fault on a copy-on-write page of the user half, like
+    /// [`PageTable64::handle_cow_fault`], whichever page table was used.
+    ///
+    /// Returns [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if
+    /// `vaddr` is in the upper half.
+    pub fn handle_cow_fault(
+        &mut self,
+        vaddr: M::VirtAddr,
+        copy: impl FnOnce(PhysAddr, PageSize) -> Option<PhysAddr>,
+    ) -> PagingResult<TlbFlush<M>> {
+        if Self::upper_half(vaddr, 1)? {
+            return Err(PagingError::WrongSpace);
+        }
+        let result = self.kernel.handle_cow_fault(vaddr, copy);
+        self.sync(vaddr, 1);
+        result
+    }
+
+    /// Mirrors the mappings of the kernel page table in the region of the
+    /// upper half into the user page table, e.g. the entry code and the
+    /// stacks it uses before switching to the kernel page table.
+    ///
+    /// The pages are mapped with the same physical addresses and flags,
+    /// with the largest pages in the region.
+    ///
+    /// Returns [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if
+    /// the region is in the user half,
+    /// [`Err(PagingError::NotMapped)`](PagingError::NotMapped) if a page of
+    /// it is not mapped in the kernel page table (nothing is mirrored then),
+    /// and the errors of [`PageTable64::map_region`] in the user page table,
+    /// e.g. [`Err(PagingError::AlreadyMapped)`](PagingError::AlreadyMapped)
+    /// if it is mirrored already, after unmapping the pages mirrored so far.
+    pub fn map_trampoline(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        if !Self::upper_half(vaddr, size)? {
+            return Err(PagingError::WrongSpace);
+        }
+        self.kernel.check_mapped_pages(vaddr.into(), size, None)?;
+        let (start, last) = Self::bounds(vaddr, size);
+        let mut next = start;
+        let mut tlb = self.user.empty_flush();
+        while size > 0 && next <= last {
+            let Some(mapping) = self.kernel.next_mapping(next) else {
+                break;
+            };
+            let (from, to) = Self::clip(&mapping, start, last);
+            let paddr = mapping.paddr.add(from - mapping.vaddr);
+            let result = self.user.map_region(
+                from.into(),
+                |va: M::VirtAddr| paddr.add(va.into() - from),
+                to - from + 1,
+                mapping.flags,
+                true,
+                false,
+            );
+            match result {
+                Ok(mapped) => tlb = tlb.merge(mapped),
+                Err(err) => {
+                    if from > start {
+                        self.user
+                            .unmap_region(vaddr, from - start, false)
+                            .map_or_else(|_| (), TlbFlushAll::flush_all);
+                    }
+                    tlb.flush_all();
+                    return Err(err);
+                }
+            }
+            match to.checked_add(1) {
+                Some(end) => next = end,
+                None => break,
+            }
+        }
+        Ok(tlb)
+    }
+
+    /// Removes the trampolines of the region from the user page table, like
+    /// [`PageTable64::unmap_region`]. They stay mapped in the kernel one.
+    ///
+    /// Returns [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if
+    /// the region is in the user half.
+    pub fn unmap_trampoline(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        if !Self::upper_half(vaddr, size)? {
+            return Err(PagingError::WrongSpace);
+        }
+        self.user.unmap_region(vaddr, size, false)
+    }
+
+    /// Copies the top-level entries of `other` for the region of the upper
+    /// half into the kernel page table, like [`PageTable64::copy_from`], e.g.
+    /// from the template of the kernel mappings.
+    ///
+    /// They must be removed with [`PtiPair::clear_copy_range`] before the
+    /// pair is dropped.
+    ///
+    /// Returns [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if
+    /// the region is in the user half.
+    pub fn copy_from<S: SharedSpace>(
+        &mut self,
+        other: &PageTable64<M, PTE, H, S>,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult {
+        if !Self::upper_half(start, size)? {
+            return Err(PagingError::WrongSpace);
+        }
+        self.kernel.copy_from(other, start, size);
+        Ok(())
+    }
+
+    /// Removes the entries copied by [`PtiPair::copy_from`], like
+    /// [`PageTable64::clear_copy_range`].
+    ///
+    /// Returns [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if
+    /// the region is in the user half.
+    pub fn clear_copy_range(&mut self, start: M::VirtAddr, size: usize) -> PagingResult {
+        if !Self::upper_half(start, size)? {
+            return Err(PagingError::WrongSpace);
+        }
+        self.kernel.clear_copy_range(start, size);
+        Ok(())
+    }
+
+    /// Shares the region of the user half copy-on-write with a new pair, like
+    /// [`PageTable64::clone_cow`], e.g. for a fork.
+    ///
+    /// The new pair has the trampolines of this one, mapped in its user page
+    /// table. Its kernel page table has no other mapping in the upper half:
+    /// the kernel mappings must be added to it (e.g. with
+    /// [`PtiPair::copy_from`]), the same as the ones of this pair for the
+    /// trampolines to be mirrors of them.
+    ///
+    /// Returns [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) if
+    /// the region is in the upper half, and the errors of
+    /// [`PageTable64::clone_cow`], with nothing changed.
+    pub fn clone_cow(
+        &mut self,
+        start: M::VirtAddr,
+        size: usize,
+    ) -> PagingResult<(Self, TlbFlushAll<M>)> {
+        if Self::upper_half(start, size)? {
+            return Err(PagingError::WrongSpace);
+        }
+        let mut user = PageTable64::try_new()?;
+        let mut next = Self::USER_HALF;
+        while let Some(mapping) = self.user.next_mapping(next) {
+            let paddr = mapping.paddr;
+            let vaddr = mapping.vaddr;
+            user.map_region(
+                vaddr.into(),
+                |va: M::VirtAddr| paddr.add(va.into() - vaddr),
+                mapping.size as usize,
+                mapping.flags,
+                true,
+                false,
+            )?
+            // The page table is not used yet.
+            .ignore();
+            match mapping.end() {
+                Some(end) => next = end,
+                None => break,
+            }
+        }
+        let (kernel, tlb) = self.kernel.clone_cow(start, size)?;
+        self.sync(start, size);
+        user.link_top_level(&kernel, 0.into(), Self::USER_HALF);
+        Ok((Self { kernel, user }, tlb))
+    }
+
+    /// Returns whether the region is in the upper half, or
+    /// [`Err(PagingError::InvalidVaddr)`](PagingError::InvalidVaddr) if it
+    /// is in both.
+    fn upper_half(vaddr: M::VirtAddr, size: usize) -> PagingResult<bool> {
+        let (start, last) = Self::bounds(vaddr, size);
+        let upper = |vaddr: usize| (vaddr >> (M::VA_MAX_BITS - 1)) & 1 == 1;
+        if upper(start) != upper(last) {
+            return Err(PagingError::InvalidVaddr(start));
+        }
+        Ok(upper(start))
+    }
+
+    /// Returns the first and last addresses of the region, the first twice if
+    /// it is empty.
+    fn bounds(vaddr: M::VirtAddr, size: usize) -> (usize, usize) {
+        let start: usize = vaddr.into();
+        (start, start.wrapping_add(size.max(1) - 1))
+    }
+
+    /// Returns the first and last addresses of the part of `mapping` between
+    /// `start` and `last`.
+    fn clip(mapping: &Mapping, start: usize, last: usize) -> (usize, usize) {
+        let end = mapping.vaddr + (mapping.size as usize - 1);
+        (mapping.vaddr.max(start), end.min(last))
+    }
+
+    /// Copies the top-level entries of the kernel page table for the region
+    /// of the user half into the user page table, for the tables it created
+    /// and the leaf entries of the top level.
+    fn sync(&mut self, vaddr: M::VirtAddr, size: usize) {
+        self.user.link_top_level(&self.kernel, vaddr, size.max(1));
+    }
+
+    /// Calls `change` for the part in the region of each trampoline of the
+    /// user page table, and returns the merged flushes.
+    fn mirror(
+        &mut self,
+        vaddr: M::VirtAddr,
+        size: usize,
+        mut change: impl FnMut(
+            &mut PageTable64<M, PTE, H>,
+            M::VirtAddr,
+            usize,
+        ) -> PagingResult<TlbFlushAll<M>>,
+    ) -> PagingResult<TlbFlushAll<M>> {
+        let (start, last) = Self::bounds(vaddr, size);
+        let mut next = start;
+        let mut tlb = self.user.empty_flush();
+        while size > 0 && next <= last {
+            let Some(mapping) = self.user.next_mapping(next) else {
+                break;
+            };
+            if mapping.vaddr > last {
+                break;
+            }
+            let (from, to) = Self::clip(&mapping, start, last);
+            match change(&mut self.user, from.into(), to - from + 1) {
+                Ok(changed) => tlb = tlb.merge(changed),
+                Err(err) => {
+                    tlb.flush_all();
+                    return Err(err);
+                }
+            }
+            match to.checked_add(1) {
+                Some(end) => next = end,
+                None => break,
+            }
+        }
+        Ok(tlb)
+    }
+}
+
+impl<M: PagingMetaData, PTE: GenericPTE, H: PagingHandler> Drop for PtiPair<M, PTE, H> {
+    fn drop(&mut self) {
+        // The tables of the user half belong to the kernel page table.
+        self.user.clear_copy_range(0.into(), Self::USER_HALF);
+    }
+}
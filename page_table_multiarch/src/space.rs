@@ -0,0 +1,76 @@
+//! The kinds of address spaces a [`PageTable64`](crate::PageTable64) can be
+//! restricted to.
+
+use crate::{MappingFlags, PagingError, PagingMetaData, PagingResult};
+
+/// The kind of address space a [`PageTable64`](crate::PageTable64) maps,
+/// which decides the mappings it accepts.
+///
+/// The new mappings and flags of [`PageTable64::map`](crate::PageTable64::map)
+/// and [`PageTable64::protect`](crate::PageTable64::protect) are checked with
+/// [`SpaceKind::check_mapping`]. If it fails, they return
+/// [`Err(PagingError::WrongSpace)`](PagingError::WrongSpace) before changing
+/// anything. Other kinds than the ones provided can be defined to
+/// lift some restrictions.
+pub trait SpaceKind {
+    /// Checks a mapping at `vaddr` with `flags`, in a page table with the
+    /// metadata `M`.
+    ///
+    /// The user and kernel addresses are told apart by
+    /// [`PagingMetaData::is_user_vaddr`] and
+    /// [`PagingMetaData::is_kernel_vaddr`]. The default accepts every
+    /// mapping.
+    #[inline]
+    fn check_mapping<M: PagingMetaData>(_vaddr: usize, _flags: MappingFlags) -> PagingResult {
+        Ok(())
+    }
+}
+
+/// The kinds of address spaces that can be shared copy-on-write by
+/// [`PageTable64::clone_cow`](crate::PageTable64::clone_cow).
+pub trait CowSpace: SpaceKind {}
+
+/// The kinds of address spaces whose entries can be copied into another page
+/// table by [`PageTable64::copy_from`](crate::PageTable64::copy_from).
+pub trait SharedSpace: SpaceKind {}
+
+/// An address space without restrictions, the default.
+pub struct AnySpace;
+
+impl SpaceKind for AnySpace {}
+impl CowSpace for AnySpace {}
+impl SharedSpace for AnySpace {}
+
+/// A user address space: mappings must have [`MappingFlags::USER`] and be at
+/// user addresses.
+pub struct UserSpace;
+
+impl SpaceKind for UserSpace {
+    #[inline]
+    fn check_mapping<M: PagingMetaData>(vaddr: usize, flags: MappingFlags) -> PagingResult {
+        if !M::is_user_vaddr(vaddr) || !flags.contains(MappingFlags::USER) {
+            return Err(PagingError::WrongSpace);
+        }
+        Ok(())
+    }
+}
+
+impl CowSpace for UserSpace {}
+
+/// A kernel address space: mappings must not have [`MappingFlags::USER`],
+/// and must be at kernel addresses, or at user addresses too if `LOWER_HALF`
+/// is `true`.
+pub struct KernelSpace<const LOWER_HALF: bool = false>;
+
+impl<const LOWER_HALF: bool> SpaceKind for KernelSpace<LOWER_HALF> {
+    #[inline]
+    fn check_mapping<M: PagingMetaData>(vaddr: usize, flags: MappingFlags) -> PagingResult {
+        let allowed = M::is_kernel_vaddr(vaddr) || (LOWER_HALF && M::is_user_vaddr(vaddr));
+        if !allowed || flags.contains(MappingFlags::USER) {
+            return Err(PagingError::WrongSpace);
+        }
+        Ok(())
+    }
+}
+
+impl<const LOWER_HALF: bool> SharedSpace for KernelSpace<LOWER_HALF> {}
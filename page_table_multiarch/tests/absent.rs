@@ -0,0 +1,76 @@
+//! Tokens stored in non-present entries.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, table_frames};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AbsentEntry, MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+
+#[test]
+fn token_lifecycle() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    assert_eq!(pt.absent_token(vaddr), None);
+    pt.set_absent_token(vaddr, 42).unwrap();
+    assert_eq!(pt.absent_token(vaddr), Some(42));
+    assert_eq!(
+        pt.query(vaddr),
+        Err(PagingError::Absent(AbsentEntry::File(42)))
+    );
+    // The neighbours are not affected.
+    assert_eq!(pt.query(vaddr + 0x1000), Err(PagingError::NotMapped));
+    pt.set_absent_token(vaddr, 43).unwrap();
+    assert_eq!(pt.absent_token(vaddr), Some(43));
+
+    // Mapping replaces the token.
+    pt.map(
+        vaddr,
+        PhysAddr::from(0x1000),
+        PageSize::Size4K,
+        MappingFlags::READ,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(pt.absent_token(vaddr), None);
+    assert_eq!(pt.query(vaddr).unwrap().0, PhysAddr::from(0x1000));
+    assert_eq!(
+        pt.set_absent_token(vaddr, 1),
+        Err(PagingError::AlreadyMapped)
+    );
+
+    // Unmapping leaves an empty entry, which can take a token again.
+    pt.unmap(vaddr).unwrap().2.ignore();
+    pt.set_absent_token(vaddr, 44).unwrap();
+    assert_eq!(pt.unmap(vaddr).map(|_| ()), Err(PagingError::NotMapped));
+    assert_eq!(pt.absent_token(vaddr), None);
+    assert_eq!(MockHandler::live_frames(), table_frames(&pt));
+}
+
+#[test]
+fn token_under_huge_page() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.map(
+        vaddr,
+        PhysAddr::from(0x20_0000),
+        PageSize::Size2M,
+        MappingFlags::READ,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        pt.set_absent_token(vaddr + 0x1000, 1),
+        Err(PagingError::MappedToHugePage {
+            vaddr: VADDR,
+            level: 2,
+        })
+    );
+}
@@ -0,0 +1,147 @@
+//! Whether accesses would fault, given the mappings and the global controls
+//! of the CPU.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AccessContext, AccessType, AccessVerdict, MappingFlags, PageSize};
+
+const USER: usize = 0x40_0000_0000;
+const KERNEL: usize = 0xffff_ffc0_4000_0000;
+const HUGE: usize = USER + 0x20_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+/// The kernel with the protections on user pages lifted, e.g. SMAP disabled
+/// or `sstatus.SUM` set.
+fn kernel_on_user() -> AccessContext {
+    AccessContext {
+        kernel_accesses_user: true,
+        ..AccessContext::kernel()
+    }
+}
+
+#[test]
+fn x86_64() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    let pages = [
+        (USER, PageSize::Size4K, RW | MappingFlags::USER),
+        (HUGE, PageSize::Size2M, RX | MappingFlags::USER),
+        (KERNEL, PageSize::Size4K, RX),
+    ];
+    for (vaddr, size, flags) in pages {
+        pt.map(va(vaddr), PhysAddr::from(0x20_0000), size, flags)
+            .unwrap()
+            .ignore();
+    }
+    let check = |vaddr, access, ctx| pt.check_access(va(vaddr), access, ctx);
+    let (user, kernel) = (AccessContext::user(), AccessContext::kernel());
+
+    assert_eq!(
+        check(USER + 0x1000, AccessType::Read, user),
+        AccessVerdict::NotMapped
+    );
+    assert_eq!(check(USER, AccessType::Write, user), AccessVerdict::Allowed);
+    assert_eq!(
+        check(USER, AccessType::Execute, user),
+        AccessVerdict::Denied
+    );
+    assert_eq!(
+        check(HUGE + 0x1000, AccessType::Execute, user),
+        AccessVerdict::Allowed
+    );
+    assert_eq!(check(KERNEL, AccessType::Read, user), AccessVerdict::Denied);
+    assert_eq!(
+        check(KERNEL, AccessType::Execute, kernel),
+        AccessVerdict::Allowed
+    );
+    assert_eq!(
+        check(KERNEL, AccessType::Write, kernel),
+        AccessVerdict::Denied
+    );
+
+    // SMAP and SMEP.
+    assert_eq!(
+        check(USER, AccessType::Write, kernel),
+        AccessVerdict::Denied
+    );
+    assert_eq!(
+        check(USER, AccessType::Write, kernel_on_user()),
+        AccessVerdict::Allowed
+    );
+    assert_eq!(
+        check(HUGE, AccessType::Execute, kernel_on_user()),
+        AccessVerdict::Denied
+    );
+    let smep_off = AccessContext {
+        kernel_executes_user: true,
+        ..kernel
+    };
+    assert_eq!(
+        check(HUGE, AccessType::Execute, smep_off),
+        AccessVerdict::Allowed
+    );
+    // CR0.WP
+    let wp_off = AccessContext {
+        write_protect: false,
+        ..kernel
+    };
+    assert_eq!(
+        check(KERNEL, AccessType::Write, wp_off),
+        AccessVerdict::Allowed
+    );
+}
+
+#[test]
+fn riscv_cow() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Sv39MetaData<VirtAddr>, Rv64PTE>::try_new().unwrap();
+    let cow = (RW | MappingFlags::USER).cow_of().unwrap();
+    pt.map(va(USER), PhysAddr::from(0x20_0000), PageSize::Size4K, cow)
+        .unwrap()
+        .ignore();
+    let ro = MappingFlags::READ | MappingFlags::USER;
+    pt.map(
+        va(USER + 0x1000),
+        PhysAddr::from(0x20_0000),
+        PageSize::Size4K,
+        ro,
+    )
+    .unwrap()
+    .ignore();
+    let check = |vaddr, access, ctx| pt.check_access(va(vaddr), access, ctx);
+    let user = AccessContext::user();
+
+    assert_eq!(check(USER, AccessType::Read, user), AccessVerdict::Allowed);
+    assert_eq!(
+        check(USER, AccessType::Write, user),
+        AccessVerdict::CopyOnWrite
+    );
+    assert_eq!(
+        check(USER + 0x1000, AccessType::Write, user),
+        AccessVerdict::Denied
+    );
+    // The kernel copies the page on write only with SUM set.
+    let kernel = AccessContext::kernel();
+    assert_eq!(
+        check(USER, AccessType::Write, kernel),
+        AccessVerdict::Denied
+    );
+    let write = check(USER, AccessType::Write, kernel_on_user());
+    assert_eq!(write, AccessVerdict::CopyOnWrite);
+
+    pt.handle_cow_fault(va(USER), |_, _| Some(PhysAddr::from(0x40_0000)))
+        .unwrap()
+        .ignore();
+    let write = pt.check_access(va(USER), AccessType::Write, user);
+    assert_eq!(write, AccessVerdict::Allowed);
+}
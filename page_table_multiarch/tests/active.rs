@@ -0,0 +1,96 @@
+//! Tracking the CPUs a page table is loaded on, and what it changes for
+//! dropping the table and for break-before-make.
+
+#![cfg(feature = "all-formats")]
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+type Meta = MockMetaData<A64PagingMetaData>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn populated() -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0x1000), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+#[test]
+fn counts_cpus() {
+    MockHandler::reset();
+    let pt = populated();
+    assert!(!pt.is_active());
+    pt.set_active(true);
+    pt.set_active(true);
+    pt.set_active(false);
+    assert!(pt.is_active());
+    pt.set_active(false);
+    assert!(!pt.is_active());
+
+    // An inactive table frees its frames.
+    let live = MockHandler::live_frames();
+    let frames = pt.table_frames();
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), live - frames);
+}
+
+#[test]
+fn untracked_tables_are_dropped() {
+    MockHandler::reset();
+    let live = MockHandler::live_frames();
+    drop(populated());
+    assert_eq!(MockHandler::live_frames(), live);
+}
+
+#[test]
+fn active_tables_are_leaked() {
+    MockHandler::reset();
+    let pt = populated();
+    pt.set_active(true);
+    let live = MockHandler::live_frames();
+    let result = catch_unwind(AssertUnwindSafe(|| drop(pt)));
+    // Only with debug assertions.
+    assert_eq!(result.is_err(), cfg!(debug_assertions));
+    assert_eq!(MockHandler::live_frames(), live);
+}
+
+#[test]
+fn break_before_make_only_when_possibly_active() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    let vaddr = va(0x40_0000_0000);
+    pt.map(vaddr, PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let remap = |pt: &mut MockPageTable<_, _>, paddr| {
+        Meta::take_flushes();
+        pt.remap(vaddr, PhysAddr::from(paddr), RW)
+            .unwrap()
+            .1
+            .ignore();
+        Meta::take_flushes()
+    };
+
+    // Untracked, then active: the entry is invalidated and flushed first.
+    assert_eq!(remap(&mut pt, 0x2000), [Some(vaddr)]);
+    pt.set_active(true);
+    assert_eq!(remap(&mut pt, 0x3000), [Some(vaddr)]);
+    // Inactive: written at once.
+    pt.set_active(false);
+    assert_eq!(remap(&mut pt, 0x4000), []);
+    assert_eq!(pt.query(vaddr).unwrap().0, PhysAddr::from(0x4000));
+}
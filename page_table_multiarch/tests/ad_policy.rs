@@ -0,0 +1,161 @@
+//! Checks the accessed and dirty bits of new mappings under each
+//! [`AccessedDirtyPolicy`], for every entry format.
+
+#![cfg(feature = "all-formats")]
+
+use core::marker::PhantomData;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AccessedDirtyPolicy, GenericPTE, MappingFlags, PageSize};
+use page_table_multiarch::{PagingError, PagingMetaData};
+
+const ALWAYS: u8 = 0;
+const HARDWARE: u8 = 1;
+const SOFTWARE: u8 = 2;
+
+/// The metadata `M` with the policy of index `P`.
+struct Policy<M, const P: u8>(PhantomData<M>);
+
+impl<M: PagingMetaData, const P: u8> PagingMetaData for Policy<M, P> {
+    const LEVELS: usize = M::LEVELS;
+    const PA_MAX_BITS: usize = M::PA_MAX_BITS;
+    const VA_MAX_BITS: usize = M::VA_MAX_BITS;
+    const TLB_CACHES_INVALID: bool = M::TLB_CACHES_INVALID;
+    const BREAK_BEFORE_MAKE: bool = M::BREAK_BEFORE_MAKE;
+    const AD_POLICY: AccessedDirtyPolicy = [
+        AccessedDirtyPolicy::AlwaysSet,
+        AccessedDirtyPolicy::HardwareManaged,
+        AccessedDirtyPolicy::SoftwareManaged,
+    ][P as usize];
+    type VirtAddr = M::VirtAddr;
+
+    // `MockMetaData` records the flushes instead.
+    fn flush_tlb(_vaddr: Option<M::VirtAddr>) {}
+}
+
+type Sv39 = Sv39MetaData<VirtAddr>;
+
+const VADDR: usize = 0x1000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// Returns the `(accessed, dirty)` bits of the page at `vaddr`.
+fn bits<M, PTE>(pt: &MockPageTable<M, PTE>, vaddr: VirtAddr) -> (bool, bool)
+where
+    M: PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: GenericPTE,
+{
+    (pt.is_accessed(vaddr).unwrap(), pt.is_dirty(vaddr).unwrap())
+}
+
+/// Maps a read-only and a writable page, and returns their bits.
+fn new_bits<M, PTE>() -> [(bool, bool); 2]
+where
+    M: PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: GenericPTE,
+{
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    for (off, flags) in [(0, MappingFlags::READ), (0x1000, RW)] {
+        pt.map(va(off), PhysAddr::from(0x1000), PageSize::Size4K, flags)
+            .unwrap()
+            .ignore();
+    }
+    [bits(&pt, va(0)), bits(&pt, va(0x1000))]
+}
+
+#[test]
+fn always_set_keeps_entry_defaults() {
+    let (clear, accessed, both) = ((false, false), (true, false), (true, true));
+    assert_eq!(
+        new_bits::<Policy<X64PagingMetaData, ALWAYS>, X64PTE>(),
+        [clear; 2]
+    );
+    assert_eq!(
+        new_bits::<Policy<A64PagingMetaData, ALWAYS>, A64PTE>(),
+        [accessed; 2]
+    );
+    assert_eq!(new_bits::<Policy<Sv39, ALWAYS>, Rv64PTE>(), [both; 2]);
+    // The D bit of LoongArch is needed to write.
+    assert_eq!(
+        new_bits::<Policy<LA64MetaData, ALWAYS>, LA64PTE>(),
+        [accessed, both]
+    );
+}
+
+#[test]
+fn managed_policies_start_clear() {
+    fn check<const P: u8>() {
+        let clear = [(false, false); 2];
+        assert_eq!(new_bits::<Policy<X64PagingMetaData, P>, X64PTE>(), clear);
+        assert_eq!(new_bits::<Policy<A64PagingMetaData, P>, A64PTE>(), clear);
+        assert_eq!(new_bits::<Policy<Sv39, P>, Rv64PTE>(), clear);
+        // LoongArch has no accessed bit.
+        assert_eq!(
+            new_bits::<Policy<LA64MetaData, P>, LA64PTE>(),
+            [(true, false); 2]
+        );
+    }
+    check::<HARDWARE>();
+    check::<SOFTWARE>();
+}
+
+fn software_faults<M, PTE>()
+where
+    M: PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: GenericPTE,
+{
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Policy<M, SOFTWARE>, PTE>::try_new().unwrap();
+    let (ro, rw) = (va(0), va(0x1000));
+    pt.map(
+        ro,
+        PhysAddr::from(0x1000),
+        PageSize::Size4K,
+        MappingFlags::READ,
+    )
+    .unwrap()
+    .ignore();
+    pt.map(rw, PhysAddr::from(0x2000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+
+    pt.handle_access_fault(rw, false).unwrap().flush();
+    assert_eq!(bits(&pt, rw), (true, false));
+    pt.handle_access_fault(rw, true).unwrap().flush();
+    assert_eq!(bits(&pt, rw), (true, true));
+    // A write to a read-only page is not an access fault.
+    pt.handle_access_fault(ro, true).unwrap().flush();
+    assert_eq!(bits(&pt, ro), (true, false));
+    assert_eq!(
+        pt.handle_access_fault(va(0x2000), false).err(),
+        Some(PagingError::NotMapped)
+    );
+
+    // Changing the permissions keeps the bits, but a read-only page is
+    // clean.
+    pt.protect(rw, RW).unwrap().1.ignore();
+    assert_eq!(bits(&pt, rw), (true, true));
+    pt.protect(rw, MappingFlags::READ).unwrap().1.ignore();
+    assert_eq!(bits(&pt, rw), (true, false));
+    // Another frame starts over.
+    pt.remap(rw, PhysAddr::from(0x3000), RW).unwrap().1.ignore();
+    assert!(!bits(&pt, rw).1);
+}
+
+#[test]
+fn software_managed_faults() {
+    software_faults::<X64PagingMetaData, X64PTE>();
+    software_faults::<A64PagingMetaData, A64PTE>();
+    software_faults::<Sv39, Rv64PTE>();
+    software_faults::<LA64MetaData, LA64PTE>();
+}
@@ -0,0 +1,122 @@
+//! Checks setting and clearing the accessed and dirty bits of the pages of a
+//! region at once.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// A 2M page, then a hole of 4K, then three 4K pages, accessed and dirty.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0), PhysAddr::from(0x20_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    for off in [0x20_1000, 0x20_2000, 0x20_3000] {
+        pt.map(va(off), PhysAddr::from(off), PageSize::Size4K, RW)
+            .unwrap()
+            .ignore();
+    }
+    // Setting the bits needs no flush.
+    for (changed, tlb) in [
+        pt.set_accessed_region(va(0), 0x40_0000, true).unwrap(),
+        pt.set_dirty_region(va(0), 0x40_0000, true).unwrap(),
+    ] {
+        assert_eq!(changed, 4);
+        assert!(!tlb.is_needed());
+        tlb.ignore();
+    }
+    pt
+}
+
+fn dirty(pt: &PageTable) -> [bool; 4] {
+    [0, 0x20_1000, 0x20_2000, 0x20_3000].map(|off| pt.is_dirty(va(off)).unwrap())
+}
+
+#[test]
+fn dirty_region() {
+    let mut pt = mapped();
+    assert_eq!(dirty(&pt), [true; 4]);
+
+    // The huge page is cleared whole, though only its end is in the region,
+    // and the hole is skipped.
+    let (changed, tlb) = pt.set_dirty_region(va(0x1f_f000), 0x3000, false).unwrap();
+    assert_eq!(changed, 2);
+    assert!(tlb.is_needed());
+    tlb.flush_all();
+    assert_eq!(MockMetaData::<X64PagingMetaData>::take_flushes(), [None]);
+    assert_eq!(dirty(&pt), [false, false, true, true]);
+    // The other bits are kept.
+    assert_eq!(
+        pt.query(va(0x1000)).unwrap(),
+        (PhysAddr::from(0x20_1000), RW, PageSize::Size2M)
+    );
+    assert!(pt.is_accessed(va(0)).unwrap());
+
+    // Only the changed leaves are counted.
+    let (changed, tlb) = pt.set_dirty_region(va(0), 0x20_4000, false).unwrap();
+    assert_eq!(changed, 2);
+    tlb.ignore();
+    assert_eq!(dirty(&pt), [false; 4]);
+    let (changed, tlb) = pt.set_dirty_region(va(0), 0x20_4000, false).unwrap();
+    assert_eq!(changed, 0);
+    assert!(!tlb.is_needed());
+    tlb.ignore();
+}
+
+#[test]
+fn accessed_region() {
+    let mut pt = mapped();
+    let (changed, tlb) = pt
+        .set_accessed_region(va(0x20_0000), 0x2000, false)
+        .unwrap();
+    assert_eq!(changed, 1);
+    tlb.ignore();
+    assert!(!pt.is_accessed(va(0x20_1000)).unwrap());
+    assert!(pt.is_accessed(va(0x20_2000)).unwrap());
+    // The dirty bit is kept.
+    assert!(pt.is_dirty(va(0x20_1000)).unwrap());
+
+    assert_eq!(
+        pt.set_accessed_region(va(0x800), 0x1000, true).err(),
+        Some(PagingError::NotAligned)
+    );
+    let (changed, tlb) = pt.set_accessed_region(va(0x40_0000), 0x1000, true).unwrap();
+    assert_eq!(changed, 0);
+    tlb.ignore();
+}
+
+#[test]
+fn contiguous_groups() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    pt.map(va(0), PhysAddr::from(0x1_0000), PageSize::Size64K, RW)
+        .unwrap()
+        .ignore();
+    pt.set_dirty_region(va(0), 0x1_0000, false)
+        .unwrap()
+        .1
+        .ignore();
+    // Dirtying one page of the group dirties all of it.
+    let (changed, tlb) = pt.set_dirty_region(va(0x8000), 0x1000, true).unwrap();
+    assert_eq!(changed, 16);
+    tlb.ignore();
+    for off in (0..0x1_0000).step_by(0x1000) {
+        assert!(pt.is_dirty(va(off)).unwrap());
+    }
+    assert_eq!(pt.query(va(0)).unwrap().2, PageSize::Size64K);
+}
@@ -0,0 +1,219 @@
+//! Aliasing frames at several virtual addresses of one page table with
+//! [`PageTable64::map_alias`]. See `cow.rs` for aliases of copy-on-write
+//! pages.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RING: usize = 0x10_0000;
+const RING_SIZE: usize = 0x4000;
+const RING_PADDR: usize = 0x8000_0000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+/// Maps a ring buffer followed by an alias of it, so that accesses past its
+/// end wrap around.
+fn ring() -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    let paddr = |va: VirtAddr| PhysAddr::from(va.as_usize() - RING + RING_PADDR);
+    pt.map_region(va(RING), paddr, RING_SIZE, RW, false, false)
+        .unwrap()
+        .ignore();
+    pt.map_alias(va(RING + RING_SIZE), va(RING), RING_SIZE)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+#[test]
+fn ring_buffer() {
+    MockHandler::reset();
+    let mut pt = ring();
+    for off in (0..RING_SIZE).step_by(0x800) {
+        let (paddr, flags, size) = pt.query(va(RING + off)).unwrap();
+        assert_eq!(
+            pt.query(va(RING + RING_SIZE + off)),
+            Ok((paddr, flags, size))
+        );
+        assert_eq!(paddr, PhysAddr::from(RING_PADDR + off));
+    }
+
+    // Unmapping one alias leaves the other, and returns the frame for each.
+    let (paddr, _, tlb) = pt.unmap(va(RING + RING_SIZE)).unwrap();
+    tlb.ignore();
+    assert_eq!(paddr, PhysAddr::from(RING_PADDR));
+    assert_eq!(pt.query(va(RING)).unwrap().0, paddr);
+
+    // Unmapping by frames removes every alias.
+    let paddrs = PhysAddrRange::from_start_size(RING_PADDR.into(), RING_SIZE);
+    let mut removed = Vec::new();
+    pt.unmap_paddr_range(paddrs, |vaddr, _| removed.push(vaddr.as_usize()))
+        .unwrap()
+        .ignore();
+    removed.sort();
+    let expected = (RING..RING + 2 * RING_SIZE).step_by(0x1000);
+    let expected = expected.filter(|&vaddr| vaddr != RING + RING_SIZE);
+    assert_eq!(removed, expected.collect::<Vec<_>>());
+}
+
+#[test]
+fn flags_and_huge_pages() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let flags = MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER;
+    pt.map(
+        va(0x20_0000),
+        PhysAddr::from(0x4020_0000),
+        PageSize::Size2M,
+        flags,
+    )
+    .unwrap()
+    .ignore();
+
+    // Aliased where a 2M page fits, and with 4K pages where it does not.
+    pt.map_alias(va(0x4000_0000), va(0x20_0000), 0x20_0000)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pt.query(va(0x4000_1234)),
+        Ok((PhysAddr::from(0x4020_1234), flags, PageSize::Size2M))
+    );
+    pt.map_alias(va(0x6000_1000), va(0x20_3000), 0x3000)
+        .unwrap()
+        .ignore();
+    for off in [0, 0x1000, 0x2fff] {
+        assert_eq!(
+            pt.query(va(0x6000_1000 + off)),
+            Ok((PhysAddr::from(0x4020_3000 + off), flags, PageSize::Size4K))
+        );
+    }
+    assert_eq!(pt.query(va(0x6000_4000)), Err(PagingError::NotMapped));
+}
+
+#[test]
+fn errors() {
+    MockHandler::reset();
+    let mut pt = ring();
+    let mut alias = |vaddr: usize, existing: usize, size: usize| {
+        pt.map_alias(va(vaddr), va(existing), size)
+            .map(|tlb| tlb.ignore())
+    };
+    assert_eq!(
+        alias(0x200_0000, RING + 0x10, 0x1000),
+        Err(PagingError::NotAligned)
+    );
+    assert_eq!(alias(0x200_0000, RING, 0x10), Err(PagingError::NotAligned));
+    assert_eq!(alias(0x200_0000, RING, 0), Ok(()));
+    // A page of the source is not mapped.
+    assert_eq!(
+        alias(0x200_0000, RING + RING_SIZE, 2 * RING_SIZE),
+        Err(PagingError::NotMapped)
+    );
+    // A page of the alias is mapped already, even at its end.
+    assert_eq!(
+        alias(RING - 0x1000, RING, 0x2000),
+        Err(PagingError::AlreadyMapped)
+    );
+    // Nothing was mapped by the failed calls.
+    assert_eq!(pt.query(va(0x200_0000)), Err(PagingError::NotMapped));
+    assert_eq!(pt.query(va(RING - 0x1000)), Err(PagingError::NotMapped));
+}
+
+/// The kernel image: 2M of code, then 16K of data.
+const IMAGE: usize = 0xffff_ffff_8000_0000;
+const IMAGE_PADDR: usize = 0x20_0000;
+const TEXT_SIZE: usize = 0x20_0000;
+const IMAGE_SIZE: usize = TEXT_SIZE + 0x4000;
+
+fn image() -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    let paddr = |va: VirtAddr| PhysAddr::from(va.as_usize() - IMAGE + IMAGE_PADDR);
+    let flags = |va: VirtAddr| match va.as_usize() - IMAGE {
+        0..TEXT_SIZE => MappingFlags::READ | MappingFlags::EXECUTE,
+        _ => RW,
+    };
+    pt.map_region_with(va(IMAGE), paddr, IMAGE_SIZE, flags, true)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+#[test]
+fn randomized_kernel_image() {
+    for slide in [0x4000_0000, 0x3000_0000 + 0x1_5000] {
+        MockHandler::reset();
+        let mut pt = image();
+        let slid = IMAGE - slide;
+        pt.map_alias(va(slid), va(IMAGE), IMAGE_SIZE)
+            .unwrap()
+            .ignore();
+        let text = pt.query(va(slid)).unwrap();
+        let data = pt.query(va(slid + TEXT_SIZE + 0x3000)).unwrap();
+
+        // Once running at the slid address, the image is unmapped at its
+        // link address.
+        pt.unmap_alias(va(IMAGE), va(slid), IMAGE_SIZE)
+            .unwrap()
+            .ignore();
+        for off in [0, TEXT_SIZE - 0x1000, TEXT_SIZE, IMAGE_SIZE - 0x1000] {
+            assert_eq!(pt.query(va(IMAGE + off)), Err(PagingError::NotMapped));
+        }
+        assert_eq!(pt.query(va(slid)), Ok(text));
+        assert_eq!(pt.query(va(slid + TEXT_SIZE + 0x3000)), Ok(data));
+        assert_eq!(text.0, PhysAddr::from(IMAGE_PADDR));
+        // Huge pages where the slide is aligned to them.
+        let size = if slide % TEXT_SIZE == 0 {
+            PageSize::Size2M
+        } else {
+            PageSize::Size4K
+        };
+        assert_eq!(text.2, size);
+    }
+}
+
+#[test]
+fn unmap_alias_errors() {
+    MockHandler::reset();
+    let mut pt = image();
+    let slid = IMAGE - 0x4000_0000;
+    pt.map_alias(va(slid), va(IMAGE), IMAGE_SIZE)
+        .unwrap()
+        .ignore();
+    let mut unmap = |vaddr: usize, alias: usize, size: usize| {
+        pt.unmap_alias(va(vaddr), va(alias), size)
+            .map(|tlb| tlb.ignore())
+    };
+    assert_eq!(
+        unmap(IMAGE, slid + 0x10, 0x1000),
+        Err(PagingError::NotAligned)
+    );
+    // Not aliased beyond the image, or at another offset.
+    assert_eq!(
+        unmap(IMAGE, slid, IMAGE_SIZE + 0x1000),
+        Err(PagingError::NotMapped)
+    );
+    assert_eq!(
+        unmap(IMAGE + TEXT_SIZE, slid + TEXT_SIZE + 0x1000, 0x1000),
+        Err(PagingError::NotMapped)
+    );
+    // The code is a single 2M page.
+    assert!(matches!(
+        unmap(IMAGE + 0x1000, slid + 0x1000, 0x1000),
+        Err(PagingError::MappedToHugePage { .. })
+    ));
+    // The data can go first.
+    assert_eq!(unmap(IMAGE + TEXT_SIZE, slid + TEXT_SIZE, 0x4000), Ok(()));
+    // Nothing else was unmapped.
+    assert!(pt.query(va(IMAGE + TEXT_SIZE - 0x1000)).is_ok());
+    assert_eq!(pt.query(va(IMAGE + TEXT_SIZE)), Err(PagingError::NotMapped));
+}
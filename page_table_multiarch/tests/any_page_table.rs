@@ -0,0 +1,165 @@
+//! Checks the layout and mappings of page tables of every architecture
+//! through `&dyn AnyPageTable`.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AnyPageTable, GenericPTE, GuestPhysAddr, Mapping, PageSize};
+use page_table_multiarch::{MappingFlags, PagingError, PagingMetaData};
+
+use PageSize::*;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+
+/// A page table of `M` with a 4K page, a 2M page, and a 4K page in the
+/// upper half at `upper`.
+fn page_table<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(
+    upper: usize,
+) -> MockPageTable<M, PTE> {
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    for (vaddr, paddr, size, flags) in [
+        (0x1000, 0x8000_0000, Size4K, RW),
+        (0x4000_0000, 0x20_0000, Size2M, RX),
+        (upper, 0x9000_0000, Size4K, RW),
+    ] {
+        pt.map(VirtAddr::from(vaddr), PhysAddr::from(paddr), size, flags)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+fn mappings(pt: &dyn AnyPageTable) -> Vec<(usize, usize, PageSize)> {
+    pt.iter()
+        .map(|m| (m.vaddr, m.paddr.as_usize(), m.size))
+        .collect()
+}
+
+#[test]
+fn every_architecture() {
+    MockHandler::reset();
+    let upper48 = 0xffff_ff80_0000_0000;
+    let upper39 = 0xffff_ffc0_0000_0000;
+    // AArch64 accepts the upper half without the sign extension.
+    let tables: [(Box<dyn AnyPageTable>, usize); 5] = [
+        (
+            Box::new(page_table::<X64PagingMetaData, X64PTE>(upper48)),
+            upper48,
+        ),
+        (
+            Box::new(page_table::<A64PagingMetaData, A64PTE>(upper48)),
+            upper48 & 0xffff_ffff_ffff,
+        ),
+        (
+            Box::new(page_table::<Sv39MetaData<VirtAddr>, Rv64PTE>(upper39)),
+            upper39,
+        ),
+        (
+            Box::new(page_table::<Sv48MetaData<VirtAddr>, Rv64PTE>(upper48)),
+            upper48,
+        ),
+        (
+            Box::new(page_table::<LA64MetaData, LA64PTE>(upper48)),
+            upper48,
+        ),
+    ];
+    let names = [
+        "x86_64",
+        "aarch64",
+        "riscv64 (Sv39)",
+        "riscv64 (Sv48)",
+        "loongarch64",
+    ];
+    let napot: &[PageSize] = if <Rv64PTE as GenericPTE>::CONTIGUOUS_HINT {
+        &[Size4K, Size64K]
+    } else {
+        &[Size4K]
+    };
+    let sizes: [Vec<PageSize>; 5] = [
+        vec![Size4K, Size2M, Size1G],
+        vec![Size4K, Size64K, Size2M, Size1G],
+        [napot, &[Size2M, Size1G]].concat(),
+        [napot, &[Size2M, Size1G, Size512G]].concat(),
+        vec![Size4K, Size2M, Size1G],
+    ];
+    for (i, (pt, upper)) in tables.iter().enumerate() {
+        let pt = pt.as_ref();
+        assert_eq!(pt.arch_name(), names[i]);
+        assert_eq!(pt.levels(), if i == 2 { 3 } else { 4 });
+        assert_eq!(pt.va_bits(), if i == 2 { 39 } else { 48 });
+        assert_eq!(pt.page_sizes(), sizes[i]);
+
+        assert_eq!(
+            mappings(pt),
+            [
+                (0x1000, 0x8000_0000, Size4K),
+                (0x4000_0000, 0x20_0000, Size2M),
+                (*upper, 0x9000_0000, Size4K),
+            ]
+        );
+        assert_eq!(
+            pt.query(0x4000_1234),
+            Ok((PhysAddr::from(0x20_1234), RX, Size2M))
+        );
+        assert_eq!(pt.query(0x2000), Err(PagingError::NotMapped));
+        // From inside a page, and from between the halves.
+        assert_eq!(pt.next_mapping(0x4010_0000).unwrap().vaddr, 0x4000_0000);
+        assert_eq!(pt.next_mapping(0x4020_0000).unwrap().vaddr, *upper);
+        if *upper > 0x1_0000_0000_0000 {
+            assert_eq!(pt.next_mapping(0x1_0000_0000_0000).unwrap().vaddr, *upper);
+        }
+        assert_eq!(pt.next_mapping(upper + 0x1000), None);
+    }
+}
+
+#[test]
+fn contiguous_groups_and_dump() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    let vaddr = VirtAddr::from(0x1_0000);
+    pt.map(vaddr, PhysAddr::from(0x5_0000), Size64K, RW)
+        .unwrap()
+        .ignore();
+    let pt: &dyn AnyPageTable = &pt;
+    assert_eq!(
+        pt.next_mapping(0x1_8000),
+        Some(Mapping {
+            vaddr: 0x1_0000,
+            paddr: PhysAddr::from(0x5_0000),
+            flags: RW,
+            size: Size64K,
+        })
+    );
+    assert_eq!(pt.iter().count(), 1);
+
+    let mut dump = String::new();
+    pt.dump(&mut dump).unwrap();
+    let lines: Vec<_> = dump.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("aarch64: 4 levels, 48-bit VA, 48-bit PA, root "));
+    assert_eq!(
+        lines[1],
+        format!("0x0000000000010000 -> PA:0x50000 Size64K {RW:?}")
+    );
+}
+
+#[test]
+fn guest_physical_addresses() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Sv39MetaData<GuestPhysAddr>, Rv64PTE>::try_new().unwrap();
+    let gpa = GuestPhysAddr::from(0x40_0000_0000);
+    pt.map(gpa, PhysAddr::from(0x8000_0000), Size4K, RW)
+        .unwrap()
+        .ignore();
+    let pt: &dyn AnyPageTable = &pt;
+    // Not sign-extended, and nothing beyond the address space.
+    assert_eq!(mappings(pt), [(0x40_0000_0000, 0x8000_0000, Size4K)]);
+    assert_eq!(pt.next_mapping(0x80_0000_0000), None);
+}
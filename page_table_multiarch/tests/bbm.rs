@@ -0,0 +1,168 @@
+//! Checks the order of entry writes and TLB flushes of break-before-make.
+
+#![cfg(feature = "all-formats")]
+
+use core::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{GenericPTE, aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingHandler, PagingMetaData};
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// Returns the entry at `level` of the first mapping at [`VADDR`].
+fn entry<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(
+    pt: &MockPageTable<M, PTE>,
+    level: usize,
+) -> *const PTE {
+    // The walk only passes copies of the entries: find the table instead.
+    let table = Cell::new(pt.root_paddr());
+    pt.walk(
+        usize::MAX,
+        Some(&|l, _, va: VirtAddr, entry: &PTE| {
+            let covered = 1usize << (12 + (M::LEVELS - 1 - l) * 9);
+            let start = va.as_usize();
+            if l + 1 == level && (start..start + covered).contains(&VADDR) {
+                table.set(entry.paddr());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let index = (VADDR >> (12 + (M::LEVELS - 1 - level) * 9)) & 511;
+    let table = MockHandler::phys_to_virt(table.get()).as_ptr() as *const PTE;
+    unsafe { table.add(index) }
+}
+
+/// Maps a 4K page at [`VADDR`] with `flags`, and watches its entry.
+fn mapped_with<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(
+    flags: MappingFlags,
+) -> (MockPageTable<M, PTE>, *const PTE) {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.map(vaddr, PhysAddr::from(0x1000), PageSize::Size4K, flags)
+        .unwrap()
+        .ignore();
+    let leaf = entry(&pt, M::LEVELS - 1);
+    MockMetaData::<M>::take_flushes();
+    MockMetaData::<M>::watch_entry(Some(leaf));
+    (pt, leaf)
+}
+
+/// Maps a 4K page at [`VADDR`] with [`FLAGS`], and watches its entry.
+fn mapped<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>()
+-> (MockPageTable<M, PTE>, *const PTE) {
+    mapped_with(FLAGS)
+}
+
+/// The raw bits of an invalid AArch64 entry written by break-before-make.
+fn cleared() -> usize {
+    let mut entry = A64PTE::empty();
+    entry.clear();
+    entry.bits()
+}
+
+/// Whether new table descriptors restrict the mappings below them, with the
+/// `arm-table-permissions` feature of `page_table_entry`.
+fn restrictive_tables() -> bool {
+    !A64PTE::new_table(PhysAddr::from(0x1000)).table_allows(FLAGS)
+}
+
+#[test]
+fn remap_breaks_before_make() {
+    type Meta = MockMetaData<A64PagingMetaData>;
+    let (mut pt, leaf) = mapped::<A64PagingMetaData, A64PTE>();
+    let vaddr = VirtAddr::from(VADDR);
+    let (_, tlb) = pt.remap(vaddr, PhysAddr::from(0x2000), FLAGS).unwrap();
+    // The entry was invalid while the TLB was flushed, then got the new value.
+    assert_eq!(Meta::take_flushes(), [Some(vaddr)]);
+    assert_eq!(Meta::take_watched(), [cleared()]);
+    let new = unsafe { *leaf };
+    assert_eq!(new.paddr(), PhysAddr::from(0x2000));
+    assert!(new.is_present());
+    // No other flush is needed afterwards.
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), []);
+
+    // Changing the memory type also needs break-before-make.
+    let (_, tlb) = pt.protect(vaddr, FLAGS | MappingFlags::DEVICE).unwrap();
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), [Some(vaddr)]);
+    assert_eq!(Meta::take_watched(), [cleared()]);
+    assert!(unsafe { *leaf }.flags().contains(MappingFlags::DEVICE));
+}
+
+#[test]
+fn permission_change_is_a_single_write() {
+    type Meta = MockMetaData<A64PagingMetaData>;
+    let (mut pt, leaf) = mapped::<A64PagingMetaData, A64PTE>();
+    let vaddr = VirtAddr::from(VADDR);
+    let (_, tlb) = pt.protect(vaddr, MappingFlags::READ).unwrap();
+    assert_eq!(Meta::take_flushes(), []);
+    // The flush is deferred to the caller, and sees the new entry.
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), [Some(vaddr)]);
+    assert_eq!(Meta::take_watched(), [unsafe { *leaf }.bits()]);
+    assert!(!unsafe { *leaf }.flags().contains(MappingFlags::WRITE));
+}
+
+#[test]
+fn other_arches_overwrite_in_place() {
+    type Meta = MockMetaData<X64PagingMetaData>;
+    let (mut pt, leaf) = mapped::<X64PagingMetaData, X64PTE>();
+    let vaddr = VirtAddr::from(VADDR);
+    let (_, tlb) = pt.remap(vaddr, PhysAddr::from(0x2000), FLAGS).unwrap();
+    assert_eq!(Meta::take_flushes(), []);
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), [Some(vaddr)]);
+    assert_eq!(Meta::take_watched(), [unsafe { *leaf }.bits()]);
+    assert_eq!(unsafe { *leaf }.paddr(), PhysAddr::from(0x2000));
+}
+
+#[test]
+fn table_widening_is_a_single_write() {
+    type Meta = MockMetaData<A64PagingMetaData>;
+    let (mut pt, _) = mapped_with::<A64PagingMetaData, A64PTE>(MappingFlags::READ);
+    let table = entry(&pt, A64PagingMetaData::LEVELS - 2);
+    assert_eq!(unsafe { *table }.table_allows(FLAGS), !restrictive_tables());
+    Meta::watch_entry(Some(table));
+
+    let vaddr = VirtAddr::from(VADDR + 0x1000);
+    pt.map(vaddr, PhysAddr::from(0x2000), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .flush();
+    let table = unsafe { *table };
+    assert!(table.table_allows(FLAGS));
+    if restrictive_tables() {
+        // The descriptor stays valid, and is flushed once widened.
+        assert_eq!(Meta::take_flushes(), [Some(vaddr)]);
+        assert_eq!(Meta::take_watched(), [table.bits()]);
+    } else {
+        assert_eq!(Meta::take_flushes(), []);
+    }
+}
+
+#[test]
+fn remap_widens_tables_after_break_before_make() {
+    type Meta = MockMetaData<A64PagingMetaData>;
+    let (mut pt, leaf) = mapped_with::<A64PagingMetaData, A64PTE>(MappingFlags::READ);
+    let vaddr = VirtAddr::from(VADDR);
+    let (_, tlb) = pt.remap(vaddr, PhysAddr::from(0x2000), FLAGS).unwrap();
+    tlb.flush();
+    let new = unsafe { *leaf }.bits();
+    if restrictive_tables() {
+        // Break-before-make of the leaf, then a flush for the widened tables.
+        assert_eq!(Meta::take_flushes(), [Some(vaddr), Some(vaddr)]);
+        assert_eq!(Meta::take_watched(), [cleared(), new]);
+    } else {
+        assert_eq!(Meta::take_flushes(), [Some(vaddr)]);
+        assert_eq!(Meta::take_watched(), [cleared()]);
+    }
+    let table = entry(&pt, A64PagingMetaData::LEVELS - 2);
+    assert!(unsafe { *table }.table_allows(FLAGS));
+}
@@ -0,0 +1,145 @@
+//! Replaying changes of kernel mappings into many page tables, all or
+//! nothing, and once for the page tables sharing a subtree.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingResult};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const KERNEL: usize = 0xffff_8000_4000_0000;
+const PADDR: usize = 0x8000_0000;
+const SIZE: usize = 0x20_4000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn tables(n: usize) -> Vec<PageTable> {
+    (0..n).map(|_| PageTable::try_new().unwrap()).collect()
+}
+
+fn query(pt: &PageTable, vaddr: usize) -> PagingResult<(PhysAddr, MappingFlags, PageSize)> {
+    pt.query(va(vaddr))
+}
+
+#[test]
+fn map_unmap_protect() {
+    MockHandler::reset();
+    let mut pts = tables(3);
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    let tlb = PageTable::broadcast_map(&mut refs, va(KERNEL), PADDR.into(), SIZE, RW, true);
+    let tlb = tlb.unwrap();
+    // A 2M page and 4 4K pages in each page table.
+    assert_eq!(tlb.pages(), Some(15));
+    tlb.ignore();
+    for pt in &pts {
+        let huge = (PhysAddr::from(PADDR + 0x1000), RW, PageSize::Size2M);
+        assert_eq!(query(pt, KERNEL + 0x1000), Ok(huge));
+        let last = (PhysAddr::from(PADDR + SIZE - 0x1000), RW, PageSize::Size4K);
+        assert_eq!(query(pt, KERNEL + SIZE - 0x1000), Ok(last));
+    }
+
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    let tlb = PageTable::broadcast_protect(&mut refs, va(KERNEL), SIZE, MappingFlags::READ);
+    let tlb = tlb.unwrap();
+    assert!(tlb.is_needed());
+    tlb.ignore();
+    let tlb = PageTable::broadcast_unmap(&mut refs, va(KERNEL + 0x20_0000), 0x4000).unwrap();
+    tlb.ignore();
+    for pt in &pts {
+        let huge = (PhysAddr::from(PADDR), MappingFlags::READ, PageSize::Size2M);
+        assert_eq!(query(pt, KERNEL), Ok(huge));
+        assert_eq!(query(pt, KERNEL + 0x20_0000), Err(PagingError::NotMapped));
+    }
+}
+
+#[test]
+fn map_rolls_back() {
+    MockHandler::reset();
+    let mut pts = tables(3);
+    // The last page is taken in the second page table.
+    let taken = KERNEL + SIZE - 0x1000;
+    pts[1]
+        .map(va(taken), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    let result = PageTable::broadcast_map(&mut refs, va(KERNEL), PADDR.into(), SIZE, RW, true);
+    assert_eq!(result.err(), Some(PagingError::AlreadyMapped));
+    for pt in &pts {
+        assert_eq!(query(pt, KERNEL), Err(PagingError::NotMapped));
+        assert_eq!(query(pt, KERNEL + 0x20_0000), Err(PagingError::NotMapped));
+    }
+    let page = (PhysAddr::from(0x1000), RW, PageSize::Size4K);
+    assert_eq!(query(&pts[1], taken), Ok(page));
+}
+
+#[test]
+fn checked_before_changes() {
+    MockHandler::reset();
+    let mut pts = tables(2);
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    PageTable::broadcast_map(&mut refs, va(KERNEL), PADDR.into(), SIZE, RW, true)
+        .unwrap()
+        .ignore();
+    pts[1].unmap(va(KERNEL + SIZE - 0x1000)).unwrap().2.ignore();
+
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    let result = PageTable::broadcast_unmap(&mut refs, va(KERNEL), SIZE);
+    assert_eq!(result.err(), Some(PagingError::NotMapped));
+    let result = PageTable::broadcast_protect(&mut refs, va(KERNEL), SIZE, MappingFlags::READ);
+    assert_eq!(result.err(), Some(PagingError::NotMapped));
+    // The region cannot cut the huge page.
+    let result = PageTable::broadcast_unmap(&mut refs, va(KERNEL + 0x1000), 0x1000);
+    assert!(matches!(result, Err(PagingError::MappedToHugePage { .. })));
+    let result = PageTable::broadcast_unmap(&mut refs, va(KERNEL), 0x1000);
+    assert!(matches!(result, Err(PagingError::MappedToHugePage { .. })));
+    for pt in &pts {
+        let huge = (PhysAddr::from(PADDR), RW, PageSize::Size2M);
+        assert_eq!(query(pt, KERNEL), Ok(huge));
+    }
+}
+
+#[test]
+fn shared_subtree_changed_once() {
+    MockHandler::reset();
+    let mut pts = tables(3);
+    // The second page table shares the 2M of 4K pages of the first one.
+    let base = KERNEL + 0x20_0000;
+    pts[0]
+        .map(va(base), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let subtree = pts[0].export_subtree(va(base), 0x20_0000).unwrap();
+    pts[1].import_subtree(va(base), &subtree).unwrap().ignore();
+
+    let vaddr = va(base + 0x1000);
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    PageTable::broadcast_map(&mut refs, vaddr, PADDR.into(), 0x3000, RW, false)
+        .unwrap()
+        .ignore();
+    for pt in &pts {
+        let page = (PhysAddr::from(PADDR), RW, PageSize::Size4K);
+        assert_eq!(query(pt, base + 0x1000), Ok(page));
+    }
+    let mut refs: Vec<_> = pts.iter_mut().collect();
+    PageTable::broadcast_protect(&mut refs, vaddr, 0x3000, MappingFlags::READ)
+        .unwrap()
+        .ignore();
+    PageTable::broadcast_unmap(&mut refs, vaddr, 0x3000)
+        .unwrap()
+        .ignore();
+    for pt in &pts {
+        assert_eq!(query(pt, base + 0x1000), Err(PagingError::NotMapped));
+    }
+
+    for pt in &mut pts[..2] {
+        pt.release_subtree(va(base), &subtree).unwrap().ignore();
+    }
+}
@@ -0,0 +1,166 @@
+//! Page tables whose entries are stored in a byte order other than the one of
+//! the target, e.g. a dump of the tables of a big-endian machine.
+
+#![cfg(feature = "all-formats")]
+
+use core::cell::RefCell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::riscv::Rv64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::{
+    BigEndian, ByteOrder, EndianPTE, GenericPTE, LittleEndian, MappingFlags, PageSize, PagingError,
+    PagingHandler, PagingMetaData,
+};
+
+type Sv39 = Sv39MetaData<VirtAddr>;
+
+/// Sv39 on a big-endian machine.
+struct BigSv39;
+
+impl PagingMetaData for BigSv39 {
+    const LEVELS: usize = Sv39::LEVELS;
+    const PA_MAX_BITS: usize = Sv39::PA_MAX_BITS;
+    const VA_MAX_BITS: usize = Sv39::VA_MAX_BITS;
+    const BYTE_ORDER: ByteOrder = ByteOrder::Big;
+    type VirtAddr = VirtAddr;
+
+    fn vaddr_is_valid(vaddr: usize) -> bool {
+        Sv39::vaddr_is_valid(vaddr)
+    }
+
+    // `MockMetaData` records the flushes instead.
+    fn flush_tlb(_vaddr: Option<VirtAddr>) {}
+}
+
+type Native = MockPageTable<Sv39, Rv64PTE>;
+type Big = MockPageTable<BigSv39, EndianPTE<Rv64PTE, BigEndian>>;
+
+const VADDR: usize = 0x4000_0000;
+const PADDR: usize = 0x8000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(off: usize) -> PhysAddr {
+    PhysAddr::from(PADDR + off)
+}
+
+/// Returns the tables below the root of `pt`.
+fn tables<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(
+    pt: &MockPageTable<M, PTE>,
+) -> Vec<PhysAddr> {
+    let tables = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, _, entry: &PTE| {
+            if level < M::LEVELS - 1 && entry.is_table() {
+                tables.borrow_mut().push(entry.paddr());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    tables.into_inner()
+}
+
+fn entries<'a>(table: PhysAddr) -> &'a mut [u64] {
+    let ptr = MockHandler::phys_to_virt(table).as_mut_ptr() as *mut u64;
+    unsafe { core::slice::from_raw_parts_mut(ptr, 512) }
+}
+
+#[test]
+fn foreign_image() {
+    MockHandler::reset();
+    let mut native = Native::try_new().unwrap();
+    for (off, size) in [(0, 0x3000), (SIZE_2M, SIZE_2M)] {
+        native
+            .map_region(va(off), |v| pa(v.as_usize() - VADDR), size, RW, true, false)
+            .unwrap()
+            .ignore();
+    }
+
+    // Byte-swap the image of the tables, and take it over with a big-endian
+    // root.
+    let big = Big::try_new().unwrap();
+    for table in tables(&native) {
+        for entry in entries(table) {
+            *entry = entry.swap_bytes();
+        }
+    }
+    let root = entries(native.root_paddr());
+    for (i, entry) in entries(big.root_paddr()).iter_mut().enumerate() {
+        *entry = root[i].swap_bytes();
+    }
+    core::mem::forget(native);
+
+    for off in [0, 0x1000, 0x2000] {
+        assert_eq!(
+            big.query(va(off)),
+            Ok((pa(off), RW, PageSize::Size4K)),
+            "{off:#x}"
+        );
+    }
+    assert_eq!(
+        big.query(va(SIZE_2M + 0x1234)),
+        Ok((pa(SIZE_2M + 0x1234), RW, PageSize::Size2M))
+    );
+    assert_eq!(big.query(va(0x3000)), Err(PagingError::NotMapped));
+}
+
+#[test]
+fn stored_in_order() {
+    MockHandler::reset();
+    let mut big = Big::try_new().unwrap();
+    big.map(va(0), pa(0), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let leaf = <Rv64PTE>::new_page(pa(0), RW, false);
+    // The table of the last level.
+    let table = tables(&big)[1];
+    assert_eq!(
+        entries(table)[0].to_ne_bytes(),
+        leaf.to_bytes(ByteOrder::Big)
+    );
+    assert_eq!(
+        <Rv64PTE>::from_bytes(entries(table)[0].to_ne_bytes(), ByteOrder::Big).bits(),
+        leaf.bits()
+    );
+
+    // Changed in place, and still read back.
+    big.protect(va(0), MappingFlags::READ).unwrap().1.ignore();
+    let leaf = <Rv64PTE>::new_page(pa(0), MappingFlags::READ, false);
+    assert_eq!(entries(table)[0], (leaf.bits() as u64).to_be());
+    assert_eq!(
+        big.query(va(0)),
+        Ok((pa(0), MappingFlags::READ, PageSize::Size4K))
+    );
+    big.unmap(va(0)).unwrap().2.ignore();
+    // Zero, or poisoned with the `debug-poison` feature.
+    let cleared = <Rv64PTE>::from_bytes(entries(table)[0].to_ne_bytes(), ByteOrder::Big);
+    assert!(cleared.is_unused());
+}
+
+/// The entries of the native order are stored as they are.
+#[cfg(target_endian = "little")]
+#[test]
+fn native_order_unchanged() {
+    MockHandler::reset();
+    let mut wrapped = MockPageTable::<Sv39, EndianPTE<Rv64PTE, LittleEndian>>::try_new().unwrap();
+    let mut native = Native::try_new().unwrap();
+    wrapped
+        .map(va(0), pa(0), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    native
+        .map(va(0), pa(0), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let (wrapped, native) = (tables(&wrapped), tables(&native));
+    assert_ne!(entries(native[1])[0], 0);
+    assert_eq!(entries(wrapped[1])[0], entries(native[1])[0]);
+}
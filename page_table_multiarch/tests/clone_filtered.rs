@@ -0,0 +1,161 @@
+//! Cloning only some mappings of a region into the child, each shared
+//! copy-on-write, shared as is, skipped, or copied right away.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::loongarch64::LA64PTE;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::{CloneAction, MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<LA64MetaData, LA64PTE>;
+
+const BASE: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const COW: MappingFlags = MappingFlags::READ.union(MappingFlags::COW);
+
+const COWED: usize = BASE;
+const SHARED: usize = BASE + 0x1000;
+const SKIPPED: usize = BASE + 0x2000;
+const RO: usize = BASE + 0x3000;
+const COPIED: usize = BASE + 0x20_0000;
+const SIZE: usize = 0x40_0000;
+const COPY: usize = 0x9000_0000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(vaddr: usize) -> PhysAddr {
+    PhysAddr::from(vaddr - BASE + 0x8000_0000)
+}
+
+fn parent() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for (vaddr, size, flags) in [
+        (COWED, PageSize::Size4K, RW),
+        (SHARED, PageSize::Size4K, RW),
+        (SKIPPED, PageSize::Size4K, RW),
+        (RO, PageSize::Size4K, MappingFlags::READ),
+        (COPIED, PageSize::Size2M, RW),
+    ] {
+        pt.map(va(vaddr), pa(vaddr), size, flags).unwrap().ignore();
+    }
+    pt
+}
+
+fn action(vaddr: VirtAddr, _: PageSize, _: MappingFlags) -> CloneAction {
+    match vaddr.as_usize() {
+        COWED => CloneAction::Cow,
+        SHARED | RO => CloneAction::Share,
+        SKIPPED => CloneAction::Skip,
+        _ => CloneAction::Copy,
+    }
+}
+
+/// The mapping of `vaddr` without the bits added by the architecture.
+fn mapping(pt: &PageTable, vaddr: usize) -> Result<(PhysAddr, MappingFlags), PagingError> {
+    pt.query(va(vaddr))
+        .map(|(paddr, flags, _)| (paddr, flags & !MappingFlags::EXECUTE))
+}
+
+#[test]
+fn actions() {
+    let mut parent = parent();
+    let mut seen = Vec::new();
+    let mut copied = Vec::new();
+    let filter = |vaddr, size, flags: MappingFlags| {
+        seen.push((vaddr, size, flags & !MappingFlags::EXECUTE));
+        action(vaddr, size, flags)
+    };
+    let copy = |paddr, size| {
+        copied.push((paddr, size));
+        Some(PhysAddr::from(COPY))
+    };
+    let (child, tlb) = parent
+        .clone_cow_filtered(va(BASE), SIZE, filter, copy)
+        .unwrap();
+    tlb.ignore();
+    let page = PageSize::Size4K;
+    assert_eq!(
+        seen,
+        [
+            (va(COWED), page, RW),
+            (va(SHARED), page, RW),
+            (va(SKIPPED), page, RW),
+            (va(RO), page, MappingFlags::READ),
+            (va(COPIED), PageSize::Size2M, RW),
+        ]
+    );
+    assert_eq!(copied, [(pa(COPIED), PageSize::Size2M)]);
+
+    // Only the page shared copy-on-write is marked in the parent.
+    assert_eq!(mapping(&parent, COWED), Ok((pa(COWED), COW)));
+    assert_eq!(mapping(&child, COWED), Ok((pa(COWED), COW)));
+    assert_eq!(MockHandler::refs(pa(COWED)), Some(2));
+    assert_eq!(MockHandler::stats().shared, 1);
+    for vaddr in [SHARED, SKIPPED, COPIED] {
+        assert_eq!(mapping(&parent, vaddr), Ok((pa(vaddr), RW)));
+    }
+    assert_eq!(mapping(&child, SHARED), Ok((pa(SHARED), RW)));
+    assert_eq!(mapping(&child, RO), Ok((pa(RO), MappingFlags::READ)));
+    assert_eq!(mapping(&child, SKIPPED), Err(PagingError::NotMapped));
+    let (paddr, flags, size) = child.query(va(COPIED + 0x5000)).unwrap();
+    assert_eq!(
+        (paddr, size),
+        (PhysAddr::from(COPY + 0x5000), PageSize::Size2M)
+    );
+    assert!(flags.contains(RW));
+
+    // The skipped page is not accounted to the child.
+    assert_eq!(child.mapped_bytes(), 0x3000 + 0x20_0000);
+}
+
+#[test]
+fn pages_already_cow() {
+    let mut parent = parent();
+    let (mut child, tlb) = parent.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+    assert_eq!(MockHandler::refs(pa(COWED)), Some(2));
+
+    // Shared as is, a copy-on-write page takes one more reference; copied, it
+    // becomes private and writable.
+    let filter = |vaddr: VirtAddr, _, _| match vaddr.as_usize() {
+        COWED => CloneAction::Share,
+        SHARED => CloneAction::Copy,
+        _ => CloneAction::Skip,
+    };
+    let copy = |_, _| Some(PhysAddr::from(COPY));
+    let (grandchild, tlb) = child
+        .clone_cow_filtered(va(BASE), SIZE, filter, copy)
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(mapping(&grandchild, COWED), Ok((pa(COWED), COW)));
+    assert_eq!(MockHandler::refs(pa(COWED)), Some(3));
+    assert_eq!(mapping(&grandchild, SHARED), Ok((PhysAddr::from(COPY), RW)));
+    assert_eq!(MockHandler::refs(pa(SHARED)), Some(2));
+    assert_eq!(mapping(&child, SHARED), Ok((pa(SHARED), COW)));
+}
+
+#[test]
+fn errors() {
+    let mut parent = parent();
+    let live = MockHandler::live_frames();
+    let result = parent.clone_cow_filtered(va(BASE), SIZE, action, |_, _| None);
+    assert_eq!(result.err(), Some(PagingError::NoMemory));
+    // The reference taken before the copy failed is dropped.
+    assert_eq!(MockHandler::refs(pa(COWED)), Some(1));
+    assert_eq!(MockHandler::live_frames(), live);
+
+    let result = parent.clone_cow_filtered(va(COPIED + 0x1000), 0x1000, action, |_, _| None);
+    assert_eq!(result.err(), Some(PagingError::NotAligned));
+    // Nothing to copy.
+    let no_copy = |_, _| -> Option<PhysAddr> { panic!("unexpected copy") };
+    let (child, tlb) = parent
+        .clone_cow_filtered(va(BASE), 0x4000, action, no_copy)
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(child.mapped_bytes(), 0x3000);
+}
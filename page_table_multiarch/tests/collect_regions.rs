@@ -0,0 +1,121 @@
+//! Collecting the coalesced regions of a range into a sink, with the same
+//! output whatever the page sizes used for the mappings.
+
+#![cfg(target_arch = "x86_64")]
+
+use core::mem::MaybeUninit;
+use core::ops::ControlFlow;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{FixedVec, MappedRegion, MappingFlags, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+
+/// `(vaddr, paddr, size, flags)` of the regions mapped by [`page_table`]:
+/// two of them mix 2M and 4K pages.
+const REGIONS: [(usize, usize, usize, MappingFlags); 4] = [
+    // 4K pages, a 2M page, and 4K pages again.
+    (0x1f_f000, 0x401f_f000, 0x20_2000, RW),
+    // Only the flags differ from the previous region.
+    (0x40_1000, 0x4040_1000, 0x1000, RX),
+    // Only the physical address differs from the previous region.
+    (0x40_2000, 0x5000_0000, 0x1000, RX),
+    // A hole before.
+    (0x60_0000, 0x6000_0000, 0x20_0000, RW),
+];
+
+fn page_table(allow_huge: bool) -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    for (vaddr, paddr, size, flags) in REGIONS {
+        let paddr = |va: VirtAddr| PhysAddr::from(va.as_usize() - vaddr + paddr);
+        pt.map_region(vaddr.into(), paddr, size, flags, allow_huge, false)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+fn collect(pt: &PageTable, start: usize, size: usize) -> Vec<(usize, usize, usize, MappingFlags)> {
+    let mut regions: Vec<MappedRegion> = Vec::new();
+    pt.collect_regions(start.into(), size, &mut regions)
+        .unwrap();
+    for region in &regions {
+        assert_eq!(region.generation, pt.generation());
+    }
+    regions
+        .iter()
+        .map(|r| (r.vaddr, r.paddr.as_usize(), r.size, r.flags))
+        .collect()
+}
+
+#[test]
+fn coalesced() {
+    MockHandler::reset();
+    let huge = page_table(true);
+    assert_eq!(huge.query(0x20_0000.into()).unwrap().2 as usize, 0x20_0000);
+    assert_eq!(collect(&huge, 0, 0x100_0000), REGIONS);
+    // The same mappings with 4K pages only.
+    let small = page_table(false);
+    assert_eq!(collect(&small, 0, 0x100_0000), REGIONS);
+}
+
+#[test]
+fn cut_to_the_range() {
+    MockHandler::reset();
+    for allow_huge in [true, false] {
+        let pt = page_table(allow_huge);
+        // From inside the 2M page, to inside the last region.
+        assert_eq!(
+            collect(&pt, 0x30_0000, 0x40_0000),
+            [
+                (0x30_0000, 0x4030_0000, 0x10_1000, RW),
+                (0x40_1000, 0x4040_1000, 0x1000, RX),
+                (0x40_2000, 0x5000_0000, 0x1000, RX),
+                (0x60_0000, 0x6000_0000, 0x10_0000, RW),
+            ]
+        );
+        assert_eq!(collect(&pt, 0x40_3000, 0x1_0000), []);
+        assert_eq!(
+            collect(&pt, 0x20_0800, 0x800),
+            [(0x20_0800, 0x4020_0800, 0x800, RW)]
+        );
+        assert_eq!(collect(&pt, 0x20_0000, 0), []);
+    }
+}
+
+#[test]
+fn sinks() {
+    MockHandler::reset();
+    let pt = page_table(true);
+
+    // A closure stopping at the first executable region.
+    let mut seen = Vec::new();
+    let mut sink = |region: MappedRegion| {
+        seen.push(region.vaddr);
+        match region.flags.contains(MappingFlags::EXECUTE) {
+            true => ControlFlow::Break(()),
+            false => ControlFlow::Continue(()),
+        }
+    };
+    pt.collect_regions(0.into(), 0x100_0000, &mut sink).unwrap();
+    assert_eq!(seen, [0x1f_f000, 0x40_1000]);
+
+    // A buffer too small counts the regions that did not fit.
+    let mut buf = [MaybeUninit::uninit(); 3];
+    let mut out = FixedVec::new(&mut buf);
+    pt.collect_regions(0.into(), 0x100_0000, &mut out).unwrap();
+    assert_eq!(out.len(), 3);
+    assert_eq!(out.check(), Err(PagingError::BufferTooSmall { needed: 4 }));
+    assert_eq!(out[2].paddr, PhysAddr::from(0x5000_0000));
+
+    assert_eq!(
+        pt.collect_regions(0x7fff_ffff_f000.into(), 0x2000, &mut out),
+        Err(PagingError::InvalidVaddr(0x7fff_ffff_f000))
+    );
+}
@@ -0,0 +1,214 @@
+//! Checks the groups of 4K entries with the contiguous hint: the AArch64
+//! contiguous bit and RISC-V Svnapot.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{GenericPTE, aarch64::A64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{
+    MockHandler, MockMetaData, MockPageTable, ShadowModel, check_contiguous,
+};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingHandler};
+
+type Meta = MockMetaData<A64PagingMetaData>;
+type PageTable = MockPageTable<A64PagingMetaData, A64PTE>;
+type Model = ShadowModel<MockMetaData<A64PagingMetaData>, A64PTE>;
+
+const VADDR: usize = 0x20_0000_0000;
+const PADDR: usize = 0x8_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_64K: usize = PageSize::Size64K as usize;
+
+fn vaddr(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// Maps two 64K groups and a 4K page at [`VADDR`] with `map_region`.
+fn mapped() -> (PageTable, Model) {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let size = 2 * SIZE_64K + 0x1000;
+    pt.map_region(
+        vaddr(0),
+        |va| PhysAddr::from(va.as_usize() - VADDR + PADDR),
+        size,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    let mut model = Model::new();
+    model.map(vaddr(0), PADDR.into(), PageSize::Size64K, FLAGS);
+    let paddr = PhysAddr::from(PADDR + SIZE_64K);
+    model.map(vaddr(SIZE_64K), paddr, PageSize::Size64K, FLAGS);
+    let paddr = PhysAddr::from(PADDR + 2 * SIZE_64K);
+    model.map(vaddr(2 * SIZE_64K), paddr, PageSize::Size4K, FLAGS);
+    model.check(&pt);
+    Meta::take_flushes();
+    (pt, model)
+}
+
+#[test]
+fn map_region_uses_groups() {
+    let (pt, _) = mapped();
+    assert_eq!(
+        pt.query(vaddr(0x5123)),
+        Ok((PhysAddr::from(PADDR + 0x5123), FLAGS, PageSize::Size64K))
+    );
+    assert_eq!(pt.mapped_bytes(), 2 * SIZE_64K + 0x1000);
+    assert_eq!(check_contiguous(&pt), []);
+}
+
+#[test]
+fn map_group() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let size = PageSize::Size64K;
+    // Unaligned addresses are aligned down, like for the other sizes.
+    pt.map(vaddr(0x3000), PADDR.into(), size, FLAGS)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pt.query(vaddr(0xf000)),
+        Ok((PhysAddr::from(PADDR + 0xf000), FLAGS, size))
+    );
+    assert_eq!(
+        pt.map(vaddr(0), PADDR.into(), size, FLAGS).err(),
+        Some(PagingError::AlreadyMapped)
+    );
+    // A single 4K mapping prevents a group.
+    let other = vaddr(SIZE_64K + 0x2000);
+    pt.map(other, PADDR.into(), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pt.map(vaddr(SIZE_64K), PADDR.into(), size, FLAGS).err(),
+        Some(PagingError::AlreadyMapped)
+    );
+    assert_eq!(check_contiguous(&pt), []);
+}
+
+#[test]
+fn unmap_breaks_the_group() {
+    let (mut pt, mut model) = mapped();
+    let (paddr, size, tlb) = pt.unmap(vaddr(0x3000)).unwrap();
+    assert_eq!(
+        (paddr, size),
+        (PhysAddr::from(PADDR + 0x3000), PageSize::Size4K)
+    );
+    // The whole group was invalidated and flushed before being rewritten.
+    let flushes: Vec<_> = (0..16).map(|i| Some(vaddr(i * 0x1000))).collect();
+    assert_eq!(Meta::take_flushes(), flushes);
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), [Some(vaddr(0x3000))]);
+    model.unmap(vaddr(0x3000));
+    model.check(&pt);
+    assert_eq!(check_contiguous(&pt), []);
+    assert_eq!(pt.mapped_bytes(), 2 * SIZE_64K);
+    // The other group is unchanged.
+    assert_eq!(pt.query(vaddr(SIZE_64K)).unwrap().2, PageSize::Size64K);
+}
+
+#[test]
+fn protect_breaks_the_group() {
+    let (mut pt, mut model) = mapped();
+    let off = SIZE_64K + 0xf000;
+    let (size, tlb) = pt.protect(vaddr(off), MappingFlags::READ).unwrap();
+    assert_eq!(size, PageSize::Size4K);
+    tlb.flush();
+    model.protect(vaddr(off), MappingFlags::READ);
+    model.check(&pt);
+    assert_eq!(check_contiguous(&pt), []);
+    assert_eq!(
+        pt.query(vaddr(SIZE_64K)),
+        Ok((PhysAddr::from(PADDR + SIZE_64K), FLAGS, PageSize::Size4K))
+    );
+}
+
+#[test]
+fn inconsistent_groups_are_reported() {
+    let (pt, _) = mapped();
+    // Remove the hint from a single entry of the second group.
+    let table = core::cell::Cell::new(PhysAddr::from(0));
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, va: VirtAddr, entry: &A64PTE| {
+            if level == 2 && va == vaddr(0) {
+                table.set(entry.paddr());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let p1 = MockHandler::phys_to_virt(table.get()).as_mut_ptr() as *mut A64PTE;
+    unsafe { (*p1.add(20)).set_contiguous(false) };
+    assert_eq!(check_contiguous(&pt), [VADDR + SIZE_64K]);
+}
+
+#[test]
+fn unsupported_without_hint() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    assert_eq!(
+        pt.map(vaddr(0), PADDR.into(), PageSize::Size64K, FLAGS)
+            .err(),
+        Some(PagingError::UnsupportedPageSize)
+    );
+    // `map_region` falls back to 4K pages.
+    pt.map_region(
+        vaddr(0),
+        |va| PhysAddr::from(va.as_usize() - VADDR + PADDR),
+        SIZE_64K,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(pt.query(vaddr(0)).unwrap().2, PageSize::Size4K);
+}
+
+#[test]
+fn napot_groups() {
+    type Meta = MockMetaData<Sv39MetaData<VirtAddr>>;
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Sv39MetaData<VirtAddr>, Rv64PTE>::try_new().unwrap();
+    let map = |pt: &mut MockPageTable<_, _>| {
+        pt.map_region(
+            vaddr(0),
+            |va| PhysAddr::from(va.as_usize() - VADDR + PADDR),
+            SIZE_64K,
+            FLAGS,
+            true,
+            false,
+        )
+    };
+    if !<Rv64PTE as GenericPTE>::CONTIGUOUS_HINT {
+        // Without the `riscv-svnapot` feature of `page_table_entry`.
+        assert_eq!(
+            pt.map(vaddr(0), PADDR.into(), PageSize::Size64K, FLAGS)
+                .err(),
+            Some(PagingError::UnsupportedPageSize)
+        );
+        map(&mut pt).unwrap().ignore();
+        assert_eq!(pt.query(vaddr(0)).unwrap().2, PageSize::Size4K);
+        return;
+    }
+    map(&mut pt).unwrap().ignore();
+    let mut model = ShadowModel::<Meta, Rv64PTE>::new();
+    model.map(vaddr(0), PADDR.into(), PageSize::Size64K, FLAGS);
+    model.check(&pt);
+    assert_eq!(check_contiguous(&pt), []);
+
+    // Members of a group find their own frame.
+    let (paddr, _, tlb) = pt.unmap(vaddr(0x7000)).unwrap();
+    tlb.ignore();
+    assert_eq!(paddr, PhysAddr::from(PADDR + 0x7000));
+    model.unmap(vaddr(0x7000));
+    model.check(&pt);
+    assert_eq!(check_contiguous(&pt), []);
+}
@@ -0,0 +1,360 @@
+//! Copy-on-write sharing, faults and unmapping, with the reference counting
+//! callbacks counted by the mock handler.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+use page_table_entry::loongarch64::LA64PTE;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::{HugeCowPolicy, MappingFlags, PageSize, PagingError, PagingHandler};
+use page_table_multiarch::{RegionCursor, StepStatus};
+
+type PageTable = MockPageTable<LA64MetaData, LA64PTE>;
+
+const BASE: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const COW: MappingFlags = MappingFlags::READ.union(MappingFlags::COW);
+
+const A: usize = BASE;
+const B: usize = BASE + 0x1000;
+const RO: usize = BASE + 0x2000;
+const DEV: usize = BASE + 0x3000;
+const HUGE: usize = BASE + 0x20_0000;
+const SIZE: usize = 0x40_0000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(vaddr: usize) -> PhysAddr {
+    PhysAddr::from(vaddr - BASE + 0x8000_0000)
+}
+
+fn parent() -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    for (vaddr, size, flags) in [
+        (A, PageSize::Size4K, RW),
+        (B, PageSize::Size4K, RW),
+        (RO, PageSize::Size4K, MappingFlags::READ),
+        (DEV, PageSize::Size4K, RW | MappingFlags::DEVICE),
+        (HUGE, PageSize::Size2M, RW),
+    ] {
+        pt.map(va(vaddr), pa(vaddr), size, flags).unwrap().ignore();
+    }
+    pt
+}
+
+fn flags_of(pt: &PageTable, vaddr: usize) -> MappingFlags {
+    pt.query(va(vaddr)).unwrap().1
+}
+
+#[test]
+fn fork_fault_unmap() {
+    MockHandler::reset();
+    let mut parent = parent();
+    let (mut child, tlb) = parent.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+
+    // Only the writable pages become shared.
+    assert_eq!(MockHandler::stats().shared, 3);
+    for vaddr in [A, B, HUGE] {
+        assert_eq!(MockHandler::refs(pa(vaddr)), Some(2));
+        for pt in [&parent, &child] {
+            assert_eq!(flags_of(pt, vaddr) & !MappingFlags::EXECUTE, COW);
+        }
+    }
+    for pt in [&parent, &child] {
+        assert!(!flags_of(pt, RO).contains(MappingFlags::COW));
+        assert!(flags_of(pt, DEV).contains(MappingFlags::WRITE));
+    }
+    assert_eq!(MockHandler::refs(pa(RO)), None);
+
+    // The child copies, then the parent is the last user and keeps the page.
+    let copy = PhysAddr::from(0x9000_0000);
+    child
+        .handle_cow_fault(va(A), |paddr, size| {
+            assert_eq!((paddr, size), (pa(A), PageSize::Size4K));
+            Some(copy)
+        })
+        .unwrap()
+        .ignore();
+    assert_eq!(child.query(va(A)).unwrap().0, copy);
+    assert!(flags_of(&child, A).contains(RW) && !flags_of(&child, A).contains(COW));
+    assert_eq!(MockHandler::refs(pa(A)), Some(1));
+    parent
+        .handle_cow_fault(va(A), |paddr, _| Some(paddr))
+        .unwrap()
+        .ignore();
+    assert!(flags_of(&parent, A).contains(RW));
+    assert_eq!(MockHandler::refs(pa(A)), Some(1));
+    assert_eq!(MockHandler::stats().unshared, 1);
+
+    // Faults on huge pages receive the whole page if asked to.
+    child.set_huge_cow_policy(HugeCowPolicy::CopyWhole);
+    child
+        .handle_cow_fault(va(HUGE + 0x5000), |paddr, size| {
+            assert_eq!((paddr, size), (pa(HUGE), PageSize::Size2M));
+            Some(PhysAddr::from(0xa000_0000))
+        })
+        .unwrap()
+        .ignore();
+    assert_eq!(MockHandler::refs(pa(HUGE)), Some(1));
+
+    // Unmapping every COW mapping drops the count to zero.
+    child.unmap(va(B)).unwrap().2.ignore();
+    parent.unmap(va(B)).unwrap().2.ignore();
+    assert_eq!(MockHandler::refs(pa(B)), Some(0));
+    parent.unmap(va(HUGE)).unwrap().2.ignore();
+    assert_eq!(MockHandler::refs(pa(HUGE)), Some(0));
+    // Private pages are not reported.
+    child.unmap(va(A)).unwrap().2.ignore();
+    parent.unmap(va(A)).unwrap().2.ignore();
+    assert_eq!(MockHandler::stats().unshared, 5);
+}
+
+#[test]
+fn huge_fault_policies() {
+    for policy in [HugeCowPolicy::Split, HugeCowPolicy::CopyWhole] {
+        MockHandler::reset();
+        let mut parent = parent();
+        let (mut child, tlb) = parent.clone_cow(va(BASE), SIZE).unwrap();
+        tlb.ignore();
+        child.set_huge_cow_policy(policy);
+        let allocated = MockHandler::stats().allocated;
+        let written = HUGE + 0x5000;
+        let mut copy = None;
+        child
+            .handle_cow_fault(va(written), |paddr, size| {
+                copy = match size {
+                    PageSize::Size4K => MockHandler::alloc_frame(),
+                    size => MockHandler::alloc_table(size as usize),
+                };
+                assert_eq!(paddr, pa(written).align_down(size));
+                copy
+            })
+            .unwrap()
+            .ignore();
+        let allocated = MockHandler::stats().allocated - allocated;
+        let (paddr, flags, size) = child.query(va(written)).unwrap();
+        assert!(flags.contains(RW));
+        match policy {
+            // A table, and a copy of the 4K page.
+            HugeCowPolicy::Split => {
+                assert_eq!(allocated, 2);
+                assert_eq!((paddr, size), (copy.unwrap(), PageSize::Size4K));
+                // The other pages are still shared.
+                let (paddr, flags, size) = child.query(va(HUGE + 0x1000)).unwrap();
+                assert_eq!((paddr, size), (pa(HUGE + 0x1000), PageSize::Size4K));
+                assert_eq!(flags & !MappingFlags::EXECUTE, COW);
+                assert_eq!(MockHandler::refs(pa(HUGE + 0x1000)), Some(2));
+                assert_eq!(MockHandler::refs(pa(written)), Some(1));
+            }
+            // A copy of the 2M page.
+            HugeCowPolicy::CopyWhole => {
+                assert_eq!(allocated, 1);
+                let copy = copy.unwrap().add(0x5000);
+                assert_eq!((paddr, size), (copy, PageSize::Size2M));
+                assert_eq!(MockHandler::refs(pa(HUGE)), Some(1));
+            }
+        }
+        assert_eq!(parent.query(va(HUGE)).unwrap().2, PageSize::Size2M);
+    }
+}
+
+#[test]
+fn fault_errors() {
+    MockHandler::reset();
+    let mut pt = parent();
+    let no_copy = |_, _| -> Option<PhysAddr> { panic!("unexpected copy") };
+    assert_eq!(
+        pt.handle_cow_fault(va(A), no_copy).map(|t| t.ignore()),
+        Err(PagingError::NotCow)
+    );
+    assert_eq!(
+        pt.handle_cow_fault(va(BASE + 0x5000), no_copy)
+            .map(|t| t.ignore()),
+        Err(PagingError::NotMapped)
+    );
+    let (_child, tlb) = pt.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+    assert_eq!(
+        pt.handle_cow_fault(va(A), |_, _| None).map(|t| t.ignore()),
+        Err(PagingError::NoMemory)
+    );
+    assert_eq!(flags_of(&pt, A) & !MappingFlags::EXECUTE, COW);
+    assert_eq!(
+        pt.clone_cow(va(BASE + 0x1234), 0x1000).map(|_| ()),
+        Err(PagingError::NotAligned)
+    );
+    // The region would split the huge page.
+    assert_eq!(
+        pt.clone_cow(va(HUGE + 0x1000), 0x1000).map(|_| ()),
+        Err(PagingError::NotAligned)
+    );
+}
+
+#[test]
+fn repeated_fork() {
+    MockHandler::reset();
+    let mut parent = parent();
+    let (mut child, tlb) = parent.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+    let (grandchild, tlb) = child.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+    assert_eq!(MockHandler::refs(pa(A)), Some(3));
+    assert_eq!(flags_of(&grandchild, A) & !MappingFlags::EXECUTE, COW);
+}
+
+#[test]
+fn aliases() {
+    MockHandler::reset();
+    let mut parent = parent();
+    let alias = BASE + 0x10000;
+    parent.map_alias(va(alias), va(A), 0x1000).unwrap().ignore();
+    assert_eq!(MockHandler::refs(pa(A)), None);
+
+    // Each alias is shared with the child on its own.
+    let (mut child, tlb) = parent.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+    assert_eq!(MockHandler::refs(pa(A)), Some(3));
+    assert_eq!(flags_of(&child, alias) & !MappingFlags::EXECUTE, COW);
+
+    // An alias of a COW page takes one more reference.
+    let outside = BASE + SIZE;
+    child
+        .map_alias(va(outside), va(alias), 0x1000)
+        .unwrap()
+        .ignore();
+    assert_eq!(flags_of(&child, outside) & !MappingFlags::EXECUTE, COW);
+    assert_eq!(MockHandler::refs(pa(A)), Some(4));
+
+    // A write fault makes only the faulting alias private.
+    let copy = PhysAddr::from(0x9000_0000);
+    child
+        .handle_cow_fault(va(outside), |_, _| Some(copy))
+        .unwrap()
+        .ignore();
+    assert_eq!(child.query(va(outside)).unwrap().0, copy);
+    assert_eq!(child.query(va(alias)).unwrap().0, pa(A));
+    assert_eq!(MockHandler::refs(pa(A)), Some(3));
+    for vaddr in [A, alias] {
+        child.unmap(va(vaddr)).unwrap().2.ignore();
+    }
+    assert_eq!(MockHandler::refs(pa(A)), Some(1));
+}
+
+#[test]
+fn alloc_failure_releases_references() {
+    for n in 0.. {
+        MockHandler::reset();
+        let mut pt = parent();
+        MockHandler::fail_alloc_at(Some(n));
+        match pt.clone_cow(va(BASE), SIZE) {
+            Ok((child, tlb)) => {
+                tlb.ignore();
+                drop(child);
+                assert!(n > 0);
+                break;
+            }
+            Err(e) => {
+                assert_eq!(e, PagingError::NoMemory);
+                let stats = MockHandler::stats();
+                assert_eq!(stats.shared, stats.unshared);
+            }
+        }
+        drop(pt);
+        assert_eq!(MockHandler::live_frames(), 0);
+    }
+}
+
+#[test]
+fn zero_region() {
+    MockHandler::reset();
+    let zero = PhysAddr::from(0x7000_0000);
+    let mut pt = PageTable::try_new().unwrap();
+    assert_eq!(
+        pt.map_zero_region(va(BASE), 0x10000, RW)
+            .map(|t| t.ignore()),
+        Err(PagingError::NoMemory)
+    );
+    MockHandler::set_zero_frame(Some(zero));
+    pt.map_zero_region(va(BASE), 0x10000, RW).unwrap().ignore();
+    for vaddr in (BASE..BASE + 0x10000).step_by(0x1000) {
+        let (paddr, flags, size) = pt.query(va(vaddr)).unwrap();
+        assert_eq!((paddr, size), (zero, PageSize::Size4K));
+        assert_eq!(flags & !MappingFlags::EXECUTE, COW);
+    }
+    pt.map(va(HUGE), pa(HUGE), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+
+    // Forking only counts the real page.
+    let (mut child, tlb) = pt.clone_cow(va(BASE), SIZE).unwrap();
+    tlb.ignore();
+    assert_eq!(MockHandler::stats().shared, 1);
+    assert_eq!(MockHandler::refs(zero), None);
+
+    // The first write gets a fresh page, and the zero frame is not released.
+    let fresh = PhysAddr::from(0x9000_0000);
+    child
+        .handle_cow_fault(va(BASE + 0x3000), |paddr, _| {
+            assert_eq!(paddr, zero);
+            Some(fresh)
+        })
+        .unwrap()
+        .ignore();
+    assert_eq!(child.query(va(BASE + 0x3000)).unwrap().0, fresh);
+    assert_eq!(pt.query(va(BASE + 0x3000)).unwrap().0, zero);
+    pt.unmap_region(va(BASE), 0x10000, false).unwrap().ignore();
+    assert_eq!(MockHandler::stats().unshared, 0);
+    assert_eq!(MockHandler::refs(zero), None);
+
+    // Read-only regions stay read-only.
+    pt.map_zero_region(va(BASE), 0x1000, MappingFlags::READ)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        flags_of(&pt, BASE) & !MappingFlags::EXECUTE,
+        MappingFlags::READ
+    );
+}
+
+#[test]
+fn fork_in_steps() {
+    MockHandler::reset();
+    let mut parent = parent();
+    let mut child = PageTable::try_new().unwrap();
+    let mut cursor = RegionCursor::new(va(BASE), SIZE);
+    let mut steps = 0;
+    // Two leaves per step: A and B, RO and DEV, then the huge page.
+    loop {
+        let (status, tlb) = parent.clone_cow_step(&mut child, &mut cursor, 2).unwrap();
+        tlb.ignore();
+        steps += 1;
+        if status == StepStatus::Done {
+            break;
+        }
+        assert!(cursor.next() > BASE);
+    }
+    assert_eq!(steps, 3);
+    assert!(cursor.is_done());
+    assert_eq!(MockHandler::stats().shared, 3);
+    for vaddr in [A, B, HUGE] {
+        assert_eq!(MockHandler::refs(pa(vaddr)), Some(2));
+        assert_eq!(flags_of(&child, vaddr) & !MappingFlags::EXECUTE, COW);
+    }
+    assert_eq!(child.mapped_bytes(), parent.mapped_bytes());
+
+    // The cursor cannot start inside a huge page.
+    let mut cursor = RegionCursor::new(va(HUGE + 0x1000), 0x1000);
+    assert_eq!(
+        parent
+            .clone_cow_step(&mut child, &mut cursor, 1)
+            .map(|(status, tlb)| {
+                tlb.ignore();
+                status
+            }),
+        Err(PagingError::NotAligned)
+    );
+}
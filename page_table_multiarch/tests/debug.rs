@@ -0,0 +1,79 @@
+//! The `Debug` output of page tables, with a bounded number of regions.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// `count` regions of two 4K pages, each mapped after a hole.
+fn mapped(count: usize) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for i in 0..count {
+        for page in 0..2 {
+            let off = i * 0x3000 + page * 0x1000;
+            pt.map(
+                VirtAddr::from(VADDR + off),
+                PhysAddr::from(0x10_0000 + off),
+                PageSize::Size4K,
+                RW,
+            )
+            .unwrap()
+            .ignore();
+        }
+    }
+    pt
+}
+
+#[test]
+fn compact() {
+    let pt = mapped(2);
+    let expected = format!(
+        "PageTable64 {{ arch: \"x86_64\", root: {:#x}, generation: 0, mapped_bytes: 0x4000, \
+         table_frames: 4, regions: [\
+         0x4000000000..0x4000002000 -> PA:0x100000 READ | WRITE, \
+         0x4000003000..0x4000005000 -> PA:0x103000 READ | WRITE] }}",
+        pt.root_paddr(),
+    );
+    if !cfg!(feature = "trace") {
+        assert_eq!(format!("{pt:?}"), expected);
+    }
+
+    let pt = PageTable::try_new().unwrap();
+    // The `trace` feature adds the stats in between.
+    let debug = format!("{pt:?}");
+    assert!(debug.contains("table_frames: 1, "));
+    assert!(debug.ends_with("regions: [] }"));
+}
+
+#[test]
+fn bounded() {
+    let pt = mapped(20);
+    let compact = format!("{pt:?}");
+    assert_eq!(compact.matches(" -> ").count(), 4);
+    assert!(compact.ends_with(", ..] }"));
+    assert!(!compact.contains('\n'));
+
+    let alternate = format!("{pt:#?}");
+    assert_eq!(alternate.matches(" -> ").count(), 16);
+    assert!(
+        alternate.contains(
+            "level_occupancy: [\n        1,\n        1,\n        1,\n        40,\n    ],"
+        )
+    );
+    assert!(alternate.ends_with("        ..,\n    ],\n}"));
+
+    // All the regions fit.
+    let pt = mapped(16);
+    let alternate = format!("{pt:#?}");
+    assert_eq!(alternate.matches(" -> ").count(), 16);
+    assert!(!alternate.contains("  ..,"));
+}
@@ -0,0 +1,164 @@
+//! Detaching whole subtrees of tables, e.g. to tear down an address space,
+//! and reclaiming their pages and tables afterwards.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+type Meta = MockMetaData<X64PagingMetaData>;
+
+const VADDR: usize = 0x40_0000_0000;
+const SIZE_2M: usize = PageSize::Size2M as usize;
+const SIZE_1G: usize = PageSize::Size1G as usize;
+const RW: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::USER);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(off: usize) -> PhysAddr {
+    PhysAddr::from(0x8000_0000 + off)
+}
+
+/// 4K pages every 64K in the first 2M, a 2M page at 4M, and a 4K page in the
+/// next 1G.
+fn mapped() -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    for off in (0..SIZE_2M).step_by(0x1_0000) {
+        pt.map(va(off), pa(off), PageSize::Size4K, RW)
+            .unwrap()
+            .ignore();
+    }
+    pt.map(va(2 * SIZE_2M), pa(2 * SIZE_2M), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    pt.map(va(SIZE_1G), pa(SIZE_1G), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+#[test]
+fn detach_and_reclaim() {
+    MockHandler::reset();
+    let live = MockHandler::live_frames();
+    let mut pt = mapped();
+    let (bytes, tables) = (pt.mapped_bytes(), pt.table_frames());
+
+    let (subtree, tlb) = pt.detach_subtree(va(0), 1).unwrap();
+    assert_eq!(subtree.region(), (va(0), SIZE_1G));
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+    assert_eq!(pt.query(va(0)), Err(PagingError::NotMapped));
+    assert_eq!(pt.query(va(2 * SIZE_2M)), Err(PagingError::NotMapped));
+    assert_eq!(
+        pt.query(va(SIZE_1G)).unwrap(),
+        (pa(SIZE_1G), RW, PageSize::Size4K)
+    );
+    // The level-2 and level-3 tables are gone.
+    assert_eq!(pt.mapped_bytes(), bytes - 32 * 0x1000 - SIZE_2M);
+    assert_eq!(pt.table_frames(), tables - 2);
+    // But only freed by the reclaim.
+    let detached = MockHandler::live_frames();
+
+    let mut pages = Vec::new();
+    subtree.reclaim(|vaddr, paddr, size, flags| pages.push((vaddr, paddr, size, flags)));
+    assert_eq!(pages.len(), 33);
+    for (i, off) in (0..SIZE_2M).step_by(0x1_0000).enumerate() {
+        assert_eq!(pages[i], (va(off), pa(off), PageSize::Size4K, RW));
+    }
+    assert_eq!(
+        pages[32],
+        (va(2 * SIZE_2M), pa(2 * SIZE_2M), PageSize::Size2M, RW)
+    );
+    assert_eq!(MockHandler::live_frames(), detached - 2);
+
+    // The tables above are kept, and reused.
+    pt.map(va(0), pa(0), PageSize::Size4K, RW).unwrap().ignore();
+    assert_eq!(pt.table_frames(), tables);
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), live);
+}
+
+#[test]
+fn lower_level() {
+    MockHandler::reset();
+    let mut pt = mapped();
+    let tables = pt.table_frames();
+    let (subtree, tlb) = pt.detach_subtree(va(0), 2).unwrap();
+    tlb.ignore();
+    assert_eq!(subtree.region(), (va(0), SIZE_2M));
+    assert_eq!(pt.table_frames(), tables - 1);
+    assert_eq!(
+        pt.query(va(2 * SIZE_2M)).unwrap(),
+        (pa(2 * SIZE_2M), RW, PageSize::Size2M)
+    );
+    let mut count = 0;
+    subtree.reclaim(|_, _, size, _| {
+        assert_eq!(size, PageSize::Size4K);
+        count += 1;
+    });
+    assert_eq!(count, 32);
+}
+
+#[test]
+fn errors() {
+    MockHandler::reset();
+    let mut pt = mapped();
+    pt.map(va(2 * SIZE_1G), pa(0), PageSize::Size1G, RW)
+        .unwrap()
+        .ignore();
+    let err = |pt: &mut PageTable, vaddr, level| pt.detach_subtree(vaddr, level).err();
+    assert_eq!(err(&mut pt, va(0x1000), 2), Some(PagingError::NotAligned));
+    assert_eq!(err(&mut pt, va(0), 3), Some(PagingError::NotAligned));
+    assert_eq!(err(&mut pt, va(SIZE_2M), 2), Some(PagingError::NotMapped));
+    assert_eq!(
+        err(&mut pt, va(2 * SIZE_2M), 2),
+        Some(PagingError::MappedToHugePage {
+            vaddr: VADDR + 2 * SIZE_2M,
+            level: 2
+        })
+    );
+    assert_eq!(
+        err(&mut pt, va(2 * SIZE_1G), 1),
+        Some(PagingError::MappedToHugePage {
+            vaddr: VADDR + 2 * SIZE_1G,
+            level: 1
+        })
+    );
+    assert_eq!(pt.query(va(0)).unwrap().0, pa(0));
+}
+
+/// x86 has no software bit for copy-on-write by default.
+#[cfg(feature = "all-formats")]
+#[test]
+fn copy_on_write_unshared() {
+    use page_table_entry::loongarch64::LA64PTE;
+    use page_table_multiarch::loongarch64::LA64MetaData;
+
+    const COW: MappingFlags = MappingFlags::READ.union(MappingFlags::COW);
+    const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+    MockHandler::reset();
+    let mut pt = MockPageTable::<LA64MetaData, LA64PTE>::try_new().unwrap();
+    pt.map(va(0), pa(0), PageSize::Size4K, COW)
+        .unwrap()
+        .ignore();
+    pt.map(va(0x1000), pa(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let (subtree, tlb) = pt.detach_subtree(va(0), 2).unwrap();
+    tlb.ignore();
+    let mut flags = Vec::new();
+    subtree.reclaim(|_, _, _, f| flags.push(f));
+    assert_eq!(flags, [COW, RW]);
+    assert_eq!(MockHandler::refs(pa(0)), Some(0));
+    assert_eq!(MockHandler::refs(pa(0x1000)), None);
+}
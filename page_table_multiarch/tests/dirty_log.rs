@@ -0,0 +1,207 @@
+//! Checks dirty logging by write protection, with guest writes simulated by
+//! faulting on the pages that are not writable.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::collections::BTreeSet;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// Maps `size` bytes of guest memory at `VADDR`, with huge pages.
+fn guest(size: usize) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map_region(
+        va(0),
+        |v| PhysAddr::from(v.as_usize() - VADDR),
+        size,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    pt
+}
+
+/// Writes to `vaddr` from the guest, faulting if the page is not writable,
+/// and returns whether it faulted.
+fn guest_write(pt: &mut PageTable, vaddr: VirtAddr, bitmap: &mut BTreeSet<usize>) -> bool {
+    let (_, flags, _) = pt.query(vaddr).unwrap();
+    if flags.contains(MappingFlags::WRITE) {
+        return false;
+    }
+    pt.handle_dirty_log_fault(vaddr, FLAGS, |page| {
+        bitmap.insert(page.as_usize() - VADDR);
+    })
+    .unwrap()
+    .flush();
+    true
+}
+
+fn collect(pt: &mut PageTable) -> BTreeSet<usize> {
+    let mut dirty = BTreeSet::new();
+    pt.collect_and_rearm(va(0), 0x40_0000, |page| {
+        dirty.insert(page.as_usize() - VADDR);
+    })
+    .unwrap()
+    .flush_all();
+    dirty
+}
+
+#[test]
+fn log_guest_writes() {
+    // Two 2M pages.
+    let mut pt = guest(0x40_0000);
+    pt.enable_dirty_log(va(0), 0x40_0000).unwrap().flush_all();
+    assert_eq!(pt.level_occupancy(), [1, 1, 2, 1024]);
+    assert_eq!(
+        pt.query(va(0x20_3000)),
+        Ok((
+            PhysAddr::from(0x20_3000),
+            MappingFlags::READ,
+            PageSize::Size4K
+        ))
+    );
+
+    let mut bitmap = BTreeSet::new();
+    assert!(guest_write(&mut pt, va(0x1234), &mut bitmap));
+    // The page is writable until collected.
+    assert!(!guest_write(&mut pt, va(0x1fff), &mut bitmap));
+    assert!(guest_write(&mut pt, va(0x3f_f000), &mut bitmap));
+    assert_eq!(bitmap, BTreeSet::from([0x1000, 0x3f_f000]));
+    assert_eq!(collect(&mut pt), bitmap);
+
+    // Every page is protected again.
+    bitmap.clear();
+    assert!(guest_write(&mut pt, va(0x1000), &mut bitmap));
+    assert_eq!(collect(&mut pt), BTreeSet::from([0x1000]));
+    assert!(collect(&mut pt).is_empty());
+    // The pages stay split.
+    assert_eq!(pt.level_occupancy(), [1, 1, 2, 1024]);
+
+    pt.protect_region(va(0), 0x40_0000, FLAGS, false)
+        .unwrap()
+        .flush_all();
+    assert!(!guest_write(&mut pt, va(0x5000), &mut bitmap));
+}
+
+#[test]
+fn collect_flushes_before_reporting() {
+    let mut pt = guest(0x4000);
+    pt.enable_dirty_log(va(0), 0x4000).unwrap().flush_all();
+    Meta::take_flushes();
+    let mut bitmap = BTreeSet::new();
+    guest_write(&mut pt, va(0x2000), &mut bitmap);
+    assert_eq!(Meta::take_flushes(), [Some(va(0x2000))]);
+
+    let tlb = pt.collect_and_rearm(va(0), 0x4000, |_| {}).unwrap();
+    assert!(tlb.is_needed());
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+}
+
+#[test]
+fn other_writable_pages_are_reported() {
+    let mut pt = guest(0x4000);
+    pt.enable_dirty_log(va(0), 0x4000).unwrap().flush_all();
+    // Made writable without a logged fault, e.g. by the hypervisor itself.
+    pt.protect(va(0x3000), FLAGS).unwrap().1.flush();
+    pt.map_region(
+        va(0x20_0000),
+        |v| PhysAddr::from(v.as_usize()),
+        0x20_0000,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    let dirty = collect(&mut pt);
+    assert_eq!(dirty.len(), 1 + 512);
+    assert!(dirty.contains(&0x3000));
+    assert_eq!(
+        pt.query(va(0x20_0000)).map(|(.., size)| size),
+        Ok(PageSize::Size4K)
+    );
+}
+
+#[test]
+fn read_only_pages_are_never_made_writable() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(
+        va(0),
+        PhysAddr::from(0x20_0000),
+        PageSize::Size2M,
+        MappingFlags::READ,
+    )
+    .unwrap()
+    .ignore();
+    // Read-only pages need no logging, and are not split.
+    pt.enable_dirty_log(va(0), 0x20_0000).unwrap().flush_all();
+    assert_eq!(pt.level_occupancy(), [1, 1, 1, 0]);
+
+    let fault = |pt: &mut PageTable, vaddr, flags| {
+        pt.handle_dirty_log_fault(vaddr, flags, |_| panic!("marked"))
+            .map(|tlb| tlb.ignore())
+    };
+    assert_eq!(fault(&mut pt, va(0), FLAGS), Err(PagingError::NotLogged));
+    assert_eq!(
+        fault(&mut pt, va(0x20_0000), FLAGS),
+        Err(PagingError::NotMapped)
+    );
+
+    let mut pt = guest(0x1000);
+    pt.enable_dirty_log(va(0), 0x1000).unwrap().flush_all();
+    let exec = FLAGS | MappingFlags::EXECUTE;
+    assert_eq!(fault(&mut pt, va(0), exec), Err(PagingError::NotLogged));
+    assert_eq!(
+        fault(&mut pt, va(0), MappingFlags::READ),
+        Err(PagingError::NotLogged)
+    );
+}
+
+#[test]
+fn spurious_fault_is_not_marked() {
+    let mut pt = guest(0x1000);
+    pt.enable_dirty_log(va(0), 0x1000).unwrap().flush_all();
+    let mut marks = 0;
+    for _ in 0..2 {
+        pt.handle_dirty_log_fault(va(0), FLAGS, |_| marks += 1)
+            .unwrap()
+            .flush();
+    }
+    assert_eq!(marks, 1);
+    assert_eq!(
+        pt.query(va(0)),
+        Ok((PhysAddr::from(0), FLAGS, PageSize::Size4K))
+    );
+}
+
+#[test]
+fn unaligned_region() {
+    let mut pt = guest(0x2000);
+    assert_eq!(
+        pt.enable_dirty_log(va(0x800), 0x1000).err(),
+        Some(PagingError::NotAligned)
+    );
+    assert_eq!(
+        pt.collect_and_rearm(va(0), 0x1800, |_| {}).err(),
+        Some(PagingError::NotAligned)
+    );
+}
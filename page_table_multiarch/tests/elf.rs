@@ -0,0 +1,259 @@
+//! Checks the mapping of ELF segments by [`PageTable64::map_elf_segments`].
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::Cell;
+
+use memory_addr::{MemoryAddr, PAGE_SIZE_4K, PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::PagingHandler;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{ElfSegment, MappingFlags, PageSize, PageTable64, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const BASE: usize = 0x40_0000_0000;
+const RX: u32 = ElfSegment::PF_R | ElfSegment::PF_X;
+const RW: u32 = ElfSegment::PF_R | ElfSegment::PF_W;
+
+/// The contents of an ELF file in physical memory.
+#[repr(C, align(4096))]
+struct File([u8; 0x4000]);
+
+impl File {
+    fn new() -> Box<Self> {
+        let mut file = Box::new(File([0; 0x4000]));
+        for (i, byte) in file.0.iter_mut().enumerate() {
+            *byte = (i % 251) as u8 + 1;
+        }
+        file
+    }
+
+    fn paddr(&self, offset: usize) -> PhysAddr {
+        PhysAddr::from(self.0.as_ptr() as usize + offset)
+    }
+}
+
+fn segment(
+    file: &File,
+    offset: usize,
+    vaddr: usize,
+    sizes: (usize, usize),
+    flags: u32,
+) -> ElfSegment {
+    ElfSegment {
+        vaddr: BASE + vaddr,
+        filesz: sizes.0,
+        memsz: sizes.1,
+        paddr: file.paddr(offset),
+        flags,
+    }
+}
+
+/// Returns the physical address and the contents of the page at `vaddr`.
+fn page(pt: &PageTable, vaddr: usize) -> (PhysAddr, MappingFlags, &'static [u8]) {
+    let (paddr, flags, size) = pt.query(VirtAddr::from(BASE + vaddr)).unwrap();
+    assert_eq!(size, PageSize::Size4K);
+    let ptr = MockHandler::phys_to_virt(paddr).as_ptr();
+    (paddr, flags, unsafe {
+        core::slice::from_raw_parts(ptr, 0x1000)
+    })
+}
+
+#[test]
+fn text_data_and_bss() {
+    MockHandler::reset();
+    let file = File::new();
+    let mut pt = PageTable::try_new().unwrap();
+    let segments = [
+        segment(&file, 0, 0, (0x1800, 0x1800), RX),
+        // The file data ends at 0x6200, the zeros at 0x7a00.
+        segment(&file, 0x2a00, 0x5a00, (0x800, 0x2000), RW),
+    ];
+    pt.map_elf_segments(segments, MappingFlags::USER)
+        .unwrap()
+        .ignore();
+    let rx = MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER;
+    let rw = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+
+    // The text and the first data page are the file pages.
+    assert_eq!(page(&pt, 0).0, file.paddr(0));
+    assert_eq!(page(&pt, 0).1, rx);
+    assert_eq!(page(&pt, 0x1000).0, file.paddr(0x1000));
+    assert_eq!(page(&pt, 0x5000).0, file.paddr(0x2000));
+    assert_eq!(page(&pt, 0x5000).1, rw);
+
+    // The file data ends in a private copy.
+    let (paddr, flags, bytes) = page(&pt, 0x6000);
+    assert_ne!(paddr, file.paddr(0x3000));
+    assert_eq!(flags, rw);
+    assert_eq!(bytes[..0x200], file.0[0x3000..0x3200]);
+    assert!(bytes[0x200..].iter().all(|&b| b == 0));
+    let (_, flags, bytes) = page(&pt, 0x7000);
+    assert_eq!(flags, rw);
+    assert!(bytes.iter().all(|&b| b == 0));
+    assert_eq!(
+        pt.query(VirtAddr::from(BASE + 0x8000)),
+        Err(PagingError::NotMapped)
+    );
+
+    // The file is unchanged, and two frames were allocated for the data.
+    assert_eq!(file.0[0x3200], (0x3200 % 251) as u8 + 1);
+    assert_eq!(MockHandler::live_frames(), pt.table_frames() + 2);
+    assert_eq!(pt.mapped_bytes(), 0x5000);
+    for vaddr in [0x6000, 0x7000] {
+        let (paddr, _, tlb) = pt.unmap(VirtAddr::from(BASE + vaddr)).unwrap();
+        tlb.ignore();
+        MockHandler::dealloc_frame(paddr);
+    }
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), 0);
+}
+
+#[test]
+fn read_only_tail_is_copied() {
+    MockHandler::reset();
+    let file = File::new();
+    let mut pt = PageTable::try_new().unwrap();
+    let segments = [segment(
+        &file,
+        0x1000,
+        0x1000,
+        (0x1100, 0x1200),
+        ElfSegment::PF_R,
+    )];
+    pt.map_elf_segments(segments, MappingFlags::empty())
+        .unwrap()
+        .ignore();
+    assert_eq!(page(&pt, 0x1000).0, file.paddr(0x1000));
+    let (paddr, flags, bytes) = page(&pt, 0x2000);
+    assert_ne!(paddr, file.paddr(0x2000));
+    assert_eq!(flags, MappingFlags::READ);
+    assert_eq!(bytes[..0x100], file.0[0x2000..0x2100]);
+    assert!(bytes[0x100..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn segment_within_a_page() {
+    MockHandler::reset();
+    let file = File::new();
+    let mut pt = PageTable::try_new().unwrap();
+    // Like for a shared page, the bytes before the segment come from the file.
+    let segments = [segment(&file, 0x1100, 0x3100, (0x100, 0x300), RW)];
+    pt.map_elf_segments(segments, MappingFlags::empty())
+        .unwrap()
+        .ignore();
+    let (paddr, _, bytes) = page(&pt, 0x3000);
+    assert_ne!(paddr, file.paddr(0x1000));
+    assert_eq!(bytes[..0x200], file.0[0x1000..0x1200]);
+    assert!(bytes[0x200..].iter().all(|&b| b == 0));
+    assert_eq!(pt.mapped_bytes(), 0x1000);
+}
+
+#[test]
+fn zeros_only_and_file_only() {
+    MockHandler::reset();
+    let file = File::new();
+    let mut pt = PageTable::try_new().unwrap();
+    let segments = [
+        // Without file data, the page offset of `paddr` does not matter.
+        segment(&file, 0, 0x10, (0, 0x1000), RW),
+        // The file end may share its page with the next bytes of the file.
+        segment(&file, 0x1000, 0x4000, (0x1800, 0x1800), RX),
+        // `filesz` is capped to `memsz`.
+        segment(&file, 0x3000, 0x8000, (0x1000, 0x800), RX),
+    ];
+    pt.map_elf_segments(segments, MappingFlags::empty())
+        .unwrap()
+        .ignore();
+    for vaddr in [0, 0x1000] {
+        let (_, _, bytes) = page(&pt, vaddr);
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+    assert_eq!(page(&pt, 0x4000).0, file.paddr(0x1000));
+    assert_eq!(page(&pt, 0x5000).0, file.paddr(0x2000));
+    assert_eq!(page(&pt, 0x8000).0, file.paddr(0x3000));
+    assert_eq!(pt.mapped_bytes(), 0x5000);
+}
+
+#[test]
+fn errors() {
+    MockHandler::reset();
+    let file = File::new();
+    let mut pt = PageTable::try_new().unwrap();
+    let segments = [segment(&file, 0x1000, 0x1200, (0x100, 0x2000), RW)];
+    assert_eq!(
+        pt.map_elf_segments(segments, MappingFlags::empty()).err(),
+        Some(PagingError::NotAligned)
+    );
+    assert_eq!(pt.mapped_bytes(), 0);
+
+    // The frame allocated for the page that failed is freed.
+    let frames = MockHandler::live_frames();
+    let segments = [segment(&file, 0x1000, 0x201000, (0x100, 0x1000), RW)];
+    MockHandler::fail_alloc_at(Some(3));
+    assert_eq!(
+        pt.map_elf_segments(segments, MappingFlags::empty()).err(),
+        Some(PagingError::NoMemory)
+    );
+    // Only the P3 and P2 tables are left.
+    assert_eq!(MockHandler::live_frames(), frames + 2);
+    assert_eq!(pt.table_frames(), 3);
+}
+
+thread_local! {
+    /// The frames accessed through [`Slots::with_frame_mapped`].
+    static MAPPED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// [`MockHandler`], where data frames are accessed through a copy, like in a
+/// temporary mapping that is torn down after use.
+struct Slots;
+
+impl PagingHandler for Slots {
+    fn alloc_frame() -> Option<PhysAddr> {
+        MockHandler::alloc_frame()
+    }
+
+    fn dealloc_frame(paddr: PhysAddr) {
+        MockHandler::dealloc_frame(paddr)
+    }
+
+    fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+        MockHandler::phys_to_virt(paddr)
+    }
+
+    fn with_frame_mapped<R>(paddr: PhysAddr, f: impl FnOnce(&mut [u8; PAGE_SIZE_4K]) -> R) -> R {
+        assert!(paddr.is_aligned_4k());
+        MAPPED.set(MAPPED.get() + 1);
+        let frame = MockHandler::phys_to_virt(paddr).as_mut_ptr() as *mut [u8; PAGE_SIZE_4K];
+        let mut slot = [0xaa; PAGE_SIZE_4K];
+        slot.copy_from_slice(unsafe { &*frame });
+        let result = f(&mut slot);
+        unsafe { *frame = slot };
+        result
+    }
+}
+
+#[test]
+fn frames_mapped_by_the_handler() {
+    MockHandler::reset();
+    let file = File::new();
+    let mut pt = PageTable64::<MockMetaData<X64PagingMetaData>, X64PTE, Slots>::try_new().unwrap();
+    let segments = [segment(&file, 0x2a00, 0x5a00, (0x800, 0x2000), RW)];
+    pt.map_elf_segments(segments, MappingFlags::empty())
+        .unwrap()
+        .ignore();
+    // The new frame, and the file page copied into it, then the zeroed one.
+    assert_eq!(MAPPED.get(), 3);
+    let contents = |vaddr: usize| {
+        let (paddr, ..) = pt.query(VirtAddr::from(BASE + vaddr)).unwrap();
+        let ptr = MockHandler::phys_to_virt(paddr).as_ptr();
+        unsafe { core::slice::from_raw_parts(ptr, PAGE_SIZE_4K) }
+    };
+    let bytes = contents(0x6000);
+    assert_eq!(bytes[..0x200], file.0[0x3000..0x3200]);
+    assert!(bytes[0x200..].iter().all(|&b| b == 0));
+    assert!(contents(0x7000).iter().all(|&b| b == 0));
+}
@@ -0,0 +1,112 @@
+//! Checks that every operation changing entries ends with
+//! [`PagingMetaData::fence_after_update`], whether or not the TLB is flushed.
+//!
+//! [`PagingMetaData::fence_after_update`]: page_table_multiarch::PagingMetaData::fence_after_update
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn setup() -> PageTable {
+    MockHandler::reset();
+    PageTable::try_new().unwrap()
+}
+
+#[test]
+fn single_page_operations() {
+    let mut pt = setup();
+    // New mappings need no flush on x86, but are still fenced.
+    let tlb = pt
+        .map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+        .unwrap();
+    assert!(!tlb.is_needed());
+    tlb.ignore();
+    assert_eq!(Meta::take_fences(), 1);
+
+    pt.remap(va(0), PhysAddr::from(0x2000), FLAGS)
+        .unwrap()
+        .1
+        .ignore();
+    assert_eq!(Meta::take_fences(), 1);
+    pt.protect(va(0), MappingFlags::READ).unwrap().1.ignore();
+    assert_eq!(Meta::take_fences(), 1);
+    pt.set_dirty(va(0), true).unwrap();
+    pt.set_accessed(va(0), true).unwrap();
+    assert_eq!(Meta::take_fences(), 2);
+    pt.unmap(va(0)).unwrap().2.ignore();
+    assert_eq!(Meta::take_fences(), 1);
+    pt.set_absent_token(va(0), 1).unwrap();
+    assert_eq!(Meta::take_fences(), 1);
+}
+
+#[test]
+fn region_operations() {
+    let mut pt = setup();
+    pt.map_region(
+        va(0),
+        |v| PhysAddr::from(v.as_usize()),
+        0x3000,
+        FLAGS,
+        false,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert!(Meta::take_fences() > 0);
+    pt.protect_region(va(0), 0x3000, MappingFlags::READ, false)
+        .unwrap()
+        .ignore();
+    assert!(Meta::take_fences() > 0);
+
+    let (_child, tlb) = pt.clone_cow(va(0), 0x1000).unwrap();
+    tlb.ignore();
+    assert!(Meta::take_fences() > 0);
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(VADDR), 0x1000);
+    pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap().ignore();
+    assert!(Meta::take_fences() > 0);
+    pt.unmap_region(va(0x1000), 0x2000, false).unwrap().ignore();
+    assert!(Meta::take_fences() > 0);
+
+    let mut other = setup();
+    other
+        .map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    Meta::take_fences();
+    pt.copy_from(&other, va(0), 0x1000);
+    assert_eq!(Meta::take_fences(), 1);
+    pt.clear_copy_range(va(0), 0x1000);
+    assert_eq!(Meta::take_fences(), 1);
+}
+
+#[test]
+fn no_fence_without_changes() {
+    let mut pt = setup();
+    pt.map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    Meta::take_fences();
+    assert!(pt.query(va(0)).is_ok());
+    assert!(pt.is_dirty(va(0)).is_ok());
+    assert_eq!(
+        pt.map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+            .err(),
+        Some(PagingError::AlreadyMapped)
+    );
+    assert_eq!(pt.unmap(va(0x1000)).err(), Some(PagingError::NotMapped));
+    assert_eq!(Meta::take_fences(), 0);
+}
@@ -0,0 +1,132 @@
+//! Fixed slots: a page mapped to one frame after another through an entry
+//! found once, until tables are unlinked from the page table.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const SLOT: usize = 0x4000_0000;
+const HUGE: usize = 0x8000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(paddr: usize) -> PhysAddr {
+    PhysAddr::from(paddr)
+}
+
+#[test]
+fn set_and_clear() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let slot = pt.fixed_slot(va(SLOT), RW).unwrap();
+    assert_eq!(slot.vaddr(), va(SLOT));
+    assert_eq!(pt.query(va(SLOT)), Err(PagingError::NotMapped));
+    let allocated = MockHandler::stats().allocated;
+
+    for i in 1..=64 {
+        let tlb = slot.set(&mut pt, pa(i * 0x1000), RW).unwrap();
+        // A new mapping, then one replacing the previous frame.
+        assert_eq!(tlb.is_needed(), i > 1);
+        tlb.ignore();
+        assert_eq!(
+            pt.query(va(SLOT + 0x123)),
+            Ok((pa(i * 0x1000 + 0x123), RW, PageSize::Size4K))
+        );
+    }
+    assert_eq!(pt.mapped_bytes(), 0x1000);
+    let (paddr, tlb) = slot.clear(&mut pt).unwrap();
+    assert_eq!(paddr, pa(64 * 0x1000));
+    assert!(tlb.is_needed());
+    tlb.ignore();
+    assert_eq!(pt.mapped_bytes(), 0);
+    assert_eq!(slot.clear(&mut pt).err(), Some(PagingError::NotMapped));
+    // No walk allocated anything after the slot was created.
+    assert_eq!(MockHandler::stats().allocated, allocated);
+
+    // Wider flags widen the tables above.
+    let user = RW | MappingFlags::USER;
+    slot.set(&mut pt, pa(0x1000), user).unwrap().ignore();
+    assert_eq!(pt.query(va(SLOT)).unwrap().1, user);
+
+    let result = slot.set(&mut pt, pa(0x1001), RW);
+    assert_eq!(result.err(), Some(PagingError::InvalidPaddr(pa(0x1001))));
+}
+
+#[test]
+fn existing_mapping() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(SLOT), pa(0x5000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let slot = pt.fixed_slot(va(SLOT), RW).unwrap();
+    assert_eq!(pt.query(va(SLOT)).unwrap().0, pa(0x5000));
+    let (paddr, tlb) = slot.clear(&mut pt).unwrap();
+    tlb.ignore();
+    assert_eq!(paddr, pa(0x5000));
+}
+
+#[test]
+fn stale_after_unlink() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let slot = pt.fixed_slot(va(SLOT), RW).unwrap();
+    slot.set(&mut pt, pa(0x1000), RW).unwrap().ignore();
+
+    // Unrelated tables, which the slot cannot tell from its own.
+    pt.clear_copy_range(va(0xffff_8000_0000_0000), 0x1000);
+    let result = slot.set(&mut pt, pa(0x2000), RW);
+    assert_eq!(result.err(), Some(PagingError::StaleSlot));
+    assert_eq!(slot.clear(&mut pt).err(), Some(PagingError::StaleSlot));
+    assert_eq!(
+        MockHandler::take_errors(),
+        [
+            ("set_fixed_slot", SLOT, PagingError::StaleSlot),
+            ("clear_fixed_slot", SLOT, PagingError::StaleSlot),
+        ]
+    );
+
+    let slot = pt.fixed_slot(va(SLOT), RW).unwrap();
+    let (paddr, tlb) = slot.clear(&mut pt).unwrap();
+    tlb.ignore();
+    assert_eq!(paddr, pa(0x1000));
+}
+
+#[test]
+fn errors() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(HUGE), pa(0x20_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pt.fixed_slot(va(HUGE + 0x1000), RW).err(),
+        Some(PagingError::MappedToHugePage {
+            vaddr: HUGE,
+            level: 2
+        })
+    );
+    assert_eq!(
+        pt.fixed_slot(va(SLOT + 0x10), RW).err(),
+        Some(PagingError::NotAligned)
+    );
+}
+
+#[test]
+#[should_panic(expected = "not the page table of the slot")]
+fn other_page_table() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let slot = pt.fixed_slot(va(SLOT), RW).unwrap();
+    let mut other = PageTable::try_new().unwrap();
+    let _ = slot.set(&mut other, pa(0x1000), RW);
+}
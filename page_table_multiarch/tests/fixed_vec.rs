@@ -0,0 +1,96 @@
+//! The lists returned in buffers provided by the caller, which report the
+//! length they need when they are too small.
+
+use std::cell::Cell;
+use std::mem::MaybeUninit;
+
+use page_table_multiarch::{FixedVec, PagingError, PagingResult, collect_vec};
+
+/// A list operation returning `0..count`, and `count`.
+fn fill(out: &mut FixedVec<'_, usize>, count: usize) -> PagingResult<usize> {
+    for i in 0..count {
+        out.push(i);
+    }
+    out.check()?;
+    Ok(count)
+}
+
+#[test]
+fn exact_fit() {
+    let mut buf = [MaybeUninit::uninit(); 4];
+    let mut out = FixedVec::new(&mut buf);
+    assert_eq!(fill(&mut out, 4), Ok(4));
+    assert_eq!(*out, [0, 1, 2, 3]);
+    assert_eq!((out.len(), out.capacity(), out.needed()), (4, 4, 4));
+}
+
+#[test]
+fn overflow() {
+    let mut buf = [MaybeUninit::uninit(); 4];
+    let mut out = FixedVec::new(&mut buf);
+    assert_eq!(
+        fill(&mut out, 6),
+        Err(PagingError::BufferTooSmall { needed: 6 })
+    );
+    // The elements that fit are kept.
+    assert_eq!(*out, [0, 1, 2, 3]);
+    assert!(!out.push(6));
+    assert_eq!(out.needed(), 7);
+
+    out.clear();
+    assert!(out.is_empty());
+    assert_eq!(fill(&mut out, 2), Ok(2));
+    assert_eq!(format!("{out:?}"), "[0, 1]");
+}
+
+#[test]
+fn zero_capacity() {
+    let mut out = FixedVec::<usize>::new(&mut []);
+    assert_eq!(fill(&mut out, 0), Ok(0));
+    assert_eq!(
+        fill(&mut out, 3),
+        Err(PagingError::BufferTooSmall { needed: 3 })
+    );
+    assert!(out.is_empty());
+}
+
+#[test]
+fn drops_elements() {
+    let drops = Cell::new(0);
+    struct Counted<'a>(&'a Cell<usize>);
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    let mut buf = [const { MaybeUninit::uninit() }; 2];
+    let mut out = FixedVec::new(&mut buf);
+    for _ in 0..3 {
+        out.push(Counted(&drops));
+    }
+    // The one that did not fit.
+    assert_eq!(drops.get(), 1);
+    drop(out);
+    assert_eq!(drops.get(), 3);
+}
+
+#[test]
+fn collected() {
+    let calls = Cell::new(0);
+    let (vec, count) = collect_vec(|out| {
+        calls.set(calls.get() + 1);
+        fill(out, 5)
+    })
+    .unwrap();
+    assert_eq!((vec, count), (vec![0, 1, 2, 3, 4], 5));
+    // A first run to count the elements.
+    assert_eq!(calls.get(), 2);
+
+    let (vec, _) = collect_vec(|out| fill(out, 0)).unwrap();
+    assert!(vec.is_empty());
+    assert_eq!(
+        collect_vec::<usize, ()>(|_| Err(PagingError::NotMapped)),
+        Err(PagingError::NotMapped)
+    );
+}
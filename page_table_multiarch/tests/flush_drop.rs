@@ -0,0 +1,127 @@
+//! Checks merging flushes, and dropping them unused with and without the
+//! `debug-flush` feature.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// Maps 4K pages at the offsets `offs` from `VADDR`.
+fn mapped(offs: &[usize]) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for &off in offs {
+        pt.map(va(off), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+            .unwrap()
+            .ignore();
+    }
+    Meta::take_flushes();
+    pt
+}
+
+#[test]
+fn merge_covers_both() {
+    let mut pt = mapped(&[0, 0x1000, 0x3000]);
+    let (.., high) = pt.unmap(va(0x3000)).unwrap();
+    let (.., low) = pt.unmap(va(0)).unwrap();
+    let generation = low.generation();
+    let tlb = high.merge(low);
+    assert!(tlb.is_needed());
+    assert_eq!(tlb.generation(), generation);
+    tlb.flush();
+    let pages = [0, 0x1000, 0x2000, 0x3000].map(|off| Some(va(off)));
+    assert_eq!(Meta::take_flushes(), pages);
+}
+
+#[test]
+fn merge_with_new_mapping() {
+    let mut pt = mapped(&[0]);
+    // The TLB cannot cache the missing mapping on x86.
+    let new = pt
+        .map(va(0x5000), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+        .unwrap();
+    assert!(!new.is_needed());
+    let (.., old) = pt.unmap(va(0)).unwrap();
+    new.merge(old).flush();
+    assert_eq!(Meta::take_flushes(), [Some(va(0))]);
+
+    let a = pt.map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS);
+    let b = pt.map(va(0x1000), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS);
+    let tlb = a.unwrap().merge(b.unwrap());
+    assert!(!tlb.is_needed());
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), []);
+}
+
+#[test]
+fn merge_all() {
+    let mut pt = mapped(&[0]);
+    let new = pt
+        .map_region(
+            va(0x1000),
+            |_| PhysAddr::from(0x1000),
+            0x1000,
+            FLAGS,
+            false,
+            false,
+        )
+        .unwrap();
+    assert!(!new.is_needed());
+    let old = pt.unmap_region(va(0), 0x1000, false).unwrap();
+    let generation = old.generation();
+    let tlb = old.merge(new);
+    assert!(tlb.is_needed());
+    assert_eq!(tlb.generation(), generation);
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+}
+
+#[test]
+#[cfg(not(feature = "debug-flush"))]
+fn drop_skips_flush() {
+    let mut pt = mapped(&[0, 0x1000]);
+    drop(pt.unmap(va(0)).unwrap());
+    drop(pt.unmap_region(va(0x1000), 0x1000, false).unwrap());
+    assert_eq!(Meta::take_flushes(), []);
+}
+
+#[test]
+#[cfg(feature = "debug-flush")]
+fn used_flushes_do_not_panic() {
+    let mut pt = mapped(&[0, 0x1000, 0x2000]);
+    pt.protect_region(va(0), 0x3000, FLAGS, false)
+        .unwrap()
+        .flush_all();
+    pt.unmap(va(0)).unwrap().2.flush();
+    pt.unmap(va(0x1000)).unwrap().2.ignore();
+    pt.unmap(va(0x2000)).unwrap().2.flush_if_current(&pt);
+}
+
+#[test]
+#[cfg(feature = "debug-flush")]
+#[should_panic(expected = "without calling `flush` or `ignore`")]
+fn drop_panics() {
+    let mut pt = mapped(&[0]);
+    drop(pt.unmap(va(0)).unwrap());
+}
+
+#[test]
+#[cfg(feature = "debug-flush")]
+#[should_panic(expected = "without calling `flush_all` or `ignore`")]
+fn drop_all_panics() {
+    let mut pt = mapped(&[0]);
+    let _ = pt.unmap_region(va(0), 0x1000, false);
+}
@@ -0,0 +1,107 @@
+//! Checks the pages reported by the flushes of region operations, and the
+//! choice between flushing them one by one or the entire TLB.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PagingMetaData, RegionCursor, StepStatus};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(vaddr: VirtAddr) -> PhysAddr {
+    PhysAddr::from(vaddr.as_usize() - VADDR)
+}
+
+/// Maps `size` bytes from [`VADDR`] with `pages` pages.
+fn mapped(size: usize, allow_huge: bool, pages: usize) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let tlb = pt
+        .map_region(va(0), pa, size, FLAGS, allow_huge, false)
+        .unwrap();
+    // New mappings need no flush on x86.
+    assert_eq!(tlb.pages(), Some(pages));
+    tlb.flush_with_threshold(usize::MAX);
+    assert!(Meta::take_flushes().is_empty());
+    pt
+}
+
+#[test]
+fn pages_or_all() {
+    let threshold = Meta::FLUSH_PAGES_THRESHOLD;
+    assert_eq!(threshold, 33);
+    let mut pt = mapped(0x2_0000, false, 32);
+    let tlb = pt
+        .protect_region(va(0), 0x2_0000, MappingFlags::READ, false)
+        .unwrap();
+    assert_eq!((tlb.pages(), tlb.contiguous_ranges()), (Some(32), Some(1)));
+    tlb.flush_with_threshold(threshold);
+    let pages: Vec<_> = (0..32).map(|i| Some(va(i * 0x1000))).collect();
+    assert_eq!(Meta::take_flushes(), pages);
+
+    let tlb = pt.unmap_region(va(0), 0x2_0000, false).unwrap();
+    tlb.flush_with_threshold(31);
+    assert_eq!(Meta::take_flushes(), [None]);
+}
+
+#[test]
+fn huge_pages() {
+    // A 2M page and a 4K page: one flush per 4K.
+    let mut pt = mapped(0x20_1000, true, 2);
+    let tlb = pt.unmap_region(va(0), 0x20_1000, false).unwrap();
+    assert_eq!(tlb.pages(), Some(2));
+    tlb.flush_with_threshold(513);
+    assert_eq!(Meta::take_flushes().len(), 513);
+
+    let mut pt = mapped(0x40_0000, true, 2);
+    let tlb = pt.unmap_region(va(0), 0x40_0000, false).unwrap();
+    assert_eq!(tlb.pages(), Some(2));
+    tlb.flush_with_threshold(2);
+    assert_eq!(Meta::take_flushes(), [Some(va(0)), Some(va(0x20_0000))]);
+}
+
+#[test]
+fn merged_flushes() {
+    let mut pt = mapped(0x4_0000, false, 64);
+    // The steps of one region are contiguous.
+    let mut cursor = RegionCursor::new(va(0), 0x1_0000);
+    let mut tlb = pt.unmap_region_step(&mut cursor, 4).unwrap().1;
+    loop {
+        let (status, step) = pt.unmap_region_step(&mut cursor, 4).unwrap();
+        tlb = tlb.merge(step);
+        if status == StepStatus::Done {
+            break;
+        }
+    }
+    assert_eq!((tlb.pages(), tlb.contiguous_ranges()), (Some(16), Some(1)));
+
+    // Another range, apart.
+    let other = pt.unmap_region(va(0x3_0000), 0x1_0000, false).unwrap();
+    let tlb = tlb.merge(other);
+    assert_eq!((tlb.pages(), tlb.contiguous_ranges()), (Some(32), Some(2)));
+    // Flushed every 4K from the first to the last page, gap included.
+    tlb.flush_with_threshold(64);
+    assert_eq!(Meta::take_flushes().len(), 64);
+
+    // The pages of other operations are not known.
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(0x2_0000), 0x1000);
+    let unknown = pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap();
+    assert_eq!(unknown.pages(), None);
+    let empty = pt.unmap_region(va(0), 0, false).unwrap();
+    assert_eq!(empty.pages(), Some(0));
+    let tlb = empty.merge(unknown);
+    assert_eq!(tlb.contiguous_ranges(), None);
+    tlb.flush_with_threshold(usize::MAX);
+    assert_eq!(Meta::take_flushes(), [None]);
+}
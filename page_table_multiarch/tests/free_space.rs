@@ -0,0 +1,129 @@
+//! The room left for new mappings: from an address, bounded by the next used
+//! entry, the end of the half of the address space and the quota, and the
+//! largest hole of a region.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+const LOWER_END: usize = 0x8000_0000_0000;
+const UPPER_START: usize = 0xffff_8000_0000_0000;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// 4K pages at 0 and 0x3000, and a 2M page at 2M.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for (off, size) in [
+        (0, PageSize::Size4K),
+        (0x3000, PageSize::Size4K),
+        (SIZE_2M, PageSize::Size2M),
+    ] {
+        pt.map(va(off), PhysAddr::from(0x4000_0000 + off), size, RW)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+#[test]
+fn mappable() {
+    let mut pt = mapped();
+    assert_eq!(pt.max_mappable_at(va(0x1000)), 0x2000);
+    assert_eq!(pt.max_mappable_at(va(0x4000)), SIZE_2M - 0x4000);
+    assert_eq!(pt.max_mappable_at(va(0x3000)), 0);
+    assert_eq!(pt.max_mappable_at(va(SIZE_2M + 0x1000)), 0);
+    // Up to the end of the half.
+    assert_eq!(
+        pt.max_mappable_at(va(2 * SIZE_2M)),
+        LOWER_END - VADDR - 2 * SIZE_2M
+    );
+    assert_eq!(
+        pt.max_mappable_at(VirtAddr::from(LOWER_END - 0x1000)),
+        0x1000
+    );
+    assert_eq!(pt.max_mappable_at(VirtAddr::from(UPPER_START)), 1 << 47);
+    assert_eq!(
+        pt.max_mappable_at(VirtAddr::from(usize::MAX - 0xfff)),
+        0x1000
+    );
+
+    // Entries carrying a payload are used.
+    pt.set_absent_token(va(0x2000), 7).unwrap();
+    assert_eq!(pt.max_mappable_at(va(0x1000)), 0x1000);
+
+    assert_eq!(pt.max_mappable_at(va(0x1800)), 0);
+    assert_eq!(pt.max_mappable_at(VirtAddr::from(LOWER_END)), 0);
+}
+
+#[test]
+fn mappable_under_quota() {
+    let mut pt = mapped();
+    pt.set_limits(pt.mapped_bytes() + 0x2800, usize::MAX);
+    assert_eq!(pt.max_mappable_at(va(0x4000)), 0x2000);
+    assert_eq!(pt.max_mappable_at(va(0x1000)), 0x2000);
+    pt.set_limits(pt.mapped_bytes(), usize::MAX);
+    assert_eq!(pt.max_mappable_at(va(0x4000)), 0);
+}
+
+#[test]
+fn largest_gap() {
+    let pt = mapped();
+    assert_eq!(
+        pt.max_free_gap(va(0), 2 * SIZE_2M),
+        Ok((va(0x4000), SIZE_2M - 0x4000))
+    );
+    assert_eq!(pt.max_free_gap(va(0), 0x4000), Ok((va(0x1000), 0x2000)));
+    // The first of the largest holes.
+    assert_eq!(
+        pt.max_free_gap(va(0x1000), 0x5000),
+        Ok((va(0x1000), 0x2000))
+    );
+    // Inside the huge page, and after it.
+    assert_eq!(
+        pt.max_free_gap(va(SIZE_2M + 0x1000), 0x2000),
+        Ok((va(SIZE_2M + 0x1000), 0))
+    );
+    assert_eq!(
+        pt.max_free_gap(va(SIZE_2M), 2 * SIZE_2M),
+        Ok((va(2 * SIZE_2M), SIZE_2M))
+    );
+    assert_eq!(pt.max_free_gap(va(0), 0), Ok((va(0), 0)));
+}
+
+#[test]
+fn gap_at_the_top() {
+    let pt = mapped();
+    let start = VirtAddr::from(usize::MAX - SIZE_2M + 1);
+    assert_eq!(pt.max_free_gap(start, SIZE_2M), Ok((start, SIZE_2M)));
+    assert_eq!(
+        pt.max_free_gap(VirtAddr::from(UPPER_START), 1 << 47),
+        Ok((VirtAddr::from(UPPER_START), 1 << 47))
+    );
+}
+
+#[test]
+fn gap_errors() {
+    let pt = mapped();
+    assert_eq!(
+        pt.max_free_gap(va(0x800), 0x1000),
+        Err(PagingError::NotAligned)
+    );
+    assert_eq!(pt.max_free_gap(va(0), 0x800), Err(PagingError::NotAligned));
+    assert_eq!(
+        pt.max_free_gap(VirtAddr::from(LOWER_END - 0x1000), 0x2000),
+        Err(PagingError::InvalidVaddr(LOWER_END - 0x1000))
+    );
+}
@@ -0,0 +1,262 @@
+//! The harness of the fuzz target in `fuzz/`: the decoding of its inputs,
+//! its seeds, and arbitrary inputs replayed on every entry format.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::VirtAddr;
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::fuzz::{self, FLAGS, Op, OpDecoder, WINDOW_PAGES};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+use proptest::prelude::*;
+
+const R: MappingFlags = MappingFlags::READ;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RWU: MappingFlags = RW.union(MappingFlags::USER);
+/// The pages of a 2M page.
+const HUGE: usize = 512;
+
+/// Runs an input of the fuzz target: a byte selecting the format, then the
+/// operations.
+fn run_target(data: &[u8]) {
+    let Some((&format, ops)) = data.split_first() else {
+        return;
+    };
+    match format % 4 {
+        0 => fuzz::run::<X64PagingMetaData, X64PTE>(ops),
+        1 => fuzz::run::<A64PagingMetaData, A64PTE>(ops),
+        2 => fuzz::run::<Sv39MetaData<VirtAddr>, Rv64PTE>(ops),
+        _ => fuzz::run::<LA64MetaData, LA64PTE>(ops),
+    }
+}
+
+/// A huge page made copy-on-write and split by a fault, then unmapped and
+/// mapped again; and a group with the contiguous hint broken up, then formed
+/// again.
+fn split_then_merge() -> Vec<Op> {
+    vec![
+        Op::MapRegion {
+            page: 0,
+            pages: HUGE,
+            flags: RW,
+            allow_huge: true,
+        },
+        Op::CloneCow {
+            page: 0,
+            pages: HUGE,
+        },
+        Op::CowFault { page: 3 },
+        Op::UnmapRegion {
+            page: 0,
+            pages: HUGE,
+        },
+        // Refused: the table of the split stays.
+        Op::MapRegion {
+            page: 0,
+            pages: HUGE,
+            flags: RW,
+            allow_huge: true,
+        },
+        Op::MapRegion {
+            page: HUGE + 16,
+            pages: 16,
+            flags: RW,
+            allow_huge: true,
+        },
+        Op::Protect {
+            page: HUGE + 20,
+            flags: R,
+        },
+        Op::UnmapRegion {
+            page: HUGE + 16,
+            pages: 16,
+        },
+        Op::MapRegion {
+            page: HUGE + 16,
+            pages: 16,
+            flags: RW,
+            allow_huge: true,
+        },
+        Op::Map {
+            page: 2 * HUGE,
+            size: PageSize::Size2M,
+            flags: RW,
+        },
+    ]
+}
+
+/// Copy-on-write pages of every size, protected before and after the faults.
+fn cow_then_protect() -> Vec<Op> {
+    vec![
+        Op::Map {
+            page: 0,
+            size: PageSize::Size4K,
+            flags: RW,
+        },
+        Op::MapRegion {
+            page: 32,
+            pages: 16,
+            flags: RW,
+            allow_huge: true,
+        },
+        Op::Map {
+            page: HUGE,
+            size: PageSize::Size2M,
+            flags: RWU,
+        },
+        Op::CloneCow {
+            page: 0,
+            pages: 2 * HUGE,
+        },
+        // No longer copy-on-write.
+        Op::Protect { page: 0, flags: RW },
+        Op::CowFault { page: 0 },
+        Op::CowFault { page: 36 },
+        Op::Protect { page: 37, flags: R },
+        Op::CowFault { page: 37 },
+        Op::CowFault { page: HUGE + 100 },
+        Op::ProtectRegion {
+            page: HUGE,
+            pages: HUGE,
+            flags: R,
+        },
+        Op::CloneCow {
+            page: 32,
+            pages: 16,
+        },
+        Op::CowFault { page: 40 },
+    ]
+}
+
+/// Region operations over each other, holes and huge pages.
+fn overlapping_regions() -> Vec<Op> {
+    vec![
+        Op::MapRegion {
+            page: 0,
+            pages: HUGE + 88,
+            flags: RW,
+            allow_huge: true,
+        },
+        Op::MapRegion {
+            page: 256,
+            pages: HUGE,
+            flags: R,
+            allow_huge: true,
+        },
+        // In the middle of the huge page.
+        Op::UnmapRegion {
+            page: 500,
+            pages: 200,
+        },
+        // Stops at the hole after the 4K pages.
+        Op::ProtectRegion {
+            page: HUGE,
+            pages: 100,
+            flags: FLAGS[2],
+        },
+        Op::UnmapRegion {
+            page: 0,
+            pages: HUGE,
+        },
+        Op::MapRegion {
+            page: 100,
+            pages: 50,
+            flags: RWU,
+            allow_huge: false,
+        },
+        Op::CloneCow {
+            page: 50,
+            pages: 950,
+        },
+        Op::UnmapRegion {
+            page: 0,
+            pages: WINDOW_PAGES,
+        },
+        Op::MapRegion {
+            page: 2 * HUGE,
+            pages: HUGE,
+            flags: RW,
+            allow_huge: true,
+        },
+        // Skipped: it ends in the huge page.
+        Op::ProtectRegion {
+            page: 2 * HUGE,
+            pages: 100,
+            flags: R,
+        },
+        Op::UnmapRegion {
+            page: 2 * HUGE,
+            pages: HUGE,
+        },
+    ]
+}
+
+/// The seeds in `fuzz/corpus/page_table_ops`, with the format they select.
+fn seeds() -> [(&'static str, u8, Vec<Op>); 3] {
+    [
+        ("split_then_merge", 1, split_then_merge()),
+        ("cow_then_protect", 0, cow_then_protect()),
+        ("overlapping_regions", 3, overlapping_regions()),
+    ]
+}
+
+fn read_seed(name: &str) -> Vec<u8> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../fuzz/corpus/page_table_ops");
+    std::fs::read(format!("{dir}/{name}")).unwrap()
+}
+
+#[test]
+fn seeds_decode() {
+    for (name, format, ops) in seeds() {
+        let data = read_seed(name);
+        assert_eq!(data[0], format, "{name}");
+        assert_eq!(data[1..], fuzz::encode(&ops), "{name}");
+        assert_eq!(
+            OpDecoder::new(&data[1..]).collect::<Vec<_>>(),
+            ops,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn seeds_on_every_format() {
+    for (name, ..) in seeds() {
+        let mut data = read_seed(name);
+        for format in 0..4 {
+            data[0] = format;
+            run_target(&data);
+        }
+    }
+}
+
+#[test]
+fn decoding_clamps() {
+    // A page and a number of pages out of the window, and flags out of the
+    // table.
+    let data = [5, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(
+        OpDecoder::new(&data).collect::<Vec<_>>(),
+        [Op::ProtectRegion {
+            page: WINDOW_PAGES - 1,
+            pages: 1,
+            flags: FLAGS[0xff % FLAGS.len()],
+        }]
+    );
+    // Cut short.
+    assert_eq!(OpDecoder::new(&data[..5]).count(), 0);
+    assert_eq!(
+        OpDecoder::new(&[8 + 7, 2, 0]).next(),
+        Some(Op::CowFault { page: 2 })
+    );
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_inputs(data in prop::collection::vec(any::<u8>(), 0..512)) {
+        run_target(&data);
+    }
+}
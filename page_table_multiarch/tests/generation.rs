@@ -0,0 +1,78 @@
+//! Checks the generation counter and the deferred flushes that depend on it.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const BASE: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn vaddr(i: usize) -> VirtAddr {
+    VirtAddr::from(BASE + i * PageSize::Size4K as usize)
+}
+
+fn map(pt: &mut PageTable, i: usize) {
+    let paddr = PhysAddr::from(0x1000 * (i + 1));
+    pt.map(vaddr(i), paddr, PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+}
+
+#[test]
+fn bumped_by_changes_that_need_a_flush() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    assert_eq!(pt.generation(), 0);
+
+    // New mappings need no flush on x86_64.
+    map(&mut pt, 0);
+    map(&mut pt, 1);
+    assert_eq!(pt.generation(), 0);
+
+    let (_, tlb) = pt.protect(vaddr(0), MappingFlags::READ).unwrap();
+    assert_eq!((tlb.generation(), pt.generation()), (1, 1));
+    tlb.ignore();
+    let (_, _, tlb) = pt.unmap(vaddr(0)).unwrap();
+    assert_eq!((tlb.generation(), pt.generation()), (2, 2));
+    tlb.ignore();
+    let tlb = pt.unmap_region(vaddr(1), 0x1000, false).unwrap();
+    assert_eq!((tlb.generation(), pt.generation()), (3, 3));
+    tlb.ignore();
+
+    // Failed operations change nothing.
+    assert!(pt.unmap(vaddr(0)).is_err());
+    assert_eq!(pt.generation(), 3);
+    assert_eq!(Meta::take_flushes(), []);
+}
+
+#[test]
+fn deferred_flushes_skipped_after_full_flush() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, 0);
+    map(&mut pt, 1);
+    let (_, _, first) = pt.unmap(vaddr(0)).unwrap();
+    let second = pt.protect_region(vaddr(1), 0x1000, FLAGS, false).unwrap();
+
+    pt.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+    first.flush_if_current(&pt);
+    second.flush_if_current(&pt);
+    assert_eq!(Meta::take_flushes(), []);
+
+    // Changes after the full flush are still flushed.
+    let (_, _, third) = pt.unmap(vaddr(1)).unwrap();
+    third.flush_if_current(&pt);
+    assert_eq!(Meta::take_flushes(), [Some(vaddr(1))]);
+    map(&mut pt, 2);
+    let tlb = pt.protect_region(vaddr(2), 0x1000, FLAGS, false).unwrap();
+    tlb.flush_if_current(&pt);
+    assert_eq!(Meta::take_flushes(), [None]);
+}
@@ -0,0 +1,111 @@
+//! Checks that the flushes of changes to global x86 mappings are marked, and
+//! escalate to a flush of the global entries.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::{PTF, X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingHandler};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const KERNEL: usize = 0xffff_8000_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// Maps 4K pages at `KERNEL` and the next page, the first one global.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for i in 0..2 {
+        let vaddr = VirtAddr::from(KERNEL + i * 0x1000);
+        let paddr = PhysAddr::from(0x10_0000 + i * 0x1000);
+        pt.map(vaddr, paddr, PageSize::Size4K, FLAGS)
+            .unwrap()
+            .ignore();
+    }
+    // Find the P1 table to set the bit, as the walk only passes copies.
+    let table = Cell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, _, entry: &X64PTE| {
+            if level == 2 {
+                table.set(Some(entry.paddr()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let p1 = MockHandler::phys_to_virt(table.get().unwrap()).as_mut_ptr() as *mut X64PTE;
+    unsafe {
+        let flags = PTF::from_bits_truncate((*p1).bits() as u64);
+        (*p1).set_flags_arch(flags | PTF::GLOBAL);
+        assert!((*p1).is_global() && !(*p1.add(1)).is_global());
+    }
+    Meta::take_flushes();
+    pt
+}
+
+#[test]
+fn unmap_and_protect() {
+    let mut pt = mapped();
+    let (_, tlb) = pt.protect(VirtAddr::from(KERNEL), FLAGS).unwrap();
+    assert!(tlb.is_global());
+    // A flush by address is enough: it reaches global entries.
+    tlb.flush();
+    assert_eq!(Meta::take_flushes(), [Some(VirtAddr::from(KERNEL))]);
+    assert_eq!(Meta::take_global_flushes(), 0);
+
+    let (_, tlb) = pt.protect(VirtAddr::from(KERNEL + 0x1000), FLAGS).unwrap();
+    assert!(!tlb.is_global());
+    tlb.ignore();
+    let (_, _, tlb) = pt.unmap(VirtAddr::from(KERNEL + 0x1000)).unwrap();
+    assert!(!tlb.is_global());
+    tlb.ignore();
+}
+
+#[test]
+fn unmap_global() {
+    let mut pt = mapped();
+    let (_, _, tlb) = pt.unmap(VirtAddr::from(KERNEL)).unwrap();
+    assert!(tlb.is_global());
+    tlb.ignore();
+}
+
+#[test]
+fn regions_escalate() {
+    let mut pt = mapped();
+    let tlb = pt
+        .protect_region(VirtAddr::from(KERNEL + 0x1000), 0x1000, FLAGS, false)
+        .unwrap();
+    assert!(!tlb.is_global());
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+
+    let tlb = pt
+        .unmap_region(VirtAddr::from(KERNEL), 0x2000, false)
+        .unwrap();
+    assert!(tlb.is_global());
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), []);
+    assert_eq!(Meta::take_global_flushes(), 1);
+}
+
+#[test]
+fn flush_all_escalates_once() {
+    let mut pt = mapped();
+    let (_, _, tlb) = pt.unmap(VirtAddr::from(KERNEL)).unwrap();
+    tlb.ignore();
+    pt.flush_all();
+    assert_eq!(Meta::take_global_flushes(), 1);
+    // The global mapping has been flushed already.
+    let (_, _, tlb) = pt.unmap(VirtAddr::from(KERNEL + 0x1000)).unwrap();
+    tlb.ignore();
+    pt.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+    assert_eq!(Meta::take_global_flushes(), 0);
+}
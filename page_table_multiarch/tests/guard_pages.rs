@@ -0,0 +1,138 @@
+//! Allocations surrounded by guard pages, which are recorded in the entries
+//! themselves and refuse to be mapped until the allocation is unmapped.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AbsentEntry, AllocOptions, MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const VADDR: usize = 0x40_0000_0000;
+/// The start of the allocations, after room for two guard pages.
+const ALLOC: usize = VADDR + 0x2000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+/// A 3-page allocation at [`ALLOC`] with two guard pages before it and one
+/// after it.
+fn allocated() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let options = AllocOptions::new().guarded(2, 1);
+    let (_, tlb) = pt.map_alloc_with(va(ALLOC), 0x3000, RW, options).unwrap();
+    tlb.ignore();
+    pt
+}
+
+fn guard_of(pt: &PageTable, vaddr: usize) -> Option<usize> {
+    match pt.query(va(vaddr)) {
+        Err(PagingError::Absent(AbsentEntry::Guard(owner))) => Some(owner as usize * 0x1000),
+        _ => None,
+    }
+}
+
+#[test]
+fn guards_around_the_allocation() {
+    let pt = allocated();
+    for vaddr in [VADDR, VADDR + 0x1000, ALLOC + 0x3000] {
+        assert_eq!(guard_of(&pt, vaddr), Some(ALLOC), "{vaddr:#x}");
+    }
+    for vaddr in [ALLOC, ALLOC + 0x2000] {
+        assert_eq!(pt.query(va(vaddr)).unwrap().1, RW);
+    }
+    assert_eq!(pt.query(va(ALLOC + 0x4000)), Err(PagingError::NotMapped));
+    // The guard pages are not free.
+    assert_eq!(pt.max_mappable_at(va(VADDR)), 0);
+    assert_eq!(
+        pt.max_free_gap(va(VADDR), 0x8000),
+        Ok((va(ALLOC + 0x4000), 0x2000))
+    );
+}
+
+#[test]
+fn guards_refuse_mappings() {
+    let mut pt = allocated();
+    let guarded = Err(PagingError::Guarded(ALLOC));
+    let paddr = PhysAddr::from(0x8000_0000);
+    assert_eq!(
+        pt.map(va(VADDR), paddr, PageSize::Size4K, RW)
+            .map(|tlb| tlb.ignore()),
+        guarded
+    );
+    assert_eq!(pt.set_absent_token(va(ALLOC + 0x3000), 1), guarded);
+    // Another allocation cannot take them, neither as pages nor as guards.
+    assert_eq!(
+        pt.map_alloc(va(ALLOC + 0x3000), 0x1000, RW, false)
+            .map(|(huge, tlb)| (huge, tlb.ignore())),
+        Err(PagingError::Guarded(ALLOC))
+    );
+    let options = AllocOptions::new().guarded(1, 0);
+    assert_eq!(
+        pt.map_alloc_with(va(ALLOC + 0x4000), 0x1000, RW, options)
+            .map(|(huge, tlb)| (huge, tlb.ignore())),
+        Err(PagingError::Guarded(ALLOC))
+    );
+    assert_eq!(guard_of(&pt, ALLOC + 0x3000), Some(ALLOC));
+    assert_eq!(pt.query(va(ALLOC + 0x4000)), Err(PagingError::NotMapped));
+}
+
+#[test]
+fn guards_must_be_free() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let paddr = PhysAddr::from(0x8000_0000);
+    pt.map(va(VADDR), paddr, PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let live = MockHandler::live_frames();
+    let options = AllocOptions::new().guarded(2, 0);
+    assert_eq!(
+        pt.map_alloc_with(va(ALLOC), 0x1000, RW, options)
+            .map(|(huge, tlb)| (huge, tlb.ignore())),
+        Err(PagingError::AlreadyMapped)
+    );
+    assert_eq!(MockHandler::live_frames(), live);
+    assert_eq!(pt.query(va(ALLOC)), Err(PagingError::NotMapped));
+    assert_eq!(pt.query(va(VADDR + 0x1000)), Err(PagingError::NotMapped));
+
+    // Nor can they leave the half of the address space.
+    assert_eq!(
+        pt.map_alloc_with(va(0), 0x1000, RW, options)
+            .map(|(huge, tlb)| (huge, tlb.ignore())),
+        Err(PagingError::InvalidVaddr(0))
+    );
+}
+
+#[test]
+fn unmap_clears_the_guards() {
+    let mut pt = allocated();
+    pt.unmap_region(va(ALLOC), 0x3000, false).unwrap().ignore();
+    for vaddr in (VADDR..ALLOC + 0x4000).step_by(0x1000) {
+        assert_eq!(pt.query(va(vaddr)), Err(PagingError::NotMapped));
+    }
+    assert_eq!(pt.max_free_gap(va(VADDR), 0x8000), Ok((va(VADDR), 0x8000)));
+    let paddr = PhysAddr::from(0x8000_0000);
+    pt.map(va(VADDR), paddr, PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+}
+
+#[test]
+fn unmap_elsewhere_keeps_the_guards() {
+    let mut pt = allocated();
+    // Only the guards of an allocation starting at the region are cleared.
+    pt.unmap_region(va(ALLOC + 0x1000), 0x2000, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(guard_of(&pt, ALLOC + 0x3000), Some(ALLOC));
+    pt.unmap_region(va(ALLOC), 0x1000, false).unwrap().ignore();
+    assert_eq!(guard_of(&pt, VADDR), None);
+    assert_eq!(guard_of(&pt, ALLOC + 0x3000), Some(ALLOC));
+}
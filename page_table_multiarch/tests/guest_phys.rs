@@ -0,0 +1,65 @@
+//! Checks G-stage page tables translating [`GuestPhysAddr`]s, whose addresses
+//! are not sign-extended like the virtual ones.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::riscv::Rv64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::{GuestPhysAddr, MappingFlags, PageSize, PagingMetaData};
+
+type GuestPageTable = MockPageTable<Sv39MetaData<GuestPhysAddr>, Rv64PTE>;
+type HostPageTable = MockPageTable<Sv39MetaData<VirtAddr>, Rv64PTE>;
+
+const FLAGS: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::USER);
+
+/// Unmaps the frame at `paddr`, and returns the addresses it was mapped at.
+fn unmap_frame<M: PagingMetaData>(pt: &mut MockPageTable<M, Rv64PTE>, paddr: usize) -> Vec<usize> {
+    let mut vaddrs = Vec::new();
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(paddr), 0x1000);
+    pt.unmap_paddr_range(paddrs, |vaddr, _| vaddrs.push(vaddr.into()))
+        .unwrap()
+        .ignore();
+    vaddrs
+}
+
+#[test]
+fn upper_guest_memory() {
+    MockHandler::reset();
+    let mut pt = GuestPageTable::try_new().unwrap();
+    // Guest memory from 256G, where bit 38 is set.
+    let gpa = GuestPhysAddr::from(0x40_0000_0000);
+    pt.map(gpa, PhysAddr::from(0x8000_0000), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .flush();
+    assert_eq!(
+        MockMetaData::<Sv39MetaData<GuestPhysAddr>>::take_flushes(),
+        [Some(gpa)]
+    );
+    assert_eq!(pt.query(gpa).unwrap().0, PhysAddr::from(0x8000_0000));
+    assert_eq!(unmap_frame(&mut pt, 0x8000_0000), [0x40_0000_0000]);
+
+    // The same entry in a page table of virtual addresses is in the upper
+    // half.
+    let mut pt = HostPageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(0xffff_ffc0_0000_0000);
+    pt.map(vaddr, PhysAddr::from(0x8000_0000), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    assert_eq!(unmap_frame(&mut pt, 0x8000_0000), [0xffff_ffc0_0000_0000]);
+}
+
+#[test]
+fn valid_addresses() {
+    type Guest = Sv39MetaData<GuestPhysAddr>;
+    type Host = Sv39MetaData<VirtAddr>;
+    assert!(Guest::vaddr_is_valid(0x7f_ffff_f000));
+    assert!(!Guest::vaddr_is_valid(0xffff_ffc0_0000_0000));
+    assert!(!Guest::vaddr_is_valid(0x80_0000_0000));
+    assert!(!Host::vaddr_is_valid(0x7f_ffff_f000));
+    assert!(Host::vaddr_is_valid(0xffff_ffc0_0000_0000));
+    assert!(Host::vaddr_is_valid(0x3f_ffff_f000));
+}
@@ -0,0 +1,85 @@
+//! Checks that the accessed and dirty bits set by the hardware while an entry
+//! is updated are not lost.
+
+#![cfg(feature = "all-formats")]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// Sets the dirty bit of an x86_64 entry.
+fn x86_write(entry: &AtomicU64) {
+    entry.fetch_or(1 << 6, Ordering::Relaxed);
+}
+
+/// Sets the dirty bit (DBM) of an AArch64 entry.
+fn arm_write(entry: &AtomicU64) {
+    entry.fetch_or(1 << 51, Ordering::Relaxed);
+}
+
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.map(vaddr, PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.is_dirty(vaddr), Ok(false));
+    pt
+}
+
+#[test]
+fn protect_keeps_dirty() {
+    let mut pt = mapped();
+    let vaddr = VirtAddr::from(VADDR);
+    MockHandler::race_leaf_writes(Some(x86_write));
+    pt.protect(vaddr, MappingFlags::READ).unwrap().1.ignore();
+    MockHandler::race_leaf_writes(None);
+    assert_eq!(pt.is_dirty(vaddr), Ok(true));
+    assert_eq!(pt.query(vaddr).unwrap().1, MappingFlags::READ);
+}
+
+#[test]
+fn clearing_accessed_keeps_dirty() {
+    let mut pt = mapped();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.set_accessed(vaddr, true).unwrap();
+    MockHandler::race_leaf_writes(Some(x86_write));
+    pt.set_accessed(vaddr, false).unwrap();
+    MockHandler::race_leaf_writes(None);
+    assert_eq!(pt.is_accessed(vaddr), Ok(false));
+    assert_eq!(pt.is_dirty(vaddr), Ok(true));
+}
+
+#[test]
+fn break_before_make_keeps_dirty_of_the_same_frame() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.map(vaddr, PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    MockHandler::race_leaf_writes(Some(arm_write));
+    // A new memory type needs break-before-make.
+    let (_, tlb) = pt.protect(vaddr, RW | MappingFlags::DEVICE).unwrap();
+    tlb.ignore();
+    assert_eq!(pt.is_dirty(vaddr), Ok(true));
+    pt.set_dirty(vaddr, false).unwrap();
+
+    // The dirty bit of another frame is not carried over.
+    let (_, tlb) = pt.remap(vaddr, PhysAddr::from(0x2000), RW).unwrap();
+    MockHandler::race_leaf_writes(None);
+    tlb.ignore();
+    assert_eq!(pt.is_dirty(vaddr), Ok(false));
+    assert_eq!(pt.query(vaddr).unwrap().0, PhysAddr::from(0x2000));
+}
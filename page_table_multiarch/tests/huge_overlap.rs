@@ -0,0 +1,134 @@
+//! Checks that operations on 4K pages inside a huge page are rejected, and
+//! leave the memory of the huge page untouched, for every entry format.
+
+#![cfg(feature = "all-formats")]
+
+use std::alloc::{Layout, alloc, dealloc};
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingError, PagingMetaData};
+
+const VADDR: usize = 0x4000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+
+/// A 2M-aligned block of host memory filled with a pattern, which the mock
+/// handler sees as physical memory.
+struct Block(*mut u64);
+
+impl Block {
+    const LAYOUT: Layout = match Layout::from_size_align(SIZE_2M, SIZE_2M) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid block layout"),
+    };
+    const PATTERN: u64 = 0xdead_beef_dead_beef;
+
+    fn new() -> Self {
+        let ptr = unsafe { alloc(Self::LAYOUT) } as *mut u64;
+        assert!(!ptr.is_null());
+        let mut block = Self(ptr);
+        block.words_mut().fill(Self::PATTERN);
+        block
+    }
+
+    fn paddr(&self) -> PhysAddr {
+        PhysAddr::from(self.0 as usize)
+    }
+
+    fn words_mut(&mut self) -> &mut [u64] {
+        unsafe { core::slice::from_raw_parts_mut(self.0, SIZE_2M / 8) }
+    }
+
+    fn is_intact(&self) -> bool {
+        let words = unsafe { core::slice::from_raw_parts(self.0, SIZE_2M / 8) };
+        words.iter().all(|&word| word == Self::PATTERN)
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.0 as *mut u8, Self::LAYOUT) }
+    }
+}
+
+fn check<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>() {
+    MockHandler::reset();
+    let block = Block::new();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    pt.map(va(VADDR), block.paddr(), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    let huge = Err(PagingError::MappedToHugePage {
+        vaddr: VADDR,
+        level: M::LEVELS - 2,
+    });
+
+    for off in [0x1000, SIZE_2M - 0x1000] {
+        let vaddr = va(VADDR + off);
+        let paddr = PhysAddr::from(0x1000);
+        let result = pt.map(vaddr, paddr, PageSize::Size4K, RW);
+        assert_eq!(result.map(|tlb| tlb.ignore()), huge);
+        assert_eq!(pt.set_absent_token(vaddr, 1), huge);
+        let result = pt.unmap(vaddr);
+        assert_eq!(result.map(|(_, _, tlb)| tlb.ignore()), huge);
+        let result = pt.protect(vaddr, MappingFlags::READ);
+        assert_eq!(result.map(|(_, tlb)| tlb.ignore()), huge);
+        let result = pt.remap(vaddr, block.paddr(), RW);
+        assert_eq!(result.map(|(_, tlb)| tlb.ignore()), huge);
+        let result = pt.map_region(vaddr, |_| paddr, 0x1000, RW, false, false);
+        assert_eq!(result.map(|tlb| tlb.ignore()), huge);
+        // Queries inside the huge page are fine.
+        assert_eq!(
+            pt.query(vaddr).unwrap(),
+            (block.paddr() + off, RW, PageSize::Size2M)
+        );
+    }
+    assert!(block.is_intact());
+
+    // A huge page below a larger one.
+    let result = pt.map(va(0), PhysAddr::from(1 << 30), PageSize::Size1G, RW);
+    result.unwrap().ignore();
+    let result = pt.map(va(SIZE_2M), block.paddr(), PageSize::Size2M, RW);
+    assert_eq!(
+        result.map(|tlb| tlb.ignore()),
+        Err(PagingError::MappedToHugePage {
+            vaddr: 0,
+            level: M::LEVELS - 3,
+        })
+    );
+
+    // The start of the huge page is fine.
+    pt.unmap(va(VADDR)).unwrap().2.ignore();
+    assert!(block.is_intact());
+}
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+#[test]
+fn x86_64() {
+    check::<X64PagingMetaData, X64PTE>();
+}
+
+#[test]
+fn aarch64() {
+    check::<A64PagingMetaData, A64PTE>();
+}
+
+#[test]
+fn riscv() {
+    check::<Sv39MetaData<VirtAddr>, Rv64PTE>();
+    check::<Sv48MetaData<VirtAddr>, Rv64PTE>();
+}
+
+#[test]
+fn loongarch64() {
+    check::<LA64MetaData, LA64PTE>();
+}
@@ -0,0 +1,226 @@
+//! Checks page tables whose levels do not all have 512 entries (see
+//! [`PagingMetaData::INDEX_BITS`]): the index of each entry, the tables
+//! larger and smaller than a frame, and the page sizes left.
+
+#![cfg(feature = "all-formats")]
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::{riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockPageTable, table_frames};
+use page_table_multiarch::{GenericPTE, MAX_LEVELS, MappingFlags, PageSize, PageTableInfo};
+use page_table_multiarch::{PagingError, PagingMetaData};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// RISC-V Sv39x4: Sv39 with a root table of 2048 entries (16K), for the
+/// guest physical addresses of G-stage page tables.
+struct Sv39x4;
+
+impl PagingMetaData for Sv39x4 {
+    const LEVELS: usize = 3;
+    const PA_MAX_BITS: usize = 56;
+    const VA_MAX_BITS: usize = 41;
+    const INDEX_BITS: [u8; MAX_LEVELS] = [11, 9, 9, 0];
+    type VirtAddr = VirtAddr;
+
+    fn vaddr_is_valid(vaddr: usize) -> bool {
+        vaddr >> Self::VA_MAX_BITS == 0
+    }
+
+    // `MockMetaData` records the flushes instead.
+    fn flush_tlb(_vaddr: Option<VirtAddr>) {}
+}
+
+/// 4 levels of 512, 512, 256 (2K) and 1024 (8K) entries, translating 48
+/// bits.
+struct Uneven;
+
+impl PagingMetaData for Uneven {
+    const LEVELS: usize = 4;
+    const PA_MAX_BITS: usize = 52;
+    const VA_MAX_BITS: usize = 48;
+    const INDEX_BITS: [u8; MAX_LEVELS] = [9, 9, 8, 10];
+    type VirtAddr = VirtAddr;
+
+    fn flush_tlb(_vaddr: Option<VirtAddr>) {}
+}
+
+/// Returns the number of entries of the tables at `level`.
+fn entries<M: PagingMetaData>(level: usize) -> usize {
+    1 << M::INDEX_BITS[level]
+}
+
+/// Returns the position of the index of the entries at `level`.
+fn shift<M: PagingMetaData>(level: usize) -> usize {
+    12 + M::INDEX_BITS[level + 1..M::LEVELS]
+        .iter()
+        .map(|&bits| bits as usize)
+        .sum::<usize>()
+}
+
+/// Maps a 4K page at each address with one index set at one level and the
+/// others zero, and checks that each is found at its indices.
+fn check_indices<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    let mut vaddrs = BTreeSet::new();
+    for level in 0..M::LEVELS {
+        for index in 0..entries::<M>(level) {
+            vaddrs.insert(index << shift::<M>(level));
+        }
+    }
+    let paddr = |vaddr: usize| PhysAddr::from(0x8000_0000 + (vaddr >> 12) % 0x10_0000 * 0x1000);
+    for &vaddr in &vaddrs {
+        pt.map(vaddr.into(), paddr(vaddr), PageSize::Size4K, RW)
+            .unwrap()
+            .ignore();
+    }
+    for &vaddr in &vaddrs {
+        let expected = (
+            PhysAddr::from(paddr(vaddr).as_usize() + 0x123),
+            RW,
+            PageSize::Size4K,
+        );
+        assert_eq!(pt.query((vaddr + 0x123).into()), Ok(expected));
+    }
+
+    // Every entry is reached at its index, with its address.
+    let leaves = RefCell::new(BTreeSet::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, index, vaddr: VirtAddr, entry: &PTE| {
+            let vaddr = vaddr.as_usize();
+            assert!(index < entries::<M>(level));
+            assert_eq!((vaddr >> shift::<M>(level)) % entries::<M>(level), index);
+            if level == M::LEVELS - 1 {
+                assert_eq!(entry.paddr(), paddr(vaddr));
+                leaves.borrow_mut().insert(vaddr);
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    assert_eq!(leaves.into_inner(), vaddrs);
+
+    // The tables through address 0 are full.
+    let root = pt.root_entries().map(|(i, _)| i).collect::<Vec<_>>();
+    assert_eq!(root, (0..entries::<M>(0)).collect::<Vec<_>>());
+    for level in 0..M::LEVELS {
+        let entries = entries::<M>(level);
+        assert_eq!(
+            pt.table_occupancy_at(0.into(), level),
+            Some((entries, entries))
+        );
+    }
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+
+    for &vaddr in &vaddrs {
+        pt.unmap(vaddr.into()).unwrap().2.ignore();
+    }
+    assert!(pt.root_entries().all(|(_, entry)| entry.is_table()));
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), 0);
+}
+
+#[test]
+fn indices() {
+    check_indices::<Sv39x4, Rv64PTE>();
+    check_indices::<Uneven, X64PTE>();
+}
+
+#[test]
+fn wide_root() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Sv39x4, Rv64PTE>::try_new().unwrap();
+    // The root table is one allocation of 4 frames.
+    assert_eq!(pt.table_frames(), 4);
+    assert_eq!(MockHandler::stats().allocated, 1);
+
+    // The whole 2 TiB are mapped, with huge pages as in Sv39.
+    let sizes = pt.page_sizes();
+    let huge = sizes.iter().filter(|size| size.is_huge());
+    assert_eq!(
+        huge.collect::<Vec<_>>(),
+        [&PageSize::Size2M, &PageSize::Size1G]
+    );
+    let top = (1 << 41) - PageSize::Size1G as usize;
+    let paddr = |va: VirtAddr| PhysAddr::from(va.as_usize() - top + 0x4000_0000);
+    pt.map_region(
+        top.into(),
+        paddr,
+        PageSize::Size1G as usize,
+        RW,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        pt.query((top + 0x1234).into()),
+        Ok((PhysAddr::from(0x4000_1234), RW, PageSize::Size1G))
+    );
+    let result = pt.map_region((1 << 41).into(), paddr, 0x1000, RW, false, false);
+    assert_eq!(
+        result.map(|tlb| tlb.ignore()),
+        Err(PagingError::InvalidVaddr(1 << 41))
+    );
+
+    // Splitting the 1G page takes the tables below the root.
+    let paddrs = PhysAddrRange::from_start_size(0x4000_0000.into(), 0x1000);
+    pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap().ignore();
+    assert_eq!(pt.query(top.into()), Err(PagingError::NotMapped));
+    assert_eq!(
+        pt.query((top + 0x1000).into()),
+        Ok((PhysAddr::from(0x4000_1000), RW, PageSize::Size4K))
+    );
+    assert_eq!(
+        pt.query((top + 0x20_0000).into()),
+        Ok((PhysAddr::from(0x4020_0000), RW, PageSize::Size2M))
+    );
+    assert_eq!(pt.table_frames(), 6);
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+}
+
+#[test]
+fn uneven_levels() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Uneven, X64PTE>::try_new().unwrap();
+    // Huge pages need tables of 512 entries below them.
+    assert_eq!(pt.page_sizes(), [PageSize::Size4K]);
+    for size in [PageSize::Size2M, PageSize::Size1G] {
+        let result = pt.map(0.into(), 0.into(), size, RW);
+        assert_eq!(
+            result.map(|tlb| tlb.ignore()),
+            Err(PagingError::UnsupportedPageSize)
+        );
+    }
+    let size = 2 * PageSize::Size1G as usize;
+    pt.map_region(
+        0.into(),
+        |va| PhysAddr::from(va.as_usize()),
+        size,
+        RW,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        pt.query((size - 1).into()),
+        Ok((PhysAddr::from(size - 1), RW, PageSize::Size4K))
+    );
+
+    // An entry at level 2 maps 4M, and one at level 1 maps 1G.
+    assert_eq!(pt.table_occupancy_at(0.into(), 1), Some((2, 512)));
+    assert_eq!(pt.table_occupancy_at(0.into(), 2), Some((256, 256)));
+    assert_eq!(pt.table_occupancy_at(0.into(), 3), Some((1024, 1024)));
+    // The root, a table at level 1, two at level 2 (one frame each), and 512
+    // at level 3 (two frames each).
+    assert_eq!(pt.table_frames(), 1 + 1 + 2 + 512 * 2);
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), 0);
+}
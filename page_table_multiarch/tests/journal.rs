@@ -0,0 +1,281 @@
+//! Checks the records of [`PageTable64::set_journal`] by keeping a shadow of
+//! the page table in sync with them only.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{ChangeJournal, ChangeRecord, GenericPTE, MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(vaddr: VirtAddr) -> PhysAddr {
+    PhysAddr::from(vaddr.as_usize() - VADDR)
+}
+
+thread_local! {
+    static RECORDS: RefCell<Vec<ChangeRecord>> = const { RefCell::new(Vec::new()) };
+    static CALLS: RefCell<usize> = const { RefCell::new(0) };
+}
+
+/// Keeps at most `capacity` records.
+struct Recorder {
+    capacity: usize,
+}
+
+impl ChangeJournal for Recorder {
+    fn record(&mut self, record: ChangeRecord) -> bool {
+        CALLS.with_borrow_mut(|calls| *calls += 1);
+        RECORDS.with_borrow_mut(|records| {
+            if records.len() == self.capacity {
+                return false;
+            }
+            records.push(record);
+            true
+        })
+    }
+}
+
+fn recorder(capacity: usize) -> Box<dyn ChangeJournal> {
+    Box::new(Recorder { capacity })
+}
+
+fn take_records() -> Vec<ChangeRecord> {
+    RECORDS.take()
+}
+
+fn journaled(capacity: usize) -> PageTable {
+    MockHandler::reset();
+    RECORDS.take();
+    CALLS.take();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.set_journal(recorder(capacity));
+    pt
+}
+
+/// The raw bits of the tables and present leaves, by level and address.
+type Shadow = BTreeMap<(usize, usize), usize>;
+
+fn snapshot(pt: &PageTable) -> Shadow {
+    let shadow = RefCell::new(Shadow::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &X64PTE| {
+            shadow
+                .borrow_mut()
+                .insert((level, vaddr.as_usize()), entry.bits());
+        }),
+        None,
+    )
+    .unwrap();
+    shadow.into_inner()
+}
+
+/// Applies the records to `shadow`, reading the changed entries from `pt`.
+fn replay(shadow: &mut Shadow, pt: &PageTable) {
+    let current = snapshot(pt);
+    for record in take_records() {
+        let (vaddr, level, count) = match record {
+            ChangeRecord::Entry {
+                vaddr,
+                level,
+                old,
+                new,
+            } => {
+                assert_ne!(old, new);
+                if let Some(&bits) = shadow.get(&(level, vaddr)) {
+                    assert_eq!(old, bits as u64, "{:#x?}", record);
+                }
+                (vaddr, level, 1)
+            }
+            ChangeRecord::Range {
+                vaddr,
+                level,
+                count,
+            } => (vaddr, level, count),
+        };
+        let size = 1 << (12 + (3 - level) * 9);
+        for i in 0..count {
+            let key = (level, vaddr + i * size);
+            match current.get(&key) {
+                Some(&bits) => shadow.insert(key, bits),
+                None => shadow.remove(&key),
+            };
+        }
+    }
+}
+
+#[test]
+fn shadow_stays_in_sync() {
+    let mut pt = journaled(usize::MAX);
+    let mut shadow = snapshot(&pt);
+    let mut check = |pt: &mut PageTable| {
+        replay(&mut shadow, pt);
+        assert_eq!(shadow, snapshot(pt));
+    };
+
+    // A 2M page and 4K pages around it.
+    pt.map_region(va(0x1f_0000), pa, 0x22_0000, FLAGS, true, false)
+        .unwrap()
+        .ignore();
+    check(&mut pt);
+    pt.protect_region(va(0x1f_0000), 0x1_0000, MappingFlags::READ, false)
+        .unwrap()
+        .ignore();
+    check(&mut pt);
+    pt.unmap(va(0x1f_1000)).unwrap().2.ignore();
+    check(&mut pt);
+    pt.remap(va(0x1f_2000), PhysAddr::from(0x5000), FLAGS)
+        .unwrap()
+        .1
+        .ignore();
+    check(&mut pt);
+    pt.set_dirty(va(0x1f_3000), true).unwrap();
+    pt.handle_access_fault(va(0x1f_4000), true)
+        .unwrap()
+        .ignore();
+    check(&mut pt);
+
+    // Splits the 2M page.
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(0x20_0000), 0x1000);
+    pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap().ignore();
+    check(&mut pt);
+    pt.enable_dirty_log(va(0x20_0000), 0x20_0000)
+        .unwrap()
+        .ignore();
+    check(&mut pt);
+    let (_child, tlb) = pt.clone_cow(va(0x1f_0000), 0x22_0000).unwrap();
+    tlb.ignore();
+    check(&mut pt);
+    pt.unmap_region(va(0x20_1000), 0x1f_f000, false)
+        .unwrap()
+        .ignore();
+    check(&mut pt);
+}
+
+#[test]
+fn consecutive_changes_are_merged() {
+    let mut pt = journaled(usize::MAX);
+    pt.map_region(va(0), pa, 0x1_0000, FLAGS, false, false)
+        .unwrap()
+        .ignore();
+    let records = take_records();
+    // The three new tables, then the leaves.
+    assert_eq!(records.len(), 4);
+    for (level, record) in records[..3].iter().enumerate() {
+        assert!(
+            matches!(*record, ChangeRecord::Entry { level: l, old: 0, .. } if l == level),
+            "{:#x?}",
+            record
+        );
+    }
+    assert_eq!(
+        records[3],
+        ChangeRecord::Range {
+            vaddr: VADDR,
+            level: 3,
+            count: 16,
+        }
+    );
+
+    let (_child, tlb) = pt.clone_cow(va(0), 0x1_0000).unwrap();
+    tlb.ignore();
+    assert_eq!(take_records(), records[3..]);
+
+    let tlb = pt
+        .map(
+            va(0x2_0000),
+            PhysAddr::from(0x1000),
+            PageSize::Size4K,
+            FLAGS,
+        )
+        .unwrap();
+    tlb.ignore();
+    let [record] = take_records()[..] else {
+        panic!("more than one record");
+    };
+    let ChangeRecord::Entry {
+        vaddr,
+        level,
+        old,
+        new,
+    } = record
+    else {
+        panic!("{:#x?}", record);
+    };
+    assert_eq!((vaddr, level, old), (VADDR + 0x2_0000, 3, 0));
+    let entry: X64PTE = GenericPTE::new_page(PhysAddr::from(0x1000), FLAGS, false);
+    assert_eq!(new, entry.bits() as u64);
+}
+
+#[test]
+fn splitting_records_the_new_table() {
+    let mut pt = journaled(usize::MAX);
+    pt.map(va(0), PhysAddr::from(0), PageSize::Size2M, FLAGS)
+        .unwrap()
+        .ignore();
+    take_records();
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(0x1000), 0x1000);
+    pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap().ignore();
+    let records = take_records();
+    assert!(matches!(
+        records[0],
+        ChangeRecord::Entry {
+            vaddr: VADDR,
+            level: 2,
+            ..
+        }
+    ));
+    assert_eq!(
+        records[1],
+        ChangeRecord::Range {
+            vaddr: VADDR,
+            level: 3,
+            count: 512,
+        }
+    );
+    assert!(matches!(
+        records[2],
+        ChangeRecord::Entry {
+            vaddr,
+            level: 3,
+            new,
+            ..
+        } if vaddr == VADDR + 0x1000 && <X64PTE as GenericPTE>::from_bits(new as usize).is_unused()
+    ));
+}
+
+#[test]
+fn full_journal_stops_recording() {
+    let mut pt = journaled(1);
+    for off in [0, 0x1_0000, 0x2_0000] {
+        pt.map(va(off), PhysAddr::from(0x1000), PageSize::Size4K, FLAGS)
+            .unwrap()
+            .ignore();
+    }
+    // The first record was kept, and nothing was passed after the second.
+    assert_eq!(take_records().len(), 1);
+    assert_eq!(CALLS.with_borrow(|calls| *calls), 2);
+
+    // The consumer resynchronized.
+    let journal = pt.set_journal(recorder(usize::MAX));
+    assert!(journal.is_some());
+    pt.unmap(va(0)).unwrap().2.ignore();
+    assert_eq!(take_records().len(), 1);
+
+    assert!(pt.take_journal().is_some());
+    pt.unmap(va(0x1_0000)).unwrap().2.ignore();
+    assert!(take_records().is_empty());
+}
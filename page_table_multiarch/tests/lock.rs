@@ -0,0 +1,186 @@
+//! Locking regions in memory: populating them first, refusing to unmap their
+//! pages without force, and leaving them out of the working set scans.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AbsentEntry, MappingFlags, PageSize, PagingError, WorkingSet};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+/// The 2M page and the three 4K pages of [`mapped`].
+const SIZE: usize = SIZE_2M + 0x3000;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// A 2M page, then three 4K pages.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0), PhysAddr::from(0x20_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    for off in [0x20_0000, 0x20_1000, 0x20_2000] {
+        pt.map(
+            va(off),
+            PhysAddr::from(0x40_0000 + off),
+            PageSize::Size4K,
+            RW,
+        )
+        .unwrap()
+        .ignore();
+    }
+    pt
+}
+
+fn no_populate(_: VirtAddr, absent: AbsentEntry) -> Option<(PhysAddr, MappingFlags)> {
+    panic!("populating {absent:?}");
+}
+
+#[test]
+fn unmap_needs_force() {
+    let mut pt = mapped();
+    // The 2M page is locked whole.
+    let (locked, tlb) = pt
+        .lock_region(va(0x1000), SIZE - 0x1000, no_populate)
+        .unwrap();
+    assert!(!tlb.is_needed());
+    tlb.ignore();
+    assert_eq!(locked, 4);
+    assert_eq!(pt.locked_bytes(), SIZE);
+    // Locking again changes nothing.
+    let (locked, tlb) = pt.lock_region(va(0), SIZE, no_populate).unwrap();
+    tlb.ignore();
+    assert_eq!(locked, 0);
+
+    assert_eq!(pt.unmap(va(0x20_1000)).err(), Some(PagingError::Locked));
+    assert_eq!(
+        pt.unmap_region(va(0), SIZE, false).err(),
+        Some(PagingError::Locked)
+    );
+    assert_eq!(
+        MockHandler::take_errors(),
+        [
+            ("unmap", VADDR + 0x20_1000, PagingError::Locked),
+            ("unmap_region", VADDR, PagingError::Locked),
+        ]
+    );
+    assert_eq!(pt.mapped_bytes(), SIZE);
+
+    // Unlocked pages are unmapped as usual.
+    assert_eq!(pt.unlock_region(va(0x20_2000), 0x1000), Ok(1));
+    let (_, _, tlb) = pt.unmap(va(0x20_2000)).unwrap();
+    tlb.ignore();
+    pt.force_unmap_region(va(0), SIZE - 0x1000, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.mapped_bytes(), 0);
+    assert_eq!(pt.locked_bytes(), 0);
+}
+
+#[test]
+fn locks_are_kept() {
+    let mut pt = mapped();
+    let (_, tlb) = pt.lock_region(va(0), SIZE, no_populate).unwrap();
+    tlb.ignore();
+    pt.protect_region(va(0), SIZE, MappingFlags::READ, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.locked_bytes(), SIZE);
+
+    // Not copied to the child.
+    let (mut child, tlb) = pt.clone_cow(va(0), SIZE).unwrap();
+    tlb.ignore();
+    assert_eq!(child.locked_bytes(), 0);
+    assert_eq!(child.mapped_bytes(), SIZE);
+    pt.unmap_all().unwrap().ignore();
+    child.unmap_all().unwrap().ignore();
+    assert_eq!(pt.locked_bytes(), 0);
+}
+
+#[test]
+fn populated_first() {
+    let mut pt = mapped();
+    for off in [0x20_3000, 0x20_4000] {
+        pt.set_absent_token(va(off), off as u64).unwrap();
+    }
+    let mut populated = Vec::new();
+    let (locked, tlb) = pt
+        .lock_region(va(0x20_0000), 0x5000, |vaddr, absent| {
+            populated.push((vaddr, absent));
+            Some((PhysAddr::from(vaddr.as_usize() - VADDR + 0x40_0000), RW))
+        })
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(locked, 5);
+    assert_eq!(
+        populated,
+        [
+            (va(0x20_3000), AbsentEntry::File(0x20_3000)),
+            (va(0x20_4000), AbsentEntry::File(0x20_4000)),
+        ]
+    );
+    assert_eq!(
+        pt.query(va(0x20_4000)),
+        Ok((PhysAddr::from(0x60_4000), RW, PageSize::Size4K))
+    );
+    assert_eq!(pt.locked_bytes(), 0x5000);
+}
+
+#[test]
+fn nothing_locked_on_failure() {
+    let mut pt = mapped();
+    pt.set_absent_token(va(0x20_3000), 1).unwrap();
+    pt.set_absent_token(va(0x20_4000), 2).unwrap();
+
+    // The first absent page is mapped, then the second fails.
+    let result = pt.lock_region(va(0), SIZE_2M + 0x5000, |_, absent| {
+        (absent == AbsentEntry::File(1)).then_some((PhysAddr::from(0x1000), RW))
+    });
+    assert_eq!(result.err(), Some(PagingError::NoMemory));
+    assert_eq!(pt.locked_bytes(), 0);
+    assert_eq!(pt.mapped_bytes(), SIZE + 0x1000);
+
+    // A hole.
+    let result = pt.lock_region(va(SIZE + 0x2000), 0x1000, no_populate);
+    assert_eq!(result.err(), Some(PagingError::NotMapped));
+    assert_eq!(pt.locked_bytes(), 0);
+    assert_eq!(
+        MockHandler::take_errors(),
+        [
+            ("lock_region", VADDR, PagingError::NoMemory),
+            ("lock_region", VADDR + SIZE + 0x2000, PagingError::NotMapped),
+        ]
+    );
+}
+
+#[test]
+fn left_out_of_working_set() {
+    let mut pt = mapped();
+    let (_, tlb) = pt.lock_region(va(0x20_0000), 0x1000, no_populate).unwrap();
+    tlb.ignore();
+    let mut idle = [7; SIZE / 0x1000];
+    let (set, tlb) = pt
+        .estimate_working_set(va(0), SIZE, false, &mut idle[..])
+        .unwrap();
+    tlb.flush_all();
+    assert_eq!(
+        set,
+        WorkingSet {
+            accessed: 0,
+            idle: SIZE_2M + 0x2000,
+            locked: 0x1000,
+        }
+    );
+    // The counter of the locked page is left as it is.
+    assert_eq!(idle[512], 7);
+    assert_eq!(idle[513], 8);
+}
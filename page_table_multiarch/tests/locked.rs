@@ -0,0 +1,293 @@
+//! Sharing page tables between threads, and the queries and fault handlers
+//! of [`LockedPageTable`] that do not take its lock.
+
+#![cfg(feature = "all-formats")]
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use lock_api::{GuardSend, RawMutex};
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{LockedPageTable, MappingFlags, PageSize, PageTable64, PagingError};
+use page_table_multiarch::{PagingMetaData, SpaceKind};
+
+type Sv39 = Sv39MetaData<VirtAddr>;
+type Locked<M> = LockedPageTable<MockMetaData<M>, Rv64PTE, MockHandler, Spin>;
+
+const VADDR: usize = 0x1000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// A spin lock.
+struct Spin(AtomicBool);
+
+unsafe impl RawMutex for Spin {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Spin(AtomicBool::new(false));
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        while !self.try_lock() {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Sv39 with break-before-make.
+struct Bbm;
+
+impl PagingMetaData for Bbm {
+    const LEVELS: usize = Sv39::LEVELS;
+    const PA_MAX_BITS: usize = Sv39::PA_MAX_BITS;
+    const VA_MAX_BITS: usize = Sv39::VA_MAX_BITS;
+    const BREAK_BEFORE_MAKE: bool = true;
+    type VirtAddr = VirtAddr;
+
+    // `MockMetaData` records the flushes instead.
+    fn flush_tlb(_vaddr: Option<VirtAddr>) {}
+}
+
+/// A kind of address space that is neither `Send` nor `Sync`.
+struct Local(#[allow(dead_code)] *const ());
+
+impl SpaceKind for Local {}
+
+/// Sets the dirty bit of a RISC-V entry.
+fn rv_write(entry: &AtomicU64) {
+    entry.fetch_or(1 << 7, Ordering::Relaxed);
+}
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(vaddr: usize) -> PhysAddr {
+    PhysAddr::from(vaddr - VADDR + 0x8000_0000)
+}
+
+/// Returns a page table with a copy-on-write 4K page at [`VADDR`] and a 2M
+/// one after it, shared with another page table that is returned too.
+fn shared<M: PagingMetaData<VirtAddr = VirtAddr>>() -> (Locked<M>, MockPageTable<M, Rv64PTE>) {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, Rv64PTE>::try_new().unwrap();
+    pt.map(va(VADDR), pa(VADDR), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let huge = VADDR + 0x20_0000;
+    pt.map(va(huge), pa(huge), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    let (other, tlb) = pt.clone_cow(va(VADDR), 0x40_0000).unwrap();
+    tlb.ignore();
+    (LockedPageTable::new(pt), other)
+}
+
+#[test]
+fn send_and_sync() {
+    fn check<T: Send + Sync>() {}
+    check::<PageTable64<X64PagingMetaData, X64PTE, MockHandler, Local>>();
+    check::<LockedPageTable<X64PagingMetaData, X64PTE, MockHandler, Spin, Local>>();
+}
+
+#[test]
+fn access_faults() {
+    MockHandler::reset();
+    let pt = Locked::<Sv39>::new(MockPageTable::try_new().unwrap());
+    assert_eq!(
+        pt.handle_access_fault(va(VADDR), false).map(|t| t.ignore()),
+        Err(PagingError::NotMapped)
+    );
+    let generation = {
+        let mut pt = pt.lock();
+        for (vaddr, flags) in [(VADDR, RW), (VADDR + 0x1000, MappingFlags::READ)] {
+            pt.map(va(vaddr), pa(vaddr), PageSize::Size4K, flags)
+                .unwrap()
+                .ignore();
+            pt.set_accessed(va(vaddr), false).unwrap();
+            pt.set_dirty(va(vaddr), false).unwrap();
+        }
+        pt.generation()
+    };
+    assert_eq!(
+        pt.query(va(VADDR + 0x123)),
+        Ok((pa(VADDR + 0x123), RW, PageSize::Size4K))
+    );
+    for vaddr in [VADDR, VADDR + 0x1000] {
+        let tlb = pt.handle_access_fault(va(vaddr), true).unwrap();
+        // Not counted in the generation, so never skipped.
+        assert_eq!(tlb.generation(), u64::MAX);
+        tlb.ignore();
+    }
+    let mut pt = pt.into_inner();
+    assert_eq!(pt.generation(), generation);
+    assert_eq!(pt.is_accessed(va(VADDR)), Ok(true));
+    assert_eq!(pt.is_dirty(va(VADDR)), Ok(true));
+    // A write to a read-only page is a permission fault.
+    assert_eq!(pt.is_accessed(va(VADDR + 0x1000)), Ok(true));
+    assert_eq!(pt.is_dirty(va(VADDR + 0x1000)), Ok(false));
+    pt.unmap(va(VADDR)).unwrap().2.ignore();
+}
+
+#[test]
+fn cow_faults() {
+    let (pt, other) = shared::<Sv39>();
+    assert_eq!(MockHandler::refs(pa(VADDR)), Some(2));
+    let copy = PhysAddr::from(0x9000_0000);
+    // The hardware setting the dirty bit meanwhile is not a change.
+    MockHandler::race_leaf_writes(Some(rv_write));
+    pt.handle_cow_fault(va(VADDR), |paddr, size| {
+        assert_eq!((paddr, size), (pa(VADDR), PageSize::Size4K));
+        Some(copy)
+    })
+    .unwrap()
+    .ignore();
+    MockHandler::race_leaf_writes(None);
+    let (paddr, flags, _) = pt.query(va(VADDR)).unwrap();
+    assert_eq!(paddr, copy);
+    assert!(flags.contains(RW) && !flags.contains(MappingFlags::COW));
+    assert_eq!(MockHandler::refs(pa(VADDR)), Some(1));
+    assert_eq!(
+        pt.handle_cow_fault(va(VADDR), |_, _| None)
+            .map(|t| t.ignore()),
+        Err(PagingError::NotCow)
+    );
+
+    // The other page table is the last user, and keeps its page.
+    let other = Locked::new(other);
+    other
+        .handle_cow_fault(va(VADDR), |paddr, _| Some(paddr))
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        other.query(va(VADDR)).unwrap(),
+        (pa(VADDR), RW, PageSize::Size4K)
+    );
+    assert_eq!(MockHandler::refs(pa(VADDR)), Some(1));
+}
+
+#[test]
+fn contended_cow_fault() {
+    let (pt, _other) = shared::<Sv39>();
+    // Another CPU resolves the fault while the page is copied.
+    let result = pt.handle_cow_fault(va(VADDR + 0x123), |paddr, size| {
+        assert_eq!((paddr, size), (pa(VADDR), PageSize::Size4K));
+        pt.lock()
+            .handle_cow_fault(va(VADDR), |_, _| Some(PhysAddr::from(0x4000_0000)))
+            .unwrap()
+            .ignore();
+        Some(PhysAddr::from(0x4000_1000))
+    });
+    assert_eq!(result.map(|t| t.ignore()), Err(PagingError::Contended));
+    // Only the page of the other CPU is unshared.
+    assert_eq!(
+        pt.query(va(VADDR)).unwrap(),
+        (PhysAddr::from(0x4000_0000), RW, PageSize::Size4K)
+    );
+    assert_eq!(MockHandler::refs(pa(VADDR)), Some(1));
+    assert_eq!(MockHandler::stats().unshared, 1);
+}
+
+#[test]
+fn huge_cow_fault_takes_the_lock() {
+    let (pt, _other) = shared::<Sv39>();
+    let huge = VADDR + 0x20_0000;
+    let copy = PhysAddr::from(0x4000_0000);
+    pt.handle_cow_fault(va(huge + 0x5000), |paddr, size| {
+        assert!(pt.try_lock().is_none());
+        assert_eq!((paddr, size), (pa(huge + 0x5000), PageSize::Size4K));
+        Some(copy)
+    })
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        pt.query(va(huge + 0x5000)).unwrap(),
+        (copy, RW, PageSize::Size4K)
+    );
+}
+
+#[test]
+fn cow_fault_with_break_before_make() {
+    let (pt, _other) = shared::<Bbm>();
+    MockMetaData::<Bbm>::take_flushes();
+    let copy = PhysAddr::from(0x9000_0000);
+    pt.handle_cow_fault(va(VADDR), |_, _| Some(copy))
+        .unwrap()
+        .ignore();
+    // The invalid entry is flushed before the new one is written.
+    assert_eq!(MockMetaData::<Bbm>::take_flushes(), [Some(va(VADDR))]);
+    assert_eq!(pt.query(va(VADDR)).unwrap().0, copy);
+    assert_eq!(MockHandler::refs(pa(VADDR)), Some(1));
+
+    // Keeping the page needs no break.
+    let mut other = Locked::new(_other);
+    other
+        .handle_cow_fault(va(VADDR), |paddr, _| Some(paddr))
+        .unwrap()
+        .ignore();
+    assert_eq!(MockMetaData::<Bbm>::take_flushes(), []);
+    assert_eq!(other.get_mut().query(va(VADDR)).unwrap().1 & RW, RW);
+}
+
+#[test]
+fn concurrent_queries() {
+    MockHandler::reset();
+    let pages = 64;
+    let mut pt = MockPageTable::<Sv39, Rv64PTE>::try_new().unwrap();
+    pt.map_region(
+        va(VADDR),
+        |va| pa(va.as_usize()),
+        pages * 0x1000,
+        RW,
+        false,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    let pt = Locked::new(pt);
+    let done = AtomicBool::new(false);
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for round in 0..200 {
+                let flags = if round % 2 == 0 {
+                    MappingFlags::READ
+                } else {
+                    RW
+                };
+                let mut pt = pt.lock();
+                for page in 0..pages {
+                    let vaddr = va(VADDR + page * 0x1000);
+                    pt.protect(vaddr, flags).unwrap().1.ignore();
+                    pt.set_accessed(vaddr, false).unwrap();
+                }
+            }
+            done.store(true, Ordering::Release);
+        });
+        for _ in 0..3 {
+            s.spawn(|| {
+                while !done.load(Ordering::Acquire) {
+                    for page in 0..pages {
+                        let vaddr = VADDR + page * 0x1000;
+                        let (paddr, flags, _) = pt.query(va(vaddr)).unwrap();
+                        assert_eq!(paddr, pa(vaddr));
+                        assert!(flags == RW || flags == MappingFlags::READ);
+                        pt.handle_access_fault(va(vaddr), true).unwrap().ignore();
+                    }
+                }
+            });
+        }
+    });
+    let pt = pt.into_inner();
+    assert_eq!(pt.query(va(VADDR)).unwrap().1, RW);
+}
@@ -0,0 +1,106 @@
+//! The huge pages of LoongArch page tables, in directory entries with the H
+//! bit.
+
+#![cfg(feature = "all-formats")]
+
+use std::cell::RefCell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::loongarch64::{LA64PTE, PTEFlags};
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingHandler};
+
+type PageTable = MockPageTable<LA64MetaData, LA64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+
+/// The level, virtual address and bits of the present leaf entries.
+fn leaves(pt: &PageTable) -> Vec<(usize, usize, u64)> {
+    let leaves = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &LA64PTE| {
+            if entry.is_present() && (level == 3 || entry.is_huge()) {
+                leaves
+                    .borrow_mut()
+                    .push((level, vaddr.as_usize(), entry.bits() as u64));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    leaves.into_inner()
+}
+
+#[test]
+fn map_region_blocks() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // A 4K page, then two 2M pages.
+    let get_paddr = |vaddr: VirtAddr| PhysAddr::from(vaddr.as_usize() - VADDR + 0x900_0000);
+    pt.map_region(
+        VirtAddr::from(VADDR + SIZE_2M - 0x1000),
+        get_paddr,
+        0x1000 + 2 * SIZE_2M,
+        RW,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+
+    // V, D, MATL, P and W, and H in the directory entries.
+    const BITS: u64 = 0x193 | 1 << 62;
+    assert_eq!(
+        leaves(&pt),
+        [
+            (3, VADDR + SIZE_2M - 0x1000, 0x91f_f000 | BITS),
+            (2, VADDR + SIZE_2M, 0x920_0000 | BITS | PTEFlags::GH.bits()),
+            (
+                2,
+                VADDR + 2 * SIZE_2M,
+                0x940_0000 | BITS | PTEFlags::GH.bits()
+            ),
+        ]
+    );
+    assert_eq!(
+        pt.query(VirtAddr::from(VADDR + SIZE_2M + 0x1234)),
+        Ok((PhysAddr::from(0x920_1234), RW, PageSize::Size2M))
+    );
+}
+
+#[test]
+fn global_huge_page() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.map(vaddr, PhysAddr::from(0x920_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    // Set HG as Linux does for kernel mappings.
+    let table = RefCell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, _, entry: &LA64PTE| {
+            if level == 1 {
+                *table.borrow_mut() = Some(entry.paddr());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let dir1 = MockHandler::phys_to_virt(table.into_inner().unwrap()).as_mut_ptr() as *mut u64;
+    unsafe { *dir1 |= PTEFlags::G.bits() };
+
+    assert_eq!(
+        pt.query(vaddr + 0x1234),
+        Ok((PhysAddr::from(0x920_1234), RW, PageSize::Size2M))
+    );
+    let (paddr, _, tlb) = pt.unmap(vaddr).unwrap();
+    assert_eq!(paddr, PhysAddr::from(0x920_0000));
+    assert!(tlb.is_global());
+    tlb.ignore();
+}
@@ -0,0 +1,163 @@
+//! Mapping regions to new zeroed frames, with huge pages where contiguous
+//! frames can be allocated, and the cleanup when an allocation fails.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PAGE_SIZE_4K, PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingHandler, QuotaKind};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+/// The first 2M block of the regions.
+const BLOCK: usize = 0x4020_0000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn is_zeroed(paddr: PhysAddr, size: PageSize) -> bool {
+    let ptr = MockHandler::phys_to_virt(paddr).as_ptr();
+    unsafe { core::slice::from_raw_parts(ptr, size as usize) }
+        .iter()
+        .all(|&b| b == 0)
+}
+
+/// Unmaps the pages of the region and frees their frames.
+fn free(pt: &mut PageTable, start: usize, size: usize) {
+    let mut vaddr = start;
+    while vaddr < start + size {
+        let (paddr, page, tlb) = pt.unmap(va(vaddr)).unwrap();
+        tlb.ignore();
+        match page {
+            PageSize::Size4K => MockHandler::dealloc_frame(paddr),
+            _ => MockHandler::dealloc_frames(paddr, page as usize / PAGE_SIZE_4K),
+        }
+        vaddr += page as usize;
+    }
+}
+
+#[test]
+fn huge_pages_in_aligned_blocks() {
+    MockHandler::reset();
+    let live = MockHandler::live_frames();
+    let mut pt = PageTable::try_new().unwrap();
+    let (start, size) = (BLOCK - 0x1000, 0x40_2000);
+    let (huge, tlb) = pt.map_alloc(va(start), size, RW, true).unwrap();
+    tlb.ignore();
+    assert_eq!(huge, 0x40_0000);
+    let pages = [
+        (start, PageSize::Size4K),
+        (BLOCK, PageSize::Size2M),
+        (BLOCK + 0x20_0000, PageSize::Size2M),
+        (BLOCK + 0x40_0000, PageSize::Size4K),
+    ];
+    for (vaddr, page) in pages {
+        let (paddr, flags, size) = pt.query(va(vaddr)).unwrap();
+        assert_eq!((flags, size), (RW, page));
+        assert!(page.is_aligned(paddr.as_usize()));
+        assert!(is_zeroed(paddr, page));
+    }
+    assert_eq!(pt.mapped_bytes(), size);
+
+    free(&mut pt, start, size);
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), live);
+}
+
+#[test]
+fn fallback_to_4k_frames() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // The frame of the first page, the 3 tables above it, and the frames of
+    // the first block are allocated before the ones of the second block.
+    MockHandler::fail_alloc_at(Some(5));
+    let (start, size) = (BLOCK - 0x1000, 0x40_2000);
+    let (huge, tlb) = pt.map_alloc(va(start), size, RW, true).unwrap();
+    tlb.ignore();
+    assert_eq!(huge, 0x20_0000);
+    assert_eq!(pt.query(va(BLOCK)).unwrap().2, PageSize::Size2M);
+    let second = BLOCK + 0x20_0000;
+    for vaddr in [second, second + 0x1000, second + 0x1f_f000] {
+        let (paddr, _, page) = pt.query(va(vaddr)).unwrap();
+        assert_eq!(page, PageSize::Size4K);
+        assert!(is_zeroed(paddr, page));
+    }
+    assert_eq!(MockHandler::stats().failed, 1);
+    free(&mut pt, start, size);
+
+    // Without huge pages.
+    let (huge, tlb) = pt.map_alloc(va(BLOCK), 0x20_0000, RW, false).unwrap();
+    tlb.ignore();
+    assert_eq!(huge, 0);
+    assert_eq!(pt.query(va(BLOCK)).unwrap().2, PageSize::Size4K);
+    free(&mut pt, BLOCK, 0x20_0000);
+}
+
+#[test]
+fn rollback_of_mixed_sizes() {
+    // The allocations are the frames of the huge page, the table above it,
+    // the frame of the first 4K page, the table of the 4K pages, and the
+    // frame of the second 4K page.
+    for (fail_at, pages) in [(1, 0), (2, 1), (3, 1), (4, 2)] {
+        MockHandler::reset();
+        let mut pt = PageTable::try_new().unwrap();
+        pt.map(va(0x1000), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+            .unwrap()
+            .ignore();
+        let (live, tables) = (MockHandler::live_frames(), pt.table_frames());
+        MockMetaData::<X64PagingMetaData>::take_flushes();
+        MockHandler::fail_alloc_at(Some(fail_at));
+        let result = pt.map_alloc(va(BLOCK), 0x20_2000, RW, true);
+        assert_eq!(result.err(), Some(PagingError::NoMemory));
+
+        for vaddr in [BLOCK, BLOCK + 0x20_0000, BLOCK + 0x20_1000] {
+            assert_eq!(pt.query(va(vaddr)), Err(PagingError::NotMapped));
+        }
+        assert_eq!(pt.mapped_bytes(), 0x1000);
+        // The frames are freed, the new tables kept.
+        let new_tables = pt.table_frames() - tables;
+        assert_eq!(MockHandler::live_frames(), live + new_tables);
+        let flushes = MockMetaData::<X64PagingMetaData>::take_flushes();
+        let mapped = [Some(va(BLOCK)), Some(va(BLOCK + 0x20_0000))];
+        assert_eq!(flushes, mapped[..pages], "failing allocation {fail_at}");
+    }
+}
+
+#[test]
+fn errors() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let allocated = MockHandler::stats().allocated;
+    let result = pt.map_alloc(va(BLOCK + 0x10), 0x1000, RW, true);
+    assert_eq!(result.err(), Some(PagingError::NotAligned));
+
+    pt.map(
+        va(BLOCK + 0x1000),
+        PhysAddr::from(0x1000),
+        PageSize::Size4K,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    let allocated_tables = MockHandler::stats().allocated;
+    let result = pt.map_alloc(va(BLOCK), 0x20_0000, RW, true);
+    assert_eq!(result.err(), Some(PagingError::AlreadyMapped));
+
+    pt.set_limits(0x2000, usize::MAX);
+    let result = pt.map_alloc(va(BLOCK + 0x2000), 0x2000, RW, true);
+    assert_eq!(
+        result.err(),
+        Some(PagingError::QuotaExceeded {
+            kind: QuotaKind::MappedBytes,
+            limit: 0x2000,
+            requested: 0x3000,
+        })
+    );
+    // Nothing was allocated for the failed calls.
+    assert_eq!(MockHandler::stats().allocated, allocated_tables);
+    assert!(allocated_tables > allocated);
+}
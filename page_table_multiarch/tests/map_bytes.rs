@@ -0,0 +1,137 @@
+//! Checks the page rounding of [`PageTable64::map_region_bytes`].
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const PADDR: usize = 0x8_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+
+/// Returns the 4K pages mapped in `[VADDR, VADDR + 0x10_0000)`.
+fn mapped_pages(pt: &PageTable) -> Vec<(usize, usize)> {
+    (0..0x100)
+        .filter_map(|i| {
+            let vaddr = VADDR + i * 0x1000;
+            let (paddr, _, _) = pt.query(VirtAddr::from(vaddr)).ok()?;
+            Some((vaddr, paddr.as_usize()))
+        })
+        .collect()
+}
+
+#[test]
+fn segment_with_page_offset() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // A segment like `LOAD 0x1234 0x...1234 0x2000`: it touches three pages.
+    pt.map_region_bytes(
+        VirtAddr::from(VADDR + 0x1234),
+        PhysAddr::from(PADDR + 0x1234),
+        0x2000,
+        FLAGS,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        mapped_pages(&pt),
+        [
+            (VADDR + 0x1000, PADDR + 0x1000),
+            (VADDR + 0x2000, PADDR + 0x2000),
+            (VADDR + 0x3000, PADDR + 0x3000),
+        ]
+    );
+    assert_eq!(
+        pt.query(VirtAddr::from(VADDR + 0x1234)),
+        Ok((PhysAddr::from(PADDR + 0x1234), FLAGS, PageSize::Size4K))
+    );
+    assert_eq!(pt.mapped_bytes(), 0x3000);
+}
+
+#[test]
+fn end_on_a_page_boundary() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // The last byte is at 0x1fff, so the page at 0x2000 stays unmapped.
+    pt.map_region_bytes(
+        VirtAddr::from(VADDR + 0x800),
+        PhysAddr::from(PADDR + 0x800),
+        0x1800,
+        FLAGS,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(
+        mapped_pages(&pt),
+        [(VADDR, PADDR), (VADDR + 0x1000, PADDR + 0x1000)]
+    );
+
+    // A single byte maps its page, an empty range maps nothing.
+    let vaddr = VirtAddr::from(VADDR + 0x5fff);
+    let paddr = PhysAddr::from(PADDR + 0x5fff);
+    pt.map_region_bytes(vaddr, paddr, 1, FLAGS)
+        .unwrap()
+        .ignore();
+    let vaddr = VirtAddr::from(VADDR + 0x8123);
+    let paddr = PhysAddr::from(PADDR + 0x8123);
+    let tlb = pt.map_region_bytes(vaddr, paddr, 0, FLAGS).unwrap();
+    // Like an empty `map_region`.
+    assert!(!tlb.is_needed());
+    assert_eq!(tlb.pages(), Some(0));
+    tlb.ignore();
+    assert_eq!(
+        mapped_pages(&pt),
+        [
+            (VADDR, PADDR),
+            (VADDR + 0x1000, PADDR + 0x1000),
+            (VADDR + 0x5000, PADDR + 0x5000),
+        ]
+    );
+}
+
+#[test]
+fn huge_pages_inside_the_range() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR + 0x1f_f800);
+    let paddr = PhysAddr::from(0x1f_f800);
+    pt.map_region_bytes(vaddr, paddr, 0x20_1000, FLAGS)
+        .unwrap()
+        .ignore();
+    let page = |off: usize| pt.query(VirtAddr::from(VADDR + off)).unwrap();
+    assert_eq!(page(0x1f_f000).2, PageSize::Size4K);
+    assert_eq!(
+        page(0x20_0000),
+        (PhysAddr::from(0x20_0000), FLAGS, PageSize::Size2M)
+    );
+    assert_eq!(page(0x40_0000).2, PageSize::Size4K);
+    assert_eq!(pt.mapped_bytes(), 0x20_2000);
+}
+
+#[test]
+fn invalid_ranges() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR + 0x234);
+    for paddr in [PADDR, PADDR + 0x1234 + 8] {
+        assert_eq!(
+            pt.map_region_bytes(vaddr, PhysAddr::from(paddr), 0x1000, FLAGS)
+                .err(),
+            Some(PagingError::NotAligned)
+        );
+    }
+    // The rounded end would wrap around.
+    let vaddr = VirtAddr::from(usize::MAX - 0xfff);
+    let paddr = PhysAddr::from(PADDR);
+    assert_eq!(
+        pt.map_region_bytes(vaddr, paddr, 0x2000, FLAGS).err(),
+        Some(PagingError::InvalidVaddr(usize::MAX - 0xfff))
+    );
+    assert_eq!(mapped_pages(&pt), []);
+    assert_eq!(pt.mapped_bytes(), 0);
+}
@@ -0,0 +1,92 @@
+//! Mapping a region with the flags of each page given by a callback, with
+//! huge pages only where the flags are the same.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+const VADDR: usize = 0x4000_0000;
+const PADDR: usize = 0x8000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+/// The page of the second 2M block that stays writable.
+const DATA: usize = VADDR + 0x30_5000;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn paddr_of(vaddr: VirtAddr) -> PhysAddr {
+    PhysAddr::from(vaddr.as_usize() - VADDR + PADDR)
+}
+
+fn flags_for(vaddr: VirtAddr) -> MappingFlags {
+    if vaddr.as_usize() == DATA { RW } else { RX }
+}
+
+#[test]
+fn mixed_flags_in_a_huge_block() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    pt.map_region_with(va(VADDR), paddr_of, 0x40_0000, flags_for, true)
+        .unwrap()
+        .ignore();
+    let query = |vaddr: usize| pt.query(va(vaddr)).unwrap();
+
+    assert_eq!(
+        query(VADDR + 0x1000),
+        (paddr_of(va(VADDR + 0x1000)), RX, PageSize::Size2M)
+    );
+    // The second block has a page of different flags: all its pages are 4K.
+    assert_eq!(query(DATA), (paddr_of(va(DATA)), RW, PageSize::Size4K));
+    for vaddr in [
+        VADDR + 0x20_0000,
+        DATA - 0x1000,
+        DATA + 0x1000,
+        VADDR + 0x3f_f000,
+    ] {
+        assert_eq!(query(vaddr), (paddr_of(va(vaddr)), RX, PageSize::Size4K));
+    }
+
+    // Without huge pages.
+    MockHandler::reset();
+    let mut pt = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    pt.map_region_with(va(VADDR), paddr_of, 0x20_0000, |_| RX, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.query(va(VADDR)).unwrap().2, PageSize::Size4K);
+}
+
+#[test]
+fn contiguous_groups() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    let vaddr = VADDR + 0x30_0000;
+    pt.map_region_with(va(vaddr), paddr_of, 0x2_0000, flags_for, true)
+        .unwrap()
+        .ignore();
+    // The group holding the data page is split, the next one is not.
+    assert_eq!(pt.query(va(DATA)).unwrap().1, RW);
+    assert_eq!(pt.query(va(DATA)).unwrap().2, PageSize::Size4K);
+    assert_eq!(pt.query(va(vaddr)).unwrap().2, PageSize::Size4K);
+    let next = pt.query(va(vaddr + 0x1_0000)).unwrap();
+    assert_eq!((next.1, next.2), (RX, PageSize::Size64K));
+}
+
+#[test]
+fn mapped_pages_are_kept_on_error() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    pt.map(va(DATA), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let result = pt.map_region_with(va(VADDR), paddr_of, 0x40_0000, flags_for, true);
+    assert_eq!(result.err(), Some(PagingError::AlreadyMapped));
+    assert_eq!(pt.query(va(VADDR)).unwrap().2, PageSize::Size2M);
+    assert_eq!(pt.query(va(DATA + 0x1000)), Err(PagingError::NotMapped));
+}
@@ -0,0 +1,85 @@
+//! The length of the mapped bytes from an address, e.g. for copying a string
+//! from user space up to the first hole.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const HUGE: usize = 0x4000_0000;
+/// The 4K pages right after the huge page, then a hole.
+const PAGES: usize = HUGE + 0x20_0000;
+const HOLE: usize = PAGES + 0x2000;
+/// A read-only page after the hole.
+const RO: usize = HOLE + 0x1000;
+const USER_R: MappingFlags = MappingFlags::READ.union(MappingFlags::USER);
+const USER_RW: MappingFlags = USER_R.union(MappingFlags::WRITE);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn page_table() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for (vaddr, size, flags) in [
+        (HUGE, PageSize::Size2M, USER_RW),
+        (PAGES, PageSize::Size4K, USER_RW),
+        (PAGES + 0x1000, PageSize::Size4K, USER_RW),
+        (RO, PageSize::Size4K, USER_R),
+    ] {
+        pt.map(va(vaddr), PhysAddr::from(vaddr), size, flags)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+#[test]
+fn up_to_the_hole() {
+    let pt = page_table();
+    // A string ending at the page boundary before the hole.
+    let string = HOLE - 0x10;
+    assert_eq!(pt.mapped_prefix_len(va(string), 0x1_0000, USER_R), 0x10);
+    assert_eq!(pt.mapped_prefix_len(va(string), 8, USER_R), 8);
+    // Across the huge page and the 4K pages after it.
+    let from = HUGE + 0x123;
+    assert_eq!(
+        pt.mapped_prefix_len(va(from), usize::MAX, USER_R),
+        HOLE - from
+    );
+    assert_eq!(pt.mapped_prefix_len(va(HOLE), 0x1000, USER_R), 0);
+    assert_eq!(pt.mapped_prefix_len(va(RO + 0x800), 0x1000, USER_R), 0x800);
+    assert_eq!(pt.mapped_prefix_len(va(RO), 0, USER_R), 0);
+    // Unmapped upper tables.
+    assert_eq!(pt.mapped_prefix_len(va(0x1000), 0x1000, USER_R), 0);
+}
+
+#[test]
+fn required_flags() {
+    let pt = page_table();
+    assert_eq!(pt.mapped_prefix_len(va(RO), 0x1000, USER_RW), 0);
+    assert_eq!(pt.mapped_prefix_len(va(RO), 0x2000, USER_R), 0x1000);
+    let exec = USER_R | MappingFlags::EXECUTE;
+    assert_eq!(pt.mapped_prefix_len(va(PAGES), 0x1000, exec), 0);
+}
+
+#[test]
+fn end_of_the_lower_half() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let last = 0x7fff_ffff_f000;
+    pt.map(va(last), PhysAddr::from(0x1000), PageSize::Size4K, USER_RW)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.mapped_prefix_len(va(last), usize::MAX, USER_R), 0x1000);
+    assert_eq!(
+        pt.mapped_prefix_len(va(0x8000_0000_0000), 0x1000, USER_R),
+        0
+    );
+}
@@ -0,0 +1,131 @@
+//! Reading the mappings as regions with a cursor, in chunks resumed after
+//! the last region read.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AnyPageTable, MappedRegion, MappingCursor, MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+const UPPER: usize = 0xffff_8000_0000_0000;
+
+/// `(vaddr, paddr, size, flags)` of the regions mapped by [`page_table`].
+const REGIONS: [(usize, usize, usize, MappingFlags); 5] = [
+    (0x1000, 0x8000_0000, 0x4000, RW),
+    // 4K pages followed by a 2M page.
+    (0x1f_f000, 0x401f_f000, 0x20_1000, RW),
+    // Only the flags differ from the previous region.
+    (0x40_0000, 0x4040_0000, 0x1000, RX),
+    // Only the physical address differs from the previous region.
+    (0x40_1000, 0x4050_0000, 0x1000, RX),
+    (UPPER, 0x9000_0000, 0x2000, RW),
+];
+
+fn page_table() -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    for (vaddr, paddr, size, flags) in REGIONS {
+        let paddr = |va: VirtAddr| PhysAddr::from(va.as_usize() - vaddr + paddr);
+        pt.map_region(vaddr.into(), paddr, size, flags, true, false)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+fn regions(cursor: MappingCursor) -> Vec<(usize, usize, usize, MappingFlags)> {
+    cursor
+        .map(|r| (r.vaddr, r.paddr.as_usize(), r.size, r.flags))
+        .collect()
+}
+
+#[test]
+fn coalesced_regions() {
+    MockHandler::reset();
+    let pt = page_table();
+    let any: &dyn AnyPageTable = &pt;
+    assert_eq!(any.iter().count(), 10);
+    assert_eq!(regions(MappingCursor::new(&pt, 0)), REGIONS);
+
+    // From inside a region, it starts at the first mapping from there.
+    let mut cursor = MappingCursor::new(&pt, 0x30_0000);
+    let region = cursor.next_region().unwrap();
+    assert_eq!((region.vaddr, region.size), (0x20_0000, 0x20_0000));
+    assert_eq!(region.paddr, PhysAddr::from(0x4020_0000));
+    assert_eq!(region.generation, pt.generation());
+    assert_eq!(cursor.count(), 3);
+
+    assert_eq!(regions(MappingCursor::new(&pt, 0x40_2000)), REGIONS[4..]);
+    let mut cursor = MappingCursor::new(&pt, UPPER + 0x2000);
+    assert_eq!(cursor.next_region(), None);
+    assert_eq!(cursor.next_region(), None);
+}
+
+#[test]
+fn chunks() {
+    MockHandler::reset();
+    let pt = page_table();
+    let mut seen = Vec::new();
+    let mut last: Option<MappedRegion> = None;
+    loop {
+        let cursor = match &last {
+            Some(last) => MappingCursor::resume(&pt, last),
+            None => MappingCursor::new(&pt, 0),
+        };
+        let chunk: Vec<_> = cursor.take(2).collect();
+        // The first region of a resumed cursor replaces the last one.
+        if last.is_some() {
+            assert_eq!(chunk[0], seen.pop().unwrap());
+        }
+        let done = chunk.len() < 2;
+        last = chunk.last().copied();
+        seen.extend(chunk);
+        if done {
+            break;
+        }
+    }
+    let seen: Vec<_> = seen
+        .iter()
+        .map(|r| (r.vaddr, r.paddr.as_usize(), r.size, r.flags))
+        .collect();
+    assert_eq!(seen, REGIONS);
+}
+
+#[test]
+fn changes_between_chunks() {
+    MockHandler::reset();
+    let mut pt = page_table();
+    let last = MappingCursor::new(&pt, 0).next_region().unwrap();
+    assert_eq!(last.size, 0x4000);
+
+    // A new page continuing the last region needs no flush on x86, so the
+    // generation is the same and the page is merged.
+    pt.map(
+        VirtAddr::from(0x5000),
+        PhysAddr::from(0x8000_4000),
+        PageSize::Size4K,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    let region = MappingCursor::resume(&pt, &last).next_region().unwrap();
+    assert_eq!((region.vaddr, region.size), (0x1000, 0x5000));
+
+    // A changed page makes the last region outdated: it is not returned
+    // again, and the cursor goes on after it.
+    pt.protect(VirtAddr::from(0x1000), MappingFlags::READ)
+        .unwrap()
+        .1
+        .ignore();
+    assert_ne!(pt.generation(), last.generation);
+    let mut cursor = MappingCursor::resume(&pt, &last);
+    let region = cursor.next_region().unwrap();
+    assert_eq!((region.vaddr, region.size), (0x5000, 0x1000));
+    assert_eq!(region.generation, pt.generation());
+    assert_eq!(cursor.next_region().unwrap().vaddr, 0x1f_f000);
+}
@@ -0,0 +1,106 @@
+//! Checks that frames are only mapped with the memory type required by
+//! [`PagingHandler::memory_type_of`].
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, MemoryType, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+/// A framebuffer, which must be mapped uncached.
+const FB: usize = 0x8000_0000;
+const RAM: usize = 0x4000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const UNCACHED: MappingFlags = RW.union(MappingFlags::UNCACHED);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn setup() -> PageTable {
+    MockHandler::reset();
+    MockHandler::set_memory_type(
+        PhysAddrRange::from_start_size(PhysAddr::from(FB), 0x10_0000),
+        MemoryType::Uncached,
+    );
+    PageTable::try_new().unwrap()
+}
+
+#[test]
+fn memory_type_of_flags() {
+    assert_eq!(MemoryType::of(RW), MemoryType::Normal);
+    assert_eq!(MemoryType::of(UNCACHED), MemoryType::Uncached);
+    let device = UNCACHED | MappingFlags::DEVICE;
+    assert_eq!(MemoryType::of(device), MemoryType::Device);
+}
+
+#[test]
+fn map_with_wrong_type() {
+    let mut pt = setup();
+    let fb = PhysAddr::from(FB);
+    assert_eq!(
+        pt.map(va(0), fb, PageSize::Size4K, RW).err(),
+        Some(PagingError::AttributeConflict(fb))
+    );
+    assert_eq!(pt.query(va(0)), Err(PagingError::NotMapped));
+    // The framebuffer can be mapped twice, both times uncached.
+    for off in [0, 0x1000] {
+        pt.map(va(off), fb, PageSize::Size4K, UNCACHED)
+            .unwrap()
+            .ignore();
+    }
+    // Frames without a required type take any.
+    pt.map(va(0x2000), PhysAddr::from(RAM), PageSize::Size4K, UNCACHED)
+        .unwrap()
+        .ignore();
+}
+
+#[test]
+fn huge_page_overlapping_range() {
+    let mut pt = setup();
+    // A single page in the middle of the 2M page must be uncached.
+    let ram = PhysAddr::from(RAM);
+    MockHandler::set_memory_type(
+        PhysAddrRange::from_start_size(ram + 0x10_0000, 0x1000),
+        MemoryType::Uncached,
+    );
+    assert_eq!(
+        pt.map_region(va(0), |v| ram + (v - va(0)), 0x20_0000, RW, true, false)
+            .err(),
+        Some(PagingError::AttributeConflict(ram))
+    );
+    assert_eq!(pt.mapped_bytes(), 0);
+}
+
+#[test]
+fn protect_and_remap() {
+    let mut pt = setup();
+    let fb = PhysAddr::from(FB);
+    pt.map(va(0), fb, PageSize::Size4K, UNCACHED)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pt.protect(va(0), RW).err(),
+        Some(PagingError::AttributeConflict(fb))
+    );
+    // Only the permissions may change.
+    let (_, tlb) = pt.protect(va(0), UNCACHED - MappingFlags::WRITE).unwrap();
+    tlb.ignore();
+
+    let ram = PhysAddr::from(RAM);
+    pt.map(va(0x1000), ram, PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pt.remap(va(0x1000), fb, RW).err(),
+        Some(PagingError::AttributeConflict(fb))
+    );
+    assert_eq!(pt.query(va(0x1000)), Ok((ram, RW, PageSize::Size4K)));
+    let (_, tlb) = pt.remap(va(0x1000), fb, UNCACHED).unwrap();
+    tlb.ignore();
+}
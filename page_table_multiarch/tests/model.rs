@@ -0,0 +1,314 @@
+//! Property tests checking [`PageTable64`] against the shadow model, and
+//! fault-injection tests for the allocation error paths.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, ShadowModel, table_frames};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingMetaData, RangeSummary};
+use proptest::prelude::*;
+
+type Meta = page_table_multiarch::mock::MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+type Model = ShadowModel<Meta, X64PTE>;
+
+/// All generated mappings fall into this 1G-aligned window.
+const BASE: usize = 0x40_0000_0000;
+const PADDR_BASE: usize = 0x1_0000_0000;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Map {
+        vaddr: usize,
+        paddr: usize,
+        size: PageSize,
+        flags: MappingFlags,
+    },
+    Unmap {
+        vaddr: usize,
+    },
+    Protect {
+        vaddr: usize,
+        flags: MappingFlags,
+    },
+}
+
+fn flags() -> impl Strategy<Value = MappingFlags> {
+    prop_oneof![
+        Just(MappingFlags::READ),
+        Just(MappingFlags::READ | MappingFlags::WRITE),
+        Just(MappingFlags::READ | MappingFlags::EXECUTE),
+        Just(MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER),
+    ]
+}
+
+fn page_size() -> impl Strategy<Value = PageSize> {
+    prop_oneof![
+        8 => Just(PageSize::Size4K),
+        3 => Just(PageSize::Size2M),
+        1 => Just(PageSize::Size1G),
+    ]
+}
+
+/// A virtual address in the first 8 pages of one of the first 4 2M blocks of
+/// the window, to make collisions likely.
+fn vaddr() -> impl Strategy<Value = usize> {
+    (0..4usize, 0..8usize).prop_map(|(block, page)| BASE + block * 0x20_0000 + page * 0x1000)
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        3 => (vaddr(), 0..64usize, page_size(), flags()).prop_map(|(vaddr, frame, size, flags)| {
+            Op::Map {
+                vaddr: vaddr.align_down(size),
+                paddr: PADDR_BASE + frame * size as usize,
+                size,
+                flags,
+            }
+        }),
+        2 => vaddr().prop_map(|vaddr| Op::Unmap { vaddr }),
+        1 => (vaddr(), flags()).prop_map(|(vaddr, flags)| Op::Protect { vaddr, flags }),
+    ]
+}
+
+fn apply(pt: &mut PageTable, model: &mut Model, op: &Op) {
+    match *op {
+        Op::Map {
+            vaddr,
+            paddr,
+            size,
+            flags,
+        } => {
+            let vaddr = VirtAddr::from(vaddr);
+            match pt.map(vaddr, PhysAddr::from(paddr), size, flags) {
+                Ok(tlb) => {
+                    tlb.ignore();
+                    model.map(vaddr, PhysAddr::from(paddr), size, flags);
+                }
+                // Empty intermediate tables left behind by `unmap` also block huge
+                // mappings, so the model cannot predict every rejection.
+                Err(PagingError::AlreadyMapped) | Err(PagingError::MappedToHugePage { .. }) => {}
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+        Op::Unmap { vaddr } => {
+            let vaddr = VirtAddr::from(vaddr);
+            match pt.unmap(vaddr) {
+                Ok((paddr, size, tlb)) => {
+                    tlb.ignore();
+                    assert_eq!(model.unmap(vaddr), Some((paddr, size)));
+                }
+                Err(PagingError::NotMapped) => assert_eq!(model.lookup(vaddr), None),
+                // Only the start of a huge page is accepted.
+                Err(PagingError::MappedToHugePage { vaddr: start, .. }) => {
+                    check_inside_huge_page(model, vaddr, start)
+                }
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+        Op::Protect { vaddr, flags } => {
+            let vaddr = VirtAddr::from(vaddr);
+            match pt.protect(vaddr, flags) {
+                Ok((size, tlb)) => {
+                    tlb.ignore();
+                    assert_eq!(model.protect(vaddr, flags), Some(size));
+                }
+                Err(PagingError::NotMapped) => assert_eq!(model.lookup(vaddr), None),
+                // Only the start of a huge page is accepted.
+                Err(PagingError::MappedToHugePage { vaddr: start, .. }) => {
+                    check_inside_huge_page(model, vaddr, start)
+                }
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+    }
+}
+
+/// Checks that `vaddr` is in the huge page of the model starting at `start`,
+/// but not at its start.
+fn check_inside_huge_page(model: &Model, vaddr: VirtAddr, start: usize) {
+    let (page, _, _, size) = model.lookup(vaddr).unwrap();
+    assert!(size.is_huge());
+    assert_eq!(page.as_usize(), start);
+    assert_ne!(vaddr.as_usize(), start);
+}
+
+proptest! {
+    #[test]
+    fn query_agrees_with_model(ops in prop::collection::vec(op(), 1..64)) {
+        MockHandler::reset();
+        {
+            let mut pt = PageTable::try_new().unwrap();
+            let mut model = Model::new();
+            for op in &ops {
+                apply(&mut pt, &mut model, op);
+                model.check(&pt);
+                prop_assert_eq!(MockHandler::live_frames(), table_frames(&pt));
+            }
+        }
+        let stats = MockHandler::stats();
+        prop_assert_eq!(stats.allocated, stats.deallocated);
+        prop_assert_eq!(MockHandler::live_frames(), 0);
+    }
+}
+
+/// Summarizes the 4K pages of `[start, start + size)` with the model, like
+/// [`PageTable64::summarize`](page_table_multiarch::PageTable64::summarize).
+fn model_summary(model: &Model, start: usize, size: usize) -> RangeSummary {
+    let mut summary = RangeSummary::default();
+    for vaddr in (start..start + size).step_by(0x1000) {
+        let Some((_, _, flags, page_size)) = model.lookup(VirtAddr::from(vaddr)) else {
+            summary.holes += 1;
+            continue;
+        };
+        summary.flags_intersection = if summary.present_pages == 0 {
+            flags
+        } else {
+            summary.flags_intersection & flags
+        };
+        summary.present_pages += 1;
+        summary.flags_union |= flags;
+        summary.any_huge |= page_size.is_huge();
+        summary.any_cow |= flags.contains(MappingFlags::COW);
+    }
+    summary
+}
+
+proptest! {
+    #[test]
+    fn summary_agrees_with_model(
+        ops in prop::collection::vec(op(), 1..64),
+        first in 0..2048usize,
+        pages in 1..2048usize,
+    ) {
+        MockHandler::reset();
+        let mut pt = PageTable::try_new().unwrap();
+        let mut model = Model::new();
+        for op in &ops {
+            apply(&mut pt, &mut model, op);
+        }
+        let (start, size) = (BASE + first * 0x1000, pages * 0x1000);
+        let mut summary = pt.summarize(VirtAddr::from(start), size).unwrap();
+        // Not modeled: the pages are never written.
+        prop_assert!(!summary.any_dirty);
+        summary.any_dirty = false;
+        prop_assert_eq!(summary, model_summary(&model, start, size));
+    }
+}
+
+/// Mappings that need a new table at every level, and some that share them.
+fn fault_injection_ops() -> Vec<Op> {
+    let flags = MappingFlags::READ | MappingFlags::WRITE;
+    [
+        (BASE, PageSize::Size4K),
+        (BASE + 0x1000, PageSize::Size4K),
+        (BASE + 0x20_0000, PageSize::Size2M),
+        (BASE + 0x40_0000, PageSize::Size4K),
+        (BASE + 0x4000_0000, PageSize::Size1G),
+        (0x80_0000_0000, PageSize::Size4K),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(i, (vaddr, size))| Op::Map {
+        vaddr,
+        paddr: PADDR_BASE + i * PageSize::Size1G as usize,
+        size,
+        flags,
+    })
+    .collect()
+}
+
+#[test]
+fn alloc_failure_at_every_site() {
+    let ops = fault_injection_ops();
+    for n in 0.. {
+        MockHandler::reset();
+        MockHandler::fail_alloc_at(Some(n));
+        let mut injected = false;
+        match PageTable::try_new() {
+            Err(e) => {
+                assert_eq!(e, PagingError::NoMemory);
+                injected = true;
+            }
+            Ok(mut pt) => {
+                let mut model = Model::new();
+                for op in &ops {
+                    let Op::Map {
+                        vaddr,
+                        paddr,
+                        size,
+                        flags,
+                    } = *op
+                    else {
+                        unreachable!()
+                    };
+                    let vaddr = VirtAddr::from(vaddr);
+                    match pt.map(vaddr, PhysAddr::from(paddr), size, flags) {
+                        Ok(tlb) => {
+                            tlb.ignore();
+                            model.map(vaddr, PhysAddr::from(paddr), size, flags);
+                        }
+                        Err(e) => {
+                            assert_eq!(e, PagingError::NoMemory);
+                            injected = true;
+                        }
+                    }
+                    model.check(&pt);
+                    assert_eq!(MockHandler::live_frames(), table_frames(&pt));
+                }
+                // The remaining mappings succeed once the allocator recovers.
+                for op in &ops {
+                    apply(&mut pt, &mut model, op);
+                }
+                assert_eq!(model.len(), ops.len());
+                model.check(&pt);
+            }
+        }
+        assert_eq!(MockHandler::live_frames(), 0, "leaked frames");
+        assert_eq!(MockHandler::stats().failed, injected as usize);
+        if !injected {
+            // All allocation sites have been exercised.
+            assert!(n > 0);
+            break;
+        }
+    }
+}
+
+#[test]
+fn mock_records_flushes() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(BASE);
+    pt.map(
+        vaddr,
+        PhysAddr::from(PADDR_BASE),
+        PageSize::Size4K,
+        MappingFlags::READ,
+    )
+    .unwrap()
+    .flush();
+    pt.protect(vaddr, MappingFlags::READ).unwrap().1.flush();
+    pt.unmap_region(vaddr, 0x1000, false).unwrap().flush_all();
+    // Only `protect` and `unmap_region` flush: creating the mapping needs no
+    // flush on x86_64.
+    assert_eq!(Meta::take_flushes(), [Some(vaddr), None]);
+    assert!(Meta::vaddr_is_valid(BASE));
+}
+
+#[test]
+fn new_mappings_not_flushed() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let vaddr = VirtAddr::from(BASE);
+    let paddr = |va: VirtAddr| PhysAddr::from(va.as_usize() - BASE + PADDR_BASE);
+    pt.map_region(vaddr, paddr, 0x40_0000, MappingFlags::READ, true, true)
+        .unwrap()
+        .flush_all();
+    assert_eq!(Meta::take_flushes(), []);
+    pt.protect_region(vaddr, 0x40_0000, MappingFlags::READ, true)
+        .unwrap()
+        .flush_all();
+    assert_eq!(Meta::take_flushes().len(), 3);
+}
@@ -0,0 +1,119 @@
+//! Narrowing the physical addresses accepted by a page table, at compile time
+//! with [`NarrowPaddr`] and at runtime with
+//! [`PagingMetaData::paddr_is_valid`].
+
+#![cfg(feature = "all-formats")]
+
+use std::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    GenericPTE, MappingFlags, NarrowPaddr, PageSize, PageTableInfo, PagingError,
+};
+use page_table_multiarch::{PagingMetaData, PagingResult};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const VADDR: usize = 0x1000_0000;
+const HUGE_VADDR: usize = 0x4000_0000;
+
+/// Maps a page of `size` at `paddr`, and unmaps it if it succeeds. Huge pages
+/// are mapped where the 4K ones left no tables.
+fn map<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(
+    pt: &mut MockPageTable<M, PTE>,
+    paddr: usize,
+    size: PageSize,
+) -> PagingResult {
+    let vaddr = VirtAddr::from(if size.is_huge() { HUGE_VADDR } else { VADDR });
+    let result = pt.map(vaddr, PhysAddr::from(paddr), size, RW);
+    result.map(|tlb| tlb.ignore())?;
+    pt.unmap(vaddr).unwrap().2.ignore();
+    Ok(())
+}
+
+#[test]
+fn narrowed_at_compile_time() {
+    type Narrow = NarrowPaddr<A64PagingMetaData, 47>;
+    assert_eq!(Narrow::PA_MAX_BITS, 47);
+    assert_eq!(Narrow::PA_MAX_ADDR, (1 << 47) - 1);
+    // Never wider than the architecture.
+    type Wide = NarrowPaddr<A64PagingMetaData, 52>;
+    assert_eq!(Wide::PA_MAX_BITS, 48);
+    assert_eq!(Wide::PA_MAX_ADDR, A64PagingMetaData::PA_MAX_ADDR);
+
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Narrow, A64PTE>::try_new().unwrap();
+    assert_eq!(pt.pa_bits(), 47);
+    let end = 1 << 47;
+    map(&mut pt, end - 0x1000, PageSize::Size4K).unwrap();
+    map(&mut pt, end - 0x20_0000, PageSize::Size2M).unwrap();
+    for (paddr, size) in [(end, PageSize::Size4K), (end, PageSize::Size2M)] {
+        assert_eq!(
+            map(&mut pt, paddr, size),
+            Err(PagingError::InvalidPaddr(PhysAddr::from(paddr)))
+        );
+    }
+    let result = pt.map_region(
+        VADDR.into(),
+        |_| PhysAddr::from(end),
+        0x1000,
+        RW,
+        false,
+        false,
+    );
+    assert_eq!(
+        result.map(|tlb| tlb.ignore()),
+        Err(PagingError::InvalidPaddr(PhysAddr::from(end)))
+    );
+}
+
+thread_local! {
+    /// The physical addresses rejected by [`Probed`].
+    static HOLE: Cell<(usize, usize)> = const { Cell::new((0, 0)) };
+}
+
+/// x86_64 with a hole in the physical address space known at runtime.
+struct Probed;
+
+impl PagingMetaData for Probed {
+    const LEVELS: usize = X64PagingMetaData::LEVELS;
+    const PA_MAX_BITS: usize = X64PagingMetaData::PA_MAX_BITS;
+    const VA_MAX_BITS: usize = X64PagingMetaData::VA_MAX_BITS;
+    type VirtAddr = VirtAddr;
+
+    fn paddr_is_valid(paddr: usize) -> bool {
+        let (start, end) = HOLE.get();
+        !(start..end).contains(&paddr)
+    }
+
+    // `MockMetaData` records the flushes instead.
+    fn flush_tlb(_vaddr: Option<VirtAddr>) {}
+}
+
+#[test]
+fn narrowed_at_runtime() {
+    MockHandler::reset();
+    // Tables are checked too, starting with the root.
+    HOLE.set((0, usize::MAX));
+    let result = MockPageTable::<Probed, X64PTE>::try_new();
+    assert!(matches!(result, Err(PagingError::InvalidPaddr(_))));
+    assert_eq!(MockHandler::live_frames(), 0);
+
+    HOLE.set((0x1_0010_0000, 0x1_0020_0000));
+    let mut pt = MockPageTable::<Probed, X64PTE>::try_new().unwrap();
+    map(&mut pt, 0x1_0000_0000, PageSize::Size4K).unwrap();
+    map(&mut pt, 0x1_0020_0000, PageSize::Size2M).unwrap();
+    // The whole page must be valid, not only its start.
+    for (paddr, size) in [
+        (0x1_0010_0000, PageSize::Size4K),
+        (0x1_0000_0000, PageSize::Size2M),
+    ] {
+        assert_eq!(
+            map(&mut pt, paddr, size),
+            Err(PagingError::InvalidPaddr(PhysAddr::from(paddr)))
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! The changes of the mappings reported to [`PageTable64::set_observer`], a
+//! single event per run of pages changed the same way.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::RefCell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{ChangeOrigin, MappingFlags, PageSize, PageTableObserver, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Mapped(usize, usize, MappingFlags, PageSize, ChangeOrigin),
+    Unmapped(usize, usize, ChangeOrigin),
+    Protected(usize, usize, MappingFlags, MappingFlags, ChangeOrigin),
+}
+
+thread_local! {
+    static EVENTS: RefCell<Vec<Event>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Recorder;
+
+impl PageTableObserver for Recorder {
+    fn mapped(
+        &mut self,
+        vaddr: usize,
+        size: usize,
+        flags: MappingFlags,
+        page_size: PageSize,
+        origin: ChangeOrigin,
+    ) {
+        let event = Event::Mapped(vaddr, size, flags, page_size, origin);
+        EVENTS.with_borrow_mut(|events| events.push(event));
+    }
+
+    fn unmapped(&mut self, vaddr: usize, size: usize, origin: ChangeOrigin) {
+        EVENTS.with_borrow_mut(|events| events.push(Event::Unmapped(vaddr, size, origin)));
+    }
+
+    fn protected(
+        &mut self,
+        vaddr: usize,
+        size: usize,
+        old: MappingFlags,
+        new: MappingFlags,
+        origin: ChangeOrigin,
+    ) {
+        let event = Event::Protected(vaddr, size, old, new, origin);
+        EVENTS.with_borrow_mut(|events| events.push(event));
+    }
+}
+
+fn take_events() -> Vec<Event> {
+    EVENTS.take()
+}
+
+fn observed() -> PageTable {
+    MockHandler::reset();
+    EVENTS.take();
+    let mut pt = PageTable::try_new().unwrap();
+    assert!(pt.set_observer(Box::new(Recorder)).is_none());
+    pt
+}
+
+fn map_region(pt: &mut PageTable, off: usize, size: usize) -> Result<(), PagingError> {
+    let get_paddr = |vaddr: VirtAddr| PhysAddr::from(vaddr.as_usize() - VADDR);
+    pt.map_region(va(off), get_paddr, size, RW, true, false)
+        .map(|tlb| tlb.ignore())
+}
+
+const NONE: ChangeOrigin = ChangeOrigin {
+    rolled_back: false,
+    child: false,
+};
+
+#[test]
+fn regions() {
+    const SIZE_2M: usize = PageSize::Size2M as usize;
+    let mut pt = observed();
+    // 4K pages up to the 2M boundary, then a 2M page.
+    map_region(&mut pt, SIZE_2M - 0x4000, 0x4000 + SIZE_2M).unwrap();
+    assert_eq!(
+        take_events(),
+        [
+            Event::Mapped(VADDR + SIZE_2M - 0x4000, 0x4000, RW, PageSize::Size4K, NONE),
+            Event::Mapped(VADDR + SIZE_2M, SIZE_2M, RW, PageSize::Size2M, NONE),
+        ]
+    );
+
+    pt.protect_region(va(SIZE_2M - 0x4000), 0x4000, MappingFlags::READ, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        take_events(),
+        [Event::Protected(
+            VADDR + SIZE_2M - 0x4000,
+            0x4000,
+            RW,
+            MappingFlags::READ,
+            NONE
+        )]
+    );
+
+    pt.unmap_region(va(SIZE_2M - 0x4000), 0x4000 + SIZE_2M, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        take_events(),
+        [Event::Unmapped(
+            VADDR + SIZE_2M - 0x4000,
+            0x4000 + SIZE_2M,
+            NONE
+        )]
+    );
+}
+
+#[test]
+fn single_pages() {
+    let mut pt = observed();
+    pt.map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        take_events(),
+        [Event::Mapped(VADDR, 0x1000, RW, PageSize::Size4K, NONE)]
+    );
+
+    // To another frame: the old one is no longer mapped.
+    pt.remap(va(0), PhysAddr::from(0x2000), MappingFlags::READ)
+        .unwrap()
+        .1
+        .ignore();
+    assert_eq!(
+        take_events(),
+        [
+            Event::Unmapped(VADDR, 0x1000, NONE),
+            Event::Mapped(VADDR, 0x1000, MappingFlags::READ, PageSize::Size4K, NONE),
+        ]
+    );
+
+    // Failed operations change nothing.
+    assert_eq!(
+        pt.map(va(0), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+            .err(),
+        Some(PagingError::AlreadyMapped)
+    );
+    assert_eq!(take_events(), []);
+
+    assert!(pt.take_observer().is_some());
+    pt.unmap(va(0)).unwrap().2.ignore();
+    assert_eq!(take_events(), []);
+}
+
+#[test]
+fn rolled_back() {
+    let mut pt = observed();
+    // Create the tables first.
+    map_region(&mut pt, 0x10_0000, 0x1000).unwrap();
+    take_events();
+
+    MockHandler::fail_alloc_at(Some(2));
+    assert_eq!(
+        pt.map_alloc(va(0), 0x4000, RW, false).err(),
+        Some(PagingError::NoMemory)
+    );
+    let rolled_back = ChangeOrigin {
+        rolled_back: true,
+        child: false,
+    };
+    assert_eq!(
+        take_events(),
+        [
+            Event::Mapped(VADDR, 0x2000, RW, PageSize::Size4K, NONE),
+            Event::Unmapped(VADDR, 0x2000, rolled_back),
+        ]
+    );
+}
+
+#[test]
+fn clone_cow() {
+    let mut pt = observed();
+    map_region(&mut pt, 0, 0x3000).unwrap();
+    take_events();
+
+    let (mut child, tlb) = pt.clone_cow(va(0), 0x3000).unwrap();
+    tlb.ignore();
+    // The x86_64 entries do not keep `MappingFlags::COW`.
+    let child_origin = ChangeOrigin {
+        rolled_back: false,
+        child: true,
+    };
+    assert_eq!(
+        take_events(),
+        [
+            Event::Protected(VADDR, 0x3000, RW, MappingFlags::READ, NONE),
+            Event::Mapped(
+                VADDR,
+                0x3000,
+                MappingFlags::READ,
+                PageSize::Size4K,
+                child_origin
+            ),
+        ]
+    );
+
+    // The child has no observer.
+    child.unmap(va(0)).unwrap().2.ignore();
+    assert_eq!(take_events(), []);
+}
@@ -0,0 +1,114 @@
+//! Checks [`PageTable64::level_occupancy`] and [`PageTable64::root_entries`]
+//! against the usage counters, and [`PageTable64::table_occupancy_at`].
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, table_frames};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const USER: usize = 0x40_0000_0000;
+const KERNEL: usize = 0xffff_8000_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ;
+
+fn map(pt: &mut PageTable, vaddr: usize, size: PageSize) {
+    pt.map(
+        VirtAddr::from(vaddr),
+        PhysAddr::from(0x4000_0000),
+        size,
+        FLAGS,
+    )
+    .unwrap()
+    .ignore();
+}
+
+/// Checks the occupancy against the counters, with 4K pages mapped at the
+/// last level and 2M pages at the level above.
+fn check(pt: &PageTable, huge_2m: usize) {
+    let occupancy = pt.level_occupancy();
+    let tables = occupancy[..3].iter().sum::<usize>() - huge_2m;
+    assert_eq!(pt.table_frames(), tables + 1);
+    assert_eq!(pt.table_frames(), table_frames(pt));
+    assert_eq!(
+        pt.mapped_bytes(),
+        occupancy[3] * 0x1000 + huge_2m * 0x20_0000
+    );
+}
+
+#[test]
+fn empty() {
+    MockHandler::reset();
+    let pt = PageTable::try_new().unwrap();
+    assert_eq!(pt.level_occupancy(), [0; 4]);
+    assert_eq!(pt.root_entries().count(), 0);
+    check(&pt, 0);
+}
+
+#[test]
+fn user_and_kernel_halves() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, USER, PageSize::Size4K);
+    map(&mut pt, USER + 0x1000, PageSize::Size4K);
+    map(&mut pt, USER + 0x20_0000, PageSize::Size2M);
+    map(&mut pt, KERNEL, PageSize::Size4K);
+    map(&mut pt, KERNEL + 0x80_0000_0000, PageSize::Size4K);
+    assert_eq!(pt.level_occupancy(), [3, 3, 4, 4]);
+    check(&pt, 1);
+
+    let root: Vec<_> = pt.root_entries().map(|(i, _)| i).collect();
+    assert_eq!(root, [0, 256, 257]);
+    let kernel = pt.root_entries().filter(|&(i, _)| i >= 256).count();
+    assert_eq!(kernel, 2);
+    for (_, entry) in pt.root_entries() {
+        assert!(entry.is_present() && !entry.is_huge());
+    }
+}
+
+#[test]
+fn after_unmapping() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, USER, PageSize::Size4K);
+    map(&mut pt, USER + 0x20_0000, PageSize::Size2M);
+    check(&pt, 1);
+    for vaddr in [USER, USER + 0x20_0000] {
+        pt.unmap(VirtAddr::from(vaddr)).unwrap().2.ignore();
+    }
+    // The tables are kept after their last mapping goes away.
+    assert_eq!(pt.level_occupancy(), [1, 1, 1, 0]);
+    assert_eq!(pt.mapped_bytes(), 0);
+    check(&pt, 0);
+}
+
+#[test]
+fn occupancy_of_one_table() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let va = VirtAddr::from;
+    assert_eq!(pt.table_occupancy_at(va(USER), 0), Some((0, 512)));
+    assert_eq!(pt.table_occupancy_at(va(USER), 1), None);
+    assert_eq!(pt.table_occupancy_at(va(USER), 4), None);
+
+    map(&mut pt, USER, PageSize::Size4K);
+    map(&mut pt, USER + 0x3000, PageSize::Size4K);
+    map(&mut pt, USER + 0x20_0000, PageSize::Size2M);
+    pt.set_absent_token(va(USER + 0x1000), 1).unwrap();
+    assert_eq!(pt.table_occupancy_at(va(USER), 0), Some((1, 512)));
+    assert_eq!(pt.table_occupancy_at(va(USER), 1), Some((1, 512)));
+    assert_eq!(pt.table_occupancy_at(va(USER), 2), Some((2, 512)));
+    // The absent entry is in use.
+    assert_eq!(pt.table_occupancy_at(va(USER + 0xf000), 3), Some((3, 512)));
+    // Below a huge page.
+    assert_eq!(
+        pt.table_occupancy_at(va(USER + 0x20_0000), 3),
+        Some((512, 512))
+    );
+    // Next to the populated tables.
+    assert_eq!(pt.table_occupancy_at(va(USER + 0x40_0000), 3), None);
+    assert_eq!(pt.table_occupancy_at(va(KERNEL), 1), None);
+}
@@ -0,0 +1,145 @@
+//! The errors reported to `PagingHandler::on_error`: once per failing
+//! operation, after its rollback, and never on success.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{ElfSegment, MappingFlags, PageSize, PagingError, RegionCursor};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x4000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+/// A page table with the 4K page at `VADDR + 0x3000` mapped.
+fn page_table() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(
+        va(VADDR + 0x3000),
+        PhysAddr::from(0x3000),
+        PageSize::Size4K,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    pt
+}
+
+fn map_region(pt: &mut PageTable, vaddr: usize, size: usize) -> Result<(), PagingError> {
+    pt.map_region(
+        va(vaddr),
+        |v| PhysAddr::from(v.as_usize()),
+        size,
+        RW,
+        true,
+        false,
+    )
+    .map(|tlb| tlb.ignore())
+}
+
+#[test]
+fn not_on_success() {
+    let mut pt = page_table();
+    map_region(&mut pt, VADDR, 0x2000).unwrap();
+    pt.protect_region(va(VADDR), 0x2000, MappingFlags::READ, false)
+        .unwrap()
+        .ignore();
+    pt.unmap_region(va(VADDR), 0x2000, false).unwrap().ignore();
+    assert_eq!(MockHandler::take_errors(), []);
+}
+
+#[test]
+fn once_per_operation() {
+    let mut pt = page_table();
+    let page = VADDR + 0x3000;
+    let result = pt.map(va(page), PhysAddr::from(0), PageSize::Size4K, RW);
+    assert!(result.is_err());
+    let result = pt.protect(va(VADDR), RW);
+    assert!(result.is_err());
+    // Fails on the mapped page, in the middle of the region.
+    assert!(map_region(&mut pt, VADDR, 0x8000).is_err());
+    let result = pt.handle_cow_fault(va(page), |_, _| None);
+    assert!(result.is_err());
+    assert_eq!(
+        MockHandler::take_errors(),
+        [
+            ("map", page, PagingError::AlreadyMapped),
+            ("protect", VADDR, PagingError::NotMapped),
+            ("map_region", VADDR, PagingError::AlreadyMapped),
+            ("handle_cow_fault", page, PagingError::NotCow),
+        ]
+    );
+    // The pages mapped before the failure stay.
+    pt.unmap_region(va(VADDR), 0x4000, false).unwrap().ignore();
+    assert_eq!(MockHandler::take_errors(), []);
+}
+
+#[test]
+fn rollbacks_are_not_reported() {
+    let mut pt = page_table();
+    // The frames of the first two pages, then the third fails.
+    MockHandler::fail_alloc_at(Some(2));
+    let result = pt.map_alloc(va(VADDR), 0x3000, RW, true);
+    assert_eq!(result.err(), Some(PagingError::NoMemory));
+    let result = pt.map_alias(va(VADDR + 0x10_0000), va(VADDR + 0x2000), 0x2000);
+    assert_eq!(result.err(), Some(PagingError::NotMapped));
+
+    let mut other = PageTable::try_new().unwrap();
+    let result = PageTable::broadcast_map(
+        &mut [&mut other, &mut pt],
+        va(VADDR + 0x2000),
+        PhysAddr::from(0x2000),
+        0x2000,
+        RW,
+        false,
+    );
+    assert_eq!(result.err(), Some(PagingError::AlreadyMapped));
+    assert_eq!(other.query(va(VADDR + 0x2000)), Err(PagingError::NotMapped));
+
+    let segment = ElfSegment {
+        vaddr: VADDR + 0x2000,
+        memsz: 0x2000,
+        filesz: 0,
+        paddr: PhysAddr::from(0),
+        flags: 0b110,
+    };
+    let result = pt.map_elf_segments([segment], MappingFlags::empty());
+    assert_eq!(result.err(), Some(PagingError::AlreadyMapped));
+
+    assert_eq!(
+        MockHandler::take_errors(),
+        [
+            ("map_alloc", VADDR, PagingError::NoMemory),
+            ("map_alias", VADDR + 0x10_0000, PagingError::NotMapped),
+            ("broadcast_map", VADDR + 0x2000, PagingError::AlreadyMapped),
+            (
+                "map_elf_segments",
+                VADDR + 0x2000,
+                PagingError::AlreadyMapped
+            ),
+        ]
+    );
+}
+
+#[test]
+fn steps() {
+    let mut pt = page_table();
+    let mut cursor = RegionCursor::new(VADDR + 0x2000, 0x2000);
+    let result = pt.map_region_step(&mut cursor, |v| PhysAddr::from(v.as_usize()), RW, false, 1);
+    result.unwrap().1.ignore();
+    let result = pt.map_region_step(&mut cursor, |v| PhysAddr::from(v.as_usize()), RW, false, 1);
+    assert!(result.is_err());
+    assert_eq!(
+        MockHandler::take_errors(),
+        [("map_region_step", cursor.next(), PagingError::AlreadyMapped)]
+    );
+    assert_eq!(cursor.next(), VADDR + 0x3000);
+}
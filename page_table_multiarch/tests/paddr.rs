@@ -0,0 +1,126 @@
+//! Checks that physical addresses an entry cannot hold are rejected, and
+//! that huge pages are not mapped to frames misaligned to their size.
+
+#![cfg(feature = "all-formats")]
+
+use std::cell::Cell;
+
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+use page_table_entry::{
+    GenericPTE, aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE,
+};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, check_huge_pages};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingHandler, PagingMetaData};
+
+const VADDR: usize = 0x1000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn check<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    let mut map = |paddr: usize, size: PageSize| {
+        let paddr = PhysAddr::from(paddr);
+        let result = pt.map(vaddr, paddr, size, FLAGS).map(|tlb| tlb.ignore());
+        (result, PagingError::InvalidPaddr(paddr))
+    };
+    let too_wide = M::PA_MAX_ADDR + 1;
+    for (paddr, size) in [
+        (too_wide, PageSize::Size4K),
+        (too_wide + 0x1000, PageSize::Size4K),
+        (0x1234, PageSize::Size4K),
+        (0x1000, PageSize::Size2M),
+        (0x20_0000 + 0x1000, PageSize::Size2M),
+        (0x4000_0000 + 0x20_0000, PageSize::Size1G),
+    ] {
+        let (result, err) = map(paddr, size);
+        assert_eq!(result, Err(err), "{:#x} ({:?})", paddr, size);
+    }
+    assert_eq!(pt.query(vaddr), Err(PagingError::NotMapped));
+    assert_eq!(
+        pt.map_region(
+            vaddr,
+            |_| PhysAddr::from(too_wide),
+            0x1000,
+            FLAGS,
+            false,
+            false
+        )
+        .err(),
+        Some(PagingError::InvalidPaddr(PhysAddr::from(too_wide)))
+    );
+
+    // The largest frame is fine.
+    let last = M::PA_MAX_ADDR + 1 - 0x1000;
+    pt.map(vaddr, PhysAddr::from(last), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.query(vaddr).unwrap().0, PhysAddr::from(last));
+    // A rejected remap leaves the mapping unchanged.
+    for paddr in [too_wide, last + 0x800] {
+        let paddr = PhysAddr::from(paddr);
+        assert_eq!(
+            pt.remap(vaddr, paddr, FLAGS).err(),
+            Some(PagingError::InvalidPaddr(paddr))
+        );
+    }
+    assert_eq!(
+        pt.query(vaddr),
+        Ok((PhysAddr::from(last), FLAGS, PageSize::Size4K))
+    );
+
+    // Nor is a huge page moved to a frame not aligned to its size.
+    let huge = VirtAddr::from(VADDR + 0x20_0000);
+    let frame = PhysAddr::from(0x4000_0000);
+    pt.map(huge, frame, PageSize::Size2M, FLAGS)
+        .unwrap()
+        .ignore();
+    let paddr = PhysAddr::from(0x4000_1000);
+    assert_eq!(
+        pt.remap(huge, paddr, FLAGS).err(),
+        Some(PagingError::InvalidPaddr(paddr))
+    );
+    assert_eq!(pt.query(huge), Ok((frame, FLAGS, PageSize::Size2M)));
+    assert_eq!(check_huge_pages(&pt), []);
+
+    // One written by other means is reported.
+    let table = Cell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &PTE| {
+            if level == M::LEVELS - 3 && vaddr == huge.align_down(PageSize::Size1G) {
+                table.set(Some(entry.paddr()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let table = MockHandler::phys_to_virt(table.get().unwrap()).as_mut_ptr() as *mut PTE;
+    let index = (huge.as_usize() >> 21) % 512;
+    unsafe { (*table.add(index)).set_paddr(PhysAddr::from(0x4000_2000)) };
+    assert_eq!(check_huge_pages(&pt), [huge.as_usize()]);
+}
+
+#[test]
+fn x86_64() {
+    check::<X64PagingMetaData, X64PTE>();
+}
+
+#[test]
+fn aarch64() {
+    check::<A64PagingMetaData, A64PTE>();
+}
+
+#[test]
+fn riscv() {
+    check::<Sv39MetaData<VirtAddr>, Rv64PTE>();
+}
+
+#[test]
+fn loongarch64() {
+    check::<LA64MetaData, LA64PTE>();
+}
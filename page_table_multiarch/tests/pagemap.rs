@@ -0,0 +1,113 @@
+//! The entries of the Linux `pagemap` file, checked against values built
+//! from the layout of its documentation.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::interop::PagemapEntry;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, NonPresentPayload, PageSize};
+use page_table_multiarch::{PagingError, PagingHandler};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const HUGE: usize = VADDR + 0x20_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// Swap type 3, offset 0x1234, in the layout of a Linux swap entry.
+const SLOT: u64 = 0x1234 << 5 | 3;
+
+/// `(vaddr, pagemap entry)` of the pages mapped by [`page_table`].
+const GOLDEN: [(usize, u64); 6] = [
+    // Present, PFN 0x80000, soft-dirty.
+    (VADDR, 0x8080_0000_0008_0000),
+    // Present, PFN 0x80001, clean.
+    (VADDR + 0x1000, 0x8000_0000_0008_0001),
+    // Swapped, type 3, offset 0x1234.
+    (VADDR + 0x2000, 0x4000_0000_0002_4683),
+    // File page, not loaded yet.
+    (VADDR + 0x3000, 0x2000_0000_0000_0000),
+    // Not mapped.
+    (VADDR + 0x4000, 0),
+    // The 6th 4K page of a 2M page at PFN 0x90000.
+    (HUGE + 0x5000, 0x8000_0000_0009_0005),
+];
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn page_table() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for (vaddr, paddr) in [(VADDR, 0x8000_0000), (VADDR + 0x1000, 0x8000_1000)] {
+        pt.map(va(vaddr), PhysAddr::from(paddr), PageSize::Size4K, RW)
+            .unwrap()
+            .ignore();
+    }
+    pt.map(va(HUGE), PhysAddr::from(0x9000_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    pt.set_dirty(va(VADDR), true).unwrap();
+    pt.set_dirty(va(VADDR + 0x1000), false).unwrap();
+    pt.set_dirty(va(HUGE), false).unwrap();
+    pt.set_absent_token(va(VADDR + 0x3000), 7).unwrap();
+
+    // Nothing swaps pages out, so the entry is written directly.
+    pt.set_absent_token(va(VADDR + 0x2000), 0).unwrap();
+    let table = Cell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &X64PTE| {
+            if level == 2 && vaddr == va(VADDR) {
+                table.set(Some(entry.paddr()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let table = MockHandler::phys_to_virt(table.get().unwrap()).as_mut_ptr() as *mut X64PTE;
+    unsafe { (*table.add(2)).set_payload(NonPresentPayload::Swap(SLOT)) };
+    pt
+}
+
+#[test]
+fn golden_entries() {
+    let pt = page_table();
+    for (vaddr, expected) in GOLDEN {
+        assert_eq!(pt.pagemap_entry(va(vaddr)), expected, "{vaddr:#x}");
+        assert_eq!(pt.pagemap_entry(va(vaddr + 0xfff)), expected, "{vaddr:#x}");
+    }
+    let swapped = PagemapEntry(pt.pagemap_entry(va(VADDR + 0x2000)));
+    assert_eq!(swapped.swap(), Some((3, 0x1234)));
+    assert_eq!(swapped.pfn(), None);
+    assert_eq!(PagemapEntry(GOLDEN[0].1).pfn(), Some(0x80000));
+}
+
+#[test]
+fn bulk() {
+    let pt = page_table();
+    let mut out = [u64::MAX; 5];
+    pt.fill_pagemap(va(VADDR), &mut out).unwrap();
+    let expected: Vec<_> = GOLDEN[..5].iter().map(|&(_, entry)| entry).collect();
+    assert_eq!(out, expected[..]);
+
+    // Across the end of the 2M page.
+    let mut out = [u64::MAX; 4];
+    pt.fill_pagemap(va(HUGE + 0x1f_e000), &mut out).unwrap();
+    assert_eq!(out, [0x8000_0000_0009_01fe, 0x8000_0000_0009_01ff, 0, 0]);
+
+    assert_eq!(
+        pt.fill_pagemap(va(VADDR + 0x10), &mut out),
+        Err(PagingError::NotAligned)
+    );
+    assert_eq!(
+        pt.fill_pagemap(va(0x7fff_ffff_f000), &mut out),
+        Err(PagingError::InvalidVaddr(0x7fff_ffff_f000))
+    );
+}
@@ -0,0 +1,73 @@
+//! Checks the entries cleared with the `debug-poison` feature.
+
+#![cfg(all(target_arch = "x86_64", feature = "debug-poison"))]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, poisoned_entries, table_frames};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn map(pt: &mut PageTable, vaddr: usize, size: PageSize) {
+    pt.map(
+        VirtAddr::from(vaddr),
+        PhysAddr::from(0x4000_0000),
+        size,
+        FLAGS,
+    )
+    .unwrap()
+    .ignore();
+}
+
+#[test]
+fn unmap_poisons() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, VADDR, PageSize::Size4K);
+    map(&mut pt, VADDR + 0x1000, PageSize::Size4K);
+    map(&mut pt, VADDR + 0x20_0000, PageSize::Size2M);
+    assert_eq!(poisoned_entries(&pt), []);
+
+    pt.unmap(VirtAddr::from(VADDR)).unwrap().2.ignore();
+    pt.unmap(VirtAddr::from(VADDR + 0x20_0000))
+        .unwrap()
+        .2
+        .ignore();
+    assert_eq!(poisoned_entries(&pt), [(2, VADDR + 0x20_0000), (3, VADDR)]);
+    // Poisoned entries are unmapped, not absent.
+    for vaddr in [VADDR, VADDR + 0x20_0000] {
+        assert_eq!(pt.query(VirtAddr::from(vaddr)), Err(PagingError::NotMapped));
+        assert_eq!(pt.absent_token(VirtAddr::from(vaddr)), None);
+    }
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+
+    // Mapping over them works, also with a table in place of a huge page.
+    map(&mut pt, VADDR, PageSize::Size4K);
+    map(&mut pt, VADDR + 0x20_0000, PageSize::Size4K);
+    assert_eq!(poisoned_entries(&pt), []);
+    assert_eq!(pt.table_frames(), 5);
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), 0);
+}
+
+#[test]
+fn clear_copy_range_poisons() {
+    MockHandler::reset();
+    let mut kernel = PageTable::try_new().unwrap();
+    map(&mut kernel, VADDR, PageSize::Size4K);
+    let mut pt = PageTable::try_new().unwrap();
+    pt.copy_from(&kernel, VirtAddr::from(VADDR), 0x1000);
+    pt.clear_copy_range(VirtAddr::from(VADDR), 0x1000);
+    // The root entry covers the first 512G.
+    assert_eq!(poisoned_entries(&pt), [(0, 0)]);
+    assert_eq!(pt.query(VirtAddr::from(VADDR)), Err(PagingError::NotMapped));
+    // Dropping does not follow the poisoned entry.
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), table_frames(&kernel));
+}
@@ -0,0 +1,160 @@
+//! The bitmap of the granules of a region holding present pages, exported by
+//! a walk of the tables and kept current as an observer.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    AllocOptions, MappingFlags, PageSize, PagingError, PagingHandler, PresentBitmap,
+};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// Two 4K pages, a 2M page, and a 4K page at the end of the next 2M block.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let get_paddr = |vaddr: VirtAddr| PhysAddr::from(vaddr.as_usize() - VADDR);
+    let pages = [
+        (0, 0x2000),
+        (SIZE_2M, SIZE_2M),
+        (3 * SIZE_2M - 0x1000, 0x1000),
+    ];
+    for (off, size) in pages {
+        pt.map_region(va(off), get_paddr, size, RW, true, false)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+fn export(pt: &PageTable, off: usize, size: usize, granularity: PageSize) -> Vec<AtomicU64> {
+    let out: Vec<_> = (0..PresentBitmap::words(size, granularity))
+        .map(|_| AtomicU64::new(0))
+        .collect();
+    pt.export_present_bitmap(va(off), size, granularity, false, &out)
+        .unwrap();
+    out
+}
+
+fn load(words: &[AtomicU64]) -> Vec<u64> {
+    words
+        .iter()
+        .map(|word| word.load(Ordering::Relaxed))
+        .collect()
+}
+
+#[test]
+fn granules_of_4k() {
+    let pt = mapped();
+    let out = load(&export(&pt, 0, 4 * SIZE_2M, PageSize::Size4K));
+    assert_eq!(out.len(), 4 * 512 / 64);
+    assert_eq!(out[0], 0b11);
+    assert!(out[1..8].iter().all(|&word| word == 0));
+    // Every 4K granule of the huge page.
+    assert!(out[8..16].iter().all(|&word| word == u64::MAX));
+    assert_eq!(out[23], 1 << 63);
+    assert!(out[24..].iter().all(|&word| word == 0));
+
+    // A region starting and ending in the huge page.
+    let out = export(&pt, SIZE_2M + 0x1000, 0x2000, PageSize::Size4K);
+    assert_eq!(load(&out), [0b11]);
+}
+
+#[test]
+fn larger_granules() {
+    let pt = mapped();
+    // Any page sets its granule, and the huge page exactly one.
+    let out = export(&pt, 0, 8 * SIZE_2M, PageSize::Size2M);
+    assert_eq!(load(&out), [0b111]);
+    let out = export(&pt, 0, 4 * PageSize::Size1G as usize, PageSize::Size1G);
+    assert_eq!(load(&out), [0b1]);
+
+    let words = export(&pt, 0, 8 * SIZE_2M, PageSize::Size2M);
+    let bitmap = PresentBitmap::new(VADDR, 8 * SIZE_2M, PageSize::Size2M, &words);
+    assert!(bitmap.contains(VADDR + 0x1234));
+    assert!(bitmap.contains(VADDR + 3 * SIZE_2M - 1));
+    assert!(!bitmap.contains(VADDR + 3 * SIZE_2M));
+    assert!(!bitmap.contains(VADDR - 1));
+    assert!(!bitmap.contains(VADDR + 8 * SIZE_2M));
+}
+
+#[test]
+fn absent_entries() {
+    let mut pt = mapped();
+    pt.set_absent_token(va(0x4000), 7).unwrap();
+    let options = AllocOptions::new().guarded(1, 0);
+    pt.map_alloc_with(va(0x6000), 0x1000, RW, options)
+        .unwrap()
+        .1
+        .ignore();
+    let out = [AtomicU64::new(0)];
+    pt.export_present_bitmap(va(0), 0x8000, PageSize::Size4K, false, &out)
+        .unwrap();
+    assert_eq!(load(&out), [0b100_0011]);
+    // The guard page at 0x5000 is never counted.
+    pt.export_present_bitmap(va(0), 0x8000, PageSize::Size4K, true, &out)
+        .unwrap();
+    assert_eq!(load(&out), [0b101_0011]);
+}
+
+#[test]
+fn checked() {
+    let pt = mapped();
+    let out = [const { AtomicU64::new(u64::MAX) }; 3];
+    assert_eq!(
+        pt.export_present_bitmap(va(0x1000), SIZE_2M, PageSize::Size2M, false, &out),
+        Err(PagingError::NotAligned)
+    );
+    assert_eq!(
+        pt.export_present_bitmap(va(0), 0xc1000, PageSize::Size4K, false, &out),
+        Err(PagingError::BufferTooSmall { needed: 4 })
+    );
+    assert_eq!(load(&out), [u64::MAX; 3]);
+    // The words after the bitmap are left alone.
+    pt.export_present_bitmap(va(0), 0x80000, PageSize::Size4K, false, &out)
+        .unwrap();
+    assert_eq!(load(&out), [0b11, 0, u64::MAX]);
+}
+
+#[test]
+fn kept_current() {
+    static WORDS: [AtomicU64; 1] = [const { AtomicU64::new(0) }; 1];
+    let mut pt = mapped();
+    let size = 8 * SIZE_2M;
+    pt.export_present_bitmap(va(0), size, PageSize::Size2M, false, &WORDS)
+        .unwrap();
+    let observer = PresentBitmap::new(VADDR, size, PageSize::Size2M, &WORDS);
+    pt.set_observer(Box::new(observer));
+    // Read while the page table keeps the words current.
+    let bitmap = PresentBitmap::new(VADDR, size, PageSize::Size2M, &WORDS);
+
+    let frame = MockHandler::alloc_frame().unwrap();
+    pt.map(va(5 * SIZE_2M), frame, PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    assert!(bitmap.contains(VADDR + 5 * SIZE_2M));
+    // A part of a granule unmapped leaves it set.
+    pt.unmap_region(va(0), 0x1000, false).unwrap().ignore();
+    // Unmapping all of it clears it.
+    pt.unmap_region(va(SIZE_2M), SIZE_2M, false)
+        .unwrap()
+        .ignore();
+    assert!(!bitmap.contains(VADDR + SIZE_2M));
+    assert!(pt.take_observer().is_some());
+    assert_eq!(load(bitmap.bits()), [0b10_0101]);
+    assert_eq!(load(&export(&pt, 0, size, PageSize::Size2M)), [0b10_0101]);
+}
@@ -0,0 +1,160 @@
+//! Pages protected with empty flags, which deny every access but keep their
+//! frame, like `mprotect(PROT_NONE)`.
+
+#![cfg(feature = "all-formats")]
+
+use core::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    AbsentEntry, AccessContext, AccessType, AccessVerdict, GenericPTE, MappingFlags, PageSize,
+    PagingError, PagingHandler, PagingMetaData,
+};
+
+type Sv39 = Sv39MetaData<VirtAddr>;
+
+const VADDR: usize = 0x4000_0000;
+const PADDR: usize = 0x8000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const NONE: MappingFlags = MappingFlags::empty();
+const ACCESSES: [AccessType; 3] = [AccessType::Read, AccessType::Write, AccessType::Execute];
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(off: usize) -> PhysAddr {
+    PhysAddr::from(PADDR + off)
+}
+
+fn mapped<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>() -> MockPageTable<M, PTE> {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    pt.map(va(0), pa(0), PageSize::Size4K, RW).unwrap().ignore();
+    pt
+}
+
+fn round_trip<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>() {
+    let mut pt = mapped::<M, PTE>();
+    let mapped_bytes = pt.mapped_bytes();
+    let kernel = AccessContext::kernel();
+
+    pt.protect(va(0), NONE).unwrap().1.ignore();
+    // The frame is still reported, without flags.
+    assert_eq!(pt.query(va(0x123)), Ok((pa(0x123), NONE, PageSize::Size4K)));
+    for access in ACCESSES {
+        assert_eq!(
+            pt.check_access(va(0), access, kernel),
+            AccessVerdict::Denied
+        );
+    }
+    assert_eq!(pt.mapped_bytes(), mapped_bytes);
+    // Nor can it be mapped over.
+    assert_eq!(
+        pt.map(va(0), pa(0x1000), PageSize::Size4K, RW)
+            .map(|tlb| tlb.ignore()),
+        Err(PagingError::AlreadyMapped)
+    );
+    pt.protect(va(0), NONE).unwrap().1.ignore();
+    assert_eq!(pt.query(va(0)), Ok((pa(0), NONE, PageSize::Size4K)));
+
+    // Accessible again, without the frame given back.
+    pt.protect(va(0), MappingFlags::READ).unwrap().1.ignore();
+    assert_eq!(
+        pt.query(va(0x123)),
+        Ok((pa(0x123), MappingFlags::READ, PageSize::Size4K))
+    );
+    let verdict = |access| pt.check_access(va(0), access, kernel);
+    assert_eq!(verdict(AccessType::Read), AccessVerdict::Allowed);
+    assert_eq!(verdict(AccessType::Execute), AccessVerdict::Denied);
+    assert_eq!(pt.mapped_bytes(), mapped_bytes);
+}
+
+#[test]
+fn round_trip_x86_64() {
+    round_trip::<X64PagingMetaData, X64PTE>();
+}
+
+#[test]
+fn round_trip_riscv() {
+    round_trip::<Sv39, Rv64PTE>();
+}
+
+#[test]
+fn round_trip_aarch64() {
+    round_trip::<A64PagingMetaData, A64PTE>();
+}
+
+#[test]
+fn round_trip_loongarch64() {
+    round_trip::<LA64MetaData, LA64PTE>();
+}
+
+#[test]
+fn non_present_entry() {
+    let mut pt = mapped::<X64PagingMetaData, X64PTE>();
+    pt.protect(va(0), NONE).unwrap().1.ignore();
+    // The table of the last level.
+    let table = Cell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, _, entry: &X64PTE| {
+            if level == 2 {
+                table.set(Some(entry.paddr()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let ptr = MockHandler::phys_to_virt(table.get().unwrap()).as_ptr() as *const X64PTE;
+    let entry = unsafe { *ptr };
+    assert!(!entry.is_present());
+    assert_eq!(
+        entry.absent(),
+        Some(AbsentEntry::Inaccessible(PADDR as u64 >> 12))
+    );
+
+    // Unmapping returns the frame.
+    let (paddr, size, tlb) = pt.unmap(va(0)).unwrap();
+    tlb.ignore();
+    assert_eq!((paddr, size), (pa(0), PageSize::Size4K));
+    assert_eq!(pt.mapped_bytes(), 0);
+    assert_eq!(pt.query(va(0)), Err(PagingError::NotMapped));
+}
+
+#[test]
+fn huge_pages() {
+    let size = PageSize::Size2M;
+    let mut x86 = mapped::<X64PagingMetaData, X64PTE>();
+    x86.map(va(size as usize), pa(size as usize), size, RW)
+        .unwrap()
+        .ignore();
+    // Only the format keeping the entry present can make it inaccessible.
+    assert_eq!(
+        x86.protect(va(size as usize), NONE)
+            .map(|(s, tlb)| (s, tlb.ignore())),
+        Err(PagingError::UnsupportedPageSize)
+    );
+    assert_eq!(x86.query(va(size as usize)).unwrap().1, RW);
+
+    let mut la = mapped::<LA64MetaData, LA64PTE>();
+    la.map(va(size as usize), pa(size as usize), size, RW)
+        .unwrap()
+        .ignore();
+    la.protect(va(size as usize), NONE).unwrap().1.ignore();
+    assert_eq!(
+        la.query(va(size as usize + 0x1234)),
+        Ok((pa(size as usize + 0x1234), NONE, size))
+    );
+    la.protect(va(size as usize), RW).unwrap().1.ignore();
+    assert_eq!(
+        la.query(va(size as usize)),
+        Ok((pa(size as usize), RW, size))
+    );
+}
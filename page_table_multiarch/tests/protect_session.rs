@@ -0,0 +1,135 @@
+//! Write-protect windows over a region, whose written pages are saved on
+//! their first write fault and whose other pages get their flags back.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, ProtectSession};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const SIZE: usize = 0x20_4000;
+/// The read-only page, after the huge page.
+const RO: usize = VADDR + 0x20_1000;
+const RW: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::USER);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(vaddr: usize) -> PhysAddr {
+    PhysAddr::from(vaddr - VADDR + 0x8000_0000)
+}
+
+/// Maps a 2M page and 4 4K pages, one of which is read-only.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let flags = |vaddr: VirtAddr| match vaddr.as_usize() {
+        RO => MappingFlags::READ | MappingFlags::USER,
+        _ => RW,
+    };
+    pt.map_region_with(va(VADDR), |v| pa(v.as_usize()), SIZE, flags, true)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+fn flags(pt: &PageTable, vaddr: usize) -> MappingFlags {
+    pt.query(va(vaddr)).unwrap().1
+}
+
+#[test]
+fn write_protect_fault_restore() {
+    let mut pt = mapped();
+    let mut store = [0u64; 9];
+    let (mut session, tlb) =
+        ProtectSession::begin(&mut pt, va(VADDR), SIZE, &mut store[..]).unwrap();
+    assert!(tlb.is_needed());
+    tlb.ignore();
+    let ro = MappingFlags::READ | MappingFlags::USER;
+    for vaddr in [VADDR, VADDR + 0x1f_f000, RO, VADDR + 0x20_3000] {
+        assert_eq!(flags(&pt, vaddr), ro);
+    }
+    // The huge page was split to protect it page by page.
+    assert_eq!(pt.query(va(VADDR)).unwrap().2, PageSize::Size4K);
+
+    let mut saved = Vec::new();
+    for vaddr in [VADDR + 0x1234, VADDR + 0x20_3000] {
+        session
+            .fault(&mut pt, va(vaddr), |page, paddr| saved.push((page, paddr)))
+            .unwrap()
+            .ignore();
+    }
+    let page = VADDR + 0x1000;
+    let last = VADDR + 0x20_3000;
+    assert_eq!(saved, [(va(page), pa(page)), (va(last), pa(last))]);
+    assert_eq!(flags(&pt, page), RW);
+    assert_eq!(flags(&pt, VADDR), ro);
+
+    // Not protected by the session: read-only, resolved already, or outside.
+    let mut fault = |vaddr: usize| {
+        session
+            .fault(&mut pt, va(vaddr), |_, _| panic!())
+            .map(|_| ())
+    };
+    assert_eq!(fault(RO), Err(PagingError::NotLogged));
+    assert_eq!(fault(page), Err(PagingError::NotLogged));
+    assert_eq!(fault(VADDR + SIZE), Err(PagingError::NotLogged));
+    assert_eq!(fault(VADDR - 0x1000), Err(PagingError::NotLogged));
+
+    let (faulted, tlb) = session.end(&mut pt).unwrap();
+    tlb.ignore();
+    assert_eq!(faulted, 2);
+    for vaddr in [VADDR, page, VADDR + 0x1f_f000, VADDR + 0x20_2000, last] {
+        assert_eq!(flags(&pt, vaddr), RW);
+    }
+    assert_eq!(flags(&pt, RO), ro);
+    assert_eq!(store, [0; 9]);
+}
+
+#[test]
+fn changes_during_the_session() {
+    let mut pt = mapped();
+    let mut store = [u64::MAX; 9];
+    let (session, tlb) = ProtectSession::begin(&mut pt, va(VADDR), SIZE, &mut store[..]).unwrap();
+    tlb.ignore();
+    // One page is unmapped, another made executable and read-only.
+    pt.unmap(va(VADDR)).unwrap().2.ignore();
+    let rx = MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER;
+    pt.protect(va(VADDR + 0x1000), rx).unwrap().1.ignore();
+
+    let (faulted, tlb) = session.end(&mut pt).unwrap();
+    tlb.ignore();
+    assert_eq!(faulted, 0);
+    assert_eq!(pt.query(va(VADDR)), Err(PagingError::NotMapped));
+    // The original flags are the current ones with write access.
+    assert_eq!(flags(&pt, VADDR + 0x1000), rx | MappingFlags::WRITE);
+    assert_eq!(flags(&pt, VADDR + 0x2000), RW);
+}
+
+#[test]
+fn errors() {
+    let mut pt = mapped();
+    let mut store = [0u64; 9];
+    let result = ProtectSession::begin(&mut pt, va(VADDR + 0x10), 0x1000, &mut store[..]);
+    assert_eq!(result.err(), Some(PagingError::NotAligned));
+    assert_eq!(flags(&pt, VADDR), RW);
+}
+
+#[test]
+#[should_panic = "not the page table of the session"]
+fn other_page_table() {
+    let mut pt = mapped();
+    let mut other = PageTable::try_new().unwrap();
+    let mut store = [0u64; 1];
+    let (session, tlb) = ProtectSession::begin(&mut pt, va(VADDR), 0x1000, &mut store[..]).unwrap();
+    tlb.ignore();
+    let _ = session.end(&mut other);
+}
@@ -0,0 +1,189 @@
+//! The kernel and user page tables of a process with page table isolation,
+//! kept in sync for the user half and the trampolines, also across forks.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::PtiPair;
+use page_table_multiarch::mock::{MockHandler, MockMetaData};
+use page_table_multiarch::riscv::Sv48MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingError, PagingMetaData};
+
+type Pair<M = X64PagingMetaData, PTE = X64PTE> = PtiPair<MockMetaData<M>, PTE, MockHandler>;
+
+const USER: usize = 0x40_0000_0000;
+/// In the next top-level entry, whose table is created after the first.
+const STACK: usize = 0x7fff_ffff_0000;
+const ENTRY: usize = 0xffff_ff80_0000_0000;
+const KERNEL: usize = 0xffff_ff80_4000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const USER_RW: MappingFlags = RW.union(MappingFlags::USER);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(paddr: usize) -> PhysAddr {
+    PhysAddr::from(paddr)
+}
+
+fn map<M, PTE>(
+    pair: &mut Pair<M, PTE>,
+    vaddr: usize,
+    paddr: usize,
+    size: usize,
+    flags: MappingFlags,
+) where
+    M: PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: GenericPTE,
+{
+    pair.map_region(
+        va(vaddr),
+        |v| pa(v.as_usize() - vaddr + paddr),
+        size,
+        flags,
+        true,
+    )
+    .unwrap()
+    .ignore();
+}
+
+/// Maps user pages, the entry code and other kernel pages, and mirrors the
+/// entry code.
+fn process<M, PTE>() -> Pair<M, PTE>
+where
+    M: PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: GenericPTE,
+{
+    let mut pair = Pair::try_new().unwrap();
+    map(&mut pair, USER, 0x20_0000, 0x20_1000, USER_RW);
+    map(&mut pair, STACK, 0x1000, 0x1000, USER_RW);
+    let rx = MappingFlags::READ | MappingFlags::EXECUTE;
+    map(&mut pair, ENTRY, 0x80_0000, 0x2000, rx);
+    map(&mut pair, KERNEL, 0x4000_0000, 0x20_0000, RW);
+    pair.map_trampoline(va(ENTRY), 0x2000).unwrap().ignore();
+    pair
+}
+
+#[test]
+fn user_half_in_both() {
+    MockHandler::reset();
+    let mut pair: Pair = process();
+    assert_ne!(pair.user_root_paddr(), pair.kernel_root_paddr());
+    assert_eq!(pair.kernel_root_paddr(), pair.kernel().root_paddr());
+    assert_eq!(pair.user_root_paddr(), pair.user().root_paddr());
+    for vaddr in [USER, USER + 0x20_0000, STACK] {
+        let mapping = pair.kernel().query(va(vaddr)).unwrap();
+        assert_eq!(pair.user().query(va(vaddr)), Ok(mapping));
+    }
+    assert_eq!(pair.user().query(va(USER)).unwrap().2, PageSize::Size2M);
+
+    let page = va(USER + 0x20_0000);
+    pair.protect_region(page, 0x1000, MappingFlags::READ | MappingFlags::USER)
+        .unwrap()
+        .ignore();
+    let flags = pair.user().query(page).unwrap().1;
+    assert_eq!(flags, MappingFlags::READ | MappingFlags::USER);
+    pair.unmap_region(va(STACK), 0x1000).unwrap().ignore();
+    assert_eq!(pair.user().query(va(STACK)), Err(PagingError::NotMapped));
+
+    // The tables of the user half are accounted to the kernel page table.
+    assert_eq!(pair.user().mapped_bytes(), 0x2000);
+    assert_eq!(pair.kernel().mapped_bytes(), 0x20_1000 + 0x2000 + 0x20_0000);
+}
+
+#[test]
+fn trampolines() {
+    MockHandler::reset();
+    let mut pair: Pair = process();
+    let entry = pair.kernel().query(va(ENTRY + 0x1000)).unwrap();
+    assert_eq!(pair.user().query(va(ENTRY + 0x1000)), Ok(entry));
+    assert_eq!(pair.user().query(va(KERNEL)), Err(PagingError::NotMapped));
+
+    // Changes through the pair apply to the mirrors.
+    pair.protect_region(va(ENTRY), 0x1000, MappingFlags::READ)
+        .unwrap()
+        .ignore();
+    assert_eq!(pair.user().query(va(ENTRY)).unwrap().1, MappingFlags::READ);
+    pair.unmap_region(va(ENTRY + 0x1000), 0x1000)
+        .unwrap()
+        .ignore();
+    assert_eq!(
+        pair.user().query(va(ENTRY + 0x1000)),
+        Err(PagingError::NotMapped)
+    );
+    pair.unmap_region(va(KERNEL), 0x20_0000).unwrap().ignore();
+
+    // Removing the mirror keeps the mapping of the kernel.
+    pair.unmap_trampoline(va(ENTRY), 0x1000).unwrap().ignore();
+    assert_eq!(pair.user().query(va(ENTRY)), Err(PagingError::NotMapped));
+    assert!(pair.kernel().query(va(ENTRY)).is_ok());
+    assert_eq!(pair.user().mapped_bytes(), 0);
+}
+
+#[test]
+fn errors() {
+    MockHandler::reset();
+    let mut pair: Pair = process();
+    let result = pair.map_trampoline(va(USER), 0x1000);
+    assert_eq!(result.err(), Some(PagingError::WrongSpace));
+    let result = pair.map_trampoline(va(KERNEL + 0x20_0000), 0x1000);
+    assert_eq!(result.err(), Some(PagingError::NotMapped));
+    let result = pair.map_trampoline(va(ENTRY), 0x1000);
+    assert_eq!(result.err(), Some(PagingError::AlreadyMapped));
+    let result = pair.unmap_region(va(0x7fff_ffff_f000), 0x2000);
+    assert_eq!(
+        result.err(),
+        Some(PagingError::InvalidVaddr(0x7fff_ffff_f000))
+    );
+    let result = pair.handle_cow_fault(va(KERNEL), |_, _| None);
+    assert_eq!(result.err(), Some(PagingError::WrongSpace));
+    let result = pair.clone_cow(va(ENTRY), 0x1000);
+    assert_eq!(result.err(), Some(PagingError::WrongSpace));
+    // The mirrored pages are the ones mapped with the kernel page table.
+    assert_eq!(pair.user().mapped_bytes(), 0x2000);
+}
+
+#[test]
+fn fork() {
+    MockHandler::reset();
+    let live = MockHandler::live_frames();
+    // Copy-on-write needs a software bit in the entries.
+    let mut parent: Pair<Sv48MetaData<VirtAddr>, Rv64PTE> = process();
+    let (mut child, tlb) = parent.clone_cow(va(USER), 0x20_1000).unwrap();
+    tlb.ignore();
+    assert!(child.kernel().query(va(KERNEL)).is_err());
+    let entry = parent.user().query(va(ENTRY)).unwrap();
+    assert_eq!(child.user().query(va(ENTRY)), Ok(entry));
+
+    // Both page tables of each pair see the pages copy-on-write, counted once
+    // for the child.
+    let page = va(USER + 0x20_0000);
+    for pair in [&parent, &child] {
+        let flags = pair.user().query(page).unwrap().1;
+        assert!(flags.contains(MappingFlags::COW));
+        assert_eq!(pair.kernel().query(page).unwrap().1, flags);
+    }
+    assert_eq!(MockHandler::refs(pa(0x40_0000)), Some(2));
+
+    // The fault of the child, in user space, copies the page for both of its
+    // page tables.
+    child
+        .handle_cow_fault(page, |_, _| Some(pa(0x100_0000)))
+        .unwrap()
+        .ignore();
+    for pt in [child.user(), child.kernel()] {
+        assert_eq!(
+            pt.query(page).unwrap(),
+            (pa(0x100_0000), USER_RW, PageSize::Size4K)
+        );
+    }
+    assert_eq!(MockHandler::refs(pa(0x40_0000)), Some(1));
+    assert_eq!(parent.user().query(page).unwrap().0, pa(0x40_0000));
+
+    drop(child);
+    drop(parent);
+    assert_eq!(MockHandler::live_frames(), live);
+}
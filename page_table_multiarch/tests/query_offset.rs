@@ -0,0 +1,102 @@
+//! Checks that `query` translates the exact byte of the address, including
+//! its offset within huge pages and contiguous groups.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
+use page_table_entry::x86_64::{MemType, PatLayout, X64PTE};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{AnyPageTable, GenericPTE, MappingFlags, PageSize, PagingMetaData};
+
+use PageSize::*;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// `(vaddr, paddr, size)` of the pages mapped by [`check`].
+const PAGES: [(usize, usize, PageSize); 4] = [
+    (0x1000_0000, 0x8765_5000, Size4K),
+    (0x1001_0000, 0x8791_0000, Size64K),
+    (0x1020_0000, 0x8aa0_0000, Size2M),
+    (0x4000_0000, 0x1_4000_0000, Size1G),
+];
+
+/// The offsets of the first, a middle, and the last byte of a page.
+fn offsets(size: PageSize) -> [usize; 3] {
+    let size = size as usize;
+    [0, size / 2 + 0x123, size - 1]
+}
+
+/// Maps [`PAGES`] with `flags`, skipping 64K groups if `M` has none, and
+/// checks the translation of bytes in each of them.
+fn check<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>(flags: MappingFlags) {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    let pages = PAGES
+        .into_iter()
+        .filter(|&(_, _, size)| size != Size64K || PTE::CONTIGUOUS_HINT);
+    for (vaddr, paddr, size) in pages.clone() {
+        pt.map(vaddr.into(), paddr.into(), size, flags)
+            .unwrap()
+            .ignore();
+    }
+    let any: &dyn AnyPageTable = &pt;
+    for (vaddr, paddr, size) in pages {
+        for off in offsets(size) {
+            let expected = (PhysAddr::from(paddr + off), flags, size);
+            let vaddr = vaddr + off;
+            assert_eq!(pt.query(vaddr.into()), Ok(expected), "{vaddr:#x}");
+            assert_eq!(any.query(vaddr), Ok(expected), "{vaddr:#x}");
+            // The base of the frame is always one alignment away.
+            let base = expected.0.align_down(size);
+            assert_eq!(base, PhysAddr::from(paddr));
+        }
+    }
+}
+
+#[test]
+fn every_arch() {
+    check::<X64PagingMetaData, X64PTE>(RW);
+    check::<A64PagingMetaData, A64PTE>(RW);
+    check::<Sv39MetaData<VirtAddr>, Rv64PTE>(RW);
+    check::<Sv48MetaData<VirtAddr>, Rv64PTE>(RW);
+    check::<LA64MetaData, LA64PTE>(RW);
+}
+
+/// A layout whose uncacheable entry needs the PAT bit.
+struct HighUncacheable;
+
+impl PatLayout for HighUncacheable {
+    const ENTRIES: [MemType; 8] = {
+        use MemType::*;
+        [
+            WriteBack,
+            WriteThrough,
+            UncachedMinus,
+            WriteCombining,
+            Uncacheable,
+            WriteThrough,
+            UncachedMinus,
+            WriteCombining,
+        ]
+    };
+}
+
+#[test]
+fn x86_pat_bit_of_huge_pages() {
+    // The PAT bit of 2M and 1G pages is where bit 12 of the address is in 4K
+    // pages, and must not leak into the translation.
+    let device = RW | MappingFlags::DEVICE;
+    check::<X64PagingMetaData, X64PTE<HighUncacheable>>(device);
+}
+
+#[test]
+fn x86_pat_bit_of_4k_pages() {
+    // The PAT bit of 4K pages is the page size bit of the entries above, and
+    // must not make them read as huge pages, whose address lacks bit 12.
+    check::<X64PagingMetaData, X64PTE>(RW | MappingFlags::UNCACHED);
+}
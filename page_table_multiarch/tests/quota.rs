@@ -0,0 +1,117 @@
+//! Checks the limits set by [`PageTable64::set_limits`].
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, table_frames};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, QuotaKind};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const BASE: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ;
+
+fn map(pt: &mut PageTable, vaddr: usize, size: PageSize) -> Result<(), PagingError> {
+    let paddr = PhysAddr::from(vaddr - BASE);
+    pt.map(VirtAddr::from(vaddr), paddr, size, FLAGS)
+        .map(|tlb| tlb.ignore())
+}
+
+#[test]
+fn mapped_bytes_limit() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let limit = 0x20_0000 + 0x2000;
+    pt.set_limits(limit, usize::MAX);
+
+    // Fill to exactly the limit, with a huge page counted by its size.
+    map(&mut pt, BASE + 0x20_0000, PageSize::Size2M).unwrap();
+    map(&mut pt, BASE, PageSize::Size4K).unwrap();
+    map(&mut pt, BASE + 0x1000, PageSize::Size4K).unwrap();
+    assert_eq!(pt.mapped_bytes(), limit);
+
+    let err = PagingError::QuotaExceeded {
+        kind: QuotaKind::MappedBytes,
+        limit,
+        requested: limit + 0x1000,
+    };
+    assert_eq!(map(&mut pt, BASE + 0x2000, PageSize::Size4K), Err(err));
+    let tlb = pt.map_region(
+        VirtAddr::from(BASE + 0x2000),
+        |va| PhysAddr::from(va.as_usize() - BASE),
+        0x1000,
+        FLAGS,
+        false,
+        false,
+    );
+    assert_eq!(tlb.err(), Some(err));
+    assert_eq!(
+        pt.query(VirtAddr::from(BASE + 0x2000)),
+        Err(PagingError::NotMapped)
+    );
+
+    pt.unmap(VirtAddr::from(BASE)).unwrap().2.ignore();
+    assert_eq!(pt.mapped_bytes(), limit - 0x1000);
+    map(&mut pt, BASE + 0x2000, PageSize::Size4K).unwrap();
+    assert_eq!(pt.mapped_bytes(), limit);
+
+    pt.unmap_region(VirtAddr::from(BASE + 0x20_0000), 0x20_0000, false)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.mapped_bytes(), 0x2000);
+}
+
+#[test]
+fn table_frames_limit() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    assert_eq!(pt.table_frames(), 1);
+    // The root, P3, P2 and P1 tables.
+    pt.set_limits(usize::MAX, 4);
+    map(&mut pt, BASE, PageSize::Size4K).unwrap();
+    assert_eq!(pt.table_frames(), 4);
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+
+    // Another P1 table is needed.
+    let err = PagingError::QuotaExceeded {
+        kind: QuotaKind::TableFrames,
+        limit: 4,
+        requested: 5,
+    };
+    assert_eq!(map(&mut pt, BASE + 0x20_0000, PageSize::Size4K), Err(err));
+    assert_eq!(MockHandler::stats().allocated, 4);
+    // Mappings in the existing tables still succeed.
+    map(&mut pt, BASE + 0x1000, PageSize::Size4K).unwrap();
+    map(&mut pt, BASE + 0x20_0000, PageSize::Size2M).unwrap();
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+    assert_eq!(pt.mapped_bytes(), 0x20_0000 + 0x2000);
+}
+
+#[test]
+fn clone_cow_limits() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, BASE, PageSize::Size4K).unwrap();
+    map(&mut pt, BASE + 0x20_0000, PageSize::Size2M).unwrap();
+    let (child, tlb) = pt.clone_cow(VirtAddr::from(BASE), 0x40_0000).unwrap();
+    tlb.ignore();
+    assert_eq!(child.mapped_bytes(), pt.mapped_bytes());
+    assert_eq!(child.table_frames(), pt.table_frames());
+    drop(child);
+
+    // The child inherits the limits.
+    pt.set_limits(0x20_0000, usize::MAX);
+    let err = PagingError::QuotaExceeded {
+        kind: QuotaKind::MappedBytes,
+        limit: 0x20_0000,
+        requested: 0x20_1000,
+    };
+    assert_eq!(
+        pt.clone_cow(VirtAddr::from(BASE), 0x40_0000).err(),
+        Some(err)
+    );
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), 0);
+}
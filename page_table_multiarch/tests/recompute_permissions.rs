@@ -0,0 +1,154 @@
+//! Recomputes the permissions of the intermediate x86_64 table entries from
+//! the mappings below them.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::{Cell, RefCell};
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::{PTF, X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingHandler};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const KERNEL: usize = 0xffff_8000_0000_0000;
+const USER: usize = 0x40_0000_0000;
+const PERMISSIONS: PTF = PTF::WRITABLE
+    .union(PTF::USER_ACCESSIBLE)
+    .union(PTF::NO_EXECUTE);
+const USER_R: MappingFlags = MappingFlags::READ.union(MappingFlags::USER);
+const USER_RWX: MappingFlags = USER_R
+    .union(MappingFlags::WRITE)
+    .union(MappingFlags::EXECUTE);
+
+fn map(pt: &mut PageTable, vaddr: usize, flags: MappingFlags) {
+    pt.map(
+        VirtAddr::from(vaddr),
+        PhysAddr::from(0x20_0000),
+        PageSize::Size4K,
+        flags,
+    )
+    .unwrap()
+    .ignore();
+}
+
+/// Returns the permissions of the table entries covering `vaddr`, from the
+/// root down.
+fn table_permissions(pt: &PageTable, vaddr: usize) -> Vec<PTF> {
+    let bits = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, va: VirtAddr, entry: &X64PTE| {
+            // The walk does not sign-extend the addresses.
+            let covered = 1usize << (12 + (3 - level) * 9);
+            let start = va.as_usize();
+            let vaddr = vaddr & ((1 << 48) - 1);
+            if level < 3 && !entry.is_huge() && (start..start + covered).contains(&vaddr) {
+                let flags = PTF::from_bits_truncate(entry.bits() as u64);
+                bits.borrow_mut().push(flags & PERMISSIONS);
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    bits.into_inner()
+}
+
+#[test]
+fn after_unmap_and_protect() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, USER, USER_R);
+    map(&mut pt, USER + 0x1000, USER_RWX);
+    map(&mut pt, KERNEL, MappingFlags::READ | MappingFlags::WRITE);
+    pt.unmap(VirtAddr::from(USER + 0x1000)).unwrap().2.ignore();
+    pt.protect(VirtAddr::from(KERNEL), MappingFlags::READ)
+        .unwrap()
+        .1
+        .ignore();
+    // The tables still allow what was mapped before.
+    assert_eq!(
+        table_permissions(&pt, USER),
+        [PTF::WRITABLE | PTF::USER_ACCESSIBLE; 3]
+    );
+    assert_eq!(
+        table_permissions(&pt, KERNEL),
+        [PTF::WRITABLE | PTF::NO_EXECUTE; 3]
+    );
+    Meta::take_flushes();
+
+    let (changed, tlb) = pt.recompute_table_permissions();
+    assert_eq!(changed, 6);
+    assert!(tlb.is_needed() && !tlb.is_global());
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+    assert_eq!(
+        table_permissions(&pt, USER),
+        [PTF::USER_ACCESSIBLE | PTF::NO_EXECUTE; 3]
+    );
+    assert_eq!(table_permissions(&pt, KERNEL), [PTF::NO_EXECUTE; 3]);
+    // The mappings are unchanged.
+    assert_eq!(
+        pt.query(VirtAddr::from(USER)).unwrap(),
+        (PhysAddr::from(0x20_0000), USER_R, PageSize::Size4K)
+    );
+
+    // Nothing left to tighten.
+    let (changed, tlb) = pt.recompute_table_permissions();
+    assert_eq!(changed, 0);
+    assert!(!tlb.is_needed());
+    tlb.ignore();
+}
+
+#[test]
+fn widened_again() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, USER, USER_RWX);
+    pt.unmap(VirtAddr::from(USER)).unwrap().2.ignore();
+    let (changed, tlb) = pt.recompute_table_permissions();
+    // Tables without mappings below allow nothing.
+    assert_eq!(changed, 3);
+    tlb.ignore();
+    assert_eq!(table_permissions(&pt, USER), [PTF::NO_EXECUTE; 3]);
+
+    // The walk cache must not skip widening the tables.
+    map(&mut pt, USER + 0x1000, USER_RWX);
+    assert_eq!(
+        table_permissions(&pt, USER),
+        [PTF::WRITABLE | PTF::USER_ACCESSIBLE; 3]
+    );
+}
+
+#[test]
+fn global_pages() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, KERNEL, MappingFlags::READ | MappingFlags::WRITE);
+    // Find the P1 table to set the bit, as the walk only passes copies.
+    let table = Cell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, _, entry: &X64PTE| {
+            if level == 2 {
+                table.set(Some(entry.paddr()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let p1 = MockHandler::phys_to_virt(table.get().unwrap()).as_mut_ptr() as *mut X64PTE;
+    unsafe {
+        let flags = PTF::from_bits_truncate((*p1).bits() as u64);
+        (*p1).set_flags_arch((flags | PTF::GLOBAL) - PTF::WRITABLE);
+    }
+
+    let (changed, tlb) = pt.recompute_table_permissions();
+    assert_eq!(changed, 3);
+    assert!(tlb.is_global());
+    tlb.flush_all();
+    assert_eq!(Meta::take_global_flushes(), 1);
+}
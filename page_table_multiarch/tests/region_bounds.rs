@@ -0,0 +1,143 @@
+//! Empty regions, and regions at the top of each half of the address space,
+//! for every metadata.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, GuestPhysAddr, MappingFlags, PageSize, PagingError};
+use page_table_multiarch::{PagingMetaData, RegionCursor};
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const PAGE: usize = 0x1000;
+const UPPER_TOP: usize = usize::MAX - PAGE + 1;
+
+/// Checks the regions of `M`, whose lower half ends at `lower_end`, with an
+/// upper half at the top of the address space if `upper`.
+fn check<M: PagingMetaData, PTE: GenericPTE>(lower_end: usize, upper: bool) {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    let va = |vaddr: usize| M::VirtAddr::from(vaddr);
+    let pa = |_| PhysAddr::from(0x8000_0000);
+    let invalid = |vaddr| Err(PagingError::InvalidVaddr(vaddr));
+
+    // Empty regions are no-ops anywhere.
+    for vaddr in [0, 0x1234, lower_end] {
+        let tlb = pt.map_region(va(vaddr), pa, 0, RW, false, false).unwrap();
+        assert!(!tlb.is_needed());
+        tlb.ignore();
+        let tlb = pt.unmap_region(va(vaddr), 0, false).unwrap();
+        assert!(!tlb.is_needed());
+        tlb.ignore();
+        let tlb = pt.protect_region(va(vaddr), 0, RW, false).unwrap();
+        assert!(!tlb.is_needed());
+        tlb.ignore();
+        let (changed, tlb) = pt.set_dirty_region(va(vaddr), 0, false).unwrap();
+        assert_eq!(changed, 0);
+        assert!(!tlb.is_needed());
+        tlb.ignore();
+    }
+
+    // The last page of the lower half.
+    let last = lower_end - PAGE;
+    let result = pt.map_region(va(last), pa, PAGE, RW, false, false);
+    result.unwrap().ignore();
+    assert_eq!(pt.query(va(last)).unwrap().2, PageSize::Size4K);
+    let result = pt.map_region(va(last), pa, 2 * PAGE, RW, false, false);
+    assert_eq!(result.map(|tlb| tlb.ignore()), invalid(last));
+    let result = pt.protect_region(va(last), 2 * PAGE, RW, false);
+    assert_eq!(result.map(|tlb| tlb.ignore()), invalid(last));
+    let result = pt.set_accessed_region(va(last), 2 * PAGE, true);
+    assert_eq!(result.map(|(_, tlb)| tlb.ignore()), invalid(last));
+    pt.unmap_region(va(last), PAGE, false).unwrap().ignore();
+    assert_eq!(pt.query(va(last)), Err(PagingError::NotMapped));
+
+    // The last page of the address space, whose end wraps.
+    if upper {
+        let tlb = pt.map_region(va(UPPER_TOP), pa, PAGE, RW, false, false);
+        let tlb = tlb.unwrap();
+        assert_eq!(tlb.pages(), Some(1));
+        tlb.ignore();
+        assert!(pt.query(va(UPPER_TOP)).is_ok());
+        let result = pt.protect_region(va(UPPER_TOP), PAGE, MappingFlags::READ, false);
+        result.unwrap().ignore();
+        let result = pt.unmap_region(va(UPPER_TOP - PAGE), 2 * PAGE, false);
+        assert_eq!(result.map(|tlb| tlb.ignore()), Err(PagingError::NotMapped));
+        let result = pt.unmap_region(va(UPPER_TOP), 2 * PAGE, false);
+        assert_eq!(result.map(|tlb| tlb.ignore()), invalid(UPPER_TOP));
+        pt.unmap_region(va(UPPER_TOP), PAGE, false)
+            .unwrap()
+            .ignore();
+    }
+
+    // The whole address space, which cannot be a region.
+    for (start, size) in [(0, usize::MAX), (PAGE, usize::MAX - PAGE + 1)] {
+        let result = pt.unmap_region(va(start), size, false);
+        assert_eq!(result.map(|tlb| tlb.ignore()), invalid(start));
+    }
+    let mut cursor = RegionCursor::new(0usize, usize::MAX);
+    let result = pt.unmap_region_step(&mut cursor, 1);
+    assert_eq!(result.map(|(_, tlb)| tlb.ignore()), invalid(0));
+    assert_eq!(cursor.next(), 0);
+}
+
+#[test]
+fn x86_64() {
+    check::<X64PagingMetaData, X64PTE>(1 << 47, true);
+}
+
+#[test]
+fn aarch64() {
+    // The upper half is also accepted without the sign extension.
+    check::<A64PagingMetaData, A64PTE>(1 << 48, true);
+}
+
+#[test]
+fn riscv() {
+    check::<Sv39MetaData<VirtAddr>, Rv64PTE>(1 << 38, true);
+    check::<Sv48MetaData<VirtAddr>, Rv64PTE>(1 << 47, true);
+    check::<Sv39MetaData<GuestPhysAddr>, Rv64PTE>(1 << 39, false);
+}
+
+#[test]
+fn loongarch64() {
+    check::<LA64MetaData, LA64PTE>(1 << 47, true);
+}
+
+#[test]
+fn unmap_all() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    let pages = [
+        (0x1000, PageSize::Size4K),
+        (0x4000_0000, PageSize::Size1G),
+        (0x7fff_ffe0_0000, PageSize::Size2M),
+        (UPPER_TOP, PageSize::Size4K),
+    ];
+    for (vaddr, size) in pages {
+        let paddr = PhysAddr::from(size as usize);
+        pt.map(VirtAddr::from(vaddr), paddr, size, RW)
+            .unwrap()
+            .ignore();
+    }
+    pt.set_absent_token(VirtAddr::from(0x2000), 7).unwrap();
+    let frames = pt.table_frames();
+
+    let tlb = pt.unmap_all().unwrap();
+    assert!(tlb.is_needed());
+    tlb.ignore();
+    for (vaddr, _) in pages {
+        assert_eq!(pt.query(VirtAddr::from(vaddr)), Err(PagingError::NotMapped));
+    }
+    assert_eq!(pt.mapped_bytes(), 0);
+    // Only the mappings are removed.
+    assert_eq!(pt.absent_token(VirtAddr::from(0x2000)), Some(7));
+    assert_eq!(pt.table_frames(), frames);
+    // Nothing left to unmap.
+    pt.unmap_all().unwrap().ignore();
+}
@@ -0,0 +1,161 @@
+//! Changing the memory type of live mappings, which goes through an invalid
+//! entry and cache maintenance on every architecture.
+
+#![cfg(target_arch = "x86_64")]
+
+use core::cell::Cell;
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    CacheOp, GenericPTE, MappingFlags, MemoryType, PageSize, PagingError, PagingHandler,
+};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+type Meta = MockMetaData<X64PagingMetaData>;
+
+const VADDR: usize = 0x40_0000_0000;
+const PADDR: usize = 0x8000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const DEVICE: MappingFlags = RW.union(MappingFlags::DEVICE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(off: usize) -> PhysAddr {
+    PhysAddr::from(PADDR + off)
+}
+
+/// Three 4K pages, a hole, and a 2M page, all with `flags`.
+fn mapped(flags: MappingFlags) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let get_paddr = |vaddr: VirtAddr| PhysAddr::from(vaddr.as_usize() - VADDR + PADDR);
+    for (off, size) in [(0, 0x3000), (SIZE_2M, SIZE_2M)] {
+        pt.map_region(va(off), get_paddr, size, flags, true, false)
+            .unwrap()
+            .ignore();
+    }
+    Meta::take_flushes();
+    pt
+}
+
+/// Returns the entry of the 4K page at [`VADDR`].
+fn leaf(pt: &PageTable) -> *const X64PTE {
+    let table = Cell::new(PhysAddr::from(0));
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &X64PTE| {
+            if level == 2 && vaddr == va(0) {
+                table.set(entry.paddr());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    MockHandler::phys_to_virt(table.get()).as_ptr() as *const X64PTE
+}
+
+fn flags(pt: &PageTable, off: usize) -> MappingFlags {
+    pt.query(va(off)).unwrap().1
+}
+
+#[test]
+fn normal_to_device() {
+    let mut pt = mapped(RW);
+    Meta::watch_entry(Some(leaf(&pt)));
+    pt.retype_region(va(0), 4 * SIZE_2M, MemoryType::Device)
+        .unwrap()
+        .ignore();
+    for off in [0, 0x1000, 0x2000, SIZE_2M] {
+        assert_eq!(flags(&pt, off), DEVICE);
+    }
+    assert_eq!(pt.query(va(SIZE_2M)).unwrap().2, PageSize::Size2M);
+
+    // Each page is flushed while invalid, even without break-before-make.
+    assert_eq!(
+        Meta::take_flushes(),
+        [
+            Some(va(0)),
+            Some(va(0x1000)),
+            Some(va(0x2000)),
+            Some(va(SIZE_2M))
+        ]
+    );
+    // Cleared, i.e. zero or poisoned with the `debug-poison` feature.
+    assert!(<X64PTE as GenericPTE>::from_bits(Meta::take_watched()[0]).is_unused());
+    assert_eq!(
+        MockHandler::take_cache_ops(),
+        [
+            (pa(0), 0x1000, CacheOp::CleanInvalidate),
+            (pa(0x1000), 0x1000, CacheOp::CleanInvalidate),
+            (pa(0x2000), 0x1000, CacheOp::CleanInvalidate),
+            (pa(SIZE_2M), SIZE_2M, CacheOp::CleanInvalidate),
+        ]
+    );
+}
+
+#[test]
+fn back_to_normal() {
+    let mut pt = mapped(DEVICE);
+    pt.retype_region(va(0x1000), 0x1000, MemoryType::Normal)
+        .unwrap()
+        .ignore();
+    assert_eq!(flags(&pt, 0), DEVICE);
+    assert_eq!(flags(&pt, 0x1000), RW);
+    assert_eq!(
+        MockHandler::take_cache_ops(),
+        [(pa(0x1000), 0x1000, CacheOp::Invalidate)]
+    );
+
+    // Both types are uncached: no cache maintenance.
+    pt.retype_region(va(0), 0x1000, MemoryType::Uncached)
+        .unwrap()
+        .ignore();
+    assert_eq!(flags(&pt, 0), RW | MappingFlags::UNCACHED);
+    assert_eq!(MockHandler::take_cache_ops(), []);
+    assert_eq!(Meta::take_flushes(), [Some(va(0x1000)), Some(va(0))]);
+
+    // The pages that already have the type are left alone.
+    pt.retype_region(va(0), 0x3000, MemoryType::Device)
+        .unwrap()
+        .ignore();
+    assert_eq!(Meta::take_flushes(), [Some(va(0)), Some(va(0x1000))]);
+}
+
+#[test]
+fn checked_first() {
+    let mut pt = mapped(RW);
+    assert_eq!(
+        pt.retype_region(va(0), SIZE_2M + 0x1000, MemoryType::Device)
+            .map(|tlb| tlb.ignore()),
+        Err(PagingError::MappedToHugePage {
+            vaddr: VADDR + SIZE_2M,
+            level: 2
+        })
+    );
+    MockHandler::set_memory_type(
+        PhysAddrRange::from_start_size(pa(0x2000), 0x1000),
+        MemoryType::Normal,
+    );
+    assert_eq!(
+        pt.retype_region(va(0), 0x3000, MemoryType::Device)
+            .map(|tlb| tlb.ignore()),
+        Err(PagingError::AttributeConflict(pa(0x2000)))
+    );
+    assert_eq!(
+        pt.retype_region(va(0x800), 0x1000, MemoryType::Device)
+            .map(|tlb| tlb.ignore()),
+        Err(PagingError::NotAligned)
+    );
+    // Nothing changed.
+    for off in [0, 0x1000, 0x2000, SIZE_2M] {
+        assert_eq!(flags(&pt, off), RW);
+    }
+    assert_eq!(Meta::take_flushes(), []);
+    assert_eq!(MockHandler::take_cache_ops(), []);
+}
@@ -0,0 +1,75 @@
+//! Checks that updating the flags of RISC-V mappings never changes the table
+//! entries above them, which would turn them into leaves.
+
+#![cfg(feature = "all-formats")]
+
+use std::cell::RefCell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::riscv::Rv64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingMetaData};
+
+const VADDR: usize = 0x4000_0000;
+const SIZE: usize = 0x40_3000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+/// The raw bits of the table entries, by level and address.
+fn tables<M: PagingMetaData<VirtAddr = VirtAddr>>(
+    pt: &MockPageTable<M, Rv64PTE>,
+) -> Vec<(usize, usize, usize)> {
+    let tables = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &Rv64PTE| {
+            if level < M::LEVELS - 1 && entry.is_table() {
+                tables
+                    .borrow_mut()
+                    .push((level, vaddr.as_usize(), entry.bits()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    tables.into_inner()
+}
+
+fn check<M: PagingMetaData<VirtAddr = VirtAddr>>() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, Rv64PTE>::try_new().unwrap();
+    // Two 2M pages and three 4K pages.
+    let va = VirtAddr::from(VADDR);
+    pt.map_region(va, |v| PhysAddr::from(v.as_usize()), SIZE, RW, true, false)
+        .unwrap()
+        .ignore();
+    let before = tables(&pt);
+    assert_eq!(before.len(), M::LEVELS - 1);
+
+    let all = RW | MappingFlags::EXECUTE | MappingFlags::USER;
+    for flags in [MappingFlags::READ, MappingFlags::EXECUTE, all, RW] {
+        pt.protect_region(va, SIZE, flags, false).unwrap().ignore();
+        assert_eq!(tables(&pt), before, "{:?}", flags);
+        for (off, size) in [(0, PageSize::Size2M), (0x40_2000, PageSize::Size4K)] {
+            let vaddr = VirtAddr::from(VADDR + off);
+            assert_eq!(pt.query(vaddr).unwrap().1, flags, "{:?}", flags);
+            assert_eq!(pt.query(vaddr).unwrap().2, size);
+        }
+        // Single pages too.
+        pt.protect(VirtAddr::from(VADDR + 0x40_1000), flags)
+            .unwrap()
+            .1
+            .ignore();
+        assert_eq!(tables(&pt), before, "{:?}", flags);
+    }
+}
+
+#[test]
+fn sv39() {
+    check::<Sv39MetaData<VirtAddr>>();
+}
+
+#[test]
+fn sv48() {
+    check::<Sv48MetaData<VirtAddr>>();
+}
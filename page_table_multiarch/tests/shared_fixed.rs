@@ -0,0 +1,159 @@
+//! Mapping the same read-only pages at a fixed address into many page
+//! tables, and keeping them shared across `clone_cow`.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::loongarch64::LA64PTE;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, SharedFixedMapping, UserSpace};
+
+type PageTable = MockPageTable<LA64MetaData, LA64PTE, UserSpace>;
+
+const VDSO: usize = 0x7fff_0000_0000;
+const USER_RX: MappingFlags = MappingFlags::READ
+    .union(MappingFlags::EXECUTE)
+    .union(MappingFlags::USER);
+const USER_R: MappingFlags = MappingFlags::READ.union(MappingFlags::USER);
+const USER_RW: MappingFlags = USER_R.union(MappingFlags::WRITE);
+
+const PAGES: [(PhysAddr, MappingFlags); 2] = [
+    (PhysAddr::from_usize(0x8000_0000), USER_RX),
+    (PhysAddr::from_usize(0x8000_5000), USER_R),
+];
+const SHARED: SharedFixedMapping = SharedFixedMapping {
+    vaddr: VDSO,
+    pages: &PAGES,
+};
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn query(pt: &PageTable, vaddr: usize) -> Result<(PhysAddr, MappingFlags), PagingError> {
+    pt.query(va(vaddr)).map(|(paddr, flags, _)| (paddr, flags))
+}
+
+#[test]
+fn apply_and_remove() {
+    MockHandler::reset();
+    let mut tables: Vec<_> = (0..3).map(|_| PageTable::try_new().unwrap()).collect();
+    for pt in &mut tables {
+        pt.apply_shared(&SHARED).unwrap().ignore();
+        assert_eq!(query(pt, VDSO), Ok(PAGES[0]));
+        assert_eq!(query(pt, VDSO + 0x1000), Ok(PAGES[1]));
+    }
+    // Never reference counted.
+    assert_eq!(MockHandler::refs(PAGES[0].0), None);
+
+    let pt = &mut tables[0];
+    let tlb = pt.remove_shared(&SHARED).unwrap();
+    assert!(tlb.is_needed());
+    tlb.ignore();
+    assert_eq!(query(pt, VDSO), Err(PagingError::NotMapped));
+    assert_eq!(query(pt, VDSO + 0x1000), Err(PagingError::NotMapped));
+    assert_eq!(
+        pt.remove_shared(&SHARED).map(|tlb| tlb.ignore()),
+        Err(PagingError::NotMapped)
+    );
+    // The others keep it.
+    assert_eq!(query(&tables[1], VDSO), Ok(PAGES[0]));
+}
+
+#[test]
+fn collisions() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(
+        va(VDSO + 0x1000),
+        PhysAddr::from(0x1000),
+        PageSize::Size4K,
+        USER_RW,
+    )
+    .unwrap()
+    .ignore();
+    // Nothing is mapped then.
+    let result = pt.apply_shared(&SHARED).map(|tlb| tlb.ignore());
+    assert_eq!(result, Err(PagingError::AlreadyMapped));
+    assert_eq!(query(&pt, VDSO), Err(PagingError::NotMapped));
+    // Nor removed, as the page is not the shared one.
+    let result = pt.remove_shared(&SHARED).map(|tlb| tlb.ignore());
+    assert_eq!(result, Err(PagingError::NotMapped));
+    assert!(query(&pt, VDSO + 0x1000).is_ok());
+
+    let mut pt = PageTable::try_new().unwrap();
+    pt.set_absent_token(va(VDSO), 1).unwrap();
+    let result = pt.apply_shared(&SHARED).map(|tlb| tlb.ignore());
+    assert_eq!(result, Err(PagingError::AlreadyMapped));
+
+    let unaligned = SharedFixedMapping {
+        vaddr: VDSO + 0x10,
+        ..SHARED
+    };
+    let result = pt.apply_shared(&unaligned).map(|tlb| tlb.ignore());
+    assert_eq!(result, Err(PagingError::NotAligned));
+}
+
+#[test]
+fn rolled_back() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // The second page is not a user page.
+    let pages = [PAGES[0], (PAGES[1].0, MappingFlags::READ)];
+    let shared = SharedFixedMapping {
+        vaddr: VDSO,
+        pages: &pages,
+    };
+    let result = pt.apply_shared(&shared).map(|tlb| tlb.ignore());
+    assert_eq!(result, Err(PagingError::WrongSpace));
+    assert_eq!(query(&pt, VDSO), Err(PagingError::NotMapped));
+}
+
+#[test]
+#[should_panic]
+fn writable() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let pages = [(PAGES[1].0, USER_RW)];
+    let shared = SharedFixedMapping {
+        vaddr: VDSO,
+        pages: &pages,
+    };
+    let _ = pt.apply_shared(&shared);
+}
+
+#[test]
+fn fork_keeps_it_shared() {
+    MockHandler::reset();
+    let mut parent = PageTable::try_new().unwrap();
+    parent.apply_shared(&SHARED).unwrap().ignore();
+    let data = 0x40_0000;
+    parent
+        .map(
+            va(data),
+            PhysAddr::from(0x9000_0000),
+            PageSize::Size4K,
+            USER_RW,
+        )
+        .unwrap()
+        .ignore();
+
+    let (mut child, tlb) = parent.clone_cow(va(0), 0x8000_0000_0000).unwrap();
+    tlb.ignore();
+    // Unlike the data page, the shared pages are neither copy-on-write nor
+    // reference counted, in both tables.
+    for pt in [&parent, &child] {
+        assert_eq!(query(pt, VDSO), Ok(PAGES[0]));
+        assert_eq!(query(pt, VDSO + 0x1000), Ok(PAGES[1]));
+        let (_, flags) = query(pt, data).unwrap();
+        assert!(flags.contains(MappingFlags::COW));
+    }
+    assert_eq!(MockHandler::refs(PAGES[0].0), None);
+    assert_eq!(MockHandler::refs(PAGES[1].0), None);
+    assert_eq!(MockHandler::refs(PhysAddr::from(0x9000_0000)), Some(2));
+
+    // Each process removes it on its own.
+    child.remove_shared(&SHARED).unwrap().ignore();
+    assert_eq!(query(&parent, VDSO + 0x1000), Ok(PAGES[1]));
+}
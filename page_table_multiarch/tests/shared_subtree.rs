@@ -0,0 +1,178 @@
+//! Sharing subtrees of tables between page tables, and releasing them in
+//! every order, with the table frames counted by the mock handler.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const SRC: usize = 0x40_0000_0000;
+const DST: usize = 0x80_0000_0000;
+const SIZE_2M: usize = PageSize::Size2M as usize;
+const SIZE_1G: usize = PageSize::Size1G as usize;
+const RO: MappingFlags = MappingFlags::READ.union(MappingFlags::USER);
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+fn pa(vaddr: VirtAddr) -> PhysAddr {
+    PhysAddr::from(vaddr.as_usize() - SRC + 0x8000_0000)
+}
+
+/// A page table with 4K pages mapped in `[SRC, SRC + size)`, every 64K.
+fn exporter(size: usize) -> PageTable {
+    let mut pt = PageTable::try_new().unwrap();
+    for vaddr in (SRC..SRC + size).step_by(0x1_0000) {
+        pt.map(va(vaddr), pa(va(vaddr)), PageSize::Size4K, RO)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+#[test]
+fn release_in_any_order() {
+    for order in [
+        [0, 1, 2],
+        [0, 2, 1],
+        [1, 0, 2],
+        [1, 2, 0],
+        [2, 0, 1],
+        [2, 1, 0],
+    ] {
+        MockHandler::reset();
+        let live = MockHandler::live_frames();
+        let mut pts = [
+            exporter(SIZE_2M),
+            PageTable::try_new().unwrap(),
+            PageTable::try_new().unwrap(),
+        ];
+        let subtree = pts[0].export_subtree(va(SRC), SIZE_2M).unwrap();
+        assert_eq!(subtree.size(), SIZE_2M);
+        let table = subtree.table_paddr();
+        for pt in &mut pts[1..] {
+            pt.import_subtree(va(DST), &subtree).unwrap().ignore();
+        }
+        assert_eq!(MockHandler::refs(table), Some(3));
+        let vaddr = va(DST + 0x1_0000);
+        assert_eq!(
+            pts[1].query(vaddr).unwrap(),
+            (pa(va(SRC + 0x1_0000)), RO, PageSize::Size4K)
+        );
+
+        // A change through one of them is seen by all.
+        pts[2].unmap(vaddr).unwrap().2.ignore();
+        assert_eq!(
+            pts[0].query(va(SRC + 0x1_0000)),
+            Err(PagingError::NotMapped)
+        );
+        assert_eq!(pts[1].query(vaddr), Err(PagingError::NotMapped));
+
+        let vaddrs = [va(SRC), va(DST), va(DST)];
+        for (n, i) in order.into_iter().enumerate() {
+            pts[i]
+                .release_subtree(vaddrs[i], &subtree)
+                .unwrap()
+                .flush_all();
+            assert_eq!(pts[i].query(vaddrs[i]), Err(PagingError::NotMapped));
+            // The others still use the tables.
+            for j in order[n + 1..].iter().copied() {
+                assert_eq!(pts[j].query(vaddrs[j]).unwrap().0, pa(va(SRC)));
+            }
+        }
+        // Freed by the last release.
+        assert_eq!(MockHandler::refs(table), Some(0));
+        assert_eq!(MockHandler::stats().deallocated, 1);
+        drop(pts);
+        assert_eq!(MockHandler::live_frames(), live);
+    }
+}
+
+#[test]
+fn exporter_dropped_first() {
+    MockHandler::reset();
+    let mut src = exporter(SIZE_1G);
+    let subtree = src.export_subtree(va(SRC), SIZE_1G).unwrap();
+    let mut dst = PageTable::try_new().unwrap();
+    dst.import_subtree(va(DST), &subtree).unwrap().ignore();
+    // The top table and its 512 leaf tables.
+    assert_eq!(MockHandler::stats().shared, 513);
+
+    let frames = src.table_frames();
+    let mapped = src.mapped_bytes();
+    src.release_subtree(va(SRC), &subtree).unwrap().ignore();
+    assert_eq!(src.table_frames(), frames - 513);
+    assert_eq!(src.mapped_bytes(), mapped - SIZE_1G / 16);
+    drop(src);
+    assert_eq!(MockHandler::stats().deallocated, 2);
+
+    let vaddr = va(DST + SIZE_1G - 0x1_0000);
+    assert_eq!(
+        dst.query(vaddr).unwrap().0,
+        pa(va(SRC + SIZE_1G - 0x1_0000))
+    );
+    // The subtree is not accounted to the importer.
+    assert_eq!(dst.table_frames(), 2);
+    assert_eq!(dst.mapped_bytes(), 0);
+    dst.release_subtree(va(DST), &subtree).unwrap().ignore();
+    assert_eq!(MockHandler::stats().deallocated, 2 + 513);
+}
+
+#[test]
+fn invalid_subtrees() {
+    MockHandler::reset();
+    let mut src = exporter(SIZE_2M);
+    src.map(
+        va(SRC + SIZE_2M),
+        PhysAddr::from(SIZE_2M),
+        PageSize::Size2M,
+        RO,
+    )
+    .unwrap()
+    .ignore();
+    for (vaddr, size) in [(SRC, 0x1000), (SRC, 0x1_0000), (SRC + 0x1000, SIZE_2M)] {
+        assert_eq!(
+            src.export_subtree(va(vaddr), size).err(),
+            Some(PagingError::NotAligned)
+        );
+    }
+    assert_eq!(
+        src.export_subtree(va(SRC + SIZE_2M), SIZE_2M).err(),
+        Some(PagingError::MappedToHugePage {
+            vaddr: SRC + SIZE_2M,
+            level: 2,
+        })
+    );
+    assert_eq!(
+        src.export_subtree(va(SRC + 2 * SIZE_2M), SIZE_2M).err(),
+        Some(PagingError::NotMapped)
+    );
+
+    let subtree = src.export_subtree(va(SRC), SIZE_2M).unwrap();
+    let mut dst = exporter(SIZE_2M);
+    assert_eq!(
+        dst.import_subtree(va(SRC), &subtree).err(),
+        Some(PagingError::AlreadyMapped)
+    );
+    assert_eq!(
+        dst.import_subtree(va(DST + 0x1000), &subtree).err(),
+        Some(PagingError::NotAligned)
+    );
+    // Not linked there.
+    assert_eq!(
+        dst.release_subtree(va(SRC), &subtree).err(),
+        Some(PagingError::NotMapped)
+    );
+    assert_eq!(
+        src.release_subtree(va(SRC + SIZE_2M), &subtree).err(),
+        Some(PagingError::NotMapped)
+    );
+    assert_eq!(MockHandler::stats().shared, 0);
+    src.release_subtree(va(SRC), &subtree).unwrap().ignore();
+}
@@ -0,0 +1,82 @@
+//! Checks that software flags are stored in the bits chosen by the layout.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::loongarch64::{LA64PTE, LA64SoftBits};
+use page_table_entry::riscv::{Rv64PTE, Rv64SoftBits};
+use page_table_entry::{GenericPTE, SoftBitLayout};
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::{MappingFlags, PageSize};
+
+const COW_FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::COW);
+
+/// COW in `RSW2` on RISC-V, and `RSW3` on LoongArch.
+struct Custom;
+
+impl SoftBitLayout for Custom {
+    const COW: u32 = 9;
+}
+
+struct CustomLA64;
+
+impl SoftBitLayout for CustomLA64 {
+    const COW: u32 = 11;
+}
+
+fn cow_bits<PTE: GenericPTE>() -> usize {
+    let pte = PTE::new_page(PhysAddr::from(0x1000), COW_FLAGS, false);
+    assert_eq!(pte.flags() & COW_FLAGS, COW_FLAGS);
+    let plain = PTE::new_page(PhysAddr::from(0x1000), MappingFlags::READ, false);
+    assert!(!plain.flags().contains(MappingFlags::COW));
+    pte.bits() ^ plain.bits()
+}
+
+#[test]
+fn default_layouts() {
+    assert_eq!(cow_bits::<Rv64PTE>(), 1 << Rv64SoftBits::COW);
+    assert_eq!(cow_bits::<LA64PTE>(), 1 << LA64SoftBits::COW);
+}
+
+#[test]
+fn custom_layouts() {
+    assert_eq!(cow_bits::<Rv64PTE<Custom>>(), 1 << 9);
+    assert_eq!(cow_bits::<LA64PTE<CustomLA64>>(), 1 << 11);
+}
+
+fn lock_bits<PTE: GenericPTE>() -> usize {
+    let plain = PTE::new_page(PhysAddr::from(0x1000), COW_FLAGS, false);
+    let mut pte = plain;
+    pte.set_locked(true);
+    assert_eq!(pte.is_locked(), PTE::LOCKABLE);
+    // Kept when the flags change.
+    pte.set_flags(MappingFlags::READ, false);
+    assert_eq!(pte.is_locked(), PTE::LOCKABLE);
+    pte.set_flags(COW_FLAGS, false);
+    pte.bits() ^ plain.bits()
+}
+
+#[test]
+fn lock_layouts() {
+    assert_eq!(lock_bits::<Rv64PTE>(), 1 << Rv64SoftBits::LOCKED.unwrap());
+    assert_eq!(lock_bits::<LA64PTE>(), 1 << LA64SoftBits::LOCKED.unwrap());
+    // Custom layouts have no lock unless they choose a bit.
+    assert_eq!(lock_bits::<Rv64PTE<Custom>>(), 0);
+}
+
+#[test]
+fn page_table_with_custom_layout() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<Sv39MetaData<VirtAddr>, Rv64PTE<Custom>>::try_new().unwrap();
+    let vaddr = VirtAddr::from(0x4000_0000);
+    pt.map(vaddr, PhysAddr::from(0x1000), PageSize::Size4K, COW_FLAGS)
+        .unwrap()
+        .ignore();
+    let (_, flags, _) = pt.query(vaddr).unwrap();
+    assert!(flags.contains(MappingFlags::COW));
+    let (_, tlb) = pt.protect(vaddr, flags.resolve_cow()).unwrap();
+    tlb.ignore();
+    let (_, flags, _) = pt.query(vaddr).unwrap();
+    assert_eq!(flags, MappingFlags::READ | MappingFlags::WRITE);
+}
@@ -0,0 +1,104 @@
+//! Checks the mappings accepted by the kinds of address spaces.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    AnySpace, KernelSpace, MappingFlags, PageSize, PagingError, PagingResult, SpaceKind, UserSpace,
+};
+
+type PageTable<K> = MockPageTable<X64PagingMetaData, X64PTE, K>;
+
+const USER: usize = 0x40_0000_0000;
+const KERNEL: usize = 0xffff_8000_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const USER_RW: MappingFlags = RW.union(MappingFlags::USER);
+
+fn map<K: SpaceKind>(pt: &mut PageTable<K>, vaddr: usize, flags: MappingFlags) -> PagingResult {
+    let paddr = PhysAddr::from(0x20_0000);
+    pt.map(VirtAddr::from(vaddr), paddr, PageSize::Size4K, flags)
+        .map(|tlb| tlb.ignore())
+}
+
+#[test]
+fn any_space() {
+    MockHandler::reset();
+    let mut pt = PageTable::<AnySpace>::try_new().unwrap();
+    map(&mut pt, USER, RW).unwrap();
+    map(&mut pt, KERNEL, USER_RW).unwrap();
+}
+
+#[test]
+fn user_space() {
+    MockHandler::reset();
+    let mut pt = PageTable::<UserSpace>::try_new().unwrap();
+    map(&mut pt, USER, USER_RW).unwrap();
+    assert_eq!(
+        map(&mut pt, USER + 0x1000, RW),
+        Err(PagingError::WrongSpace)
+    );
+    assert_eq!(map(&mut pt, KERNEL, USER_RW), Err(PagingError::WrongSpace));
+    // `map_region` goes through the same checks.
+    let tlb = pt.map_region(
+        VirtAddr::from(USER + 0x1000),
+        |_| PhysAddr::from(0x20_0000),
+        0x1000,
+        RW,
+        false,
+        false,
+    );
+    assert_eq!(tlb.err(), Some(PagingError::WrongSpace));
+    assert_eq!(
+        pt.query(VirtAddr::from(USER + 0x1000)),
+        Err(PagingError::NotMapped)
+    );
+
+    // `protect` cannot remove `USER`.
+    let vaddr = VirtAddr::from(USER);
+    assert_eq!(pt.protect(vaddr, RW).err(), Some(PagingError::WrongSpace));
+    assert_eq!(pt.query(vaddr).unwrap().1, USER_RW);
+    pt.protect(vaddr, MappingFlags::READ | MappingFlags::USER)
+        .unwrap()
+        .1
+        .ignore();
+
+    let (child, tlb) = pt.clone_cow(VirtAddr::from(USER), 0x1000).unwrap();
+    tlb.ignore();
+    assert_eq!(child.mapped_bytes(), 0x1000);
+}
+
+#[test]
+fn kernel_space() {
+    MockHandler::reset();
+    let mut pt = PageTable::<KernelSpace>::try_new().unwrap();
+    map(&mut pt, KERNEL, RW).unwrap();
+    assert_eq!(
+        map(&mut pt, KERNEL + 0x1000, USER_RW),
+        Err(PagingError::WrongSpace)
+    );
+    assert_eq!(map(&mut pt, USER, RW), Err(PagingError::WrongSpace));
+    assert_eq!(pt.mapped_bytes(), 0x1000);
+
+    // The lower half can be allowed, e.g. for an identity mapping.
+    let mut pt = PageTable::<KernelSpace<true>>::try_new().unwrap();
+    map(&mut pt, USER, RW).unwrap();
+    assert_eq!(map(&mut pt, KERNEL, USER_RW), Err(PagingError::WrongSpace));
+}
+
+#[test]
+fn copy_kernel_template() {
+    MockHandler::reset();
+    let mut kernel = PageTable::<KernelSpace>::try_new().unwrap();
+    map(&mut kernel, KERNEL, RW).unwrap();
+    let mut user = PageTable::<UserSpace>::try_new().unwrap();
+    map(&mut user, USER, USER_RW).unwrap();
+    user.copy_from(&kernel, VirtAddr::from(KERNEL), 0x80_0000_0000);
+    assert_eq!(
+        user.query(VirtAddr::from(KERNEL)),
+        Ok((PhysAddr::from(0x20_0000), RW, PageSize::Size4K))
+    );
+    user.clear_copy_range(VirtAddr::from(KERNEL), 0x80_0000_0000);
+}
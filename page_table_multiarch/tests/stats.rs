@@ -0,0 +1,107 @@
+//! Counting the work done by the operations with the `trace` feature.
+
+#![cfg(all(target_arch = "x86_64", feature = "trace"))]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, OpStats, PageSize};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+const SIZE_1G: usize = PageSize::Size1G as usize;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+#[test]
+fn huge_region_writes_one_leaf() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    assert_eq!(pt.stats(), OpStats::default());
+
+    // Creates the P3, P2, and P1 tables.
+    pt.map(va(0), PhysAddr::from(0), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    let stats = pt.take_stats();
+    assert_eq!(stats.frames_allocated, 3);
+    assert_eq!(stats.entries_written, 4);
+    assert_eq!(stats.huge_mappings_used, 0);
+    assert_eq!(pt.stats(), OpStats::default());
+
+    pt.map_region(
+        va(SIZE_2M),
+        |_| PhysAddr::from(SIZE_2M),
+        SIZE_2M,
+        RW,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    let stats = pt.take_stats();
+    assert_eq!(stats.entries_written, 1);
+    assert_eq!(stats.frames_allocated, 0);
+    assert_eq!(stats.huge_mappings_used, 1);
+
+    // The same region in 4K pages needs a table and 512 leaves.
+    pt.map_region(
+        va(2 * SIZE_2M),
+        |va| PhysAddr::from(va.as_usize()),
+        SIZE_2M,
+        RW,
+        false,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    let stats = pt.take_stats();
+    assert_eq!(stats.entries_written, 513);
+    assert_eq!(stats.frames_allocated, 1);
+    assert_eq!(stats.huge_mappings_used, 0);
+}
+
+#[test]
+fn queries_read_one_entry_per_level() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0x1000), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    pt.map(va(SIZE_1G), PhysAddr::from(0), PageSize::Size1G, RW)
+        .unwrap()
+        .ignore();
+    pt.take_stats();
+
+    pt.query(va(SIZE_1G + 0x1234)).unwrap();
+    assert_eq!(pt.take_stats().entries_read, 2);
+    pt.query(va(0x1234)).unwrap();
+    let stats = pt.take_stats();
+    assert!((1..=4).contains(&stats.entries_read), "{stats:?}");
+    assert_eq!(stats.entries_written, 0);
+}
+
+#[test]
+fn region_walks_skip_unused_subtrees() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0x1000), PhysAddr::from(0x1000), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    pt.take_stats();
+
+    // The other 511 entries of the P2 table are unused.
+    pt.set_dirty_region(va(0), SIZE_1G, true)
+        .unwrap()
+        .1
+        .ignore();
+    let stats = pt.take_stats();
+    assert_eq!(stats.subtrees_skipped, 511);
+    assert_eq!(stats.entries_written, 1);
+    assert_eq!(stats.frames_freed, 0);
+}
@@ -0,0 +1,130 @@
+//! Checks the region operations done in steps with a [`RegionCursor`].
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    MappingFlags, PageSize, PagingError, PagingResult, RegionCursor, StepStatus, TlbFlushAll,
+};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(vaddr: VirtAddr) -> PhysAddr {
+    PhysAddr::from(vaddr.as_usize() - VADDR)
+}
+
+fn mapped(size: usize) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map_region(va(0), pa, size, FLAGS, false, false)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+/// Runs `step` until the region is done, merging the flushes, and returns
+/// the number of steps.
+fn run(
+    mut step: impl FnMut() -> PagingResult<(StepStatus, TlbFlushAll<Meta>)>,
+) -> (usize, TlbFlushAll<Meta>) {
+    let (mut status, mut tlb) = step().unwrap();
+    let mut steps = 1;
+    while status == StepStatus::Pending {
+        let (next, next_tlb) = step().unwrap();
+        (status, tlb) = (next, tlb.merge(next_tlb));
+        steps += 1;
+    }
+    (steps, tlb)
+}
+
+#[test]
+fn unmap_in_steps() {
+    let mut pt = mapped(0x40_000);
+    let mut cursor = RegionCursor::new(va(0), 0x40_000);
+    let (steps, tlb) = run(|| pt.unmap_region_step(&mut cursor, 10));
+    // 64 pages, 10 at a time.
+    assert_eq!(steps, 7);
+    assert!(cursor.is_done());
+    assert_eq!(cursor.remaining(), 0);
+    assert_eq!(pt.mapped_bytes(), 0);
+    assert!(tlb.is_needed());
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+}
+
+#[test]
+fn map_and_protect_in_steps() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // A 2M page, then two 4K pages, one at a time.
+    let size = 0x20_2000;
+    let mut cursor = RegionCursor::new(va(0), size);
+    let (steps, tlb) = run(|| pt.map_region_step(&mut cursor, pa, FLAGS, true, 1));
+    tlb.ignore();
+    assert_eq!(steps, 3);
+    assert_eq!(pt.mapped_bytes(), size);
+    assert_eq!(
+        pt.query(va(0x1000)),
+        Ok((PhysAddr::from(0x1000), FLAGS, PageSize::Size2M))
+    );
+
+    let mut cursor = RegionCursor::new(va(0), size);
+    let (steps, tlb) = run(|| pt.protect_region_step(&mut cursor, MappingFlags::READ, 2));
+    tlb.ignore();
+    assert_eq!(steps, 2);
+    assert_eq!(pt.query(va(0x20_1000)).unwrap().1, MappingFlags::READ);
+
+    let mut cursor = RegionCursor::new(va(0x800), 0x1000);
+    assert_eq!(
+        pt.map_region_step(&mut cursor, pa, FLAGS, false, 1)
+            .map(|(status, tlb)| {
+                tlb.ignore();
+                status
+            }),
+        Err(PagingError::NotAligned)
+    );
+}
+
+#[test]
+fn changes_between_steps() {
+    let mut pt = mapped(0x10_000);
+    let mut cursor = RegionCursor::new(va(0), 0x10_000);
+    let (status, tlb) = pt.unmap_region_step(&mut cursor, 4).unwrap();
+    tlb.ignore();
+    assert_eq!(status, StepStatus::Pending);
+    assert_eq!(cursor.next(), VADDR + 0x4000);
+
+    // Unrelated changes do not matter, as the cursor only holds addresses.
+    pt.map(va(0x100_0000), PhysAddr::from(0), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    pt.unmap(va(0x100_0000)).unwrap().2.ignore();
+    // A page removed from the rest of the region stops the next step there.
+    pt.unmap(va(0x6000)).unwrap().2.ignore();
+    assert_eq!(
+        pt.unmap_region_step(&mut cursor, 4).map(|(status, tlb)| {
+            tlb.ignore();
+            status
+        }),
+        Err(PagingError::NotMapped)
+    );
+    assert_eq!(cursor.next(), VADDR + 0x6000);
+    pt.map(va(0x6000), PhysAddr::from(0x6000), PageSize::Size4K, FLAGS)
+        .unwrap()
+        .ignore();
+    let (steps, tlb) = run(|| pt.unmap_region_step(&mut cursor, 4));
+    tlb.ignore();
+    assert_eq!(steps, 3);
+    assert_eq!(pt.mapped_bytes(), 0);
+}
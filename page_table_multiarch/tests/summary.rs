@@ -0,0 +1,139 @@
+//! The summaries of the pages of regions, counted in 4K pages.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, RangeSummary};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// A 2M page, then a read-write 4K page, a hole and an executable 4K page.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0), PhysAddr::from(0x20_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    for (off, flags) in [(SIZE_2M, RW), (SIZE_2M + 0x2000, RX)] {
+        pt.map(
+            va(off),
+            PhysAddr::from(0x40_0000 + off),
+            PageSize::Size4K,
+            flags,
+        )
+        .unwrap()
+        .ignore();
+    }
+    pt
+}
+
+#[test]
+fn flags_and_holes() {
+    let pt = mapped();
+    assert_eq!(
+        pt.summarize(va(0), SIZE_2M + 0x4000),
+        Ok(RangeSummary {
+            present_pages: 514,
+            holes: 2,
+            flags_union: RW | RX,
+            flags_intersection: MappingFlags::READ,
+            any_huge: true,
+            any_cow: false,
+            any_dirty: false,
+        })
+    );
+    // The part of the huge page in the region only.
+    let summary = pt.summarize(va(SIZE_2M - 0x3000), 0x5000).unwrap();
+    assert_eq!((summary.present_pages, summary.holes), (4, 1));
+    assert_eq!(summary.flags_intersection, RW);
+
+    let summary = pt.summarize(va(SIZE_2M + 0x2000), 0x1000).unwrap();
+    assert!(!summary.any_huge);
+    assert_eq!(summary.flags_union, summary.flags_intersection);
+}
+
+#[test]
+fn empty() {
+    let pt = mapped();
+    assert_eq!(pt.summarize(va(0), 0), Ok(RangeSummary::default()));
+    // Nothing is mapped: no flag is common to all the pages.
+    assert_eq!(
+        pt.summarize(va(0x4000_0000), 0x4000_0000),
+        Ok(RangeSummary {
+            holes: 0x4_0000,
+            ..Default::default()
+        })
+    );
+}
+
+#[test]
+fn absent_pages_are_holes() {
+    let mut pt = mapped();
+    pt.set_absent_token(va(SIZE_2M + 0x1000), 7).unwrap();
+    let summary = pt.summarize(va(SIZE_2M), 0x3000).unwrap();
+    assert_eq!((summary.present_pages, summary.holes), (2, 1));
+}
+
+#[test]
+fn dirty() {
+    let mut pt = mapped();
+    assert!(!pt.summarize(va(0), SIZE_2M + 0x4000).unwrap().any_dirty);
+    pt.set_dirty(va(SIZE_2M), true).unwrap();
+    assert!(pt.summarize(va(SIZE_2M), 0x1000).unwrap().any_dirty);
+    assert!(!pt.summarize(va(0), SIZE_2M).unwrap().any_dirty);
+}
+
+/// With LoongArch entries, which keep [`MappingFlags::COW`].
+#[cfg(feature = "all-formats")]
+#[test]
+fn cow() {
+    use page_table_entry::loongarch64::LA64PTE;
+    use page_table_multiarch::loongarch64::LA64MetaData;
+
+    MockHandler::reset();
+    let mut pt = MockPageTable::<LA64MetaData, LA64PTE>::try_new().unwrap();
+    for off in [0, 0x1000] {
+        pt.map(
+            va(off),
+            PhysAddr::from(0x40_0000 + off),
+            PageSize::Size4K,
+            RW,
+        )
+        .unwrap()
+        .ignore();
+    }
+    let (_child, tlb) = pt.clone_cow(va(0), 0x1000).unwrap();
+    tlb.ignore();
+
+    let summary = pt.summarize(va(0), 0x2000).unwrap();
+    assert!(summary.any_cow);
+    assert_eq!(summary.flags_union, RW | MappingFlags::COW);
+    assert_eq!(summary.flags_intersection, MappingFlags::READ);
+    assert!(!pt.summarize(va(0x1000), 0x1000).unwrap().any_cow);
+}
+
+#[test]
+fn errors() {
+    let pt = mapped();
+    assert_eq!(
+        pt.summarize(va(0x800), 0x1000),
+        Err(PagingError::NotAligned)
+    );
+    assert_eq!(pt.summarize(va(0), 0x800), Err(PagingError::NotAligned));
+    assert_eq!(
+        pt.summarize(VirtAddr::from(0x7fff_ffff_f000), 0x2000),
+        Err(PagingError::InvalidVaddr(0x7fff_ffff_f000))
+    );
+}
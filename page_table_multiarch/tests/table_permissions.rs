@@ -0,0 +1,122 @@
+//! Checks the permissions of the intermediate x86_64 table entries.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::RefCell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::{PTF, X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize};
+
+type Meta = MockMetaData<X64PagingMetaData>;
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const KERNEL: usize = 0xffff_8000_0000_0000;
+const USER: usize = 0x40_0000_0000;
+const PERMISSIONS: PTF = PTF::WRITABLE
+    .union(PTF::USER_ACCESSIBLE)
+    .union(PTF::NO_EXECUTE);
+
+fn map(pt: &mut PageTable, vaddr: usize, size: PageSize, flags: MappingFlags) {
+    pt.map(
+        VirtAddr::from(vaddr),
+        PhysAddr::from(0x20_0000),
+        size,
+        flags,
+    )
+    .unwrap()
+    .flush();
+}
+
+/// Returns the permissions of the table entries covering `vaddr`, from the
+/// root down.
+fn table_permissions(pt: &PageTable, vaddr: usize) -> Vec<PTF> {
+    let bits = RefCell::new(Vec::new());
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, va: VirtAddr, entry: &X64PTE| {
+            // The walk does not sign-extend the addresses.
+            let covered = 1usize << (12 + (3 - level) * 9);
+            let start = va.as_usize();
+            let vaddr = vaddr & ((1 << 48) - 1);
+            if level < 3 && !entry.is_huge() && (start..start + covered).contains(&vaddr) {
+                let flags = PTF::from_bits_truncate(entry.bits() as u64);
+                bits.borrow_mut().push(flags & PERMISSIONS);
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    bits.into_inner()
+}
+
+#[test]
+fn kernel_only() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, KERNEL, PageSize::Size4K, MappingFlags::READ);
+    assert_eq!(table_permissions(&pt, KERNEL), [PTF::NO_EXECUTE; 3]);
+
+    let rw = MappingFlags::READ | MappingFlags::WRITE;
+    map(&mut pt, KERNEL + 0x1000, PageSize::Size4K, rw);
+    assert_eq!(
+        table_permissions(&pt, KERNEL),
+        [PTF::WRITABLE | PTF::NO_EXECUTE; 3]
+    );
+    // Widening the existing entries needs a flush.
+    assert_eq!(
+        Meta::take_flushes(),
+        [Some(VirtAddr::from(KERNEL + 0x1000))]
+    );
+
+    let rx = MappingFlags::READ | MappingFlags::EXECUTE;
+    map(&mut pt, KERNEL + 0x40_0000, PageSize::Size2M, rx);
+    assert_eq!(
+        table_permissions(&pt, KERNEL + 0x40_0000),
+        [PTF::WRITABLE; 2]
+    );
+    // The P1 table entry of the first 2M block keeps NX.
+    assert_eq!(
+        table_permissions(&pt, KERNEL)[2],
+        PTF::WRITABLE | PTF::NO_EXECUTE
+    );
+}
+
+#[test]
+fn user_only() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let flags = MappingFlags::READ | MappingFlags::USER;
+    map(&mut pt, USER, PageSize::Size4K, flags);
+    map(&mut pt, USER + 0x1000, PageSize::Size4K, flags);
+    assert_eq!(
+        table_permissions(&pt, USER),
+        [PTF::USER_ACCESSIBLE | PTF::NO_EXECUTE; 3]
+    );
+    // No existing entry was widened.
+    assert_eq!(Meta::take_flushes(), []);
+}
+
+#[test]
+fn mixed() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    map(&mut pt, USER, PageSize::Size4K, MappingFlags::READ);
+    let user_rx = MappingFlags::READ | MappingFlags::EXECUTE | MappingFlags::USER;
+    map(&mut pt, USER + 0x1000, PageSize::Size4K, user_rx);
+    assert_eq!(table_permissions(&pt, USER), [PTF::USER_ACCESSIBLE; 3]);
+
+    // `protect` widens the tables too.
+    let vaddr = VirtAddr::from(USER + 0x1000);
+    let user_rw = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+    pt.protect(vaddr, user_rw).unwrap().1.flush();
+    assert_eq!(
+        table_permissions(&pt, USER),
+        [PTF::WRITABLE | PTF::USER_ACCESSIBLE; 3]
+    );
+    // Other subtrees are not affected.
+    map(&mut pt, KERNEL, PageSize::Size4K, MappingFlags::READ);
+    assert_eq!(table_permissions(&pt, KERNEL), [PTF::NO_EXECUTE; 3]);
+}
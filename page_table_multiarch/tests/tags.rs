@@ -0,0 +1,110 @@
+//! Tags of the mappings, kept in software bits across protection, forks and
+//! splits, and the bytes mapped with each.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{GenericPTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const HUGE: usize = 0x40_0000_0000;
+const PAGES: usize = HUGE + 0x20_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const HEAP: u8 = 3;
+const STACK: u8 = 5;
+
+fn va(vaddr: usize) -> VirtAddr {
+    VirtAddr::from(vaddr)
+}
+
+/// A 2M page of the heap, and two 4K pages of a stack after it.
+fn tagged() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(HUGE), PhysAddr::from(0x20_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    pt.map_region(
+        va(PAGES),
+        |v| PhysAddr::from(v.as_usize() - HUGE),
+        0x2000,
+        RW,
+        false,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    // Partly overlapped, the huge page is tagged whole.
+    assert_eq!(pt.set_tag_region(va(HUGE + 0x1000), 0x1000, HEAP), Ok(1));
+    assert_eq!(pt.set_tag_region(va(PAGES), 0x3000, STACK), Ok(2));
+    pt
+}
+
+#[test]
+fn set_and_read() {
+    let mut pt = tagged();
+    assert_eq!(pt.tag(va(HUGE + 0x1234)), Ok(HEAP));
+    assert_eq!(pt.tag(va(PAGES + 0x1000)), Ok(STACK));
+    assert_eq!(pt.tag(va(PAGES + 0x2000)), Err(PagingError::NotMapped));
+    let mut bytes = [0; 16];
+    (bytes[HEAP as usize], bytes[STACK as usize]) = (0x20_0000, 0x2000);
+    assert_eq!(pt.mapped_bytes_by_tag(), bytes);
+
+    // Protection keeps the tag, mapping again resets it.
+    pt.protect(va(PAGES), MappingFlags::READ)
+        .unwrap()
+        .1
+        .ignore();
+    assert_eq!(pt.tag(va(PAGES)), Ok(STACK));
+    pt.unmap(va(PAGES + 0x1000)).unwrap().2.ignore();
+    pt.map(va(PAGES + 0x1000), PhysAddr::from(0), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    assert_eq!(pt.tag(va(PAGES + 0x1000)), Ok(0));
+    (bytes[0], bytes[STACK as usize]) = (0x1000, 0x1000);
+    assert_eq!(pt.mapped_bytes_by_tag(), bytes);
+
+    // Wider tags are truncated.
+    assert_eq!(pt.set_tag_region(va(PAGES), 0x1000, 0x12), Ok(1));
+    assert_eq!(pt.tag(va(PAGES)), Ok(2));
+}
+
+#[test]
+fn fork_and_split() {
+    let mut parent = tagged();
+    let (mut child, tlb) = parent.clone_cow(va(HUGE), 0x20_2000).unwrap();
+    tlb.ignore();
+    assert_eq!(child.tag(va(HUGE)), Ok(HEAP));
+    assert_eq!(child.tag(va(PAGES)), Ok(STACK));
+
+    // Splitting the huge page to sample its pages one by one.
+    let mut idle = [0u8; 512];
+    let (_, tlb) = child
+        .estimate_working_set(va(HUGE), 0x20_0000, true, &mut idle[..])
+        .unwrap();
+    tlb.ignore();
+    let (_, _, size) = child.query(va(HUGE + 0x5000)).unwrap();
+    assert_eq!(size, PageSize::Size4K);
+    assert_eq!(child.tag(va(HUGE + 0x5000)), Ok(HEAP));
+    assert_eq!(child.mapped_bytes_by_tag()[HEAP as usize], 0x20_0000);
+    assert_eq!(parent.tag(va(HUGE)), Ok(HEAP));
+}
+
+#[test]
+fn formats_without_tags() {
+    MockHandler::reset();
+    assert_eq!(<Rv64PTE>::TAG_BITS, 0);
+    let mut pt = MockPageTable::<Sv39MetaData<VirtAddr>, Rv64PTE>::try_new().unwrap();
+    pt.map(va(0x4000_0000), PhysAddr::from(0), PageSize::Size4K, RW)
+        .unwrap()
+        .ignore();
+    // The tag is dropped with a warning, and no entry changes.
+    assert_eq!(pt.set_tag_region(va(0x4000_0000), 0x1000, HEAP), Ok(0));
+    assert_eq!(pt.tag(va(0x4000_0000)), Ok(0));
+    assert_eq!(pt.mapped_bytes_by_tag()[0], 0x1000);
+}
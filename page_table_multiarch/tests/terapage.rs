@@ -0,0 +1,131 @@
+//! Checks 512G pages mapped by root entries of RISC-V Sv48 page tables, and
+//! that other page tables reject them.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, riscv::Rv64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, table_frames};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<Sv48MetaData<VirtAddr>, Rv64PTE>;
+
+const SIZE: usize = PageSize::Size512G as usize;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn va(addr: usize) -> VirtAddr {
+    VirtAddr::from(addr)
+}
+
+#[test]
+fn direct_map() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // 2T of physical memory at 0x1000_0000_0000.
+    let base = 0x1000_0000_0000;
+    pt.map_region(
+        va(base),
+        |v| PhysAddr::from(v - va(base)),
+        4 * SIZE,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(pt.level_occupancy(), [4, 0, 0, 0]);
+    assert_eq!(table_frames(&pt), 1);
+    assert_eq!(pt.mapped_bytes(), 4 * SIZE);
+
+    let vaddr = va(base + SIZE + 0x1234_5678);
+    let paddr = PhysAddr::from(SIZE + 0x1234_5678);
+    assert_eq!(pt.query(vaddr), Ok((paddr, FLAGS, PageSize::Size512G)));
+    assert_eq!(
+        pt.protect(va(base + SIZE), MappingFlags::READ)
+            .map(|(size, tlb)| {
+                tlb.ignore();
+                size
+            }),
+        Ok(PageSize::Size512G)
+    );
+    let (unmapped, size, tlb) = pt.unmap(va(base + SIZE)).unwrap();
+    tlb.ignore();
+    assert_eq!((unmapped, size), (PhysAddr::from(SIZE), PageSize::Size512G));
+    assert_eq!(pt.query(vaddr), Err(PagingError::NotMapped));
+    assert_eq!(pt.level_occupancy(), [3, 0, 0, 0]);
+}
+
+#[test]
+fn unaligned_region_uses_smaller_pages() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // The physical addresses are only 1G-aligned.
+    pt.map_region(
+        va(0),
+        |v| PhysAddr::from(v.as_usize() + 0x4000_0000),
+        SIZE,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(pt.level_occupancy(), [1, 512, 0, 0]);
+    assert_eq!(
+        pt.query(va(0)),
+        Ok((PhysAddr::from(0x4000_0000), FLAGS, PageSize::Size1G))
+    );
+}
+
+#[test]
+fn split_by_paddr() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0), PhysAddr::from(0), PageSize::Size512G, FLAGS)
+        .unwrap()
+        .ignore();
+    // Removing a 4K page splits down to the 4K entries.
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(0x4000_0000), 0x1000);
+    pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap().ignore();
+    assert_eq!(pt.level_occupancy(), [1, 512, 512, 511]);
+    assert_eq!(pt.mapped_bytes(), SIZE - 0x1000);
+    assert_eq!(
+        pt.query(va(0x4000_1000)),
+        Ok((PhysAddr::from(0x4000_1000), FLAGS, PageSize::Size4K))
+    );
+    assert_eq!(
+        pt.query(va(0x8000_0000)),
+        Ok((PhysAddr::from(0x8000_0000), FLAGS, PageSize::Size1G))
+    );
+}
+
+#[test]
+fn rejected_elsewhere() {
+    fn check<T>(map: impl FnOnce() -> Result<T, PagingError>) {
+        assert_eq!(map().err(), Some(PagingError::UnsupportedPageSize));
+    }
+    MockHandler::reset();
+    let paddr = PhysAddr::from(0);
+    let mut x86 = MockPageTable::<X64PagingMetaData, X64PTE>::try_new().unwrap();
+    check(|| x86.map(va(0), paddr, PageSize::Size512G, FLAGS));
+    let mut arm = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    check(|| arm.map(va(0), paddr, PageSize::Size512G, FLAGS));
+    let mut sv39 = MockPageTable::<Sv39MetaData<VirtAddr>, Rv64PTE>::try_new().unwrap();
+    check(|| sv39.map(va(0), paddr, PageSize::Size512G, FLAGS));
+
+    // The largest pages are 1G.
+    x86.map_region(
+        va(0),
+        |v| PhysAddr::from(v.as_usize()),
+        SIZE,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(x86.level_occupancy(), [1, 512, 0, 0]);
+}
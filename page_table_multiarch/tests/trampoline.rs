@@ -0,0 +1,165 @@
+//! Trampoline tables built in frames given by the caller, identity-mapping a
+//! few pages and borrowing the top-level entries of a kernel page table.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::aarch64::A64PTE;
+use page_table_entry::loongarch64::LA64PTE;
+use page_table_entry::riscv::Rv64PTE;
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    GenericPTE, MappingFlags, PageSize, PagingError, PagingMetaData, PhysFrameSlot,
+};
+
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+/// Two pages of trampoline code.
+const CODE: usize = 0x8000_0000;
+/// A 2M page of data after it.
+const DATA: usize = 0x8020_0000;
+const SIZE_2M: usize = PageSize::Size2M as usize;
+const KERNEL: usize = 0xffff_ffc0_8000_0000;
+const KERNEL_PADDR: usize = 0x9000_0000;
+
+/// A frame for a table. The mock maps the physical memory at the same
+/// addresses, so its address is its physical address.
+#[derive(Clone)]
+#[repr(align(4096))]
+struct Frame([u64; 512]);
+
+fn buffers(count: usize) -> Vec<Frame> {
+    vec![Frame([0; 512]); count]
+}
+
+fn slots(buffers: &mut [Frame]) -> Vec<PhysFrameSlot<'_>> {
+    buffers
+        .iter_mut()
+        .map(|frame| PhysFrameSlot::new(PhysAddr::from(frame.0.as_ptr() as usize), &mut frame.0))
+        .collect()
+}
+
+/// Translates `vaddr` with the tables from `root`, like the hardware.
+fn translate<PTE: GenericPTE>(
+    root: PhysAddr,
+    levels: usize,
+    vaddr: usize,
+) -> Option<(PhysAddr, MappingFlags)> {
+    let mut table = root.as_usize() as *const PTE;
+    for level in 0..levels {
+        let shift = 12 + 9 * (levels - 1 - level);
+        let entry = unsafe { *table.add(vaddr >> shift & 511) };
+        if entry.is_unused() {
+            return None;
+        }
+        if level == levels - 1 || entry.is_huge() {
+            let offset = vaddr & ((1 << shift) - 1);
+            return Some((entry.paddr() + offset, entry.flags()));
+        }
+        table = entry.paddr().as_usize() as *const PTE;
+    }
+    unreachable!()
+}
+
+fn trampoline<M: PagingMetaData<VirtAddr = VirtAddr>, PTE: GenericPTE>() {
+    MockHandler::reset();
+    let mut kernel = MockPageTable::<M, PTE>::try_new().unwrap();
+    kernel
+        .map(
+            KERNEL.into(),
+            PhysAddr::from(KERNEL_PADDR),
+            PageSize::Size4K,
+            RX,
+        )
+        .unwrap()
+        .ignore();
+    let allocated = MockHandler::stats().allocated;
+
+    let mut buffers = buffers(8);
+    let mut frames = slots(&mut buffers);
+    let identity = [
+        (PhysAddr::from(CODE), 0x2000, RX),
+        (PhysAddr::from(DATA), SIZE_2M, RW),
+    ];
+    let root = MockPageTable::<M, PTE>::build_trampoline_table(
+        &mut frames,
+        &identity,
+        Some((&kernel, KERNEL.into(), 0x1000)),
+    )
+    .unwrap();
+    assert_eq!(root, frames[0].paddr());
+    // The root and a table at each level down to the 4K pages of the code.
+    let used = frames.iter().filter(|slot| slot.is_used()).count();
+    assert_eq!(used, M::LEVELS);
+    assert_eq!(MockHandler::stats().allocated, allocated);
+
+    let levels = M::LEVELS;
+    let (paddr, flags) = translate::<PTE>(root, levels, CODE + 0x1234).unwrap();
+    assert_eq!(paddr, PhysAddr::from(CODE + 0x1234));
+    assert!(flags.contains(RX));
+    let (paddr, flags) = translate::<PTE>(root, levels, DATA + 0x1_2345).unwrap();
+    assert_eq!(paddr, PhysAddr::from(DATA + 0x1_2345));
+    assert!(flags.contains(RW));
+    assert_eq!(translate::<PTE>(root, levels, CODE + 0x2000), None);
+    // Through the tables of the kernel page table.
+    let (paddr, _) = translate::<PTE>(root, levels, KERNEL + 0x10).unwrap();
+    assert_eq!(paddr, PhysAddr::from(KERNEL_PADDR + 0x10));
+}
+
+#[test]
+fn x86_64() {
+    trampoline::<X64PagingMetaData, X64PTE>();
+}
+
+#[test]
+fn aarch64() {
+    trampoline::<A64PagingMetaData, A64PTE>();
+}
+
+#[test]
+fn riscv() {
+    trampoline::<Sv39MetaData<VirtAddr>, Rv64PTE>();
+    trampoline::<Sv48MetaData<VirtAddr>, Rv64PTE>();
+}
+
+#[test]
+fn loongarch64() {
+    trampoline::<LA64MetaData, LA64PTE>();
+}
+
+#[test]
+fn errors() {
+    type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+    MockHandler::reset();
+    let kernel = PageTable::try_new().unwrap();
+    let code = [(PhysAddr::from(CODE), 0x1000, RX)];
+
+    // Four tables are needed.
+    let mut buffers = buffers(3);
+    let mut frames = slots(&mut buffers);
+    let result = PageTable::build_trampoline_table(&mut frames, &code, None);
+    assert_eq!(result, Err(PagingError::NoMemory));
+    assert!(frames.iter().all(|slot| !slot.is_used()));
+
+    let mut buffers = self::buffers(4);
+    let mut frames = slots(&mut buffers);
+    let overlapping = [code[0], (PhysAddr::from(CODE), SIZE_2M, RW)];
+    let result = PageTable::build_trampoline_table(&mut frames, &overlapping, None);
+    assert_eq!(result, Err(PagingError::AlreadyMapped));
+    let extra = Some((&kernel, VirtAddr::from(CODE), 0x1000));
+    let result = PageTable::build_trampoline_table(&mut frames, &code, extra);
+    assert_eq!(result, Err(PagingError::AlreadyMapped));
+    let unaligned = [(PhysAddr::from(CODE + 0x10), 0x1000, RX)];
+    let result = PageTable::build_trampoline_table(&mut frames, &unaligned, None);
+    assert_eq!(result, Err(PagingError::NotAligned));
+    assert!(frames.iter().all(|slot| !slot.is_used()));
+
+    // The frames can be used again.
+    let root = PageTable::build_trampoline_table(&mut frames, &code, None).unwrap();
+    assert_eq!(root, frames[0].paddr());
+}
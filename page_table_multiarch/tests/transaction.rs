@@ -0,0 +1,220 @@
+//! Transactions replacing parts of several mappings, which restore the old
+//! entries when an operation fails while committing.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{self, MockHandler, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    AnySpace, MappingFlags, PageSize, PagingError, SavedEntry, Transaction,
+};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const RX: MappingFlags = MappingFlags::READ.union(MappingFlags::EXECUTE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+/// The frames mapped by the transactions.
+const NEW_PADDR: usize = 0x800_0000;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// Two regions of three 4K pages with a hole between them, and a 2M page.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    for (off, size) in [(0, 0x3000), (0x5000, 0x3000), (SIZE_2M, SIZE_2M)] {
+        let get_paddr = |vaddr: VirtAddr| PhysAddr::from(vaddr.as_usize() - VADDR + 0x100_0000);
+        pt.map_region(va(off), get_paddr, size, RW, true, false)
+            .unwrap()
+            .ignore();
+    }
+    pt.set_dirty(va(0x1000), true).unwrap();
+    pt
+}
+
+/// The translation of a 4K page, and whether it is dirty.
+type Page = Option<(PhysAddr, MappingFlags, PageSize, bool)>;
+
+/// The pages of the first 4M, and the mapped bytes of the page table.
+fn state(pt: &PageTable) -> (Vec<Page>, usize) {
+    let pages = (0..2 * SIZE_2M)
+        .step_by(0x1000)
+        .map(|off| {
+            let (paddr, flags, size) = pt.query(va(off)).ok()?;
+            Some((paddr, flags, size, pt.is_dirty(va(off)).unwrap()))
+        })
+        .collect();
+    let debug = format!("{pt:?}");
+    let mapped = debug.split("mapped_bytes: ").nth(1).unwrap();
+    let mapped = usize::from_str_radix(&mapped[2..mapped.find(',').unwrap()], 16).unwrap();
+    (pages, mapped)
+}
+
+#[test]
+fn map_fixed_over_several_mappings() {
+    let mut pt = mapped();
+    let mut saved = [SavedEntry::default(); 8];
+    let mut tx = pt.transaction(&mut saved);
+    tx.unmap_region(va(0x2000), 0x4000).unwrap();
+    tx.map_region(va(0x2000), PhysAddr::from(NEW_PADDR), 0x4000, RX, false)
+        .unwrap();
+    tx.commit().unwrap().ignore();
+
+    assert_eq!(
+        pt.query(va(0x1000)),
+        Ok((PhysAddr::from(0x100_1000), RW, PageSize::Size4K))
+    );
+    for off in (0x2000..0x6000).step_by(0x1000) {
+        assert_eq!(
+            pt.query(va(off)),
+            Ok((
+                PhysAddr::from(NEW_PADDR + off - 0x2000),
+                RX,
+                PageSize::Size4K
+            ))
+        );
+    }
+    assert_eq!(
+        pt.query(va(0x6000)),
+        Ok((PhysAddr::from(0x100_6000), RW, PageSize::Size4K))
+    );
+    // The hole is filled.
+    assert_eq!(state(&pt).1, 0x8000 + SIZE_2M);
+}
+
+#[test]
+fn rolled_back() {
+    let mut pt = mapped();
+    let before = state(&pt);
+    let live = MockHandler::live_frames();
+    // Room for two of the four new pages.
+    pt.set_limits(before.1, usize::MAX);
+    let mut saved = [SavedEntry::default(); 8];
+    let mut tx = pt.transaction(&mut saved);
+    tx.protect_region(va(0), 0x2000, MappingFlags::READ)
+        .unwrap();
+    tx.unmap_region(va(0x2000), 0x4000).unwrap();
+    tx.map_region(va(0x2000), PhysAddr::from(NEW_PADDR), 0x4000, RX, false)
+        .unwrap();
+    assert!(matches!(
+        tx.commit().err(),
+        Some(PagingError::QuotaExceeded { .. })
+    ));
+
+    // The protected pages keep their dirty bit.
+    assert_eq!(state(&pt), before);
+    assert_eq!(MockHandler::live_frames(), live);
+    assert_eq!(mock::check_well_formed(&pt), []);
+}
+
+#[test]
+fn huge_page_rolled_back() {
+    let mut pt = mapped();
+    let before = state(&pt);
+    let tables = mock::table_frames(&pt);
+    // The 4K pages need a table in place of the 2M page, which is freed.
+    pt.set_limits(usize::MAX, tables + 1);
+    let mut saved = [SavedEntry::default(); 8];
+    let mut tx = pt.transaction(&mut saved);
+    tx.unmap_region(va(SIZE_2M), SIZE_2M).unwrap();
+    tx.map_region(va(SIZE_2M), PhysAddr::from(NEW_PADDR), 0x2000, RX, true)
+        .unwrap();
+    // A new table is needed at a level above.
+    tx.map_region(va(0x4000_0000), PhysAddr::from(NEW_PADDR), 0x1000, RX, true)
+        .unwrap();
+    assert!(matches!(
+        tx.commit().err(),
+        Some(PagingError::QuotaExceeded { .. })
+    ));
+
+    assert_eq!(state(&pt), before);
+    assert_eq!(mock::table_frames(&pt), tables);
+    assert_eq!(
+        pt.query(va(SIZE_2M)),
+        Ok((PhysAddr::from(0x120_0000), RW, PageSize::Size2M))
+    );
+}
+
+#[test]
+fn staging_errors() {
+    let mut pt = mapped();
+    let before = state(&pt);
+    let mut saved = [SavedEntry::default(); 4];
+    let mut tx = pt.transaction(&mut saved);
+    assert_eq!(
+        tx.unmap_region(va(0x800), 0x1000),
+        Err(PagingError::NotAligned)
+    );
+    assert_eq!(
+        tx.unmap_region(va(SIZE_2M), 0x1000),
+        Err(PagingError::MappedToHugePage {
+            vaddr: VADDR + SIZE_2M,
+            level: 2
+        })
+    );
+    // Five pages do not fit in the buffer.
+    assert_eq!(tx.unmap_region(va(0), 0x8000), Err(PagingError::NoMemory));
+    // The third page is not unmapped by the transaction.
+    tx.unmap_region(va(0), 0x2000).unwrap();
+    assert_eq!(
+        tx.map_region(va(0), PhysAddr::from(NEW_PADDR), 0x3000, RX, false),
+        Err(PagingError::AlreadyMapped)
+    );
+    assert_eq!(
+        tx.protect_region(va(0x1000), 0x1000, MappingFlags::READ),
+        Err(PagingError::Overlapping)
+    );
+    tx.map_region(va(0), PhysAddr::from(NEW_PADDR), 0x2000, RX, false)
+        .unwrap();
+    assert_eq!(
+        tx.unmap_region(va(0x1000), 0x1000),
+        Err(PagingError::Overlapping)
+    );
+    // Discarded without committing.
+    assert_eq!(state(&pt), before);
+
+    let mut tx = pt.transaction(&mut saved);
+    for i in 0..Transaction::<X64PagingMetaData, X64PTE, MockHandler, AnySpace>::MAX_OPS {
+        tx.map_region(
+            va(0x1000_0000 + i * 0x1000),
+            PhysAddr::from(NEW_PADDR),
+            0x1000,
+            RX,
+            false,
+        )
+        .unwrap();
+    }
+    assert_eq!(tx.unmap_region(va(0), 0x1000), Err(PagingError::NoMemory));
+    // Empty operations are not staged.
+    tx.unmap_region(va(0), 0).unwrap();
+    tx.commit().unwrap().ignore();
+}
+
+#[test]
+fn locked_and_absent_pages() {
+    let mut pt = mapped();
+    pt.lock_region(va(0), 0x1000, |_, _| None)
+        .unwrap()
+        .1
+        .ignore();
+    pt.set_absent_token(va(0x3000), 7).unwrap();
+    let mut saved = [SavedEntry::default(); 8];
+    let mut tx = pt.transaction(&mut saved);
+    assert_eq!(tx.unmap_region(va(0), 0x2000), Err(PagingError::Locked));
+    // The payload is cleared with the pages.
+    tx.unmap_region(va(0x2000), 0x2000).unwrap();
+    tx.map_region(va(0x2000), PhysAddr::from(NEW_PADDR), 0x2000, RX, false)
+        .unwrap();
+    tx.commit().unwrap().ignore();
+    assert_eq!(pt.absent_token(va(0x3000)), None);
+    assert_eq!(
+        pt.query(va(0x3000)),
+        Ok((PhysAddr::from(NEW_PADDR + 0x1000), RX, PageSize::Size4K))
+    );
+}
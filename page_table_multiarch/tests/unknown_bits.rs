@@ -0,0 +1,111 @@
+//! Bits of the leaf entries not modeled by the crate, e.g. set by firmware,
+//! which are reported and kept by the changes of the mappings.
+
+#![cfg(target_arch = "x86_64")]
+
+use std::cell::Cell;
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, check_unknown_bits};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingHandler};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const VADDR: usize = 0x1000;
+const HUGE_VADDR: usize = 0x4000_0000;
+/// G and a protection key, which the crate never sets.
+const FOREIGN: u64 = 1 << 8 | 0b0110 << 59;
+
+/// Returns a pointer to the entry of `vaddr` at `level`, as the walk only
+/// passes copies.
+fn entry(pt: &PageTable, vaddr: usize, level: usize) -> *mut X64PTE {
+    let shift = |level: usize| 39 - 9 * level;
+    let table = Cell::new(None);
+    pt.walk(
+        usize::MAX,
+        Some(&|l, _, va: VirtAddr, entry: &X64PTE| {
+            if l + 1 == level && va.as_usize() == vaddr >> shift(l) << shift(l) {
+                table.set(Some(entry.paddr()));
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let table = MockHandler::phys_to_virt(table.get().unwrap()).as_mut_ptr() as *mut X64PTE;
+    unsafe { table.add(vaddr >> shift(level) & 511) }
+}
+
+/// Maps a 4K page and a 2M page, then adds [`FOREIGN`] to their entries like
+/// firmware that built the table.
+fn adopted() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(
+        VADDR.into(),
+        PhysAddr::from(0x8000_0000),
+        PageSize::Size4K,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    pt.map(
+        HUGE_VADDR.into(),
+        PhysAddr::from(0x8020_0000),
+        PageSize::Size2M,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(check_unknown_bits(&pt), []);
+    for (vaddr, level) in [(VADDR, 3), (HUGE_VADDR, 2)] {
+        let pte = entry(&pt, vaddr, level);
+        unsafe { *pte = core::mem::transmute::<u64, X64PTE>((*pte).bits() as u64 | FOREIGN) };
+    }
+    pt
+}
+
+#[test]
+fn reported() {
+    let pt = adopted();
+    let foreign = FOREIGN as usize;
+    assert_eq!(
+        check_unknown_bits(&pt),
+        [(VADDR, foreign), (HUGE_VADDR, foreign)]
+    );
+    // They do not change the mappings.
+    assert_eq!(
+        pt.query(VADDR.into()),
+        Ok((PhysAddr::from(0x8000_0000), RW, PageSize::Size4K))
+    );
+}
+
+#[test]
+fn kept_by_changes() {
+    let mut pt = adopted();
+    pt.protect(VADDR.into(), MappingFlags::READ)
+        .unwrap()
+        .1
+        .ignore();
+    pt.remap(VADDR.into(), PhysAddr::from(0x9000_0000), RW)
+        .unwrap()
+        .1
+        .ignore();
+    let pte = unsafe { *entry(&pt, VADDR, 3) };
+    assert_eq!(pte.unknown_bits() as u64, FOREIGN);
+    assert_eq!(pte.paddr(), PhysAddr::from(0x9000_0000));
+    assert_eq!(pte.flags(), RW);
+
+    // The pages split from the huge page have them too.
+    let paddrs = PhysAddrRange::from_start_size(PhysAddr::from(0x8020_1000), 0x1000);
+    pt.unmap_paddr_range(paddrs, |_, _| {}).unwrap().ignore();
+    let unknown = check_unknown_bits(&pt);
+    assert_eq!(unknown.len(), 512);
+    assert!(unknown.iter().all(|&(_, bits)| bits as u64 == FOREIGN));
+    assert_eq!(
+        pt.query(VirtAddr::from(HUGE_VADDR + 0x2000)),
+        Ok((PhysAddr::from(0x8020_2000), RW, PageSize::Size4K))
+    );
+}
@@ -0,0 +1,221 @@
+//! Checks the removal of all the mappings of a physical range, with the huge
+//! pages and groups that only partially overlap it split.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, PhysAddrRange, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, loongarch64::LA64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{
+    MockHandler, MockMetaData, MockPageTable, check_contiguous, table_frames,
+};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingHandler};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const PADDR: usize = 0x4000_0000;
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn range(start: usize, size: usize) -> PhysAddrRange {
+    PhysAddrRange::from_start_size(PhysAddr::from(PADDR + start), size)
+}
+
+/// Unmaps `paddrs` from `pt`, and returns the removed pages.
+fn unmap<M, PTE>(pt: &mut MockPageTable<M, PTE>, paddrs: PhysAddrRange) -> Vec<(VirtAddr, PageSize)>
+where
+    M: page_table_multiarch::PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: page_table_multiarch::GenericPTE,
+{
+    let mut removed = Vec::new();
+    pt.unmap_paddr_range(paddrs, |vaddr, size| removed.push((vaddr, size)))
+        .unwrap()
+        .flush_all();
+    removed
+}
+
+fn query(pt: &PageTable, off: usize) -> Result<(PhysAddr, PageSize), PagingError> {
+    let (paddr, _, size) = pt.query(VirtAddr::from(VADDR + off))?;
+    Ok((paddr, size))
+}
+
+#[test]
+fn whole_pages() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    // The same frame is mapped twice, and the next one once.
+    for (vaddr, paddr) in [
+        (VADDR, PADDR),
+        (VADDR + 0x5000, PADDR),
+        (VADDR + 0x1000, PADDR + 0x1000),
+    ] {
+        pt.map(vaddr.into(), paddr.into(), PageSize::Size4K, FLAGS)
+            .unwrap()
+            .ignore();
+    }
+    let removed = unmap(&mut pt, range(0, 0x1000));
+    let expected = [VADDR, VADDR + 0x5000].map(|va| (VirtAddr::from(va), PageSize::Size4K));
+    assert_eq!(removed, expected);
+    assert_eq!(query(&pt, 0), Err(PagingError::NotMapped));
+    assert_eq!(query(&pt, 0x5000), Err(PagingError::NotMapped));
+    assert_eq!(
+        query(&pt, 0x1000),
+        Ok((PhysAddr::from(PADDR + 0x1000), PageSize::Size4K))
+    );
+    assert_eq!(pt.mapped_bytes(), 0x1000);
+    // An empty range, or one that reaches no frame, removes nothing.
+    assert_eq!(unmap(&mut pt, range(0, 0)), []);
+    assert_eq!(unmap(&mut pt, range(0x2000, 0x1000)), []);
+}
+
+#[test]
+fn split_huge_page() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let size = PageSize::Size2M as usize;
+    pt.map(VADDR.into(), PADDR.into(), PageSize::Size2M, FLAGS)
+        .unwrap()
+        .ignore();
+    let frames = pt.table_frames();
+    // The range also covers memory that is not mapped.
+    let removed = unmap(&mut pt, range(0x1f_e000, 0x10_0000));
+    let expected =
+        [0x1f_e000, 0x1f_f000].map(|off| (VirtAddr::from(VADDR + off), PageSize::Size4K));
+    assert_eq!(removed, expected);
+    assert_eq!(pt.mapped_bytes(), size - 0x2000);
+    assert_eq!(pt.table_frames(), frames + 1);
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+    for off in (0..size - 0x2000).step_by(0x1000) {
+        let (paddr, _, page_size) = pt.query(VirtAddr::from(VADDR + off)).unwrap();
+        assert_eq!(
+            (paddr, page_size),
+            (PhysAddr::from(PADDR + off), PageSize::Size4K)
+        );
+    }
+    assert_eq!(query(&pt, 0x1f_e000), Err(PagingError::NotMapped));
+    drop(pt);
+    assert_eq!(MockHandler::live_frames(), 0);
+}
+
+#[test]
+fn split_recursively() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(VADDR.into(), PADDR.into(), PageSize::Size1G, FLAGS)
+        .unwrap()
+        .ignore();
+    let removed = unmap(&mut pt, range(0x20_3000, 0x1000));
+    assert_eq!(
+        removed,
+        [(VirtAddr::from(VADDR + 0x20_3000), PageSize::Size4K)]
+    );
+    // Only the 2M page containing the frame is split into 4K pages.
+    assert_eq!(
+        query(&pt, 0x1234),
+        Ok((PhysAddr::from(PADDR + 0x1234), PageSize::Size2M))
+    );
+    assert_eq!(
+        query(&pt, 0x20_4000),
+        Ok((PhysAddr::from(PADDR + 0x20_4000), PageSize::Size4K))
+    );
+    assert_eq!(query(&pt, 0x20_3000), Err(PagingError::NotMapped));
+    assert_eq!(pt.mapped_bytes(), PageSize::Size1G as usize - 0x1000);
+    assert_eq!(pt.table_frames(), table_frames(&pt));
+}
+
+#[test]
+fn split_fails_without_memory() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(VADDR.into(), PADDR.into(), PageSize::Size2M, FLAGS)
+        .unwrap()
+        .ignore();
+    pt.set_limits(usize::MAX, pt.table_frames());
+    let result = pt.unmap_paddr_range(range(0, 0x1000), |_, _| panic!());
+    assert!(matches!(result, Err(PagingError::QuotaExceeded { .. })));
+    assert_eq!(
+        query(&pt, 0x1000),
+        Ok((PhysAddr::from(PADDR + 0x1000), PageSize::Size2M))
+    );
+}
+
+#[test]
+fn split_cow_huge_page() {
+    MockHandler::reset();
+    // The x86_64 entries have no bit for copy-on-write.
+    let mut pt = MockPageTable::<LA64MetaData, LA64PTE>::try_new().unwrap();
+    let cow = FLAGS.cow_of().unwrap();
+    pt.map(VADDR.into(), PADDR.into(), PageSize::Size2M, cow)
+        .unwrap()
+        .ignore();
+    MockHandler::frame_shared(PhysAddr::from(PADDR), PageSize::Size2M);
+    unmap(&mut pt, range(0, 0x1000));
+    // Each 4K page got a reference, and the huge page and the removed page
+    // lost theirs. The first 4K page has the address of the huge page.
+    let stats = MockHandler::stats();
+    assert_eq!((stats.shared, stats.unshared), (1 + 512, 2));
+    assert_eq!(MockHandler::refs(PhysAddr::from(PADDR)), Some(1));
+    assert_eq!(MockHandler::refs(PhysAddr::from(PADDR + 0x1000)), Some(2));
+    assert_eq!(
+        pt.query(VirtAddr::from(VADDR + 0x1000)),
+        Ok((PhysAddr::from(PADDR + 0x1000), cow, PageSize::Size4K))
+    );
+}
+
+#[test]
+fn contiguous_groups() {
+    type Meta = MockMetaData<A64PagingMetaData>;
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    let size = 2 * PageSize::Size64K as usize;
+    pt.map_region(
+        VADDR.into(),
+        |va| PhysAddr::from(va.as_usize() - VADDR + PADDR),
+        size,
+        FLAGS,
+        true,
+        false,
+    )
+    .unwrap()
+    .ignore();
+    Meta::take_flushes();
+    // The first group is removed whole, and the second one is broken up.
+    let removed = unmap(&mut pt, range(0, 0x1_1000));
+    let vaddr = |off| VirtAddr::from(VADDR + off);
+    assert_eq!(
+        removed,
+        [
+            (vaddr(0), PageSize::Size64K),
+            (vaddr(0x1_0000), PageSize::Size4K)
+        ]
+    );
+    assert_eq!(check_contiguous(&pt), []);
+    assert_eq!(pt.query(vaddr(0x4000)), Err(PagingError::NotMapped));
+    assert_eq!(
+        pt.query(vaddr(0x1_1000)),
+        Ok((PhysAddr::from(PADDR + 0x1_1000), FLAGS, PageSize::Size4K))
+    );
+    assert_eq!(pt.mapped_bytes(), size - 0x1_1000);
+}
+
+#[test]
+fn break_before_make() {
+    type Meta = MockMetaData<A64PagingMetaData>;
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    pt.map(VADDR.into(), PADDR.into(), PageSize::Size2M, FLAGS)
+        .unwrap()
+        .ignore();
+    Meta::take_flushes();
+    let mut removed = Vec::new();
+    let tlb = pt
+        .unmap_paddr_range(range(0, 0x1000), |vaddr, size| removed.push((vaddr, size)))
+        .unwrap();
+    // The block was invalidated and flushed before the table was written.
+    assert_eq!(Meta::take_flushes(), [Some(VirtAddr::from(VADDR))]);
+    tlb.flush_all();
+    assert_eq!(Meta::take_flushes(), [None]);
+    assert_eq!(removed, [(VirtAddr::from(VADDR), PageSize::Size4K)]);
+}
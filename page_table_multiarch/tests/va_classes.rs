@@ -0,0 +1,122 @@
+//! The user and kernel addresses of each paging mode, checked at the exact
+//! boundaries, and the spaces that accept them.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::aarch64::A64PTE;
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::riscv::{Sv39MetaData, Sv48MetaData};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{
+    GuestPhysAddr, KernelSpace, MappingFlags, NarrowPaddr, PageSize, PagingError, PagingMetaData,
+    UserSpace,
+};
+
+/// Checks the classes of the addresses around the boundaries, with the user
+/// addresses ending at `user_end` and the kernel ones starting at
+/// `kernel_start`.
+fn classes<M: PagingMetaData>(user_end: usize, kernel_start: usize) {
+    assert_eq!(M::USER_VA_END, user_end, "{}", M::ARCH_NAME);
+    for (vaddr, user, kernel) in [
+        (0, true, false),
+        (user_end - 1, true, false),
+        (user_end, false, false),
+        (kernel_start - 1, false, false),
+        (kernel_start, false, true),
+        (usize::MAX, false, true),
+    ] {
+        assert_eq!(M::is_user_vaddr(vaddr), user, "{} {vaddr:#x}", M::ARCH_NAME);
+        assert_eq!(
+            M::is_kernel_vaddr(vaddr),
+            kernel,
+            "{} {vaddr:#x}",
+            M::ARCH_NAME
+        );
+    }
+
+    let range = M::user_va_range();
+    assert_eq!((range.start.into(), range.end.into()), (0, user_end));
+    let range = M::kernel_va_range();
+    assert_eq!(
+        (range.start.into(), range.end.into()),
+        (kernel_start, usize::MAX)
+    );
+}
+
+#[test]
+fn x86_64() {
+    classes::<X64PagingMetaData>(0x0000_8000_0000_0000, 0xffff_8000_0000_0000);
+}
+
+#[test]
+fn aarch64() {
+    // The whole range of TTBR0_EL1, then of TTBR1_EL1.
+    classes::<A64PagingMetaData>(0x0001_0000_0000_0000, 0xffff_0000_0000_0000);
+}
+
+#[test]
+fn riscv() {
+    classes::<Sv39MetaData<VirtAddr>>(1 << 38, 0xffff_ffc0_0000_0000);
+    classes::<Sv48MetaData<VirtAddr>>(1 << 47, 0xffff_8000_0000_0000);
+}
+
+#[test]
+fn riscv_guest() {
+    // Guest physical addresses are not sign-extended: all are user ones.
+    type Sv39 = Sv39MetaData<GuestPhysAddr>;
+    assert_eq!(Sv39::USER_VA_END, 1 << 39);
+    assert!(Sv39::is_user_vaddr((1 << 39) - 1));
+    assert!(!Sv39::is_user_vaddr(1 << 39));
+    assert!(!Sv39::is_kernel_vaddr(usize::MAX));
+    type Sv48 = Sv48MetaData<GuestPhysAddr>;
+    assert_eq!(Sv48::USER_VA_END, 1 << 48);
+    assert!(Sv48::is_user_vaddr((1 << 48) - 1));
+    assert!(!Sv48::is_kernel_vaddr(0xffff_8000_0000_0000));
+}
+
+#[test]
+fn loongarch64() {
+    classes::<LA64MetaData>(0x0000_8000_0000_0000, 0xffff_8000_0000_0000);
+}
+
+#[test]
+fn forwarded() {
+    classes::<MockMetaData<A64PagingMetaData>>(1 << 48, 0xffff_0000_0000_0000);
+    classes::<NarrowPaddr<A64PagingMetaData, 40>>(1 << 48, 0xffff_0000_0000_0000);
+    assert!(MockMetaData::<Sv39MetaData<GuestPhysAddr>>::is_user_vaddr(
+        1 << 38
+    ));
+}
+
+#[test]
+fn spaces() {
+    const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+    const USER_RW: MappingFlags = RW.union(MappingFlags::USER);
+    // In the upper half of the 48 bits, but still translated from TTBR0_EL1.
+    const USER: usize = 0x0000_8000_0000_0000;
+    const HOLE: usize = 0x0001_0000_0000_0000;
+    const KERNEL: usize = 0xffff_0000_0000_0000;
+    let paddr = PhysAddr::from(0x20_0000);
+    MockHandler::reset();
+
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE, UserSpace>::try_new().unwrap();
+    let mut map = |vaddr: usize| pt.map(vaddr.into(), paddr, PageSize::Size4K, USER_RW);
+    map(USER).unwrap().ignore();
+    assert_eq!(map(HOLE).err(), Some(PagingError::WrongSpace));
+    assert_eq!(map(KERNEL).err(), Some(PagingError::WrongSpace));
+
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE, KernelSpace>::try_new().unwrap();
+    let mut map = |vaddr: usize| pt.map(vaddr.into(), paddr, PageSize::Size4K, RW);
+    map(KERNEL).unwrap().ignore();
+    assert_eq!(map(HOLE).err(), Some(PagingError::WrongSpace));
+    assert_eq!(map(USER).err(), Some(PagingError::WrongSpace));
+
+    // The hole is in neither half.
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE, KernelSpace<true>>::try_new().unwrap();
+    let mut map = |vaddr: usize| pt.map(vaddr.into(), paddr, PageSize::Size4K, RW);
+    map(USER).unwrap().ignore();
+    assert_eq!(map(HOLE).err(), Some(PagingError::WrongSpace));
+}
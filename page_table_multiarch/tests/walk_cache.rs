@@ -0,0 +1,95 @@
+//! Checks that the walk cache never returns stale tables after the upper
+//! levels are modified.
+
+#![cfg(target_arch = "x86_64")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable, ShadowModel};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+type Model = ShadowModel<MockMetaData<X64PagingMetaData>, X64PTE>;
+
+const FLAGS: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+
+fn map(pt: &mut PageTable, model: &mut Model, vaddr: usize, paddr: usize, size: PageSize) {
+    let (vaddr, paddr) = (VirtAddr::from(vaddr), PhysAddr::from(paddr));
+    pt.map(vaddr, paddr, size, FLAGS).unwrap().ignore();
+    model.map(vaddr, paddr, size, FLAGS);
+}
+
+#[test]
+fn cache_invalidated_by_root_changes() {
+    MockHandler::reset();
+    let vaddr = 0x40_0000_0000;
+    let mut pt = PageTable::try_new().unwrap();
+    let mut other = PageTable::try_new().unwrap();
+    let mut model = Model::new();
+    let mut other_model = Model::new();
+
+    // Fill the cache of `pt` with tables covering `vaddr`.
+    map(&mut pt, &mut model, vaddr, 0x1000, PageSize::Size4K);
+    map(
+        &mut pt,
+        &mut model,
+        vaddr + 0x1000,
+        0x2000,
+        PageSize::Size4K,
+    );
+    map(
+        &mut other,
+        &mut other_model,
+        vaddr + 0x20_0000,
+        0x20_0000,
+        PageSize::Size2M,
+    );
+    model.check(&pt);
+
+    // Replace the root entry: the cached tables are no longer reachable.
+    pt.clear_copy_range(VirtAddr::from(vaddr), 0x1000);
+    assert_eq!(pt.query(VirtAddr::from(vaddr)), Err(PagingError::NotMapped));
+    assert_eq!(
+        pt.unmap(VirtAddr::from(vaddr + 0x1000)).map(|r| r.0),
+        Err(PagingError::NotMapped)
+    );
+    pt.copy_from(&other, VirtAddr::from(vaddr), 0x1000);
+    other_model.check(&pt);
+
+    // Mapping through the shared tables shows up in both page tables.
+    map(&mut pt, &mut other_model, vaddr, 0x3000, PageSize::Size4K);
+    other_model.check(&pt);
+    other_model.check(&other);
+
+    pt.clear_copy_range(VirtAddr::from(vaddr), 0x1000);
+    Model::new().check(&pt);
+    drop(other);
+    drop(pt);
+    // The tables detached by `clear_copy_range` above are leaked.
+    assert_eq!(MockHandler::live_frames(), 3);
+}
+
+#[test]
+fn cache_follows_sequential_operations() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let mut model = Model::new();
+    let base = 0x40_0000_0000;
+    // Alternate between two P1 tables and a 2M mapping under another P2 table.
+    for i in 0..64 {
+        let off = (i % 2) * 0x20_0000 + (i / 2) * 0x1000;
+        map(&mut pt, &mut model, base + off, off, PageSize::Size4K);
+        if i % 16 == 0 {
+            let huge = base + 0x4000_0000 + i * 0x20_0000;
+            map(&mut pt, &mut model, huge, huge, PageSize::Size2M);
+        }
+        model.check(&pt);
+    }
+    for i in (0..64).step_by(3) {
+        let vaddr = VirtAddr::from(base + (i % 2) * 0x20_0000 + (i / 2) * 0x1000);
+        pt.unmap(vaddr).unwrap().2.ignore();
+        model.unmap(vaddr);
+        model.check(&pt);
+    }
+}
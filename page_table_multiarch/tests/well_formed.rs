@@ -0,0 +1,69 @@
+//! The entries with reserved bits set, e.g. by firmware, reported by the
+//! consistency check of the mock.
+
+#![cfg(feature = "all-formats")]
+
+use std::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::riscv::Rv64PTE;
+use page_table_multiarch::mock::{MockHandler, MockPageTable, check_well_formed};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingHandler};
+
+type PageTable = MockPageTable<Sv39MetaData<VirtAddr>, Rv64PTE>;
+
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const VADDR: usize = 0x4000_0000;
+const HUGE_VADDR: usize = 0x8000_0000;
+/// A bit reserved by the privileged specification.
+const RESERVED: u64 = 1 << 57;
+
+/// Returns a pointer to the entry of `vaddr` at `level`, as the walk only
+/// passes copies.
+fn entry(pt: &PageTable, vaddr: usize, level: usize) -> *mut Rv64PTE {
+    let shift = |level: usize| 30 - 9 * level;
+    let table = Cell::new(pt.root_paddr());
+    pt.walk(
+        usize::MAX,
+        Some(&|l, _, va: VirtAddr, entry: &Rv64PTE| {
+            if l + 1 == level && va.as_usize() == vaddr >> shift(l) << shift(l) {
+                table.set(entry.paddr());
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    let table = MockHandler::phys_to_virt(table.get()).as_mut_ptr() as *mut Rv64PTE;
+    unsafe { table.add(vaddr >> shift(level) & 511) }
+}
+
+#[test]
+fn reported() {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(
+        VADDR.into(),
+        PhysAddr::from(0x8000_0000),
+        PageSize::Size4K,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    pt.map(
+        HUGE_VADDR.into(),
+        PhysAddr::from(0x8020_0000),
+        PageSize::Size2M,
+        RW,
+    )
+    .unwrap()
+    .ignore();
+    assert_eq!(check_well_formed(&pt), []);
+
+    // The table entry above the 4K page, and the huge page.
+    for (vaddr, level) in [(VADDR, 1), (HUGE_VADDR, 1)] {
+        let pte = entry(&pt, vaddr, level);
+        unsafe { *pte = Rv64PTE::from_bits((*pte).bits() as u64 | RESERVED) };
+    }
+    assert_eq!(check_well_formed(&pt), [(1, VADDR), (1, HUGE_VADDR)]);
+}
@@ -0,0 +1,224 @@
+//! Estimating the working set of a region from the accessed bits, with idle
+//! counters kept by the caller.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::{aarch64::A64PTE, x86_64::X64PTE};
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::mock::{MockHandler, MockMetaData, MockPageTable};
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{MappingFlags, PageSize, PagingError, WorkingSet};
+
+type PageTable = MockPageTable<X64PagingMetaData, X64PTE>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const SIZE_2M: usize = PageSize::Size2M as usize;
+/// The 2M page, the hole, and the three 4K pages of [`mapped`].
+const SIZE: usize = SIZE_2M + 0x4000;
+const PAGES: usize = SIZE / 0x1000;
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+/// A 2M page, then a hole of 4K, then three 4K pages, none accessed.
+fn mapped() -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    pt.map(va(0), PhysAddr::from(0x20_0000), PageSize::Size2M, RW)
+        .unwrap()
+        .ignore();
+    for off in [0x20_1000, 0x20_2000, 0x20_3000] {
+        pt.map(va(off), PhysAddr::from(off), PageSize::Size4K, RW)
+            .unwrap()
+            .ignore();
+    }
+    pt
+}
+
+/// Scans the region of [`mapped`], and flushes if needed.
+fn scan(pt: &mut PageTable, idle: &mut [u8], split: bool) -> (WorkingSet, bool) {
+    let (set, tlb) = pt.estimate_working_set(va(0), SIZE, split, idle).unwrap();
+    let needed = tlb.is_needed();
+    tlb.flush_all();
+    (set, needed)
+}
+
+fn set(accessed: usize, idle: usize) -> WorkingSet {
+    WorkingSet {
+        accessed,
+        idle,
+        locked: 0,
+    }
+}
+
+#[test]
+fn idle_counters() {
+    let mut pt = mapped();
+    let mut idle = [0; PAGES];
+
+    // Nothing accessed yet, so nothing to clear or flush.
+    let (result, needed) = scan(&mut pt, &mut idle, false);
+    assert_eq!(result, set(0, SIZE - 0x1000));
+    assert!(!needed);
+    // The hole keeps its counter.
+    assert!(idle[..512].iter().all(|&n| n == 1));
+    assert_eq!(idle[512..], [0, 1, 1, 1]);
+
+    // The huge page counts as a whole.
+    pt.set_accessed(va(0x1000), true).unwrap();
+    pt.set_accessed(va(0x20_2000), true).unwrap();
+    let (result, needed) = scan(&mut pt, &mut idle, false);
+    assert_eq!(result, set(SIZE_2M + 0x1000, 0x2000));
+    assert!(needed);
+    assert!(idle[..512].iter().all(|&n| n == 0));
+    assert_eq!(idle[512..], [0, 2, 0, 2]);
+    assert_eq!(MockMetaData::<X64PagingMetaData>::take_flushes(), [None]);
+    assert!(!pt.is_accessed(va(0)).unwrap());
+    assert!(!pt.is_accessed(va(0x20_2000)).unwrap());
+
+    // The counters saturate.
+    idle[515] = u8::MAX;
+    let (result, needed) = scan(&mut pt, &mut idle, false);
+    assert_eq!(result, set(0, SIZE - 0x1000));
+    assert!(!needed);
+    assert_eq!(idle[512..], [0, 3, 1, u8::MAX]);
+}
+
+#[test]
+fn huge_pages_in_part() {
+    let mut pt = mapped();
+    pt.set_accessed(va(0), true).unwrap();
+    // Only the last 4K of the huge page is in the region, but it counts whole.
+    let mut idle = [0; 2];
+    let (result, tlb) = pt
+        .estimate_working_set(va(SIZE_2M - 0x1000), 0x2000, false, &mut idle[..])
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(result, set(SIZE_2M, 0));
+    assert_eq!(idle, [0, 0]);
+    assert!(!pt.is_accessed(va(0)).unwrap());
+}
+
+#[test]
+fn split_huge_pages() {
+    let mut pt = mapped();
+    let frames = pt.table_frames();
+    let mut idle = [0; PAGES];
+    pt.set_accessed(va(0), true).unwrap();
+
+    // The split pages inherit the bit of the huge page.
+    let (result, needed) = scan(&mut pt, &mut idle, true);
+    assert_eq!(result, set(SIZE_2M, 0x3000));
+    assert!(needed);
+    assert_eq!(pt.table_frames(), frames + 1);
+    assert_eq!(
+        pt.query(va(0x1000)).unwrap(),
+        (PhysAddr::from(0x20_1000), RW, PageSize::Size4K)
+    );
+
+    // From now on, the pages are sampled one by one.
+    pt.set_accessed(va(0x5000), true).unwrap();
+    let (result, needed) = scan(&mut pt, &mut idle, true);
+    assert_eq!(result, set(0x1000, SIZE - 0x2000));
+    assert!(needed);
+    assert_eq!(idle[..6], [1, 1, 1, 1, 1, 0]);
+    assert_eq!(pt.table_frames(), frames + 1);
+}
+
+#[test]
+fn split_fails_without_frames() {
+    let mut pt = mapped();
+    let mut idle = [0; PAGES];
+    MockHandler::fail_alloc_at(Some(0));
+    let result = pt.estimate_working_set(va(0), SIZE, true, &mut idle[..]);
+    MockHandler::fail_alloc_at(None);
+    assert_eq!(
+        result.map(|(_, tlb)| tlb.ignore()),
+        Err(PagingError::NoMemory)
+    );
+    assert_eq!(pt.query(va(0)).unwrap().2, PageSize::Size2M);
+}
+
+#[test]
+fn deterministic() {
+    // The same accesses give the same results, whatever the pieces the
+    // region is scanned in.
+    let accesses = [[0x20_1000, 0x20_3000], [0x20_2000, 0x20_3000]];
+    let expected = [
+        (set(0x2000, 0x1000), [0, 0, 1, 0]),
+        (set(0x2000, 0x1000), [0, 1, 0, 0]),
+    ];
+    for pieces in [1, 2, 4] {
+        let mut pt = mapped();
+        let mut idle = [0; 4];
+        let size = 0x4000 / pieces;
+        for (vaddrs, expected) in accesses.iter().zip(expected) {
+            for &vaddr in vaddrs {
+                pt.set_accessed(va(vaddr), true).unwrap();
+            }
+            let mut total = set(0, 0);
+            for (i, idle) in idle.chunks_mut(size / 0x1000).enumerate() {
+                let start = va(SIZE_2M + i * size);
+                let (result, tlb) = pt.estimate_working_set(start, size, false, idle).unwrap();
+                tlb.flush_all();
+                total.accessed += result.accessed;
+                total.idle += result.idle;
+            }
+            assert_eq!((total, idle), expected, "{pieces} pieces");
+        }
+    }
+}
+
+#[test]
+fn contiguous_groups() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<A64PagingMetaData, A64PTE>::try_new().unwrap();
+    let vaddr = VirtAddr::from(VADDR);
+    pt.map(vaddr, PhysAddr::from(0x1_0000), PageSize::Size64K, RW)
+        .unwrap()
+        .ignore();
+    let mut idle = [0; 16];
+
+    // The entries are mapped with the access flag, and are one page.
+    let (result, tlb) = pt
+        .estimate_working_set(vaddr, 0x1_0000, false, &mut idle[..])
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(result, set(0x1_0000, 0));
+    let (result, tlb) = pt
+        .estimate_working_set(vaddr, 0x1_0000, false, &mut idle[..])
+        .unwrap();
+    assert!(!tlb.is_needed());
+    tlb.ignore();
+    assert_eq!(result, set(0, 0x1_0000));
+    assert_eq!(idle, [1; 16]);
+
+    // Also from the middle of the group.
+    pt.set_accessed(vaddr + 0x3000, true).unwrap();
+    let (result, tlb) = pt
+        .estimate_working_set(vaddr + 0x8000, 0x8000, false, &mut idle[8..])
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(result, set(0x1_0000, 0));
+    assert_eq!(idle[8..], [0; 8]);
+
+    // Split, the group is still one page for this scan, and the pages are
+    // sampled one by one from the next one on.
+    pt.set_accessed(vaddr + 0x3000, true).unwrap();
+    let (result, tlb) = pt
+        .estimate_working_set(vaddr, 0x1_0000, true, &mut idle[..])
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(result, set(0x1_0000, 0));
+    assert_eq!(pt.query(vaddr).unwrap().2, PageSize::Size4K);
+    pt.set_accessed(vaddr + 0x3000, true).unwrap();
+    let (result, tlb) = pt
+        .estimate_working_set(vaddr, 0x1_0000, true, &mut idle[..])
+        .unwrap();
+    tlb.ignore();
+    assert_eq!(result, set(0x1000, 0xf000));
+    assert_eq!(idle[2..4], [1, 0]);
+}
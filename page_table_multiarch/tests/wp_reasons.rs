@@ -0,0 +1,204 @@
+//! Copy-on-write, dirty logging and protect sessions write-protecting the same
+//! pages, each with its own reason, so that resolving one fault never makes a
+//! page writable while another still needs it protected.
+
+#![cfg(feature = "all-formats")]
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::SoftBitLayout;
+use page_table_entry::loongarch64::LA64PTE;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::{MappingFlags, PagingError, ProtectSession, WpReasons};
+
+/// COW and both reasons in the three bits reserved for software.
+struct Layout;
+
+impl SoftBitLayout for Layout {
+    const COW: u32 = 9;
+    const DIRTY_LOG: Option<u32> = Some(10);
+    const SNAPSHOT: Option<u32> = Some(11);
+}
+
+type PageTable = MockPageTable<LA64MetaData, LA64PTE<Layout>>;
+
+const VADDR: usize = 0x40_0000_0000;
+const RW: MappingFlags = MappingFlags::READ.union(MappingFlags::WRITE);
+const COW: MappingFlags = MappingFlags::READ.union(MappingFlags::COW);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn mapped(flags: MappingFlags) -> PageTable {
+    MockHandler::reset();
+    let mut pt = PageTable::try_new().unwrap();
+    let get_paddr = |vaddr: VirtAddr| PhysAddr::from(vaddr.as_usize() - VADDR + 0x8000_0000);
+    pt.map_region(va(0), get_paddr, 0x4000, flags, false, false)
+        .unwrap()
+        .ignore();
+    pt
+}
+
+fn flags(pt: &PageTable, off: usize) -> MappingFlags {
+    pt.query(va(off)).unwrap().1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fault {
+    Cow,
+    DirtyLog,
+    Snapshot,
+}
+
+#[test]
+fn every_order() {
+    let faults = [Fault::Cow, Fault::DirtyLog, Fault::Snapshot];
+    let mut orders = Vec::new();
+    for a in faults {
+        for b in faults {
+            for c in faults {
+                if a != b && b != c && a != c {
+                    orders.push([a, b, c]);
+                }
+            }
+        }
+    }
+    assert_eq!(orders.len(), 6);
+
+    for order in orders {
+        let mut pt = mapped(COW);
+        pt.enable_dirty_log(va(0), 0x4000).unwrap().ignore();
+        let mut store = [0u64; 1];
+        let (mut session, tlb) =
+            ProtectSession::begin(&mut pt, va(0), 0x4000, &mut store[..]).unwrap();
+        tlb.ignore();
+        assert_eq!(pt.wp_reasons(va(0)), Ok(WpReasons::all()));
+
+        let (mut marked, mut saved) = (Vec::new(), Vec::new());
+        for (i, fault) in order.into_iter().enumerate() {
+            match fault {
+                Fault::Cow => pt.handle_cow_fault(va(0), |paddr, _| Some(paddr)),
+                Fault::DirtyLog => pt.handle_dirty_log_fault(va(0x10), RW, |v| marked.push(v)),
+                Fault::Snapshot => session.fault(&mut pt, va(0x10), |v, _| saved.push(v)),
+            }
+            .unwrap()
+            .ignore();
+
+            let left = &order[i + 1..];
+            let mut reasons = WpReasons::empty();
+            if left.contains(&Fault::DirtyLog) {
+                reasons |= WpReasons::DIRTY_LOG;
+            }
+            if left.contains(&Fault::Snapshot) {
+                reasons |= WpReasons::SNAPSHOT;
+            }
+            assert_eq!(
+                pt.wp_reasons(va(0)),
+                Ok(reasons),
+                "{order:?} after {fault:?}"
+            );
+            let expected = if left.is_empty() {
+                RW
+            } else if left.contains(&Fault::Cow) {
+                COW
+            } else {
+                MappingFlags::READ
+            };
+            assert_eq!(flags(&pt, 0), expected, "{order:?} after {fault:?}");
+        }
+        assert_eq!(marked, [va(0)]);
+        assert_eq!(saved, [va(0)]);
+
+        // A resolved reason does not fault again.
+        assert_eq!(
+            pt.handle_dirty_log_fault(va(0), RW, |_| panic!("marked"))
+                .map(|tlb| tlb.ignore()),
+            Ok(())
+        );
+        // The other pages are still protected for all three reasons.
+        assert_eq!(flags(&pt, 0x1000), COW);
+        assert_eq!(pt.wp_reasons(va(0x1000)), Ok(WpReasons::all()));
+        let (faulted, tlb) = session.end(&mut pt).unwrap();
+        tlb.ignore();
+        assert_eq!(faulted, 1);
+        assert_eq!(pt.wp_reasons(va(0x1000)), Ok(WpReasons::DIRTY_LOG));
+        assert_eq!(flags(&pt, 0x1000), COW);
+    }
+}
+
+#[test]
+fn fault_for_another_reason() {
+    let mut pt = mapped(RW);
+    let mut store = [0u64; 1];
+    let (session, tlb) = ProtectSession::begin(&mut pt, va(0), 0x4000, &mut store[..]).unwrap();
+    tlb.ignore();
+    // Not logged: the fault is the session's.
+    assert_eq!(
+        pt.handle_dirty_log_fault(va(0), RW, |_| panic!("marked"))
+            .map(|tlb| tlb.ignore()),
+        Err(PagingError::NotLogged)
+    );
+    assert_eq!(flags(&pt, 0), MappingFlags::READ);
+
+    // Logging starts during the session, and outlives it.
+    pt.enable_dirty_log(va(0), 0x2000).unwrap().ignore();
+    session.end(&mut pt).unwrap().1.ignore();
+    assert_eq!(flags(&pt, 0x1000), MappingFlags::READ);
+    assert_eq!(flags(&pt, 0x2000), RW);
+    let (changed, tlb) = pt.disable_dirty_log(va(0), 0x4000).unwrap();
+    tlb.ignore();
+    assert_eq!(changed, 2);
+    for off in (0..0x4000).step_by(0x1000) {
+        assert_eq!(flags(&pt, off), RW);
+        assert_eq!(pt.wp_reasons(va(off)), Ok(WpReasons::empty()));
+    }
+}
+
+#[test]
+fn collect_and_rearm() {
+    let mut pt = mapped(RW);
+    pt.enable_dirty_log(va(0), 0x4000).unwrap().ignore();
+    pt.handle_dirty_log_fault(va(0x1000), RW, |_| {})
+        .unwrap()
+        .ignore();
+    let mut store = [0u64; 1];
+    let (session, tlb) = ProtectSession::begin(&mut pt, va(0), 0x4000, &mut store[..]).unwrap();
+    tlb.ignore();
+
+    // The written page is reported even though it is still protected by the
+    // session, and logged again.
+    let mut dirty = Vec::new();
+    pt.collect_and_rearm(va(0), 0x4000, |v| dirty.push(v))
+        .unwrap()
+        .ignore();
+    assert_eq!(dirty, [va(0x1000)]);
+    assert_eq!(pt.wp_reasons(va(0x1000)), Ok(WpReasons::all()));
+    session.end(&mut pt).unwrap().1.ignore();
+    assert_eq!(flags(&pt, 0x1000), MappingFlags::READ);
+}
+
+#[test]
+fn protect_keeps_reasons() {
+    let mut pt = mapped(RW);
+    pt.enable_dirty_log(va(0), 0x4000).unwrap().ignore();
+    // Still logged with new flags that may be written.
+    let rwx = RW | MappingFlags::EXECUTE;
+    pt.protect(va(0), rwx).unwrap().1.ignore();
+    assert_eq!(flags(&pt, 0), MappingFlags::READ | MappingFlags::EXECUTE);
+    let mut marked = Vec::new();
+    pt.handle_dirty_log_fault(va(0), rwx, |v| marked.push(v))
+        .unwrap()
+        .ignore();
+    assert_eq!(marked, [va(0)]);
+    assert_eq!(flags(&pt, 0), rwx);
+
+    // A read-only page is no longer logged.
+    pt.protect(va(0x1000), MappingFlags::READ)
+        .unwrap()
+        .1
+        .ignore();
+    assert_eq!(pt.wp_reasons(va(0x1000)), Ok(WpReasons::empty()));
+    pt.protect(va(0x1000), RW).unwrap().1.ignore();
+    assert_eq!(flags(&pt, 0x1000), RW);
+}
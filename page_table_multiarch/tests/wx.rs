@@ -0,0 +1,179 @@
+//! Refusing mappings both writable and executable (W^X) when the page table
+//! enforces it, and lifting it explicitly, with the permissions lowered to
+//! each format: x86 and LoongArch forbid execution with a bit, RISC-V and
+//! AArch64 at EL1 allow it with one or by its absence.
+
+#![cfg(feature = "all-formats")]
+
+use core::cell::Cell;
+
+use memory_addr::{PhysAddr, VirtAddr};
+use page_table_entry::aarch64::A64PTE;
+use page_table_entry::loongarch64::LA64PTE;
+use page_table_entry::riscv::Rv64PTE;
+use page_table_entry::x86_64::X64PTE;
+use page_table_multiarch::aarch64::A64PagingMetaData;
+use page_table_multiarch::loongarch64::LA64MetaData;
+use page_table_multiarch::mock::{MockHandler, MockPageTable};
+use page_table_multiarch::riscv::Sv39MetaData;
+use page_table_multiarch::x86_64::X64PagingMetaData;
+use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingError, PagingMetaData};
+
+const VADDR: usize = 0x1000_0000;
+const R: MappingFlags = MappingFlags::READ;
+const RW: MappingFlags = R.union(MappingFlags::WRITE);
+const RX: MappingFlags = R.union(MappingFlags::EXECUTE);
+const RWX: MappingFlags = RW.union(MappingFlags::EXECUTE);
+
+fn va(off: usize) -> VirtAddr {
+    VirtAddr::from(VADDR + off)
+}
+
+fn pa(off: usize) -> PhysAddr {
+    PhysAddr::from(0x8000_0000 + off)
+}
+
+fn violation(off: usize) -> PagingError {
+    PagingError::WxViolation(VADDR + off)
+}
+
+/// Runs the W^X checks on a page table of the format, and returns the bits
+/// of a W+X entry mapped with the policy lifted.
+fn check<M, PTE>() -> u64
+where
+    M: PagingMetaData<VirtAddr = VirtAddr>,
+    PTE: GenericPTE,
+{
+    MockHandler::reset();
+    let mut pt = MockPageTable::<M, PTE>::try_new().unwrap();
+    assert!(!pt.wx_enforced());
+    pt.enforce_wx(true);
+
+    // Creation.
+    let map = |pt: &mut MockPageTable<M, PTE>, off, flags| {
+        pt.map(va(off), pa(off), PageSize::Size4K, flags)
+            .map(|tlb| tlb.ignore())
+    };
+    assert_eq!(map(&mut pt, 0, RWX), Err(violation(0)));
+    let get_paddr = |vaddr: VirtAddr| pa(vaddr.as_usize() - VADDR);
+    assert_eq!(
+        pt.map_region(va(0), get_paddr, 0x2000, RWX, false, false)
+            .map(|tlb| tlb.ignore()),
+        Err(violation(0))
+    );
+    let cow = R | MappingFlags::EXECUTE | MappingFlags::COW;
+    assert_eq!(map(&mut pt, 0, cow), Err(violation(0)));
+    assert_eq!(pt.query(va(0)), Err(PagingError::NotMapped));
+
+    // Transitions, both ways.
+    map(&mut pt, 0, RW).unwrap();
+    map(&mut pt, 0x1000, RX).unwrap();
+    assert_eq!(
+        pt.protect(va(0), RWX).map(|(_, tlb)| tlb.ignore()),
+        Err(violation(0))
+    );
+    assert_eq!(
+        pt.protect_region(va(0x1000), 0x1000, RWX, false)
+            .map(|tlb| tlb.ignore()),
+        Err(violation(0x1000))
+    );
+    assert_eq!(
+        pt.remap(va(0x1000), pa(0x1000), RWX)
+            .map(|(_, tlb)| tlb.ignore()),
+        Err(violation(0x1000))
+    );
+    assert_eq!(
+        pt.transaction(&mut []).protect_region(va(0), 0x1000, RWX),
+        Err(violation(0))
+    );
+    assert_eq!(pt.query(va(0)).unwrap().1, RW);
+    assert_eq!(pt.query(va(0x1000)).unwrap().1, RX);
+    // Switching between the two is fine.
+    pt.protect(va(0), RX).unwrap().1.ignore();
+    pt.protect(va(0x1000), RW).unwrap().1.ignore();
+
+    // The override, which leaves the policy enforced afterwards.
+    pt.allow_wx(|pt| {
+        map(pt, 0x2000, RWX).unwrap();
+        pt.protect(va(0), RWX).unwrap().1.ignore();
+    });
+    assert!(pt.wx_enforced());
+    assert_eq!(pt.query(va(0x2000)).unwrap().1, RWX);
+    assert_eq!(pt.query(va(0)).unwrap().1, RWX);
+    assert_eq!(map(&mut pt, 0x3000, RWX), Err(violation(0x3000)));
+
+    // Not enforced.
+    pt.enforce_wx(false);
+    map(&mut pt, 0x3000, RWX).unwrap();
+
+    let bits = Cell::new(0);
+    pt.walk(
+        usize::MAX,
+        Some(&|level, _, vaddr: VirtAddr, entry: &PTE| {
+            if level == M::LEVELS - 1 && vaddr == va(0x2000) {
+                bits.set(entry.bits() as u64);
+            }
+        }),
+        None,
+    )
+    .unwrap();
+    bits.get()
+}
+
+#[test]
+fn x86_64() {
+    let bits = check::<X64PagingMetaData, X64PTE>();
+    // Writable, and not no-execute.
+    assert_eq!(bits & 1 << 1, 1 << 1);
+    assert_eq!(bits & 1 << 63, 0);
+}
+
+#[test]
+fn riscv() {
+    let bits = check::<Sv39MetaData<VirtAddr>, Rv64PTE>();
+    // W and X.
+    assert_eq!(bits & 0b1100, 0b1100);
+}
+
+#[test]
+fn loongarch64() {
+    let bits = check::<LA64MetaData, LA64PTE>();
+    // W and D, and not NX.
+    assert_eq!(bits & 0b1_0000_0010, 0b1_0000_0010);
+    assert_eq!(bits & 1 << 62, 0);
+}
+
+#[test]
+fn aarch64() {
+    let bits = check::<A64PagingMetaData, A64PTE>();
+    // Neither read-only nor privileged execute-never.
+    assert_eq!(bits & 1 << 7, 0);
+    assert_eq!(bits & 1 << 53, 0);
+}
+
+#[test]
+fn clone_cow_carve_out() {
+    MockHandler::reset();
+    let mut pt = MockPageTable::<LA64MetaData, LA64PTE>::try_new().unwrap();
+    pt.map(va(0), pa(0), PageSize::Size4K, RWX)
+        .unwrap()
+        .ignore();
+    pt.enforce_wx(true);
+    let (mut child, tlb) = pt.clone_cow(va(0), 0x1000).unwrap();
+    tlb.ignore();
+    assert!(child.wx_enforced());
+    let cow = RX | MappingFlags::COW;
+    assert_eq!(pt.query(va(0)).unwrap().1, cow);
+    assert_eq!(child.query(va(0)).unwrap().1, cow);
+    // The write fault gives the mapping back.
+    child
+        .handle_cow_fault(va(0), |_, _| Some(pa(0x1000)))
+        .unwrap()
+        .ignore();
+    assert_eq!(child.query(va(0)).unwrap().1, RWX);
+    // But it cannot be created again.
+    assert_eq!(
+        child.protect(va(0), cow).map(|(_, tlb)| tlb.ignore()),
+        Err(violation(0))
+    );
+}